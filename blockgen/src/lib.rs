@@ -10,19 +10,23 @@ use cfxcore::{
         work_notify::NotifyWork,
     },
     pow::*,
-    transaction_pool::DEFAULT_MAX_BLOCK_GAS_LIMIT,
+    transaction_pool::{PackingStrategy, DEFAULT_MAX_BLOCK_GAS_LIMIT},
+    verification::median_time_past_of,
     SharedSynchronizationGraph, SharedSynchronizationService,
     SharedTransactionPool,
 };
 use lazy_static::lazy_static;
 use log::{debug, info, trace, warn};
-use metrics::{Gauge, GaugeUsize};
+use metrics::{register_meter_with_group, Gauge, GaugeUsize, Meter};
 use parking_lot::{Mutex, RwLock};
 use primitives::*;
 use std::{
     cmp::max,
-    collections::HashSet,
-    sync::{mpsc, Arc},
+    collections::{BTreeMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc,
+    },
     thread, time,
 };
 use time::{SystemTime, UNIX_EPOCH};
@@ -30,13 +34,178 @@ use txgen::{SharedTransactionGenerator, SpecialTransactionGenerator};
 lazy_static! {
     static ref PACKED_ACCOUNT_SIZE: Arc<dyn Gauge<usize>> =
         GaugeUsize::register_with_group("txpool", "packed_account_size");
+    static ref MINING_HASHRATE: Arc<dyn Meter> =
+        register_meter_with_group("blockgen", "hashrate");
 }
 
+/// The number of nonces a mining thread takes from the nonce space of the
+/// current job at a time. Threads check for a new job between two chunks.
+const NONCE_CHUNK_SIZE: u64 = 10000;
+
+/// The number of latest block templates kept so that solutions of slightly
+/// outdated templates can still be submitted.
+const MAX_BLOCK_TEMPLATES: usize = 16;
+/// A new block template is assembled if at least this many transactions are
+/// added to the transaction pool after the latest template is assembled.
+const BLOCK_TEMPLATE_REFRESH_TX_COUNT: usize = 100;
+/// The block template subscribers are notified as soon as the best block
+/// changes, while the transaction pool is checked at this period.
+const BLOCK_TEMPLATE_TX_CHECK_PERIOD_MS: u64 = 500;
+
 enum MiningState {
     Start,
     Stop,
 }
 
+/// A block to be solved by external miners.
+#[derive(Clone, Debug)]
+pub struct BlockTemplate {
+    pub template_id: u64,
+    pub block_header: BlockHeader,
+    pub problem_hash: H256,
+    pub boundary: H256,
+    pub transaction_hashes: Vec<H256>,
+}
+
+impl BlockTemplate {
+    fn new(template_id: u64, block: &Block) -> Self {
+        BlockTemplate {
+            template_id,
+            block_header: block.block_header.clone(),
+            problem_hash: block.block_header.problem_hash(),
+            boundary: difficulty_to_boundary(block.block_header.difficulty()),
+            transaction_hashes: block
+                .transactions
+                .iter()
+                .map(|tx| tx.hash())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct BlockTemplates {
+    next_template_id: u64,
+    /// template id -> assembled block without nonce
+    blocks: BTreeMap<u64, Block>,
+    /// The number of unpacked transactions in the transaction pool when the
+    /// latest template was assembled.
+    unpacked_txs: usize,
+    subscribers: Vec<mpsc::Sender<BlockTemplate>>,
+    notifier_started: bool,
+}
+
+impl BlockTemplates {
+    /// The latest block template, if any.
+    fn latest(&self) -> Option<BlockTemplate> {
+        self.blocks
+            .iter()
+            .next_back()
+            .map(|(template_id, block)| BlockTemplate::new(*template_id, block))
+    }
+
+    /// Check if the latest block template should be replaced because the
+    /// best block changed or many transactions were received since it was
+    /// assembled.
+    fn is_outdated(&self, best_block_hash: &H256, unpacked_txs: usize) -> bool {
+        let latest = match self.blocks.values().next_back() {
+            Some(block) => block,
+            None => return true,
+        };
+        latest.block_header.parent_hash() != best_block_hash
+            || unpacked_txs
+                >= self.unpacked_txs + BLOCK_TEMPLATE_REFRESH_TX_COUNT
+    }
+
+    /// Add an assembled block as the latest template, and push it to the
+    /// subscribers. The oldest templates are dropped.
+    fn insert(&mut self, block: Block, unpacked_txs: usize) -> BlockTemplate {
+        let template_id = self.next_template_id;
+        self.next_template_id += 1;
+        self.unpacked_txs = unpacked_txs;
+
+        let template = BlockTemplate::new(template_id, &block);
+        self.blocks.insert(template_id, block);
+        while self.blocks.len() > MAX_BLOCK_TEMPLATES {
+            let oldest = *self.blocks.keys().next().unwrap();
+            self.blocks.remove(&oldest);
+        }
+        // drop disconnected subscribers
+        self.subscribers
+            .retain(|sender| sender.send(template.clone()).is_ok());
+        template
+    }
+
+    /// Validate the nonce for the specified block template, and return the
+    /// block with the nonce set.
+    fn solve(&self, template_id: u64, nonce: u64) -> Result<Block, String> {
+        let mut block = match self.blocks.get(&template_id) {
+            Some(block) => block.clone(),
+            None => {
+                return Err(format!(
+                    "Unknown or expired block template {}",
+                    template_id
+                ))
+            }
+        };
+
+        let difficulty = *block.block_header.difficulty();
+        let problem = ProofOfWorkProblem {
+            block_hash: block.block_header.problem_hash(),
+            difficulty,
+            boundary: difficulty_to_boundary(&difficulty),
+        };
+        if !validate(&problem, &ProofOfWorkSolution { nonce }) {
+            return Err(format!(
+                "Invalid nonce {} for block template {}",
+                nonce, template_id
+            ));
+        }
+
+        block.block_header.set_nonce(nonce);
+        block.block_header.compute_hash();
+        Ok(block)
+    }
+}
+
+/// Return the latest block template, or assemble a new one with `assemble`
+/// if it's outdated. The block is assembled without holding the templates
+/// lock, so that the latest template can still be read and solved meanwhile,
+/// while `assembling` makes the concurrent callers wait for the template
+/// being assembled instead of assembling their own.
+fn latest_block_template(
+    templates: &Mutex<BlockTemplates>, assembling: &Mutex<()>,
+    is_outdated: impl Fn(&BlockTemplates) -> bool,
+    assemble: impl FnOnce() -> (Block, usize),
+) -> BlockTemplate
+{
+    let latest_if_fresh = || {
+        let templates = templates.lock();
+        if is_outdated(&templates) {
+            None
+        } else {
+            templates.latest()
+        }
+    };
+    if let Some(template) = latest_if_fresh() {
+        return template;
+    }
+    let _assembling = assembling.lock();
+    // The template may have been assembled while waiting for the lock.
+    if let Some(template) = latest_if_fresh() {
+        return template;
+    }
+    let (block, unpacked_txs) = assemble();
+    let template = templates.lock().insert(block, unpacked_txs);
+    debug!(
+        "Assembled block template {} with parent {:?} and {} txs",
+        template.template_id,
+        template.block_header.parent_hash(),
+        template.transaction_hashes.len()
+    );
+    template
+}
+
 /// The interface for a conflux block generator
 pub struct BlockGenerator {
     pub pow_config: ProofOfWorkConfig,
@@ -47,8 +216,58 @@ pub struct BlockGenerator {
     special_txgen: Arc<Mutex<SpecialTransactionGenerator>>,
     sync: SharedSynchronizationService,
     state: RwLock<MiningState>,
-    workers: Mutex<Vec<(Worker, mpsc::Sender<ProofOfWorkProblem>)>>,
+    workers: Mutex<Vec<(Worker, mpsc::Sender<Arc<MiningJob>>)>>,
     pub stratum: RwLock<Option<Stratum>>,
+    templates: Mutex<BlockTemplates>,
+    /// Held while a block template is being assembled.
+    template_assembling: Mutex<()>,
+    packing_strategy: Arc<dyn PackingStrategy>,
+}
+
+/// A PoW problem shared by all mining threads. The nonce space is split
+/// into chunks which are taken by the threads on demand, so that a faster
+/// thread simply searches more chunks.
+pub struct MiningJob {
+    problem: ProofOfWorkProblem,
+    next_nonce: AtomicU64,
+    solved: AtomicBool,
+}
+
+impl MiningJob {
+    fn new(problem: ProofOfWorkProblem) -> Self {
+        MiningJob::with_start_nonce(problem, rand::random())
+    }
+
+    fn with_start_nonce(problem: ProofOfWorkProblem, start_nonce: u64) -> Self {
+        MiningJob {
+            problem,
+            next_nonce: AtomicU64::new(start_nonce),
+            solved: AtomicBool::new(false),
+        }
+    }
+
+    /// Take the next chunk of nonces to search.
+    fn next_chunk(&self) -> u64 {
+        self.next_nonce
+            .fetch_add(NONCE_CHUNK_SIZE, Ordering::Relaxed)
+    }
+
+    /// Search the next chunk of nonces. If a nonce solves the problem, the
+    /// job is marked as solved so that the other threads stop searching it.
+    fn search_next_chunk(&self) -> Option<u64> {
+        let start = self.next_chunk();
+        for i in 0..NONCE_CHUNK_SIZE {
+            let nonce = start.wrapping_add(i);
+            let hash = compute(nonce, &self.problem.block_hash);
+            if hash < self.problem.boundary {
+                self.solved.store(true, Ordering::Relaxed);
+                return Some(nonce);
+            }
+        }
+        None
+    }
+
+    fn is_solved(&self) -> bool { self.solved.load(Ordering::Relaxed) }
 }
 
 pub struct Worker {
@@ -60,7 +279,7 @@ impl Worker {
     pub fn new(
         bg: Arc<BlockGenerator>,
         solution_sender: mpsc::Sender<ProofOfWorkSolution>,
-        problem_receiver: mpsc::Receiver<ProofOfWorkProblem>,
+        job_receiver: mpsc::Receiver<Arc<MiningJob>>,
     ) -> Self
     {
         let bg_handle = bg.clone();
@@ -69,7 +288,7 @@ impl Worker {
             .name("blockgen".into())
             .spawn(move || {
                 let sleep_duration = time::Duration::from_millis(100);
-                let mut problem: Option<ProofOfWorkProblem> = None;
+                let mut job: Option<Arc<MiningJob>> = None;
 
                 loop {
                     match *bg_handle.state.read() {
@@ -77,49 +296,37 @@ impl Worker {
                         _ => {}
                     }
 
-                    // check if there is a new problem
-                    let new_problem = problem_receiver.try_recv();
-                    if new_problem.is_ok() {
-                        problem = Some(new_problem.unwrap());
+                    // switch to the latest job if there is any
+                    while let Ok(new_job) = job_receiver.try_recv() {
+                        job = Some(new_job);
+                    }
+                    if job.as_ref().map_or(false, |job| job.is_solved()) {
+                        job = None;
                     }
-                    // check if there is a problem to be solved
-                    if problem.is_some() {
-                        let boundary = problem.unwrap().boundary;
-                        let block_hash = problem.unwrap().block_hash;
 
-                        #[cfg(test)]
-                        {
-                            let difficulty = problem.unwrap().difficulty;
-                            if difficulty > 500000.into() {
-                                warn!("Difficulty is too high to mine!");
-                            }
+                    let current_job = match job {
+                        Some(ref job) => job.clone(),
+                        None => {
+                            thread::sleep(sleep_duration);
+                            continue;
                         }
+                    };
 
-                        for _i in 0..100000 {
-                            //TODO: adjust the number of times
-                            let nonce = rand::random();
-                            let hash = compute(nonce, &block_hash);
-                            if hash < boundary {
-                                // problem solved
-                                match solution_sender
-                                    .send(ProofOfWorkSolution { nonce })
-                                {
-                                    Ok(_) => {}
-                                    Err(e) => {
-                                        warn!("{}", e);
-                                    }
-                                }
-                                // TODO Update problem fast. This will cause
-                                // miner to stop mining
-                                // until the previous blocks is processed by
-                                // ConsensusGraph
-                                problem = None;
-                                break;
-                            }
+                    #[cfg(test)]
+                    {
+                        if current_job.problem.difficulty > 500000.into() {
+                            warn!("Difficulty is too high to mine!");
+                        }
+                    }
+
+                    if let Some(nonce) = current_job.search_next_chunk() {
+                        if let Err(e) =
+                            solution_sender.send(ProofOfWorkSolution { nonce })
+                        {
+                            warn!("{}", e);
                         }
-                    } else {
-                        thread::sleep(sleep_duration);
                     }
+                    MINING_HASHRATE.mark(NONCE_CHUNK_SIZE as usize);
                 }
             })
             .expect("only one blockgen thread, so it should not fail");
@@ -133,6 +340,7 @@ impl BlockGenerator {
         sync: SharedSynchronizationService, txgen: SharedTransactionGenerator,
         special_txgen: Arc<Mutex<SpecialTransactionGenerator>>,
         pow_config: ProofOfWorkConfig, mining_author: Address,
+        packing_strategy: Arc<dyn PackingStrategy>,
     ) -> Self
     {
         BlockGenerator {
@@ -146,6 +354,9 @@ impl BlockGenerator {
             state: RwLock::new(MiningState::Start),
             workers: Mutex::new(Vec::new()),
             stratum: RwLock::new(None),
+            templates: Mutex::new(Default::default()),
+            template_assembling: Mutex::new(()),
+            packing_strategy,
         }
     }
 
@@ -167,9 +378,10 @@ impl BlockGenerator {
                 .unwrap()
                 .notify(problem.block_hash, problem.boundary);
         } else {
+            let job = Arc::new(MiningJob::new(problem));
             for item in bg.workers.lock().iter() {
                 item.1
-                    .send(problem)
+                    .send(job.clone())
                     .expect("Failed to send the PoW problem.")
             }
         }
@@ -213,7 +425,20 @@ impl BlockGenerator {
             .unwrap()
             .as_secs();
 
-        let my_timestamp = max(parent_timestamp, now);
+        let mut my_timestamp = max(parent_timestamp, now);
+        if self
+            .graph
+            .verification_config
+            .median_time_past_active(parent_height + 1)
+        {
+            // The block is rejected unless its timestamp is after the median
+            // time past of its parent.
+            if let Some(median) = median_time_past_of(&parent_hash, |hash| {
+                self.graph.data_man.block_header_by_hash(hash)
+            }) {
+                my_timestamp = max(my_timestamp, median + 1);
+            }
+        }
 
         let block_header = BlockHeaderBuilder::new()
             .with_transactions_root(Block::compute_transaction_root(
@@ -267,6 +492,7 @@ impl BlockGenerator {
             num_txs,
             block_gas_limit,
             block_size_limit,
+            &*self.packing_strategy,
         );
 
         Ok(self.assemble_new_block_impl(
@@ -298,6 +524,7 @@ impl BlockGenerator {
                 block_size_limit,
                 block_gas_limit,
                 additional_transactions,
+                &*self.packing_strategy,
             );
 
         let mut sender_accounts = HashSet::new();
@@ -362,6 +589,7 @@ impl BlockGenerator {
                 block_size_limit,
                 block_gas_limit,
                 additional_transactions,
+                &*self.packing_strategy,
             );
 
         let (
@@ -431,6 +659,109 @@ impl BlockGenerator {
         false
     }
 
+    /// Check if the latest block template should be replaced.
+    fn is_block_template_outdated(&self, templates: &BlockTemplates) -> bool {
+        templates.is_outdated(
+            &self.graph.consensus.best_block_hash(),
+            self.txpool.total_unpacked(),
+        )
+    }
+
+    /// Return the latest block template for external miners, a new template
+    /// is assembled if the latest one is outdated.
+    pub fn get_block_template(&self) -> BlockTemplate {
+        latest_block_template(
+            &self.templates,
+            &self.template_assembling,
+            |templates| self.is_block_template_outdated(templates),
+            || {
+                let unpacked_txs = self.txpool.total_unpacked();
+                let block = self.assemble_new_block(
+                    MAX_TRANSACTION_COUNT_PER_BLOCK,
+                    MAX_BLOCK_SIZE_IN_BYTES,
+                    vec![],
+                );
+                (block, unpacked_txs)
+            },
+        )
+    }
+
+    /// Validate the nonce for the specified block template, then assemble
+    /// the block and broadcast it. Return the hash of the mined block.
+    pub fn submit_block_solution(
+        &self, template_id: u64, nonce: u64,
+    ) -> Result<H256, String> {
+        let block = self.templates.lock().solve(template_id, nonce)?;
+        let hash = block.hash();
+        info!(
+            "Block {:?} mined with template {} by external miner",
+            hash, template_id
+        );
+        self.on_mined_block(block);
+        Ok(hash)
+    }
+
+    /// Subscribe to block template updates. A new template is pushed
+    /// whenever one is assembled, which happens at latest shortly after the
+    /// best block changes or the transaction pool contents change
+    /// materially. The current template is not pushed, subscribers should
+    /// get it with `get_block_template`.
+    pub fn subscribe_block_templates(
+        bg: Arc<BlockGenerator>,
+    ) -> mpsc::Receiver<BlockTemplate> {
+        let (sender, receiver) = mpsc::channel();
+        let mut templates = bg.templates.lock();
+        templates.subscribers.push(sender);
+        if !templates.notifier_started {
+            templates.notifier_started = true;
+            let bg = bg.clone();
+            thread::Builder::new()
+                .name("block template notifier".into())
+                .spawn(move || BlockGenerator::notify_block_templates(bg))
+                .expect("Block template notifier thread spawn error");
+        }
+        receiver
+    }
+
+    fn notify_block_templates(bg: Arc<BlockGenerator>) {
+        let check_period =
+            time::Duration::from_millis(BLOCK_TEMPLATE_TX_CHECK_PERIOD_MS);
+
+        // Wake up to refresh the template as soon as the best block changes.
+        let (best_block_sender, best_block_receiver) = mpsc::channel();
+        let best_block_sender = Mutex::new(best_block_sender);
+        let observer_id = bg.graph.consensus.register_best_info_observer(
+            Box::new(move |_best_info| {
+                best_block_sender.lock().send(()).ok();
+            }),
+        );
+
+        loop {
+            match *bg.state.read() {
+                MiningState::Stop => break,
+                _ => {}
+            }
+
+            let outdated = {
+                let templates = bg.templates.lock();
+                !templates.subscribers.is_empty()
+                    && (bg.pow_config.test_mode || !bg.sync.catch_up_mode())
+                    && bg.is_block_template_outdated(&templates)
+            };
+            if outdated {
+                // The new template is pushed to the subscribers when it's
+                // assembled.
+                bg.get_block_template();
+            }
+
+            // wait for a new best block or check the transaction pool again
+            best_block_receiver.recv_timeout(check_period).ok();
+            // drain the notifications of the same update
+            while best_block_receiver.try_recv().is_ok() {}
+        }
+        bg.graph.consensus.unregister_best_info_observer(observer_id);
+    }
+
     pub fn generate_special_transactions(
         &self, block_size_limit: &mut usize, num_txs_simple: usize,
         num_txs_erc20: usize,
@@ -520,6 +851,7 @@ impl BlockGenerator {
                 0,
                 block_gas_limit,
                 Vec::new(),
+                &*self.packing_strategy,
             );
         let (
             blame,
@@ -635,14 +967,10 @@ impl BlockGenerator {
         let (solution_sender, solution_receiver) = mpsc::channel();
         let mut workers = bg.workers.lock();
         for _ in 0..num_worker {
-            let (problem_sender, problem_receiver) = mpsc::channel();
+            let (job_sender, job_receiver) = mpsc::channel();
             workers.push((
-                Worker::new(
-                    bg.clone(),
-                    solution_sender.clone(),
-                    problem_receiver,
-                ),
-                problem_sender,
+                Worker::new(bg.clone(), solution_sender.clone(), job_receiver),
+                job_sender,
             ));
         }
         solution_receiver
@@ -664,29 +992,41 @@ impl BlockGenerator {
         solution_receiver
     }
 
-    pub fn start_mining(bg: Arc<BlockGenerator>, _payload_len: u32) {
+    /// Start mining with `num_threads` mining threads, or through the
+    /// stratum service if it is enabled.
+    pub fn start_mining(bg: Arc<BlockGenerator>, num_threads: u32) {
         let mut current_mining_block = None;
         let mut current_problem: Option<ProofOfWorkProblem> = None;
-        // FIXME: change to notification.
         let sleep_duration = time::Duration::from_millis(50);
+        let num_threads = max(num_threads, 1);
 
         let receiver: mpsc::Receiver<ProofOfWorkSolution> =
             if bg.pow_config.use_stratum {
                 BlockGenerator::start_new_stratum_worker(bg.clone())
             } else {
-                BlockGenerator::start_new_worker(1, bg.clone())
+                BlockGenerator::start_new_worker(num_threads, bg.clone())
             };
 
+        // Wake up to refresh the mining block as soon as the best block
+        // changes.
+        let (best_block_sender, best_block_receiver) = mpsc::channel();
+        let best_block_sender = Mutex::new(best_block_sender);
+        let observer_id = bg.graph.consensus.register_best_info_observer(
+            Box::new(move |_best_info| {
+                best_block_sender.lock().send(()).ok();
+            }),
+        );
+
         loop {
             match *bg.state.read() {
-                MiningState::Stop => return,
+                MiningState::Stop => break,
                 _ => {}
             }
 
             if bg.is_mining_block_outdated(current_mining_block.as_ref()) {
                 // TODO: #transations TBD
                 if !bg.pow_config.test_mode && bg.sync.catch_up_mode() {
-                    thread::sleep(sleep_duration);
+                    best_block_receiver.recv_timeout(sleep_duration).ok();
                     continue;
                 }
 
@@ -745,11 +1085,268 @@ impl BlockGenerator {
                     current_mining_block = None;
                     current_problem = None;
                 } else {
-                    // wait a moment and check again
-                    thread::sleep(sleep_duration);
+                    // wait for a new best block or a moment and check again
+                    best_block_receiver.recv_timeout(sleep_duration).ok();
+                    // drain the notifications of the same update
+                    while best_block_receiver.try_recv().is_ok() {}
                     continue;
                 }
             }
         }
+        bg.graph.consensus.unregister_best_info_observer(observer_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        latest_block_template, BlockTemplates, MiningJob,
+        BLOCK_TEMPLATE_REFRESH_TX_COUNT, MAX_BLOCK_TEMPLATES, NONCE_CHUNK_SIZE,
+    };
+    use cfx_types::{H256, U256};
+    use cfxcore::pow::{
+        compute, difficulty_to_boundary, validate, ProofOfWorkProblem,
+        ProofOfWorkSolution,
+    };
+    use parking_lot::Mutex;
+    use primitives::{Block, BlockHeaderBuilder};
+    use std::{
+        collections::HashSet,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        thread, time,
+    };
+
+    fn problem(boundary: H256) -> ProofOfWorkProblem {
+        ProofOfWorkProblem {
+            block_hash: H256::from_low_u64_be(1),
+            difficulty: U256::one(),
+            boundary,
+        }
+    }
+
+    #[test]
+    fn test_next_chunk() {
+        let job = MiningJob::with_start_nonce(
+            problem(H256::zero()),
+            u64::max_value() - NONCE_CHUNK_SIZE,
+        );
+        assert_eq!(job.next_chunk(), u64::max_value() - NONCE_CHUNK_SIZE);
+        // The nonce space wraps around.
+        assert_eq!(job.next_chunk(), u64::max_value());
+        assert_eq!(job.next_chunk(), NONCE_CHUNK_SIZE - 1);
+    }
+
+    #[test]
+    fn test_next_chunk_concurrently() {
+        let job =
+            Arc::new(MiningJob::with_start_nonce(problem(H256::zero()), 0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let job = job.clone();
+                thread::spawn(move || {
+                    (0..100).map(|_| job.next_chunk()).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        // Every chunk is taken by exactly one thread.
+        let mut chunks = HashSet::new();
+        for thread in threads {
+            for start in thread.join().unwrap() {
+                assert_eq!(start % NONCE_CHUNK_SIZE, 0);
+                assert!(chunks.insert(start));
+            }
+        }
+        assert_eq!(chunks.len(), 400);
+    }
+
+    fn min_hash_of_chunk(block_hash: &H256, start: u64) -> H256 {
+        (start..start + NONCE_CHUNK_SIZE)
+            .map(|nonce| compute(nonce, block_hash))
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_search_next_chunk() {
+        // No nonce is below the zero boundary.
+        let job = MiningJob::with_start_nonce(problem(H256::zero()), 0);
+        assert_eq!(job.search_next_chunk(), None);
+        assert!(!job.is_solved());
+        assert_eq!(job.next_chunk(), NONCE_CHUNK_SIZE);
+
+        // Choose a boundary which is only met by a nonce of the second chunk.
+        let (block_hash, boundary) = (1..)
+            .map(H256::from_low_u64_be)
+            .map(|block_hash| {
+                let boundary = min_hash_of_chunk(&block_hash, 0);
+                (block_hash, boundary)
+            })
+            .find(|(block_hash, boundary)| {
+                min_hash_of_chunk(block_hash, NONCE_CHUNK_SIZE) < *boundary
+            })
+            .unwrap();
+
+        let job = MiningJob::with_start_nonce(
+            ProofOfWorkProblem {
+                block_hash,
+                difficulty: U256::one(),
+                boundary,
+            },
+            0,
+        );
+        assert_eq!(job.search_next_chunk(), None);
+        assert!(!job.is_solved());
+        let nonce = job.search_next_chunk().unwrap();
+        assert!(job.is_solved());
+        assert!(nonce >= NONCE_CHUNK_SIZE && nonce < 2 * NONCE_CHUNK_SIZE);
+        assert!(validate(&job.problem, &ProofOfWorkSolution { nonce }));
+    }
+
+    #[test]
+    fn test_search_next_chunk_easiest() {
+        let job = MiningJob::with_start_nonce(
+            problem(difficulty_to_boundary(&U256::one())),
+            42,
+        );
+        assert_eq!(job.search_next_chunk(), Some(42));
+        assert!(job.is_solved());
+    }
+
+    fn block(parent: u64, difficulty: U256) -> Block {
+        Block::new(
+            BlockHeaderBuilder::new()
+                .with_parent_hash(H256::from_low_u64_be(parent))
+                .with_difficulty(difficulty)
+                .build(),
+            vec![],
+        )
+    }
+
+    #[test]
+    fn test_block_template_expired() {
+        let mut templates = BlockTemplates::default();
+        for _ in 0..=MAX_BLOCK_TEMPLATES {
+            templates.insert(block(1, U256::one()), 0);
+        }
+        let latest = templates.latest().unwrap();
+        assert_eq!(latest.template_id, MAX_BLOCK_TEMPLATES as u64);
+        assert_eq!(templates.blocks.len(), MAX_BLOCK_TEMPLATES);
+
+        // The oldest template is expired.
+        assert!(templates.solve(0, 0).unwrap_err().contains("Unknown"));
+        assert!(templates.solve(1, 0).is_ok());
+        // The template is unknown.
+        assert!(templates
+            .solve(MAX_BLOCK_TEMPLATES as u64 + 1, 0)
+            .unwrap_err()
+            .contains("Unknown"));
+    }
+
+    #[test]
+    fn test_block_template_solution() {
+        let mut templates = BlockTemplates::default();
+        // No nonce is below the boundary of the largest difficulty.
+        let hard = templates.insert(block(1, U256::max_value()), 0);
+        assert!(templates
+            .solve(hard.template_id, 0)
+            .unwrap_err()
+            .contains("Invalid nonce"));
+
+        let template = templates.insert(block(1, 2.into()), 0);
+        let problem = ProofOfWorkProblem {
+            block_hash: template.problem_hash,
+            difficulty: 2.into(),
+            boundary: template.boundary,
+        };
+        let valid =
+            |nonce: u64| validate(&problem, &ProofOfWorkSolution { nonce });
+        let nonce = (0..).find(|nonce| valid(*nonce)).unwrap();
+        let invalid_nonce = (0..).find(|nonce| !valid(*nonce)).unwrap();
+        assert!(templates.solve(template.template_id, invalid_nonce).is_err());
+
+        let solved = templates.solve(template.template_id, nonce).unwrap();
+        let mut expected = template.block_header.clone();
+        expected.set_nonce(nonce);
+        assert_eq!(solved.block_header.nonce(), nonce);
+        assert_eq!(solved.hash(), expected.hash());
+        assert_eq!(solved.block_header.problem_hash(), template.problem_hash);
+    }
+
+    #[test]
+    fn test_block_template_outdated() {
+        let mut templates = BlockTemplates::default();
+        let best = H256::from_low_u64_be(1);
+        assert!(templates.is_outdated(&best, 0));
+
+        templates.insert(block(1, U256::one()), 10);
+        assert!(!templates.is_outdated(&best, 10));
+        // The best block changed.
+        assert!(templates.is_outdated(&H256::from_low_u64_be(2), 10));
+        // Many transactions were received.
+        assert!(!templates
+            .is_outdated(&best, 10 + BLOCK_TEMPLATE_REFRESH_TX_COUNT - 1));
+        assert!(
+            templates.is_outdated(&best, 10 + BLOCK_TEMPLATE_REFRESH_TX_COUNT)
+        );
+    }
+
+    #[test]
+    fn test_block_template_subscribers() {
+        let mut templates = BlockTemplates::default();
+        let (sender, receiver) = mpsc::channel();
+        let (closed_sender, _) = mpsc::channel();
+        templates.subscribers.push(sender);
+        templates.subscribers.push(closed_sender);
+
+        let template = templates.insert(block(1, U256::one()), 0);
+        assert_eq!(
+            receiver.try_recv().unwrap().template_id,
+            template.template_id
+        );
+        // The disconnected subscribers are dropped.
+        assert_eq!(templates.subscribers.len(), 1);
+    }
+
+    #[test]
+    fn test_latest_block_template_assembled_once() {
+        let templates = Arc::new(Mutex::new(BlockTemplates::default()));
+        let assembling = Arc::new(Mutex::new(()));
+        let assembled = Arc::new(AtomicUsize::new(0));
+        let latest = |best: u64| {
+            let (templates, assembling, assembled) =
+                (templates.clone(), assembling.clone(), assembled.clone());
+            thread::spawn(move || {
+                latest_block_template(
+                    &templates,
+                    &assembling,
+                    |templates| {
+                        templates.is_outdated(&H256::from_low_u64_be(best), 0)
+                    },
+                    || {
+                        assembled.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(time::Duration::from_millis(50));
+                        (block(best, U256::one()), 0)
+                    },
+                )
+                .template_id
+            })
+        };
+
+        // The concurrent callers get the same template.
+        let threads: Vec<_> = (0..8).map(|_| latest(1)).collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 0);
+        }
+        assert_eq!(assembled.load(Ordering::SeqCst), 1);
+        assert_eq!(latest(1).join().unwrap(), 0);
+        assert_eq!(assembled.load(Ordering::SeqCst), 1);
+
+        // A new template is assembled after the best block changes.
+        assert_eq!(latest(2).join().unwrap(), 1);
+        assert_eq!(assembled.load(Ordering::SeqCst), 2);
     }
 }
@@ -165,7 +165,11 @@ impl StratumImpl {
         Ok(match params {
             Params::Array(vals) => {
                 // first two elements are service messages (worker_id & job_id)
-                match self.dispatcher.submit(vals.iter().skip(2)
+                let worker_id = match vals.first() {
+                    Some(Value::String(ref s)) => s.as_str(),
+                    _ => "",
+                };
+                match self.dispatcher.submit(worker_id, vals.iter().skip(2)
                     .filter_map(|val| match *val {
                         Value::String(ref s) => Some(s.to_owned()),
                         _ => None
@@ -295,7 +299,11 @@ mod tests {
     pub struct VoidManager;
 
     impl JobDispatcher for VoidManager {
-        fn submit(&self, _payload: Vec<String>) -> Result<(), Error> { Ok(()) }
+        fn submit(
+            &self, _worker_id: &str, _payload: Vec<String>,
+        ) -> Result<(), Error> {
+            Ok(())
+        }
     }
 
     fn dummy_request(addr: &SocketAddr, data: &str) -> Vec<u8> {
@@ -347,7 +355,11 @@ mod tests {
     }
 
     impl JobDispatcher for DummyManager {
-        fn submit(&self, _payload: Vec<String>) -> Result<(), Error> { Ok(()) }
+        fn submit(
+            &self, _worker_id: &str, _payload: Vec<String>,
+        ) -> Result<(), Error> {
+            Ok(())
+        }
     }
 
     fn terminated_str(origin: &'static str) -> String {
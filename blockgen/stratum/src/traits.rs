@@ -45,8 +45,10 @@ impl From<PushMessageError> for Error {
 
 /// Interface that can provide pow/blockchain-specific responses for the clients
 pub trait JobDispatcher: Send + Sync {
-    // miner job result
-    fn submit(&self, payload: Vec<String>) -> Result<(), Error>;
+    // miner job result of the worker
+    fn submit(
+        &self, worker_id: &str, payload: Vec<String>,
+    ) -> Result<(), Error>;
 }
 
 /// Interface that can handle requests to push job for workers
@@ -3,28 +3,119 @@
 // See http://www.gnu.org/licenses/
 
 use cfx_bytes::Bytes;
-use cfx_types::U256;
+use cfx_types::{Address, H256, U256};
 use cfxcore::{
-    executive::Executive,
+    executive::{contract_address, Executive},
     machine::new_machine_with_builtin,
-    state::State,
+    state::{CleanupMode, State},
     statedb::StateDb,
     storage::state_manager::{SnapshotAndEpochIdRef, StateManagerTrait},
-    vm::{Env, Spec},
+    transaction_pool::DEFAULT_MAX_BLOCK_GAS_LIMIT,
+    vm::{CreateContractAddress, Env, Spec},
     vm_factory::VmFactory,
+    ConsensusGraph,
+};
+use client::{
+    archive::{ArchiveClient, ArchiveClientHandle},
+    configuration::Configuration,
 };
-use client::{archive::ArchiveClient, configuration::Configuration};
 use criterion::{criterion_group, criterion_main, Criterion};
 use ethkey::{Generator, KeyPair, Random};
 use parking_lot::{Condvar, Mutex};
-use primitives::{Action, Transaction};
-use std::sync::Arc;
+use primitives::{
+    filter::Filter, Action, EpochNumber, SignedTransaction, Transaction,
+};
+use rustc_hex::FromHex;
+use std::{
+    sync::Arc,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+use tempdir::TempDir;
+
+/// The FixedSupplyToken contract in `tests/contracts/erc20.sol`, whose
+/// transfers emit a `Transfer` log.
+const ERC20_BYTECODE: &str =
+    include_str!("../../tests/contracts/erc20_bytecode.dat");
 
-fn txgen_benchmark(c: &mut Criterion) {
+/// The max time to wait for the generated blocks to be executed.
+const EXECUTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Start the client shared by all the benchmarks, whose databases are in
+/// `data_dir`.
+fn start_client(data_dir: &TempDir) -> ArchiveClientHandle {
     let mut conf = Configuration::default();
     conf.raw_conf.test_mode = true;
+    let path =
+        |name: &str| data_dir.path().join(name).to_string_lossy().into_owned();
+    conf.raw_conf.db_dir = Some(path("blockchain_db"));
+    conf.raw_conf.storage_db_path = path("storage_db");
     let exit = Arc::new((Mutex::new(false), Condvar::new()));
-    let handler = ArchiveClient::start(conf, exit.clone()).unwrap();
+    ArchiveClient::start(conf, exit).unwrap()
+}
+
+/// The genesis account with balance in test mode.
+fn genesis_key_pair() -> KeyPair {
+    KeyPair::from_secret(
+        "46b9e861b63d3509c88b7817275a30d22d62c8cd8fa6486ddee35ef0d8e0495f"
+            .parse()
+            .unwrap(),
+    )
+    .unwrap()
+}
+
+/// The state for executing the next epoch of the best block.
+fn best_state(consensus: &ConsensusGraph) -> State {
+    State::new(
+        StateDb::new(
+            consensus
+                .data_man
+                .storage_manager
+                .get_state_for_next_epoch(
+                    // FIXME: delta height
+                    SnapshotAndEpochIdRef::new(
+                        &consensus.best_block_hash(),
+                        None,
+                    ),
+                )
+                .unwrap()
+                .unwrap(),
+        ),
+        0.into(),
+        VmFactory::new(1024 * 32),
+    )
+}
+
+fn block_env(number: u64) -> Env {
+    Env {
+        number,
+        author: Default::default(),
+        timestamp: Default::default(),
+        difficulty: Default::default(),
+        gas_used: U256::zero(),
+        gas_limit: DEFAULT_MAX_BLOCK_GAS_LIMIT.into(),
+        last_hashes: Arc::new(vec![]),
+    }
+}
+
+fn sign(
+    kp: &KeyPair, nonce: U256, gas: u64, value: U256, action: Action,
+    data: Bytes,
+) -> SignedTransaction
+{
+    Transaction {
+        nonce,
+        gas_price: U256::from(100u64),
+        gas: U256::from(gas),
+        value,
+        action,
+        data,
+    }
+    .sign(kp.secret())
+}
+
+fn txgen_benchmark(c: &mut Criterion, handler: &Arc<ArchiveClientHandle>) {
+    let handler = handler.clone();
     c.bench_function("Randomly generate 1 transaction", move |b| {
         b.iter(|| {
             handler.txgen.generate_transaction();
@@ -32,21 +123,13 @@ fn txgen_benchmark(c: &mut Criterion) {
     });
 }
 
-fn txexe_benchmark(c: &mut Criterion) {
-    let mut conf = Configuration::default();
-    conf.raw_conf.test_mode = true;
-    let exit = Arc::new((Mutex::new(false), Condvar::new()));
-    let handler = ArchiveClient::start(conf, exit.clone()).unwrap();
-    let kp = KeyPair::from_secret(
-        "46b9e861b63d3509c88b7817275a30d22d62c8cd8fa6486ddee35ef0d8e0495f"
-            .parse()
-            .unwrap(),
-    )
-    .unwrap();
+fn txexe_benchmark(c: &mut Criterion, handler: &Arc<ArchiveClientHandle>) {
+    let handler = handler.clone();
+    let kp = genesis_key_pair();
     let receiver_kp = Random.generate().expect("Fail to generate KeyPair.");
 
     let tx = Transaction {
-        nonce: 0.into(),
+        nonce: best_state(&handler.consensus).nonce(&kp.address()).unwrap(),
         gas_price: U256::from(100u64),
         gas: U256::from(21000u64),
         value: 1.into(),
@@ -66,25 +149,7 @@ fn txexe_benchmark(c: &mut Criterion) {
     };
     let spec = Spec::new_spec();
     c.bench_function("Execute 1 transaction", move |b| {
-        let mut state = State::new(
-            StateDb::new(
-                handler
-                    .consensus
-                    .data_man
-                    .storage_manager
-                    .get_state_for_next_epoch(
-                        // FIXME: delta height
-                        SnapshotAndEpochIdRef::new(
-                            &handler.consensus.best_block_hash(),
-                            None,
-                        ),
-                    )
-                    .unwrap()
-                    .unwrap(),
-            ),
-            0.into(),
-            VmFactory::new(1024 * 32),
-        );
+        let mut state = best_state(&handler.consensus);
         let mut ex = Executive::new(&mut state, &env, &machine, &spec);
         let mut nonce_increased = false;
         b.iter(|| {
@@ -94,5 +159,182 @@ fn txexe_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, txgen_benchmark, txexe_benchmark);
+/// Execute the transfers of an epoch with (blocks, transactions per block),
+/// each to a new account, and commit the state of the epoch.
+fn epoch_execution_benchmark(
+    c: &mut Criterion, handler: &Arc<ArchiveClientHandle>,
+) {
+    let handler = handler.clone();
+    let kp = genesis_key_pair();
+    let machine = new_machine_with_builtin();
+    let spec = Spec::new_spec();
+    c.bench_function_over_inputs(
+        "Execute and commit 1 epoch",
+        move |b, &(num_blocks, num_txs): &(usize, usize)| {
+            let nonce =
+                best_state(&handler.consensus).nonce(&kp.address()).unwrap();
+            let txs: Vec<SignedTransaction> = (0..num_blocks * num_txs)
+                .map(|i| {
+                    sign(
+                        &kp,
+                        nonce + i,
+                        21000,
+                        1.into(),
+                        Action::Call(Address::random()),
+                        Bytes::new(),
+                    )
+                })
+                .collect();
+            b.iter_with_setup(
+                || best_state(&handler.consensus),
+                |mut state| {
+                    let mut nonce_increased = false;
+                    for (number, block_txs) in txs.chunks(num_txs).enumerate()
+                    {
+                        let env = block_env(number as u64);
+                        let mut ex =
+                            Executive::new(&mut state, &env, &machine, &spec);
+                        for tx in block_txs {
+                            ex.transact(tx, &mut nonce_increased).unwrap();
+                        }
+                    }
+                    state.commit(H256::random()).unwrap();
+                },
+            )
+        },
+        vec![(1, 100), (10, 100), (10, 1000)],
+    );
+}
+
+/// Commit the state with the given number of updated accounts into the MPT.
+fn mpt_commit_benchmark(c: &mut Criterion, handler: &Arc<ArchiveClientHandle>) {
+    let handler = handler.clone();
+    c.bench_function_over_inputs(
+        "Commit accounts into MPT",
+        move |b, &num_accounts: &usize| {
+            b.iter_with_setup(
+                || {
+                    let mut state = best_state(&handler.consensus);
+                    for _ in 0..num_accounts {
+                        state
+                            .add_balance(
+                                &Address::random(),
+                                &1.into(),
+                                CleanupMode::NoEmpty,
+                            )
+                            .unwrap();
+                    }
+                    state
+                },
+                |mut state| state.commit(H256::random()).unwrap(),
+            )
+        },
+        vec![1_000, 10_000, 100_000],
+    );
+}
+
+/// Query the `Transfer` logs of the given number of epochs, each with a block
+/// of 100 token transfers.
+fn logs_benchmark(c: &mut Criterion, handler: &Arc<ArchiveClientHandle>) {
+    const EPOCHS: u64 = 200;
+    const TRANSFERS_PER_EPOCH: usize = 100;
+
+    let handler = handler.clone();
+    let kp = genesis_key_pair();
+    let mut nonce =
+        best_state(&handler.consensus).nonce(&kp.address()).unwrap();
+
+    let code: Bytes = ERC20_BYTECODE.from_hex().unwrap();
+    let token = contract_address(
+        CreateContractAddress::FromSenderAndNonce,
+        &kp.address(),
+        &nonce,
+        &code,
+    )
+    .0;
+    let create = sign(&kp, nonce, 3_000_000, 0.into(), Action::Create, code);
+    handler
+        .blockgen
+        .generate_custom_block(vec![Arc::new(create)]);
+    nonce += 1.into();
+
+    // The data of `transfer(address,uint256)` with amount 1.
+    let mut data: Bytes = "a9059cbb".from_hex().unwrap();
+    data.extend_from_slice(&[0u8; 12]);
+    data.extend_from_slice(Address::random().as_ref());
+    data.extend_from_slice(H256::from_low_u64_be(1).as_ref());
+    let first_epoch = handler.consensus.best_epoch_number() + 1;
+    for _ in 0..EPOCHS {
+        let transfers = (0..TRANSFERS_PER_EPOCH)
+            .map(|_| {
+                let tx = sign(
+                    &kp,
+                    nonce,
+                    100_000,
+                    0.into(),
+                    Action::Call(token),
+                    data.clone(),
+                );
+                nonce += 1.into();
+                Arc::new(tx)
+            })
+            .collect();
+        handler.blockgen.generate_custom_block(transfers);
+    }
+    let last_epoch = first_epoch + EPOCHS - 1;
+    // The execution of an epoch is deferred until the pivot chain grows
+    // beyond it, and then it's executed asynchronously.
+    for _ in 0..handler.consensus.data_man.deferred_state_epoch_count() {
+        handler.blockgen.generate_custom_block(vec![]);
+    }
+    let deadline = Instant::now() + EXECUTION_TIMEOUT;
+    while handler.consensus.executed_best_state_epoch_number() < last_epoch {
+        assert!(
+            Instant::now() < deadline,
+            "Epoch {} is not executed in {:?}",
+            last_epoch,
+            EXECUTION_TIMEOUT
+        );
+        sleep(Duration::from_millis(10));
+    }
+
+    c.bench_function_over_inputs(
+        "Query logs of epochs",
+        move |b, &num_epochs: &u64| {
+            b.iter(|| {
+                let filter = Filter {
+                    from_epoch: EpochNumber::Number(
+                        last_epoch + 1 - num_epochs,
+                    ),
+                    to_epoch: EpochNumber::Number(last_epoch),
+                    block_hashes: None,
+                    address: Some(vec![token]),
+                    topics: vec![None, None, None, None],
+                    limit: None,
+                };
+                let logs = handler.consensus.logs(filter).unwrap();
+                assert_eq!(
+                    logs.len(),
+                    num_epochs as usize * TRANSFERS_PER_EPOCH
+                );
+            })
+        },
+        vec![1, 20, EPOCHS],
+    );
+}
+
+/// Run the benchmarks on one client, since each client binds the same ports
+/// and opens its own databases. The benchmarks run in order, and the ones
+/// generating blocks go last.
+fn client_benchmarks(c: &mut Criterion) {
+    let data_dir = TempDir::new("client_benchmark").unwrap();
+    let handler = Arc::new(start_client(&data_dir));
+    txgen_benchmark(c, &handler);
+    txexe_benchmark(c, &handler);
+    epoch_execution_benchmark(c, &handler);
+    mpt_commit_benchmark(c, &handler);
+    logs_benchmark(c, &handler);
+}
+
+criterion_group!(benches, client_benchmarks);
 criterion_main!(benches);
@@ -3,7 +3,12 @@
 // See http://www.gnu.org/licenses/
 
 use super::{
-    http::Server as HttpServer, tcp::Server as TcpServer, TESTNET_VERSION,
+    config_reload::{reload_on_sighup, ConfigReloader},
+    firehose::FirehoseSink,
+    http::Server as HttpServer,
+    tcp::Server as TcpServer,
+    ws::Server as WsServer,
+    TESTNET_VERSION,
 };
 pub use crate::configuration::Configuration;
 use blockgen::BlockGenerator;
@@ -11,22 +16,26 @@ use blockgen::BlockGenerator;
 use crate::rpc::{
     extractor::RpcExtractor,
     impls::{
-        cfx::RpcImpl, common::RpcImpl as CommonImpl, pubsub::PubSubClient,
+        cfx::RpcImpl,
+        common::RpcImpl as CommonImpl,
+        personal::{open_keystore, PersonalImpl},
+        pubsub::PubSubClient,
     },
-    setup_debug_rpc_apis, setup_public_rpc_apis,
+    setup_debug_rpc_apis, setup_local_rpc_apis, setup_public_rpc_apis,
+    QueryCache, StateHandles,
 };
 use cfx_types::{Address, U256};
 use cfxcore::{
     block_data_manager::BlockDataManager,
-    genesis,
+    genesis, migration,
     state_exposer::{SharedStateExposer, StateExposer},
     statistics::Statistics,
     storage::StorageManager,
     sync::SyncPhaseType,
-    transaction_pool::DEFAULT_MAX_BLOCK_GAS_LIMIT,
+    transaction_pool::{new_packing_strategy, DEFAULT_MAX_BLOCK_GAS_LIMIT},
     vm_factory::VmFactory,
     ConsensusGraph, LightProvider, SynchronizationGraph,
-    SynchronizationService, TransactionPool, WORKER_COMPUTATION_PARALLELISM,
+    SynchronizationService, TransactionPool,
 };
 use ctrlc::CtrlC;
 use db::SystemDB;
@@ -37,6 +46,7 @@ use runtime::Runtime;
 use secret_store::SecretStore;
 use std::{
     any::Any,
+    path::PathBuf,
     str::FromStr,
     sync::{Arc, Weak},
     thread,
@@ -51,6 +61,7 @@ use txgen::{
 pub struct ArchiveClientHandle {
     pub debug_rpc_http_server: Option<HttpServer>,
     pub rpc_tcp_server: Option<TcpServer>,
+    pub rpc_ws_server: Option<WsServer>,
     pub rpc_http_server: Option<HttpServer>,
     pub consensus: Arc<ConsensusGraph>,
     pub txpool: Arc<TransactionPool>,
@@ -74,6 +85,7 @@ impl ArchiveClientHandle {
                 self.consensus,
                 self.debug_rpc_http_server,
                 self.rpc_tcp_server,
+                self.rpc_ws_server,
                 self.rpc_http_server,
                 self.txpool,
                 self.sync,
@@ -94,6 +106,12 @@ impl ArchiveClient {
     ) -> Result<ArchiveClientHandle, String> {
         info!("Working directory: {:?}", std::env::current_dir());
 
+        if conf.raw_conf.block_body_horizon.is_some() {
+            return Err(
+                "block_body_horizon is not supported by archive nodes".into()
+            );
+        }
+
         if conf.raw_conf.metrics_enabled {
             metrics::enable();
             let reporter = metrics::FileReporter::new(
@@ -103,11 +121,17 @@ impl ArchiveClient {
                 reporter,
                 Duration::from_millis(conf.raw_conf.metrics_report_interval_ms),
             );
+            if let Some(port) = conf.raw_conf.metrics_prometheus_port {
+                super::prometheus_exporter::start(
+                    conf.raw_conf.metrics_prometheus_address,
+                    port,
+                )?;
+            }
         }
 
         let worker_thread_pool = Arc::new(Mutex::new(ThreadPool::with_name(
             "Tx Recover".into(),
-            WORKER_COMPUTATION_PARALLELISM,
+            conf.raw_conf.tx_recover_threads,
         )));
 
         let mut network_config = conf.net_config()?;
@@ -120,6 +144,12 @@ impl ArchiveClient {
             &db_config,
         )
         .map_err(|e| format!("Failed to open database {:?}", e))?;
+        migration::migrate(&ledger_db)?;
+        migration::check_deferred_state_epoch_count(
+            &ledger_db,
+            conf.raw_conf.deferred_state_epoch_count,
+            false, /* read_only */
+        )?;
 
         let secret_store = Arc::new(SecretStore::new());
         let storage_manager = Arc::new(StorageManager::new(
@@ -164,12 +194,15 @@ impl ArchiveClient {
 
         // FIXME: move genesis block to a dedicated directory near all conflux
         // FIXME: parameters.
-        let genesis_block = storage_manager.initialize(
-            genesis_accounts,
-            DEFAULT_MAX_BLOCK_GAS_LIMIT.into(),
-            Address::from_str(TESTNET_VERSION).unwrap(),
-            U256::zero(),
-        );
+        let genesis_block = match &conf.genesis_spec {
+            Some(spec) => storage_manager.initialize_with_spec(spec),
+            None => storage_manager.initialize(
+                genesis_accounts,
+                DEFAULT_MAX_BLOCK_GAS_LIMIT.into(),
+                Address::from_str(TESTNET_VERSION).unwrap(),
+                U256::zero(),
+            ),
+        };
         debug!("Initialize genesis_block={:?}", genesis_block);
 
         let data_man = Arc::new(BlockDataManager::new(
@@ -180,11 +213,21 @@ impl ArchiveClient {
             worker_thread_pool,
             conf.data_mananger_config(),
         ));
+        BlockDataManager::start_cache_warm_up(&data_man);
 
         let txpool = Arc::new(TransactionPool::with_capacity(
             conf.raw_conf.tx_pool_size,
+            conf.raw_conf.tx_pool_min_replace_gas_price_bump_percent,
+            conf.raw_conf.tx_pool_memory_budget_mb * 1024 * 1024,
             data_man.clone(),
+            conf.verification_config(),
         ));
+        if let Some(ref path) = conf.raw_conf.tx_pool_journal_path {
+            txpool.enable_journal(
+                PathBuf::from(path),
+                conf.raw_conf.tx_pool_journal_size,
+            );
+        }
 
         let statistics = Arc::new(Statistics::new());
 
@@ -270,17 +313,22 @@ impl ArchiveClient {
             special_txgen.clone(),
             pow_config.clone(),
             maybe_author.clone().unwrap_or_default(),
+            new_packing_strategy(
+                &conf.raw_conf.packing_strategy,
+                conf.raw_conf.packing_target_gas_percent,
+            )?,
         ));
         if conf.raw_conf.start_mining {
             if maybe_author.is_none() {
                 panic!("mining-author is not set correctly, so you'll not get mining rewards!!!");
             }
             let bg = blockgen.clone();
+            let mining_threads = conf.raw_conf.mining_threads;
             info!("Start mining with pow config: {:?}", pow_config);
             thread::Builder::new()
                 .name("mining".into())
                 .spawn(move || {
-                    BlockGenerator::start_mining(bg, 0);
+                    BlockGenerator::start_mining(bg, mining_threads);
                 })
                 .expect("Mining thread spawn error");
         }
@@ -288,7 +336,20 @@ impl ArchiveClient {
         let tx_conf = conf.tx_gen_config();
         let txgen_handle = if tx_conf.generate_tx {
             let txgen_clone = txgen.clone();
-            let t = if conf.raw_conf.test_mode {
+            let t = if let Some(workload) = tx_conf.workload {
+                let period = tx_conf.period;
+                thread::Builder::new()
+                    .name("txgen".into())
+                    .spawn(move || {
+                        TransactionGenerator::generate_workload(
+                            txgen_clone,
+                            period,
+                            workload,
+                        )
+                        .unwrap();
+                    })
+                    .expect("should succeed")
+            } else if conf.raw_conf.test_mode {
                 match conf.raw_conf.genesis_secrets {
                     Some(ref _file) => {
                         thread::Builder::new()
@@ -338,7 +399,46 @@ impl ArchiveClient {
             blockgen.clone(),
             txpool.clone(),
             txgen.clone(),
+            None,
+            QueryCache::new(
+                Duration::from_millis(conf.raw_conf.rpc_query_cache_ttl_ms),
+                conf.raw_conf.rpc_query_cache_size,
+            ),
+            StateHandles::new(
+                Duration::from_millis(
+                    conf.raw_conf.rpc_state_handle_max_ttl_ms,
+                ),
+                conf.raw_conf.rpc_state_handle_limit,
+            ),
+        ));
+
+        if let Some(interval_s) = conf.raw_conf.pivot_dump_interval_s {
+            super::pivot_dump::start(
+                Arc::downgrade(&consensus),
+                state_exposer.clone(),
+                Duration::from_secs(interval_s),
+                conf.raw_conf.pivot_dump_epoch_count,
+                conf.raw_conf.pivot_dump_file.clone(),
+                exit.clone(),
+            );
+        }
+
+        if let Some(ref sink) = conf.raw_conf.firehose_sink {
+            super::firehose::start(
+                &consensus,
+                FirehoseSink::parse(sink)?,
+                conf.raw_conf.firehose_cursor_path.clone(),
+                conf.raw_conf.firehose_queue_size,
+                exit.clone(),
+            )?;
+        }
+
+        let config_reloader = Arc::new(ConfigReloader::new(
+            conf.clone(),
+            consensus.clone(),
+            txpool.clone(),
         ));
+        reload_on_sighup(config_reloader.clone())?;
 
         let common_impl = Arc::new(CommonImpl::new(
             exit,
@@ -346,10 +446,33 @@ impl ArchiveClient {
             network.clone(),
             txpool.clone(),
             state_exposer.clone(),
+            config_reloader,
         ));
 
         let runtime = Runtime::with_default_thread_count();
-        let pubsub = PubSubClient::new(runtime.executor());
+        let pubsub = PubSubClient::new(
+            runtime.executor(),
+            consensus.clone(),
+            blockgen.clone(),
+            conf.pubsub_config(),
+        );
+
+        let personal = if conf.raw_conf.account_service {
+            if conf.raw_conf.jsonrpc_local_http_port.is_none() {
+                warn!("The account service is only served on the local rpc, which is disabled");
+            }
+            Some(PersonalImpl::new(
+                open_keystore(&conf.raw_conf.keystore_dir)?,
+                rpc_impl.clone(),
+                txpool.clone(),
+                conf.raw_conf.chain_id,
+                Duration::from_secs(
+                    conf.raw_conf.account_max_unlock_duration_s,
+                ),
+            ))
+        } else {
+            None
+        };
 
         let debug_rpc_http_server = super::rpc::start_http(
             super::rpc::HttpConfiguration::new(
@@ -358,7 +481,12 @@ impl ArchiveClient {
                 conf.raw_conf.jsonrpc_cors.clone(),
                 conf.raw_conf.jsonrpc_http_keep_alive,
             ),
-            setup_debug_rpc_apis(common_impl.clone(), rpc_impl.clone(), None),
+            setup_local_rpc_apis(
+                common_impl.clone(),
+                rpc_impl.clone(),
+                personal,
+            ),
+            conf.batch_config(),
         )?;
 
         let rpc_tcp_server = super::rpc::start_tcp(
@@ -366,6 +494,25 @@ impl ArchiveClient {
                 None,
                 conf.raw_conf.jsonrpc_tcp_port,
             ),
+            if conf.raw_conf.test_mode {
+                setup_debug_rpc_apis(
+                    common_impl.clone(),
+                    rpc_impl.clone(),
+                    Some(pubsub.clone()),
+                )
+            } else {
+                setup_public_rpc_apis(
+                    common_impl.clone(),
+                    rpc_impl.clone(),
+                    Some(pubsub.clone()),
+                )
+            },
+            conf.batch_config(),
+            RpcExtractor,
+        )?;
+
+        let rpc_ws_server = super::rpc::start_ws(
+            conf.ws_config(),
             if conf.raw_conf.test_mode {
                 setup_debug_rpc_apis(
                     common_impl.clone(),
@@ -379,6 +526,7 @@ impl ArchiveClient {
                     Some(pubsub),
                 )
             },
+            conf.batch_config(),
             RpcExtractor,
         )?;
 
@@ -402,6 +550,7 @@ impl ArchiveClient {
                     None,
                 )
             },
+            conf.batch_config(),
         )?;
 
         Ok(ArchiveClientHandle {
@@ -409,6 +558,7 @@ impl ArchiveClient {
             debug_rpc_http_server,
             rpc_http_server,
             rpc_tcp_server,
+            rpc_ws_server,
             txpool,
             txgen,
             txgen_join_handle: txgen_handle,
@@ -441,6 +591,8 @@ impl ArchiveClient {
     }
 
     pub fn close(handle: ArchiveClientHandle) {
+        handle.consensus.data_man.save_hot_cache_entries();
+        handle.consensus.data_man.close();
         let (ledger_db, blockgen, to_drop) = handle.into_be_dropped();
         BlockGenerator::stop(&blockgen);
         drop(blockgen);
@@ -0,0 +1,48 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::{
+    common::{
+        new_consensus_graph, new_data_manager, open_ledger_db,
+        recover_graph_from_db,
+    },
+    configuration::Configuration,
+};
+use std::fs;
+
+/// Recover the consensus graph from the local database without connecting to
+/// the network, and audit the blame information of the pivot blocks in
+/// `[from, to]`. The report is written as JSON to `output`, or to stdout if
+/// it is not specified.
+pub fn run(
+    conf: Configuration, from: u64, to: u64, output: Option<&str>,
+) -> Result<(), String> {
+    let data_man =
+        new_data_manager(&conf, open_ledger_db(&conf, false /* read_only */)?)?;
+    let consensus = new_consensus_graph(&conf, data_man);
+    let _sync_graph = recover_graph_from_db(&conf, consensus.clone());
+
+    info!("Start auditing the blame of epochs [{}, {}]", from, to);
+    let report = consensus.audit_blame(from, to)?;
+    let json = report.to_json();
+    match output {
+        Some(path) => fs::write(path, json)
+            .map_err(|e| format!("Failed to write {}: {:?}", path, e))?,
+        None => println!("{}", json),
+    }
+    for entry in report.entries.iter().filter(|e| !e.mismatches.is_empty()) {
+        error!(
+            "Pivot block {:?} at height {}: {}",
+            entry.pivot_hash,
+            entry.height,
+            entry.mismatches.join("; ")
+        );
+    }
+
+    if report.is_consistent() {
+        Ok(())
+    } else {
+        Err("Blame audit found mismatches".into())
+    }
+}
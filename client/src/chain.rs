@@ -0,0 +1,412 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+//! Export and import of the blocks of the local database in a portable
+//! archive, to move test networks and backups between machines.
+//!
+//! An archive is a sequence of records, each of which is a 4-byte big-endian
+//! length followed by the RLP of an `ArchiveRecord`. It starts with a
+//! `Header`, followed by the blocks in the order of the epochs they are
+//! executed in, so that the parent and referees of a block are always before
+//! it. A `Checkpoint` is written every `CHECKPOINT_EPOCH_INTERVAL` epochs and
+//! after the last epoch.
+
+use crate::{
+    common::{
+        new_consensus_graph, new_data_manager, open_ledger_db,
+        recover_graph_from_db,
+    },
+    configuration::Configuration,
+};
+use cfx_types::H256;
+use cfxcore::ConsensusGraph;
+use primitives::{Block, EpochNumber};
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+const ARCHIVE_VERSION: u8 = 1;
+const CHECKPOINT_EPOCH_INTERVAL: u64 = 1000;
+const MAX_RECORD_SIZE: usize = 64 * 1024 * 1024;
+/// The import fails if the consensus graph processes no block for this long
+/// while there are inserted blocks not processed yet.
+const PROCESS_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+const RECORD_HEADER: u8 = 0;
+const RECORD_BLOCK: u8 = 1;
+const RECORD_CHECKPOINT: u8 = 2;
+
+enum ArchiveRecord {
+    Header {
+        version: u8,
+        genesis_hash: H256,
+    },
+    Block(Block),
+    /// The pivot block of `epoch_number`, and the number of the blocks in
+    /// the archive up to this epoch.
+    Checkpoint {
+        epoch_number: u64,
+        pivot_hash: H256,
+        block_count: u64,
+    },
+}
+
+impl Encodable for ArchiveRecord {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self {
+            ArchiveRecord::Header {
+                version,
+                genesis_hash,
+            } => {
+                s.begin_list(3)
+                    .append(&RECORD_HEADER)
+                    .append(version)
+                    .append(genesis_hash);
+            }
+            ArchiveRecord::Block(block) => {
+                s.begin_list(2).append(&RECORD_BLOCK).append(block);
+            }
+            ArchiveRecord::Checkpoint {
+                epoch_number,
+                pivot_hash,
+                block_count,
+            } => {
+                s.begin_list(4)
+                    .append(&RECORD_CHECKPOINT)
+                    .append(epoch_number)
+                    .append(pivot_hash)
+                    .append(block_count);
+            }
+        }
+    }
+}
+
+impl Decodable for ArchiveRecord {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        match rlp.val_at::<u8>(0)? {
+            RECORD_HEADER => Ok(ArchiveRecord::Header {
+                version: rlp.val_at(1)?,
+                genesis_hash: rlp.val_at(2)?,
+            }),
+            RECORD_BLOCK => Ok(ArchiveRecord::Block(rlp.val_at(1)?)),
+            RECORD_CHECKPOINT => Ok(ArchiveRecord::Checkpoint {
+                epoch_number: rlp.val_at(1)?,
+                pivot_hash: rlp.val_at(2)?,
+                block_count: rlp.val_at(3)?,
+            }),
+            _ => Err(DecoderError::Custom("Unknown archive record type")),
+        }
+    }
+}
+
+fn write_record(
+    writer: &mut dyn Write, record: &ArchiveRecord,
+) -> Result<(), String> {
+    let payload = rlp::encode(record);
+    writer
+        .write_all(&(payload.len() as u32).to_be_bytes())
+        .and_then(|_| writer.write_all(&payload))
+        .map_err(|e| format!("Failed to write archive: {:?}", e))
+}
+
+/// Reads the next record, or `None` at the end of the archive.
+fn read_record(reader: &mut dyn Read) -> Result<Option<ArchiveRecord>, String> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            return Ok(None)
+        }
+        Err(e) => return Err(format!("Failed to read archive: {:?}", e)),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_RECORD_SIZE {
+        return Err(format!("Archive record of {} bytes is too large", len));
+    }
+    let mut payload = vec![0; len];
+    reader
+        .read_exact(&mut payload)
+        .map_err(|e| format!("Archive is truncated: {:?}", e))?;
+    rlp::decode(&payload)
+        .map(Some)
+        .map_err(|e| format!("Invalid archive record: {:?}", e))
+}
+
+/// Wait until the consensus graph has processed `count` blocks in total.
+fn wait_for_processed(
+    consensus: &ConsensusGraph, count: usize,
+) -> Result<(), String> {
+    let mut processed = consensus.get_processed_block_count();
+    let mut last_progress = Instant::now();
+    while processed < count {
+        thread::sleep(Duration::from_millis(100));
+        let now_processed = consensus.get_processed_block_count();
+        if now_processed > processed {
+            processed = now_processed;
+            last_progress = Instant::now();
+        } else if last_progress.elapsed() > PROCESS_STALL_TIMEOUT {
+            return Err(format!(
+                "The consensus graph has processed {} of {} blocks and made \
+                 no progress in {:?}",
+                processed, count, PROCESS_STALL_TIMEOUT
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Export the blocks of all the epochs recorded in the local database to the
+/// archive at `path`. The database is opened read-only, so a running node
+/// can be exported from a snapshot of its data directory.
+pub fn export(conf: Configuration, path: &str) -> Result<(), String> {
+    let data_man =
+        new_data_manager(&conf, open_ledger_db(&conf, true /* read_only */)?)?;
+    let file = File::create(path)
+        .map_err(|e| format!("Failed to create {}: {:?}", path, e))?;
+    let mut writer = BufWriter::new(file);
+
+    write_record(
+        &mut writer,
+        &ArchiveRecord::Header {
+            version: ARCHIVE_VERSION,
+            genesis_hash: data_man.genesis_block().hash(),
+        },
+    )?;
+
+    // The genesis block is rebuilt from the configuration on import.
+    let mut epoch_number = 1;
+    let mut block_count = 0;
+    let mut last_checkpoint = None;
+    while let Some(hashes) = data_man.epoch_set_hashes_from_db(epoch_number) {
+        for hash in &hashes {
+            let block = data_man.block_from_db(hash).ok_or_else(|| {
+                format!(
+                    "Body of block {:?} is not in the database, export from \
+                     an archive node instead",
+                    hash
+                )
+            })?;
+            write_record(&mut writer, &ArchiveRecord::Block(block))?;
+            block_count += 1;
+        }
+        let checkpoint = ArchiveRecord::Checkpoint {
+            epoch_number,
+            pivot_hash: *hashes.last().unwrap(),
+            block_count,
+        };
+        if epoch_number % CHECKPOINT_EPOCH_INTERVAL == 0 {
+            write_record(&mut writer, &checkpoint)?;
+            info!(
+                "Exported {} blocks of epochs [1, {}]",
+                block_count, epoch_number
+            );
+            last_checkpoint = None;
+        } else {
+            last_checkpoint = Some(checkpoint);
+        }
+        epoch_number += 1;
+    }
+    if let Some(checkpoint) = last_checkpoint {
+        write_record(&mut writer, &checkpoint)?;
+    }
+    writer
+        .flush()
+        .map_err(|e| format!("Failed to write archive: {:?}", e))?;
+
+    info!(
+        "Exported {} blocks of epochs [1, {}] to {}",
+        block_count,
+        epoch_number - 1,
+        path
+    );
+    Ok(())
+}
+
+/// Import the blocks of the archive at `path` into the local database. The
+/// blocks go through the verification of the synchronization graph and are
+/// executed by the consensus graph as if they were received from peers, and
+/// the pivot chain is checked against each checkpoint of the archive. The
+/// blocks already in the database are skipped, so an interrupted import can
+/// be resumed by importing the same archive again.
+pub fn import(conf: Configuration, path: &str) -> Result<(), String> {
+    let data_man =
+        new_data_manager(&conf, open_ledger_db(&conf, false /* read_only */)?)?;
+    let file = File::open(path)
+        .map_err(|e| format!("Failed to open {}: {:?}", path, e))?;
+    let mut reader = BufReader::new(file);
+
+    match read_record(&mut reader)? {
+        Some(ArchiveRecord::Header {
+            version,
+            genesis_hash,
+        }) => {
+            if version != ARCHIVE_VERSION {
+                return Err(format!("Unsupported archive version {}", version));
+            }
+            if genesis_hash != data_man.genesis_block().hash() {
+                return Err(format!(
+                    "Archive genesis {:?} does not match the local genesis \
+                     {:?}",
+                    genesis_hash,
+                    data_man.genesis_block().hash()
+                ));
+            }
+        }
+        _ => return Err(format!("{} is not a block archive", path)),
+    }
+
+    let consensus = new_consensus_graph(&conf, data_man.clone());
+    let sync_graph = recover_graph_from_db(&conf, consensus.clone());
+
+    // The graph-ready blocks are processed by the consensus worker in order,
+    // so all the inserted blocks which become graph-ready are processed once
+    // the count is reached.
+    let processed_before = consensus.get_processed_block_count();
+    let mut block_count = 0;
+    let mut inserted = 0;
+    let mut last_is_checkpoint = false;
+    while let Some(record) = read_record(&mut reader)? {
+        last_is_checkpoint = false;
+        match record {
+            ArchiveRecord::Header { .. } => {
+                return Err("Unexpected archive header".into());
+            }
+            ArchiveRecord::Block(mut block) => {
+                block_count += 1;
+                let hash = block.hash();
+                if sync_graph.contains_block(&hash) {
+                    continue;
+                }
+                let header = &block.block_header;
+                if !sync_graph.contains_block(header.parent_hash())
+                    || header
+                        .referee_hashes()
+                        .iter()
+                        .any(|referee| !sync_graph.contains_block(referee))
+                {
+                    return Err(format!(
+                        "Block {:?} is before its parent or referees in the \
+                         archive",
+                        hash
+                    ));
+                }
+                data_man.recover_block(&mut block).map_err(|e| {
+                    format!("Failed to recover block {:?}: {:?}", hash, e)
+                })?;
+                let (valid, _) = sync_graph.insert_block_header(
+                    &mut block.block_header,
+                    true,  // need_to_verify
+                    false, // bench_mode
+                    false, // insert_into_consensus
+                    true,  // persistent
+                );
+                if !valid {
+                    return Err(format!("Invalid block header {:?}", hash));
+                }
+                let (success, _) = sync_graph.insert_block(
+                    block, true,  /* need_to_verify */
+                    true,  /* persistent */
+                    false, /* recover_from_db */
+                );
+                if !success {
+                    return Err(format!("Invalid block {:?}", hash));
+                }
+                // A block inserted into the synchronization graph is sent to
+                // the consensus graph only if it's graph-ready.
+                if sync_graph.contains_block(&hash) {
+                    inserted += 1;
+                }
+            }
+            ArchiveRecord::Checkpoint {
+                epoch_number,
+                pivot_hash,
+                block_count: expected_block_count,
+            } => {
+                if block_count != expected_block_count {
+                    return Err(format!(
+                        "Archive has {} blocks before the checkpoint of epoch \
+                         {}, expected {}",
+                        block_count, epoch_number, expected_block_count
+                    ));
+                }
+                wait_for_processed(&consensus, processed_before + inserted)?;
+                let local_pivot_hash = consensus.get_hash_from_epoch_number(
+                    EpochNumber::Number(epoch_number),
+                )?;
+                if local_pivot_hash != pivot_hash {
+                    return Err(format!(
+                        "Pivot block {:?} of epoch {} does not match {:?} in \
+                         the archive",
+                        local_pivot_hash, epoch_number, pivot_hash
+                    ));
+                }
+                info!(
+                    "Imported {} blocks of epochs [1, {}], {} of which are new",
+                    block_count, epoch_number, inserted
+                );
+                last_is_checkpoint = true;
+            }
+        }
+    }
+
+    if !last_is_checkpoint {
+        return Err(format!(
+            "Archive {} does not end with a checkpoint, it may be truncated",
+            path
+        ));
+    }
+    info!("Imported {} new blocks from {}", inserted, path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_record, write_record, ArchiveRecord};
+    use cfx_types::H256;
+    use primitives::{Block, BlockHeaderBuilder};
+
+    #[test]
+    fn test_archive_record_round_trip() {
+        let block = Block::new(
+            BlockHeaderBuilder::new()
+                .with_height(1)
+                .with_parent_hash(H256::from_low_u64_be(1))
+                .build(),
+            Vec::new(),
+        );
+        let records = vec![
+            ArchiveRecord::Header {
+                version: 1,
+                genesis_hash: H256::from_low_u64_be(2),
+            },
+            ArchiveRecord::Block(block),
+            ArchiveRecord::Checkpoint {
+                epoch_number: 1,
+                pivot_hash: H256::from_low_u64_be(3),
+                block_count: 1,
+            },
+        ];
+        let mut archive = Vec::new();
+        for record in &records {
+            write_record(&mut archive, record).unwrap();
+        }
+
+        let mut reader = &archive[..];
+        for record in &records {
+            let decoded = read_record(&mut reader).unwrap().unwrap();
+            assert_eq!(rlp::encode(&decoded), rlp::encode(record));
+        }
+        assert!(read_record(&mut reader).unwrap().is_none());
+
+        // A truncated record is an error rather than the end of the archive.
+        let mut reader = &archive[..archive.len() - 1];
+        read_record(&mut reader).unwrap();
+        read_record(&mut reader).unwrap();
+        assert!(read_record(&mut reader).is_err());
+    }
+}
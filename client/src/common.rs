@@ -0,0 +1,143 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+//! The construction of the data manager and the consensus graph shared by
+//! the offline subcommands, which work on the local database without
+//! connecting to the network.
+
+use super::TESTNET_VERSION;
+use crate::configuration::Configuration;
+use cfx_types::{Address, U256};
+use cfxcore::{
+    block_data_manager::BlockDataManager,
+    genesis, migration,
+    state_exposer::{SharedStateExposer, StateExposer},
+    statistics::Statistics,
+    storage::StorageManager,
+    transaction_pool::DEFAULT_MAX_BLOCK_GAS_LIMIT,
+    vm_factory::VmFactory,
+    ConsensusGraph, SynchronizationGraph, TransactionPool,
+};
+use db::SystemDB;
+use parking_lot::Mutex;
+use primitives::Block;
+use secret_store::SecretStore;
+use std::{str::FromStr, sync::Arc};
+use threadpool::ThreadPool;
+
+/// Open the local database. A read-only database is not migrated, so its
+/// schema version must be the current one.
+pub(crate) fn open_ledger_db(
+    conf: &Configuration, read_only: bool,
+) -> Result<Arc<SystemDB>, String> {
+    let mut db_config = conf.db_config();
+    db_config.read_only = read_only;
+    let ledger_db =
+        db::open_database(conf.raw_conf.db_dir.as_ref().unwrap(), &db_config)
+            .map_err(|e| format!("Failed to open database {:?}", e))?;
+    if read_only {
+        migration::check_schema_version(&ledger_db)?;
+    } else {
+        migration::migrate(&ledger_db)?;
+    }
+    migration::check_deferred_state_epoch_count(
+        &ledger_db,
+        conf.raw_conf.deferred_state_epoch_count,
+        read_only,
+    )?;
+    Ok(ledger_db)
+}
+
+/// Build the genesis block of the configuration. Its state is committed to
+/// `storage_manager`, which is discarded if the database is read-only.
+fn initialize_genesis(
+    conf: &Configuration, storage_manager: &StorageManager,
+) -> Result<Block, String> {
+    let secret_store = SecretStore::new();
+    let genesis_accounts = if conf.raw_conf.test_mode {
+        match conf.raw_conf.genesis_secrets {
+            Some(ref file) => {
+                genesis::default(&secret_store);
+                genesis::load_secrets_file(file, &secret_store)?
+            }
+            None => genesis::default(&secret_store),
+        }
+    } else {
+        match conf.raw_conf.genesis_accounts {
+            Some(ref file) => genesis::load_file(file)?,
+            None => genesis::default(&secret_store),
+        }
+    };
+    let genesis_block = match &conf.genesis_spec {
+        Some(spec) => storage_manager.initialize_with_spec(spec),
+        None => storage_manager.initialize(
+            genesis_accounts,
+            DEFAULT_MAX_BLOCK_GAS_LIMIT.into(),
+            Address::from_str(TESTNET_VERSION).unwrap(),
+            U256::zero(),
+        ),
+    };
+    debug!("Initialize genesis_block={:?}", genesis_block);
+    Ok(genesis_block)
+}
+
+pub(crate) fn new_data_manager(
+    conf: &Configuration, ledger_db: Arc<SystemDB>,
+) -> Result<Arc<BlockDataManager>, String> {
+    let worker_thread_pool = Arc::new(Mutex::new(ThreadPool::with_name(
+        "Tx Recover".into(),
+        conf.raw_conf.tx_recover_threads,
+    )));
+    let storage_manager = Arc::new(StorageManager::new(
+        ledger_db.clone(),
+        conf.storage_config(),
+    ));
+    let genesis_block = initialize_genesis(conf, &storage_manager)?;
+
+    Ok(Arc::new(BlockDataManager::new(
+        conf.cache_config(),
+        Arc::new(genesis_block),
+        ledger_db,
+        storage_manager,
+        worker_thread_pool,
+        conf.data_mananger_config(),
+    )))
+}
+
+/// Build a consensus graph on `data_man` with a transaction pool of its own.
+pub(crate) fn new_consensus_graph(
+    conf: &Configuration, data_man: Arc<BlockDataManager>,
+) -> Arc<ConsensusGraph> {
+    let txpool = Arc::new(TransactionPool::with_capacity(
+        conf.raw_conf.tx_pool_size,
+        conf.raw_conf.tx_pool_min_replace_gas_price_bump_percent,
+        conf.raw_conf.tx_pool_memory_budget_mb * 1024 * 1024,
+        data_man.clone(),
+        conf.verification_config(),
+    ));
+    Arc::new(ConsensusGraph::new(
+        conf.consensus_config(),
+        VmFactory::new(1024 * 32),
+        txpool,
+        Arc::new(Statistics::new()),
+        data_man,
+        conf.pow_config(),
+        SharedStateExposer::new(StateExposer::new()),
+    ))
+}
+
+/// Recover the block DAG persisted in the database into `consensus`, as an
+/// archive node does after a restart.
+pub(crate) fn recover_graph_from_db(
+    conf: &Configuration, consensus: Arc<ConsensusGraph>,
+) -> SynchronizationGraph {
+    let sync_graph = SynchronizationGraph::new(
+        consensus,
+        conf.verification_config(),
+        conf.pow_config(),
+        false, /* is_full_node */
+    );
+    sync_graph.recover_graph_from_db(false /* header_only */);
+    sync_graph
+}
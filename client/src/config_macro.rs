@@ -68,36 +68,8 @@ macro_rules! build_config{
             pub fn parse(matches: &clap::ArgMatches) -> Result<RawConfiguration, String> {
                 let mut config = RawConfiguration::default();
 
-                let config_filename = matches.value_of("config");
-                if config_filename.is_some() {
-                    let mut config_file = File::open(config_filename.unwrap())
-                        .map_err(|e| format!("failed to open configuration file: {:?}", e))?;
-
-                    let mut config_str = String::new();
-                    config_file
-                        .read_to_string(&mut config_str)
-                        .map_err(|e| format!("failed to read configuration file: {:?}", e))?;
-
-                    let config_value = config_str.parse::<toml::Value>()
-                        .map_err(|e| format!("failed to parse configuration file: {:?}", e))?;
-                    $(
-                        if let Some(value) = config_value.get(stringify!($name)) {
-                            config.$name = if_option!(
-                                $($type)+,
-                                THEN{ Some(value.clone().try_into().map_err(|_| concat!("Invalid ", stringify!($name)).to_owned())?) }
-                                ELSE{ value.clone().try_into().map_err(|_| concat!("Invalid ", stringify!($name)).to_owned())? }
-                            );
-                        }
-                    )*
-                    $(
-                        if let Some(value) = config_value.get(stringify!($c_name)) {
-                            config.$c_name = if_option!(
-                                $($c_type)+,
-                                THEN{ Some($converter(value.as_str().unwrap())?) }
-                                ELSE{ $converter(value.as_str().unwrap())? }
-                            )
-                        }
-                    )*
+                if let Some(config_filename) = matches.value_of("config") {
+                    config.load_file(config_filename)?;
                 }
 
                 $(
@@ -120,6 +92,85 @@ macro_rules! build_config{
                 )*
                 Ok(config)
             }
+
+            // Replace the parameters given in the config file.
+            pub fn load_file(&mut self, config_filename: &str) -> Result<(), String> {
+                let mut config_file = File::open(config_filename)
+                    .map_err(|e| format!("failed to open configuration file: {:?}", e))?;
+
+                let mut config_str = String::new();
+                config_file
+                    .read_to_string(&mut config_str)
+                    .map_err(|e| format!("failed to read configuration file: {:?}", e))?;
+
+                let config_value = config_str.parse::<toml::Value>()
+                    .map_err(|e| format!("failed to parse configuration file: {:?}", e))?;
+                $(
+                    if let Some(value) = config_value.get(stringify!($name)) {
+                        self.$name = if_option!(
+                            $($type)+,
+                            THEN{ Some(value.clone().try_into().map_err(|_| concat!("Invalid ", stringify!($name)).to_owned())?) }
+                            ELSE{ value.clone().try_into().map_err(|_| concat!("Invalid ", stringify!($name)).to_owned())? }
+                        );
+                    }
+                )*
+                $(
+                    if let Some(value) = config_value.get(stringify!($c_name)) {
+                        self.$c_name = if_option!(
+                            $($c_type)+,
+                            THEN{ Some($converter(value.as_str().unwrap())?) }
+                            ELSE{ $converter(value.as_str().unwrap())? }
+                        )
+                    }
+                )*
+                Ok(())
+            }
+
+            // The names of the parameters given in commandline.
+            pub fn cli_keys(matches: &clap::ArgMatches) -> Vec<&'static str> {
+                let mut keys = Vec::new();
+                $(
+                    if matches.is_present(underscore_to_hyphen!(stringify!($name))) {
+                        keys.push(stringify!($name));
+                    }
+                )*
+                $(
+                    if matches.is_present(underscore_to_hyphen!(stringify!($c_name))) {
+                        keys.push(stringify!($c_name));
+                    }
+                )*
+                keys
+            }
+
+            // The names of the parameters whose values differ from `other`.
+            pub fn diff(&self, other: &RawConfiguration) -> Vec<&'static str> {
+                let mut keys = Vec::new();
+                $(
+                    if self.$name != other.$name {
+                        keys.push(stringify!($name));
+                    }
+                )*
+                $(
+                    if self.$c_name != other.$c_name {
+                        keys.push(stringify!($c_name));
+                    }
+                )*
+                keys
+            }
+
+            // Copy the values of the specified parameters from `other`.
+            pub fn copy_from(&mut self, other: &RawConfiguration, keys: &[&str]) {
+                $(
+                    if keys.contains(&stringify!($name)) {
+                        self.$name = other.$name.clone();
+                    }
+                )*
+                $(
+                    if keys.contains(&stringify!($c_name)) {
+                        self.$c_name = other.$c_name.clone();
+                    }
+                )*
+            }
         }
     }
 }
@@ -0,0 +1,250 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::{configuration::Configuration, log_control};
+use cfxcore::{SharedConsensusGraph, SharedTransactionPool};
+use network::throttling::THROTTLING_SERVICE;
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// The parameters that can be changed without restarting the node.
+const RELOADABLE_KEYS: [&str; 9] = [
+    "ledger_cache_size",
+    "tx_pool_size",
+    "tx_pool_memory_budget_mb",
+    "log_level",
+    "gas_price_block_sample_size",
+    "gas_price_transaction_sample_size",
+    "egress_queue_capacity",
+    "egress_min_throttle",
+    "egress_max_throttle",
+];
+const CACHE_KEYS: &[&str] = &["ledger_cache_size"];
+const TX_POOL_KEYS: &[&str] = &["tx_pool_size", "tx_pool_memory_budget_mb"];
+const GAS_PRICE_KEYS: &[&str] = &[
+    "gas_price_block_sample_size",
+    "gas_price_transaction_sample_size",
+];
+const THROTTLING_KEYS: &[&str] = &[
+    "egress_queue_capacity",
+    "egress_min_throttle",
+    "egress_max_throttle",
+];
+
+/// The changed parameters found by a configuration reload.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReloadReport {
+    /// Parameters applied at runtime.
+    pub applied: Vec<String>,
+    /// Parameters that only take effect after the node restarts.
+    pub restart_required: Vec<String>,
+}
+
+/// Reloads the configuration file at runtime, and applies the changes of the
+/// parameters in `RELOADABLE_KEYS` to the running components.
+pub struct ConfigReloader {
+    conf: Mutex<Configuration>,
+    consensus: SharedConsensusGraph,
+    tx_pool: SharedTransactionPool,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        conf: Configuration, consensus: SharedConsensusGraph,
+        tx_pool: SharedTransactionPool,
+    ) -> Self
+    {
+        ConfigReloader {
+            conf: Mutex::new(conf),
+            consensus,
+            tx_pool,
+        }
+    }
+
+    /// Read the configuration file again and apply the changed parameters
+    /// that are safe to change at runtime. Nothing is applied if any of them
+    /// is invalid. The parameters given in commandline are not changed.
+    pub fn reload(&self) -> Result<ReloadReport, String> {
+        let mut conf = self.conf.lock();
+        let new_conf = Configuration {
+            raw_conf: conf.reload_raw_conf()?,
+            ..conf.clone()
+        };
+        let (applied, restart_required): (Vec<_>, Vec<_>) = conf
+            .raw_conf
+            .diff(&new_conf.raw_conf)
+            .into_iter()
+            .partition(|key| RELOADABLE_KEYS.contains(key));
+
+        self.apply(&new_conf, &applied)?;
+        conf.raw_conf.copy_from(&new_conf.raw_conf, &applied);
+
+        let report = ReloadReport {
+            applied: applied.into_iter().map(Into::into).collect(),
+            restart_required: restart_required
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        };
+        info!("Configuration reloaded: {:?}", report);
+        Ok(report)
+    }
+
+    fn apply(&self, conf: &Configuration, keys: &[&str]) -> Result<(), String> {
+        validate(conf, keys)?;
+        let changed = |group: &[&str]| group.iter().any(|k| keys.contains(k));
+        let raw_conf = &conf.raw_conf;
+
+        // Reloading the log configuration may fail, e.g. if the `log_conf`
+        // file is broken, so it is applied first.
+        if changed(&["log_level"]) {
+            log_control::reload(raw_conf.log_level)?;
+        }
+        if changed(THROTTLING_KEYS) {
+            THROTTLING_SERVICE.write().reconfigure(
+                raw_conf.egress_queue_capacity,
+                raw_conf.egress_min_throttle,
+                raw_conf.egress_max_throttle,
+            )?;
+        }
+        if changed(CACHE_KEYS) {
+            self.consensus
+                .data_man
+                .set_ledger_cache_size(conf.cache_config().ledger_mb());
+        }
+        if changed(TX_POOL_KEYS) {
+            self.tx_pool.set_limits(
+                raw_conf.tx_pool_size,
+                raw_conf.tx_pool_memory_budget_mb * 1024 * 1024,
+            );
+        }
+        if changed(GAS_PRICE_KEYS) {
+            self.consensus
+                .set_gas_price_oracle_config(conf.gas_price_oracle_config());
+        }
+        Ok(())
+    }
+}
+
+/// Check that the changes of the parameters in `keys` can be applied, so
+/// that nothing is applied if any of them is invalid.
+fn validate(conf: &Configuration, keys: &[&str]) -> Result<(), String> {
+    let changed = |group: &[&str]| group.iter().any(|k| keys.contains(k));
+    let raw_conf = &conf.raw_conf;
+
+    if changed(CACHE_KEYS) && conf.cache_config().ledger_mb() == 0 {
+        return Err("ledger_cache_size should be positive".into());
+    }
+    if changed(TX_POOL_KEYS)
+        && (raw_conf.tx_pool_size == 0
+            || raw_conf.tx_pool_memory_budget_mb == 0)
+    {
+        return Err("Transaction pool limits should be positive".into());
+    }
+    let gas_price_oracle = conf.gas_price_oracle_config();
+    if changed(GAS_PRICE_KEYS)
+        && (gas_price_oracle.block_sample_size == 0
+            || gas_price_oracle.transaction_sample_size == 0)
+    {
+        return Err("Gas price sample sizes should be positive".into());
+    }
+    if changed(THROTTLING_KEYS) {
+        THROTTLING_SERVICE.read().clone().reconfigure(
+            raw_conf.egress_queue_capacity,
+            raw_conf.egress_min_throttle,
+            raw_conf.egress_max_throttle,
+        )?;
+    }
+    Ok(())
+}
+
+/// Reload the configuration whenever the process receives SIGHUP.
+#[cfg(unix)]
+pub fn reload_on_sighup(reloader: Arc<ConfigReloader>) -> Result<(), String> {
+    use signal_hook::{iterator::Signals, SIGHUP};
+
+    let signals = Signals::new(&[SIGHUP])
+        .map_err(|e| format!("failed to register SIGHUP handler: {:?}", e))?;
+    std::thread::Builder::new()
+        .name("config_reload".into())
+        .spawn(move || {
+            for _ in signals.forever() {
+                if let Err(e) = reloader.reload() {
+                    warn!("Failed to reload configuration: {}", e);
+                }
+            }
+        })
+        .map_err(|e| {
+            format!("failed to spawn config reload thread: {:?}", e)
+        })?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_sighup(_reloader: Arc<ConfigReloader>) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, RELOADABLE_KEYS};
+    use crate::configuration::{Configuration, RawConfiguration};
+    use log::LevelFilter;
+
+    #[test]
+    fn test_diff_and_copy_from() {
+        let old = RawConfiguration::default();
+        let mut new = old.clone();
+        new.network_id = 2;
+        new.tx_pool_size = 1000;
+        new.egress_max_throttle = 128;
+        new.log_level = LevelFilter::Debug;
+
+        let keys = old.diff(&new);
+        assert_eq!(
+            keys,
+            vec![
+                "network_id",
+                "tx_pool_size",
+                "egress_max_throttle",
+                "log_level"
+            ]
+        );
+        let (applied, restart_required): (Vec<_>, Vec<_>) = keys
+            .into_iter()
+            .partition(|key| RELOADABLE_KEYS.contains(key));
+        assert_eq!(restart_required, vec!["network_id"]);
+
+        // Only the applied parameters are copied.
+        let mut conf = old.clone();
+        conf.copy_from(&new, &applied);
+        assert_eq!(conf.diff(&new), vec!["network_id"]);
+        assert_eq!(conf.tx_pool_size, 1000);
+        assert_eq!(conf.egress_max_throttle, 128);
+        assert_eq!(conf.log_level, LevelFilter::Debug);
+        assert_eq!(conf.network_id, old.network_id);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut conf = Configuration::default();
+        assert_eq!(validate(&conf, &RELOADABLE_KEYS), Ok(()));
+
+        conf.raw_conf.tx_pool_size = 0;
+        // The parameters not changed are not validated.
+        assert_eq!(validate(&conf, &["log_level"]), Ok(()));
+        assert!(validate(&conf, &["tx_pool_size"]).is_err());
+        conf.raw_conf.tx_pool_size = 1000;
+
+        conf.raw_conf.gas_price_transaction_sample_size = 0;
+        assert!(validate(&conf, &["gas_price_block_sample_size"]).is_err());
+        conf.raw_conf.gas_price_transaction_sample_size = 10;
+
+        conf.raw_conf.egress_min_throttle = conf.raw_conf.egress_max_throttle;
+        assert!(validate(&conf, &["egress_min_throttle"]).is_err());
+        conf.raw_conf.egress_min_throttle = 20;
+        assert_eq!(validate(&conf, &RELOADABLE_KEYS), Ok(()));
+    }
+}
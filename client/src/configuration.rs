@@ -2,16 +2,29 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
+use crate::rpc::{
+    impls::pubsub::PubSubConfiguration, BatchConfiguration, WsConfiguration,
+};
 use cfx_types::H256;
 use cfxcore::{
-    block_data_manager::{DataManagerConfiguration, DbType},
-    consensus::{ConsensusConfig, ConsensusInnerConfig},
+    block_data_manager::{CacheWarmUpConfig, DataManagerConfiguration, DbType},
+    block_parameters::{ACCEPTABLE_TIME_DRIFT, VALID_TIME_DRIFT},
+    consensus::{
+        new_referee_selector, ConsensusConfig, ConsensusInnerConfig,
+        DefaultGhastRule, GasPriceOracleConfig, StableHashTrustPolicy,
+        StableHashVerificationConfig,
+    },
     consensus_parameters::*,
+    genesis::{self, GenesisSpec},
+    light_protocol::LightConfiguration,
+    machine::new_machine_with_builtin,
     storage::{self, state_manager::StorageConfiguration},
     sync::ProtocolConfiguration,
+    transaction_pool::DEFAULT_MAX_BLOCK_GAS_LIMIT,
 };
-use std::convert::TryInto;
-use txgen::TransactionGeneratorConfig;
+use primitives::transaction::MAX_CHAIN_ID;
+use std::{convert::TryInto, net::Ipv4Addr, sync::Arc};
+use txgen::{ArrivalPattern, TransactionGeneratorConfig, WorkloadConfig};
 
 // usage:
 // ```
@@ -40,11 +53,25 @@ build_config! {
         (jsonrpc_http_port, (Option<u16>), None)
         (jsonrpc_cors, (Option<String>), None)
         (jsonrpc_http_keep_alive, (bool), false)
+        (jsonrpc_ws_port, (Option<u16>), None)
+        (jsonrpc_ws_max_connections, (usize), 100)
+        (pubsub_max_subscriptions_per_connection, (usize), 100)
+        (pubsub_max_pending_notifications, (usize), 1000)
+        (account_service, (bool), false)
+        (keystore_dir, (String), "./keystore".to_string())
+        (account_max_unlock_duration_s, (u64), 3600)
         (genesis_accounts, (Option<String>), None)
         (genesis_secrets, (Option<String>), None)
+        (genesis_spec, (Option<String>), None)
         (log_conf, (Option<String>), None)
         (log_file, (Option<String>), None)
+        (log_json, (bool), false)
         (network_id, (u64), 1)
+        (chain_id, (u64), 1)
+        (chain_id_enforcement_height, (Option<u64>), None)
+        (median_time_past_activation_height, (Option<u64>), None)
+        (valid_time_drift_secs, (u64), VALID_TIME_DRIFT)
+        (acceptable_time_drift_secs, (u64), ACCEPTABLE_TIME_DRIFT)
         (bootnodes, (Option<String>), None)
         (netconf_dir, (Option<String>), Some("./net_config".to_string()))
         (net_key, (Option<String>), None)
@@ -56,6 +83,11 @@ build_config! {
         (discovery_housekeeping_timeout_ms, (u64), 1000)
         (node_table_timeout, (Option<u64>), Some(300))
         (node_table_promotion_timeout, (Option<u64>), Some(3 * 24 * 3600))
+        (session_ping_interval_ms, (u64), 30_000)
+        (session_ping_timeout_ms, (u64), 30_000)
+        (session_idle_timeout_ms, (Option<u64>), None)
+        (send_queue_max_bytes, (usize), 64 * 1024 * 1024)
+        (send_queue_congestion_latency_ms, (u64), 5_000)
         (test_mode, (bool), false)
         (db_cache_size, (Option<usize>), Some(128))
         (db_compaction_profile, (Option<String>), None)
@@ -68,20 +100,34 @@ build_config! {
         (storage_recent_lfu_factor, (f64), storage::defaults::DEFAULT_RECENT_LFU_FACTOR)
         (storage_idle_size, (u32), storage::defaults::DEFAULT_IDLE_SIZE)
         (storage_node_map_size, (u32), storage::defaults::MAX_CACHED_TRIE_NODES_R_LFU_COUNTER)
+        (archive_state_snapshot_interval, (Option<u64>), None)
+        (storage_dedup_trie_nodes, (bool), false)
+        (storage_external_value_threshold, (Option<usize>), None)
+        (storage_slab_max_idle_size, (Option<u32>), None)
         (send_tx_period_ms, (u64), 1300)
         (check_request_period_ms, (u64), 1000)
         (block_cache_gc_period_ms, (u64), 5000)
         (headers_request_timeout_ms, (u64), 10_000)
         (blocks_request_timeout_ms, (u64), 30_000)
         (transaction_request_timeout_ms, (u64), 30_000)
+        (max_request_timeout_ms, (u64), 120_000)
+        (min_request_timeout_ms, (Option<u64>), None)
+        (consistency_check_period_ms, (Option<u64>), None)
         (tx_maintained_for_peer_timeout_ms, (u64), 600_000)
         (max_inflight_request_count, (u64), 64)
         (received_tx_index_maintain_timeout_ms, (u64), 600_000)
         (max_trans_count_received_in_catch_up, (u64), 60_000)
         (request_block_with_public, (bool), false)
         (start_mining, (bool), false)
+        (mining_threads, (u32), 1)
+        (packing_strategy, (String), "random".to_string())
+        (packing_target_gas_percent, (u64), 100)
         (initial_difficulty, (Option<u64>), None)
         (tx_pool_size, (usize), 500_000)
+        (tx_pool_min_replace_gas_price_bump_percent, (u64), 10)
+        (tx_pool_memory_budget_mb, (usize), 1024)
+        (tx_pool_journal_path, (Option<String>), None)
+        (tx_pool_journal_size, (usize), 100_000)
         (mining_author, (Option<String>), None)
         (use_stratum, (bool), false)
         (stratum_port, (u16), 32525)
@@ -90,6 +136,7 @@ build_config! {
         (egress_min_throttle, (usize), 10)
         (egress_max_throttle, (usize), 64)
         (subnet_quota, (usize), 32)
+        (max_outgoing_peers_per_subnet, (usize), 4)
         (session_ip_limits, (String), "1,8,4,2".into())
         (data_propagate_enabled, (bool), false)
         (data_propagate_interval_ms, (u64), 1000)
@@ -103,21 +150,77 @@ build_config! {
         (heavy_block_difficulty_ratio, (u64), HEAVY_BLOCK_DEFAULT_DIFFICULTY_RATIO)
         (era_epoch_count, (u64), ERA_DEFAULT_EPOCH_COUNT)
         (era_checkpoint_gap, (u64), ERA_DEFAULT_CHECKPOINT_GAP)
+        (deferred_state_epoch_count, (u64), DEFERRED_STATE_EPOCH_COUNT)
+        (anticone_cache_stride, (usize), ANTICONE_CACHE_DEFAULT_STRIDE)
+        (anticone_cache_max_anticone_size, (usize), ANTICONE_CACHE_DEFAULT_MAX_ANTICONE_SIZE)
+        (adaptive_anticone_cache, (bool), false)
+        (pastset_cache_capacity, (usize), PASTSET_CACHE_DEFAULT_CAPACITY)
+        (referee_selection_strategy, (String), "lca_height".to_string())
+        (stable_hash_trust_policy, (String), "majority".to_string())
+        (stable_hash_verification_peers, (usize), 0)
+        (stable_hash_verification_timeout_ms, (u64), 30000)
+        (construct_pivot_state_batch_size, (usize), 1000)
+        (max_execution_queue_len, (usize), 1000)
+        (profile_consensus_lock, (bool), false)
+        (estimate_gas_upper_bound, (u64), DEFAULT_MAX_BLOCK_GAS_LIMIT)
+        (gas_price_block_sample_size, (usize), GasPriceOracleConfig::default().block_sample_size)
+        (gas_price_transaction_sample_size, (usize), GasPriceOracleConfig::default().transaction_sample_size)
         // FIXME: break into two options: one for enable, one for path.
         (debug_dump_dir_invalid_state_root, (String), "./storage/debug_dump_invalid_state_root/".to_string())
+        (archive_rpc_address, (Option<String>), None)
+        (archive_rpc_timeout_ms, (u64), 5000)
+        (rpc_query_cache_ttl_ms, (u64), 3000)
+        (rpc_query_cache_size, (usize), 1000)
+        (rpc_state_handle_max_ttl_ms, (u64), 60000)
+        (rpc_state_handle_limit, (usize), 1000)
+        (rpc_batch_max_size, (usize), 100)
+        (rpc_batch_max_concurrency, (usize), 8)
+        (rpc_batch_max_response_mb, (usize), 10)
+        (pivot_dump_interval_s, (Option<u64>), None)
+        (pivot_dump_epoch_count, (usize), 100)
+        (pivot_dump_file, (Option<String>), None)
+        (firehose_sink, (Option<String>), None)
+        (firehose_cursor_path, (String), "./firehose_cursor".to_string())
+        (firehose_queue_size, (usize), 1000)
         (metrics_enabled, (bool), false)
         (metrics_report_interval_ms, (u64), 5000)
         (metrics_output_file, (String), "metrics.log".to_string())
+        (metrics_prometheus_port, (Option<u16>), None)
         (min_peers_propagation, (usize), 8)
         (max_peers_propagation, (usize), 128)
         (future_block_buffer_capacity, (usize), 32768)
         (txgen_account_count, (usize), 10)
+        (txgen_workload, (bool), false)
+        (txgen_transfer_weight, (u32), 1)
+        (txgen_erc20_weight, (u32), 0)
+        (txgen_storage_weight, (u32), 0)
+        (txgen_storage_slots, (usize), 10)
+        (txgen_key_seed, (Option<u64>), None)
+        (txgen_burst_size, (u32), 1)
         (tx_cache_count, (usize), 250000)
+        (tx_recover_threads, (usize), 8)
         (max_download_state_peers, (usize), 8)
+        (light_witness_history_epochs, (u64), LightConfiguration::default().witness_history_epochs)
         (block_db_type, (String), "rocksdb".to_string())
+        (block_body_horizon, (Option<u64>), None)
+        (cache_warm_up, (bool), false)
+        (cache_warm_up_max_entries, (usize), 100000)
+        (cache_warm_up_bytes_per_sec, (usize), 8 * 1024 * 1024)
         (rocksdb_disable_wal, (bool), false)
     }
     {
+        (
+            jsonrpc_ws_address, (Ipv4Addr), Ipv4Addr::new(127, 0, 0, 1), |a| {
+                a.parse::<Ipv4Addr>()
+                    .map_err(|e| format!("Invalid jsonrpc_ws_address: {}", e))
+            }
+        )
+        (
+            metrics_prometheus_address, (Ipv4Addr), Ipv4Addr::new(127, 0, 0, 1), |a| {
+                a.parse::<Ipv4Addr>()
+                    .map_err(|e| format!("Invalid metrics_prometheus_address: {}", e))
+            }
+        )
         (
             log_level, (LevelFilter), LevelFilter::Info, |l| {
                 match l {
@@ -134,14 +237,26 @@ build_config! {
     }
 }
 
+#[derive(Clone)]
 pub struct Configuration {
     pub raw_conf: RawConfiguration,
+    /// The path of the configuration file, which is read again on reload.
+    pub config_file: Option<String>,
+    /// The parameters given in commandline, which override the ones in the
+    /// configuration file on reload as well.
+    pub cli_keys: Vec<&'static str>,
+    /// The genesis spec in the `genesis_spec` file, with the accounts in the
+    /// `genesis_accounts` file added, or `None` if `genesis_spec` is not set.
+    pub genesis_spec: Option<GenesisSpec>,
 }
 
 impl Default for Configuration {
     fn default() -> Self {
         Configuration {
             raw_conf: Default::default(),
+            config_file: None,
+            cli_keys: Vec::new(),
+            genesis_spec: None,
         }
     }
 }
@@ -150,9 +265,71 @@ impl Configuration {
     pub fn parse(matches: &clap::ArgMatches) -> Result<Configuration, String> {
         let mut config = Configuration::default();
         config.raw_conf = RawConfiguration::parse(matches)?;
+        config.config_file = matches.value_of("config").map(Into::into);
+        config.cli_keys = RawConfiguration::cli_keys(matches);
+        new_referee_selector(&config.raw_conf.referee_selection_strategy)?;
+        config
+            .raw_conf
+            .stable_hash_trust_policy
+            .parse::<StableHashTrustPolicy>()?;
+        if config.raw_conf.acceptable_time_drift_secs
+            > config.raw_conf.valid_time_drift_secs
+        {
+            return Err(format!(
+                "acceptable_time_drift_secs {} is larger than \
+                 valid_time_drift_secs {}",
+                config.raw_conf.acceptable_time_drift_secs,
+                config.raw_conf.valid_time_drift_secs
+            ));
+        }
+        if config.raw_conf.tx_recover_threads == 0 {
+            return Err("tx_recover_threads should be positive".into());
+        }
+        if config.raw_conf.chain_id > MAX_CHAIN_ID {
+            return Err(format!(
+                "chain_id should be at most {}, got {}",
+                MAX_CHAIN_ID, config.raw_conf.chain_id
+            ));
+        }
+        config.genesis_spec = Self::load_genesis_spec(&config.raw_conf)?;
+        if let Some(spec) = &config.genesis_spec {
+            match spec.deferred_state_epoch_count {
+                Some(count)
+                    if count != config.raw_conf.deferred_state_epoch_count =>
+                {
+                    return Err(format!(
+                        "deferred_state_epoch_count {} does not match {} in \
+                         the genesis spec",
+                        config.raw_conf.deferred_state_epoch_count, count
+                    ));
+                }
+                _ => {}
+            }
+            match spec.chain_id {
+                Some(chain_id) if chain_id != config.raw_conf.chain_id => {
+                    return Err(format!(
+                        "chain_id {} does not match {} in the genesis spec",
+                        config.raw_conf.chain_id, chain_id
+                    ));
+                }
+                _ => {}
+            }
+        }
+        config.consensus_config().inner_conf.validate()?;
         Ok(config)
     }
 
+    /// Read the configuration file again, and return the new raw
+    /// configuration with the parameters given in commandline unchanged.
+    pub fn reload_raw_conf(&self) -> Result<RawConfiguration, String> {
+        let mut raw_conf = RawConfiguration::default();
+        if let Some(ref config_file) = self.config_file {
+            raw_conf.load_file(config_file)?;
+        }
+        raw_conf.copy_from(&self.raw_conf, &self.cli_keys);
+        Ok(raw_conf)
+    }
+
     pub fn net_config(&self) -> Result<NetworkConfiguration, String> {
         let mut network_config = match self.raw_conf.port {
             Some(port) => NetworkConfiguration::new_with_port(port),
@@ -195,6 +372,8 @@ impl Configuration {
         }
         network_config.test_mode = self.raw_conf.test_mode;
         network_config.subnet_quota = self.raw_conf.subnet_quota;
+        network_config.max_outgoing_peers_per_subnet =
+            self.raw_conf.max_outgoing_peers_per_subnet;
         network_config.session_ip_limit_config =
             self.raw_conf.session_ip_limits.clone().try_into().map_err(
                 |e| format!("failed to parse session ip limit config: {}", e),
@@ -207,6 +386,20 @@ impl Configuration {
         network_config.housekeeping_timeout = Duration::from_millis(
             self.raw_conf.discovery_housekeeping_timeout_ms,
         );
+        network_config.session_ping_interval =
+            Duration::from_millis(self.raw_conf.session_ping_interval_ms);
+        network_config.session_ping_timeout =
+            Duration::from_millis(self.raw_conf.session_ping_timeout_ms);
+        network_config.session_idle_timeout = self
+            .raw_conf
+            .session_idle_timeout_ms
+            .map(Duration::from_millis);
+        network_config.send_queue_config.max_bytes =
+            self.raw_conf.send_queue_max_bytes;
+        network_config.send_queue_config.congestion_latency =
+            Duration::from_millis(
+                self.raw_conf.send_queue_congestion_latency_ms,
+            );
         Ok(network_config)
     }
 
@@ -242,12 +435,28 @@ impl Configuration {
         )
     }
 
-    pub fn consensus_config(&self) -> ConsensusConfig {
-        let enable_optimistic_execution = if DEFERRED_STATE_EPOCH_COUNT <= 1 {
-            false
-        } else {
-            self.raw_conf.enable_optimistic_execution
+    /// Load the genesis spec in the `genesis_spec` file, with the accounts in
+    /// the `genesis_accounts` file added.
+    fn load_genesis_spec(
+        raw_conf: &RawConfiguration,
+    ) -> Result<Option<GenesisSpec>, String> {
+        let mut spec = match raw_conf.genesis_spec {
+            Some(ref file) => genesis::load_spec_file(file)?,
+            None => return Ok(None),
         };
+        if let Some(ref file) = raw_conf.genesis_accounts {
+            spec.add_balances(genesis::load_file(file)?)?;
+        }
+        Ok(Some(spec))
+    }
+
+    pub fn consensus_config(&self) -> ConsensusConfig {
+        let enable_optimistic_execution =
+            if self.raw_conf.deferred_state_epoch_count <= 1 {
+                false
+            } else {
+                self.raw_conf.enable_optimistic_execution
+            };
         ConsensusConfig {
             debug_dump_dir_invalid_state_root: self
                 .raw_conf
@@ -266,9 +475,54 @@ impl Configuration {
                     .heavy_block_difficulty_ratio,
                 era_epoch_count: self.raw_conf.era_epoch_count,
                 era_checkpoint_gap: self.raw_conf.era_checkpoint_gap,
+                deferred_state_epoch_count: self
+                    .raw_conf
+                    .deferred_state_epoch_count,
                 enable_optimistic_execution,
+                anticone_cache_stride: self.raw_conf.anticone_cache_stride,
+                anticone_cache_max_anticone_size: self
+                    .raw_conf
+                    .anticone_cache_max_anticone_size,
+                adaptive_anticone_cache: self.raw_conf.adaptive_anticone_cache,
+                pastset_cache_capacity: self.raw_conf.pastset_cache_capacity,
             },
             bench_mode: false,
+            construct_pivot_state_batch_size: self
+                .raw_conf
+                .construct_pivot_state_batch_size,
+            max_execution_queue_len: self.raw_conf.max_execution_queue_len,
+            estimate_gas_upper_bound: self.raw_conf.estimate_gas_upper_bound,
+            gas_price_oracle: self.gas_price_oracle_config(),
+            ghast_rule: Arc::new(DefaultGhastRule),
+            referee_selector: new_referee_selector(
+                &self.raw_conf.referee_selection_strategy,
+            )
+            .expect("checked in Configuration::parse"),
+            stable_hash_verification: StableHashVerificationConfig {
+                trust_policy: self
+                    .raw_conf
+                    .stable_hash_trust_policy
+                    .parse()
+                    .expect("checked in Configuration::parse"),
+                min_peers: self.raw_conf.stable_hash_verification_peers,
+                timeout: Duration::from_millis(
+                    self.raw_conf.stable_hash_verification_timeout_ms,
+                ),
+            },
+            profile_inner_lock: self.raw_conf.profile_consensus_lock,
+            machine: Arc::new(match &self.genesis_spec {
+                Some(spec) => spec.machine(),
+                None => new_machine_with_builtin(),
+            }),
+        }
+    }
+
+    pub fn gas_price_oracle_config(&self) -> GasPriceOracleConfig {
+        GasPriceOracleConfig {
+            block_sample_size: self.raw_conf.gas_price_block_sample_size,
+            transaction_sample_size: self
+                .raw_conf
+                .gas_price_transaction_sample_size,
         }
     }
 
@@ -298,7 +552,50 @@ impl Configuration {
     }
 
     pub fn verification_config(&self) -> VerificationConfig {
-        VerificationConfig::new(self.raw_conf.test_mode)
+        VerificationConfig::new(
+            self.raw_conf.test_mode,
+            self.raw_conf.chain_id,
+            self.raw_conf.chain_id_enforcement_height,
+            self.raw_conf.median_time_past_activation_height,
+            self.raw_conf.valid_time_drift_secs,
+            self.raw_conf.acceptable_time_drift_secs,
+        )
+    }
+
+    pub fn light_config(&self) -> LightConfiguration {
+        LightConfiguration {
+            witness_history_epochs: self.raw_conf.light_witness_history_epochs,
+        }
+    }
+
+    pub fn pubsub_config(&self) -> PubSubConfiguration {
+        PubSubConfiguration {
+            max_subscriptions_per_connection: self
+                .raw_conf
+                .pubsub_max_subscriptions_per_connection,
+            max_pending_notifications: self
+                .raw_conf
+                .pubsub_max_pending_notifications,
+        }
+    }
+
+    pub fn ws_config(&self) -> WsConfiguration {
+        let ip = self.raw_conf.jsonrpc_ws_address.octets();
+        WsConfiguration::new(
+            Some((ip[0], ip[1], ip[2], ip[3])),
+            self.raw_conf.jsonrpc_ws_port,
+            self.raw_conf.jsonrpc_ws_max_connections,
+        )
+    }
+
+    pub fn batch_config(&self) -> BatchConfiguration {
+        BatchConfiguration {
+            max_batch_size: self.raw_conf.rpc_batch_max_size,
+            max_concurrency: self.raw_conf.rpc_batch_max_concurrency,
+            max_response_bytes: self.raw_conf.rpc_batch_max_response_mb
+                * 1024
+                * 1024,
+        }
     }
 
     pub fn tx_gen_config(&self) -> TransactionGeneratorConfig {
@@ -306,9 +603,32 @@ impl Configuration {
             self.raw_conf.generate_tx,
             self.raw_conf.generate_tx_period_us.expect("has default"),
             self.raw_conf.txgen_account_count,
+            if self.raw_conf.txgen_workload {
+                Some(self.tx_workload_config())
+            } else {
+                None
+            },
         )
     }
 
+    pub fn tx_workload_config(&self) -> WorkloadConfig {
+        WorkloadConfig {
+            transfer_weight: self.raw_conf.txgen_transfer_weight,
+            erc20_weight: self.raw_conf.txgen_erc20_weight,
+            storage_weight: self.raw_conf.txgen_storage_weight,
+            storage_slots: self.raw_conf.txgen_storage_slots,
+            sender_count: self.raw_conf.txgen_account_count,
+            key_seed: self.raw_conf.txgen_key_seed,
+            arrival: if self.raw_conf.txgen_burst_size > 1 {
+                ArrivalPattern::Bursty {
+                    burst_size: self.raw_conf.txgen_burst_size,
+                }
+            } else {
+                ArrivalPattern::Constant
+            },
+        }
+    }
+
     pub fn storage_config(&self) -> StorageConfiguration {
         StorageConfiguration {
             cache_start_size: self.raw_conf.storage_cache_start_size,
@@ -316,6 +636,14 @@ impl Configuration {
             idle_size: self.raw_conf.storage_idle_size,
             node_map_size: self.raw_conf.storage_node_map_size,
             recent_lfu_factor: self.raw_conf.storage_recent_lfu_factor,
+            archive_snapshot_interval: self
+                .raw_conf
+                .archive_state_snapshot_interval,
+            dedup_trie_nodes: self.raw_conf.storage_dedup_trie_nodes,
+            external_value_threshold: self
+                .raw_conf
+                .storage_external_value_threshold,
+            slab_max_idle_size: self.raw_conf.storage_slab_max_idle_size,
         }
     }
 
@@ -339,6 +667,17 @@ impl Configuration {
             transaction_request_timeout: Duration::from_millis(
                 self.raw_conf.transaction_request_timeout_ms,
             ),
+            max_request_timeout: Duration::from_millis(
+                self.raw_conf.max_request_timeout_ms,
+            ),
+            min_request_timeout: self
+                .raw_conf
+                .min_request_timeout_ms
+                .map(Duration::from_millis),
+            consistency_check_period: self
+                .raw_conf
+                .consistency_check_period_ms
+                .map(Duration::from_millis),
             tx_maintained_for_peer_timeout: Duration::from_millis(
                 self.raw_conf.tx_maintained_for_peer_timeout_ms,
             ),
@@ -359,6 +698,7 @@ impl Configuration {
                 .future_block_buffer_capacity,
             max_download_state_peers: self.raw_conf.max_download_state_peers,
             test_mode: self.raw_conf.test_mode,
+            chain_id: self.raw_conf.chain_id,
         }
     }
 
@@ -371,6 +711,18 @@ impl Configuration {
                 "sqlite" => DbType::Sqlite,
                 _ => panic!("Invalid block_db_type parameter!"),
             },
+            self.raw_conf.block_body_horizon,
+            self.raw_conf.deferred_state_epoch_count,
+            if self.raw_conf.cache_warm_up {
+                Some(CacheWarmUpConfig {
+                    max_entries: self.raw_conf.cache_warm_up_max_entries,
+                    max_bytes_per_sec: self
+                        .raw_conf
+                        .cache_warm_up_bytes_per_sec,
+                })
+            } else {
+                None
+            },
         )
     }
 }
@@ -0,0 +1,563 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::rpc::types::Bytes;
+use cfxcore::{
+    consensus::{EpochExecutedObserver, ExecutedEpoch},
+    ConsensusGraph,
+};
+use metrics::{register_meter_with_group, Gauge, GaugeUsize, Meter};
+use parking_lot::{Condvar, Mutex};
+use serde_json::{json, Value};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, TryRecvError, TrySendError},
+        Arc, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+lazy_static! {
+    static ref FIREHOSE_DELIVERED: Arc<dyn Meter> =
+        register_meter_with_group("firehose", "delivered");
+    static ref FIREHOSE_REPLAYED: Arc<dyn Meter> =
+        register_meter_with_group("firehose", "replayed");
+    static ref FIREHOSE_DROPPED: Arc<dyn Meter> =
+        register_meter_with_group("firehose", "dropped");
+    static ref FIREHOSE_SINK_ERROR: Arc<dyn Meter> =
+        register_meter_with_group("firehose", "sink_error");
+    static ref FIREHOSE_QUEUE_LEN: Arc<dyn Gauge<usize>> =
+        GaugeUsize::register_with_group("firehose", "queue_len");
+}
+
+/// The interval to check the exit flag and the epochs to replay when there
+/// is nothing queued.
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+/// The interval to reconnect after the sink fails.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(3);
+
+/// The destination of the firehose records, which are newline-delimited JSON
+/// objects.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FirehoseSink {
+    /// Append to a file.
+    File(PathBuf),
+    /// Write to a listening unix domain socket.
+    Unix(PathBuf),
+    /// Write to a listening TCP address, e.g. a bridge to a message queue.
+    Tcp(String),
+}
+
+impl FirehoseSink {
+    /// Parse a sink in the form of `file:<path>`, `unix:<path>` or
+    /// `tcp:<host>:<port>`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().unwrap_or_default();
+        let target = match parts.next() {
+            Some(target) if !target.is_empty() => target,
+            _ => return Err(format!("Invalid firehose sink {}", s)),
+        };
+        match kind {
+            "file" => Ok(FirehoseSink::File(target.into())),
+            "unix" if cfg!(unix) => Ok(FirehoseSink::Unix(target.into())),
+            "tcp" => Ok(FirehoseSink::Tcp(target.into())),
+            _ => Err(format!("Unsupported firehose sink {}", s)),
+        }
+    }
+
+    fn connect(&self) -> io::Result<Box<dyn Write + Send>> {
+        match self {
+            FirehoseSink::File(path) => Ok(Box::new(BufWriter::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            ))),
+            #[cfg(unix)]
+            FirehoseSink::Unix(path) => Ok(Box::new(BufWriter::new(
+                std::os::unix::net::UnixStream::connect(path)?,
+            ))),
+            #[cfg(not(unix))]
+            FirehoseSink::Unix(_) => unreachable!(),
+            FirehoseSink::Tcp(address) => {
+                Ok(Box::new(BufWriter::new(TcpStream::connect(address)?)))
+            }
+        }
+    }
+}
+
+/// Create the observer queueing the newly executed epochs for the firehose
+/// thread. The observer runs on the execution thread, so it never blocks: if
+/// `queue_size` epochs are already queued, the epoch is dropped and replayed
+/// from the database later without its state diff.
+fn epoch_queue(
+    queue_size: usize,
+) -> (
+    EpochExecutedObserver,
+    Receiver<ExecutedEpoch>,
+    Arc<AtomicUsize>,
+) {
+    let (sender, receiver) = mpsc::sync_channel(queue_size);
+    let sender = Mutex::new(sender);
+    let queue_len = Arc::new(AtomicUsize::new(0));
+    let observer_queue_len = queue_len.clone();
+    let observer: EpochExecutedObserver = Box::new(move |epoch| {
+        // Counted before sending, so that the firehose thread never sees a
+        // received epoch uncounted.
+        let len = observer_queue_len.fetch_add(1, Ordering::Relaxed) + 1;
+        match sender.lock().try_send(epoch.clone()) {
+            Ok(()) => FIREHOSE_QUEUE_LEN.update(len),
+            Err(e) => {
+                observer_queue_len.fetch_sub(1, Ordering::Relaxed);
+                // Otherwise the firehose thread has exited.
+                if let TrySendError::Full(_) = e {
+                    FIREHOSE_DROPPED.mark(1);
+                }
+            }
+        }
+    });
+    (observer, receiver, queue_len)
+}
+
+/// Stream the executed epochs on the pivot chain to `sink`, so that external
+/// indexers don't need to poll RPCs.
+///
+/// A record is delivered at least once: the number of the last delivered
+/// epoch is saved in `cursor_path` after the record is written, and the
+/// epochs after it are loaded from the database after a restart or when
+/// their records are dropped from a full queue. The replayed records have no
+/// state diff. A record may be delivered again after a pivot chain reorg or a
+/// restart, so indexers should deduplicate them by epoch hash.
+pub fn start(
+    consensus: &Arc<ConsensusGraph>, sink: FirehoseSink, cursor_path: String,
+    queue_size: usize, exit: Arc<(Mutex<bool>, Condvar)>,
+) -> Result<(), String>
+{
+    let cursor = load_cursor(Path::new(&cursor_path))?;
+    let (observer, receiver, queue_len) = epoch_queue(queue_size);
+    consensus.register_epoch_executed_observer(observer);
+
+    let mut worker = FirehoseWorker {
+        consensus: Arc::downgrade(consensus),
+        receiver,
+        queue_len,
+        pending: None,
+        sink,
+        writer: None,
+        cursor_path: cursor_path.into(),
+        cursor,
+        exit,
+    };
+    thread::Builder::new()
+        .name("firehose".into())
+        .spawn(move || worker.run())
+        .map_err(|e| format!("Failed to start firehose: {:?}", e))?;
+    Ok(())
+}
+
+#[derive(Debug)]
+enum NextEpoch {
+    /// Deliver the queued epoch.
+    Queued(ExecutedEpoch),
+    /// Load the epoch of the number from the database and deliver it.
+    Replay(u64),
+    /// Nothing to deliver.
+    Idle,
+}
+
+struct FirehoseWorker {
+    consensus: Weak<ConsensusGraph>,
+    receiver: Receiver<ExecutedEpoch>,
+    queue_len: Arc<AtomicUsize>,
+    /// The epoch received from the queue, which waits for the epochs before
+    /// it to be replayed.
+    pending: Option<ExecutedEpoch>,
+    sink: FirehoseSink,
+    writer: Option<Box<dyn Write + Send>>,
+    cursor_path: PathBuf,
+    /// The number of the last delivered epoch.
+    cursor: Option<u64>,
+    exit: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl FirehoseWorker {
+    fn run(&mut self) {
+        while !*self.exit.0.lock() {
+            let consensus = match self.consensus.upgrade() {
+                Some(consensus) => consensus,
+                None => return,
+            };
+            let executed_best = consensus.executed_best_state_epoch_number();
+            if !self.receive(executed_best) {
+                return;
+            }
+            let epoch = match self.next_epoch(executed_best) {
+                NextEpoch::Queued(epoch) => epoch,
+                NextEpoch::Replay(epoch_number) => {
+                    match consensus.executed_epoch(epoch_number) {
+                        Ok(epoch) => {
+                            FIREHOSE_REPLAYED.mark(1);
+                            epoch
+                        }
+                        Err(e) => {
+                            debug!(
+                                "Failed to replay epoch {} to firehose: {}",
+                                epoch_number, e
+                            );
+                            drop(consensus);
+                            if !self.wait(IDLE_INTERVAL) {
+                                return;
+                            }
+                            continue;
+                        }
+                    }
+                }
+                NextEpoch::Idle => continue,
+            };
+            drop(consensus);
+            if !self.deliver(&epoch) {
+                return;
+            }
+            self.cursor = Some(epoch.epoch_number);
+            if let Err(e) = save_cursor(&self.cursor_path, epoch.epoch_number)
+            {
+                warn!("Failed to save firehose cursor: {}", e);
+            }
+            FIREHOSE_DELIVERED.mark(1);
+        }
+    }
+
+    /// Receive the next queued epoch unless one is pending. It only waits
+    /// for the queue if there is no epoch to replay. It returns false if the
+    /// observer is dropped.
+    fn receive(&mut self, executed_best: u64) -> bool {
+        if self.pending.is_some() {
+            return true;
+        }
+        let replay_due =
+            self.cursor.map_or(false, |cursor| cursor < executed_best);
+        let received = if replay_due {
+            match self.receiver.try_recv() {
+                Ok(epoch) => Some(epoch),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        } else {
+            match self.receiver.recv_timeout(IDLE_INTERVAL) {
+                Ok(epoch) => Some(epoch),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return false,
+            }
+        };
+        if received.is_some() {
+            let len = self.queue_len.fetch_sub(1, Ordering::Relaxed);
+            FIREHOSE_QUEUE_LEN.update(len - 1);
+        }
+        self.pending = received;
+        true
+    }
+
+    /// The next epoch to deliver. The epochs missing after the cursor are
+    /// replayed before the pending one.
+    fn next_epoch(&mut self, executed_best: u64) -> NextEpoch {
+        let next = match self.cursor {
+            Some(cursor) => cursor + 1,
+            None => {
+                return match self.pending.take() {
+                    Some(epoch) => NextEpoch::Queued(epoch),
+                    None => NextEpoch::Idle,
+                };
+            }
+        };
+        match self.pending.take() {
+            Some(epoch) if epoch.epoch_number <= next => {
+                NextEpoch::Queued(epoch)
+            }
+            pending => {
+                self.pending = pending;
+                if next <= executed_best {
+                    NextEpoch::Replay(next)
+                } else {
+                    NextEpoch::Idle
+                }
+            }
+        }
+    }
+
+    /// Wait for `duration` unless the client exits. It returns false if the
+    /// client exits.
+    fn wait(&self, duration: Duration) -> bool {
+        let mut exit_lock = self.exit.0.lock();
+        !*exit_lock
+            && self.exit.1.wait_for(&mut exit_lock, duration).timed_out()
+    }
+
+    /// Write the record of `epoch` until it succeeds. It returns false if the
+    /// client exits before that.
+    fn deliver(&mut self, epoch: &ExecutedEpoch) -> bool {
+        let mut line = epoch_record(epoch).to_string();
+        line.push('\n');
+        loop {
+            match self.write(line.as_bytes()) {
+                Ok(()) => return true,
+                Err(e) => {
+                    FIREHOSE_SINK_ERROR.mark(1);
+                    warn!("Firehose sink {:?} error: {:?}", self.sink, e);
+                    self.writer = None;
+                }
+            }
+            if !self.wait(RECONNECT_INTERVAL) {
+                return false;
+            }
+        }
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.writer.is_none() {
+            self.writer = Some(self.sink.connect()?);
+        }
+        let writer = self.writer.as_mut().unwrap();
+        writer.write_all(line)?;
+        writer.flush()
+    }
+}
+
+fn load_cursor(path: &Path) -> Result<Option<u64>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path).map_err(|e| {
+        format!("Failed to read firehose cursor {:?}: {:?}", path, e)
+    })?;
+    content
+        .trim()
+        .parse()
+        .map(Some)
+        .map_err(|e| format!("Invalid firehose cursor {:?}: {:?}", path, e))
+}
+
+/// Save the cursor into a temporary file first, so that it's not corrupted
+/// if the client crashes while writing it.
+fn save_cursor(path: &Path, epoch_number: u64) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut file = File::create(&tmp_path)?;
+        write!(file, "{}", epoch_number)?;
+        file.sync_all()?;
+    }
+    fs::rename(tmp_path, path)
+}
+
+fn epoch_record(epoch: &ExecutedEpoch) -> Value {
+    let blocks = epoch
+        .blocks
+        .iter()
+        .zip(epoch.receipts.iter())
+        .map(|(block, receipts)| {
+            let header = &block.block_header;
+            let transactions = block
+                .transactions
+                .iter()
+                .zip(receipts.iter())
+                .map(|(tx, receipt)| {
+                    let logs = receipt
+                        .logs
+                        .iter()
+                        .map(|log| {
+                            json!({
+                                "address": log.address,
+                                "topics": log.topics,
+                                "data": Bytes::new(log.data.clone()),
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    json!({
+                        "hash": tx.hash(),
+                        "from": tx.sender(),
+                        "raw": Bytes::new(tx.transaction.to_raw()),
+                        "outcomeStatus": receipt.outcome_status,
+                        "gasUsed": receipt.gas_used,
+                        "logs": logs,
+                    })
+                })
+                .collect::<Vec<_>>();
+            json!({
+                "hash": block.hash(),
+                "parentHash": header.parent_hash(),
+                "refereeHashes": header.referee_hashes(),
+                "height": header.height(),
+                "timestamp": header.timestamp(),
+                "author": header.author(),
+                "transactions": transactions,
+            })
+        })
+        .collect::<Vec<_>>();
+    let state_diff = epoch.state_diff.as_ref().map(|diff| {
+        diff.iter()
+            .map(|account_diff| {
+                let account = account_diff.account.as_ref().map(|account| {
+                    json!({
+                        "balance": account.balance,
+                        "nonce": account.nonce,
+                        "codeHash": account.code_hash,
+                    })
+                });
+                json!({
+                    "address": account_diff.address,
+                    "account": account,
+                    "storageChanges": account_diff.storage_changes,
+                })
+            })
+            .collect::<Vec<_>>()
+    });
+    json!({
+        "epochNumber": epoch.epoch_number,
+        "epochHash": epoch.epoch_hash,
+        "blocks": blocks,
+        "stateDiff": state_diff,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        epoch_queue, epoch_record, load_cursor, save_cursor, FirehoseSink,
+        FirehoseWorker, NextEpoch,
+    };
+    use cfx_types::H256;
+    use cfxcore::consensus::ExecutedEpoch;
+    use parking_lot::{Condvar, Mutex};
+    use std::sync::{atomic::Ordering, mpsc::Receiver, Arc, Weak};
+    use tempdir::TempDir;
+
+    fn executed_epoch(epoch_number: u64) -> ExecutedEpoch {
+        ExecutedEpoch {
+            epoch_hash: H256::from_low_u64_be(epoch_number),
+            epoch_number,
+            blocks: vec![],
+            receipts: vec![],
+            state_diff: None,
+        }
+    }
+
+    fn worker(
+        receiver: Receiver<ExecutedEpoch>, cursor: Option<u64>,
+    ) -> FirehoseWorker {
+        FirehoseWorker {
+            consensus: Weak::new(),
+            receiver,
+            queue_len: Default::default(),
+            pending: None,
+            sink: FirehoseSink::Tcp("127.0.0.1:0".into()),
+            writer: None,
+            cursor_path: "firehose_cursor".into(),
+            cursor,
+            exit: Arc::new((Mutex::new(false), Condvar::new())),
+        }
+    }
+
+    fn queued_epoch_number(next: NextEpoch) -> Option<u64> {
+        match next {
+            NextEpoch::Queued(epoch) => Some(epoch.epoch_number),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_parse_sink() {
+        assert_eq!(
+            FirehoseSink::parse("file:./firehose.jsonl"),
+            Ok(FirehoseSink::File("./firehose.jsonl".into()))
+        );
+        assert_eq!(
+            FirehoseSink::parse("tcp:127.0.0.1:9000"),
+            Ok(FirehoseSink::Tcp("127.0.0.1:9000".into()))
+        );
+        assert!(FirehoseSink::parse("file:").is_err());
+        assert!(FirehoseSink::parse("kafka:topic").is_err());
+    }
+
+    #[test]
+    fn test_cursor() {
+        let dir = TempDir::new("firehose").unwrap();
+        let path = dir.path().join("cursor");
+        assert_eq!(load_cursor(&path), Ok(None));
+
+        save_cursor(&path, 10).unwrap();
+        save_cursor(&path, 11).unwrap();
+        assert_eq!(load_cursor(&path), Ok(Some(11)));
+    }
+
+    #[test]
+    fn test_full_queue_drops_epochs() {
+        let (observer, receiver, queue_len) = epoch_queue(2);
+        for epoch_number in 1..=3 {
+            observer(&executed_epoch(epoch_number));
+        }
+        assert_eq!(queue_len.load(Ordering::Relaxed), 2);
+
+        let mut worker = worker(receiver, None);
+        worker.queue_len = queue_len.clone();
+        assert!(worker.receive(0));
+        assert_eq!(queued_epoch_number(worker.next_epoch(0)), Some(1));
+        assert!(worker.receive(0));
+        assert_eq!(queued_epoch_number(worker.next_epoch(0)), Some(2));
+        assert_eq!(queue_len.load(Ordering::Relaxed), 0);
+
+        // The observer is dropped with the consensus graph.
+        drop(observer);
+        assert!(!worker.receive(0));
+    }
+
+    #[test]
+    fn test_next_epoch_replays_missing_epochs() {
+        let (observer, receiver, _) = epoch_queue(10);
+        let mut worker = worker(receiver, Some(5));
+        observer(&executed_epoch(8));
+
+        // The queued epoch is received without waiting and kept pending
+        // until the epochs before it are replayed.
+        assert!(worker.receive(8));
+        match worker.next_epoch(8) {
+            NextEpoch::Replay(6) => {}
+            next => panic!("unexpected {:?}", next),
+        }
+        worker.cursor = Some(6);
+        match worker.next_epoch(8) {
+            NextEpoch::Replay(7) => {}
+            next => panic!("unexpected {:?}", next),
+        }
+        worker.cursor = Some(7);
+        assert_eq!(queued_epoch_number(worker.next_epoch(8)), Some(8));
+
+        worker.cursor = Some(8);
+        match worker.next_epoch(8) {
+            NextEpoch::Idle => {}
+            next => panic!("unexpected {:?}", next),
+        }
+    }
+
+    #[test]
+    fn test_next_epoch_after_reorg() {
+        let (observer, receiver, _) = epoch_queue(10);
+        let mut worker = worker(receiver, Some(5));
+        // The pivot chain is reorged to a shorter one, whose epoch 4 is
+        // executed again.
+        observer(&executed_epoch(4));
+        assert!(worker.receive(5));
+        assert_eq!(queued_epoch_number(worker.next_epoch(5)), Some(4));
+    }
+
+    #[test]
+    fn test_epoch_record() {
+        let record = epoch_record(&executed_epoch(3));
+        assert_eq!(record["epochNumber"], 3);
+        assert_eq!(record["blocks"].as_array().map(Vec::len), Some(0));
+        assert!(record["stateDiff"].is_null());
+    }
+}
@@ -7,19 +7,33 @@
 
 use jsonrpc_http_server as http;
 use jsonrpc_tcp_server as tcp;
+use jsonrpc_ws_server as ws;
 
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate lazy_static;
 extern crate ethkey as keylib;
 
 #[macro_use]
 mod config_macro;
 pub mod archive;
+pub mod blame_audit;
+pub mod chain;
+mod common;
+pub mod config_reload;
 pub mod configuration;
+mod firehose;
 pub mod full;
 pub mod light;
+pub mod log_control;
+mod pivot_dump;
+mod prometheus_exporter;
+pub mod query;
+pub mod reindex;
+pub mod replay;
 pub mod rpc;
 #[cfg(test)]
 mod tests;
@@ -27,19 +27,21 @@ use crate::{
 };
 use cfxcore::{
     block_data_manager::BlockDataManager,
-    genesis,
+    genesis, migration,
     state_exposer::{SharedStateExposer, StateExposer},
     statistics::Statistics,
     storage::StorageManager,
     transaction_pool::DEFAULT_MAX_BLOCK_GAS_LIMIT,
     vm_factory::VmFactory,
     ConsensusGraph, LightQueryService, SynchronizationGraph, TransactionPool,
-    WORKER_COMPUTATION_PARALLELISM,
 };
 use std::str::FromStr;
 
 use super::{
-    http::Server as HttpServer, tcp::Server as TcpServer, TESTNET_VERSION,
+    config_reload::{reload_on_sighup, ConfigReloader},
+    http::Server as HttpServer,
+    tcp::Server as TcpServer,
+    TESTNET_VERSION,
 };
 
 pub struct LightClientHandle {
@@ -88,11 +90,17 @@ impl LightClient {
                 reporter,
                 Duration::from_millis(conf.raw_conf.metrics_report_interval_ms),
             );
+            if let Some(port) = conf.raw_conf.metrics_prometheus_port {
+                super::prometheus_exporter::start(
+                    conf.raw_conf.metrics_prometheus_address,
+                    port,
+                )?;
+            }
         }
 
         let worker_thread_pool = Arc::new(Mutex::new(ThreadPool::with_name(
             "Tx Recover".into(),
-            WORKER_COMPUTATION_PARALLELISM,
+            conf.raw_conf.tx_recover_threads,
         )));
 
         let network_config = conf.net_config()?;
@@ -104,6 +112,12 @@ impl LightClient {
             &db_config,
         )
         .map_err(|e| format!("Failed to open database {:?}", e))?;
+        migration::migrate(&ledger_db)?;
+        migration::check_deferred_state_epoch_count(
+            &ledger_db,
+            conf.raw_conf.deferred_state_epoch_count,
+            false, /* read_only */
+        )?;
 
         let secret_store = Arc::new(SecretStore::new());
         let storage_manager = Arc::new(StorageManager::new(
@@ -148,12 +162,15 @@ impl LightClient {
 
         // FIXME: move genesis block to a dedicated directory near all conflux
         // FIXME: parameters.
-        let genesis_block = storage_manager.initialize(
-            genesis_accounts,
-            DEFAULT_MAX_BLOCK_GAS_LIMIT.into(),
-            Address::from_str(TESTNET_VERSION).unwrap(),
-            U256::zero(),
-        );
+        let genesis_block = match &conf.genesis_spec {
+            Some(spec) => storage_manager.initialize_with_spec(spec),
+            None => storage_manager.initialize(
+                genesis_accounts,
+                DEFAULT_MAX_BLOCK_GAS_LIMIT.into(),
+                Address::from_str(TESTNET_VERSION).unwrap(),
+                U256::zero(),
+            ),
+        };
         debug!("Initialize genesis_block={:?}", genesis_block);
 
         let data_man = Arc::new(BlockDataManager::new(
@@ -167,7 +184,10 @@ impl LightClient {
 
         let txpool = Arc::new(TransactionPool::with_capacity(
             conf.raw_conf.tx_pool_size,
+            conf.raw_conf.tx_pool_min_replace_gas_price_bump_percent,
+            conf.raw_conf.tx_pool_memory_budget_mb * 1024 * 1024,
             data_man.clone(),
+            conf.verification_config(),
         ));
 
         let statistics = Arc::new(Statistics::new());
@@ -205,17 +225,37 @@ impl LightClient {
             consensus.clone(),
             sync_graph.clone(),
             network.clone(),
+            conf.light_config(),
         ));
         light.register().unwrap();
 
         let rpc_impl = Arc::new(RpcImpl::new(light.clone()));
 
+        if let Some(interval_s) = conf.raw_conf.pivot_dump_interval_s {
+            super::pivot_dump::start(
+                Arc::downgrade(&consensus),
+                state_exposer.clone(),
+                Duration::from_secs(interval_s),
+                conf.raw_conf.pivot_dump_epoch_count,
+                conf.raw_conf.pivot_dump_file.clone(),
+                exit.clone(),
+            );
+        }
+
+        let config_reloader = Arc::new(ConfigReloader::new(
+            conf.clone(),
+            consensus.clone(),
+            txpool.clone(),
+        ));
+        reload_on_sighup(config_reloader.clone())?;
+
         let common_impl = Arc::new(CommonImpl::new(
             exit,
             consensus.clone(),
             network.clone(),
             txpool.clone(),
             state_exposer.clone(),
+            config_reloader,
         ));
 
         let debug_rpc_http_server = super::rpc::start_http(
@@ -226,6 +266,7 @@ impl LightClient {
                 conf.raw_conf.jsonrpc_http_keep_alive,
             ),
             setup_debug_rpc_apis_light(common_impl.clone(), rpc_impl.clone()),
+            conf.batch_config(),
         )?;
 
         let rpc_tcp_server = super::rpc::start_tcp(
@@ -244,6 +285,7 @@ impl LightClient {
                     rpc_impl.clone(),
                 )
             },
+            conf.batch_config(),
             RpcExtractor,
         )?;
 
@@ -265,6 +307,7 @@ impl LightClient {
                     rpc_impl.clone(),
                 )
             },
+            conf.batch_config(),
         )?;
 
         Ok(LightClientHandle {
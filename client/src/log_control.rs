@@ -0,0 +1,234 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::configuration::Configuration;
+use log::LevelFilter;
+use log4rs::{
+    append::{console::ConsoleAppender, file::FileAppender},
+    config::{Appender, Config as LogConfig, Logger, Root},
+    encode::{json::JsonEncoder, pattern::PatternEncoder, Encode},
+    Handle,
+};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+
+const LOG_PATTERN: &str = "{d} {h({l}):5.5} {T:<20.20} {t:12.12} - {m}{n}";
+
+// Should add new crate names here
+const CRATE_NAMES: [&str; 9] = [
+    "blockgen",
+    "cfxcore",
+    "conflux",
+    "db",
+    "keymgr",
+    "network",
+    "txgen",
+    "client",
+    "primitives",
+];
+
+lazy_static! {
+    static ref LOG_CONTROL: Mutex<Option<LogControl>> = Mutex::new(None);
+}
+
+/// How the log configuration is built before the runtime overrides are
+/// applied.
+enum LogBase {
+    /// Loaded from the `log_conf` file.
+    File(String),
+    /// Built from `log_file`, `log_level` and `log_json`.
+    Default {
+        log_file: Option<String>,
+        level: LevelFilter,
+        json: bool,
+    },
+}
+
+impl LogBase {
+    fn build(&self) -> Result<LogConfig, String> {
+        match self {
+            LogBase::File(log_conf) => log4rs::load_config_file(
+                log_conf,
+                Default::default(),
+            )
+            .map_err(|e| {
+                format!("failed to load log configuration file: {:?}", e)
+            }),
+            LogBase::Default {
+                log_file,
+                level,
+                json,
+            } => {
+                let mut console_builder = ConsoleAppender::builder();
+                if *json {
+                    console_builder =
+                        console_builder.encoder(Box::new(JsonEncoder::new()));
+                }
+                let mut conf_builder = LogConfig::builder().appender(
+                    Appender::builder()
+                        .build("stdout", Box::new(console_builder.build())),
+                );
+                let mut root_builder = Root::builder().appender("stdout");
+                if let Some(log_file) = log_file {
+                    let encoder: Box<dyn Encode> = if *json {
+                        Box::new(JsonEncoder::new())
+                    } else {
+                        Box::new(PatternEncoder::new(LOG_PATTERN))
+                    };
+                    let file_appender = FileAppender::builder()
+                        .encoder(encoder)
+                        .build(log_file)
+                        .map_err(|e| {
+                            format!("failed to build log pattern: {:?}", e)
+                        })?;
+                    conf_builder = conf_builder.appender(
+                        Appender::builder()
+                            .build("logfile", Box::new(file_appender)),
+                    );
+                    root_builder = root_builder.appender("logfile");
+                }
+                for crate_name in CRATE_NAMES.iter() {
+                    conf_builder = conf_builder
+                        .logger(Logger::builder().build(*crate_name, *level));
+                }
+                conf_builder
+                    .build(root_builder.build(LevelFilter::Info))
+                    .map_err(|e| format!("failed to build log config: {:?}", e))
+            }
+        }
+    }
+}
+
+/// The installed logger and the log levels of modules changed at runtime.
+struct LogControl {
+    handle: Handle,
+    base: LogBase,
+    overrides: BTreeMap<String, LevelFilter>,
+}
+
+impl LogControl {
+    /// Rebuild the log configuration with the current overrides, each of
+    /// which replaces the logger of the same module path in the base
+    /// configuration.
+    fn build_config(&self) -> Result<LogConfig, String> {
+        let (appenders, root, loggers) = self.base.build()?.unpack();
+        let loggers = loggers
+            .into_iter()
+            .filter(|logger| !self.overrides.contains_key(logger.name()));
+        let overrides = self.overrides.iter().map(|(module, level)| {
+            Logger::builder().build(module.clone(), *level)
+        });
+        LogConfig::builder()
+            .appenders(appenders)
+            .loggers(loggers)
+            .loggers(overrides)
+            .build(root)
+            .map_err(|e| format!("failed to build log config: {:?}", e))
+    }
+
+    fn apply(&self) -> Result<(), String> {
+        self.handle.set_config(self.build_config()?);
+        Ok(())
+    }
+}
+
+/// Initialize the global logger. If `log_conf` is provided, use it for log
+/// configuration and ignore `log_file`, `log_level` and `log_json`.
+/// Otherwise, set stdout to INFO and set all our crate log to `log_level`.
+pub fn init(conf: &Configuration) -> Result<(), String> {
+    let base = match conf.raw_conf.log_conf {
+        Some(ref log_conf) => LogBase::File(log_conf.clone()),
+        None => LogBase::Default {
+            log_file: conf.raw_conf.log_file.clone(),
+            level: conf.raw_conf.log_level,
+            json: conf.raw_conf.log_json,
+        },
+    };
+    let handle = log4rs::init_config(base.build()?).map_err(|e| {
+        format!("failed to initialize log with config: {:?}", e)
+    })?;
+    *LOG_CONTROL.lock() = Some(LogControl {
+        handle,
+        base,
+        overrides: BTreeMap::new(),
+    });
+    Ok(())
+}
+
+fn with_log_control<T, F>(f: F) -> Result<T, String>
+where F: FnOnce(&mut LogControl) -> Result<T, String> {
+    match &mut *LOG_CONTROL.lock() {
+        Some(log_control) => f(log_control),
+        None => Err("Logger is not initialized".into()),
+    }
+}
+
+/// Change the log level of the given module path, e.g. `cfxcore::sync`, at
+/// runtime.
+pub fn set_module_level(
+    module: &str, level: LevelFilter,
+) -> Result<(), String> {
+    if module.is_empty() {
+        return Err("Empty module path".into());
+    }
+    with_log_control(|log_control| {
+        let previous = log_control.overrides.insert(module.into(), level);
+        log_control.apply().map_err(|e| {
+            match previous {
+                Some(previous) => {
+                    log_control.overrides.insert(module.into(), previous)
+                }
+                None => log_control.overrides.remove(module),
+            };
+            e
+        })
+    })
+}
+
+/// Revert the log level of the given module, or all modules if not
+/// specified, to the one in the startup configuration.
+pub fn reset_module_level(module: Option<&str>) -> Result<(), String> {
+    with_log_control(|log_control| {
+        match module {
+            Some(module) => {
+                log_control.overrides.remove(module);
+            }
+            None => log_control.overrides.clear(),
+        }
+        log_control.apply()
+    })
+}
+
+/// Rebuild the startup log configuration with the new `log_level`, or read
+/// the `log_conf` file again if it is provided. The log levels changed at
+/// runtime are kept.
+pub fn reload(log_level: LevelFilter) -> Result<(), String> {
+    with_log_control(|log_control| {
+        let previous = match log_control.base {
+            LogBase::Default { ref mut level, .. } => {
+                Some(std::mem::replace(level, log_level))
+            }
+            LogBase::File(_) => None,
+        };
+        log_control.apply().map_err(|e| {
+            if let (Some(previous), LogBase::Default { level, .. }) =
+                (previous, &mut log_control.base)
+            {
+                *level = previous;
+            }
+            e
+        })
+    })
+}
+
+/// The log levels changed at runtime, keyed by module path.
+pub fn module_levels() -> Result<BTreeMap<String, String>, String> {
+    with_log_control(|log_control| {
+        Ok(log_control
+            .overrides
+            .iter()
+            .map(|(module, level)| (module.clone(), level.to_string()))
+            .collect())
+    })
+}
@@ -0,0 +1,118 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use cfxcore::{state_exposer::SharedStateExposer, ConsensusGraph};
+use parking_lot::{Condvar, Mutex};
+use serde_json::json;
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Weak},
+    thread,
+    time::Duration,
+};
+
+/// Periodically expose the pivot chain tail and the terminal blocks, and
+/// optionally write them into `file`, so that external watchdogs can detect
+/// a stalled pivot chain without calling RPCs.
+pub fn start(
+    consensus: Weak<ConsensusGraph>, state_exposer: SharedStateExposer,
+    interval: Duration, epoch_count: usize, file: Option<String>,
+    exit: Arc<(Mutex<bool>, Condvar)>,
+)
+{
+    thread::Builder::new()
+        .name("pivot dump".into())
+        .spawn(move || {
+            while wait(&exit, interval) {
+                match consensus.upgrade() {
+                    None => return,
+                    Some(consensus) => {
+                        consensus.expose_pivot_tail(epoch_count)
+                    }
+                }
+
+                if let Some(ref file) = file {
+                    let content = {
+                        let exposer = &state_exposer.read().consensus_graph;
+                        json!({
+                            "bestEpochNumber": exposer.best_epoch_number,
+                            "pivotTail": exposer.pivot_tail,
+                            "terminalBlockHashes":
+                                exposer.terminal_block_hashes,
+                        })
+                    };
+                    if let Err(e) =
+                        write_dump(Path::new(file), &content.to_string())
+                    {
+                        warn!(
+                            "Failed to dump pivot chain to {}: {:?}",
+                            file, e
+                        );
+                    }
+                }
+            }
+        })
+        .expect("Pivot dump thread spawn error");
+}
+
+/// Wait for `interval` unless the client exits. It returns false if the
+/// client exits, which is checked again after waking up, so that an exit
+/// notified before the wait is not missed.
+fn wait(exit: &(Mutex<bool>, Condvar), interval: Duration) -> bool {
+    let mut exit_lock = exit.0.lock();
+    if !*exit_lock {
+        exit.1.wait_for(&mut exit_lock, interval);
+    }
+    !*exit_lock
+}
+
+/// Write `content` into a temporary file and then rename it to `path`, so
+/// that watchers never read a partially written dump.
+fn write_dump(path: &Path, content: &str) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+    }
+    fs::rename(tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{wait, write_dump};
+    use parking_lot::{Condvar, Mutex};
+    use std::{fs, time::Duration};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_wait() {
+        let exit = (Mutex::new(false), Condvar::new());
+        assert!(wait(&exit, Duration::from_millis(1)));
+
+        // The exit notified while not waiting is not missed.
+        *exit.0.lock() = true;
+        exit.1.notify_all();
+        assert!(!wait(&exit, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_write_dump() {
+        let dir = TempDir::new("pivot_dump").unwrap();
+        let path = dir.path().join("pivot.json");
+
+        write_dump(&path, "{\"bestEpochNumber\":1}").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "{\"bestEpochNumber\":1}"
+        );
+
+        // The previous dump is replaced as a whole.
+        write_dump(&path, "{}").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}");
+        assert!(!path.with_extension("tmp").exists());
+    }
+}
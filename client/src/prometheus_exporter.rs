@@ -0,0 +1,63 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_HEAD_SIZE: usize = 8 * 1024;
+
+/// Serve all registered metrics in the Prometheus text exposition format
+/// over HTTP on `ip:port`. Every request is answered with the metrics,
+/// regardless of its method and path.
+pub fn start(ip: Ipv4Addr, port: u16) -> Result<(), String> {
+    let address = SocketAddrV4::new(ip, port);
+    let listener = TcpListener::bind(address).map_err(|e| {
+        format!("Failed to bind metrics exporter to {}: {:?}", address, e)
+    })?;
+    info!("Prometheus metrics exporter listening on {}", address);
+
+    thread::Builder::new()
+        .name("prometheus exporter".into())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(respond);
+                if let Err(e) = result {
+                    debug!("Failed to serve metrics: {:?}", e);
+                }
+            }
+        })
+        .map_err(|e| format!("Failed to spawn metrics exporter: {:?}", e))?;
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    // Consume the request head, whose content does not matter.
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n")
+        && head.len() < MAX_REQUEST_HEAD_SIZE
+    {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+
+    let body = metrics::prometheus_report();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
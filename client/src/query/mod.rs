@@ -0,0 +1,216 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::{
+    http::Server as HttpServer, tcp::Server as TcpServer,
+    ws::Server as WsServer,
+};
+use crate::{
+    common::{new_data_manager, open_ledger_db},
+    configuration::Configuration,
+    rpc::{
+        extractor::RpcExtractor, impls::query::RpcImpl, setup_rpc_apis_query,
+    },
+};
+use cfxcore::{
+    block_data_manager::BlockDataManager,
+    storage::{state_manager::StateManagerTrait, SnapshotAndEpochIdRef},
+};
+use ctrlc::CtrlC;
+use db::SystemDB;
+use parking_lot::{Condvar, Mutex};
+use std::{
+    any::Any,
+    sync::{Arc, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub struct QueryClientHandle {
+    pub debug_rpc_http_server: Option<HttpServer>,
+    pub rpc_tcp_server: Option<TcpServer>,
+    pub rpc_ws_server: Option<WsServer>,
+    pub rpc_http_server: Option<HttpServer>,
+    pub data_man: Arc<BlockDataManager>,
+    pub ledger_db: Weak<SystemDB>,
+}
+
+impl QueryClientHandle {
+    pub fn into_be_dropped(self) -> (Weak<SystemDB>, Box<dyn Any>) {
+        (
+            self.ledger_db,
+            Box::new((
+                self.debug_rpc_http_server,
+                self.rpc_tcp_server,
+                self.rpc_ws_server,
+                self.rpc_http_server,
+                self.data_man,
+            )),
+        )
+    }
+}
+
+/// A client which serves the state, block and receipt queries from the
+/// database of another node without networking or consensus, e.g. to run
+/// query replicas against a snapshot of the data directory of an archive
+/// node. The database is opened read-only, so only the epochs executed
+/// before the snapshot are served.
+pub struct QueryClient {}
+
+impl QueryClient {
+    pub fn start(conf: Configuration) -> Result<QueryClientHandle, String> {
+        info!("Working directory: {:?}", std::env::current_dir());
+
+        // The database is opened in the read-only mode of RocksDB, which
+        // doesn't take the lock of the data directory, so the genesis and
+        // the other writes of the data manager are discarded.
+        let ledger_db = open_ledger_db(&conf, true /* read_only */)?;
+        let data_man = new_data_manager(&conf, ledger_db.clone())?;
+
+        let latest_epoch = Self::latest_executed_epoch(&data_man);
+        info!("Serving queries of epochs [0, {}] from db", latest_epoch);
+        let rpc_impl = Arc::new(RpcImpl::new(data_man.clone(), latest_epoch));
+
+        let debug_rpc_http_server = super::rpc::start_http(
+            super::rpc::HttpConfiguration::new(
+                Some((127, 0, 0, 1)),
+                conf.raw_conf.jsonrpc_local_http_port,
+                conf.raw_conf.jsonrpc_cors.clone(),
+                conf.raw_conf.jsonrpc_http_keep_alive,
+            ),
+            setup_rpc_apis_query(rpc_impl.clone()),
+            conf.batch_config(),
+        )?;
+
+        let rpc_tcp_server = super::rpc::start_tcp(
+            super::rpc::TcpConfiguration::new(
+                None,
+                conf.raw_conf.jsonrpc_tcp_port,
+            ),
+            setup_rpc_apis_query(rpc_impl.clone()),
+            conf.batch_config(),
+            RpcExtractor,
+        )?;
+
+        let rpc_ws_server = super::rpc::start_ws(
+            conf.ws_config(),
+            setup_rpc_apis_query(rpc_impl.clone()),
+            conf.batch_config(),
+            RpcExtractor,
+        )?;
+
+        let rpc_http_server = super::rpc::start_http(
+            super::rpc::HttpConfiguration::new(
+                None,
+                conf.raw_conf.jsonrpc_http_port,
+                conf.raw_conf.jsonrpc_cors.clone(),
+                conf.raw_conf.jsonrpc_http_keep_alive,
+            ),
+            setup_rpc_apis_query(rpc_impl),
+            conf.batch_config(),
+        )?;
+
+        Ok(QueryClientHandle {
+            debug_rpc_http_server,
+            rpc_tcp_server,
+            rpc_ws_server,
+            rpc_http_server,
+            data_man,
+            ledger_db: Arc::downgrade(&ledger_db),
+        })
+    }
+
+    /// The latest epoch whose blocks and state are both in the database.
+    /// The epoch sets are recorded in the order of the epoch numbers, so the
+    /// last recorded one is found with an exponential search followed by a
+    /// binary search. The states of the last recorded epochs may not have
+    /// been committed yet, so it then steps back to the latest epoch with
+    /// state.
+    pub(crate) fn latest_executed_epoch(data_man: &BlockDataManager) -> u64 {
+        let recorded = |epoch_number| {
+            data_man.epoch_set_hashes_from_db(epoch_number).is_some()
+        };
+        // The genesis epoch is always recorded.
+        let mut low = 0;
+        let mut high = 1;
+        while recorded(high) {
+            low = high;
+            high *= 2;
+        }
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            if recorded(mid) {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+
+        let mut epoch_number = low;
+        while epoch_number > 0 {
+            let pivot_hash = *data_man
+                .epoch_set_hashes_from_db(epoch_number)
+                .unwrap()
+                .last()
+                .unwrap();
+            if data_man
+                .storage_manager
+                .contains_state(SnapshotAndEpochIdRef::new(&pivot_hash, None))
+                .unwrap_or(false)
+            {
+                break;
+            }
+            epoch_number -= 1;
+        }
+        epoch_number
+    }
+
+    /// Use a Weak pointer to ensure that other Arc pointers are released
+    fn wait_for_drop<T>(w: Weak<T>) {
+        let sleep_duration = Duration::from_secs(1);
+        let warn_timeout = Duration::from_secs(5);
+        let max_timeout = Duration::from_secs(10);
+        let instant = Instant::now();
+        let mut warned = false;
+        while instant.elapsed() < max_timeout {
+            if w.upgrade().is_none() {
+                return;
+            }
+            if !warned && instant.elapsed() > warn_timeout {
+                warned = true;
+                warn!("Shutdown is taking longer than expected.");
+            }
+            thread::sleep(sleep_duration);
+        }
+        eprintln!("Shutdown timeout reached, exiting uncleanly.");
+    }
+
+    pub fn close(handle: QueryClientHandle) {
+        let (ledger_db, to_drop) = handle.into_be_dropped();
+        drop(to_drop);
+
+        // Make sure ledger_db is properly dropped, so rocksdb can be closed
+        // cleanly
+        QueryClient::wait_for_drop(ledger_db);
+    }
+
+    pub fn run_until_closed(
+        exit: Arc<(Mutex<bool>, Condvar)>, keep_alive: QueryClientHandle,
+    ) {
+        CtrlC::set_handler({
+            let e = exit.clone();
+            move || {
+                *e.0.lock() = true;
+                e.1.notify_all();
+            }
+        });
+
+        let mut lock = exit.0.lock();
+        if !*lock {
+            let _ = exit.1.wait(&mut lock);
+        }
+
+        QueryClient::close(keep_alive);
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::{
+    common::{
+        new_consensus_graph, new_data_manager, open_ledger_db,
+        recover_graph_from_db,
+    },
+    configuration::Configuration,
+};
+use cfxcore::consensus::ReindexedEpoch;
+
+/// The number of epochs between two progress reports, after each of which
+/// the progress is persisted so that an interrupted reindexing can resume.
+const REINDEX_PROGRESS_EPOCHS: u64 = 1000;
+
+/// Rebuild the transaction addresses and the receipts of the persisted
+/// epochs from `from_epoch` to the latest one, without connecting to the
+/// network. If `from_epoch` is not specified, the interrupted reindexing is
+/// resumed, or all the epochs are reindexed.
+pub fn run(conf: Configuration, from_epoch: Option<u64>) -> Result<(), String> {
+    if !conf.raw_conf.record_tx_address {
+        return Err(
+            "Reindexing requires record_tx_address to be enabled".into()
+        );
+    }
+    let data_man =
+        new_data_manager(&conf, open_ledger_db(&conf, false /* read_only */)?)?;
+    let from_epoch = from_epoch
+        .or_else(|| data_man.reindex_progress())
+        .unwrap_or(0);
+    let consensus = new_consensus_graph(&conf, data_man.clone());
+    let _sync_graph = recover_graph_from_db(&conf, consensus.clone());
+
+    reindex_epochs(
+        from_epoch,
+        consensus.best_epoch_number(),
+        |epoch_number| consensus.reindex_epoch(epoch_number),
+        |next_epoch| data_man.set_reindex_progress(next_epoch),
+    )
+}
+
+/// Reindex the epochs `[from_epoch, to_epoch]` with `reindex_epoch`. The
+/// next epoch to reindex is recorded with `set_progress` every
+/// `REINDEX_PROGRESS_EPOCHS` epochs, and the record is removed when all the
+/// epochs are reindexed.
+fn reindex_epochs(
+    from_epoch: u64, to_epoch: u64,
+    reindex_epoch: impl Fn(u64) -> Result<ReindexedEpoch, String>,
+    set_progress: impl Fn(Option<u64>),
+) -> Result<(), String>
+{
+    info!("Start reindexing epochs [{}, {}]", from_epoch, to_epoch);
+    let (mut replayed, mut reexecuted, mut transactions) = (0, 0, 0);
+    let mut unavailable = Vec::new();
+    for epoch_number in from_epoch..=to_epoch {
+        match reindex_epoch(epoch_number)? {
+            ReindexedEpoch::Replayed { transactions: n } => {
+                replayed += 1;
+                transactions += n;
+            }
+            ReindexedEpoch::Reexecuted => reexecuted += 1,
+            ReindexedEpoch::Unavailable => {
+                warn!("Epoch {} cannot be reindexed", epoch_number);
+                unavailable.push(epoch_number);
+            }
+        }
+        if (epoch_number + 1) % REINDEX_PROGRESS_EPOCHS == 0 {
+            set_progress(Some(epoch_number + 1));
+            info!(
+                "Reindexed epochs up to {}/{}: {} replayed with {} transactions, {} re-executed, {} unavailable",
+                epoch_number,
+                to_epoch,
+                replayed,
+                transactions,
+                reexecuted,
+                unavailable.len()
+            );
+        }
+    }
+    set_progress(None);
+    info!(
+        "Finish reindexing epochs [{}, {}]: {} replayed with {} transactions, {} re-executed, {} unavailable",
+        from_epoch,
+        to_epoch,
+        replayed,
+        transactions,
+        reexecuted,
+        unavailable.len()
+    );
+
+    if unavailable.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} epochs cannot be reindexed, the first is epoch {}",
+            unavailable.len(),
+            unavailable[0]
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reindex_epochs, REINDEX_PROGRESS_EPOCHS};
+    use cfxcore::consensus::ReindexedEpoch;
+    use std::cell::{Cell, RefCell};
+
+    #[test]
+    fn test_reindex_epochs_resume() {
+        let progress = Cell::new(None);
+        let reindexed = RefCell::new(Vec::new());
+        let interrupted = Cell::new(true);
+        let interrupted_epoch = 2 * REINDEX_PROGRESS_EPOCHS + 10;
+        let to_epoch = 3 * REINDEX_PROGRESS_EPOCHS;
+        let reindex_epoch = |epoch_number: u64| {
+            if interrupted.get() && epoch_number == interrupted_epoch {
+                return Err("interrupted".to_string());
+            }
+            reindexed.borrow_mut().push(epoch_number);
+            Ok(ReindexedEpoch::Replayed { transactions: 1 })
+        };
+
+        // The progress is recorded before the interruption.
+        assert!(reindex_epochs(
+            0,
+            to_epoch,
+            &reindex_epoch,
+            |next_epoch| progress.set(next_epoch)
+        )
+        .is_err());
+        assert_eq!(progress.get(), Some(2 * REINDEX_PROGRESS_EPOCHS));
+
+        // The reindexing is resumed from the recorded progress, and the
+        // record is removed when it finishes.
+        interrupted.set(false);
+        reindexed.borrow_mut().clear();
+        let from_epoch = progress.get().unwrap();
+        assert_eq!(
+            reindex_epochs(from_epoch, to_epoch, &reindex_epoch, |next_epoch| {
+                progress.set(next_epoch)
+            }),
+            Ok(())
+        );
+        assert_eq!(progress.get(), None);
+        assert_eq!(
+            *reindexed.borrow(),
+            (2 * REINDEX_PROGRESS_EPOCHS..=to_epoch).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_reindex_epochs_unavailable() {
+        let progress = Cell::new(Some(1));
+        let result = reindex_epochs(
+            0,
+            10,
+            |epoch_number| {
+                Ok(if epoch_number % 4 == 3 {
+                    ReindexedEpoch::Unavailable
+                } else {
+                    ReindexedEpoch::Reexecuted
+                })
+            },
+            |next_epoch| progress.set(next_epoch),
+        );
+        // All the epochs are tried, and the first unavailable one is
+        // reported.
+        assert_eq!(
+            result,
+            Err("2 epochs cannot be reindexed, the first is epoch 3".into())
+        );
+        assert_eq!(progress.get(), None);
+    }
+}
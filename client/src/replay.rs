@@ -0,0 +1,68 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::{
+    common::{new_consensus_graph, new_data_manager, open_ledger_db},
+    configuration::Configuration,
+};
+use cfxcore::{
+    block_data_manager::db_manager::DBManager, db::NUM_COLUMNS, migration,
+};
+use std::path::Path;
+use tempdir::TempDir;
+
+/// Replay the persisted blocks of the local database into a fresh consensus
+/// graph without connecting to the network, and verify the pivot chain,
+/// epochs and state roots of the epochs in `[from, to]` against the recorded
+/// values. The local database is opened read-only, and the blocks are
+/// executed again into a scratch database which is removed afterwards.
+pub fn run(conf: Configuration, from: u64, to: u64) -> Result<(), String> {
+    let db_dir = conf.raw_conf.db_dir.as_ref().unwrap();
+    let source = DBManager::new_from_rocksdb(open_ledger_db(
+        &conf, true, /* read_only */
+    )?);
+
+    // The scratch database is put next to the local one, which is expected
+    // to have enough space for the replayed epochs.
+    let scratch_dir = TempDir::new_in(
+        Path::new(db_dir).parent().unwrap_or_else(|| Path::new(".")),
+        "replay",
+    )
+    .map_err(|e| format!("Failed to create scratch directory {:?}", e))?;
+    let scratch_path = scratch_dir.path().to_str().unwrap();
+    let ledger_db = db::open_database(
+        scratch_path,
+        &db::db_config(
+            scratch_dir.path(),
+            conf.raw_conf.db_cache_size.clone(),
+            db::DatabaseCompactionProfile::default(),
+            NUM_COLUMNS.clone(),
+            true, /* disable_wal */
+        ),
+    )
+    .map_err(|e| format!("Failed to open scratch database {:?}", e))?;
+    migration::migrate(&ledger_db)?;
+
+    let consensus =
+        new_consensus_graph(&conf, new_data_manager(&conf, ledger_db)?);
+
+    info!("Start replaying epochs [{}, {}] from db", from, to);
+    let report = consensus.replay_from_db(&source, from, to)?;
+    info!(
+        "Replayed {} blocks, verified {} epochs",
+        report.replayed_blocks, report.verified_epochs
+    );
+    for mismatch in &report.mismatches {
+        error!("{}", mismatch);
+    }
+
+    if report.is_consistent() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Replay found {} mismatches with the recorded values",
+            report.mismatches.len()
+        ))
+    }
+}
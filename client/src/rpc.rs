@@ -8,6 +8,7 @@ use crate::{
         ServerBuilder as HttpServerBuilder,
     },
     tcp::{self, Server as TcpServer, ServerBuilder as TcpServerBuilder},
+    ws::{self, Server as WsServer, ServerBuilder as WsServerBuilder},
 };
 use jsonrpc_core::MetaIoHandler;
 use std::{
@@ -26,19 +27,29 @@ mod traits;
 mod types;
 
 use self::{
+    helpers::BatchLimiter,
     impls::{
-        cfx::{CfxHandler, DebugRpcImpl, RpcImpl, TestRpcImpl},
+        cfx::{AdminRpcImpl, CfxHandler, DebugRpcImpl, RpcImpl, TestRpcImpl},
         common::RpcImpl as CommonImpl,
         light::{
-            CfxHandler as LightCfxHandler, DebugRpcImpl as LightDebugRpcImpl,
-            RpcImpl as LightImpl, TestRpcImpl as LightTestRpcImpl,
+            AdminRpcImpl as LightAdminRpcImpl, CfxHandler as LightCfxHandler,
+            DebugRpcImpl as LightDebugRpcImpl, RpcImpl as LightImpl,
+            TestRpcImpl as LightTestRpcImpl,
         },
+        personal::PersonalImpl,
         pubsub::PubSubClient,
+        query::{CfxHandler as QueryCfxHandler, RpcImpl as QueryImpl},
+    },
+    traits::{
+        admin::AdminRpc, cfx::Cfx, debug::DebugRpc, personal::PersonalRpc,
+        pubsub::PubSub, test::TestRpc,
     },
-    traits::{cfx::Cfx, debug::DebugRpc, pubsub::PubSub, test::TestRpc},
 };
 
-pub use self::types::{Block as RpcBlock, Origin};
+pub use self::{
+    helpers::{ArchiveProxy, BatchConfiguration, QueryCache, StateHandles},
+    types::{Block as RpcBlock, Origin},
+};
 pub use metadata::Metadata;
 
 #[derive(Debug, PartialEq)]
@@ -98,6 +109,29 @@ impl HttpConfiguration {
     }
 }
 
+#[derive(Debug, PartialEq)]
+pub struct WsConfiguration {
+    pub enabled: bool,
+    pub address: SocketAddr,
+    pub max_connections: usize,
+}
+
+impl WsConfiguration {
+    pub fn new(
+        ip: Option<(u8, u8, u8, u8)>, port: Option<u16>, max_connections: usize,
+    ) -> Self {
+        let ipv4 = match ip {
+            Some(ip) => Ipv4Addr::new(ip.0, ip.1, ip.2, ip.3),
+            None => Ipv4Addr::new(0, 0, 0, 0),
+        };
+        WsConfiguration {
+            enabled: port.is_some(),
+            address: SocketAddr::V4(SocketAddrV4::new(ipv4, port.unwrap_or(0))),
+            max_connections,
+        }
+    }
+}
+
 pub fn setup_public_rpc_apis(
     common: Arc<CommonImpl>, rpc: Arc<RpcImpl>, pubsub: Option<PubSubClient>,
 ) -> MetaIoHandler<Metadata> {
@@ -117,19 +151,34 @@ pub fn setup_debug_rpc_apis(
 ) -> MetaIoHandler<Metadata> {
     let cfx = CfxHandler::new(common.clone(), rpc.clone()).to_delegate();
     let test = TestRpcImpl::new(common.clone(), rpc.clone()).to_delegate();
-    let debug = DebugRpcImpl::new(common.clone(), rpc).to_delegate();
+    let debug = DebugRpcImpl::new(common.clone(), rpc.clone()).to_delegate();
+    let admin = AdminRpcImpl::new(common.clone(), rpc).to_delegate();
 
     // extend_with maps each method in RpcImpl object into a RPC handler
     let mut handler = MetaIoHandler::default();
     handler.extend_with(cfx);
     handler.extend_with(test);
     handler.extend_with(debug);
+    handler.extend_with(admin);
     if let Some(pubsub) = pubsub {
         handler.extend_with(pubsub.to_delegate());
     }
     handler
 }
 
+/// The debug APIs and, if the account service is enabled, the management
+/// APIs of the local keystore, which are only served on the local debug RPC
+/// server.
+pub fn setup_local_rpc_apis(
+    common: Arc<CommonImpl>, rpc: Arc<RpcImpl>, personal: Option<PersonalImpl>,
+) -> MetaIoHandler<Metadata> {
+    let mut handler = setup_debug_rpc_apis(common, rpc, None);
+    if let Some(personal) = personal {
+        handler.extend_with(personal.to_delegate());
+    }
+    handler
+}
+
 pub fn setup_public_rpc_apis_light(
     common: Arc<CommonImpl>, rpc: Arc<LightImpl>,
 ) -> MetaIoHandler<Metadata> {
@@ -147,26 +196,39 @@ pub fn setup_debug_rpc_apis_light(
     let cfx = LightCfxHandler::new(common.clone(), rpc.clone()).to_delegate();
     let test = LightTestRpcImpl::new(common.clone(), rpc.clone()).to_delegate();
     let debug = LightDebugRpcImpl::new(common.clone(), rpc).to_delegate();
+    let admin = LightAdminRpcImpl::new(common).to_delegate();
 
     // extend_with maps each method in RpcImpl object into a RPC handler
     let mut handler = MetaIoHandler::default();
     handler.extend_with(cfx);
     handler.extend_with(test);
     handler.extend_with(debug);
+    handler.extend_with(admin);
     handler
 }
 
-pub fn start_tcp<H, T>(
-    conf: TcpConfiguration, handler: H, extractor: T,
+/// The cfx APIs served from a database opened read-only, which are the same
+/// for the public and the debug RPC servers.
+pub fn setup_rpc_apis_query(rpc: Arc<QueryImpl>) -> MetaIoHandler<Metadata> {
+    let cfx = QueryCfxHandler::new(rpc).to_delegate();
+
+    let mut handler = MetaIoHandler::default();
+    handler.extend_with(cfx);
+    handler
+}
+
+pub fn start_tcp<T>(
+    conf: TcpConfiguration, handler: MetaIoHandler<Metadata>,
+    batch: BatchConfiguration, extractor: T,
 ) -> Result<Option<TcpServer>, String>
 where
-    H: Into<MetaIoHandler<Metadata>>,
     T: tcp::MetaExtractor<Metadata> + 'static,
 {
     if !conf.enabled {
         return Ok(None);
     }
 
+    let handler = BatchLimiter::wrap(handler, batch);
     match TcpServerBuilder::with_meta_extractor(handler, extractor)
         .start(&conf.address)
     {
@@ -177,14 +239,39 @@ where
     }
 }
 
+pub fn start_ws<T>(
+    conf: WsConfiguration, handler: MetaIoHandler<Metadata>,
+    batch: BatchConfiguration, extractor: T,
+) -> Result<Option<WsServer>, String>
+where
+    T: ws::MetaExtractor<Metadata> + 'static,
+{
+    if !conf.enabled {
+        return Ok(None);
+    }
+
+    let handler = BatchLimiter::wrap(handler, batch);
+    match WsServerBuilder::with_meta_extractor(handler, extractor)
+        .max_connections(conf.max_connections)
+        .start(&conf.address)
+    {
+        Ok(server) => Ok(Some(server)),
+        Err(ws_error) => {
+            Err(format!("WS error: {} (addr = {})", ws_error, conf.address))
+        }
+    }
+}
+
 pub fn start_http(
     conf: HttpConfiguration, handler: MetaIoHandler<Metadata>,
-) -> Result<Option<HttpServer>, String> {
+    batch: BatchConfiguration,
+) -> Result<Option<HttpServer>, String>
+{
     if !conf.enabled {
         return Ok(None);
     }
 
-    match HttpServerBuilder::new(handler)
+    match HttpServerBuilder::new(BatchLimiter::wrap(handler, batch))
         .keep_alive(conf.keep_alive)
         .cors(conf.cors_domains.clone())
         .start_http(&conf.address)
@@ -19,9 +19,10 @@
 use std::sync::Arc;
 
 use crate::rpc::{http_common::HttpMetaExtractor, Metadata, Origin};
+use cfx_types::H256;
 use jsonrpc_pubsub::Session;
 use jsonrpc_tcp_server as tcp;
-//use ws;
+use jsonrpc_ws_server as ws;
 
 /// Common HTTP & IPC metadata extractor.
 pub struct RpcExtractor;
@@ -52,6 +53,17 @@ impl tcp::MetaExtractor<Metadata> for RpcExtractor {
     }
 }
 
+impl ws::MetaExtractor<Metadata> for RpcExtractor {
+    fn extract(&self, req: &ws::RequestContext) -> Metadata {
+        Metadata {
+            origin: Origin::Ws {
+                session: H256::from_low_u64_be(req.session_id),
+            },
+            session: Some(Arc::new(Session::new(req.sender()))),
+        }
+    }
+}
+
 ///// WebSockets server metadata extractor and request middleware.
 //pub struct WsExtractor {
 //    authcodes_path: Option<PathBuf>,
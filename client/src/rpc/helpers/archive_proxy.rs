@@ -0,0 +1,150 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::errors;
+use jsonrpc_core::{
+    Call, Id, MethodCall, Output, Params, Request, Result as RpcResult, Value,
+    Version,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Forwards queries that a pruned node cannot answer to the JSON-RPC TCP
+/// endpoint of an archive node.
+pub struct ArchiveProxy {
+    address: SocketAddr,
+    timeout: Duration,
+    next_id: AtomicU64,
+}
+
+impl ArchiveProxy {
+    pub fn new(address: SocketAddr, timeout: Duration) -> Self {
+        ArchiveProxy {
+            address,
+            timeout,
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    pub fn call<T: DeserializeOwned>(
+        &self, method: &str, params: Vec<Value>,
+    ) -> RpcResult<T> {
+        debug!(
+            "Proxy RPC request {} to archive node {}",
+            method, self.address
+        );
+
+        let request = Request::Single(Call::MethodCall(MethodCall {
+            jsonrpc: Some(Version::V2),
+            method: method.into(),
+            params: Params::Array(params),
+            id: Id::Num(self.next_id.fetch_add(1, Ordering::Relaxed)),
+        }));
+        let mut line =
+            serde_json::to_string(&request).map_err(errors::fetch)?;
+        line.push('\n');
+
+        let mut stream =
+            TcpStream::connect_timeout(&self.address, self.timeout)
+                .map_err(errors::fetch)?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(errors::fetch)?;
+        stream
+            .set_write_timeout(Some(self.timeout))
+            .map_err(errors::fetch)?;
+        stream.write_all(line.as_bytes()).map_err(errors::fetch)?;
+
+        let mut response = String::new();
+        BufReader::new(stream)
+            .read_line(&mut response)
+            .map_err(errors::fetch)?;
+
+        match serde_json::from_str(&response).map_err(errors::fetch)? {
+            Output::Success(success) => {
+                serde_json::from_value(success.result).map_err(errors::fetch)
+            }
+            Output::Failure(failure) => Err(failure.error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArchiveProxy;
+    use jsonrpc_core::ErrorCode;
+    use serde_json::{json, Value};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+        time::Duration,
+    };
+
+    /// Serve one request on a local listener with `response`, and return
+    /// the proxy connected to it and the handle returning the request.
+    fn serve_once(
+        response: Value,
+    ) -> (ArchiveProxy, thread::JoinHandle<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let request: Value = serde_json::from_str(&line).unwrap();
+            let mut response = response;
+            response["id"] = request["id"].clone();
+            let mut stream = reader.into_inner();
+            writeln!(stream, "{}", response).unwrap();
+            request
+        });
+        (ArchiveProxy::new(address, Duration::from_secs(5)), handle)
+    }
+
+    #[test]
+    fn test_archive_proxy_success() {
+        let (proxy, handle) =
+            serve_once(json!({"jsonrpc": "2.0", "result": "0x10"}));
+        let result: String = proxy
+            .call("cfx_getBalance", vec![json!("0x01"), json!("0x5")])
+            .unwrap();
+        assert_eq!(result, "0x10");
+
+        let request = handle.join().unwrap();
+        assert_eq!(request["jsonrpc"], "2.0");
+        assert_eq!(request["method"], "cfx_getBalance");
+        assert_eq!(request["params"], json!(["0x01", "0x5"]));
+    }
+
+    #[test]
+    fn test_archive_proxy_failure() {
+        let (proxy, handle) = serve_once(json!({
+            "jsonrpc": "2.0",
+            "error": {"code": -32602, "message": "invalid params"},
+        }));
+        let err = proxy
+            .call::<String>("cfx_getCode", vec![json!("0x01")])
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidParams);
+        assert_eq!(err.message, "invalid params");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_archive_proxy_unreachable() {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let proxy = ArchiveProxy::new(address, Duration::from_secs(5));
+        assert!(proxy.call::<String>("cfx_getCode", vec![]).is_err());
+    }
+}
@@ -0,0 +1,369 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::rpc::Metadata;
+use jsonrpc_core::{
+    futures::{
+        future::{self, Either},
+        sync::oneshot,
+        Future,
+    },
+    middleware, Call, Error, ErrorCode, Failure, MetaIoHandler, Middleware,
+    Output, Request, Response, Version,
+};
+use parking_lot::Mutex;
+use std::{
+    cmp,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use threadpool::ThreadPool;
+
+type BatchFuture = Box<dyn Future<Item = Option<Response>, Error = ()> + Send>;
+
+#[derive(Clone, Debug)]
+pub struct BatchConfiguration {
+    /// The maximum number of calls in a batch request, or 0 for no limit.
+    pub max_batch_size: usize,
+    /// The maximum number of calls of the batch requests processed at the
+    /// same time.
+    pub max_concurrency: usize,
+    /// The maximum total size in bytes of the responses to the calls of a
+    /// batch request, or 0 for no limit.
+    pub max_response_bytes: usize,
+}
+
+/// Processes the calls of batch requests in a pool of `max_concurrency`
+/// threads, and rejects the whole batch if it has too many calls or its
+/// responses grow beyond `max_response_bytes`, so that a single batch can't
+/// occupy the rpc server. Other requests are passed to the wrapped handler as
+/// is.
+pub struct BatchLimiter {
+    handler: Arc<MetaIoHandler<Metadata>>,
+    conf: BatchConfiguration,
+    workers: Mutex<ThreadPool>,
+}
+
+impl BatchLimiter {
+    /// Wrap `handler` with the batch limits in `conf`.
+    pub fn wrap(
+        handler: MetaIoHandler<Metadata>, conf: BatchConfiguration,
+    ) -> MetaIoHandler<Metadata, BatchLimiter> {
+        let workers = ThreadPool::with_name(
+            "rpc_batch".into(),
+            cmp::max(conf.max_concurrency, 1),
+        );
+        MetaIoHandler::with_middleware(BatchLimiter {
+            handler: Arc::new(handler),
+            conf,
+            workers: Mutex::new(workers),
+        })
+    }
+
+    /// Respond to each call of a rejected batch with a failure of `message`.
+    /// The notifications are not responded.
+    fn reject(calls: &[Call], message: String) -> Option<Response> {
+        let outputs: Vec<Output> = calls
+            .iter()
+            .filter_map(|call| match call {
+                Call::MethodCall(call) => Some(call.id.clone()),
+                Call::Invalid { id } => Some(id.clone()),
+                Call::Notification(_) => None,
+            })
+            .map(|id| {
+                Output::Failure(Failure {
+                    jsonrpc: Some(Version::V2),
+                    error: Error {
+                        code: ErrorCode::InvalidRequest,
+                        message: message.clone(),
+                        data: None,
+                    },
+                    id,
+                })
+            })
+            .collect();
+        if outputs.is_empty() {
+            None
+        } else {
+            Some(Response::Batch(outputs))
+        }
+    }
+
+    fn handle_batch(&self, calls: Vec<Call>, meta: Metadata) -> BatchFuture {
+        let max_batch_size = self.conf.max_batch_size;
+        if max_batch_size != 0 && calls.len() > max_batch_size {
+            let message = format!(
+                "Batch request has {} calls, more than the limit {}",
+                calls.len(),
+                max_batch_size
+            );
+            return Box::new(future::ok(Self::reject(&calls, message)));
+        }
+
+        let max_response_bytes = match self.conf.max_response_bytes {
+            0 => usize::max_value(),
+            max_response_bytes => max_response_bytes,
+        };
+        let response_bytes = Arc::new(AtomicUsize::new(0));
+        let exceeded = Arc::new(AtomicBool::new(false));
+
+        let workers = self.workers.lock();
+        let outputs: Vec<_> = calls
+            .iter()
+            .cloned()
+            .map(|call| {
+                let (sender, receiver) = oneshot::channel();
+                let handler = self.handler.clone();
+                let meta = meta.clone();
+                let response_bytes = response_bytes.clone();
+                let exceeded = exceeded.clone();
+                workers.execute(move || {
+                    // The calls left when the responses exceed the limit are
+                    // not executed, since the batch is rejected anyway.
+                    if exceeded.load(Ordering::Relaxed) {
+                        sender.send(None).ok();
+                        return;
+                    }
+                    let output =
+                        handler.handle_call(call, meta).wait().unwrap_or(None);
+                    if let Some(ref output) = output {
+                        let size = serde_json::to_vec(output)
+                            .map_or(0, |bytes| bytes.len());
+                        let total = response_bytes
+                            .fetch_add(size, Ordering::Relaxed)
+                            .saturating_add(size);
+                        if total > max_response_bytes {
+                            exceeded.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    sender.send(output).ok();
+                });
+                receiver
+            })
+            .collect();
+        drop(workers);
+
+        Box::new(future::join_all(outputs).then(move |result| {
+            // The receivers are only cancelled if a worker panics.
+            let outputs = result.map_err(|_| ())?;
+            if exceeded.load(Ordering::Relaxed) {
+                let message = format!(
+                    "Batch response exceeds the limit {} bytes",
+                    max_response_bytes
+                );
+                return Ok(Self::reject(&calls, message));
+            }
+            let outputs: Vec<Output> =
+                outputs.into_iter().filter_map(|output| output).collect();
+            // A batch of notifications has no response.
+            if outputs.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(Response::Batch(outputs)))
+            }
+        }))
+    }
+}
+
+impl Middleware<Metadata> for BatchLimiter {
+    type CallFuture = middleware::NoopCallFuture;
+    type Future = BatchFuture;
+
+    fn on_request<F, X>(
+        &self, request: Request, meta: Metadata, _next: F,
+    ) -> Either<Self::Future, X>
+    where
+        F: FnOnce(Request, Metadata) -> X + Send,
+        X: Future<Item = Option<Response>, Error = ()> + Send + 'static,
+    {
+        match request {
+            Request::Batch(calls) => Either::A(self.handle_batch(calls, meta)),
+            request => Either::A(Box::new(
+                self.handler.handle_rpc_request(request, meta),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BatchConfiguration, BatchLimiter};
+    use crate::rpc::Metadata;
+    use jsonrpc_core::{MetaIoHandler, Params, Value};
+    use parking_lot::Mutex;
+    use serde_json::json;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    fn batch_config(
+        max_batch_size: usize, max_concurrency: usize,
+        max_response_bytes: usize,
+    ) -> BatchConfiguration
+    {
+        BatchConfiguration {
+            max_batch_size,
+            max_concurrency,
+            max_response_bytes,
+        }
+    }
+
+    /// A handler whose `echo` method returns its parameters, and counts the
+    /// calls in `calls`.
+    fn echo_handler(calls: Arc<AtomicUsize>) -> MetaIoHandler<Metadata> {
+        let mut handler = MetaIoHandler::default();
+        handler.add_method("echo", move |params: Params| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(match params {
+                Params::Array(values) => Value::Array(values),
+                _ => Value::Null,
+            })
+        });
+        handler
+    }
+
+    fn echo_batch(count: usize, text: &str) -> String {
+        let calls: Vec<_> = (0..count)
+            .map(|id| {
+                json!({"jsonrpc": "2.0", "method": "echo", "params": [text], "id": id})
+            })
+            .collect();
+        Value::Array(calls).to_string()
+    }
+
+    fn handle(
+        handler: &MetaIoHandler<Metadata, BatchLimiter>, request: &str,
+    ) -> Option<Value> {
+        handler
+            .handle_request_sync(request, Metadata::default())
+            .map(|response| serde_json::from_str(&response).unwrap())
+    }
+
+    /// The ids of the outputs in a batch response, and whether they failed.
+    fn outputs(response: Value) -> Vec<(u64, bool)> {
+        response
+            .as_array()
+            .expect("batch response")
+            .iter()
+            .map(|output| {
+                (
+                    output["id"].as_u64().unwrap(),
+                    output.get("error").is_some(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_batch_size_limit() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = BatchLimiter::wrap(
+            echo_handler(calls.clone()),
+            batch_config(2, 2, 0),
+        );
+
+        let response = handle(&handler, &echo_batch(2, "a")).unwrap();
+        assert_eq!(outputs(response), vec![(0, false), (1, false)]);
+
+        // Each call of the rejected batch fails with its own id, and none of
+        // them is executed.
+        let response = handle(&handler, &echo_batch(3, "a")).unwrap();
+        assert_eq!(outputs(response), vec![(0, true), (1, true), (2, true)]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_no_batch_size_limit() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler =
+            BatchLimiter::wrap(echo_handler(calls), batch_config(0, 2, 0));
+        let response = handle(&handler, &echo_batch(10, "a")).unwrap();
+        assert_eq!(outputs(response).len(), 10);
+    }
+
+    #[test]
+    fn test_response_size_limit() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = BatchLimiter::wrap(
+            echo_handler(calls.clone()),
+            batch_config(0, 1, 1500),
+        );
+
+        let text = "a".repeat(1000);
+        let response = handle(&handler, &echo_batch(1, &text)).unwrap();
+        assert_eq!(outputs(response), vec![(0, false)]);
+
+        // The calls after the responses exceed the limit are not executed.
+        calls.store(0, Ordering::SeqCst);
+        let response = handle(&handler, &echo_batch(4, &text)).unwrap();
+        assert_eq!(
+            outputs(response),
+            vec![(0, true), (1, true), (2, true), (3, true)]
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_notifications() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = BatchLimiter::wrap(
+            echo_handler(calls.clone()),
+            batch_config(1, 1, 0),
+        );
+        let notification =
+            json!({"jsonrpc": "2.0", "method": "echo", "params": []});
+        let batch = Value::Array(vec![notification]).to_string();
+        assert_eq!(handle(&handler, &batch), None);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A rejected batch of notifications has no response either.
+        let notification =
+            json!({"jsonrpc": "2.0", "method": "echo", "params": []});
+        let batch =
+            Value::Array(vec![notification.clone(), notification]).to_string();
+        assert_eq!(handle(&handler, &batch), None);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_concurrency() {
+        // The numbers of the calls running now and at most.
+        let running = Arc::new(Mutex::new((0, 0)));
+        let mut handler = MetaIoHandler::default();
+        {
+            let running = running.clone();
+            handler.add_method("sleep", move |_| {
+                {
+                    let mut running = running.lock();
+                    running.0 += 1;
+                    running.1 = running.1.max(running.0);
+                }
+                thread::sleep(Duration::from_millis(100));
+                running.lock().0 -= 1;
+                Ok(Value::Null)
+            });
+        }
+        let handler = BatchLimiter::wrap(handler, batch_config(0, 3, 0));
+
+        let calls: Vec<_> = (0..6)
+            .map(|id| json!({"jsonrpc": "2.0", "method": "sleep", "params": [], "id": id}))
+            .collect();
+        let response =
+            handle(&handler, &Value::Array(calls).to_string()).unwrap();
+        // The responses keep the order of the calls.
+        assert_eq!(
+            outputs(response),
+            (0..6).map(|id| (id, false)).collect::<Vec<_>>()
+        );
+        let max_running = running.lock().1;
+        assert!(max_running > 1 && max_running <= 3);
+    }
+}
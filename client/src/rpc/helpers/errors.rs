@@ -20,7 +20,10 @@
 
 use std::fmt;
 
+use cfxcore::consensus::ConsensusError;
+use ethcore_accounts::{Error as AccountError, SignError};
 use jsonrpc_core::{Error, ErrorCode, Value};
+use rustc_hex::ToHex;
 
 mod codes {
     // NOTE [ToDr] Codes from [-32099, -32000]
@@ -36,9 +39,7 @@ mod codes {
     pub const EXECUTION_ERROR: i64 = -32015;
     pub const EXCEPTION_ERROR: i64 = -32016;
     pub const DATABASE_ERROR: i64 = -32017;
-    #[cfg(any(test, feature = "accounts"))]
     pub const ACCOUNT_LOCKED: i64 = -32020;
-    #[cfg(any(test, feature = "accounts"))]
     pub const PASSWORD_INVALID: i64 = -32021;
     pub const ACCOUNT_ERROR: i64 = -32023;
     pub const PRIVATE_ERROR: i64 = -32024;
@@ -53,6 +54,8 @@ mod codes {
     pub const DEPRECATED: i64 = -32070;
     pub const EXPERIMENTAL_RPC: i64 = -32071;
     pub const CANNOT_RESTART: i64 = -32080;
+    pub const INVALID_EPOCH: i64 = -32090;
+    pub const STATE_UNAVAILABLE: i64 = -32091;
 }
 
 pub fn unimplemented(details: Option<String>) -> Error {
@@ -63,6 +66,119 @@ pub fn unimplemented(details: Option<String>) -> Error {
     }
 }
 
+pub fn subscription_limit_exceeded(limit: usize) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::REQUEST_REJECTED_LIMIT),
+        message: format!(
+            "Too many subscriptions on this connection (limit {}).",
+            limit
+        ),
+        data: None,
+    }
+}
+
+pub fn fetch<T: fmt::Debug>(error: T) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::FETCH_ERROR),
+        message: "Error while fetching content.".into(),
+        data: Some(Value::String(format!("{:?}", error))),
+    }
+}
+
+/// The epoch number or hash doesn't refer to an existing or executed epoch.
+pub fn invalid_epoch(details: String) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::INVALID_EPOCH),
+        message: "Invalid epoch.".into(),
+        data: Some(Value::String(details)),
+    }
+}
+
+/// The transaction is rejected by the transaction pool with the reason.
+pub fn transaction_rejected(reason: String) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::TRANSACTION_ERROR),
+        message: "Transaction rejected.".into(),
+        data: Some(Value::String(reason)),
+    }
+}
+
+/// The output of a reverted execution is returned in `data`, so that tools
+/// can decode the revert reason.
+fn execution_reverted(output: Vec<u8>) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::EXECUTION_ERROR),
+        message: "Transaction execution reverted.".into(),
+        data: Some(Value::String(format!("0x{}", output.to_hex()))),
+    }
+}
+
+pub fn consensus(error: ConsensusError) -> Error {
+    match error {
+        ConsensusError::InvalidEpoch(details) => invalid_epoch(details),
+        ConsensusError::StateUnavailable(details) => Error {
+            code: ErrorCode::ServerError(codes::STATE_UNAVAILABLE),
+            message: "State is not available.".into(),
+            data: Some(Value::String(details)),
+        },
+        ConsensusError::InvalidTransaction(details) => Error {
+            code: ErrorCode::ServerError(codes::TRANSACTION_ERROR),
+            message: "Invalid transaction.".into(),
+            data: Some(Value::String(details)),
+        },
+        ConsensusError::Reverted(output) => execution_reverted(output),
+        ConsensusError::OutOfGas { gas_limit } => Error {
+            code: ErrorCode::ServerError(codes::EXECUTION_ERROR),
+            message: format!(
+                "Transaction execution out of gas with gas limit {}.",
+                gas_limit
+            ),
+            data: None,
+        },
+        ConsensusError::ExecutionFailed(details) => Error {
+            code: ErrorCode::ServerError(codes::EXCEPTION_ERROR),
+            message: "Transaction execution failed.".into(),
+            data: Some(Value::String(details)),
+        },
+        ConsensusError::Other(details) => Error::invalid_params(details),
+    }
+}
+
+pub fn account<T: fmt::Debug>(error: &str, details: T) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::ACCOUNT_ERROR),
+        message: error.into(),
+        data: Some(Value::String(format!("{:?}", details))),
+    }
+}
+
+pub fn password(error: AccountError) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::PASSWORD_INVALID),
+        message: "Account password is invalid or account does not exist."
+            .into(),
+        data: Some(Value::String(format!("{:?}", error))),
+    }
+}
+
+pub fn signing(error: SignError) -> Error {
+    match error {
+        SignError::NotUnlocked => Error {
+            code: ErrorCode::ServerError(codes::ACCOUNT_LOCKED),
+            message: "Your account is locked. Unlock the account via \
+                      personal_unlockAccount or pass the password."
+                .into(),
+            data: None,
+        },
+        SignError::NotFound => Error {
+            code: ErrorCode::ServerError(codes::ACCOUNT_ERROR),
+            message: "Account does not exist.".into(),
+            data: None,
+        },
+        SignError::SStore(error) => password(error),
+    }
+}
+
 pub fn invalid_params<T: fmt::Debug>(param: &str, details: T) -> Error {
     Error {
         code: ErrorCode::InvalidParams,
@@ -70,3 +186,65 @@ pub fn invalid_params<T: fmt::Debug>(param: &str, details: T) -> Error {
         data: Some(Value::String(format!("{:?}", details))),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{codes, consensus};
+    use cfxcore::consensus::ConsensusError;
+    use jsonrpc_core::{ErrorCode, Value};
+
+    #[test]
+    fn test_consensus_error_codes() {
+        let code = |e| consensus(e).code;
+        assert_eq!(
+            code(ConsensusError::InvalidEpoch("".into())),
+            ErrorCode::ServerError(codes::INVALID_EPOCH)
+        );
+        assert_eq!(
+            code(ConsensusError::StateUnavailable("".into())),
+            ErrorCode::ServerError(codes::STATE_UNAVAILABLE)
+        );
+        assert_eq!(
+            code(ConsensusError::InvalidTransaction("".into())),
+            ErrorCode::ServerError(codes::TRANSACTION_ERROR)
+        );
+        assert_eq!(
+            code(ConsensusError::ExecutionFailed("".into())),
+            ErrorCode::ServerError(codes::EXCEPTION_ERROR)
+        );
+        assert_eq!(
+            code(ConsensusError::Other("".into())),
+            ErrorCode::InvalidParams
+        );
+
+        // the output of a reverted execution is kept for decoding the reason
+        let reverted = consensus(ConsensusError::Reverted(vec![0xab, 0xcd]));
+        assert_eq!(
+            reverted.code,
+            ErrorCode::ServerError(codes::EXECUTION_ERROR)
+        );
+        assert_eq!(reverted.data, Some(Value::String("0xabcd".into())));
+
+        let out_of_gas = consensus(ConsensusError::OutOfGas {
+            gas_limit: 100000.into(),
+        });
+        assert_eq!(
+            out_of_gas.code,
+            ErrorCode::ServerError(codes::EXECUTION_ERROR)
+        );
+        assert_eq!(
+            out_of_gas.message,
+            "Transaction execution out of gas with gas limit 100000."
+        );
+    }
+
+    #[test]
+    fn test_consensus_error_to_string_keeps_kind() {
+        let pruned: String =
+            ConsensusError::StateUnavailable("pruned".into()).into();
+        assert_eq!(pruned, "state unavailable: pruned");
+
+        let reverted: String = ConsensusError::Reverted(vec![0x01]).into();
+        assert_eq!(reverted, "reverted: execution reverted with output 0x01");
+    }
+}
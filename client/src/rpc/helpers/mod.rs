@@ -1,8 +1,18 @@
 #[macro_use]
 pub mod errors;
 
+mod archive_proxy;
+mod batch_limiter;
 mod poll_manager;
+mod query_cache;
+mod state_handles;
 mod subscribers;
 //mod subscription_mananger;
 
-pub use self::subscribers::Subscribers;
+pub use self::{
+    archive_proxy::ArchiveProxy,
+    batch_limiter::{BatchConfiguration, BatchLimiter},
+    query_cache::QueryCache,
+    state_handles::StateHandles,
+    subscribers::Subscribers,
+};
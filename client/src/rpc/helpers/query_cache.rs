@@ -0,0 +1,163 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use jsonrpc_core::{Result as RpcResult, Value};
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// A small TTL cache for the results of idempotent heavy queries, e.g.
+/// `cfx_getLogs` with identical filters issued by dashboards every few
+/// seconds. Results are stored as JSON values keyed by the method name and
+/// the request parameters. The cache should be cleared whenever the pivot
+/// chain is updated.
+pub struct QueryCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// The number of times the cache has been cleared. A result computed
+    /// before a clear is not cached, as it may be computed against the
+    /// previous pivot chain.
+    generation: u64,
+    results: HashMap<String, (Instant, Value)>,
+}
+
+impl QueryCache {
+    /// Create a cache holding at most `capacity` results for `ttl`. The cache
+    /// is disabled if either of them is zero.
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        QueryCache {
+            ttl,
+            capacity,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.ttl > Duration::from_secs(0) && self.capacity > 0
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock();
+        entries.generation += 1;
+        entries.results.clear();
+    }
+
+    /// Return the cached result of `key` if not expired, otherwise compute
+    /// it with `compute` and cache the result if it succeeds.
+    pub fn get_or_compute<T, F>(&self, key: String, compute: F) -> RpcResult<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> RpcResult<T>,
+    {
+        self.get_or_compute_if(key, compute, |_| true)
+    }
+
+    /// Like `get_or_compute`, but a computed result is only cached if
+    /// `cacheable` returns true for it.
+    pub fn get_or_compute_if<T, F, P>(
+        &self, key: String, compute: F, cacheable: P,
+    ) -> RpcResult<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> RpcResult<T>,
+        P: FnOnce(&T) -> bool,
+    {
+        if !self.is_enabled() {
+            return compute();
+        }
+
+        let generation = {
+            let entries = self.entries.lock();
+            if let Some((time, value)) = entries.results.get(&key) {
+                if time.elapsed() < self.ttl {
+                    if let Ok(result) = serde_json::from_value(value.clone()) {
+                        return Ok(result);
+                    }
+                }
+            }
+            entries.generation
+        };
+
+        let result = compute()?;
+        if !cacheable(&result) {
+            return Ok(result);
+        }
+
+        if let Ok(value) = serde_json::to_value(&result) {
+            let mut entries = self.entries.lock();
+            if entries.generation != generation {
+                return Ok(result);
+            }
+            if entries.results.len() >= self.capacity {
+                let ttl = self.ttl;
+                entries.results.retain(|_, (time, _)| time.elapsed() < ttl);
+            }
+            if entries.results.len() < self.capacity {
+                entries.results.insert(key, (Instant::now(), value));
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueryCache;
+    use std::{cell::Cell, time::Duration};
+
+    #[test]
+    fn test_query_cache() {
+        let cache = QueryCache::new(Duration::from_secs(60), 1);
+        let computed = Cell::new(0);
+        let compute = |value: u64| {
+            computed.set(computed.get() + 1);
+            Ok(value)
+        };
+
+        assert_eq!(cache.get_or_compute("a".into(), || compute(1)), Ok(1));
+        assert_eq!(cache.get_or_compute("a".into(), || compute(2)), Ok(1));
+        assert_eq!(computed.get(), 1);
+
+        // cache is full
+        assert_eq!(cache.get_or_compute("b".into(), || compute(3)), Ok(3));
+        assert_eq!(cache.get_or_compute("b".into(), || compute(4)), Ok(4));
+        assert_eq!(computed.get(), 3);
+
+        cache.clear();
+        assert_eq!(cache.get_or_compute("a".into(), || compute(5)), Ok(5));
+        assert_eq!(computed.get(), 4);
+    }
+
+    #[test]
+    fn test_query_cache_cleared_during_compute() {
+        let cache = QueryCache::new(Duration::from_secs(60), 10);
+        // The result computed against the previous pivot chain is returned
+        // but not cached.
+        let result = cache.get_or_compute("a".into(), || {
+            cache.clear();
+            Ok(1)
+        });
+        assert_eq!(result, Ok(1));
+        assert_eq!(cache.get_or_compute("a".into(), || Ok(2)), Ok(2));
+        assert_eq!(cache.get_or_compute("a".into(), || Ok(3)), Ok(2));
+    }
+
+    #[test]
+    fn test_query_cache_uncacheable_result() {
+        let cache = QueryCache::new(Duration::from_secs(60), 10);
+        let result = cache.get_or_compute_if("a".into(), || Ok(1), |_| false);
+        assert_eq!(result, Ok(1));
+        assert_eq!(cache.get_or_compute("a".into(), || Ok(2)), Ok(2));
+        assert_eq!(cache.get_or_compute("a".into(), || Ok(3)), Ok(2));
+    }
+}
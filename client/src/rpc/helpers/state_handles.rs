@@ -0,0 +1,183 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use cfx_types::H256;
+use cfxcore::consensus::ConsensusError;
+use parking_lot::Mutex;
+use std::{
+    cmp,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// A state handle pins the state of an executed epoch by its hash, so that
+/// multiple reads issued by a client are served from exactly the same
+/// snapshot, even if the pivot chain changes in between. Handles expire
+/// after a bounded TTL, and a handle is closed once the state of its epoch
+/// is found pruned, so it never serves the reads from another snapshot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StateHandle {
+    pub epoch_number: u64,
+    pub epoch_hash: H256,
+    expire_at: Instant,
+}
+
+struct StateHandlesInner {
+    next_id: u64,
+    handles: HashMap<u64, StateHandle>,
+}
+
+pub struct StateHandles {
+    max_ttl: Duration,
+    capacity: usize,
+    inner: Mutex<StateHandlesInner>,
+}
+
+impl StateHandles {
+    /// Create a registry holding at most `capacity` handles, each of which
+    /// lives no longer than `max_ttl`.
+    pub fn new(max_ttl: Duration, capacity: usize) -> Self {
+        StateHandles {
+            max_ttl,
+            capacity,
+            inner: Mutex::new(StateHandlesInner {
+                next_id: 0,
+                handles: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Open a handle of the specified epoch, which expires after `ttl` or
+    /// `max_ttl` if not specified or larger.
+    pub fn open(
+        &self, epoch_number: u64, epoch_hash: H256, ttl: Option<Duration>,
+    ) -> Result<u64, String> {
+        let ttl = cmp::min(ttl.unwrap_or(self.max_ttl), self.max_ttl);
+        let now = Instant::now();
+
+        let mut inner = self.inner.lock();
+        inner.handles.retain(|_, handle| handle.expire_at > now);
+        if inner.handles.len() >= self.capacity {
+            return Err(format!(
+                "Too many open state handles, the limit is {}",
+                self.capacity
+            ));
+        }
+
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.handles.insert(
+            id,
+            StateHandle {
+                epoch_number,
+                epoch_hash,
+                expire_at: now + ttl,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Return the handle with the given id if it has not expired.
+    pub fn get(&self, id: u64) -> Option<StateHandle> {
+        let mut inner = self.inner.lock();
+        let handle = *inner.handles.get(&id)?;
+        if handle.expire_at <= Instant::now() {
+            inner.handles.remove(&id);
+            return None;
+        }
+        Some(handle)
+    }
+
+    /// Read the state pinned by the handle with the given id with `f`. If
+    /// the state has been pruned, the handle is closed and later reads with
+    /// it fail as if it has expired.
+    pub fn read<T, F>(&self, id: u64, f: F) -> Result<T, ConsensusError>
+    where F: FnOnce(&H256) -> Result<T, ConsensusError> {
+        let handle = self.get(id).ok_or_else(|| {
+            ConsensusError::Other(format!(
+                "State handle {} does not exist or has expired",
+                id
+            ))
+        })?;
+        match f(&handle.epoch_hash) {
+            Err(ConsensusError::StateUnavailable(details)) => {
+                self.close(id);
+                Err(ConsensusError::StateUnavailable(format!(
+                    "The state of epoch {:?} pinned by handle {} has been \
+                     pruned and the handle is closed: {}",
+                    handle.epoch_hash, id, details
+                )))
+            }
+            result => result,
+        }
+    }
+
+    /// Close the handle with the given id, return `false` if it does not
+    /// exist or has expired.
+    pub fn close(&self, id: u64) -> bool {
+        match self.inner.lock().handles.remove(&id) {
+            Some(handle) => handle.expire_at > Instant::now(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StateHandles;
+    use cfx_types::H256;
+    use cfxcore::consensus::ConsensusError;
+    use std::time::Duration;
+
+    #[test]
+    fn test_state_handles() {
+        let handles = StateHandles::new(Duration::from_secs(60), 2);
+        let hash = H256::from_low_u64_be(1);
+
+        let a = handles.open(1, hash, None).unwrap();
+        let b = handles.open(1, hash, Some(Duration::from_secs(0))).unwrap();
+        assert_eq!(handles.get(a).map(|h| h.epoch_hash), Some(hash));
+
+        // expired handles are removed
+        assert_eq!(handles.get(b), None);
+        let c = handles
+            .open(2, hash, Some(Duration::from_secs(3600)))
+            .unwrap();
+
+        // the handle limit is reached
+        assert!(handles.open(3, hash, None).is_err());
+
+        assert!(handles.close(a));
+        assert!(!handles.close(a));
+        assert_eq!(handles.get(c).map(|h| h.epoch_number), Some(2));
+    }
+
+    #[test]
+    fn test_read_closes_pruned_handle() {
+        let handles = StateHandles::new(Duration::from_secs(60), 2);
+        let hash = H256::from_low_u64_be(1);
+        let a = handles.open(1, hash, None).unwrap();
+
+        assert_eq!(handles.read(a, |epoch_hash| Ok(*epoch_hash)), Ok(hash));
+        // other errors keep the handle open
+        assert!(handles
+            .read(a, |_| -> Result<(), _> {
+                Err(ConsensusError::Other("no account".into()))
+            })
+            .is_err());
+        assert!(handles.get(a).is_some());
+
+        match handles.read(a, |_| -> Result<(), _> {
+            Err(ConsensusError::StateUnavailable("pruned".into()))
+        }) {
+            Err(ConsensusError::StateUnavailable(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(handles.get(a), None);
+        match handles.read(a, |epoch_hash| Ok(*epoch_hash)) {
+            Err(ConsensusError::Other(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -113,26 +113,30 @@ impl<T> Subscribers<T> {
 }
 
 impl<T> Subscribers<Sink<T>> {
-    /// Assigns id and adds a subscriber to the list.
-    pub fn push(&mut self, sub: Subscriber<T>) {
+    /// Assigns id and adds a subscriber to the list. Returns the assigned id
+    /// if the subscriber is still connected.
+    pub fn push(&mut self, sub: Subscriber<T>) -> Option<SubscriptionId> {
         let id = self.next_id();
-        if let Ok(sink) = sub.assign_id(SubscriptionId::String(id.as_string()))
-        {
-            debug!(target: "pubsub", "Adding subscription id={:?}", id);
-            self.subscriptions.insert(id, sink);
-        }
+        let subscription_id = SubscriptionId::String(id.as_string());
+        let sink = sub.assign_id(subscription_id.clone()).ok()?;
+        debug!(target: "pubsub", "Adding subscription id={:?}", id);
+        self.subscriptions.insert(id, sink);
+        Some(subscription_id)
     }
 }
 
 impl<T, V> Subscribers<(Sink<T>, V)> {
-    /// Assigns id and adds a subscriber to the list.
-    pub fn push(&mut self, sub: Subscriber<T>, val: V) {
+    /// Assigns id and adds a subscriber to the list. Returns the assigned id
+    /// if the subscriber is still connected.
+    pub fn push(
+        &mut self, sub: Subscriber<T>, val: V,
+    ) -> Option<SubscriptionId> {
         let id = self.next_id();
-        if let Ok(sink) = sub.assign_id(SubscriptionId::String(id.as_string()))
-        {
-            debug!(target: "pubsub", "Adding subscription id={:?}", id);
-            self.subscriptions.insert(id, (sink, val));
-        }
+        let subscription_id = SubscriptionId::String(id.as_string());
+        let sink = sub.assign_id(subscription_id.clone()).ok()?;
+        debug!(target: "pubsub", "Adding subscription id={:?}", id);
+        self.subscriptions.insert(id, (sink, val));
+        Some(subscription_id)
     }
 }
 
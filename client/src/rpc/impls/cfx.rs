@@ -4,40 +4,68 @@
 
 use delegate::delegate;
 
-use crate::rpc::{
-    traits::{cfx::Cfx, debug::DebugRpc, test::TestRpc},
-    types::{
-        BlameInfo, Block as RpcBlock, Bytes, EpochNumber, Filter as RpcFilter,
-        Log as RpcLog, Receipt as RpcReceipt, Status as RpcStatus,
-        Transaction as RpcTransaction, H160 as RpcH160, H256 as RpcH256,
-        U256 as RpcU256, U64 as RpcU64,
+use crate::{
+    config_reload::ReloadReport,
+    rpc::{
+        helpers::{errors, ArchiveProxy, QueryCache, StateHandles},
+        traits::{admin::AdminRpc, cfx::Cfx, debug::DebugRpc, test::TestRpc},
+        types::{
+            AccountState, BlameInfo, Block as RpcBlock, BlockTemplate, Bytes,
+            ChainHealth, DecodedTransaction, DifficultyPeriod, EpochNumber,
+            ExcludedTerminal, Filter as RpcFilter, LockContention,
+            Log as RpcLog, MisbehaviorReport, Peer, Receipt as RpcReceipt,
+            SkippedTransactions, StateVerification, Status as RpcStatus,
+            Transaction as RpcTransaction, TxPoolAdmission, H160 as RpcH160,
+            H256 as RpcH256, U256 as RpcU256, U64 as RpcU64,
+        },
     },
 };
 use blockgen::BlockGenerator;
-use cfx_types::{H160, H256};
+use cfx_types::{H160, H256, U64};
 use cfxcore::{
-    block_parameters::MAX_BLOCK_SIZE_IN_BYTES, PeerInfo, SharedConsensusGraph,
-    SharedSynchronizationService, SharedTransactionPool,
+    block_parameters::MAX_BLOCK_SIZE_IN_BYTES,
+    consensus::{BestInfoObserverId, ConsensusError, TreeGraphFormat},
+    pow::{simulate_difficulties, MAX_DIFFICULTY_HISTORY},
+    PeerInfo, SharedConsensusGraph, SharedSynchronizationService,
+    SharedTransactionPool,
 };
 use jsonrpc_core::{Error as RpcError, Result as RpcResult};
 use network::{
     node_table::{Node, NodeId},
-    throttling, SessionDetails, UpdateNodeOperation,
+    throttling, Ban, BanTarget, SessionDetails, UpdateNodeOperation,
 };
+use parking_lot::Mutex;
 use primitives::{
     Action, SignedTransaction, Transaction, TransactionWithSignature,
 };
 use rlp::Rlp;
-use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 
 use super::common::RpcImpl as CommonImpl;
 
+/// The default number of recent epochs exported by `debug_exportTreeGraph`.
+const DEFAULT_TREE_GRAPH_WINDOW: u64 = 100;
+
 pub struct RpcImpl {
     pub consensus: SharedConsensusGraph,
     sync: SharedSynchronizationService,
     block_gen: Arc<BlockGenerator>,
     tx_pool: SharedTransactionPool,
     tx_gen: Arc<TransactionGenerator>,
+    archive_proxy: Option<ArchiveProxy>,
+    query_cache: Arc<QueryCache>,
+    state_handles: StateHandles,
+    /// The observer clearing `query_cache`, which is unregistered when the
+    /// `RpcImpl` is dropped.
+    best_info_observer: BestInfoObserverId,
 }
 use txgen::TransactionGenerator;
 
@@ -45,15 +73,55 @@ impl RpcImpl {
     pub fn new(
         consensus: SharedConsensusGraph, sync: SharedSynchronizationService,
         block_gen: Arc<BlockGenerator>, tx_pool: SharedTransactionPool,
-        tx_gen: Arc<TransactionGenerator>,
+        tx_gen: Arc<TransactionGenerator>, archive_proxy: Option<ArchiveProxy>,
+        query_cache: QueryCache, state_handles: StateHandles,
     ) -> Self
     {
+        let query_cache = Arc::new(query_cache);
+
+        // cached results may be changed once the pivot chain is updated
+        let weak = Arc::downgrade(&query_cache);
+        let last_best_hash = Mutex::new(H256::zero());
+        let best_info_observer =
+            consensus.register_best_info_observer(Box::new(move |best_info| {
+                let mut last_best_hash = last_best_hash.lock();
+                if *last_best_hash != best_info.best_block_hash {
+                    *last_best_hash = best_info.best_block_hash;
+                    if let Some(query_cache) = weak.upgrade() {
+                        query_cache.clear();
+                    }
+                }
+            }));
+
         RpcImpl {
             consensus,
             sync,
             block_gen,
             tx_pool,
             tx_gen,
+            archive_proxy,
+            query_cache,
+            state_handles,
+            best_info_observer,
+        }
+    }
+
+    /// Return `local` if it succeeds. If it fails because the state of the
+    /// epoch has been pruned locally, forward the request to the archive node
+    /// if one is configured. Other errors, e.g. an invalid epoch or a missing
+    /// account, are returned as they are. The result is returned with whether
+    /// it's fetched from the archive node.
+    fn local_or_proxy<T: DeserializeOwned>(
+        &self, local: Result<T, ConsensusError>, method: &str,
+        params: Vec<Value>,
+    ) -> RpcResult<(T, bool)>
+    {
+        match (local, &self.archive_proxy) {
+            (Ok(result), _) => Ok((result, false)),
+            (Err(ConsensusError::StateUnavailable(_)), Some(proxy)) => {
+                proxy.call(method, params).map(|result| (result, true))
+            }
+            (Err(err), _) => Err(errors::consensus(err)),
         }
     }
 
@@ -67,10 +135,14 @@ impl RpcImpl {
             address, epoch_number
         );
 
-        self.consensus
-            .get_code(address, epoch_number.into())
-            .map(Bytes::new)
-            .map_err(|err| RpcError::invalid_params(err))
+        self.local_or_proxy(
+            self.consensus
+                .get_code(address, epoch_number.clone().into())
+                .map(Bytes::new),
+            "cfx_getCode",
+            vec![json!(RpcH160::from(address)), json!(epoch_number)],
+        )
+        .map(|(code, _)| code)
     }
 
     fn balance(
@@ -83,10 +155,97 @@ impl RpcImpl {
             address, num
         );
 
-        self.consensus
-            .get_balance(address, num.into())
-            .map(|x| x.into())
-            .map_err(|err| RpcError::invalid_params(err))
+        // only the balance at a finalized epoch is idempotent
+        if let EpochNumber::Num(epoch) = num {
+            if epoch <= self.consensus.current_era_genesis_height() {
+                // The results of the archive node are not cached, so that
+                // they are not served as local ones.
+                return self
+                    .query_cache
+                    .get_or_compute_if(
+                        format!("cfx_getBalance({:?}, {:?})", address, num),
+                        || self.balance_uncached(address, num.clone()),
+                        |(_, proxied)| !proxied,
+                    )
+                    .map(|(balance, _)| balance);
+            }
+        }
+        self.balance_uncached(address, num).map(|(balance, _)| balance)
+    }
+
+    fn balance_uncached(
+        &self, address: H160, num: EpochNumber,
+    ) -> RpcResult<(RpcU256, bool)> {
+        self.local_or_proxy(
+            self.consensus
+                .get_balance(address, num.clone().into())
+                .map(|x| x.into()),
+            "cfx_getBalance",
+            vec![json!(RpcH160::from(address)), json!(num)],
+        )
+    }
+
+    fn accounts(
+        &self, addrs: Vec<RpcH160>, num: Option<EpochNumber>,
+    ) -> RpcResult<Vec<Option<AccountState>>> {
+        let num = num.unwrap_or(EpochNumber::LatestState);
+        let addresses: Vec<H160> = addrs.into_iter().map(Into::into).collect();
+        info!(
+            "RPC Request: cfx_getAccounts addresses={:?} epoch_num={:?}",
+            addresses, num
+        );
+
+        self.local_or_proxy(
+            self.consensus
+                .get_accounts(&addresses, num.clone().into())
+                .map(|accounts| {
+                    accounts
+                        .into_iter()
+                        .map(|maybe_acc| maybe_acc.map(Into::into))
+                        .collect()
+                }),
+            "cfx_getAccounts",
+            vec![
+                json!(addresses
+                    .into_iter()
+                    .map(RpcH160::from)
+                    .collect::<Vec<_>>()),
+                json!(num),
+            ],
+        )
+        .map(|(mut accounts, proxied)| {
+            if proxied {
+                for account in accounts.iter_mut().flatten() {
+                    account.proxied = Some(true);
+                }
+            }
+            accounts
+        })
+    }
+
+    fn storage_slots(
+        &self, addr: RpcH160, keys: Vec<RpcH256>, num: Option<EpochNumber>,
+    ) -> RpcResult<Vec<RpcH256>> {
+        let num = num.unwrap_or(EpochNumber::LatestState);
+        let address: H160 = addr.into();
+        let keys: Vec<H256> = keys.into_iter().map(Into::into).collect();
+        info!(
+            "RPC Request: cfx_getStorageSlots address={:?} keys={:?} epoch_num={:?}",
+            address, keys, num
+        );
+
+        self.local_or_proxy(
+            self.consensus
+                .get_storage_slots(address, &keys, num.clone().into())
+                .map(|values| values.into_iter().map(Into::into).collect()),
+            "cfx_getStorageSlots",
+            vec![
+                json!(RpcH160::from(address)),
+                json!(keys.into_iter().map(RpcH256::from).collect::<Vec<_>>()),
+                json!(num),
+            ],
+        )
+        .map(|(values, _)| values)
     }
 
     //    fn account(
@@ -122,6 +281,29 @@ impl RpcImpl {
     //            .map_err(|err| RpcError::invalid_params(err))
     //    }
 
+    fn block_template(&self) -> RpcResult<BlockTemplate> {
+        info!("RPC Request: cfx_getBlockTemplate");
+        if !self.block_gen.pow_config.test_mode && self.sync.catch_up_mode() {
+            return Err(RpcError::invalid_request());
+        }
+        Ok(self.block_gen.get_block_template().into())
+    }
+
+    fn submit_block_solution(
+        &self, template_id: RpcU64, nonce: RpcU64,
+    ) -> RpcResult<RpcH256> {
+        let template_id: U64 = template_id.into();
+        let nonce: U64 = nonce.into();
+        info!(
+            "RPC Request: cfx_submitBlockSolution template_id={} nonce={}",
+            template_id, nonce
+        );
+        self.block_gen
+            .submit_block_solution(template_id.as_u64(), nonce.as_u64())
+            .map(Into::into)
+            .map_err(|e| RpcError::invalid_params(e))
+    }
+
     fn send_raw_transaction(&self, raw: Bytes) -> RpcResult<RpcH256> {
         info!("RPC Request: cfx_sendRawTransaction bytes={:?}", raw);
         Rlp::new(&raw.into_vec())
@@ -129,33 +311,41 @@ impl RpcImpl {
             .map_err(|err| {
                 RpcError::invalid_params(format!("Error: {:?}", err))
             })
-            .and_then(|tx| {
-                let (signed_trans, failed_trans) = self.tx_pool.insert_new_transactions(
-                    vec![tx],
-                );
-                if signed_trans.len() + failed_trans.len() > 1 {
-                    // This should never happen
-                    error!("insert_new_transactions failed, invalid length of returned result vector {}", signed_trans.len() + failed_trans.len());
-                    Ok(H256::zero().into())
-                } else if signed_trans.len() + failed_trans.len() == 0 {
-                    // For tx in transactions_pubkey_cache, we simply ignore them
-                    debug!("insert_new_transactions ignores inserted transactions");
-                    Err(RpcError::invalid_params(String::from("tx already exist")))
-                } else {
-                    if signed_trans.is_empty() {
-                        let mut tx_err = String::from("");
-                        for (_, e) in failed_trans.iter() {
-                            tx_err = e.clone();
-                            break;
-                        }
-                        Err(RpcError::invalid_params(tx_err))
-                    } else {
-                        let tx_hash = signed_trans[0].hash();
-                        self.sync.append_received_transactions(signed_trans);
-                        Ok(tx_hash.into())
-                    }
+            .and_then(|tx| self.send_transaction_with_signature(tx))
+    }
+
+    /// Insert a signed transaction into the transaction pool as a local
+    /// transaction and propagate it to peers.
+    pub fn send_transaction_with_signature(
+        &self, tx: TransactionWithSignature,
+    ) -> RpcResult<RpcH256> {
+        let (signed_trans, failed_trans) =
+            self.tx_pool.insert_new_transactions(vec![tx]);
+        if signed_trans.len() + failed_trans.len() > 1 {
+            // This should never happen
+            error!("insert_new_transactions failed, invalid length of returned result vector {}", signed_trans.len() + failed_trans.len());
+            Ok(H256::zero().into())
+        } else if signed_trans.len() + failed_trans.len() == 0 {
+            // For tx in transactions_pubkey_cache, we simply ignore them
+            debug!("insert_new_transactions ignores inserted transactions");
+            Err(errors::transaction_rejected(String::from(
+                "tx already exist",
+            )))
+        } else {
+            if signed_trans.is_empty() {
+                let mut tx_err = String::from("");
+                for (_, e) in failed_trans.iter() {
+                    tx_err = e.clone();
+                    break;
                 }
-            })
+                Err(errors::transaction_rejected(tx_err))
+            } else {
+                let tx_hash = signed_trans[0].hash();
+                self.tx_pool.mark_local(&tx_hash);
+                self.sync.append_received_transactions(signed_trans);
+                Ok(tx_hash.into())
+            }
+        }
     }
 
     fn send_usable_genesis_accounts(
@@ -203,8 +393,25 @@ impl RpcImpl {
         let transaction_info =
             self.consensus.get_transaction_info_by_hash(&hash);
         let (tx, receipt, address) = match transaction_info {
-            None => return Ok(None),
             Some(info) => info,
+            None => match &self.archive_proxy {
+                // Only the transactions whose receipts have been pruned are
+                // proxied, while the unknown ones, e.g. those not executed
+                // yet, are not.
+                Some(proxy)
+                    if self.consensus.data_man.transaction_pruned(&hash) =>
+                {
+                    let receipt: Option<RpcReceipt> = proxy.call(
+                        "cfx_getTransactionReceipt",
+                        vec![json!(RpcH256::from(hash))],
+                    )?;
+                    return Ok(receipt.map(|mut receipt| {
+                        receipt.proxied = Some(true);
+                        receipt
+                    }));
+                }
+                _ => return Ok(None),
+            },
         };
         let hash = address.block_hash.into();
         let mut receipt = RpcReceipt::new(tx, receipt, address);
@@ -366,11 +573,7 @@ impl RpcImpl {
         ))
     }
 
-    fn call(
-        &self, rpc_tx: RpcTransaction, epoch: Option<EpochNumber>,
-    ) -> RpcResult<Bytes> {
-        let epoch = epoch.unwrap_or(EpochNumber::LatestState);
-
+    fn virtual_transaction(rpc_tx: RpcTransaction) -> SignedTransaction {
         let tx = Transaction {
             nonce: rpc_tx.nonce.into(),
             gas: rpc_tx.gas.into(),
@@ -382,49 +585,147 @@ impl RpcImpl {
             },
             data: rpc_tx.data.into(),
         };
-        debug!("RPC Request: cfx_call");
         let mut signed_tx = SignedTransaction::new_unsigned(
             TransactionWithSignature::new_unsigned(tx),
         );
         signed_tx.sender = rpc_tx.from.into();
+        signed_tx
+    }
+
+    fn call(
+        &self, rpc_tx: RpcTransaction, epoch: Option<EpochNumber>,
+    ) -> RpcResult<Bytes> {
+        let epoch = epoch.unwrap_or(EpochNumber::LatestState);
+        debug!("RPC Request: cfx_call");
+        let signed_tx = Self::virtual_transaction(rpc_tx);
         trace!("call tx {:?}", signed_tx);
         self.consensus
             .call_virtual(&signed_tx, epoch.into())
             .map(|output| Bytes::new(output.0))
-            .map_err(|e| RpcError::invalid_params(e))
+            .map_err(errors::consensus)
     }
 
-    fn get_logs(&self, filter: RpcFilter) -> RpcResult<Vec<RpcLog>> {
-        info!("RPC Request: cfx_getLogs({:?})", filter);
+    fn open_state_handle(
+        &self, epoch: Option<EpochNumber>, ttl_ms: Option<RpcU64>,
+    ) -> RpcResult<RpcU64> {
+        let epoch = epoch.unwrap_or(EpochNumber::LatestState);
+        info!(
+            "RPC Request: cfx_openStateHandle epoch={:?} ttl_ms={:?}",
+            epoch, ttl_ms
+        );
+        let (epoch_number, epoch_hash) = self
+            .consensus
+            .get_stated_epoch_hash(epoch.into())
+            .map_err(errors::consensus)?;
         self.consensus
-            .logs(filter.into())
-            .map_err(|e| format!("{}", e))
+            .check_state_available(&epoch_hash)
+            .map_err(errors::consensus)?;
+        let ttl = ttl_ms.map(|ttl| {
+            let ttl: U64 = ttl.into();
+            Duration::from_millis(ttl.as_u64())
+        });
+        self.state_handles
+            .open(epoch_number, epoch_hash, ttl)
+            .map(Into::into)
             .map_err(RpcError::invalid_params)
-            .map(|logs| logs.iter().cloned().map(RpcLog::from).collect())
     }
 
-    fn estimate_gas(&self, rpc_tx: RpcTransaction) -> RpcResult<RpcU256> {
-        let tx = Transaction {
-            nonce: rpc_tx.nonce.into(),
-            gas: rpc_tx.gas.into(),
-            gas_price: rpc_tx.gas_price.into(),
-            value: rpc_tx.value.into(),
-            action: match rpc_tx.to {
-                Some(to) => Action::Call(to.into()),
-                None => Action::Create,
-            },
-            data: rpc_tx.data.into(),
+    fn close_state_handle(&self, handle: RpcU64) -> RpcResult<bool> {
+        let handle: U64 = handle.into();
+        info!("RPC Request: cfx_closeStateHandle handle={}", handle);
+        Ok(self.state_handles.close(handle.as_u64()))
+    }
+
+    fn balance_with_state_handle(
+        &self, handle: RpcU64, address: RpcH160,
+    ) -> RpcResult<RpcU256> {
+        let handle: U64 = handle.into();
+        self.state_handles
+            .read(handle.as_u64(), |epoch_hash| {
+                self.consensus
+                    .get_balance_by_epoch_hash(address.into(), epoch_hash)
+            })
+            .map(Into::into)
+            .map_err(errors::consensus)
+    }
+
+    fn code_with_state_handle(
+        &self, handle: RpcU64, address: RpcH160,
+    ) -> RpcResult<Bytes> {
+        let handle: U64 = handle.into();
+        self.state_handles
+            .read(handle.as_u64(), |epoch_hash| {
+                self.consensus
+                    .get_code_by_epoch_hash(address.into(), epoch_hash)
+            })
+            .map(Bytes::new)
+            .map_err(errors::consensus)
+    }
+
+    fn call_with_state_handle(
+        &self, handle: RpcU64, rpc_tx: RpcTransaction,
+    ) -> RpcResult<Bytes> {
+        let handle: U64 = handle.into();
+        let signed_tx = Self::virtual_transaction(rpc_tx);
+        self.state_handles
+            .read(handle.as_u64(), |epoch_hash| {
+                self.consensus
+                    .call_virtual_by_epoch_hash(&signed_tx, epoch_hash)
+            })
+            .map(|output| Bytes::new(output.0))
+            .map_err(errors::consensus)
+    }
+
+    fn get_logs(&self, filter: RpcFilter) -> RpcResult<Vec<RpcLog>> {
+        info!("RPC Request: cfx_getLogs({:?})", filter);
+        // The relative epochs, e.g. `latest_state`, advance without the
+        // pivot chain being updated, so their logs are not cached.
+        let cacheable = filter.block_hashes.is_some()
+            || (is_epoch_num(&filter.from_epoch)
+                && is_epoch_num(&filter.to_epoch));
+        let key = format!("cfx_getLogs({:?})", filter);
+        let compute = || {
+            self.consensus
+                .logs(filter.clone().into())
+                .map_err(|e| format!("{}", e))
+                .map_err(RpcError::invalid_params)
+                .map(|logs| logs.iter().cloned().map(RpcLog::from).collect())
         };
-        let mut signed_tx = SignedTransaction::new_unsigned(
-            TransactionWithSignature::new_unsigned(tx),
-        );
-        signed_tx.sender = rpc_tx.from.into();
+        if cacheable {
+            self.query_cache.get_or_compute(key, compute)
+        } else {
+            compute()
+        }
+    }
+
+    fn blocks_by_epoch(&self, num: EpochNumber) -> RpcResult<Vec<RpcH256>> {
+        info!("RPC Request: cfx_getBlocks epoch_number={:?}", num);
+        let key = format!("cfx_getBlocks({:?})", num);
+        let cacheable = match num {
+            EpochNumber::Num(_) => true,
+            _ => false,
+        };
+        let compute = || {
+            self.consensus
+                .block_hashes_by_epoch(num.into())
+                .map_err(|err| RpcError::invalid_params(err))
+                .map(|vec| vec.into_iter().map(|x| x.into()).collect())
+        };
+        if cacheable {
+            self.query_cache.get_or_compute(key, compute)
+        } else {
+            compute()
+        }
+    }
+
+    fn estimate_gas(&self, rpc_tx: RpcTransaction) -> RpcResult<RpcU256> {
+        let signed_tx = Self::virtual_transaction(rpc_tx);
         trace!("call tx {:?}", signed_tx);
-        let result = self.consensus.estimate_gas(&signed_tx);
-        result
+        self.consensus
+            .estimate_gas(&signed_tx)
             .map_err(|e| {
-                warn!("Transaction execution error {:?}", e);
-                RpcError::internal_error()
+                debug!("Failed to estimate gas {:?}", e);
+                errors::consensus(e)
             })
             .map(|x| x.into())
     }
@@ -433,12 +734,142 @@ impl RpcImpl {
         Ok(self.sync.current_sync_phase().name().into())
     }
 
+    fn sync_phase(&self) -> RpcResult<String> { self.current_sync_phase() }
+
+    fn verify_state(&self, epoch: EpochNumber) -> RpcResult<StateVerification> {
+        info!("RPC Request: admin_verifyState epoch={:?}", epoch);
+        let epoch_number = self
+            .consensus
+            .get_height_from_epoch_number(epoch.into())
+            .map_err(errors::invalid_epoch)?;
+        let (computed, committed) = self
+            .consensus
+            .verify_epoch_state(epoch_number)
+            .map_err(RpcError::invalid_params)?;
+        Ok(StateVerification {
+            epoch_number,
+            computed_state_root: computed.into(),
+            committed_state_root: committed.into(),
+            consistent: computed == committed,
+        })
+    }
+
+    fn stratum_shares(
+        &self,
+    ) -> RpcResult<BTreeMap<String, BTreeMap<String, u64>>> {
+        let stratum = self.block_gen.stratum.read();
+        let stratum = match *stratum {
+            Some(ref stratum) => stratum,
+            None => {
+                return Err(RpcError::invalid_request());
+            }
+        };
+
+        let mut ret = BTreeMap::new();
+        for (worker_id, stats) in stratum.share_stats() {
+            let mut shares = BTreeMap::new();
+            shares.insert("accepted".into(), stats.accepted);
+            shares.insert("stale".into(), stats.stale);
+            shares.insert("rejected".into(), stats.rejected);
+            ret.insert(worker_id, shares);
+        }
+        Ok(ret)
+    }
+
+    fn pow_difficulty_history(
+        &self, count: Option<usize>,
+    ) -> RpcResult<Vec<DifficultyPeriod>> {
+        let count = count.unwrap_or(MAX_DIFFICULTY_HISTORY);
+        Ok(self
+            .consensus
+            .data_man
+            .target_difficulty_manager
+            .history(count)
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    fn export_tree_graph(
+        &self, path: String, format: Option<String>, window: Option<u64>,
+    ) -> RpcResult<usize> {
+        info!(
+            "RPC Request: debug_exportTreeGraph path={} format={:?} window={:?}",
+            path, format, window
+        );
+        let format = format
+            .unwrap_or("dot".into())
+            .parse::<TreeGraphFormat>()
+            .map_err(|e| RpcError::invalid_params(e))?;
+        self.consensus
+            .export_tree_graph(
+                Path::new(&path),
+                format,
+                window.unwrap_or(DEFAULT_TREE_GRAPH_WINDOW),
+            )
+            .map_err(|e| RpcError::invalid_params(e))
+    }
+
+    fn dump_epoch_diagnostics(&self, epoch: EpochNumber) -> RpcResult<String> {
+        info!("RPC Request: debug_dumpEpochDiagnostics epoch={:?}", epoch);
+        let epoch_hash = self
+            .consensus
+            .get_hash_from_epoch_number(epoch.into())
+            .map_err(errors::invalid_epoch)?;
+        self.consensus
+            .dump_epoch_diagnostics(&epoch_hash, None)
+            .map(|path| path.to_string_lossy().into_owned())
+            .map_err(RpcError::invalid_params)
+    }
+
+    fn skipped_transactions(
+        &self, epoch: EpochNumber,
+    ) -> RpcResult<Option<SkippedTransactions>> {
+        info!("RPC Request: debug_skippedTransactions epoch={:?}", epoch);
+        let epoch_hash = self
+            .consensus
+            .get_hash_from_epoch_number(epoch.into())
+            .map_err(errors::invalid_epoch)?;
+        Ok(self
+            .consensus
+            .skipped_transaction_stats(&epoch_hash)
+            .map(SkippedTransactions::from))
+    }
+
+    fn pow_simulate_difficulties(
+        &self, timestamps: Vec<RpcU64>,
+    ) -> RpcResult<Vec<RpcU256>> {
+        let timestamps: Vec<u64> = timestamps
+            .into_iter()
+            .map(|timestamp| U64::from(timestamp).as_u64())
+            .collect();
+        simulate_difficulties(&self.block_gen.pow_config, &timestamps)
+            .map(|difficulties| {
+                difficulties.into_iter().map(Into::into).collect()
+            })
+            .map_err(RpcError::invalid_params)
+    }
+
     fn expire_block_gc(&self, timeout: u64) -> RpcResult<()> {
         self.sync.expire_block_gc(timeout);
         Ok(())
     }
 }
 
+impl Drop for RpcImpl {
+    fn drop(&mut self) {
+        self.consensus
+            .unregister_best_info_observer(self.best_info_observer);
+    }
+}
+
+fn is_epoch_num(epoch: &Option<EpochNumber>) -> bool {
+    match epoch {
+        Some(EpochNumber::Num(_)) => true,
+        _ => false,
+    }
+}
+
 #[allow(dead_code)]
 pub struct CfxHandler {
     common: Arc<CommonImpl>,
@@ -458,8 +889,8 @@ impl Cfx for CfxHandler {
             fn block_by_epoch_number(&self, epoch_num: EpochNumber, include_txs: bool) -> RpcResult<RpcBlock>;
             fn block_by_hash_with_pivot_assumption(&self, block_hash: RpcH256, pivot_hash: RpcH256, epoch_number: RpcU64) -> RpcResult<RpcBlock>;
             fn block_by_hash(&self, hash: RpcH256, include_txs: bool) -> RpcResult<Option<RpcBlock>>;
-            fn blocks_by_epoch(&self, num: EpochNumber) -> RpcResult<Vec<RpcH256>>;
             fn epoch_number(&self, epoch_num: Option<EpochNumber>) -> RpcResult<RpcU256>;
+            fn decode_raw_transaction(&self, raw: Bytes) -> RpcResult<DecodedTransaction>;
             fn gas_price(&self) -> RpcResult<RpcU256>;
             fn transaction_count(&self, address: RpcH160, num: Option<EpochNumber>) -> RpcResult<RpcU256>;
         }
@@ -467,10 +898,20 @@ impl Cfx for CfxHandler {
         target self.rpc_impl {
             fn code(&self, addr: RpcH160, epoch_number: Option<EpochNumber>) -> RpcResult<Bytes>;
             fn balance(&self, address: RpcH160, num: Option<EpochNumber>) -> RpcResult<RpcU256>;
+            fn accounts(&self, addrs: Vec<RpcH160>, num: Option<EpochNumber>) -> RpcResult<Vec<Option<AccountState>>>;
+            fn storage_slots(&self, addr: RpcH160, keys: Vec<RpcH256>, num: Option<EpochNumber>) -> RpcResult<Vec<RpcH256>>;
+            fn blocks_by_epoch(&self, num: EpochNumber) -> RpcResult<Vec<RpcH256>>;
             fn call(&self, rpc_tx: RpcTransaction, epoch: Option<EpochNumber>) -> RpcResult<Bytes>;
             fn estimate_gas(&self, rpc_tx: RpcTransaction) -> RpcResult<RpcU256>;
             fn get_logs(&self, filter: RpcFilter) -> RpcResult<Vec<RpcLog>>;
             fn send_raw_transaction(&self, raw: Bytes) -> RpcResult<RpcH256>;
+            fn block_template(&self) -> RpcResult<BlockTemplate>;
+            fn submit_block_solution(&self, template_id: RpcU64, nonce: RpcU64) -> RpcResult<RpcH256>;
+            fn open_state_handle(&self, epoch: Option<EpochNumber>, ttl_ms: Option<RpcU64>) -> RpcResult<RpcU64>;
+            fn close_state_handle(&self, handle: RpcU64) -> RpcResult<bool>;
+            fn balance_with_state_handle(&self, handle: RpcU64, address: RpcH160) -> RpcResult<RpcU256>;
+            fn code_with_state_handle(&self, handle: RpcU64, address: RpcH160) -> RpcResult<Bytes>;
+            fn call_with_state_handle(&self, handle: RpcU64, rpc_tx: RpcTransaction) -> RpcResult<Bytes>;
             fn send_usable_genesis_accounts(& self, account_start_index: usize) -> RpcResult<Bytes>;
             fn transaction_by_hash(&self, hash: RpcH256) -> RpcResult<Option<RpcTransaction>>;
             fn transaction_receipt(&self, tx_hash: RpcH256) -> RpcResult<Option<RpcReceipt>>;
@@ -541,13 +982,68 @@ impl DebugRpc for DebugRpcImpl {
             fn net_sessions(&self, node_id: Option<NodeId>) -> RpcResult<Vec<SessionDetails>>;
             fn net_throttling(&self) -> RpcResult<throttling::Service>;
             fn tx_inspect(&self, hash: RpcH256) -> RpcResult<BTreeMap<String, String>>;
+            fn txpool_account_queues(&self, address: RpcH160) -> RpcResult<BTreeMap<String, Vec<RpcTransaction>>>;
+            fn misbehavior_report(&self, era_genesis_height: Option<u64>) -> RpcResult<Vec<MisbehaviorReport>>;
+            fn chain_health(&self) -> RpcResult<ChainHealth>;
+            fn consensus_lock_contention(&self, limit: Option<usize>) -> RpcResult<Vec<LockContention>>;
+            fn excluded_terminals(&self) -> RpcResult<Vec<ExcludedTerminal>>;
+            fn txpool_local_transactions(&self) -> RpcResult<Vec<RpcTransaction>>;
+            fn txpool_mark_local(&self, hash: RpcH256) -> RpcResult<bool>;
             fn txpool_content(&self) -> RpcResult<BTreeMap<String, BTreeMap<String, BTreeMap<usize, Vec<RpcTransaction>>>>>;
             fn txpool_inspect(&self) -> RpcResult<BTreeMap<String, BTreeMap<String, BTreeMap<usize, Vec<String>>>>>;
             fn txpool_status(&self) -> RpcResult<BTreeMap<String, usize>>;
+            fn txpool_memory_stats(&self) -> RpcResult<BTreeMap<String, usize>>;
+            fn state_snapshot(&self) -> RpcResult<serde_json::Value>;
+            fn set_log_level(&self, module: String, level: String) -> RpcResult<()>;
+            fn reset_log_level(&self, module: Option<String>) -> RpcResult<()>;
+            fn log_levels(&self) -> RpcResult<BTreeMap<String, String>>;
         }
 
         target self.rpc_impl {
             fn current_sync_phase(&self) -> RpcResult<String>;
+            fn stratum_shares(&self) -> RpcResult<BTreeMap<String, BTreeMap<String, u64>>>;
+            fn pow_difficulty_history(&self, count: Option<usize>) -> RpcResult<Vec<DifficultyPeriod>>;
+            fn pow_simulate_difficulties(&self, timestamps: Vec<RpcU64>) -> RpcResult<Vec<RpcU256>>;
+            fn export_tree_graph(&self, path: String, format: Option<String>, window: Option<u64>) -> RpcResult<usize>;
+            fn dump_epoch_diagnostics(&self, epoch: EpochNumber) -> RpcResult<String>;
+            fn skipped_transactions(&self, epoch: EpochNumber) -> RpcResult<Option<SkippedTransactions>>;
+        }
+    }
+}
+
+pub struct AdminRpcImpl {
+    common: Arc<CommonImpl>,
+    rpc_impl: Arc<RpcImpl>,
+}
+
+impl AdminRpcImpl {
+    pub fn new(common: Arc<CommonImpl>, rpc_impl: Arc<RpcImpl>) -> Self {
+        AdminRpcImpl { common, rpc_impl }
+    }
+}
+
+impl AdminRpc for AdminRpcImpl {
+    delegate! {
+        target self.common {
+            fn peers(&self) -> RpcResult<Vec<Peer>>;
+            fn add_trusted_peer(&self, node_id: NodeId, address: SocketAddr) -> RpcResult<()>;
+            fn remove_trusted_peer(&self, node_id: NodeId) -> RpcResult<()>;
+            fn ban_node(&self, node_id: NodeId) -> RpcResult<bool>;
+            fn ban_ip(&self, ip: IpAddr) -> RpcResult<usize>;
+            fn unban_ip(&self, ip: IpAddr) -> RpcResult<bool>;
+            fn banned_ips(&self) -> RpcResult<Vec<IpAddr>>;
+            fn add_ban(&self, target: BanTarget, reason: Option<String>, duration: Option<u64>) -> RpcResult<usize>;
+            fn remove_ban(&self, target: BanTarget) -> RpcResult<bool>;
+            fn list_bans(&self) -> RpcResult<Vec<Ban>>;
+            fn gc_caches(&self) -> RpcResult<usize>;
+            fn reload_config(&self) -> RpcResult<ReloadReport>;
+            fn txpool_admission(&self) -> RpcResult<TxPoolAdmission>;
+            fn set_txpool_admission(&self, admission: TxPoolAdmission) -> RpcResult<()>;
+        }
+
+        target self.rpc_impl {
+            fn sync_phase(&self) -> RpcResult<String>;
+            fn verify_state(&self, epoch: EpochNumber) -> RpcResult<StateVerification>;
         }
     }
 }
@@ -4,11 +4,14 @@
 
 use std::{
     collections::{BTreeMap, HashSet},
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
     sync::Arc,
+    time::Duration,
 };
 
 use jsonrpc_core::{Error as RpcError, Result as RpcResult};
+use log::LevelFilter;
 use parking_lot::{Condvar, Mutex};
 
 use cfx_types::H256;
@@ -22,13 +25,22 @@ use network::{
     get_high_priority_packets,
     node_table::{Node, NodeEndpoint, NodeEntry, NodeId},
     throttling::{self, THROTTLING_SERVICE},
-    NetworkService, SessionDetails, UpdateNodeOperation,
+    Ban, BanTarget, NetworkService, SessionDetails, UpdateNodeOperation,
 };
 
-use crate::rpc::types::{
-    Block as RpcBlock, EpochNumber, Receipt as RpcReceipt, Status as RpcStatus,
-    Transaction as RpcTransaction, H160 as RpcH160, H256 as RpcH256,
-    U256 as RpcU256, U64 as RpcU64,
+use crate::{
+    config_reload::{ConfigReloader, ReloadReport},
+    log_control,
+    rpc::{
+        helpers::errors,
+        types::{
+            Block as RpcBlock, Bytes, ChainHealth, DecodedTransaction,
+            EpochNumber, ExcludedTerminal, LockContention, MisbehaviorReport,
+            Peer, Receipt as RpcReceipt, Status as RpcStatus,
+            Transaction as RpcTransaction, TxPoolAdmission, H160 as RpcH160,
+            H256 as RpcH256, U256 as RpcU256, U64 as RpcU64,
+        },
+    },
 };
 
 fn grouped_txs<T, F>(
@@ -59,6 +71,7 @@ pub struct RpcImpl {
     network: Arc<NetworkService>,
     tx_pool: SharedTransactionPool,
     state_exposer: SharedStateExposer,
+    config_reloader: Arc<ConfigReloader>,
 }
 
 impl RpcImpl {
@@ -66,14 +79,15 @@ impl RpcImpl {
         exit: Arc<(Mutex<bool>, Condvar)>, consensus: SharedConsensusGraph,
         network: Arc<NetworkService>, tx_pool: SharedTransactionPool,
         state_exposer: SharedStateExposer,
-    ) -> Self
-    {
+        config_reloader: Arc<ConfigReloader>,
+    ) -> Self {
         RpcImpl {
             exit,
             consensus,
             network,
             tx_pool,
             state_exposer,
+            config_reloader,
         }
     }
 }
@@ -105,7 +119,7 @@ impl RpcImpl {
             .get_height_from_epoch_number(epoch_num.into())
         {
             Ok(height) => Ok(height.into()),
-            Err(e) => Err(RpcError::invalid_params(e)),
+            Err(e) => Err(errors::invalid_epoch(e)),
         }
     }
 
@@ -117,10 +131,10 @@ impl RpcImpl {
         let epoch_height = self
             .consensus
             .get_height_from_epoch_number(epoch_num.into())
-            .map_err(|err| RpcError::invalid_params(err))?;
+            .map_err(errors::invalid_epoch)?;
         inner
             .get_hash_from_epoch_number(epoch_height)
-            .map_err(|err| RpcError::invalid_params(err))
+            .map_err(errors::invalid_epoch)
             .and_then(|hash| {
                 let block = self
                     .consensus
@@ -206,9 +220,24 @@ impl RpcImpl {
 
         self.consensus
             .transaction_count(address.into(), num.into())
-            .map_err(|err| RpcError::invalid_params(err))
+            .map_err(errors::consensus)
             .map(|x| x.into())
     }
+
+    pub fn decode_raw_transaction(
+        &self, raw: Bytes,
+    ) -> RpcResult<DecodedTransaction> {
+        info!("RPC Request: cfx_decodeRawTransaction bytes={:?}", raw);
+        let raw = raw.into_vec();
+        let tx = SignedTransaction::from_raw(&raw)
+            .map_err(|e| RpcError::invalid_params(format!("{}", e)))?;
+        Ok(DecodedTransaction {
+            chain_id: tx.chain_id().map(Into::into),
+            size: raw.len(),
+            rejection: self.tx_pool.check_transaction(&tx.transaction).err(),
+            transaction: RpcTransaction::from_signed(&tx, None),
+        })
+    }
 }
 
 // Test RPC implementation
@@ -499,9 +528,89 @@ impl RpcImpl {
         ret.insert("ready".into(), grouped_txs(ready_txs, converter));
         ret.insert("deferred".into(), grouped_txs(deferred_txs, converter));
 
+        let (pending_txs, queued_txs) = self.tx_pool.pending_and_queued();
+        ret.insert("pending".into(), grouped_txs(pending_txs, converter));
+        ret.insert("queued".into(), grouped_txs(queued_txs, converter));
+
         Ok(ret)
     }
 
+    pub fn txpool_account_queues(
+        &self, address: RpcH160,
+    ) -> RpcResult<BTreeMap<String, Vec<RpcTransaction>>> {
+        let (ready_txs, future_txs) =
+            self.tx_pool.account_pending_queues(&address.into());
+        let converter =
+            |txs: Vec<Arc<SignedTransaction>>| -> Vec<RpcTransaction> {
+                txs.iter()
+                    .map(|tx| RpcTransaction::from_signed(tx, None))
+                    .collect()
+            };
+
+        let mut ret = BTreeMap::new();
+        ret.insert("ready".into(), converter(ready_txs));
+        ret.insert("future".into(), converter(future_txs));
+        Ok(ret)
+    }
+
+    pub fn txpool_local_transactions(&self) -> RpcResult<Vec<RpcTransaction>> {
+        Ok(self
+            .tx_pool
+            .local_transactions()
+            .iter()
+            .map(|tx| RpcTransaction::from_signed(tx, None))
+            .collect())
+    }
+
+    pub fn txpool_mark_local(&self, hash: RpcH256) -> RpcResult<bool> {
+        Ok(self.tx_pool.mark_local(&hash.into()))
+    }
+
+    pub fn misbehavior_report(
+        &self, era_genesis_height: Option<u64>,
+    ) -> RpcResult<Vec<MisbehaviorReport>> {
+        info!(
+            "RPC Request: debug_misbehaviorReport era_genesis_height={:?}",
+            era_genesis_height
+        );
+        Ok(self
+            .consensus
+            .misbehavior_report(era_genesis_height)
+            .into_iter()
+            .map(|(era, author, misbehavior)| {
+                MisbehaviorReport::new(era, author, misbehavior)
+            })
+            .collect())
+    }
+
+    pub fn chain_health(&self) -> RpcResult<ChainHealth> {
+        info!("RPC Request: debug_chainHealth");
+        Ok(self.consensus.chain_health().into())
+    }
+
+    pub fn consensus_lock_contention(
+        &self, limit: Option<usize>,
+    ) -> RpcResult<Vec<LockContention>> {
+        Ok(self
+            .consensus
+            .inner_lock_contention(limit.unwrap_or(usize::max_value()))
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    pub fn excluded_terminals(&self) -> RpcResult<Vec<ExcludedTerminal>> {
+        info!("RPC Request: debug_excludedTerminals");
+        Ok(self
+            .consensus
+            .best_info()
+            .excluded_terminals
+            .iter()
+            .cloned()
+            .map(Into::into)
+            .collect())
+    }
+
     pub fn txpool_inspect(
         &self,
     ) -> RpcResult<
@@ -527,6 +636,10 @@ impl RpcImpl {
         ret.insert("ready".into(), grouped_txs(ready_txs, converter));
         ret.insert("deferred".into(), grouped_txs(deferred_txs, converter));
 
+        let (pending_txs, queued_txs) = self.tx_pool.pending_and_queued();
+        ret.insert("pending".into(), grouped_txs(pending_txs, converter));
+        ret.insert("queued".into(), grouped_txs(queued_txs, converter));
+
         Ok(ret)
     }
 
@@ -540,6 +653,173 @@ impl RpcImpl {
         ret.insert("received".into(), received_len);
         ret.insert("unexecuted".into(), unexecuted_len);
 
+        let (pending_len, queued_len) = self.tx_pool.status();
+        ret.insert("pending".into(), pending_len);
+        ret.insert("queued".into(), queued_len);
+
+        Ok(ret)
+    }
+
+    pub fn txpool_memory_stats(&self) -> RpcResult<BTreeMap<String, usize>> {
+        let (used, budget, evictions) = self.tx_pool.memory_stats();
+
+        let mut ret: BTreeMap<String, usize> = BTreeMap::new();
+        ret.insert("memoryUsed".into(), used);
+        ret.insert("memoryBudget".into(), budget);
+        ret.insert("evictedByGc".into(), evictions.garbage_collected);
+        ret.insert("evictedByMemory".into(), evictions.over_memory_budget);
+        ret.insert("replaced".into(), evictions.replaced);
+
         Ok(ret)
     }
+
+    pub fn set_log_level(
+        &self, module: String, level: String,
+    ) -> RpcResult<()> {
+        info!(
+            "RPC Request: debug_setLogLevel module={} level={}",
+            module, level
+        );
+        let level = LevelFilter::from_str(&level)
+            .map_err(|_| RpcError::invalid_params("Invalid log level"))?;
+        log_control::set_module_level(&module, level)
+            .map_err(RpcError::invalid_params)
+    }
+
+    pub fn reset_log_level(&self, module: Option<String>) -> RpcResult<()> {
+        info!("RPC Request: debug_resetLogLevel module={:?}", module);
+        log_control::reset_module_level(module.as_ref().map(|m| m.as_str()))
+            .map_err(RpcError::invalid_params)
+    }
+
+    pub fn log_levels(&self) -> RpcResult<BTreeMap<String, String>> {
+        log_control::module_levels().map_err(RpcError::invalid_params)
+    }
+
+    pub fn state_snapshot(&self) -> RpcResult<serde_json::Value> {
+        info!("RPC Request: debug_stateSnapshot");
+        serde_json::from_str(&self.state_exposer.snapshot())
+            .map_err(|_| RpcError::internal_error())
+    }
+}
+
+// Admin RPC implementation
+impl RpcImpl {
+    pub fn peers(&self) -> RpcResult<Vec<Peer>> {
+        let sessions = self.network.get_detailed_sessions(None);
+        Ok(sessions
+            .unwrap_or_default()
+            .into_iter()
+            .map(|session| Peer {
+                trusted: session
+                    .node_id
+                    .and_then(|id| self.network.get_node(&id))
+                    .map_or(false, |(trusted, _)| trusted),
+                session,
+            })
+            .collect())
+    }
+
+    pub fn add_trusted_peer(
+        &self, node_id: NodeId, address: SocketAddr,
+    ) -> RpcResult<()> {
+        info!(
+            "RPC Request: admin_addTrustedPeer node_id={:?} address={:?}",
+            node_id, address
+        );
+        self.add_peer(node_id, address)
+    }
+
+    pub fn remove_trusted_peer(&self, node_id: NodeId) -> RpcResult<()> {
+        info!("RPC Request: admin_removeTrustedPeer node_id={:?}", node_id);
+        self.network
+            .drop_node(node_id)
+            .map_err(|_| RpcError::internal_error())
+    }
+
+    pub fn ban_node(&self, node_id: NodeId) -> RpcResult<bool> {
+        info!("RPC Request: admin_banNode node_id={:?}", node_id);
+        self.network
+            .ban_node(&node_id)
+            .map_err(|_| RpcError::internal_error())
+    }
+
+    pub fn ban_ip(&self, ip: IpAddr) -> RpcResult<usize> {
+        info!("RPC Request: admin_banIp ip={}", ip);
+        self.network
+            .ban(BanTarget::Ip(ip), "admin_banIp".into(), None)
+            .map_err(|_| RpcError::internal_error())
+    }
+
+    pub fn unban_ip(&self, ip: IpAddr) -> RpcResult<bool> {
+        info!("RPC Request: admin_unbanIp ip={}", ip);
+        self.network
+            .unban(&BanTarget::Ip(ip))
+            .map_err(|_| RpcError::internal_error())
+    }
+
+    pub fn banned_ips(&self) -> RpcResult<Vec<IpAddr>> {
+        Ok(self
+            .network
+            .bans()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|ban| match ban.target {
+                BanTarget::Ip(ip) => Some(ip),
+                BanTarget::NodeId(_) => None,
+            })
+            .collect())
+    }
+
+    pub fn add_ban(
+        &self, target: BanTarget, reason: Option<String>, duration: Option<u64>,
+    ) -> RpcResult<usize> {
+        info!(
+            "RPC Request: admin_addBan target={:?} reason={:?} duration={:?}",
+            target, reason, duration
+        );
+        self.network
+            .ban(
+                target,
+                reason.unwrap_or_default(),
+                duration.map(Duration::from_secs),
+            )
+            .map_err(|_| RpcError::internal_error())
+    }
+
+    pub fn remove_ban(&self, target: BanTarget) -> RpcResult<bool> {
+        info!("RPC Request: admin_removeBan target={:?}", target);
+        self.network
+            .unban(&target)
+            .map_err(|_| RpcError::internal_error())
+    }
+
+    pub fn list_bans(&self) -> RpcResult<Vec<Ban>> {
+        Ok(self.network.bans().unwrap_or_default())
+    }
+
+    pub fn gc_caches(&self) -> RpcResult<usize> {
+        info!("RPC Request: admin_gcCaches");
+        self.consensus.data_man.cache_gc();
+        Ok(self.consensus.data_man.cache_size().total())
+    }
+
+    pub fn reload_config(&self) -> RpcResult<ReloadReport> {
+        info!("RPC Request: admin_reloadConfig");
+        self.config_reloader
+            .reload()
+            .map_err(RpcError::invalid_params)
+    }
+
+    pub fn txpool_admission(&self) -> RpcResult<TxPoolAdmission> {
+        Ok(self.tx_pool.admission_policy().into())
+    }
+
+    pub fn set_txpool_admission(
+        &self, admission: TxPoolAdmission,
+    ) -> RpcResult<()> {
+        info!("RPC Request: admin_setTxPoolAdmission {:?}", admission);
+        self.tx_pool.set_admission_policy(admission.into());
+        Ok(())
+    }
 }
@@ -4,7 +4,11 @@
 
 use delegate::delegate;
 use jsonrpc_core::{Error as RpcError, Result as RpcResult};
-use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
 use cfx_types::{H160, H256};
 use cfxcore::{LightQueryService, PeerInfo};
@@ -12,16 +16,22 @@ use primitives::TransactionWithSignature;
 
 use network::{
     node_table::{Node, NodeId},
-    throttling, SessionDetails, UpdateNodeOperation,
+    throttling, Ban, BanTarget, SessionDetails, UpdateNodeOperation,
 };
 
-use crate::rpc::{
-    traits::{cfx::Cfx, debug::DebugRpc, test::TestRpc},
-    types::{
-        BlameInfo, Block as RpcBlock, Bytes, EpochNumber, Filter as RpcFilter,
-        Log as RpcLog, Receipt as RpcReceipt, Status as RpcStatus,
-        Transaction as RpcTransaction, H160 as RpcH160, H256 as RpcH256,
-        U256 as RpcU256, U64 as RpcU64,
+use crate::{
+    config_reload::ReloadReport,
+    rpc::{
+        traits::{admin::AdminRpc, cfx::Cfx, debug::DebugRpc, test::TestRpc},
+        types::{
+            AccountState, BlameInfo, Block as RpcBlock, BlockTemplate, Bytes,
+            ChainHealth, DecodedTransaction, DifficultyPeriod, EpochNumber,
+            ExcludedTerminal, Filter as RpcFilter, LockContention,
+            Log as RpcLog, MisbehaviorReport, Peer, Receipt as RpcReceipt,
+            SkippedTransactions, StateVerification, Status as RpcStatus,
+            Transaction as RpcTransaction, TxPoolAdmission, H160 as RpcH160,
+            H256 as RpcH256, U256 as RpcU256, U64 as RpcU64,
+        },
     },
 };
 
@@ -135,12 +145,29 @@ impl RpcImpl {
 
         // TODO(thegaram): try to retrieve from local tx pool or cache first
 
-        let tx = self
+        // retrieve the executed tx along with its receipt, which are
+        // verified against the receipts root provided by the witnesses; the
+        // tx might not have been packed yet, so it is retrieved without
+        // receipt at the same time
+        let (tx, maybe_info) = self
             .light
-            .get_tx(hash.into())
+            .get_tx_with_info(hash.into())
             .map_err(RpcError::invalid_params)?;
 
-        Ok(Some(RpcTransaction::from_signed(&tx, None)))
+        let receipt = maybe_info.map(|info| {
+            let (tx, receipt, address, maybe_epoch, maybe_state_root) = info;
+
+            let mut receipt = RpcReceipt::new(tx, receipt, address);
+            receipt.set_epoch_number(maybe_epoch);
+
+            if let Some(state_root) = maybe_state_root {
+                receipt.set_state_root(state_root.into());
+            }
+
+            receipt
+        });
+
+        Ok(Some(RpcTransaction::from_signed(&tx, receipt)))
     }
 
     fn transaction_receipt(
@@ -165,19 +192,6 @@ impl RpcImpl {
     }
 }
 
-// macro for reducing boilerplate for unsupported methods
-macro_rules! not_supported {
-    () => {};
-    ( fn $fn:ident ( &self $(, $name:ident : $type:ty)* ) $( -> $ret:ty )? ; $($tail:tt)* ) => {
-        #[allow(unused_variables)]
-        fn $fn ( &self $(, $name : $type)* ) $( -> $ret )? {
-            Err(RpcError::method_not_found())
-        }
-
-        not_supported!($($tail)*);
-    };
-}
-
 #[allow(dead_code)]
 pub struct CfxHandler {
     common: Arc<CommonImpl>,
@@ -199,6 +213,7 @@ impl Cfx for CfxHandler {
             fn block_by_hash(&self, hash: RpcH256, include_txs: bool) -> RpcResult<Option<RpcBlock>>;
             fn blocks_by_epoch(&self, num: EpochNumber) -> RpcResult<Vec<RpcH256>>;
             fn epoch_number(&self, epoch_num: Option<EpochNumber>) -> RpcResult<RpcU256>;
+            fn decode_raw_transaction(&self, raw: Bytes) -> RpcResult<DecodedTransaction>;
             fn gas_price(&self) -> RpcResult<RpcU256>;
             fn transaction_count(&self, address: RpcH160, num: Option<EpochNumber>) -> RpcResult<RpcU256>;
         }
@@ -215,6 +230,18 @@ impl Cfx for CfxHandler {
             fn transaction_receipt(&self, tx_hash: RpcH256) -> RpcResult<Option<RpcReceipt>>;
         }
     }
+
+    not_supported! {
+        fn accounts(&self, addrs: Vec<RpcH160>, num: Option<EpochNumber>) -> RpcResult<Vec<Option<AccountState>>>;
+        fn storage_slots(&self, addr: RpcH160, keys: Vec<RpcH256>, num: Option<EpochNumber>) -> RpcResult<Vec<RpcH256>>;
+        fn block_template(&self) -> RpcResult<BlockTemplate>;
+        fn submit_block_solution(&self, template_id: RpcU64, nonce: RpcU64) -> RpcResult<RpcH256>;
+        fn open_state_handle(&self, epoch: Option<EpochNumber>, ttl_ms: Option<RpcU64>) -> RpcResult<RpcU64>;
+        fn close_state_handle(&self, handle: RpcU64) -> RpcResult<bool>;
+        fn balance_with_state_handle(&self, handle: RpcU64, address: RpcH160) -> RpcResult<RpcU256>;
+        fn code_with_state_handle(&self, handle: RpcU64, address: RpcH160) -> RpcResult<Bytes>;
+        fn call_with_state_handle(&self, handle: RpcU64, rpc_tx: RpcTransaction) -> RpcResult<Bytes>;
+    }
 }
 
 #[allow(dead_code)]
@@ -281,13 +308,65 @@ impl DebugRpc for DebugRpcImpl {
             fn net_sessions(&self, node_id: Option<NodeId>) -> RpcResult<Vec<SessionDetails>>;
             fn net_throttling(&self) -> RpcResult<throttling::Service>;
             fn tx_inspect(&self, hash: RpcH256) -> RpcResult<BTreeMap<String, String>>;
+            fn txpool_account_queues(&self, address: RpcH160) -> RpcResult<BTreeMap<String, Vec<RpcTransaction>>>;
+            fn misbehavior_report(&self, era_genesis_height: Option<u64>) -> RpcResult<Vec<MisbehaviorReport>>;
+            fn chain_health(&self) -> RpcResult<ChainHealth>;
+            fn consensus_lock_contention(&self, limit: Option<usize>) -> RpcResult<Vec<LockContention>>;
+            fn excluded_terminals(&self) -> RpcResult<Vec<ExcludedTerminal>>;
+            fn txpool_local_transactions(&self) -> RpcResult<Vec<RpcTransaction>>;
+            fn txpool_mark_local(&self, hash: RpcH256) -> RpcResult<bool>;
             fn txpool_content(&self) -> RpcResult<BTreeMap<String, BTreeMap<String, BTreeMap<usize, Vec<RpcTransaction>>>>>;
             fn txpool_inspect(&self) -> RpcResult<BTreeMap<String, BTreeMap<String, BTreeMap<usize, Vec<String>>>>>;
             fn txpool_status(&self) -> RpcResult<BTreeMap<String, usize>>;
+            fn txpool_memory_stats(&self) -> RpcResult<BTreeMap<String, usize>>;
+            fn state_snapshot(&self) -> RpcResult<serde_json::Value>;
+            fn set_log_level(&self, module: String, level: String) -> RpcResult<()>;
+            fn reset_log_level(&self, module: Option<String>) -> RpcResult<()>;
+            fn log_levels(&self) -> RpcResult<BTreeMap<String, String>>;
         }
     }
 
     not_supported! {
         fn current_sync_phase(&self) -> RpcResult<String>;
+        fn stratum_shares(&self) -> RpcResult<BTreeMap<String, BTreeMap<String, u64>>>;
+        fn pow_difficulty_history(&self, count: Option<usize>) -> RpcResult<Vec<DifficultyPeriod>>;
+        fn pow_simulate_difficulties(&self, timestamps: Vec<RpcU64>) -> RpcResult<Vec<RpcU256>>;
+        fn export_tree_graph(&self, path: String, format: Option<String>, window: Option<u64>) -> RpcResult<usize>;
+        fn dump_epoch_diagnostics(&self, epoch: EpochNumber) -> RpcResult<String>;
+        fn skipped_transactions(&self, epoch: EpochNumber) -> RpcResult<Option<SkippedTransactions>>;
+    }
+}
+
+pub struct AdminRpcImpl {
+    common: Arc<CommonImpl>,
+}
+
+impl AdminRpcImpl {
+    pub fn new(common: Arc<CommonImpl>) -> Self { AdminRpcImpl { common } }
+}
+
+impl AdminRpc for AdminRpcImpl {
+    delegate! {
+        target self.common {
+            fn peers(&self) -> RpcResult<Vec<Peer>>;
+            fn add_trusted_peer(&self, node_id: NodeId, address: SocketAddr) -> RpcResult<()>;
+            fn remove_trusted_peer(&self, node_id: NodeId) -> RpcResult<()>;
+            fn ban_node(&self, node_id: NodeId) -> RpcResult<bool>;
+            fn ban_ip(&self, ip: IpAddr) -> RpcResult<usize>;
+            fn unban_ip(&self, ip: IpAddr) -> RpcResult<bool>;
+            fn banned_ips(&self) -> RpcResult<Vec<IpAddr>>;
+            fn add_ban(&self, target: BanTarget, reason: Option<String>, duration: Option<u64>) -> RpcResult<usize>;
+            fn remove_ban(&self, target: BanTarget) -> RpcResult<bool>;
+            fn list_bans(&self) -> RpcResult<Vec<Ban>>;
+            fn gc_caches(&self) -> RpcResult<usize>;
+            fn reload_config(&self) -> RpcResult<ReloadReport>;
+            fn txpool_admission(&self) -> RpcResult<TxPoolAdmission>;
+            fn set_txpool_admission(&self, admission: TxPoolAdmission) -> RpcResult<()>;
+        }
+    }
+
+    not_supported! {
+        fn sync_phase(&self) -> RpcResult<String>;
+        fn verify_state(&self, epoch: EpochNumber) -> RpcResult<StateVerification>;
     }
 }
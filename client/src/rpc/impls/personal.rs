@@ -0,0 +1,267 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::rpc::{
+    helpers::errors,
+    impls::cfx::RpcImpl,
+    traits::personal::PersonalRpc,
+    types::{SendTxRequest, H160 as RpcH160, H256 as RpcH256},
+};
+use cfx_types::{Address, H256, U256};
+use cfxcore::SharedTransactionPool;
+use ethcore_accounts::{AccountProvider, AccountProviderSettings};
+use ethstore::{accounts_dir::RootDiskDirectory, EthStore};
+use jsonrpc_core::Result as RpcResult;
+use keylib::{Password, Secret};
+use parking_lot::Mutex;
+use primitives::{Action, Transaction, TransactionWithSignature};
+use std::{cmp, sync::Arc, time::Duration};
+
+/// The unlock duration of `personal_unlockAccount` if not given.
+const DEFAULT_UNLOCK_DURATION_S: u64 = 300;
+/// The gas of a sent transaction if not given, which is enough for a
+/// transfer.
+const DEFAULT_GAS: u64 = 21_000;
+
+/// Open the accounts in the encrypted (V3) keystore files under `dir`, which
+/// is created if it doesn't exist.
+pub fn open_keystore(dir: &str) -> Result<AccountProvider, String> {
+    let key_dir = RootDiskDirectory::create(dir)
+        .map_err(|e| format!("Failed to open keystore {}: {}", dir, e))?;
+    let store = EthStore::open(Box::new(key_dir))
+        .map_err(|e| format!("Failed to load keystore {}: {}", dir, e))?;
+    Ok(AccountProvider::new(
+        Box::new(store),
+        AccountProviderSettings::default(),
+    ))
+}
+
+/// The node services used to price and send the transactions signed by the
+/// local accounts.
+pub trait TransactionSender: Send + Sync {
+    fn gas_price(&self) -> U256;
+
+    fn send_transaction(
+        &self, tx: TransactionWithSignature,
+    ) -> RpcResult<RpcH256>;
+}
+
+impl TransactionSender for RpcImpl {
+    fn gas_price(&self) -> U256 {
+        self.consensus.gas_price().unwrap_or_default()
+    }
+
+    fn send_transaction(
+        &self, tx: TransactionWithSignature,
+    ) -> RpcResult<RpcH256> {
+        self.send_transaction_with_signature(tx)
+    }
+}
+
+pub struct PersonalImpl {
+    accounts: AccountProvider,
+    sender: Arc<dyn TransactionSender>,
+    tx_pool: SharedTransactionPool,
+    chain_id: u64,
+    max_unlock_duration: Duration,
+    /// Serializes the sent transactions, so that each of them takes the
+    /// nonce following the previous one of the same account.
+    send_lock: Mutex<()>,
+}
+
+impl PersonalImpl {
+    pub fn new(
+        accounts: AccountProvider, sender: Arc<dyn TransactionSender>,
+        tx_pool: SharedTransactionPool, chain_id: u64,
+        max_unlock_duration: Duration,
+    ) -> Self
+    {
+        PersonalImpl {
+            accounts,
+            sender,
+            tx_pool,
+            chain_id,
+            max_unlock_duration,
+            send_lock: Mutex::new(()),
+        }
+    }
+
+    /// Fill the omitted fields of `request`, sign it with the sender's key and
+    /// send it. The account must be unlocked if `password` is not given.
+    fn sign_and_send(
+        &self, request: SendTxRequest, password: Option<Password>,
+    ) -> RpcResult<RpcH256> {
+        let _guard = self.send_lock.lock();
+        let from: Address = request.from.into();
+        let tx = Transaction {
+            nonce: match request.nonce {
+                Some(nonce) => nonce.into(),
+                None => self.tx_pool.get_next_nonce(&from),
+            },
+            gas_price: match request.gas_price {
+                Some(gas_price) => gas_price.into(),
+                None => self.sender.gas_price(),
+            },
+            gas: request.gas.map_or(DEFAULT_GAS.into(), Into::into),
+            value: request.value.map_or(U256::zero(), Into::into),
+            action: match request.to {
+                Some(to) => Action::Call(to.into()),
+                None => Action::Create,
+            },
+            data: request.data.map_or_else(Vec::new, Into::into),
+        };
+        let signature = self
+            .accounts
+            .sign(from, password, tx.signature_hash(Some(self.chain_id)))
+            .map_err(errors::signing)?;
+        self.sender
+            .send_transaction(tx.with_signature(signature, Some(self.chain_id)))
+    }
+}
+
+impl PersonalRpc for PersonalImpl {
+    fn list_accounts(&self) -> RpcResult<Vec<RpcH160>> {
+        info!("RPC Request: personal_listAccounts()");
+        self.accounts
+            .accounts()
+            .map(|accounts| accounts.into_iter().map(Into::into).collect())
+            .map_err(|e| errors::account("Could not fetch accounts.", e))
+    }
+
+    fn new_account(&self, password: String) -> RpcResult<RpcH160> {
+        info!("RPC Request: personal_newAccount()");
+        self.accounts
+            .new_account(&password.into())
+            .map(Into::into)
+            .map_err(|e| errors::account("Could not create account.", e))
+    }
+
+    fn import_raw_key(
+        &self, secret: RpcH256, password: String,
+    ) -> RpcResult<RpcH160> {
+        info!("RPC Request: personal_importRawKey()");
+        let secret: H256 = secret.into();
+        let secret = Secret::from_unsafe_slice(&secret[..])
+            .map_err(|e| errors::account("Invalid secret.", e))?;
+        self.accounts
+            .insert_account(secret, &password.into())
+            .map(Into::into)
+            .map_err(|e| errors::account("Could not import account.", e))
+    }
+
+    fn unlock_account(
+        &self, address: RpcH160, password: String, duration: Option<u64>,
+    ) -> RpcResult<bool> {
+        let duration = cmp::min(
+            Duration::from_secs(duration.unwrap_or(DEFAULT_UNLOCK_DURATION_S)),
+            self.max_unlock_duration,
+        );
+        info!(
+            "RPC Request: personal_unlockAccount({:?}, {:?})",
+            address, duration
+        );
+        self.accounts
+            .unlock_account_timed(address.into(), password.into(), duration)
+            .map(|_| true)
+            .map_err(errors::password)
+    }
+
+    fn send_transaction_with_password(
+        &self, request: SendTxRequest, password: String,
+    ) -> RpcResult<RpcH256> {
+        info!("RPC Request: personal_sendTransaction({:?})", request);
+        self.sign_and_send(request, Some(password.into()))
+    }
+
+    fn send_transaction(&self, request: SendTxRequest) -> RpcResult<RpcH256> {
+        info!("RPC Request: cfx_sendTransaction({:?})", request);
+        self.sign_and_send(request, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PersonalImpl, TransactionSender};
+    use crate::rpc::types::{SendTxRequest, H256 as RpcH256};
+    use cfx_types::{H256, U256};
+    use cfxcore::{
+        sync::utils::initialize_synchronization_graph, SharedTransactionPool,
+    };
+    use ethcore_accounts::AccountProvider;
+    use jsonrpc_core::Result as RpcResult;
+    use primitives::TransactionWithSignature;
+    use std::{fs, sync::Arc, time::Duration};
+
+    const GAS_PRICE: u64 = 7;
+
+    /// Insert the sent transactions into the pool directly, without
+    /// broadcasting them.
+    struct PoolSender(SharedTransactionPool);
+
+    impl TransactionSender for PoolSender {
+        fn gas_price(&self) -> U256 { GAS_PRICE.into() }
+
+        fn send_transaction(
+            &self, tx: TransactionWithSignature,
+        ) -> RpcResult<RpcH256> {
+            let (signed, failed) = self.0.insert_new_transactions(vec![tx]);
+            assert!(failed.is_empty(), "{:?}", failed);
+            Ok(signed[0].hash().into())
+        }
+    }
+
+    #[test]
+    fn test_sign_and_send() {
+        let db_dir = "./personal_test_db";
+        let _ = fs::remove_dir_all(db_dir);
+        {
+            let (_, consensus, _) =
+                initialize_synchronization_graph(db_dir, 1, 1, 1, 1, 50000);
+            let tx_pool = consensus.txpool.clone();
+            let accounts = AccountProvider::transient_provider();
+            let from = accounts.new_account(&"password".into()).unwrap();
+            let personal = PersonalImpl::new(
+                accounts,
+                Arc::new(PoolSender(tx_pool.clone())),
+                tx_pool.clone(),
+                1,
+                Duration::from_secs(60),
+            );
+            let request = SendTxRequest {
+                from: from.into(),
+                ..Default::default()
+            };
+
+            // The account is locked.
+            assert!(personal.sign_and_send(request.clone(), None).is_err());
+            assert_eq!(tx_pool.get_next_nonce(&from), U256::zero());
+
+            // The omitted nonces follow the ready transactions of the sender.
+            for nonce in 0..2u64 {
+                let hash: H256 = personal
+                    .sign_and_send(request.clone(), Some("password".into()))
+                    .unwrap()
+                    .into();
+                let tx = tx_pool.get_transaction(&hash).unwrap();
+                assert_eq!(tx.sender, from);
+                assert_eq!(tx.nonce, nonce.into());
+                assert_eq!(tx.gas_price, GAS_PRICE.into());
+                assert_eq!(tx.chain_id(), Some(1));
+                assert_eq!(tx_pool.get_next_nonce(&from), (nonce + 1).into());
+            }
+
+            // A transaction after a nonce gap is not ready, so it doesn't
+            // advance the next nonce.
+            let future = SendTxRequest {
+                nonce: Some(U256::from(3).into()),
+                ..request.clone()
+            };
+            personal
+                .sign_and_send(future, Some("password".into()))
+                .unwrap();
+            assert_eq!(tx_pool.get_next_nonce(&from), 2.into());
+        }
+        fs::remove_dir_all(db_dir).unwrap();
+    }
+}
@@ -9,40 +9,155 @@ use crate::rpc::{
     helpers::{errors, Subscribers},
     metadata::Metadata,
     traits::PubSub,
-    types::{pubsub, Header, Log},
+    types::{pubsub, Header, Log, Origin},
+};
+use blockgen::{BlockGenerator, BlockTemplate};
+use cfx_types::H256;
+use cfxcore::{
+    consensus::BestInfoObserverId, BestInformation, SharedConsensusGraph,
 };
 use jsonrpc_core::{
     futures::{self, sync::mpsc, Future, IntoFuture, Stream},
     BoxFuture, Error, Result,
 };
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use primitives::{filter::Filter, log_entry::LocalizedLogEntry, EpochNumber};
 use runtime::Executor;
 use std::{
-    collections::BTreeMap,
-    sync::{Arc, Weak},
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc, Weak,
+    },
+    thread,
+    time::Duration,
 };
 
 type Client = Sink<pubsub::Result>;
 
+/// The number of notifications of a subscriber that are queued for its
+/// connection but not sent yet.
+type Pending = Arc<AtomicUsize>;
+
+/// The number of the latest pivot epochs remembered to detect the epochs
+/// changed by a pivot chain reorganization.
+const MAX_TRACKED_EPOCHS: u64 = 1000;
+
+/// The period at which the block template forwarding thread checks whether
+/// there are still `blockTemplates` subscribers while no template arrives.
+const TEMPLATE_SUBSCRIBERS_CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct PubSubConfiguration {
+    /// The maximum number of active subscriptions of a connection.
+    pub max_subscriptions_per_connection: usize,
+    /// The maximum number of notifications queued for a subscriber. Further
+    /// notifications are dropped until the subscriber catches up.
+    pub max_pending_notifications: usize,
+}
+
 /// Cfx PubSub implementation.
+#[derive(Clone)]
 pub struct PubSubClient {
     handler: Arc<ChainNotificationHandler>,
-    heads_subscribers: Arc<RwLock<Subscribers<Client>>>,
+    heads_subscribers: Arc<RwLock<Subscribers<(Client, Pending)>>>,
+    epochs_subscribers: Arc<RwLock<Subscribers<(Client, Pending)>>>,
+    logs_subscribers: Arc<RwLock<Subscribers<(Client, (Pending, Filter))>>>,
+    confirmation_subscribers:
+        Arc<RwLock<Subscribers<(Client, pubsub::ConfirmationPolicy)>>>,
+    template_subscribers: Arc<RwLock<Subscribers<(Client, Pending)>>>,
+    connections: Arc<Mutex<ConnectionSubscriptions>>,
+    block_gen: Arc<BlockGenerator>,
+    template_forwarding_started: Arc<Mutex<bool>>,
+    best_info_observer: Arc<BestInfoObserverRegistration>,
+}
+
+/// The best info observer of a `PubSubClient`, which is unregistered from
+/// the consensus graph once the last clone of the client is dropped.
+struct BestInfoObserverRegistration {
+    consensus: SharedConsensusGraph,
+    id: BestInfoObserverId,
+}
+
+impl Drop for BestInfoObserverRegistration {
+    fn drop(&mut self) {
+        // Dropping the observer also closes the channels of the notifier
+        // threads, so that they exit.
+        self.consensus.unregister_best_info_observer(self.id);
+    }
 }
 
 impl PubSubClient {
     /// Creates new `PubSubClient`.
-    pub fn new(executor: Executor) -> Self {
+    pub fn new(
+        executor: Executor, consensus: SharedConsensusGraph,
+        block_gen: Arc<BlockGenerator>, conf: PubSubConfiguration,
+    ) -> Self {
         let heads_subscribers = Arc::new(RwLock::new(Subscribers::default()));
+        let epochs_subscribers = Arc::new(RwLock::new(Subscribers::default()));
+        let logs_subscribers = Arc::new(RwLock::new(Subscribers::default()));
+        let confirmation_subscribers =
+            Arc::new(RwLock::new(Subscribers::default()));
+        let template_subscribers =
+            Arc::new(RwLock::new(Subscribers::default()));
+        let connections = Arc::new(Mutex::new(ConnectionSubscriptions {
+            max_per_connection: conf.max_subscriptions_per_connection,
+            counts: HashMap::new(),
+            owners: HashMap::new(),
+        }));
 
         let handler = Arc::new(ChainNotificationHandler {
+            consensus: consensus.clone(),
             executor,
+            max_pending_notifications: conf.max_pending_notifications,
             heads_subscribers: heads_subscribers.clone(),
+            epochs_subscribers: epochs_subscribers.clone(),
+            logs_subscribers: logs_subscribers.clone(),
+            confirmation_subscribers: confirmation_subscribers.clone(),
+            template_subscribers: template_subscribers.clone(),
+            connections: connections.clone(),
+            pivot_epochs: Mutex::new(EpochTracker::default()),
+            executed_epochs: Mutex::new(EpochTracker::default()),
         });
 
+        // Notify the subscribers every time the best info changes. The
+        // notifications are prepared in separate threads, so that the
+        // consensus thread is not blocked by reading the epochs, logs and
+        // transactions, and the confirmations do not delay the epochs.
+        let notifier = spawn_best_info_notifier(
+            "pubsub notifier",
+            &handler,
+            ChainNotificationHandler::notify_best_info,
+        );
+        let confirmation_notifier = spawn_best_info_notifier(
+            "pubsub confirmations",
+            &handler,
+            ChainNotificationHandler::notify_confirmations,
+        );
+        let senders = Mutex::new((notifier, confirmation_notifier));
+        let best_info_observer_id =
+            consensus.register_best_info_observer(Box::new(move |best_info| {
+                // The notifier threads have exited if the handler is dropped.
+                let senders = senders.lock();
+                let _ = senders.0.send(best_info.clone());
+                let _ = senders.1.send(best_info);
+            }));
+
         PubSubClient {
             handler,
             heads_subscribers,
+            epochs_subscribers,
+            logs_subscribers,
+            confirmation_subscribers,
+            template_subscribers,
+            connections,
+            block_gen,
+            template_forwarding_started: Arc::new(Mutex::new(false)),
+            best_info_observer: Arc::new(BestInfoObserverRegistration {
+                consensus,
+                id: best_info_observer_id,
+            }),
         }
     }
 
@@ -50,12 +165,215 @@ impl PubSubClient {
     pub fn handler(&self) -> Weak<ChainNotificationHandler> {
         Arc::downgrade(&self.handler)
     }
+
+    /// Subscribe to the block templates of the block generator once the
+    /// first `blockTemplates` subscription arrives, so that templates are
+    /// not assembled when nobody is listening. The forwarding stops once
+    /// all the `blockTemplates` subscriptions are gone, and is started again
+    /// by the next one.
+    fn forward_block_templates(&self) {
+        let mut started = self.template_forwarding_started.lock();
+        if *started {
+            return;
+        }
+        *started = true;
+
+        let receiver =
+            BlockGenerator::subscribe_block_templates(self.block_gen.clone());
+        let weak = Arc::downgrade(&self.handler);
+        let started = self.template_forwarding_started.clone();
+        let subscribers = self.template_subscribers.clone();
+        thread::Builder::new()
+            .name("pubsub block templates".into())
+            .spawn(move || {
+                forward_until_idle(
+                    &receiver,
+                    TEMPLATE_SUBSCRIBERS_CHECK_PERIOD,
+                    |template| match weak.upgrade() {
+                        Some(handler) => {
+                            handler.notify_block_template(template);
+                            true
+                        }
+                        None => false,
+                    },
+                    || {
+                        // Checked under the lock, so that a new subscription
+                        // either is seen here or starts a new thread.
+                        let mut started = started.lock();
+                        if subscribers.read().is_empty() {
+                            *started = false;
+                        }
+                        !*started
+                    },
+                );
+                // The block generator stops sending templates once the
+                // receiver is dropped.
+            })
+            .expect("Block template forwarding thread spawn error");
+    }
+}
+
+/// Spawn a thread calling `notify` on the handler with the best info sent to
+/// the returned channel, until the handler is dropped.
+fn spawn_best_info_notifier<F>(
+    name: &str, handler: &Arc<ChainNotificationHandler>, notify: F,
+) -> std::sync::mpsc::Sender<Arc<BestInformation>>
+where F: Fn(&ChainNotificationHandler, &BestInformation) + Send + 'static
+{
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let weak = Arc::downgrade(handler);
+    thread::Builder::new()
+        .name(name.into())
+        .spawn(move || {
+            while let Ok(best_info) = receiver.recv() {
+                // Only the latest best info matters, since the epochs skipped
+                // in between are caught up by the trackers, and the
+                // confirmations are checked at the latest epoch.
+                let best_info: Arc<BestInformation> =
+                    receiver.try_iter().last().unwrap_or(best_info);
+                match weak.upgrade() {
+                    Some(handler) => notify(&handler, &best_info),
+                    None => return,
+                }
+            }
+        })
+        .expect("PubSub notifier thread spawn error");
+    sender
+}
+
+/// Pass the items received to `forward` until it returns false, the sender
+/// is dropped, or `stop_if_idle` returns true, which is checked after each
+/// item and every `check_period` without items.
+fn forward_until_idle<T, F, S>(
+    receiver: &Receiver<T>, check_period: Duration, mut forward: F,
+    stop_if_idle: S,
+) where
+    F: FnMut(T) -> bool,
+    S: Fn() -> bool,
+{
+    loop {
+        match receiver.recv_timeout(check_period) {
+            Ok(item) => {
+                if !forward(item) {
+                    return;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        if stop_if_idle() {
+            return;
+        }
+    }
+}
+
+/// The active subscriptions of each connection, which are limited to
+/// `max_per_connection`.
+struct ConnectionSubscriptions {
+    max_per_connection: usize,
+    counts: HashMap<Origin, usize>,
+    owners: HashMap<SubscriptionId, Origin>,
+}
+
+impl ConnectionSubscriptions {
+    fn is_full(&self, origin: &Origin) -> bool {
+        self.counts.get(origin).cloned().unwrap_or(0) >= self.max_per_connection
+    }
+
+    fn add(&mut self, origin: Origin, id: SubscriptionId) {
+        *self.counts.entry(origin.clone()).or_insert(0) += 1;
+        self.owners.insert(id, origin);
+    }
+
+    fn remove(&mut self, id: &SubscriptionId) {
+        if let Some(origin) = self.owners.remove(id) {
+            let count = self.counts.entry(origin.clone()).or_insert(0);
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.counts.remove(&origin);
+            }
+        }
+    }
+}
+
+/// The pivot blocks of the latest epochs that have been notified, so that the
+/// epochs replaced by a pivot chain reorganization are notified again.
+#[derive(Default)]
+struct EpochTracker {
+    notified: BTreeMap<u64, H256>,
+}
+
+impl EpochTracker {
+    /// Returns the epochs up to `to_epoch` that are new or have changed since
+    /// the last call, along with the block hashes of each epoch.
+    fn advance(
+        &mut self, consensus: &SharedConsensusGraph, to_epoch: u64,
+    ) -> Vec<(u64, Vec<H256>)> {
+        let epoch_hashes = |epoch| {
+            consensus
+                .get_block_hashes_by_epoch(EpochNumber::Number(epoch))
+                .ok()
+        };
+        // The epochs beyond `to_epoch` have been retracted.
+        let retracted: Vec<u64> = self
+            .notified
+            .range(to_epoch + 1..)
+            .map(|(e, _)| *e)
+            .collect();
+        for epoch in retracted {
+            self.notified.remove(&epoch);
+        }
+
+        // Find the latest notified epoch that is still on the pivot chain.
+        let lowest = to_epoch.saturating_sub(MAX_TRACKED_EPOCHS);
+        let mut from_epoch = match self.notified.keys().next_back() {
+            Some(latest) => latest + 1,
+            // Start from the current epoch on the first call.
+            None => to_epoch,
+        };
+        while from_epoch > lowest {
+            let epoch = from_epoch - 1;
+            let pivot = epoch_hashes(epoch).and_then(|h| h.last().cloned());
+            match (self.notified.get(&epoch), pivot) {
+                (Some(notified), Some(pivot)) if *notified == pivot => break,
+                (None, _) => break,
+                _ => from_epoch = epoch,
+            }
+        }
+
+        let mut epochs = Vec::new();
+        for epoch in from_epoch..=to_epoch {
+            if let Some(hashes) = epoch_hashes(epoch) {
+                if let Some(pivot) = hashes.last() {
+                    self.notified.insert(epoch, *pivot);
+                }
+                epochs.push((epoch, hashes));
+            }
+        }
+        while self.notified.len() as u64 > MAX_TRACKED_EPOCHS {
+            let oldest = *self.notified.keys().next().unwrap();
+            self.notified.remove(&oldest);
+        }
+        epochs
+    }
 }
 
 /// PubSub notification handler.
 pub struct ChainNotificationHandler {
+    consensus: SharedConsensusGraph,
     executor: Executor,
-    heads_subscribers: Arc<RwLock<Subscribers<Client>>>,
+    max_pending_notifications: usize,
+    heads_subscribers: Arc<RwLock<Subscribers<(Client, Pending)>>>,
+    epochs_subscribers: Arc<RwLock<Subscribers<(Client, Pending)>>>,
+    logs_subscribers: Arc<RwLock<Subscribers<(Client, (Pending, Filter))>>>,
+    confirmation_subscribers:
+        Arc<RwLock<Subscribers<(Client, pubsub::ConfirmationPolicy)>>>,
+    template_subscribers: Arc<RwLock<Subscribers<(Client, Pending)>>>,
+    connections: Arc<Mutex<ConnectionSubscriptions>>,
+    /// The pivot chain epochs notified to heads and epochs subscribers.
+    pivot_epochs: Mutex<EpochTracker>,
+    /// The executed epochs notified to logs subscribers.
+    executed_epochs: Mutex<EpochTracker>,
 }
 
 impl ChainNotificationHandler {
@@ -67,27 +385,350 @@ impl ChainNotificationHandler {
         ));
     }
 
-    fn notify_heads(&self, headers: &[(Vec<u8>, BTreeMap<String, String>)]) {
-        for subscriber in self.heads_subscribers.read().values() {}
+    /// Notify a subscriber unless it already has
+    /// `max_pending_notifications` notifications queued, in which case the
+    /// notification is dropped so that a slow connection doesn't accumulate
+    /// unbounded memory.
+    fn notify_with_backpressure(
+        &self, subscriber: &Client, pending: &Pending, result: pubsub::Result,
+    ) {
+        if pending.fetch_add(1, Ordering::SeqCst)
+            >= self.max_pending_notifications
+        {
+            pending.fetch_sub(1, Ordering::SeqCst);
+            warn!(
+                target: "rpc",
+                "Notification dropped for a slow subscriber, {} pending",
+                self.max_pending_notifications
+            );
+            return;
+        }
+        let pending = pending.clone();
+        self.executor
+            .spawn(subscriber.notify(Ok(result)).then(move |r| {
+                pending.fetch_sub(1, Ordering::SeqCst);
+                if let Err(e) = r {
+                    warn!(target: "rpc", "Unable to send notification: {}", e);
+                }
+                Ok(())
+            }));
+    }
+
+    fn notify_best_info(&self, best_info: &BestInformation) {
+        self.notify_epochs(best_info);
+        self.notify_logs();
+    }
+
+    /// Notify heads subscribers with the new pivot blocks and epochs
+    /// subscribers with the new epochs, including the epochs replaced by a
+    /// pivot chain reorganization.
+    fn notify_epochs(&self, best_info: &BestInformation) {
+        if self.heads_subscribers.read().is_empty()
+            && self.epochs_subscribers.read().is_empty()
+        {
+            return;
+        }
+        let epochs = self
+            .pivot_epochs
+            .lock()
+            .advance(&self.consensus, best_info.best_epoch_number);
+
+        for (epoch_number, hashes) in epochs {
+            let pivot_hash = match hashes.last() {
+                Some(hash) => *hash,
+                None => continue,
+            };
+            if let Some(header) =
+                self.consensus.data_man.block_header_by_hash(&pivot_hash)
+            {
+                let header = Header::new(
+                    &header,
+                    &*self.consensus.inner.read_recursive(),
+                );
+                self.notify_heads(header);
+            }
+            let result = pubsub::Result::Epoch(pubsub::Epoch {
+                epoch_number: epoch_number.into(),
+                epoch_hashes_ordered: hashes
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+            });
+            for (subscriber, pending) in self.epochs_subscribers.read().values()
+            {
+                self.notify_with_backpressure(
+                    subscriber,
+                    pending,
+                    result.clone(),
+                );
+            }
+        }
+    }
+
+    fn notify_heads(&self, header: Header) {
+        let result = pubsub::Result::Header(header);
+        for (subscriber, pending) in self.heads_subscribers.read().values() {
+            self.notify_with_backpressure(subscriber, pending, result.clone());
+        }
+    }
+
+    /// Notify logs subscribers with the matching logs of the newly executed
+    /// epochs.
+    fn notify_logs(&self) {
+        if self.logs_subscribers.read().is_empty() {
+            return;
+        }
+        let epochs = self.executed_epochs.lock().advance(
+            &self.consensus,
+            self.consensus.executed_best_state_epoch_number(),
+        );
+
+        for (_, hashes) in epochs {
+            // The logs of an epoch are read once and filtered for each
+            // subscriber.
+            let logs = self.consensus.logs_from_blocks(hashes, |_| true, None);
+            if logs.is_empty() {
+                continue;
+            }
+            for (subscriber, (pending, filter)) in
+                self.logs_subscribers.read().values()
+            {
+                for log in matching_logs(filter, &logs) {
+                    self.notify_with_backpressure(
+                        subscriber,
+                        pending,
+                        pubsub::Result::Log(Log::from(log.clone())),
+                    );
+                }
+            }
+        }
+    }
+
+    fn notify_block_template(&self, template: BlockTemplate) {
+        let result = pubsub::Result::BlockTemplate(template.into());
+        for (subscriber, pending) in self.template_subscribers.read().values()
+        {
+            self.notify_with_backpressure(subscriber, pending, result.clone());
+        }
+    }
+
+    /// The block containing the transaction `hash`, along with its epoch
+    /// and its confirmation risk.
+    fn locate_transaction(
+        &self, hash: &H256,
+    ) -> Option<(H256, u64, Option<f64>)> {
+        let (_, _, address) =
+            self.consensus.get_transaction_info_by_hash(hash)?;
+        let block_hash = address.block_hash;
+        let epoch = self.consensus.get_block_epoch_number(&block_hash)?;
+        let risk = self.consensus.confirmation_risk_by_hash(block_hash);
+        Some((block_hash, epoch, risk))
+    }
+
+    /// Notify confirmation subscribers whose condition has been reached.
+    /// Each subscription is notified at most once and removed afterwards.
+    /// Each watched transaction is looked up once for all its subscriptions.
+    fn notify_confirmations(&self, best_info: &BestInformation) {
+        // The subscriptions are copied, so that subscribing is not blocked
+        // by the lookups.
+        let subscriptions: Vec<_> = self
+            .confirmation_subscribers
+            .read()
+            .iter()
+            .map(|(id, (subscriber, policy))| {
+                (id.as_string(), subscriber.clone(), policy.clone())
+            })
+            .collect();
+        if subscriptions.is_empty() {
+            return;
+        }
+
+        let best_epoch = best_info.best_epoch_number;
+        let mut locations = HashMap::new();
+        let mut reached = vec![];
+
+        for (id, subscriber, policy) in subscriptions {
+            let hash: H256 = policy.transaction_hash.clone().into();
+            let location = *locations
+                .entry(hash)
+                .or_insert_with(|| self.locate_transaction(&hash));
+            let confirmation =
+                location.and_then(|(block_hash, epoch, risk)| {
+                    reached_confirmation(
+                        &policy, block_hash, epoch, risk, best_epoch,
+                    )
+                });
+            if let Some(c) = confirmation {
+                let result = pubsub::Result::Confirmation(c);
+                Self::notify(&self.executor, &subscriber, result);
+                reached.push(SubscriptionId::String(id));
+            }
+        }
+
+        let mut subscribers = self.confirmation_subscribers.write();
+
+        for id in &reached {
+            subscribers.remove(id);
+        }
+        drop(subscribers);
+
+        let mut connections = self.connections.lock();
+        for id in &reached {
+            connections.remove(id);
+        }
     }
 }
 
+/// The confirmation of a transaction in `block_hash` of `epoch` with the
+/// confirmation `risk`, if it has reached `policy` at `best_epoch`.
+fn reached_confirmation(
+    policy: &pubsub::ConfirmationPolicy, block_hash: H256, epoch: u64,
+    risk: Option<f64>, best_epoch: u64,
+) -> Option<pubsub::Confirmation>
+{
+    let confirmed_epochs = best_epoch.saturating_sub(epoch);
+
+    if let Some(epochs) = policy.epochs {
+        if (confirmed_epochs as usize) < epochs.as_usize() {
+            return None;
+        }
+    }
+
+    if let Some(threshold) = policy.risk {
+        match risk {
+            Some(risk) if risk <= threshold => {}
+            _ => return None,
+        }
+    }
+
+    Some(pubsub::Confirmation {
+        transaction_hash: policy.transaction_hash.clone(),
+        block_hash: block_hash.into(),
+        epoch_number: epoch.into(),
+        confirmed_epochs: confirmed_epochs.into(),
+        risk,
+    })
+}
+
+/// The logs of an epoch matching the filter of a logs subscription, at most
+/// the latest `filter.limit` of them.
+fn matching_logs<'a>(
+    filter: &Filter, logs: &'a [LocalizedLogEntry],
+) -> Vec<&'a LocalizedLogEntry> {
+    let mut matched: Vec<_> = logs
+        .iter()
+        .filter(|log| {
+            filter
+                .block_hashes
+                .as_ref()
+                .map_or(true, |hashes| hashes.contains(&log.block_hash))
+                && filter.matches(&log.entry)
+        })
+        .collect();
+    if let Some(limit) = filter.limit {
+        let skipped = matched.len().saturating_sub(limit);
+        matched.drain(..skipped);
+    }
+    matched
+}
+
 impl PubSub for PubSubClient {
     type Metadata = Metadata;
 
     fn subscribe(
-        &self, _meta: Metadata, subscriber: Subscriber<pubsub::Result>,
+        &self, meta: Metadata, subscriber: Subscriber<pubsub::Result>,
         kind: pubsub::Kind, params: Option<pubsub::Params>,
     )
     {
+        let mut connections = self.connections.lock();
+        if connections.is_full(&meta.origin) {
+            let _ = subscriber.reject(errors::subscription_limit_exceeded(
+                connections.max_per_connection,
+            ));
+            return;
+        }
+
         let error = match (kind, params) {
             (pubsub::Kind::NewHeads, None) => {
-                self.heads_subscribers.write().push(subscriber);
+                let id = self
+                    .heads_subscribers
+                    .write()
+                    .push(subscriber, Pending::default());
+                if let Some(id) = id {
+                    connections.add(meta.origin, id);
+                }
                 return;
             }
             (pubsub::Kind::NewHeads, _) => {
                 errors::invalid_params("newHeads", "Expected no parameters.")
             }
+            (pubsub::Kind::Epochs, None) => {
+                let id = self
+                    .epochs_subscribers
+                    .write()
+                    .push(subscriber, Pending::default());
+                if let Some(id) = id {
+                    connections.add(meta.origin, id);
+                }
+                return;
+            }
+            (pubsub::Kind::Epochs, _) => {
+                errors::invalid_params("epochs", "Expected no parameters.")
+            }
+            (pubsub::Kind::Logs, Some(pubsub::Params::Logs(filter))) => {
+                let id = self
+                    .logs_subscribers
+                    .write()
+                    .push(subscriber, (Pending::default(), filter.into()));
+                if let Some(id) = id {
+                    connections.add(meta.origin, id);
+                }
+                return;
+            }
+            (pubsub::Kind::Logs, _) => {
+                errors::invalid_params("logs", "Expected a filter object.")
+            }
+            (
+                pubsub::Kind::TransactionConfirmation,
+                Some(pubsub::Params::Confirmation(policy)),
+            ) => {
+                if policy.epochs.is_none() && policy.risk.is_none() {
+                    errors::invalid_params(
+                        "transactionConfirmation",
+                        "Expected `epochs` or `risk`.",
+                    )
+                } else {
+                    let id = self
+                        .confirmation_subscribers
+                        .write()
+                        .push(subscriber, policy);
+                    if let Some(id) = id {
+                        connections.add(meta.origin, id);
+                    }
+                    return;
+                }
+            }
+            (pubsub::Kind::TransactionConfirmation, _) => {
+                errors::invalid_params(
+                    "transactionConfirmation",
+                    "Expected a confirmation policy.",
+                )
+            }
+            (pubsub::Kind::BlockTemplates, None) => {
+                let id = self
+                    .template_subscribers
+                    .write()
+                    .push(subscriber, Pending::default());
+                if let Some(id) = id {
+                    connections.add(meta.origin, id);
+                }
+                self.forward_block_templates();
+                return;
+            }
+            (pubsub::Kind::BlockTemplates, _) => errors::invalid_params(
+                "blockTemplates",
+                "Expected no parameters.",
+            ),
             _ => errors::unimplemented(None),
         };
 
@@ -97,8 +738,191 @@ impl PubSub for PubSubClient {
     fn unsubscribe(
         &self, _: Option<Self::Metadata>, id: SubscriptionId,
     ) -> Result<bool> {
-        let res = self.heads_subscribers.write().remove(&id).is_some();
+        let res = self.heads_subscribers.write().remove(&id).is_some()
+            || self.epochs_subscribers.write().remove(&id).is_some()
+            || self.logs_subscribers.write().remove(&id).is_some()
+            || self.confirmation_subscribers.write().remove(&id).is_some()
+            || self.template_subscribers.write().remove(&id).is_some();
+        // Subscriptions are also unsubscribed when their connection closes.
+        self.connections.lock().remove(&id);
 
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{forward_until_idle, matching_logs, reached_confirmation};
+    use crate::rpc::types::pubsub::ConfirmationPolicy;
+    use cfx_types::{Address, H256};
+    use primitives::{
+        filter::Filter,
+        log_entry::{LocalizedLogEntry, LogEntry},
+        EpochNumber,
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc,
+        },
+        time::Duration,
+    };
+
+    fn log(block: u64, address: u64, topic: u64) -> LocalizedLogEntry {
+        LocalizedLogEntry {
+            entry: LogEntry {
+                address: Address::from_low_u64_be(address),
+                topics: vec![H256::from_low_u64_be(topic)],
+                data: vec![],
+            },
+            block_hash: H256::from_low_u64_be(block),
+            ..Default::default()
+        }
+    }
+
+    fn filter() -> Filter {
+        Filter {
+            from_epoch: EpochNumber::LatestState,
+            to_epoch: EpochNumber::LatestState,
+            block_hashes: None,
+            address: None,
+            topics: vec![],
+            limit: None,
+        }
+    }
+
+    #[test]
+    fn test_matching_logs() {
+        let logs = vec![
+            log(1, 10, 100),
+            log(1, 11, 101),
+            log(2, 10, 101),
+            log(2, 11, 100),
+        ];
+
+        assert_eq!(matching_logs(&filter(), &logs).len(), 4);
+
+        let by_address = Filter {
+            address: Some(vec![Address::from_low_u64_be(10)]),
+            ..filter()
+        };
+        assert_eq!(matching_logs(&by_address, &logs), vec![&logs[0], &logs[2]]);
+
+        let by_topic = Filter {
+            topics: vec![Some(vec![H256::from_low_u64_be(101)])],
+            ..filter()
+        };
+        assert_eq!(matching_logs(&by_topic, &logs), vec![&logs[1], &logs[2]]);
+
+        let by_block = Filter {
+            block_hashes: Some(vec![H256::from_low_u64_be(2)]),
+            ..filter()
+        };
+        assert_eq!(matching_logs(&by_block, &logs), vec![&logs[2], &logs[3]]);
+
+        // Only the latest logs within the limit are notified.
+        let limited = Filter {
+            limit: Some(3),
+            ..filter()
+        };
+        assert_eq!(
+            matching_logs(&limited, &logs),
+            vec![&logs[1], &logs[2], &logs[3]]
+        );
+    }
+
+    fn policy(epochs: Option<u64>, risk: Option<f64>) -> ConfirmationPolicy {
+        ConfirmationPolicy {
+            transaction_hash: H256::from_low_u64_be(1).into(),
+            epochs: epochs.map(Into::into),
+            risk,
+        }
+    }
+
+    #[test]
+    fn test_reached_confirmation() {
+        let block = H256::from_low_u64_be(2);
+        let reached = |policy: &ConfirmationPolicy, risk, best_epoch| {
+            reached_confirmation(policy, block, 10, risk, best_epoch)
+        };
+
+        let by_epochs = policy(Some(3), None);
+        assert_eq!(reached(&by_epochs, None, 12), None);
+        let confirmation = reached(&by_epochs, None, 13).unwrap();
+        assert_eq!(H256::from(confirmation.block_hash), block);
+        assert_eq!(confirmation.epoch_number.as_usize(), 10);
+        assert_eq!(confirmation.confirmed_epochs.as_usize(), 3);
+
+        // The risk is unknown until the block is on the pivot chain.
+        let by_risk = policy(None, Some(1e-4));
+        assert_eq!(reached(&by_risk, None, 20), None);
+        assert_eq!(reached(&by_risk, Some(1e-3), 20), None);
+        assert!(reached(&by_risk, Some(1e-5), 10).is_some());
+
+        // Both conditions have to be met.
+        let both = policy(Some(3), Some(1e-4));
+        assert_eq!(reached(&both, Some(1e-5), 12), None);
+        assert_eq!(reached(&both, Some(1e-3), 13), None);
+        assert!(reached(&both, Some(1e-5), 13).is_some());
+    }
+
+    #[test]
+    fn test_forward_until_idle() {
+        let check_period = Duration::from_millis(10);
+        let (sender, receiver) = mpsc::channel();
+        for i in 0..5 {
+            sender.send(i).unwrap();
+        }
+
+        // The forwarding stops once there are no subscribers left.
+        let subscribers = AtomicUsize::new(3);
+        let mut forwarded = vec![];
+        forward_until_idle(
+            &receiver,
+            check_period,
+            |item| {
+                forwarded.push(item);
+                subscribers.fetch_sub(1, Ordering::SeqCst);
+                true
+            },
+            || subscribers.load(Ordering::SeqCst) == 0,
+        );
+        assert_eq!(forwarded, vec![0, 1, 2]);
+
+        // It also stops without new items.
+        let checks = AtomicUsize::new(0);
+        let (_sender, idle_receiver) = mpsc::channel::<u32>();
+        forward_until_idle(
+            &idle_receiver,
+            check_period,
+            |_| true,
+            || checks.fetch_add(1, Ordering::SeqCst) == 2,
+        );
+        assert_eq!(checks.load(Ordering::SeqCst), 3);
+
+        // The forwarding stops if the handler is gone, or the sender.
+        let mut forwarded = vec![];
+        forward_until_idle(
+            &receiver,
+            check_period,
+            |item| {
+                forwarded.push(item);
+                false
+            },
+            || false,
+        );
+        assert_eq!(forwarded, vec![3]);
+        drop(sender);
+        let mut forwarded = vec![];
+        forward_until_idle(
+            &receiver,
+            check_period,
+            |item| {
+                forwarded.push(item);
+                true
+            },
+            || false,
+        );
+        assert_eq!(forwarded, vec![4]);
+    }
+}
@@ -0,0 +1,385 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use delegate::delegate;
+use jsonrpc_core::{Error as RpcError, Result as RpcResult};
+use std::sync::Arc;
+
+use cfx_types::{H160, H256};
+use cfxcore::{
+    block_data_manager::{BlockDataManager, BlockExecutionResultWithEpoch},
+    statedb::StateDb,
+    storage::{state_manager::StateManagerTrait, SnapshotAndEpochIdRef},
+};
+
+use crate::rpc::{
+    traits::cfx::Cfx,
+    types::{
+        AccountState, Block as RpcBlock, BlockTemplate, Bytes,
+        DecodedTransaction, EpochNumber, Filter as RpcFilter, Log as RpcLog,
+        Receipt as RpcReceipt, Transaction as RpcTransaction, H160 as RpcH160,
+        H256 as RpcH256, U256 as RpcU256, U64 as RpcU64,
+    },
+};
+
+/// Serves the queries of the blocks, receipts and states recorded in a
+/// database opened read-only, without consensus or networking. The pivot
+/// chain is the one recorded with the executed epochs, and it stays at
+/// `latest_epoch` as the database is not updated.
+pub struct RpcImpl {
+    data_man: Arc<BlockDataManager>,
+    latest_epoch: u64,
+}
+
+impl RpcImpl {
+    pub fn new(data_man: Arc<BlockDataManager>, latest_epoch: u64) -> Self {
+        RpcImpl {
+            data_man,
+            latest_epoch,
+        }
+    }
+
+    fn epoch_height(&self, epoch: EpochNumber) -> RpcResult<u64> {
+        match epoch {
+            EpochNumber::Earliest => Ok(0),
+            EpochNumber::LatestMined | EpochNumber::LatestState => {
+                Ok(self.latest_epoch)
+            }
+            EpochNumber::Num(n) if n <= self.latest_epoch => Ok(n),
+            EpochNumber::Num(n) => Err(RpcError::invalid_params(format!(
+                "Epoch {} is after the latest epoch {} in the database",
+                n, self.latest_epoch
+            ))),
+        }
+    }
+
+    fn epoch_hashes(&self, epoch_number: u64) -> RpcResult<Vec<H256>> {
+        self.data_man
+            .epoch_set_hashes_from_db(epoch_number)
+            .ok_or_else(|| {
+                RpcError::invalid_params(format!(
+                    "Epoch {} is not in the database",
+                    epoch_number
+                ))
+            })
+    }
+
+    fn pivot_hash(&self, epoch_number: u64) -> RpcResult<H256> {
+        Ok(*self.epoch_hashes(epoch_number)?.last().unwrap())
+    }
+
+    /// The number and the hash of the epoch in which `block_hash` is
+    /// executed.
+    fn block_epoch(&self, block_hash: &H256) -> Option<(u64, H256)> {
+        let BlockExecutionResultWithEpoch(epoch_hash, _) = self
+            .data_man
+            .block_execution_result_by_hash_from_db(block_hash)?;
+        let epoch_number =
+            self.data_man.block_header_by_hash(&epoch_hash)?.height();
+        if epoch_number > self.latest_epoch {
+            return None;
+        }
+        Some((epoch_number, epoch_hash))
+    }
+
+    /// The state root after executing the epoch, which is recorded when the
+    /// state is deferred to a later pivot block.
+    fn state_root(&self, epoch_number: u64) -> Option<H256> {
+        let pivot_hash = self
+            .data_man
+            .epoch_set_hashes_from_db(
+                epoch_number + self.data_man.deferred_state_epoch_count(),
+            )?
+            .last()
+            .cloned()?;
+        self.data_man
+            .consensus_graph_execution_info_from_db(&pivot_hash)
+            .map(|info| info.original_deferred_state_root)
+    }
+
+    fn state_db(&self, epoch: Option<EpochNumber>) -> RpcResult<StateDb> {
+        let epoch_number =
+            self.epoch_height(epoch.unwrap_or(EpochNumber::LatestState))?;
+        let epoch_hash = self.pivot_hash(epoch_number)?;
+        let snapshot = self
+            .data_man
+            .storage_manager
+            .snapshot_of_epoch(epoch_number);
+        match self.data_man.storage_manager.get_state_no_commit(
+            SnapshotAndEpochIdRef::new_with_snapshot(
+                &epoch_hash,
+                &snapshot,
+                epoch_number,
+            ),
+        ) {
+            Ok(Some(state)) => Ok(StateDb::new(state)),
+            Ok(None) => Err(RpcError::invalid_params(format!(
+                "State of epoch {} does not exist",
+                epoch_number
+            ))),
+            Err(e) => Err(RpcError::invalid_params(format!(
+                "Error to get state, err={:?}",
+                e
+            ))),
+        }
+    }
+
+    fn rpc_block(
+        &self, block_hash: &H256, include_txs: bool,
+    ) -> Option<RpcBlock> {
+        let block = self
+            .data_man
+            .block_by_hash(block_hash, false /* update_cache */)?;
+        let epoch = self.block_epoch(block_hash);
+        let receipts = epoch.and_then(|(_, epoch_hash)| {
+            self.data_man.block_execution_result_by_hash_with_epoch(
+                block_hash,
+                &epoch_hash,
+                false, /* update_cache */
+            )
+        });
+        Some(RpcBlock::new_with_receipts(
+            &*block,
+            epoch,
+            receipts.as_ref().map(|result| result.receipts.as_slice()),
+            include_txs,
+        ))
+    }
+
+    fn epoch_number(
+        &self, epoch_num: Option<EpochNumber>,
+    ) -> RpcResult<RpcU256> {
+        let epoch_num = epoch_num.unwrap_or(EpochNumber::LatestMined);
+        info!("RPC Request: cfx_epochNumber({:?})", epoch_num);
+        self.epoch_height(epoch_num).map(Into::into)
+    }
+
+    fn best_block_hash(&self) -> RpcResult<RpcH256> {
+        info!("RPC Request: cfx_getBestBlockHash()");
+        self.pivot_hash(self.latest_epoch).map(Into::into)
+    }
+
+    fn blocks_by_epoch(&self, num: EpochNumber) -> RpcResult<Vec<RpcH256>> {
+        info!("RPC Request: cfx_getBlocksByEpoch epoch_number={:?}", num);
+        let epoch_number = self.epoch_height(num)?;
+        Ok(self
+            .epoch_hashes(epoch_number)?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    fn block_by_hash(
+        &self, hash: RpcH256, include_txs: bool,
+    ) -> RpcResult<Option<RpcBlock>> {
+        let hash: H256 = hash.into();
+        info!(
+            "RPC Request: cfx_getBlockByHash hash={:?} include_txs={:?}",
+            hash, include_txs
+        );
+        Ok(self.rpc_block(&hash, include_txs))
+    }
+
+    fn block_by_epoch_number(
+        &self, epoch_num: EpochNumber, include_txs: bool,
+    ) -> RpcResult<RpcBlock> {
+        info!("RPC Request: cfx_getBlockByEpochNumber epoch_number={:?} include_txs={:?}", epoch_num, include_txs);
+        let pivot_hash = self.pivot_hash(self.epoch_height(epoch_num)?)?;
+        self.rpc_block(&pivot_hash, include_txs).ok_or_else(|| {
+            RpcError::invalid_params(format!(
+                "Block {:?} is not in the database",
+                pivot_hash
+            ))
+        })
+    }
+
+    fn balance(
+        &self, address: RpcH160, num: Option<EpochNumber>,
+    ) -> RpcResult<RpcU256> {
+        let address: H160 = address.into();
+        info!(
+            "RPC Request: cfx_getBalance address={:?} epoch_num={:?}",
+            address, num
+        );
+        let account = self
+            .state_db(num)?
+            .get_account(&address)
+            .map_err(|e| RpcError::invalid_params(format!("{:?}", e)))?;
+        Ok(account
+            .map(|account| account.balance.into())
+            .unwrap_or_default())
+    }
+
+    fn transaction_count(
+        &self, address: RpcH160, num: Option<EpochNumber>,
+    ) -> RpcResult<RpcU256> {
+        let address: H160 = address.into();
+        info!(
+            "RPC Request: cfx_getTransactionCount address={:?} epoch_num={:?}",
+            address, num
+        );
+        let account = self
+            .state_db(num)?
+            .get_account(&address)
+            .map_err(|e| RpcError::invalid_params(format!("{:?}", e)))?;
+        Ok(account
+            .map(|account| account.nonce.into())
+            .unwrap_or_default())
+    }
+
+    fn code(
+        &self, address: RpcH160, num: Option<EpochNumber>,
+    ) -> RpcResult<Bytes> {
+        let address: H160 = address.into();
+        info!(
+            "RPC Request: cfx_getCode address={:?} epoch_num={:?}",
+            address, num
+        );
+        let state_db = self.state_db(num)?;
+        let code = match state_db
+            .get_account(&address)
+            .map_err(|e| RpcError::invalid_params(format!("{:?}", e)))?
+        {
+            Some(account) => state_db
+                .get_code(&address, &account.code_hash)
+                .unwrap_or_default(),
+            None => vec![],
+        };
+        Ok(Bytes::new(code))
+    }
+
+    fn accounts(
+        &self, addrs: Vec<RpcH160>, num: Option<EpochNumber>,
+    ) -> RpcResult<Vec<Option<AccountState>>> {
+        let addresses: Vec<H160> = addrs.into_iter().map(Into::into).collect();
+        info!(
+            "RPC Request: cfx_getAccounts addresses={:?} epoch_num={:?}",
+            addresses, num
+        );
+        let accounts = self
+            .state_db(num)?
+            .get_accounts(&addresses)
+            .map_err(|e| RpcError::invalid_params(format!("{:?}", e)))?;
+        Ok(accounts
+            .into_iter()
+            .map(|maybe_acc| maybe_acc.map(Into::into))
+            .collect())
+    }
+
+    fn storage_slots(
+        &self, addr: RpcH160, keys: Vec<RpcH256>, num: Option<EpochNumber>,
+    ) -> RpcResult<Vec<RpcH256>> {
+        let address: H160 = addr.into();
+        let keys: Vec<H256> = keys.into_iter().map(Into::into).collect();
+        info!(
+            "RPC Request: cfx_getStorageSlots address={:?} keys={:?} epoch_num={:?}",
+            address, keys, num
+        );
+        let values = self
+            .state_db(num)?
+            .get_storage_values(&address, &keys)
+            .map_err(|e| RpcError::invalid_params(format!("{:?}", e)))?;
+        Ok(values
+            .into_iter()
+            .map(|maybe_value| maybe_value.unwrap_or_default().into())
+            .collect())
+    }
+
+    fn transaction_by_hash(
+        &self, hash: RpcH256,
+    ) -> RpcResult<Option<RpcTransaction>> {
+        let hash: H256 = hash.into();
+        info!("RPC Request: cfx_getTransactionByHash({:?})", hash);
+        let tx = match self.data_man.transaction_by_hash(&hash) {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+        let receipt = self.receipt(&hash);
+        Ok(Some(RpcTransaction::from_signed(&tx, receipt)))
+    }
+
+    fn transaction_receipt(
+        &self, tx_hash: RpcH256,
+    ) -> RpcResult<Option<RpcReceipt>> {
+        let hash: H256 = tx_hash.into();
+        info!("RPC Request: cfx_getTransactionReceipt({:?})", hash);
+        Ok(self.receipt(&hash))
+    }
+
+    fn receipt(&self, tx_hash: &H256) -> Option<RpcReceipt> {
+        let address = self.data_man.transaction_address_by_hash(
+            tx_hash, false, /* update_cache */
+        )?;
+        let block = self.data_man.block_by_hash(
+            &address.block_hash,
+            false, /* update_cache */
+        )?;
+        let tx = block.transactions.get(address.index)?.clone();
+        let result = self.data_man.block_execution_result_by_hash_with_epoch(
+            &address.block_hash,
+            &address.epoch_hash,
+            false, /* update_cache */
+        )?;
+        let receipt = result.receipts.get(address.index)?.clone();
+        let epoch_number = self
+            .data_man
+            .block_header_by_hash(&address.epoch_hash)
+            .map(|header| header.height())
+            .filter(|epoch_number| *epoch_number <= self.latest_epoch);
+
+        let mut rpc_receipt = RpcReceipt::new((*tx).clone(), receipt, address);
+        rpc_receipt.set_epoch_number(epoch_number);
+        if let Some(state_root) =
+            epoch_number.and_then(|epoch_number| self.state_root(epoch_number))
+        {
+            rpc_receipt.set_state_root(state_root.into());
+        }
+        Some(rpc_receipt)
+    }
+}
+
+pub struct CfxHandler {
+    rpc_impl: Arc<RpcImpl>,
+}
+
+impl CfxHandler {
+    pub fn new(rpc_impl: Arc<RpcImpl>) -> Self { CfxHandler { rpc_impl } }
+}
+
+impl Cfx for CfxHandler {
+    delegate! {
+        target self.rpc_impl {
+            fn accounts(&self, addrs: Vec<RpcH160>, num: Option<EpochNumber>) -> RpcResult<Vec<Option<AccountState>>>;
+            fn balance(&self, address: RpcH160, num: Option<EpochNumber>) -> RpcResult<RpcU256>;
+            fn best_block_hash(&self) -> RpcResult<RpcH256>;
+            fn block_by_epoch_number(&self, epoch_num: EpochNumber, include_txs: bool) -> RpcResult<RpcBlock>;
+            fn block_by_hash(&self, hash: RpcH256, include_txs: bool) -> RpcResult<Option<RpcBlock>>;
+            fn blocks_by_epoch(&self, num: EpochNumber) -> RpcResult<Vec<RpcH256>>;
+            fn code(&self, address: RpcH160, num: Option<EpochNumber>) -> RpcResult<Bytes>;
+            fn epoch_number(&self, epoch_num: Option<EpochNumber>) -> RpcResult<RpcU256>;
+            fn storage_slots(&self, addr: RpcH160, keys: Vec<RpcH256>, num: Option<EpochNumber>) -> RpcResult<Vec<RpcH256>>;
+            fn transaction_by_hash(&self, hash: RpcH256) -> RpcResult<Option<RpcTransaction>>;
+            fn transaction_count(&self, address: RpcH160, num: Option<EpochNumber>) -> RpcResult<RpcU256>;
+            fn transaction_receipt(&self, tx_hash: RpcH256) -> RpcResult<Option<RpcReceipt>>;
+        }
+    }
+
+    not_supported! {
+        fn block_by_hash_with_pivot_assumption(&self, block_hash: RpcH256, pivot_hash: RpcH256, epoch_number: RpcU64) -> RpcResult<RpcBlock>;
+        fn call(&self, rpc_tx: RpcTransaction, epoch: Option<EpochNumber>) -> RpcResult<Bytes>;
+        fn decode_raw_transaction(&self, raw: Bytes) -> RpcResult<DecodedTransaction>;
+        fn estimate_gas(&self, rpc_tx: RpcTransaction) -> RpcResult<RpcU256>;
+        fn gas_price(&self) -> RpcResult<RpcU256>;
+        fn get_logs(&self, filter: RpcFilter) -> RpcResult<Vec<RpcLog>>;
+        fn send_raw_transaction(&self, raw: Bytes) -> RpcResult<RpcH256>;
+        fn send_usable_genesis_accounts(&self, account_start_index: usize) -> RpcResult<Bytes>;
+        fn block_template(&self) -> RpcResult<BlockTemplate>;
+        fn submit_block_solution(&self, template_id: RpcU64, nonce: RpcU64) -> RpcResult<RpcH256>;
+        fn open_state_handle(&self, epoch: Option<EpochNumber>, ttl_ms: Option<RpcU64>) -> RpcResult<RpcU64>;
+        fn close_state_handle(&self, handle: RpcU64) -> RpcResult<bool>;
+        fn balance_with_state_handle(&self, handle: RpcU64, address: RpcH160) -> RpcResult<RpcU256>;
+        fn code_with_state_handle(&self, handle: RpcU64, address: RpcH160) -> RpcResult<Bytes>;
+        fn call_with_state_handle(&self, handle: RpcU64, rpc_tx: RpcTransaction) -> RpcResult<Bytes>;
+    }
+}
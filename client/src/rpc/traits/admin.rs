@@ -0,0 +1,98 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::super::types::{
+    EpochNumber, Peer, StateVerification, TxPoolAdmission,
+};
+use crate::config_reload::ReloadReport;
+use jsonrpc_core::Result as RpcResult;
+use jsonrpc_derive::rpc;
+use network::{node_table::NodeId, Ban, BanTarget};
+use std::net::{IpAddr, SocketAddr};
+
+/// Node management APIs for operators, which are only served on the local
+/// debug RPC server.
+#[rpc]
+pub trait AdminRpc {
+    /// Returns the connected peers with their capabilities and traffic.
+    #[rpc(name = "admin_peers")]
+    fn peers(&self) -> RpcResult<Vec<Peer>>;
+
+    /// Adds a node to the trusted node table, which is connected
+    /// preferentially.
+    #[rpc(name = "admin_addTrustedPeer")]
+    fn add_trusted_peer(
+        &self, node_id: NodeId, address: SocketAddr,
+    ) -> RpcResult<()>;
+
+    /// Removes a node from the node tables.
+    #[rpc(name = "admin_removeTrustedPeer")]
+    fn remove_trusted_peer(&self, node_id: NodeId) -> RpcResult<()>;
+
+    /// Blacklists a node and disconnects it. Returns false if the node is
+    /// unknown.
+    #[rpc(name = "admin_banNode")]
+    fn ban_node(&self, node_id: NodeId) -> RpcResult<bool>;
+
+    /// Refuses the connections from an IP address permanently, and returns
+    /// the number of disconnected sessions.
+    #[rpc(name = "admin_banIp")]
+    fn ban_ip(&self, ip: IpAddr) -> RpcResult<usize>;
+
+    /// Lifts the ban of an IP address. Returns false if it is not banned.
+    #[rpc(name = "admin_unbanIp")]
+    fn unban_ip(&self, ip: IpAddr) -> RpcResult<bool>;
+
+    #[rpc(name = "admin_bannedIps")]
+    fn banned_ips(&self) -> RpcResult<Vec<IpAddr>>;
+
+    /// Refuses the connections with a node id or an IP address for
+    /// `duration` seconds, or permanently if not given, and returns the
+    /// number of disconnected sessions. The ban survives restarts.
+    #[rpc(name = "admin_addBan")]
+    fn add_ban(
+        &self, target: BanTarget, reason: Option<String>, duration: Option<u64>,
+    ) -> RpcResult<usize>;
+
+    /// Lifts the ban of a node id or an IP address. Returns false if it is not
+    /// banned.
+    #[rpc(name = "admin_removeBan")]
+    fn remove_ban(&self, target: BanTarget) -> RpcResult<bool>;
+
+    /// Returns the bans not expired yet.
+    #[rpc(name = "admin_listBans")]
+    fn list_bans(&self) -> RpcResult<Vec<Ban>>;
+
+    #[rpc(name = "admin_syncPhase")]
+    fn sync_phase(&self) -> RpcResult<String>;
+
+    /// Evicts the least recently used entries from the block and
+    /// transaction caches until they fit in the configured cache size, and
+    /// returns the size of the block caches in bytes afterwards.
+    #[rpc(name = "admin_gcCaches")]
+    fn gc_caches(&self) -> RpcResult<usize>;
+
+    /// Executes an epoch again on the state of its parent in the local storage
+    /// and compares the recomputed state root with the committed one.
+    #[rpc(name = "admin_verifyState")]
+    fn verify_state(&self, epoch: EpochNumber) -> RpcResult<StateVerification>;
+
+    /// Reads the configuration file again and applies the changed parameters
+    /// that can be changed at runtime, e.g. cache budgets, transaction pool
+    /// limits, the log level, the gas price oracle and throttling limits.
+    /// Returns the applied parameters and the ones requiring a restart.
+    #[rpc(name = "admin_reloadConfig")]
+    fn reload_config(&self) -> RpcResult<ReloadReport>;
+
+    /// Returns the rules to admit the transactions into the transaction pool.
+    #[rpc(name = "admin_txPoolAdmission")]
+    fn txpool_admission(&self) -> RpcResult<TxPoolAdmission>;
+
+    /// Replaces the rules to admit the transactions into the transaction
+    /// pool, which are kept after a restart. The transactions already in the
+    /// pool are kept.
+    #[rpc(name = "admin_setTxPoolAdmission")]
+    fn set_txpool_admission(&self, admission: TxPoolAdmission)
+        -> RpcResult<()>;
+}
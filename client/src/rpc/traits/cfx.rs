@@ -3,9 +3,10 @@
 // See http://www.gnu.org/licenses/
 
 use super::super::types::{
-    Block, Bytes, EpochNumber, Filter as RpcFilter, Log as RpcLog,
-    Receipt as RpcReceipt, Transaction, Transaction as RpcTransaction,
-    H160 as RpcH160, H256 as RpcH256, U256 as RpcU256, U64 as RpcU64,
+    AccountState, Block, BlockTemplate, Bytes, DecodedTransaction, EpochNumber,
+    Filter as RpcFilter, Log as RpcLog, Receipt as RpcReceipt, Transaction,
+    Transaction as RpcTransaction, H160 as RpcH160, H256 as RpcH256,
+    U256 as RpcU256, U64 as RpcU64,
 };
 use jsonrpc_core::Result as RpcResult;
 use jsonrpc_derive::rpc;
@@ -55,6 +56,21 @@ pub trait Cfx {
         &self, addr: RpcH160, epoch_number: Option<EpochNumber>,
     ) -> RpcResult<Bytes>;
 
+    /// Returns the states of the given accounts at given time (epoch number),
+    /// or null for the accounts that don't exist.
+    #[rpc(name = "cfx_getAccounts")]
+    fn accounts(
+        &self, addrs: Vec<RpcH160>, epoch_number: Option<EpochNumber>,
+    ) -> RpcResult<Vec<Option<AccountState>>>;
+
+    /// Returns the values of the given storage slots of an account at given
+    /// time (epoch number).
+    #[rpc(name = "cfx_getStorageSlots")]
+    fn storage_slots(
+        &self, addr: RpcH160, keys: Vec<RpcH256>,
+        epoch_number: Option<EpochNumber>,
+    ) -> RpcResult<Vec<RpcH256>>;
+
     //        /// Returns content of the storage at given address.
     //        #[rpc(name = "cfx_getStorageAt")]
     //        fn storage_at(&self, RpcH160, RpcU256, Option<BlockNumber>) ->
@@ -113,6 +129,53 @@ pub trait Cfx {
     #[rpc(name = "cfx_sendRawTransaction")]
     fn send_raw_transaction(&self, raw_tx: Bytes) -> RpcResult<RpcH256>;
 
+    /// Decodes a signed transaction and checks it without sending it.
+    #[rpc(name = "cfx_decodeRawTransaction")]
+    fn decode_raw_transaction(
+        &self, raw_tx: Bytes,
+    ) -> RpcResult<DecodedTransaction>;
+
+    /// Pins the state of an executed epoch for at most `ttl_ms` milliseconds,
+    /// returning a handle to read exactly this state with across calls.
+    #[rpc(name = "cfx_openStateHandle")]
+    fn open_state_handle(
+        &self, epoch_number: Option<EpochNumber>, ttl_ms: Option<RpcU64>,
+    ) -> RpcResult<RpcU64>;
+
+    /// Releases a state handle, returning whether it was open.
+    #[rpc(name = "cfx_closeStateHandle")]
+    fn close_state_handle(&self, handle: RpcU64) -> RpcResult<bool>;
+
+    /// Returns balance of the given account in the state of a state handle.
+    #[rpc(name = "cfx_getBalanceWithStateHandle")]
+    fn balance_with_state_handle(
+        &self, handle: RpcU64, addr: RpcH160,
+    ) -> RpcResult<RpcU256>;
+
+    /// Returns the code of the given contract in the state of a state handle.
+    #[rpc(name = "cfx_getCodeWithStateHandle")]
+    fn code_with_state_handle(
+        &self, handle: RpcU64, addr: RpcH160,
+    ) -> RpcResult<Bytes>;
+
+    /// Call contract in the state of a state handle, returning the output
+    /// data.
+    #[rpc(name = "cfx_callWithStateHandle")]
+    fn call_with_state_handle(
+        &self, handle: RpcU64, tx: RpcTransaction,
+    ) -> RpcResult<Bytes>;
+
+    /// Returns the latest block template to be solved by external miners.
+    #[rpc(name = "cfx_getBlockTemplate")]
+    fn block_template(&self) -> RpcResult<BlockTemplate>;
+
+    /// Submits the nonce that solves a block template, returning the hash of
+    /// the mined block.
+    #[rpc(name = "cfx_submitBlockSolution")]
+    fn submit_block_solution(
+        &self, template_id: RpcU64, nonce: RpcU64,
+    ) -> RpcResult<RpcH256>;
+
     #[rpc(name = "cfx_sendUsableGenesisAccounts")]
     fn send_usable_genesis_accounts(
         &self, account_start_index: usize,
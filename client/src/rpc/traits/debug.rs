@@ -2,7 +2,12 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
-use super::super::types::{Transaction as RpcTransaction, H256 as RpcH256};
+use super::super::types::{
+    ChainHealth, DifficultyPeriod, EpochNumber, ExcludedTerminal,
+    LockContention, MisbehaviorReport, SkippedTransactions,
+    Transaction as RpcTransaction, H160 as RpcH160, H256 as RpcH256,
+    U256 as RpcU256, U64 as RpcU64,
+};
 use jsonrpc_core::Result as RpcResult;
 use jsonrpc_derive::rpc;
 use network::{
@@ -16,6 +21,9 @@ pub trait DebugRpc {
     #[rpc(name = "txpool_status")]
     fn txpool_status(&self) -> RpcResult<BTreeMap<String, usize>>;
 
+    #[rpc(name = "txpool_memoryStats")]
+    fn txpool_memory_stats(&self) -> RpcResult<BTreeMap<String, usize>>;
+
     #[rpc(name = "tx_inspect")]
     fn tx_inspect(&self, hash: RpcH256) -> RpcResult<BTreeMap<String, String>>;
 
@@ -36,6 +44,41 @@ pub trait DebugRpc {
         >,
     >;
 
+    #[rpc(name = "txpool_accountQueues")]
+    fn txpool_account_queues(
+        &self, address: RpcH160,
+    ) -> RpcResult<BTreeMap<String, Vec<RpcTransaction>>>;
+
+    #[rpc(name = "txpool_localTransactions")]
+    fn txpool_local_transactions(&self) -> RpcResult<Vec<RpcTransaction>>;
+
+    #[rpc(name = "txpool_markLocal")]
+    fn txpool_mark_local(&self, hash: RpcH256) -> RpcResult<bool>;
+
+    #[rpc(name = "debug_misbehaviorReport")]
+    fn misbehavior_report(
+        &self, era_genesis_height: Option<u64>,
+    ) -> RpcResult<Vec<MisbehaviorReport>>;
+
+    /// Returns the DAG health stats of the recent blocks, e.g. the anticone
+    /// sizes, the fork rate and the pivot chain switch rate.
+    #[rpc(name = "debug_chainHealth")]
+    fn chain_health(&self) -> RpcResult<ChainHealth>;
+
+    /// Returns the time waiting for and holding the consensus inner lock at
+    /// the `limit` (all by default) most contended call sites. It's empty
+    /// unless `profile_consensus_lock` is enabled.
+    #[rpc(name = "debug_consensusLockContention")]
+    fn consensus_lock_contention(
+        &self, limit: Option<usize>,
+    ) -> RpcResult<Vec<LockContention>>;
+
+    /// Returns the terminal blocks which are not referenced by new blocks
+    /// because there are more than `REFEREE_BOUND` terminals, and why each
+    /// of them is excluded.
+    #[rpc(name = "debug_excludedTerminals")]
+    fn excluded_terminals(&self) -> RpcResult<Vec<ExcludedTerminal>>;
+
     #[rpc(name = "clear_tx_pool")]
     fn clear_tx_pool(&self) -> RpcResult<()>;
 
@@ -58,6 +101,66 @@ pub trait DebugRpc {
     #[rpc(name = "net_high_priority_packets")]
     fn net_high_priority_packets(&self) -> RpcResult<usize>;
 
+    /// Returns a snapshot of the internal state exposed for the test
+    /// framework, covering the consensus graph, the synchronization graph,
+    /// the transaction pool and the execution queue.
+    #[rpc(name = "debug_stateSnapshot")]
+    fn state_snapshot(&self) -> RpcResult<serde_json::Value>;
+
+    /// Changes the log level of a module path, e.g. "cfxcore::sync", until
+    /// the node restarts.
+    #[rpc(name = "debug_setLogLevel")]
+    fn set_log_level(&self, module: String, level: String) -> RpcResult<()>;
+
+    /// Reverts the log level of the given module, or all modules if not
+    /// specified, to the startup configuration.
+    #[rpc(name = "debug_resetLogLevel")]
+    fn reset_log_level(&self, module: Option<String>) -> RpcResult<()>;
+
+    /// Returns the log levels changed at runtime, keyed by module path.
+    #[rpc(name = "debug_logLevels")]
+    fn log_levels(&self) -> RpcResult<BTreeMap<String, String>>;
+
     #[rpc(name = "current_sync_phase")]
     fn current_sync_phase(&self) -> RpcResult<String>;
+
+    #[rpc(name = "stratum_shares")]
+    fn stratum_shares(
+        &self,
+    ) -> RpcResult<BTreeMap<String, BTreeMap<String, u64>>>;
+
+    /// Returns the statistics of the latest `count` difficulty adjustment
+    /// periods.
+    #[rpc(name = "pow_difficultyHistory")]
+    fn pow_difficulty_history(
+        &self, count: Option<usize>,
+    ) -> RpcResult<Vec<DifficultyPeriod>>;
+
+    /// Simulates the difficulty adjustment on a pivot chain with the given
+    /// block timestamps under the current PoW config.
+    #[rpc(name = "pow_simulateDifficulties")]
+    fn pow_simulate_difficulties(
+        &self, timestamps: Vec<RpcU64>,
+    ) -> RpcResult<Vec<RpcU256>>;
+
+    /// Dumps the recent TreeGraph into `path` as "dot" (default) or "json",
+    /// and returns the number of exported blocks.
+    #[rpc(name = "debug_exportTreeGraph")]
+    fn export_tree_graph(
+        &self, path: String, format: Option<String>, window: Option<u64>,
+    ) -> RpcResult<usize>;
+
+    /// Re-executes the given epoch and dumps its receipts, touched accounts
+    /// and the executor config into the invalid state root dump directory,
+    /// and returns the path of the written bundle.
+    #[rpc(name = "debug_dumpEpochDiagnostics")]
+    fn dump_epoch_diagnostics(&self, epoch: EpochNumber) -> RpcResult<String>;
+
+    /// Returns the number of transactions skipped without bumping the sender
+    /// nonce in the given epoch, grouped by reason, or null if the epoch is
+    /// not executed recently by this node.
+    #[rpc(name = "debug_skippedTransactions")]
+    fn skipped_transactions(
+        &self, epoch: EpochNumber,
+    ) -> RpcResult<Option<SkippedTransactions>>;
 }
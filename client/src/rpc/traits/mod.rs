@@ -2,12 +2,16 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
+pub mod admin;
 pub mod cfx;
 pub mod debug;
+pub mod personal;
 pub mod pubsub;
 pub mod test;
 
+pub use admin::AdminRpc;
 pub use cfx::Cfx;
 pub use debug::DebugRpc;
+pub use personal::PersonalRpc;
 pub use pubsub::PubSub;
 pub use test::TestRpc;
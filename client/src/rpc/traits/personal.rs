@@ -0,0 +1,46 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::super::types::{SendTxRequest, H160 as RpcH160, H256 as RpcH256};
+use jsonrpc_core::Result as RpcResult;
+use jsonrpc_derive::rpc;
+
+/// Management of the accounts in the local keystore, which is only served on
+/// the local debug RPC server when the account service is enabled.
+#[rpc]
+pub trait PersonalRpc {
+    /// Returns the accounts in the keystore.
+    #[rpc(name = "personal_listAccounts")]
+    fn list_accounts(&self) -> RpcResult<Vec<RpcH160>>;
+
+    /// Creates a new account encrypted with the password, and returns its
+    /// address.
+    #[rpc(name = "personal_newAccount")]
+    fn new_account(&self, password: String) -> RpcResult<RpcH160>;
+
+    /// Imports an account from its raw secret key, encrypted with the
+    /// password, and returns its address.
+    #[rpc(name = "personal_importRawKey")]
+    fn import_raw_key(
+        &self, secret: RpcH256, password: String,
+    ) -> RpcResult<RpcH160>;
+
+    /// Unlocks an account for `duration` seconds, 300 by default, so that
+    /// `cfx_sendTransaction` can sign with it without the password.
+    #[rpc(name = "personal_unlockAccount")]
+    fn unlock_account(
+        &self, address: RpcH160, password: String, duration: Option<u64>,
+    ) -> RpcResult<bool>;
+
+    /// Signs a transaction with the account unlocked by the password and
+    /// sends it, without keeping the account unlocked.
+    #[rpc(name = "personal_sendTransaction")]
+    fn send_transaction_with_password(
+        &self, request: SendTxRequest, password: String,
+    ) -> RpcResult<RpcH256>;
+
+    /// Signs a transaction with an unlocked account and sends it.
+    #[rpc(name = "cfx_sendTransaction")]
+    fn send_transaction(&self, request: SendTxRequest) -> RpcResult<RpcH256>;
+}
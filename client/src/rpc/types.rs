@@ -5,33 +5,57 @@
 mod account;
 mod blame_info;
 mod block;
+mod block_template;
 mod bytes;
+mod chain_health;
+mod decoded_transaction;
+mod difficulty_period;
 mod epoch_number;
+mod excluded_terminal;
 mod filter;
 mod hash;
 mod index;
+mod lock_contention;
 mod log;
+mod misbehavior_report;
+mod peer;
 mod provenance;
 mod receipt;
+mod send_tx_request;
+mod skipped_transactions;
+mod state_verification;
 mod status;
 mod transaction;
+mod txpool_admission;
 mod uint;
 
 pub mod pubsub;
 
 pub use self::{
-    account::Account,
+    account::{Account, AccountState},
     blame_info::BlameInfo,
     block::{Block, BlockTransactions, Header},
+    block_template::BlockTemplate,
     bytes::Bytes,
+    chain_health::ChainHealth,
+    decoded_transaction::DecodedTransaction,
+    difficulty_period::DifficultyPeriod,
     epoch_number::EpochNumber,
+    excluded_terminal::ExcludedTerminal,
     filter::Filter,
     hash::{H160, H2048, H256, H512, H64},
     index::Index,
+    lock_contention::LockContention,
     log::Log,
+    misbehavior_report::MisbehaviorReport,
+    peer::Peer,
     provenance::Origin,
     receipt::Receipt,
+    send_tx_request::SendTxRequest,
+    skipped_transactions::SkippedTransactions,
+    state_verification::StateVerification,
     status::Status,
     transaction::Transaction,
+    txpool_admission::TxPoolAdmission,
     uint::{U128, U256, U64},
 };
@@ -2,7 +2,8 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
-use crate::rpc::types::{BlockTransactions, U256};
+use crate::rpc::types::{BlockTransactions, H256, U256};
+use primitives::Account as PrimitiveAccount;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -10,3 +11,26 @@ pub struct Account {
     pub balance: U256,
     pub transactions: BlockTransactions,
 }
+
+/// The state of an account in an epoch.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountState {
+    pub balance: U256,
+    pub nonce: U256,
+    pub code_hash: H256,
+    /// Whether this state was fetched from an archive node.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+}
+
+impl From<PrimitiveAccount> for AccountState {
+    fn from(account: PrimitiveAccount) -> Self {
+        AccountState {
+            balance: account.balance.into(),
+            nonce: account.nonce.into(),
+            code_hash: account.code_hash.into(),
+            proxied: None,
+        }
+    }
+}
@@ -3,16 +3,18 @@
 // See http://www.gnu.org/licenses/
 
 use crate::rpc::types::{Receipt, Transaction, H160, H256, U256};
+use cfx_types::H256 as CfxH256;
 use cfxcore::consensus::ConsensusGraphInner;
 use jsonrpc_core::Error as RpcError;
 use primitives::{
     receipt::{
+        Receipt as PrimitiveReceipt,
         TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING,
         TRANSACTION_OUTCOME_EXCEPTION_WITH_NONCE_BUMPING,
         TRANSACTION_OUTCOME_SUCCESS,
     },
-    Block as PrimitiveBlock, BlockHeaderBuilder, StateRootWithAuxInfo,
-    TransactionAddress,
+    Block as PrimitiveBlock, BlockHeader as PrimitiveBlockHeader,
+    BlockHeaderBuilder, StateRootWithAuxInfo, TransactionAddress,
 };
 use serde::{
     de::{Deserialize, Deserializer, Error, Unexpected},
@@ -129,56 +131,114 @@ impl Block {
         include_txs: bool,
     ) -> Self
     {
+        let hash = b.hash();
         let transactions = match include_txs {
-            false => BlockTransactions::Hashes(
-                b.transactions
-                    .iter()
-                    .map(|x| H256::from(x.hash()))
-                    .collect(),
-            ),
+            false => Self::transaction_hashes(b),
             true => {
-                let tx_vec = match consensus_inner
-                    .block_receipts_by_hash(&b.hash(), false /* update_cache */)
-                {
-                    Some(receipts) => b
-                        .transactions
-                        .iter()
-                        .enumerate()
-                        .map(|(idx, tx)| {
-                            let receipt = receipts.get(idx).unwrap();
-                            match receipt.outcome_status {
-                                TRANSACTION_OUTCOME_SUCCESS
-                                | TRANSACTION_OUTCOME_EXCEPTION_WITH_NONCE_BUMPING => {
-                                    Transaction::from_signed(
-                                        tx,
-                                        Some(Receipt::new(
-                                            (**tx).clone(),
-                                            receipt.clone(),
-                                            TransactionAddress {
-                                                block_hash: b.hash(),
-                                                index: idx,
-                                            },
-                                        )),
-                                    )
-                                }
-                                TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING => {
-                                    Transaction::from_signed(tx, None)
-                                }
-                                _ => {
-                                    unreachable!();
-                                }
-                            }
-                        })
-                        .collect(),
-                    None => b
-                        .transactions
-                        .iter()
-                        .map(|x| Transaction::from_signed(x, None))
-                        .collect(),
-                };
-                BlockTransactions::Full(tx_vec)
+                let receipts = consensus_inner.block_receipts_by_hash(
+                    &hash, false, /* update_cache */
+                );
+                Self::executed_transactions(
+                    b,
+                    consensus_inner
+                        .get_epoch_hash_for_block(&hash)
+                        .unwrap_or_default(),
+                    receipts.as_ref().map(|receipts| receipts.as_slice()),
+                )
             }
         };
+        Self::from_parts(
+            b,
+            consensus_inner.get_block_epoch_number(&hash),
+            consensus_inner.is_stable(&hash),
+            transactions,
+        )
+    }
+
+    /// Build a block without the consensus graph, from the epoch and the
+    /// receipts of the block recorded in the database. Whether the block is
+    /// stable is unknown.
+    pub fn new_with_receipts(
+        b: &PrimitiveBlock, epoch: Option<(u64, CfxH256)>,
+        receipts: Option<&[PrimitiveReceipt]>, include_txs: bool,
+    ) -> Self
+    {
+        let transactions = match include_txs {
+            false => Self::transaction_hashes(b),
+            true => Self::executed_transactions(
+                b,
+                epoch.map(|(_, epoch_hash)| epoch_hash).unwrap_or_default(),
+                receipts,
+            ),
+        };
+        Self::from_parts(
+            b,
+            epoch.map(|(epoch_number, _)| epoch_number),
+            None,
+            transactions,
+        )
+    }
+
+    fn transaction_hashes(b: &PrimitiveBlock) -> BlockTransactions {
+        BlockTransactions::Hashes(
+            b.transactions
+                .iter()
+                .map(|x| H256::from(x.hash()))
+                .collect(),
+        )
+    }
+
+    fn executed_transactions(
+        b: &PrimitiveBlock, epoch_hash: CfxH256,
+        receipts: Option<&[PrimitiveReceipt]>,
+    ) -> BlockTransactions
+    {
+        let tx_vec = match receipts {
+            Some(receipts) => b
+                .transactions
+                .iter()
+                .enumerate()
+                .map(|(idx, tx)| {
+                    let receipt = receipts.get(idx).unwrap();
+                    match receipt.outcome_status {
+                        TRANSACTION_OUTCOME_SUCCESS
+                        | TRANSACTION_OUTCOME_EXCEPTION_WITH_NONCE_BUMPING => {
+                            Transaction::from_signed(
+                                tx,
+                                Some(Receipt::new(
+                                    (**tx).clone(),
+                                    receipt.clone(),
+                                    TransactionAddress {
+                                        block_hash: b.hash(),
+                                        index: idx,
+                                        epoch_hash,
+                                    },
+                                )),
+                            )
+                        }
+                        TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING => {
+                            Transaction::from_signed(tx, None)
+                        }
+                        _ => {
+                            unreachable!();
+                        }
+                    }
+                })
+                .collect(),
+            None => b
+                .transactions
+                .iter()
+                .map(|x| Transaction::from_signed(x, None))
+                .collect(),
+        };
+        BlockTransactions::Full(tx_vec)
+    }
+
+    fn from_parts(
+        b: &PrimitiveBlock, epoch_number: Option<u64>, stable: Option<bool>,
+        transactions: BlockTransactions,
+    ) -> Self
+    {
         Block {
             hash: H256::from(b.block_header.hash().clone()),
             parent_hash: H256::from(b.block_header.parent_hash().clone()),
@@ -202,15 +262,13 @@ impl Block {
                 b.block_header.transactions_root().clone(),
             ),
             // PrimitiveBlock does not contain this information
-            epoch_number: consensus_inner
-                .get_block_epoch_number(&b.block_header.hash())
-                .map(Into::into),
+            epoch_number: epoch_number.map(Into::into),
             // fee system
             gas_limit: b.block_header.gas_limit().into(),
             timestamp: b.block_header.timestamp().into(),
             difficulty: b.block_header.difficulty().clone().into(),
             // PrimitiveBlock does not contain this information
-            stable: consensus_inner.is_stable(&b.block_header.hash()),
+            stable,
             adaptive: b.block_header.adaptive(),
             referee_hashes: b
                 .block_header
@@ -320,6 +378,47 @@ pub struct Header {
     pub size: Option<U256>,
 }
 
+impl Header {
+    pub fn new(
+        h: &PrimitiveBlockHeader, consensus_inner: &ConsensusGraphInner,
+    ) -> Self {
+        Header {
+            hash: Some(H256::from(h.hash())),
+            parent_hash: H256::from(h.parent_hash().clone()),
+            height: h.height().into(),
+            miner: H160::from(h.author().clone()),
+            deferred_state_root: H256::from(h.deferred_state_root().clone()),
+            deferred_state_root_with_aux: h
+                .deferred_state_root_with_aux_info()
+                .clone(),
+            deferred_receipts_root: H256::from(
+                h.deferred_receipts_root().clone(),
+            ),
+            deferred_logs_bloom_hash: H256::from(
+                h.deferred_logs_bloom_hash().clone(),
+            ),
+            blame: h.blame(),
+            transactions_root: H256::from(h.transactions_root().clone()),
+            epoch_number: consensus_inner
+                .get_block_epoch_number(&h.hash())
+                .map(Into::into),
+            gas_limit: h.gas_limit().into(),
+            timestamp: h.timestamp().into(),
+            difficulty: h.difficulty().clone().into(),
+            referee_hashes: h
+                .referee_hashes()
+                .iter()
+                .map(|x| H256::from(*x))
+                .collect(),
+            stable: consensus_inner.is_stable(&h.hash()),
+            adaptive: h.adaptive(),
+            nonce: h.nonce().into(),
+            // The header alone doesn't tell the size of the block.
+            size: None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Block, BlockTransactions};
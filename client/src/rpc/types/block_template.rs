@@ -0,0 +1,86 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::rpc::types::{H160, H256, U256, U64};
+use blockgen::BlockTemplate as CoreBlockTemplate;
+use serde_derive::Serialize;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockTemplate {
+    /// Id to submit the solution of the template with
+    pub template_id: U64,
+    /// Hash of the parent
+    pub parent_hash: H256,
+    /// Distance to genesis
+    pub height: U64,
+    /// Timestamp
+    pub timestamp: U64,
+    /// Miner's address
+    pub miner: H160,
+    /// Referee hashes
+    pub referee_hashes: Vec<H256>,
+    /// Blame of the block
+    pub blame: u32,
+    /// State root hash
+    pub deferred_state_root: H256,
+    /// Root hash of all receipts in this block's epoch
+    pub deferred_receipts_root: H256,
+    /// Hash of aggregrated bloom filter of all receipts in the block's epoch
+    pub deferred_logs_bloom_hash: H256,
+    /// Transactions root hash
+    pub transactions_root: H256,
+    /// Gas Limit
+    pub gas_limit: U256,
+    /// Adaptive
+    pub adaptive: bool,
+    /// Difficulty
+    pub difficulty: U256,
+    /// Hash of the block header without nonce to be solved
+    pub problem_hash: H256,
+    /// The PoW hash of a valid nonce should be lower than the boundary
+    pub boundary: H256,
+    /// Hashes of the transactions packed in the block
+    pub transactions: Vec<H256>,
+}
+
+impl From<CoreBlockTemplate> for BlockTemplate {
+    fn from(template: CoreBlockTemplate) -> Self {
+        let header = &template.block_header;
+        BlockTemplate {
+            template_id: template.template_id.into(),
+            parent_hash: header.parent_hash().clone().into(),
+            height: header.height().into(),
+            timestamp: header.timestamp().into(),
+            miner: header.author().clone().into(),
+            referee_hashes: header
+                .referee_hashes()
+                .iter()
+                .cloned()
+                .map(Into::into)
+                .collect(),
+            blame: header.blame(),
+            deferred_state_root: header.deferred_state_root().clone().into(),
+            deferred_receipts_root: header
+                .deferred_receipts_root()
+                .clone()
+                .into(),
+            deferred_logs_bloom_hash: header
+                .deferred_logs_bloom_hash()
+                .clone()
+                .into(),
+            transactions_root: header.transactions_root().clone().into(),
+            gas_limit: header.gas_limit().clone().into(),
+            adaptive: header.adaptive(),
+            difficulty: header.difficulty().clone().into(),
+            problem_hash: template.problem_hash.into(),
+            boundary: template.boundary.into(),
+            transactions: template
+                .transaction_hashes
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use cfxcore::consensus::ChainHealth as CoreChainHealth;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainHealth {
+    /// Number of recent blocks the stats are aggregated over
+    pub num_blocks: usize,
+    /// Number of blocks generated per second according to their timestamps
+    pub block_rate: f64,
+    /// Average anticone size of the blocks
+    pub avg_anticone_size: f64,
+    /// Largest anticone size of the blocks
+    pub max_anticone_size: usize,
+    /// Number of blocks with each number of referees
+    pub referee_count_distribution: BTreeMap<usize, usize>,
+    /// Fraction of the blocks not extending the tip of the pivot chain
+    pub fork_rate: f64,
+    /// Fraction of the blocks switching the pivot chain
+    pub pivot_switch_rate: f64,
+    /// Average number of pivot blocks retracted by a pivot chain switch
+    pub avg_reorg_depth: f64,
+    /// Largest number of pivot blocks retracted by a pivot chain switch
+    pub max_reorg_depth: u64,
+    /// Fraction of the blocks whose weights are adaptive
+    pub adaptive_rate: f64,
+}
+
+impl From<CoreChainHealth> for ChainHealth {
+    fn from(health: CoreChainHealth) -> Self {
+        ChainHealth {
+            num_blocks: health.num_blocks,
+            block_rate: health.block_rate,
+            avg_anticone_size: health.avg_anticone_size,
+            max_anticone_size: health.max_anticone_size,
+            referee_count_distribution: health.referee_count_distribution,
+            fork_rate: health.fork_rate,
+            pivot_switch_rate: health.pivot_switch_rate,
+            avg_reorg_depth: health.avg_reorg_depth,
+            max_reorg_depth: health.max_reorg_depth,
+            adaptive_rate: health.adaptive_rate,
+        }
+    }
+}
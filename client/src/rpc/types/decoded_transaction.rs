@@ -0,0 +1,20 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::rpc::types::{Transaction, U64};
+use serde_derive::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedTransaction {
+    /// The transaction with the sender recovered from the signature
+    pub transaction: Transaction,
+    /// The chain id of the signature, or null if it's signed without one
+    pub chain_id: Option<U64>,
+    /// The size of the raw transaction in bytes
+    pub size: usize,
+    /// Why the transaction pool would reject the transaction, or null if it
+    /// passes the checks not depending on the state
+    pub rejection: Option<String>,
+}
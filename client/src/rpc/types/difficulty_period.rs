@@ -0,0 +1,40 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::rpc::types::{H256, U256, U64};
+use cfxcore::pow::DifficultyPeriod as CoreDifficultyPeriod;
+use serde_derive::Serialize;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DifficultyPeriod {
+    /// Height of the upper boundary block of the period
+    pub height: U64,
+    /// Hash of the upper boundary block of the period
+    pub block_hash: H256,
+    /// Number of blocks in the period
+    pub block_count: U64,
+    /// Timespan of the period in seconds
+    pub timespan: U64,
+    /// Difficulty of the period
+    pub difficulty: U256,
+    /// Difficulty of the next period
+    pub target_difficulty: U256,
+    /// Estimated network hashrate in hashes per second
+    pub estimated_hashrate: U256,
+}
+
+impl From<CoreDifficultyPeriod> for DifficultyPeriod {
+    fn from(period: CoreDifficultyPeriod) -> Self {
+        DifficultyPeriod {
+            height: period.height.into(),
+            block_hash: period.block_hash.into(),
+            block_count: period.block_count.into(),
+            timespan: period.timespan.into(),
+            difficulty: period.difficulty.into(),
+            target_difficulty: period.target_difficulty.into(),
+            estimated_hashrate: period.estimated_hashrate().into(),
+        }
+    }
+}
@@ -0,0 +1,25 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::rpc::types::H256;
+use cfxcore::consensus::ExcludedTerminal as CoreExcludedTerminal;
+use serde_derive::Serialize;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExcludedTerminal {
+    /// Hash of the terminal block
+    pub hash: H256,
+    /// Why the terminal is not referenced by new blocks
+    pub reason: String,
+}
+
+impl From<CoreExcludedTerminal> for ExcludedTerminal {
+    fn from(terminal: CoreExcludedTerminal) -> Self {
+        ExcludedTerminal {
+            hash: terminal.hash.into(),
+            reason: terminal.reason,
+        }
+    }
+}
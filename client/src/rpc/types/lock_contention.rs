@@ -0,0 +1,40 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::rpc::types::U64;
+use cfxcore::consensus::LockSiteReport;
+use serde_derive::Serialize;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LockContention {
+    /// The call site acquiring the lock
+    pub site: String,
+    /// Number of acquisitions
+    pub acquisitions: U64,
+    /// Number of acquisitions which waited for the lock
+    pub contended: U64,
+    /// Total time waiting for the lock in microseconds
+    pub total_wait_us: U64,
+    /// Longest time waiting for the lock in microseconds
+    pub max_wait_us: U64,
+    /// Total time holding the lock in microseconds
+    pub total_hold_us: U64,
+    /// Longest time holding the lock in microseconds
+    pub max_hold_us: U64,
+}
+
+impl From<LockSiteReport> for LockContention {
+    fn from(report: LockSiteReport) -> Self {
+        LockContention {
+            site: report.site.into(),
+            acquisitions: report.acquisitions.into(),
+            contended: report.contended.into(),
+            total_wait_us: (report.total_wait.as_micros() as u64).into(),
+            max_wait_us: (report.max_wait.as_micros() as u64).into(),
+            total_hold_us: (report.total_hold.as_micros() as u64).into(),
+            max_hold_us: (report.max_hold.as_micros() as u64).into(),
+        }
+    }
+}
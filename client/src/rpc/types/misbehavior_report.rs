@@ -0,0 +1,63 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::rpc::types::{H160, H256};
+use cfx_types::Address;
+use cfxcore::consensus::consensus_inner::misbehavior_tracker::{
+    MisbehaviorEvidence as CoreMisbehaviorEvidence, ProducerMisbehavior,
+};
+use serde_derive::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MisbehaviorEvidence {
+    /// The number of misbehaving blocks
+    pub count: u64,
+    /// Hashes of (at most a limited number of) misbehaving blocks
+    pub block_hashes: Vec<H256>,
+}
+
+impl From<CoreMisbehaviorEvidence> for MisbehaviorEvidence {
+    fn from(evidence: CoreMisbehaviorEvidence) -> Self {
+        MisbehaviorEvidence {
+            count: evidence.count,
+            block_hashes: evidence
+                .block_hashes
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MisbehaviorReport {
+    /// Height of the genesis block of the era
+    pub era_genesis_height: u64,
+    /// Author of the misbehaving blocks
+    pub author: H160,
+    /// Blocks that are partially invalid
+    pub partial_invalid: MisbehaviorEvidence,
+    /// Blocks with timestamps ahead of the local clock
+    pub timestamp_deviation: MisbehaviorEvidence,
+    /// Blocks whose states are blamed by descendants
+    pub blamed: MisbehaviorEvidence,
+}
+
+impl MisbehaviorReport {
+    pub fn new(
+        era_genesis_height: u64, author: Address,
+        misbehavior: ProducerMisbehavior,
+    ) -> Self
+    {
+        MisbehaviorReport {
+            era_genesis_height,
+            author: author.into(),
+            partial_invalid: misbehavior.partial_invalid.into(),
+            timestamp_deviation: misbehavior.timestamp_deviation.into(),
+            blamed: misbehavior.blamed.into(),
+        }
+    }
+}
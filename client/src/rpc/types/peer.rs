@@ -0,0 +1,16 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use network::SessionDetails;
+use serde_derive::Serialize;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Peer {
+    /// Whether the peer is in the trusted node table
+    pub trusted: bool,
+    /// The session with the peer, including its capabilities and traffic
+    #[serde(flatten)]
+    pub session: SessionDetails,
+}
@@ -20,13 +20,13 @@
 
 //! Pub-Sub types.
 
-use super::{Filter, Header, Log, H256};
+use super::{BlockTemplate, Filter, Header, Log, H256, U64};
 use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::{from_value, Value};
 
 /// Subscription result.
 #[allow(dead_code)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Result {
     /// New block header.
     Header(Header),
@@ -34,6 +34,12 @@ pub enum Result {
     Log(Log),
     /// Transaction hash
     TransactionHash(H256),
+    /// Transaction confirmation
+    Confirmation(Confirmation),
+    /// New block template
+    BlockTemplate(BlockTemplate),
+    /// New pivot chain epoch
+    Epoch(Epoch),
 }
 
 impl Serialize for Result {
@@ -45,6 +51,9 @@ impl Serialize for Result {
             Result::Header(ref header) => header.serialize(serializer),
             Result::Log(ref log) => log.serialize(serializer),
             Result::TransactionHash(ref hash) => hash.serialize(serializer),
+            Result::Confirmation(ref c) => c.serialize(serializer),
+            Result::BlockTemplate(ref t) => t.serialize(serializer),
+            Result::Epoch(ref e) => e.serialize(serializer),
         }
     }
 }
@@ -62,15 +71,64 @@ pub enum Kind {
     NewPendingTransactions,
     /// Node syncing status subscription.
     Syncing,
+    /// Single notification once a transaction is confirmed.
+    TransactionConfirmation,
+    /// New block templates for external block producers.
+    BlockTemplates,
+    /// New epochs on the pivot chain.
+    Epochs,
+}
+
+/// A new epoch on the pivot chain.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Epoch {
+    /// Number of the epoch.
+    pub epoch_number: U64,
+    /// Hashes of the blocks in the epoch in execution order, where the last
+    /// one is the pivot block.
+    pub epoch_hashes_ordered: Vec<H256>,
+}
+
+/// Confirmation policy of a transaction confirmation subscription.
+/// The subscriber is notified once all the specified conditions are met.
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmationPolicy {
+    /// Hash of the transaction to watch.
+    pub transaction_hash: H256,
+    /// Number of epochs that have to be built on top of the transaction.
+    pub epochs: Option<U64>,
+    /// Confirmation risk the transaction's block has to go below.
+    pub risk: Option<f64>,
+}
+
+/// Transaction confirmation event.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Confirmation {
+    /// Hash of the confirmed transaction.
+    pub transaction_hash: H256,
+    /// Hash of the block containing the transaction.
+    pub block_hash: H256,
+    /// Epoch number of the block containing the transaction.
+    pub epoch_number: U64,
+    /// Number of epochs built on top of the transaction.
+    pub confirmed_epochs: U64,
+    /// Confirmation risk of the block containing the transaction.
+    pub risk: Option<f64>,
 }
 
 /// Subscription kind.
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Params {
     /// No parameters passed.
     None,
     /// Log parameters.
     Logs(Filter),
+    /// Transaction confirmation parameters.
+    Confirmation(ConfirmationPolicy),
 }
 
 impl Default for Params {
@@ -88,6 +146,11 @@ impl<'a> Deserialize<'a> for Params {
             return Ok(Params::None);
         }
 
+        // `Filter` accepts any object, so try the stricter policy first
+        if let Ok(policy) = from_value(v.clone()) {
+            return Ok(Params::Confirmation(policy));
+        }
+
         from_value(v.clone()).map(Params::Logs).map_err(|e| {
             D::Error::custom(format!("Invalid Pub-Sub parameters: {}", e))
         })
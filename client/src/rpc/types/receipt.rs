@@ -2,11 +2,12 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
-use crate::rpc::types::{Log, H256, U256};
+use crate::rpc::types::{Bytes, Log, H256, U256};
 use cfx_types::{Address, Bloom};
 use cfxcore::{executive::contract_address, vm::CreateContractAddress};
 use primitives::{
-    receipt::Receipt as PrimitiveReceipt, transaction::Action,
+    receipt::{Receipt as PrimitiveReceipt, TRANSACTION_OUTCOME_SUCCESS},
+    transaction::Action,
     SignedTransaction as PrimitiveTransaction, TransactionAddress,
 };
 use serde_derive::Serialize;
@@ -29,6 +30,14 @@ pub struct Receipt {
     pub to: Option<Address>,
     /// The total gas used in the block following execution of the transaction.
     pub gas_used: U256,
+    /// The gas of this transaction charged from the sender, null if the
+    /// transaction is executed before the execution details are stored.
+    #[serde(default)]
+    pub gas_charged: Option<U256>,
+    /// The gas refunded after the execution of this transaction, null if the
+    /// transaction is executed before the execution details are stored.
+    #[serde(default)]
+    pub gas_refunded: Option<U256>,
     /// Address of contracts created during execution of transaction.
     pub contract_created: Option<Address>,
     /// Array of log objects, which this transaction generated.
@@ -39,6 +48,13 @@ pub struct Receipt {
     pub state_root: H256,
     /// Transaction outcome.
     pub outcome_status: u8,
+    /// The output of the transaction if it is reverted, null if the
+    /// transaction is executed before the execution details are stored.
+    #[serde(default)]
+    pub revert_reason: Option<Bytes>,
+    /// Whether this receipt was fetched from an archive node.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
 }
 
 impl Receipt {
@@ -47,21 +63,37 @@ impl Receipt {
         transaction_address: TransactionAddress,
     ) -> Receipt
     {
-        let mut address = None;
-        if Action::Create == transaction.action {
-            let (created_address, _) = contract_address(
-                CreateContractAddress::FromSenderAndNonce,
-                &transaction.sender,
-                &transaction.nonce,
-                &transaction.data,
-            );
-            address = Some(created_address);
-        }
+        let address = match receipt.details {
+            Some(ref details) => details.contract_created,
+            // The receipts executed before the details are stored don't have
+            // the created contract, which is only created if the transaction
+            // succeeds.
+            None if Action::Create == transaction.action
+                && receipt.outcome_status == TRANSACTION_OUTCOME_SUCCESS =>
+            {
+                let (created_address, _) = contract_address(
+                    CreateContractAddress::FromSenderAndNonce,
+                    &transaction.sender,
+                    &transaction.nonce,
+                    &transaction.data,
+                );
+                Some(created_address)
+            }
+            None => None,
+        };
         Receipt {
             transaction_hash: transaction.hash.into(),
             index: transaction_address.index.into(),
             block_hash: transaction_address.block_hash.into(),
             gas_used: receipt.gas_used.into(),
+            gas_charged: receipt
+                .details
+                .as_ref()
+                .map(|details| details.gas_charged.into()),
+            gas_refunded: receipt
+                .details
+                .as_ref()
+                .map(|details| details.gas_refunded.into()),
             from: transaction.sender.into(),
             to: match transaction.action {
                 Action::Create => None,
@@ -71,8 +103,12 @@ impl Receipt {
             contract_created: address.into(),
             logs: receipt.logs.iter().cloned().map(Log::from).collect(),
             logs_bloom: receipt.log_bloom.into(),
+            revert_reason: receipt
+                .details
+                .map(|details| details.revert_reason.into()),
             state_root: Default::default(),
             epoch_number: None,
+            proxied: None,
         }
     }
 
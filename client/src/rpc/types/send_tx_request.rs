@@ -0,0 +1,25 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::rpc::types::{Bytes, H160, U256};
+use serde_derive::Deserialize;
+
+/// A transaction to be signed with a local account. The omitted fields are
+/// filled by the node.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendTxRequest {
+    /// The local account signing the transaction
+    pub from: H160,
+    /// The recipient, or none to create a contract
+    pub to: Option<H160>,
+    /// The next nonce of the sender by default
+    pub nonce: Option<U256>,
+    /// The current gas price by default
+    pub gas_price: Option<U256>,
+    /// 21000 by default
+    pub gas: Option<U256>,
+    pub value: Option<U256>,
+    pub data: Option<Bytes>,
+}
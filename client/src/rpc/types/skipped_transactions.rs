@@ -0,0 +1,36 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use cfxcore::consensus::SkippedTransactionStats;
+use serde_derive::Serialize;
+
+/// The number of transactions in an epoch which are skipped without bumping
+/// the sender nonce, grouped by reason.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedTransactions {
+    pub invalid_nonce: usize,
+    pub not_enough_base_gas: usize,
+    pub sender_must_exist: usize,
+    pub block_gas_limit_reached: usize,
+    pub other: usize,
+    /// Skipped because an earlier transaction in the same block is invalid
+    /// after the invalid transaction reject height.
+    pub rejected: usize,
+    pub total: usize,
+}
+
+impl From<SkippedTransactionStats> for SkippedTransactions {
+    fn from(stats: SkippedTransactionStats) -> Self {
+        SkippedTransactions {
+            invalid_nonce: stats.invalid_nonce,
+            not_enough_base_gas: stats.not_enough_base_gas,
+            sender_must_exist: stats.sender_must_exist,
+            block_gas_limit_reached: stats.block_gas_limit_reached,
+            other: stats.other,
+            rejected: stats.rejected,
+            total: stats.total(),
+        }
+    }
+}
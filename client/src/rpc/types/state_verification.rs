@@ -0,0 +1,19 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::rpc::types::H256;
+use serde_derive::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateVerification {
+    /// The number of the verified epoch
+    pub epoch_number: u64,
+    /// State root recomputed by executing the epoch again
+    pub computed_state_root: H256,
+    /// State root committed by the deferred pivot block
+    pub committed_state_root: H256,
+    /// Whether the two state roots match
+    pub consistent: bool,
+}
@@ -26,7 +26,7 @@ pub struct Transaction {
     pub contract_created: Option<RpcH160>,
     pub data: Bytes,
     pub status: Option<RpcU256>,
-    /// The standardised V field of the signature.
+    /// The V field of the signature, which encodes the chain id if any.
     pub v: RpcU256,
     /// The R field of the signature.
     pub r: RpcU256,
@@ -81,7 +81,7 @@ impl Transaction {
                 value: self.value.into(),
                 data: self.data.into(),
             },
-            v: self.v.as_usize() as u8,
+            v: self.v.as_usize() as u64,
             r: self.r.into(),
             s: self.s.into(),
             hash: self.hash.into(),
@@ -0,0 +1,70 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::rpc::types::{H160, U256};
+use cfx_types::Address;
+use cfxcore::transaction_pool::AdmissionPolicy;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// The rules to admit the transactions into the transaction pool. The rules
+/// omitted admit all the transactions.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TxPoolAdmission {
+    pub max_gas: Option<U256>,
+    /// The maximum size of the transaction data in bytes
+    pub max_data_size: Option<usize>,
+    pub sender_blacklist: Vec<H160>,
+    /// Only admit the transactions of these senders if set
+    pub sender_whitelist: Option<Vec<H160>>,
+    pub recipient_blacklist: Vec<H160>,
+    /// Only admit the calls to these recipients if set
+    pub recipient_whitelist: Option<Vec<H160>>,
+    /// Pairs of the fullness of the pool in percent and the minimum gas price
+    /// from that fullness
+    pub min_gas_price_schedule: Vec<(usize, U256)>,
+}
+
+impl From<AdmissionPolicy> for TxPoolAdmission {
+    fn from(policy: AdmissionPolicy) -> Self {
+        let addresses = |set: BTreeSet<Address>| -> Vec<H160> {
+            set.into_iter().map(Into::into).collect()
+        };
+        TxPoolAdmission {
+            max_gas: policy.max_gas.map(Into::into),
+            max_data_size: policy.max_data_size,
+            sender_blacklist: addresses(policy.sender_blacklist),
+            sender_whitelist: policy.sender_whitelist.map(addresses),
+            recipient_blacklist: addresses(policy.recipient_blacklist),
+            recipient_whitelist: policy.recipient_whitelist.map(addresses),
+            min_gas_price_schedule: policy
+                .min_gas_price_schedule
+                .into_iter()
+                .map(|(fullness, gas_price)| (fullness, gas_price.into()))
+                .collect(),
+        }
+    }
+}
+
+impl Into<AdmissionPolicy> for TxPoolAdmission {
+    fn into(self) -> AdmissionPolicy {
+        let addresses = |list: Vec<H160>| -> BTreeSet<Address> {
+            list.into_iter().map(Into::into).collect()
+        };
+        AdmissionPolicy {
+            max_gas: self.max_gas.map(Into::into),
+            max_data_size: self.max_data_size,
+            sender_blacklist: addresses(self.sender_blacklist),
+            sender_whitelist: self.sender_whitelist.map(addresses),
+            recipient_blacklist: addresses(self.recipient_blacklist),
+            recipient_whitelist: self.recipient_whitelist.map(addresses),
+            min_gas_price_schedule: self
+                .min_gas_price_schedule
+                .into_iter()
+                .map(|(fullness, gas_price)| (fullness, gas_price.into()))
+                .collect(),
+        }
+    }
+}
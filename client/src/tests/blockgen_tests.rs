@@ -18,7 +18,7 @@ fn test_mining_10_epochs_inner(handle: &ArchiveClientHandle) {
     let bgen = handle.blockgen.clone();
     //println!("Pow Config: {:?}", bgen.pow_config());
     thread::spawn(move || {
-        BlockGenerator::start_mining(bgen, 0);
+        BlockGenerator::start_mining(bgen, 1);
     });
     let sync_graph = handle.sync.get_synchronization_graph();
     let best_block_hash = sync_graph.consensus.best_block_hash();
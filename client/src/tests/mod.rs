@@ -6,3 +6,5 @@
 mod blockgen_tests;
 #[cfg(test)]
 mod load_chain_tests;
+#[cfg(test)]
+mod query_tests;
@@ -0,0 +1,70 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+extern crate tempdir;
+
+use self::tempdir::TempDir;
+use crate::{
+    archive::{ArchiveClient, Configuration},
+    query::QueryClient,
+};
+use parking_lot::{Condvar, Mutex};
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn test_query_while_archive_running() {
+    let mut conf = Configuration::default();
+    conf.raw_conf.test_mode = true;
+    let tmp_dir = TempDir::new("conflux-test").unwrap();
+    conf.raw_conf.db_dir = Some(
+        tmp_dir
+            .path()
+            .join("db")
+            .into_os_string()
+            .into_string()
+            .unwrap(),
+    );
+    conf.raw_conf.netconf_dir = Some(
+        tmp_dir
+            .path()
+            .join("config")
+            .into_os_string()
+            .into_string()
+            .unwrap(),
+    );
+    conf.raw_conf.port = Some(13002);
+    conf.raw_conf.jsonrpc_http_port = Some(18002);
+
+    let exit = Arc::new((Mutex::new(false), Condvar::new()));
+    let handle = ArchiveClient::start(conf.clone(), exit).unwrap();
+    for _ in 0..10 {
+        handle.blockgen.generate_block(0, 300_000, vec![]);
+    }
+    let data_man = handle.consensus.data_man.clone();
+    let start = Instant::now();
+    while QueryClient::latest_executed_epoch(&data_man) == 0 {
+        assert!(start.elapsed() < Duration::from_secs(30));
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // The database is still locked by the archive node.
+    let mut query_conf = conf;
+    query_conf.raw_conf.jsonrpc_http_port = Some(18003);
+    let query = QueryClient::start(query_conf).unwrap();
+    let latest_epoch = QueryClient::latest_executed_epoch(&query.data_man);
+    assert!(latest_epoch > 0);
+    assert!(latest_epoch < 10);
+    assert_eq!(
+        query.data_man.epoch_set_hashes_from_db(latest_epoch),
+        data_man.epoch_set_hashes_from_db(latest_epoch)
+    );
+
+    QueryClient::close(query);
+    drop(data_man);
+    ArchiveClient::close(handle);
+}
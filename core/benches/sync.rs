@@ -0,0 +1,91 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use cfx_types::{Address, U256};
+use cfxcore::sync::{GetBlocksResponse, LazyBlock};
+use criterion::{criterion_group, criterion_main, Criterion};
+use ethkey::{Generator, Random};
+use primitives::{Action, Block, BlockHeaderBuilder, Transaction};
+use rlp::Rlp;
+use std::sync::Arc;
+
+const NUM_BLOCKS: usize = 32;
+const NUM_TXS_PER_BLOCK: usize = 200;
+
+fn new_blocks() -> Vec<Arc<Block>> {
+    let sender = Random.generate().unwrap();
+    (0..NUM_BLOCKS)
+        .map(|height| {
+            let transactions = (0..NUM_TXS_PER_BLOCK)
+                .map(|nonce| {
+                    Arc::new(
+                        Transaction {
+                            nonce: U256::from(nonce),
+                            gas_price: U256::from(1),
+                            gas: U256::from(21000),
+                            action: Action::Call(Address::random()),
+                            value: U256::from(100),
+                            data: vec![0; 64],
+                        }
+                        .sign(sender.secret()),
+                    )
+                })
+                .collect();
+            let header =
+                BlockHeaderBuilder::new().with_height(height as u64).build();
+            Arc::new(Block::new(header, transactions))
+        })
+        .collect()
+}
+
+/// The encoded `GetBlocksResponse` of a block flood.
+fn new_response() -> Vec<u8> {
+    rlp::encode(&GetBlocksResponse {
+        request_id: 0,
+        blocks: new_blocks().into_iter().map(LazyBlock::from).collect(),
+    })
+}
+
+fn decode_blocks_benchmark(c: &mut Criterion) {
+    let encoded = new_response();
+    c.bench_function("Decode blocks eagerly", move |b| {
+        b.iter(|| {
+            let blocks: Vec<Block> = Rlp::new(&encoded).list_at(1).unwrap();
+            assert_eq!(blocks.len(), NUM_BLOCKS);
+        });
+    });
+
+    let encoded = new_response();
+    c.bench_function("Decode blocks lazily", move |b| {
+        b.iter(|| {
+            let response: GetBlocksResponse = rlp::decode(&encoded).unwrap();
+            assert_eq!(response.blocks.len(), NUM_BLOCKS);
+        });
+    });
+
+    let encoded = new_response();
+    c.bench_function("Decode blocks lazily and materialize them", move |b| {
+        b.iter(|| {
+            let response: GetBlocksResponse = rlp::decode(&encoded).unwrap();
+            for block in response.blocks {
+                block.into_block().unwrap();
+            }
+        });
+    });
+}
+
+fn encode_blocks_benchmark(c: &mut Criterion) {
+    let blocks = new_blocks();
+    c.bench_function("Encode local blocks", move |b| {
+        b.iter(|| {
+            rlp::encode(&GetBlocksResponse {
+                request_id: 0,
+                blocks: blocks.iter().cloned().map(LazyBlock::from).collect(),
+            })
+        });
+    });
+}
+
+criterion_group!(benches, decode_blocks_benchmark, encode_blocks_benchmark);
+criterion_main!(benches);
@@ -1622,6 +1622,10 @@ impl TxReplayer {
                     cfxcore::storage::defaults::DEFAULT_NODE_MAP_SIZE,
                 recent_lfu_factor:
                     cfxcore::storage::defaults::DEFAULT_RECENT_LFU_FACTOR,
+                archive_snapshot_interval: None,
+                dedup_trie_nodes: false,
+                external_value_threshold: None,
+                slab_max_idle_size: None,
             },
         ));
 
@@ -1,4 +1,4 @@
-use cfx_types::{Bloom, H256};
+use cfx_types::{Address, Bloom, H256, U256};
 use malloc_size_of::{MallocSizeOf, MallocSizeOfOps};
 use malloc_size_of_derive::MallocSizeOf as DeriveMallocSizeOf;
 use primitives::Receipt;
@@ -45,18 +45,28 @@ impl MallocSizeOf for BlockExecutionResult {
     }
 }
 
+/// The receipts are stored with their execution details. The receipts
+/// without the details are still decoded, for the databases not migrated.
 impl Encodable for BlockExecutionResult {
     fn rlp_append(&self, s: &mut RlpStream) {
-        s.begin_list(2)
-            .append_list(&self.receipts.as_ref())
-            .append(&self.bloom);
+        s.begin_list(2);
+        s.begin_list(self.receipts.len());
+        for receipt in self.receipts.iter() {
+            receipt.rlp_append_with_details(s);
+        }
+        s.append(&self.bloom);
     }
 }
 
 impl Decodable for BlockExecutionResult {
     fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        let receipts = rlp
+            .at(0)?
+            .iter()
+            .map(|r| Receipt::decode_with_details(&r))
+            .collect::<Result<Vec<_>, _>>()?;
         Ok(BlockExecutionResult {
-            receipts: Arc::new(rlp.list_at(0)?),
+            receipts: Arc::new(receipts),
             bloom: rlp.val_at(1)?,
         })
     }
@@ -209,6 +219,82 @@ impl BlockStatus {
     fn to_db_status(&self) -> u8 { *self as u8 }
 }
 
+/// The roots of an epoch verified by a light node, either directly from a
+/// trusted pivot header or through the witness of a blamed header.
+#[derive(RlpEncodable, RlpDecodable, Clone)]
+pub struct WitnessRoots {
+    pub state_root_hash: H256,
+    pub receipts_root_hash: H256,
+    pub logs_bloom_hash: H256,
+}
+
+/// The version of `PowQualityRecord` currently written to the database.
+/// Records with other versions are ignored and recomputed.
+pub const POW_QUALITY_RECORD_VERSION: u8 = 1;
+
+/// The PoW quality of a block header, stored alongside the header so that we
+/// do not need to recompute the PoW hash every time the header is loaded.
+#[derive(RlpEncodable, RlpDecodable, Clone)]
+pub struct PowQualityRecord {
+    pub version: u8,
+    pub pow_quality: U256,
+}
+
+impl PowQualityRecord {
+    pub fn new(pow_quality: U256) -> Self {
+        Self {
+            version: POW_QUALITY_RECORD_VERSION,
+            pow_quality,
+        }
+    }
+}
+
+/// The reward breakdown of a block, computed when the epoch it belongs to is
+/// rewarded.
+#[derive(RlpEncodable, RlpDecodable, Clone, Debug, PartialEq)]
+pub struct BlockRewardResult {
+    pub block_hash: H256,
+    pub author: Address,
+    /// The base mining reward before the anticone penalty.
+    pub base_reward: U256,
+    pub anticone_penalty: U256,
+    /// The share of the transaction fees of the epoch given to the block.
+    pub tx_fee: U256,
+}
+
+impl BlockRewardResult {
+    pub fn total_reward(&self) -> U256 {
+        self.base_reward - self.anticone_penalty + self.tx_fee
+    }
+}
+
+/// The logs bloom of a pivot chain epoch in the bloom index.
+#[derive(RlpEncodable, RlpDecodable, Clone)]
+pub struct EpochBloom {
+    /// The pivot block of the epoch when it was indexed.
+    pub epoch_hash: H256,
+    pub bloom: Bloom,
+}
+
+/// The logs bloom aggregated over an aligned range of epoch numbers in the
+/// bloom index. It also includes the logs of the epochs that were on the pivot
+/// chain when executed but aren't any more, which only causes false positives.
+#[derive(RlpEncodable, RlpDecodable, Clone, Default)]
+pub struct AggregatedBloom {
+    pub bloom: Bloom,
+    /// The number of epoch numbers in the range that have been indexed.
+    pub indexed_epochs: u64,
+}
+
+/// The recently used cache entries saved at shutdown, which are loaded into
+/// the cache again after a restart.
+#[derive(RlpEncodable, RlpDecodable, Clone, Default)]
+pub struct HotCacheEntries {
+    pub block_headers: Vec<H256>,
+    pub blocks: Vec<H256>,
+    pub transaction_addresses: Vec<H256>,
+}
+
 /// The checkpoint information stored in the database
 #[derive(RlpEncodable, RlpDecodable, Clone)]
 pub struct CheckpointHashes {
@@ -1,14 +1,20 @@
 use crate::{
     block_data_manager::{
-        BlockExecutionResultWithEpoch, CheckpointHashes,
-        ConsensusGraphExecutionInfo, EpochExecutionContext, LocalBlockInfo,
+        AggregatedBloom, BlockExecutionResultWithEpoch, BlockRewardResult,
+        CheckpointHashes, ConsensusGraphExecutionInfo, EpochBloom,
+        EpochExecutionContext, HotCacheEntries, LocalBlockInfo,
+        PowQualityRecord, WitnessRoots, POW_QUALITY_RECORD_VERSION,
+    },
+    db::{
+        COL_BLAME_STATE_VALID, COL_BLOCKS, COL_BLOOM_INDEX, COL_EPOCH_NUMBER,
+        COL_LIGHT_WITNESSES, COL_MISC, COL_TX_ADDRESS,
     },
-    db::{COL_BLOCKS, COL_EPOCH_NUMBER, COL_MISC, COL_TX_ADDRESS},
     storage::{storage_db::KeyValueDbTrait, KvdbRocksdb, KvdbSqlite},
+    transaction_pool::AdmissionPolicy,
     verification::VerificationConfig,
 };
-use byteorder::{ByteOrder, LittleEndian};
-use cfx_types::H256;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use cfx_types::{H256, U256};
 use db::SystemDB;
 use primitives::{Block, BlockHeader, SignedTransaction, TransactionAddress};
 use rlp::{Decodable, Encodable, Rlp};
@@ -19,6 +25,8 @@ const BLOCK_BODY_SUFFIX_BYTE: u8 = 2;
 const BLOCK_EXECUTION_RESULT_SUFFIX_BYTE: u8 = 3;
 const EPOCH_EXECUTION_CONTEXT_SUFFIX_BYTE: u8 = 4;
 const EPOCH_CONSENSUS_EXECUTION_INFO_SUFFIX_BYTE: u8 = 5;
+const POW_QUALITY_SUFFIX_BYTE: u8 = 6;
+const EPOCH_REWARDS_SUFFIX_BYTE: u8 = 7;
 
 #[derive(Clone, Copy, Hash, Ord, PartialOrd, Eq, PartialEq)]
 enum DBTable {
@@ -26,6 +34,9 @@ enum DBTable {
     Blocks,
     Transactions,
     EpochNumbers,
+    LightWitnesses,
+    BlameStateValid,
+    BloomIndex,
 }
 
 fn rocks_db_col(table: DBTable) -> Option<u32> {
@@ -34,6 +45,9 @@ fn rocks_db_col(table: DBTable) -> Option<u32> {
         DBTable::Blocks => COL_BLOCKS,
         DBTable::Transactions => COL_TX_ADDRESS,
         DBTable::EpochNumbers => COL_EPOCH_NUMBER,
+        DBTable::LightWitnesses => COL_LIGHT_WITNESSES,
+        DBTable::BlameStateValid => COL_BLAME_STATE_VALID,
+        DBTable::BloomIndex => COL_BLOOM_INDEX,
     }
 }
 
@@ -43,6 +57,9 @@ fn sqlite_db_table(table: DBTable) -> String {
         DBTable::Blocks => "blocks",
         DBTable::Transactions => "transactions",
         DBTable::EpochNumbers => "epoch_numbers",
+        DBTable::LightWitnesses => "light_witnesses",
+        DBTable::BlameStateValid => "blame_state_valid",
+        DBTable::BloomIndex => "bloom_index",
     }
     .into()
 }
@@ -59,6 +76,9 @@ impl DBManager {
             DBTable::Blocks,
             DBTable::Transactions,
             DBTable::EpochNumbers,
+            DBTable::LightWitnesses,
+            DBTable::BlameStateValid,
+            DBTable::BloomIndex,
         ] {
             table_db.insert(
                 table,
@@ -84,6 +104,9 @@ impl DBManager {
             DBTable::Blocks,
             DBTable::Transactions,
             DBTable::EpochNumbers,
+            DBTable::LightWitnesses,
+            DBTable::BlameStateValid,
+            DBTable::BloomIndex,
         ] {
             let table_str = sqlite_db_table(table);
             let sqlite_db = KvdbSqlite::create_and_open(
@@ -121,17 +144,54 @@ impl DBManager {
             header.hash().as_bytes(),
             header,
         );
-    }
-
+        // `pow_quality` is not part of the header rlp and is computed here if
+        // it has not been computed yet
+        let pow_quality = if header.pow_quality.is_zero() {
+            let mut header = header.clone();
+            VerificationConfig::compute_header_pow_quality(&mut header);
+            header.pow_quality
+        } else {
+            header.pow_quality
+        };
+        self.insert_pow_quality_to_db(&header.hash(), pow_quality);
+    }
+
+    /// Load a block header from db. The PoW quality is taken from the stored
+    /// `PowQualityRecord` if there is a valid one, otherwise it is recomputed
+    /// without writing anything, so that the read-only databases are loaded
+    /// in the same way. The records of the databases created before are
+    /// backfilled by a migration.
     pub fn block_header_from_db(&self, hash: &H256) -> Option<BlockHeader> {
-        let mut block_header =
+        let mut block_header: BlockHeader =
             self.load_decodable_val(DBTable::Blocks, hash.as_bytes())?;
-        VerificationConfig::compute_header_pow_quality(&mut block_header);
+        match self.pow_quality_from_db(hash) {
+            Some(pow_quality) => block_header.pow_quality = pow_quality,
+            None => {
+                VerificationConfig::compute_header_pow_quality(
+                    &mut block_header,
+                );
+            }
+        }
         Some(block_header)
     }
 
     pub fn remove_block_header_from_db(&self, hash: &H256) {
         self.remove_from_db(DBTable::Blocks, hash.as_bytes());
+        self.remove_from_db(DBTable::Blocks, &pow_quality_key(hash));
+    }
+
+    fn insert_pow_quality_to_db(&self, hash: &H256, pow_quality: U256) {
+        self.insert_encodable_val(
+            DBTable::Blocks,
+            &pow_quality_key(hash),
+            &PowQualityRecord::new(pow_quality),
+        );
+    }
+
+    fn pow_quality_from_db(&self, hash: &H256) -> Option<U256> {
+        let encoded =
+            self.load_from_db(DBTable::Blocks, &pow_quality_key(hash))?;
+        decode_pow_quality_record(&encoded)
     }
 
     pub fn insert_transaction_address_to_db(
@@ -146,6 +206,10 @@ impl DBManager {
         self.load_decodable_val(DBTable::Transactions, hash.as_bytes())
     }
 
+    pub fn remove_transaction_address_from_db(&self, hash: &H256) {
+        self.remove_from_db(DBTable::Transactions, hash.as_bytes())
+    }
+
     /// Store block info to db. Block info includes block status and
     /// the sequence number when the block enters consensus graph.
     /// The db key is the block hash plus one extra byte, so we can get better
@@ -217,6 +281,10 @@ impl DBManager {
         )
     }
 
+    pub fn remove_block_execution_result_from_db(&self, hash: &H256) {
+        self.remove_from_db(DBTable::Blocks, &block_execution_result_key(hash))
+    }
+
     pub fn insert_checkpoint_hashes_to_db(
         &self, checkpoint_prev: &H256, checkpoint_cur: &H256,
     ) {
@@ -233,6 +301,82 @@ impl DBManager {
         Some((checkpoints.prev_hash, checkpoints.cur_hash))
     }
 
+    /// The checkpoint being advanced to, which is removed after the
+    /// advancement finishes.
+    pub fn insert_checkpoint_advancement_to_db(
+        &self, era_genesis_hash: &H256, stable_hash: &H256,
+    ) {
+        self.insert_encodable_val(
+            DBTable::Misc,
+            b"checkpoint_advancement",
+            &CheckpointHashes::new(*era_genesis_hash, *stable_hash),
+        );
+    }
+
+    pub fn checkpoint_advancement_from_db(&self) -> Option<(H256, H256)> {
+        let checkpoints: CheckpointHashes =
+            self.load_decodable_val(DBTable::Misc, b"checkpoint_advancement")?;
+        Some((checkpoints.prev_hash, checkpoints.cur_hash))
+    }
+
+    pub fn remove_checkpoint_advancement_from_db(&self) {
+        self.remove_from_db(DBTable::Misc, b"checkpoint_advancement")
+    }
+
+    /// The next epoch to reindex, which is removed after the reindexing
+    /// finishes.
+    pub fn insert_reindex_progress_to_db(&self, epoch: u64) {
+        self.insert_encodable_val(DBTable::Misc, b"reindex_progress", &epoch);
+    }
+
+    pub fn reindex_progress_from_db(&self) -> Option<u64> {
+        self.load_decodable_val(DBTable::Misc, b"reindex_progress")
+    }
+
+    pub fn remove_reindex_progress_from_db(&self) {
+        self.remove_from_db(DBTable::Misc, b"reindex_progress")
+    }
+
+    pub fn insert_hot_cache_entries_to_db(&self, entries: &HotCacheEntries) {
+        self.insert_encodable_val(DBTable::Misc, b"hot_cache_entries", entries);
+    }
+
+    pub fn hot_cache_entries_from_db(&self) -> Option<HotCacheEntries> {
+        self.load_decodable_val(DBTable::Misc, b"hot_cache_entries")
+    }
+
+    pub fn remove_hot_cache_entries_from_db(&self) {
+        self.remove_from_db(DBTable::Misc, b"hot_cache_entries")
+    }
+
+    /// The earliest epoch whose block bodies are kept, see
+    /// `BlockDataManager::prune_block_bodies`.
+    pub fn insert_earliest_block_body_epoch_to_db(&self, epoch: u64) {
+        self.insert_encodable_val(
+            DBTable::Misc,
+            b"earliest_block_body_epoch",
+            &epoch,
+        );
+    }
+
+    pub fn earliest_block_body_epoch_from_db(&self) -> Option<u64> {
+        self.load_decodable_val(DBTable::Misc, b"earliest_block_body_epoch")
+    }
+
+    pub fn insert_txpool_admission_policy_to_db(
+        &self, policy: &AdmissionPolicy,
+    ) {
+        self.insert_encodable_val(
+            DBTable::Misc,
+            b"txpool_admission_policy",
+            policy,
+        );
+    }
+
+    pub fn txpool_admission_policy_from_db(&self) -> Option<AdmissionPolicy> {
+        self.load_decodable_val(DBTable::Misc, b"txpool_admission_policy")
+    }
+
     pub fn insert_epoch_set_hashes_to_db(
         &self, epoch: u64, hashes: &Vec<H256>,
     ) {
@@ -258,6 +402,84 @@ impl DBManager {
         self.load_decodable_list(DBTable::Misc, b"terminals")
     }
 
+    pub fn insert_witness_roots_to_db(&self, epoch: u64, roots: &WitnessRoots) {
+        self.insert_encodable_val(
+            DBTable::LightWitnesses,
+            &epoch_set_key(epoch),
+            roots,
+        );
+    }
+
+    pub fn witness_roots_from_db(&self, epoch: u64) -> Option<WitnessRoots> {
+        self.load_decodable_val(DBTable::LightWitnesses, &epoch_set_key(epoch))
+    }
+
+    pub fn remove_witness_roots_from_db(&self, epoch: u64) {
+        self.remove_from_db(DBTable::LightWitnesses, &epoch_set_key(epoch));
+    }
+
+    pub fn insert_latest_verified_header_to_db(&self, height: u64) {
+        self.insert_encodable_val(
+            DBTable::Misc,
+            b"light_latest_verified_header",
+            &height,
+        );
+    }
+
+    pub fn latest_verified_header_from_db(&self) -> Option<u64> {
+        self.load_decodable_val(DBTable::Misc, b"light_latest_verified_header")
+    }
+
+    pub fn insert_blame_state_valid_to_db(
+        &self, hash: &H256, state_valid: bool,
+    ) {
+        self.insert_encodable_val(
+            DBTable::BlameStateValid,
+            hash.as_bytes(),
+            &state_valid,
+        );
+    }
+
+    pub fn blame_state_valid_from_db(&self, hash: &H256) -> Option<bool> {
+        self.load_decodable_val(DBTable::BlameStateValid, hash.as_bytes())
+    }
+
+    pub fn insert_epoch_bloom_to_db(&self, epoch: u64, bloom: &EpochBloom) {
+        self.insert_encodable_val(
+            DBTable::BloomIndex,
+            &bloom_index_key(0, epoch),
+            bloom,
+        );
+    }
+
+    pub fn epoch_bloom_from_db(&self, epoch: u64) -> Option<EpochBloom> {
+        self.load_decodable_val(DBTable::BloomIndex, &bloom_index_key(0, epoch))
+    }
+
+    pub fn remove_epoch_bloom_from_db(&self, epoch: u64) {
+        self.remove_from_db(DBTable::BloomIndex, &bloom_index_key(0, epoch))
+    }
+
+    /// `level` starts from 1, as level 0 is used by the per-epoch blooms.
+    pub fn insert_aggregated_bloom_to_db(
+        &self, level: u8, index: u64, bloom: &AggregatedBloom,
+    ) {
+        self.insert_encodable_val(
+            DBTable::BloomIndex,
+            &bloom_index_key(level, index),
+            bloom,
+        );
+    }
+
+    pub fn aggregated_bloom_from_db(
+        &self, level: u8, index: u64,
+    ) -> Option<AggregatedBloom> {
+        self.load_decodable_val(
+            DBTable::BloomIndex,
+            &bloom_index_key(level, index),
+        )
+    }
+
     pub fn insert_consensus_graph_execution_info_to_db(
         &self, hash: &H256, ctx: &ConsensusGraphExecutionInfo,
     ) {
@@ -304,6 +526,22 @@ impl DBManager {
         )
     }
 
+    pub fn insert_epoch_rewards_to_db(
+        &self, hash: &H256, rewards: &Vec<BlockRewardResult>,
+    ) {
+        self.insert_encodable_list(
+            DBTable::Blocks,
+            &epoch_rewards_key(hash),
+            rewards,
+        )
+    }
+
+    pub fn epoch_rewards_from_db(
+        &self, hash: &H256,
+    ) -> Option<Vec<BlockRewardResult>> {
+        self.load_decodable_list(DBTable::Blocks, &epoch_rewards_key(hash))
+    }
+
     /// The functions below are private utils used by the DBManager to access
     /// database
     fn insert_to_db(&self, table: DBTable, db_key: &[u8], value: Vec<u8>) {
@@ -368,10 +606,23 @@ fn epoch_set_key(epoch_number: u64) -> [u8; 8] {
     epoch_key
 }
 
-fn block_execution_result_key(hash: &H256) -> Vec<u8> {
+fn bloom_index_key(level: u8, index: u64) -> [u8; 9] {
+    let mut key = [0; 9];
+    key[0] = level;
+    BigEndian::write_u64(&mut key[1..9], index);
+    key
+}
+
+pub fn block_execution_result_key(hash: &H256) -> Vec<u8> {
     append_suffix(hash, BLOCK_EXECUTION_RESULT_SUFFIX_BYTE)
 }
 
+/// Whether `key` in `COL_BLOCKS` is the key of a `BlockExecutionResult`.
+pub fn is_block_execution_result_key(key: &[u8]) -> bool {
+    key.len() == H256::len_bytes() + 1
+        && key[H256::len_bytes()] == BLOCK_EXECUTION_RESULT_SUFFIX_BYTE
+}
+
 fn epoch_execution_context_key(hash: &H256) -> Vec<u8> {
     append_suffix(hash, EPOCH_EXECUTION_CONTEXT_SUFFIX_BYTE)
 }
@@ -379,3 +630,26 @@ fn epoch_execution_context_key(hash: &H256) -> Vec<u8> {
 fn epoch_consensus_execution_info_key(hash: &H256) -> Vec<u8> {
     append_suffix(hash, EPOCH_CONSENSUS_EXECUTION_INFO_SUFFIX_BYTE)
 }
+
+fn epoch_rewards_key(hash: &H256) -> Vec<u8> {
+    append_suffix(hash, EPOCH_REWARDS_SUFFIX_BYTE)
+}
+
+pub fn pow_quality_key(hash: &H256) -> Vec<u8> {
+    append_suffix(hash, POW_QUALITY_SUFFIX_BYTE)
+}
+
+/// Whether `key` in `COL_BLOCKS` is the key of a `BlockHeader`.
+pub fn is_block_header_key(key: &[u8]) -> bool {
+    key.len() == H256::len_bytes()
+}
+
+/// The PoW quality in an encoded `PowQualityRecord`, or `None` if it is
+/// written by another version, which might not be decodable.
+pub fn decode_pow_quality_record(encoded: &[u8]) -> Option<U256> {
+    let record: PowQualityRecord = Rlp::new(encoded).as_val().ok()?;
+    if record.version != POW_QUALITY_RECORD_VERSION {
+        return None;
+    }
+    Some(record.pow_quality)
+}
@@ -6,12 +6,12 @@ use crate::{
     cache_config::CacheConfig,
     cache_manager::{CacheId, CacheManager, CacheSize},
     ext_db::SystemDB,
-    parameters::consensus::DEFERRED_STATE_EPOCH_COUNT,
     pow::TargetDifficultyManager,
     storage::{
         state_manager::{SnapshotAndEpochIdRef, StateManagerTrait},
         StorageManager,
     },
+    transaction_pool::AdmissionPolicy,
 };
 use cfx_types::{Bloom, H256};
 use malloc_size_of::{new_malloc_size_ops, MallocSizeOf};
@@ -27,12 +27,21 @@ use primitives::{
 };
 use rlp::DecoderError;
 use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
+    cmp::{max, min},
+    collections::{HashMap, HashSet, VecDeque},
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 use threadpool::ThreadPool;
 pub mod block_data_types;
 pub mod db_manager;
+#[cfg(test)]
+mod tests;
 pub mod tx_data_manager;
 use crate::block_data_manager::{
     db_manager::DBManager, tx_data_manager::TransactionDataManager,
@@ -42,6 +51,23 @@ use std::{hash::Hash, path::Path};
 
 pub const NULLU64: u64 = !0;
 
+/// The number of epochs aggregated by a bloom index entry of each level above
+/// the per-epoch blooms.
+pub const BLOOM_INDEX_LEVEL_SIZES: [u64; 2] = [256, 65536];
+
+const MB: usize = 1024 * 1024;
+
+/// The max number of epochs whose block bodies are pruned in a round, so
+/// that a long backlog doesn't stall the caller.
+const MAX_PRUNED_BODY_EPOCHS_PER_ROUND: u64 = 1000;
+
+/// The preferred and maximum sizes of the block caches in bytes with the
+/// given ledger cache budget in MB.
+fn ledger_cache_size(ledger_mb: usize) -> (usize, usize) {
+    let max_cache_size = ledger_mb * MB;
+    (max_cache_size * 3 / 4, max_cache_size)
+}
+
 pub struct BlockDataManager {
     block_headers: RwLock<HashMap<H256, Arc<BlockHeader>>>,
     blocks: RwLock<HashMap<H256, Arc<Block>>>,
@@ -67,12 +93,28 @@ pub struct BlockDataManager {
     invalid_block_set: RwLock<HashSet<H256>>,
     cur_consensus_era_genesis_hash: RwLock<H256>,
     cur_consensus_era_stable_hash: RwLock<H256>,
+    /// The height ranges of the epochs in the old eras whose cached blocks
+    /// are released in the next `cache_gc`.
+    pending_era_cleanups: Mutex<VecDeque<(u64, u64)>>,
+    /// The block bodies, execution results and transaction addresses of the
+    /// epochs before it have been pruned if `block_body_horizon` is set.
+    earliest_block_body_epoch: Mutex<u64>,
     instance_id: Mutex<u64>,
+    /// Serializes the read-modify-write updates of the bloom index.
+    bloom_index_lock: Mutex<()>,
 
     config: DataManagerConfiguration,
 
     tx_data_manager: TransactionDataManager,
-    db_manager: DBManager,
+    db_manager: Arc<DBManager>,
+    /// Persists the checkpoints advanced by the consensus graph in the
+    /// background, since snapshotting the state of a checkpoint walks the
+    /// whole delta MPT. It's stopped by `close`.
+    checkpoint_worker:
+        Mutex<Option<(mpsc::Sender<(H256, H256)>, thread::JoinHandle<()>)>>,
+    /// The stop flag and the thread of the cache warm-up, which is stopped by
+    /// `close`.
+    cache_warm_up: Mutex<Option<(Arc<AtomicBool>, thread::JoinHandle<()>)>>,
 
     pub genesis_block: Arc<Block>,
     pub true_genesis_block: Arc<Block>,
@@ -89,22 +131,27 @@ impl BlockDataManager {
     ) -> Self
     {
         let genesis_hash = genesis_block.block_header.hash();
-        let mb = 1024 * 1024;
-        let max_cache_size = cache_conf.ledger_mb() * mb;
-        let pref_cache_size = max_cache_size * 3 / 4;
+        let (pref_cache_size, max_cache_size) =
+            ledger_cache_size(cache_conf.ledger_mb());
         let cache_man = Arc::new(Mutex::new(CacheManager::new(
             pref_cache_size,
             max_cache_size,
-            3 * mb,
+            3 * MB,
         )));
         let tx_data_manager =
             TransactionDataManager::new(config.tx_cache_count, worker_pool);
-        let db_manager = match config.db_type {
+        let db_manager = Arc::new(match config.db_type {
             DbType::Rocksdb => DBManager::new_from_rocksdb(db),
             DbType::Sqlite => {
                 DBManager::new_from_sqlite(Path::new("./sqlite_db"))
             }
-        };
+        });
+        let earliest_block_body_epoch =
+            db_manager.earliest_block_body_epoch_from_db().unwrap_or(0);
+        let checkpoint_worker = Self::start_checkpoint_worker(
+            db_manager.clone(),
+            storage_manager.clone(),
+        );
 
         let mut data_man = Self {
             block_headers: RwLock::new(HashMap::new()),
@@ -120,15 +167,21 @@ impl BlockDataManager {
             storage_manager,
             cache_man,
             instance_id: Mutex::new(0),
+            bloom_index_lock: Mutex::new(()),
             config,
             target_difficulty_manager: TargetDifficultyManager::new(),
             cur_consensus_era_genesis_hash: RwLock::new(genesis_hash),
             cur_consensus_era_stable_hash: RwLock::new(genesis_hash),
+            pending_era_cleanups: Default::default(),
+            earliest_block_body_epoch: Mutex::new(earliest_block_body_epoch),
             tx_data_manager,
             db_manager,
+            checkpoint_worker: Mutex::new(Some(checkpoint_worker)),
+            cache_warm_up: Default::default(),
         };
 
         data_man.initialize_instance_id();
+        data_man.recover_checkpoint_advancement();
 
         if let Some((checkpoint_hash, stable_hash)) =
             data_man.db_manager.checkpoint_hashes_from_db()
@@ -148,7 +201,9 @@ impl BlockDataManager {
                     {
                         let mut cur_hash =
                             *checkpoint_block.block_header.parent_hash();
-                        for _ in 0..DEFERRED_STATE_EPOCH_COUNT - 1 {
+                        for _ in
+                            0..data_man.config.deferred_state_epoch_count - 1
+                        {
                             assert_ne!(cur_hash, H256::default());
                             let cur_block = data_man.block_by_hash(
                                 &cur_hash, false, /* update_cache */
@@ -376,8 +431,8 @@ impl BlockDataManager {
     }
 
     pub fn block_height_by_hash(&self, hash: &H256) -> Option<u64> {
-        let result = self.block_by_hash(hash, false /* update_cache */)?;
-        Some(result.block_header.height())
+        // The header is used since the body may have been pruned.
+        Some(self.block_header_by_hash(hash)?.height())
     }
 
     pub fn compact_block_by_hash(&self, hash: &H256) -> Option<CompactBlock> {
@@ -479,6 +534,14 @@ impl BlockDataManager {
             .note_used(CacheId::BlockReceipts(hash));
     }
 
+    /// remove block execution results in memory cache and db
+    pub fn remove_block_results(&self, hash: &H256, remove_db: bool) {
+        if remove_db {
+            self.db_manager.remove_block_execution_result_from_db(hash);
+        }
+        self.block_receipts.write().remove(hash);
+    }
+
     pub fn transaction_address_by_hash(
         &self, hash: &H256, update_cache: bool,
     ) -> Option<TransactionAddress> {
@@ -514,6 +577,11 @@ impl BlockDataManager {
             .insert_transaction_address_to_db(hash, tx_address);
     }
 
+    pub fn remove_transaction_address(&self, hash: &H256) {
+        self.transaction_addresses.write().remove(hash);
+        self.db_manager.remove_transaction_address_from_db(hash);
+    }
+
     fn insert<K, V, InsertF>(
         &self, key: K, value: V, in_mem: &RwLock<HashMap<K, V>>,
         insert_f: InsertF, maybe_cache_id: Option<CacheId>, persistent: bool,
@@ -573,6 +641,178 @@ impl BlockDataManager {
         self.db_manager.terminals_from_db()
     }
 
+    /// Persist the admission rules of the transaction pool set at runtime.
+    pub fn insert_txpool_admission_policy_to_db(
+        &self, policy: &AdmissionPolicy,
+    ) {
+        self.db_manager.insert_txpool_admission_policy_to_db(policy)
+    }
+
+    pub fn txpool_admission_policy_from_db(&self) -> Option<AdmissionPolicy> {
+        self.db_manager.txpool_admission_policy_from_db()
+    }
+
+    pub fn insert_witness_roots_to_db(&self, epoch: u64, roots: &WitnessRoots) {
+        self.db_manager.insert_witness_roots_to_db(epoch, roots)
+    }
+
+    pub fn witness_roots_from_db(&self, epoch: u64) -> Option<WitnessRoots> {
+        self.db_manager.witness_roots_from_db(epoch)
+    }
+
+    pub fn remove_witness_roots_from_db(&self, epoch: u64) {
+        self.db_manager.remove_witness_roots_from_db(epoch)
+    }
+
+    pub fn insert_latest_verified_header_to_db(&self, height: u64) {
+        self.db_manager.insert_latest_verified_header_to_db(height)
+    }
+
+    pub fn latest_verified_header_from_db(&self) -> Option<u64> {
+        self.db_manager.latest_verified_header_from_db()
+    }
+
+    /// Persist the `state_valid` of a pivot block derived from the blame
+    /// information when recovering from a checkpoint.
+    pub fn insert_blame_state_valid_to_db(
+        &self, hash: &H256, state_valid: bool,
+    ) {
+        self.db_manager
+            .insert_blame_state_valid_to_db(hash, state_valid)
+    }
+
+    pub fn blame_state_valid_from_db(&self, hash: &H256) -> Option<bool> {
+        self.db_manager.blame_state_valid_from_db(hash)
+    }
+
+    /// Add the logs bloom of an epoch executed on the local pivot chain to the
+    /// bloom index. The aggregated blooms only accumulate, so an epoch
+    /// replaced on the pivot chain leaves its logs in them.
+    pub fn index_epoch_bloom(
+        &self, epoch_number: u64, epoch_hash: &H256, bloom: Bloom,
+    ) {
+        let _lock = self.bloom_index_lock.lock();
+        let newly_indexed =
+            self.db_manager.epoch_bloom_from_db(epoch_number).is_none();
+        self.db_manager.insert_epoch_bloom_to_db(
+            epoch_number,
+            &EpochBloom {
+                epoch_hash: *epoch_hash,
+                bloom,
+            },
+        );
+        for (level, size) in BLOOM_INDEX_LEVEL_SIZES.iter().enumerate() {
+            let level = level as u8 + 1;
+            let index = epoch_number / size;
+            let mut aggregated = self
+                .db_manager
+                .aggregated_bloom_from_db(level, index)
+                .unwrap_or_default();
+            aggregated.bloom.accrue_bloom(&bloom);
+            if newly_indexed {
+                aggregated.indexed_epochs += 1;
+            }
+            self.db_manager.insert_aggregated_bloom_to_db(
+                level,
+                index,
+                &aggregated,
+            );
+        }
+    }
+
+    /// Make the bloom index entry of `epoch_number` follow the pivot block
+    /// `epoch_hash` whose execution is skipped because it has been executed
+    /// before, e.g. off the pivot chain. The bloom is recovered from the
+    /// stored execution results, and if they are not available, the epoch is
+    /// removed from the index so that its ranges are not skipped.
+    pub fn index_executed_epoch_bloom(
+        &self, epoch_number: u64, epoch_hash: &H256,
+        epoch_block_hashes: &Vec<H256>,
+    )
+    {
+        if let Some(epoch_bloom) = self.epoch_bloom(epoch_number) {
+            if epoch_bloom.epoch_hash == *epoch_hash {
+                return;
+            }
+        }
+        let mut bloom = Bloom::zero();
+        for hash in epoch_block_hashes {
+            match self.block_execution_result_by_hash_with_epoch(
+                hash, epoch_hash, false, /* update_cache */
+            ) {
+                Some(result) => bloom.accrue_bloom(&result.bloom),
+                None => {
+                    self.unindex_epoch_bloom(epoch_number);
+                    return;
+                }
+            }
+        }
+        self.index_epoch_bloom(epoch_number, epoch_hash, bloom);
+    }
+
+    /// Remove the logs bloom of an epoch from the bloom index. The aggregated
+    /// blooms keep its logs, but the ranges containing it are incomplete
+    /// until it is indexed again.
+    pub fn unindex_epoch_bloom(&self, epoch_number: u64) {
+        let _lock = self.bloom_index_lock.lock();
+        if self.db_manager.epoch_bloom_from_db(epoch_number).is_none() {
+            return;
+        }
+        self.db_manager.remove_epoch_bloom_from_db(epoch_number);
+        for (level, size) in BLOOM_INDEX_LEVEL_SIZES.iter().enumerate() {
+            let level = level as u8 + 1;
+            let index = epoch_number / size;
+            if let Some(mut aggregated) =
+                self.db_manager.aggregated_bloom_from_db(level, index)
+            {
+                aggregated.indexed_epochs =
+                    aggregated.indexed_epochs.saturating_sub(1);
+                self.db_manager.insert_aggregated_bloom_to_db(
+                    level,
+                    index,
+                    &aggregated,
+                );
+            }
+        }
+    }
+
+    pub fn epoch_bloom(&self, epoch_number: u64) -> Option<EpochBloom> {
+        self.db_manager.epoch_bloom_from_db(epoch_number)
+    }
+
+    /// The logs bloom of the `index`-th range of
+    /// `BLOOM_INDEX_LEVEL_SIZES[level]` epochs, if all of them are indexed.
+    pub fn aggregated_bloom(&self, level: usize, index: u64) -> Option<Bloom> {
+        self.db_manager
+            .aggregated_bloom_from_db(level as u8 + 1, index)
+            .filter(|aggregated| {
+                aggregated.indexed_epochs == BLOOM_INDEX_LEVEL_SIZES[level]
+            })
+            .map(|aggregated| aggregated.bloom)
+    }
+
+    /// Return the epoch number after the largest aligned range of epochs
+    /// starting from `epoch_number` and ending no later than `to_epoch`, whose
+    /// aggregated logs bloom in the bloom index doesn't match the filter.
+    pub fn skip_epochs_by_bloom_index<F>(
+        &self, epoch_number: u64, to_epoch: u64, bloom_match: F,
+    ) -> Option<u64>
+    where F: Fn(&Bloom) -> bool {
+        for (level, size) in BLOOM_INDEX_LEVEL_SIZES.iter().enumerate().rev() {
+            if epoch_number % size != 0 || epoch_number + size - 1 > to_epoch {
+                continue;
+            }
+            if let Some(bloom) =
+                self.aggregated_bloom(level, epoch_number / size)
+            {
+                if !bloom_match(&bloom) {
+                    return Some(epoch_number + size);
+                }
+            }
+        }
+        None
+    }
+
     /// This only inserts reference because the object will be stored in
     /// ConsensusInner
     pub fn insert_consensus_graph_execution_info_to_db(
@@ -588,6 +828,20 @@ impl BlockDataManager {
         self.db_manager.consensus_graph_execution_info_from_db(hash)
     }
 
+    /// Persist the reward breakdown of the blocks in the epoch with the given
+    /// pivot block hash.
+    pub fn insert_epoch_rewards_to_db(
+        &self, hash: &H256, rewards: &Vec<BlockRewardResult>,
+    ) {
+        self.db_manager.insert_epoch_rewards_to_db(hash, rewards)
+    }
+
+    pub fn epoch_rewards_from_db(
+        &self, hash: &H256,
+    ) -> Option<Vec<BlockRewardResult>> {
+        self.db_manager.epoch_rewards_from_db(hash)
+    }
+
     pub fn insert_epoch_set_hashes_to_db(
         &self, epoch_number: u64, epoch_set: &Vec<H256>,
     ) {
@@ -595,6 +849,10 @@ impl BlockDataManager {
             .insert_epoch_set_hashes_to_db(epoch_number, epoch_set)
     }
 
+    pub fn deferred_state_epoch_count(&self) -> u64 {
+        self.config.deferred_state_epoch_count
+    }
+
     pub fn epoch_set_hashes_from_db(
         &self, epoch_number: u64,
     ) -> Option<Vec<H256>> {
@@ -706,34 +964,96 @@ impl BlockDataManager {
                     return false;
                 }
             }
-            // Recover tx address if we will skip pivot chain execution
-            for (block_idx, block_hash) in epoch_block_hashes.iter().enumerate()
+            // Recover tx address if we will skip pivot chain execution. The
+            // block bodies may have been pruned, in which case the addresses
+            // are left as they are.
+            if let Err(e) = self.recover_transaction_addresses(
+                epoch_hash,
+                epoch_block_hashes,
+                &epoch_receipts,
+                true, /* update_cache */
+            ) {
+                warn!(
+                    "Failed to recover transaction addresses of epoch {:?}: {}",
+                    epoch_hash, e
+                );
+            }
+        }
+        true
+    }
+
+    /// Insert the addresses of the executed transactions in the epoch
+    /// `epoch_hash` according to the receipts of its blocks, and return the
+    /// number of the inserted addresses. It fails if a block body does not
+    /// exist, e.g. it has been pruned, or if the receipts do not match the
+    /// transactions.
+    pub fn recover_transaction_addresses(
+        &self, epoch_hash: &H256, epoch_block_hashes: &Vec<H256>,
+        epoch_receipts: &Vec<Arc<Vec<Receipt>>>, update_cache: bool,
+    ) -> Result<usize, String>
+    {
+        if epoch_block_hashes.len() != epoch_receipts.len() {
+            return Err(format!(
+                "{} blocks in epoch {:?} but {} block receipts",
+                epoch_block_hashes.len(),
+                epoch_hash,
+                epoch_receipts.len()
+            ));
+        }
+
+        let mut recovered = 0;
+        for (block_hash, receipts) in
+            epoch_block_hashes.iter().zip(epoch_receipts)
+        {
+            let block =
+                self.block_by_hash(block_hash, update_cache).ok_or_else(
+                    || format!("Block body {:?} does not exist", block_hash),
+                )?;
+            if block.transactions.len() != receipts.len() {
+                return Err(format!(
+                    "{} transactions in block {:?} but {} receipts",
+                    block.transactions.len(),
+                    block_hash,
+                    receipts.len()
+                ));
+            }
+            for (tx_idx, (tx, receipt)) in
+                block.transactions.iter().zip(receipts.iter()).enumerate()
             {
-                let block = self
-                    .block_by_hash(block_hash, true /* update_cache */)
-                    .expect("block exists");
-                for (tx_idx, tx) in block.transactions.iter().enumerate() {
-                    match epoch_receipts[block_idx]
-                        .get(tx_idx)
-                        .unwrap()
-                        .outcome_status
-                    {
-                        TRANSACTION_OUTCOME_SUCCESS
-                        | TRANSACTION_OUTCOME_EXCEPTION_WITH_NONCE_BUMPING => {
-                            self.insert_transaction_address(
-                                &tx.hash,
-                                &TransactionAddress {
-                                    block_hash: *block_hash,
-                                    index: tx_idx,
-                                },
-                            )
-                        }
-                        _ => {}
+                match receipt.outcome_status {
+                    TRANSACTION_OUTCOME_SUCCESS
+                    | TRANSACTION_OUTCOME_EXCEPTION_WITH_NONCE_BUMPING => {
+                        self.insert_transaction_address(
+                            &tx.hash,
+                            &TransactionAddress {
+                                block_hash: *block_hash,
+                                index: tx_idx,
+                                epoch_hash: *epoch_hash,
+                            },
+                        );
+                        recovered += 1;
                     }
+                    _ => {}
                 }
             }
         }
-        true
+        Ok(recovered)
+    }
+
+    pub fn record_tx_address(&self) -> bool { self.config.record_tx_address }
+
+    /// The next epoch to reindex if a reindexing is interrupted.
+    pub fn reindex_progress(&self) -> Option<u64> {
+        self.db_manager.reindex_progress_from_db()
+    }
+
+    /// Record the next epoch to reindex, or remove the record if the
+    /// reindexing finishes.
+    pub fn set_reindex_progress(&self, next_epoch: Option<u64>) {
+        match next_epoch {
+            Some(epoch) => self.db_manager.insert_reindex_progress_to_db(epoch),
+            None => self.db_manager.remove_reindex_progress_from_db(),
+        }
     }
 
     pub fn invalidate_block(&self, block_hash: H256) {
@@ -845,27 +1165,389 @@ impl BlockDataManager {
         exeuction_contexts.shrink_to_fit();
     }
 
+    /// Release the cached blocks in the epochs of the old eras, which are no
+    /// longer in consensus. They are still kept in the database.
+    fn old_era_cache_gc(&self) {
+        let cleanups: Vec<_> =
+            self.pending_era_cleanups.lock().drain(..).collect();
+        if cleanups.is_empty() {
+            return;
+        }
+        let in_old_era = |height: u64| {
+            cleanups
+                .iter()
+                .any(|(start, end)| *start <= height && height < *end)
+        };
+        debug!("Release cached blocks of epochs {:?}", cleanups);
+
+        // The blocks are selected by the heights of the cached headers, so
+        // that no epoch set is read from the database.
+        let mut released = HashSet::new();
+        self.block_headers.write().retain(|hash, header| {
+            if in_old_era(header.height()) {
+                released.insert(*hash);
+                false
+            } else {
+                true
+            }
+        });
+        self.blocks.write().retain(|hash, block| {
+            if in_old_era(block.block_header.height()) {
+                released.insert(*hash);
+                false
+            } else {
+                true
+            }
+        });
+        self.compact_blocks
+            .write()
+            .retain(|_, block| !in_old_era(block.block_header.height()));
+        self.block_receipts
+            .write()
+            .retain(|hash, _| !released.contains(hash));
+    }
+
     pub fn cache_gc(&self) {
+        self.old_era_cache_gc();
         self.block_cache_gc();
         self.tx_data_manager.tx_cache_gc();
     }
 
-    pub fn set_cur_consensus_era_genesis_hash(
-        &self, cur_era_hash: &H256, next_era_hash: &H256,
+    /// The earliest epoch whose block bodies and receipts are served.
+    pub fn earliest_block_body_epoch(&self) -> u64 {
+        *self.earliest_block_body_epoch.lock()
+    }
+
+    /// Remove the bodies and execution results of the blocks in the epochs
+    /// more than `block_body_horizon` epochs before `best_epoch_number`,
+    /// while their headers and transaction addresses are kept, see
+    /// `BlockDataManager::transaction_pruned`. Only the epochs before the
+    /// current era genesis are pruned since the others may still be executed.
+    /// It returns the new earliest epoch with block bodies if it is advanced.
+    pub fn prune_block_bodies(&self, best_epoch_number: u64) -> Option<u64> {
+        let horizon = self.config.block_body_horizon?;
+        let era_genesis_height = self
+            .block_height_by_hash(&self.get_cur_consensus_era_genesis_hash())?;
+        let target = min(
+            best_epoch_number.saturating_sub(horizon),
+            era_genesis_height,
+        );
+        let mut earliest_block_body_epoch =
+            self.earliest_block_body_epoch.lock();
+        // The true genesis block is always kept.
+        let start = max(*earliest_block_body_epoch, 1);
+        if start >= target {
+            return None;
+        }
+        let end = min(target, start + MAX_PRUNED_BODY_EPOCHS_PER_ROUND);
+        for epoch_number in start..end {
+            let hashes = match self.epoch_set_hashes_from_db(epoch_number) {
+                Some(hashes) => hashes,
+                None => continue,
+            };
+            for hash in &hashes {
+                self.remove_block_body(hash, true /* remove_db */);
+                self.compact_blocks.write().remove(hash);
+                self.remove_block_results(hash, true /* remove_db */);
+            }
+        }
+        self.db_manager.insert_earliest_block_body_epoch_to_db(end);
+        *earliest_block_body_epoch = end;
+        info!("Pruned the block bodies of the epochs before {}", end);
+        Some(end)
+    }
+
+    /// Whether the transaction `tx_hash` is executed in an epoch whose block
+    /// bodies and receipts have been pruned, i.e. it's known but can only be
+    /// served by an archive node.
+    pub fn transaction_pruned(&self, tx_hash: &H256) -> bool {
+        self.transaction_address_by_hash(tx_hash, false /* update_cache */)
+            .and_then(|address| self.block_height_by_hash(&address.epoch_hash))
+            .map_or(false, |epoch_number| {
+                epoch_number < self.earliest_block_body_epoch()
+            })
+    }
+
+    /// Change the budget of the block caches, which takes effect from the
+    /// next garbage collection.
+    pub fn set_ledger_cache_size(&self, ledger_mb: usize) {
+        let (pref_cache_size, max_cache_size) = ledger_cache_size(ledger_mb);
+        self.cache_man
+            .lock()
+            .set_cache_size(pref_cache_size, max_cache_size);
+    }
+
+    /// Advance the checkpoint to the era genesis `era_genesis_hash` whose
+    /// stable block is `stable_hash`. The checkpoint takes effect in memory
+    /// immediately, and the cached blocks of the old era are scheduled to be
+    /// released. The checkpoint is persisted by the checkpoint worker, so
+    /// that the caller, which holds the consensus lock, isn't blocked by the
+    /// snapshot of its state.
+    pub fn advance_checkpoint(
+        &self, era_genesis_hash: &H256, stable_hash: &H256,
     ) {
-        self.db_manager
-            .insert_checkpoint_hashes_to_db(cur_era_hash, next_era_hash);
+        let prev_era_genesis_hash = self.get_cur_consensus_era_genesis_hash();
+        *self.cur_consensus_era_genesis_hash.write() = *era_genesis_hash;
+        *self.cur_consensus_era_stable_hash.write() = *stable_hash;
+
+        if let (Some(start_height), Some(end_height)) = (
+            self.block_height_by_hash(&prev_era_genesis_hash),
+            self.block_height_by_hash(era_genesis_hash),
+        ) {
+            self.pending_era_cleanups
+                .lock()
+                .push_back((start_height, end_height));
+        }
+
+        let sent = match &*self.checkpoint_worker.lock() {
+            Some((sender, _)) => {
+                sender.send((*era_genesis_hash, *stable_hash)).is_ok()
+            }
+            None => false,
+        };
+        if !sent {
+            Self::persist_checkpoint(
+                &self.db_manager,
+                &self.storage_manager,
+                era_genesis_hash,
+                stable_hash,
+            );
+        }
+    }
 
-        let mut era_hash = self.cur_consensus_era_genesis_hash.write();
-        let mut stable_hash = self.cur_consensus_era_stable_hash.write();
-        *era_hash = cur_era_hash.clone();
-        *stable_hash = next_era_hash.clone();
+    fn start_checkpoint_worker(
+        db_manager: Arc<DBManager>, storage_manager: Arc<StorageManager>,
+    ) -> (mpsc::Sender<(H256, H256)>, thread::JoinHandle<()>) {
+        let (sender, receiver) = mpsc::channel::<(H256, H256)>();
+        let handle = thread::Builder::new()
+            .name("checkpoint_worker".into())
+            .spawn(move || {
+                // The checkpoints are persisted in order, and the worker exits
+                // after the sender is dropped and the queue is drained.
+                for (era_genesis_hash, stable_hash) in receiver {
+                    Self::persist_checkpoint(
+                        &db_manager,
+                        &storage_manager,
+                        &era_genesis_hash,
+                        &stable_hash,
+                    );
+                }
+            })
+            .expect("Failed to start the checkpoint worker");
+        (sender, handle)
+    }
+
+    /// Persist the checkpoint to `era_genesis_hash`. The advancement is
+    /// recorded in the database first, so that it's finished or abandoned by
+    /// `recover_checkpoint_advancement` if the node crashes in between. Then
+    /// the state of the new era genesis is snapshotted and the checkpoint is
+    /// written.
+    fn persist_checkpoint(
+        db_manager: &DBManager, storage_manager: &StorageManager,
+        era_genesis_hash: &H256, stable_hash: &H256,
+    )
+    {
+        db_manager
+            .insert_checkpoint_advancement_to_db(era_genesis_hash, stable_hash);
+        Self::finish_checkpoint_persistence(
+            db_manager,
+            storage_manager,
+            era_genesis_hash,
+            stable_hash,
+        );
+    }
+
+    fn finish_checkpoint_persistence(
+        db_manager: &DBManager, storage_manager: &StorageManager,
+        era_genesis_hash: &H256, stable_hash: &H256,
+    )
+    {
+        // A failed snapshot doesn't block the advancement, since the states
+        // are still available from the delta MPT.
+        if let Err(e) = storage_manager.snapshot_checkpoint(era_genesis_hash) {
+            warn!(
+                "Failed to snapshot the state of checkpoint {:?}: {}",
+                era_genesis_hash, e
+            );
+        }
+        db_manager
+            .insert_checkpoint_hashes_to_db(era_genesis_hash, stable_hash);
+        db_manager.remove_checkpoint_advancement_from_db();
+    }
+
+    /// Stop the cache warm-up, wait for the checkpoints advanced so far to be
+    /// persisted, and stop the background workers. The later checkpoints are
+    /// persisted synchronously.
+    pub fn close(&self) {
+        if let Some((stop, handle)) = self.cache_warm_up.lock().take() {
+            stop.store(true, Ordering::Relaxed);
+            if handle.join().is_err() {
+                error!("The cache warm-up panicked");
+            }
+        }
+        if let Some((sender, handle)) = self.checkpoint_worker.lock().take() {
+            drop(sender);
+            if handle.join().is_err() {
+                error!("The checkpoint worker panicked");
+            }
+        }
+    }
+
+    /// Finish the checkpoint advancement interrupted by a crash if the state
+    /// of the new era genesis exists, and abandon it otherwise.
+    fn recover_checkpoint_advancement(&self) {
+        let (era_genesis_hash, stable_hash) =
+            match self.db_manager.checkpoint_advancement_from_db() {
+                Some(hashes) => hashes,
+                None => return,
+            };
+        let state_exists = self
+            .storage_manager
+            .contains_state(SnapshotAndEpochIdRef::new(&era_genesis_hash, None))
+            .unwrap_or(false);
+        if state_exists {
+            info!(
+                "Finish the interrupted advancement to checkpoint {:?}",
+                era_genesis_hash
+            );
+            Self::finish_checkpoint_persistence(
+                &self.db_manager,
+                &self.storage_manager,
+                &era_genesis_hash,
+                &stable_hash,
+            );
+            *self.cur_consensus_era_genesis_hash.write() = era_genesis_hash;
+            *self.cur_consensus_era_stable_hash.write() = stable_hash;
+        } else {
+            warn!(
+                "Abandon the interrupted advancement to checkpoint {:?}",
+                era_genesis_hash
+            );
+            self.db_manager.remove_checkpoint_advancement_from_db();
+        }
     }
 
     pub fn get_cur_consensus_era_genesis_hash(&self) -> H256 {
         self.cur_consensus_era_genesis_hash.read().clone()
     }
 
+    /// Save the most recently used cache entries if the cache warm-up is
+    /// enabled, which are loaded by `start_cache_warm_up` after a restart.
+    pub fn save_hot_cache_entries(&self) {
+        let conf = match self.config.cache_warm_up {
+            Some(ref conf) => conf,
+            None => return,
+        };
+
+        let mut entries = HotCacheEntries::default();
+        for id in self.cache_man.lock().hot_entries(conf.max_entries) {
+            match id {
+                CacheId::BlockHeader(hash) => entries.block_headers.push(hash),
+                CacheId::Block(hash) => entries.blocks.push(hash),
+                CacheId::TransactionAddress(hash) => {
+                    entries.transaction_addresses.push(hash)
+                }
+                // The receipts are also keyed by the epoch, and the compact
+                // blocks are only used by the sync of new blocks.
+                CacheId::BlockReceipts(_) | CacheId::CompactBlock(_) => {}
+            }
+        }
+        info!(
+            "Save hot cache entries: {} headers, {} blocks, {} tx addresses",
+            entries.block_headers.len(),
+            entries.blocks.len(),
+            entries.transaction_addresses.len()
+        );
+        self.db_manager.insert_hot_cache_entries_to_db(&entries);
+    }
+
+    /// Load the cache entries saved by `save_hot_cache_entries` in the
+    /// background if the cache warm-up is enabled. At most
+    /// `max_bytes_per_sec` bytes of entries are loaded per second, so that the
+    /// warm-up doesn't starve the other reads of the database. The saved
+    /// entries are removed once they are read, so that a crash afterwards
+    /// doesn't warm up the same stale entries again.
+    pub fn start_cache_warm_up(data_man: &Arc<BlockDataManager>) {
+        let conf = match data_man.config.cache_warm_up {
+            Some(ref conf) => conf.clone(),
+            None => return,
+        };
+        let entries = match data_man.db_manager.hot_cache_entries_from_db() {
+            Some(entries) => entries,
+            None => return,
+        };
+        data_man.db_manager.remove_hot_cache_entries_from_db();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        // The warm-up doesn't keep the data manager alive, so that the
+        // database can be closed while it's still running.
+        let weak = Arc::downgrade(data_man);
+        let handle = thread::Builder::new()
+            .name("cache_warm_up".into())
+            .spawn(move || {
+                let start = Instant::now();
+                let bytes_per_sec = max(conf.max_bytes_per_sec, 1) as u128;
+                // Wait until `loaded_bytes` are within the budget, checking
+                // the stop flag at least every 100ms. Return `None` to stop.
+                let wait = |loaded_bytes: usize| loop {
+                    if stopped.load(Ordering::Relaxed) {
+                        return None;
+                    }
+                    let due = start
+                        + Duration::from_micros(
+                            (loaded_bytes as u128 * 1_000_000 / bytes_per_sec)
+                                as u64,
+                        );
+                    let now = Instant::now();
+                    if due <= now {
+                        return Some(());
+                    }
+                    thread::sleep(min(due - now, Duration::from_millis(100)));
+                };
+
+                let mut loaded = 0;
+                let mut loaded_bytes = 0;
+                let mut warm_up = || -> Option<()> {
+                    for hash in &entries.block_headers {
+                        wait(loaded_bytes)?;
+                        loaded_bytes += weak
+                            .upgrade()?
+                            .block_header_by_hash(hash)
+                            .map_or(0, |header| header.size());
+                        loaded += 1;
+                    }
+                    for hash in &entries.blocks {
+                        wait(loaded_bytes)?;
+                        loaded_bytes += weak
+                            .upgrade()?
+                            .block_by_hash(hash, true /* update_cache */)
+                            .map_or(0, |block| block.size());
+                        loaded += 1;
+                    }
+                    for hash in &entries.transaction_addresses {
+                        wait(loaded_bytes)?;
+                        weak.upgrade()?.transaction_address_by_hash(
+                            hash, true, /* update_cache */
+                        );
+                        loaded_bytes += mem::size_of::<TransactionAddress>();
+                        loaded += 1;
+                    }
+                    Some(())
+                };
+                let finished = warm_up().is_some();
+                info!(
+                    "Cache warm-up {}, {} entries ({} bytes) loaded in {:?}",
+                    if finished { "finished" } else { "stopped" },
+                    loaded,
+                    loaded_bytes,
+                    start.elapsed()
+                );
+            })
+            .expect("Thread spawn failure");
+        *data_man.cache_warm_up.lock() = Some((stop, handle));
+    }
+
     pub fn get_cur_consensus_era_stable_hash(&self) -> H256 {
         self.cur_consensus_era_stable_hash.read().clone()
     }
@@ -900,20 +1582,46 @@ pub enum DbType {
     Sqlite,
 }
 
+/// The cache warm-up after a restart, see
+/// `BlockDataManager::start_cache_warm_up`.
+#[derive(Clone)]
+pub struct CacheWarmUpConfig {
+    /// The maximum number of the cache entries saved at shutdown.
+    pub max_entries: usize,
+    /// The maximum number of the bytes of the cache entries loaded per
+    /// second, as the block bodies vary widely in size.
+    pub max_bytes_per_sec: usize,
+}
+
 pub struct DataManagerConfiguration {
     record_tx_address: bool,
     tx_cache_count: usize,
     db_type: DbType,
+    /// If set, only the block bodies and receipts of the recent epochs within
+    /// the horizon are kept, see `BlockDataManager::prune_block_bodies`.
+    block_body_horizon: Option<u64>,
+    /// The number of epochs by which the execution is deferred, which is the
+    /// same as `ConsensusInnerConfig::deferred_state_epoch_count`.
+    deferred_state_epoch_count: u64,
+    /// If set, the hot cache entries are saved at shutdown and loaded again
+    /// after a restart.
+    cache_warm_up: Option<CacheWarmUpConfig>,
 }
 
 impl DataManagerConfiguration {
     pub fn new(
         record_tx_address: bool, tx_cache_count: usize, db_type: DbType,
-    ) -> Self {
+        block_body_horizon: Option<u64>, deferred_state_epoch_count: u64,
+        cache_warm_up: Option<CacheWarmUpConfig>,
+    ) -> Self
+    {
         Self {
             record_tx_address,
             tx_cache_count,
             db_type,
+            block_body_horizon,
+            deferred_state_epoch_count,
+            cache_warm_up,
         }
     }
 }
@@ -0,0 +1,570 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::{
+    BlockDataManager, BlockRewardResult, CacheWarmUpConfig,
+    DataManagerConfiguration, DbType, BLOOM_INDEX_LEVEL_SIZES,
+};
+use crate::{
+    cache_config::CacheConfig,
+    db::NUM_COLUMNS,
+    parameters::consensus::DEFERRED_STATE_EPOCH_COUNT,
+    storage::{state_manager::StorageConfiguration, StorageManager},
+    transaction_pool::DEFAULT_MAX_BLOCK_GAS_LIMIT,
+};
+use cfx_types::{Address, Bloom, BloomInput, H256, U256};
+use parking_lot::Mutex;
+use primitives::{
+    log_entry::LogEntry,
+    receipt::{
+        Receipt, TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING,
+        TRANSACTION_OUTCOME_SUCCESS,
+    },
+    Block, BlockHeaderBuilder, TransactionAddress,
+};
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+use threadpool::ThreadPool;
+
+fn open_data_man(
+    db_dir: &str, config: DataManagerConfiguration,
+) -> BlockDataManager {
+    let ledger_db = db::open_database(
+        db_dir,
+        &db::db_config(
+            Path::new(db_dir),
+            Some(128),
+            db::DatabaseCompactionProfile::default(),
+            NUM_COLUMNS,
+            false,
+        ),
+    )
+    .unwrap();
+    let storage_manager = Arc::new(StorageManager::new(
+        ledger_db.clone(),
+        StorageConfiguration::default(),
+    ));
+    let genesis_block = Arc::new(storage_manager.initialize(
+        HashMap::new(),
+        DEFAULT_MAX_BLOCK_GAS_LIMIT.into(),
+        Address::zero(),
+        U256::from(10),
+    ));
+    BlockDataManager::new(
+        CacheConfig::default(),
+        genesis_block,
+        ledger_db,
+        storage_manager,
+        Arc::new(Mutex::new(ThreadPool::new(1))),
+        config,
+    )
+}
+
+fn default_config() -> DataManagerConfiguration {
+    DataManagerConfiguration::new(
+        false,
+        250000,
+        DbType::Rocksdb,
+        None,
+        DEFERRED_STATE_EPOCH_COUNT,
+        None,
+    )
+}
+
+/// Run `f` with a fresh database directory, which is removed afterwards.
+fn with_db_dir<F: FnOnce(&str)>(db_dir: &str, f: F) {
+    let _ = fs::remove_dir_all(db_dir);
+    f(db_dir);
+    fs::remove_dir_all(db_dir).unwrap();
+}
+
+#[test]
+fn test_advance_checkpoint_in_background() {
+    with_db_dir("./data_man_checkpoint_test_db", |db_dir| {
+        let stable_hash = H256::from_low_u64_be(1);
+        let data_man = open_data_man(db_dir, default_config());
+        let genesis_hash = data_man.true_genesis_block.hash();
+        data_man.advance_checkpoint(&genesis_hash, &stable_hash);
+        assert_eq!(data_man.get_cur_consensus_era_stable_hash(), stable_hash);
+
+        // The pending checkpoints are persisted before `close` returns.
+        data_man.close();
+        assert_eq!(
+            data_man.db_manager.checkpoint_hashes_from_db(),
+            Some((genesis_hash, stable_hash))
+        );
+        assert_eq!(data_man.db_manager.checkpoint_advancement_from_db(), None);
+
+        // The checkpoints advanced after `close` are persisted synchronously.
+        let stable_hash = H256::from_low_u64_be(2);
+        data_man.advance_checkpoint(&genesis_hash, &stable_hash);
+        assert_eq!(
+            data_man.db_manager.checkpoint_hashes_from_db(),
+            Some((genesis_hash, stable_hash))
+        );
+    });
+}
+
+#[test]
+fn test_recover_checkpoint_advancement_after_crash() {
+    with_db_dir("./data_man_recovery_test_db", |db_dir| {
+        let stable_hash = H256::from_low_u64_be(1);
+        let genesis_hash = {
+            // Crash after the advancement to a checkpoint whose state exists
+            // is recorded.
+            let data_man = open_data_man(db_dir, default_config());
+            let genesis_hash = data_man.true_genesis_block.hash();
+            data_man.db_manager.insert_checkpoint_advancement_to_db(
+                &genesis_hash,
+                &stable_hash,
+            );
+            data_man.close();
+            genesis_hash
+        };
+        {
+            // The advancement is finished after the restart.
+            let data_man = open_data_man(db_dir, default_config());
+            assert_eq!(
+                data_man.db_manager.checkpoint_hashes_from_db(),
+                Some((genesis_hash, stable_hash))
+            );
+            assert_eq!(
+                data_man.db_manager.checkpoint_advancement_from_db(),
+                None
+            );
+            assert_eq!(
+                data_man.get_cur_consensus_era_stable_hash(),
+                stable_hash
+            );
+
+            // Crash after the advancement to a checkpoint without state is
+            // recorded.
+            data_man.db_manager.insert_checkpoint_advancement_to_db(
+                &H256::from_low_u64_be(3),
+                &H256::from_low_u64_be(4),
+            );
+            data_man.close();
+        }
+        {
+            // The advancement is abandoned after the restart.
+            let data_man = open_data_man(db_dir, default_config());
+            assert_eq!(
+                data_man.db_manager.checkpoint_hashes_from_db(),
+                Some((genesis_hash, stable_hash))
+            );
+            assert_eq!(
+                data_man.db_manager.checkpoint_advancement_from_db(),
+                None
+            );
+            assert_eq!(
+                data_man.get_cur_consensus_era_genesis_hash(),
+                genesis_hash
+            );
+            data_man.close();
+        }
+    });
+}
+
+#[test]
+fn test_save_and_warm_up_hot_cache_entries() {
+    with_db_dir("./data_man_warm_up_test_db", |db_dir| {
+        let config = || {
+            DataManagerConfiguration::new(
+                false,
+                250000,
+                DbType::Rocksdb,
+                None,
+                DEFERRED_STATE_EPOCH_COUNT,
+                Some(CacheWarmUpConfig {
+                    max_entries: 10,
+                    max_bytes_per_sec: 1024 * 1024,
+                }),
+            )
+        };
+
+        let mut header = BlockHeaderBuilder::new().with_height(1).build();
+        header.compute_hash();
+        let hash = header.hash();
+        let saved = {
+            let data_man = open_data_man(db_dir, config());
+            data_man.insert_block_header(hash, Arc::new(header), true);
+            data_man.save_hot_cache_entries();
+            data_man.close();
+            data_man.db_manager.hot_cache_entries_from_db().unwrap()
+        };
+        assert!(saved.block_headers.contains(&hash));
+
+        let data_man = Arc::new(open_data_man(db_dir, config()));
+        assert!(!data_man.block_headers.read().contains_key(&hash));
+        BlockDataManager::start_cache_warm_up(&data_man);
+        // The saved entries are removed once they are read.
+        assert!(data_man.db_manager.hot_cache_entries_from_db().is_none());
+        let (_, handle) = data_man.cache_warm_up.lock().take().unwrap();
+        handle.join().unwrap();
+        assert!(data_man.block_headers.read().contains_key(&hash));
+
+        // `close` stops a running warm-up.
+        data_man.save_hot_cache_entries();
+        data_man.close();
+        drop(data_man);
+        let data_man = Arc::new(open_data_man(db_dir, config()));
+        BlockDataManager::start_cache_warm_up(&data_man);
+        data_man.close();
+        assert!(data_man.cache_warm_up.lock().is_none());
+    });
+}
+
+#[test]
+fn test_recover_transaction_addresses_with_mismatched_receipts() {
+    with_db_dir("./data_man_recover_tx_address_test_db", |db_dir| {
+        let data_man = open_data_man(db_dir, default_config());
+        let genesis = data_man.genesis_block();
+        let genesis_hash = genesis.hash();
+        let receipts = |count: usize| {
+            Arc::new(
+                (0..count)
+                    .map(|_| {
+                        Receipt::new(
+                            TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING,
+                            U256::zero(),
+                            vec![],
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let num_txs = genesis.transactions.len();
+
+        // The transactions without nonce bumping are not indexed.
+        assert_eq!(
+            data_man.recover_transaction_addresses(
+                &genesis_hash,
+                &vec![genesis_hash],
+                &vec![receipts(num_txs)],
+                false,
+            ),
+            Ok(0)
+        );
+        // A block without receipts.
+        assert!(data_man
+            .recover_transaction_addresses(
+                &genesis_hash,
+                &vec![genesis_hash],
+                &vec![],
+                false,
+            )
+            .is_err());
+        // More receipts than transactions.
+        assert!(data_man
+            .recover_transaction_addresses(
+                &genesis_hash,
+                &vec![genesis_hash],
+                &vec![receipts(num_txs + 1)],
+                false,
+            )
+            .is_err());
+        // The block body does not exist.
+        let missing_hash = H256::from_low_u64_be(1);
+        assert!(data_man
+            .recover_transaction_addresses(
+                &missing_hash,
+                &vec![missing_hash],
+                &vec![receipts(0)],
+                false,
+            )
+            .is_err());
+        data_man.close();
+    });
+}
+
+#[test]
+fn test_prune_block_bodies() {
+    with_db_dir("./data_man_prune_block_bodies_test_db", |db_dir| {
+        let config = |block_body_horizon| {
+            DataManagerConfiguration::new(
+                true,
+                250000,
+                DbType::Rocksdb,
+                block_body_horizon,
+                DEFERRED_STATE_EPOCH_COUNT,
+                None,
+            )
+        };
+        let data_man = open_data_man(db_dir, config(Some(2)));
+        let mut parent_hash = data_man.true_genesis_block.hash();
+        let mut hashes = vec![parent_hash];
+        for height in 1..=10 {
+            let mut header = BlockHeaderBuilder::new()
+                .with_parent_hash(parent_hash)
+                .with_height(height)
+                .build();
+            header.compute_hash();
+            let hash = header.hash();
+            data_man.insert_block(Arc::new(Block::new(header, vec![])), true);
+            data_man.insert_epoch_set_hashes_to_db(height, &vec![hash]);
+            data_man.insert_block_results(hash, hash, Arc::new(vec![]), true);
+            hashes.push(hash);
+            parent_hash = hash;
+        }
+        let tx_in_epoch = |height: usize| {
+            let tx_hash = H256::from_low_u64_be(height as u64);
+            data_man.insert_transaction_address(
+                &tx_hash,
+                &TransactionAddress {
+                    block_hash: hashes[height],
+                    index: 0,
+                    epoch_hash: hashes[height],
+                },
+            );
+            tx_hash
+        };
+        let pruned_tx = tx_in_epoch(3);
+        let kept_tx = tx_in_epoch(7);
+
+        // The epochs in the current era are not pruned.
+        assert_eq!(data_man.prune_block_bodies(10), None);
+
+        *data_man.cur_consensus_era_genesis_hash.write() = hashes[6];
+        assert_eq!(data_man.prune_block_bodies(10), Some(6));
+        assert_eq!(data_man.earliest_block_body_epoch(), 6);
+        for (height, hash) in hashes.iter().enumerate() {
+            let pruned = height >= 1 && height < 6;
+            assert_eq!(
+                data_man.block_by_hash(hash, false).is_none(),
+                pruned,
+                "block body of epoch {}",
+                height
+            );
+            assert!(data_man.block_header_by_hash(hash).is_some());
+            if height >= 1 {
+                assert_eq!(
+                    data_man
+                        .block_execution_result_by_hash_from_db(hash)
+                        .is_none(),
+                    pruned
+                );
+            }
+        }
+        // The addresses are kept, so that the pruned transactions can be told
+        // apart from the unknown ones.
+        assert!(data_man
+            .transaction_address_by_hash(&pruned_tx, false)
+            .is_some());
+        assert!(data_man.transaction_pruned(&pruned_tx));
+        assert!(!data_man.transaction_pruned(&kept_tx));
+        assert!(!data_man.transaction_pruned(&H256::from_low_u64_be(100)));
+        // Nothing more to prune until the horizon or the era advances.
+        assert_eq!(data_man.prune_block_bodies(10), None);
+
+        *data_man.cur_consensus_era_genesis_hash.write() = hashes[10];
+        assert_eq!(data_man.prune_block_bodies(10), Some(8));
+        data_man.close();
+        drop(data_man);
+
+        // The earliest epoch with block bodies is persisted, and nothing is
+        // pruned without a horizon.
+        let data_man = open_data_man(db_dir, config(None));
+        assert_eq!(data_man.earliest_block_body_epoch(), 8);
+        *data_man.cur_consensus_era_genesis_hash.write() = hashes[10];
+        assert_eq!(data_man.prune_block_bodies(10), None);
+        data_man.close();
+    });
+}
+
+fn bloom_of(data: &[u8]) -> Bloom {
+    let mut bloom = Bloom::zero();
+    bloom.accrue(BloomInput::Raw(data));
+    bloom
+}
+
+#[test]
+fn test_bloom_index_aggregation() {
+    with_db_dir("./data_man_bloom_index_test_db", |db_dir| {
+        let data_man = open_data_man(db_dir, default_config());
+        let size = BLOOM_INDEX_LEVEL_SIZES[0];
+        for epoch in 0..size {
+            assert!(data_man.aggregated_bloom(0, 0).is_none());
+            data_man.index_epoch_bloom(
+                epoch,
+                &H256::from_low_u64_be(epoch),
+                bloom_of(&epoch.to_be_bytes()),
+            );
+        }
+        let aggregated = data_man.aggregated_bloom(0, 0).unwrap();
+        for epoch in 0..size {
+            assert!(aggregated.contains_bloom(&bloom_of(&epoch.to_be_bytes())));
+        }
+        // The ranges of the next level are not complete yet.
+        assert!(data_man.aggregated_bloom(1, 0).is_none());
+
+        // Indexing an epoch again does not count it twice.
+        data_man.index_epoch_bloom(
+            size,
+            &H256::from_low_u64_be(size),
+            bloom_of(b"again"),
+        );
+        data_man.index_epoch_bloom(
+            size,
+            &H256::from_low_u64_be(size),
+            bloom_of(b"again"),
+        );
+        assert_eq!(
+            data_man
+                .db_manager
+                .aggregated_bloom_from_db(1, 1)
+                .unwrap()
+                .indexed_epochs,
+            1
+        );
+        data_man.close();
+    });
+}
+
+#[test]
+fn test_skip_epochs_by_bloom_index() {
+    with_db_dir("./data_man_bloom_index_skip_test_db", |db_dir| {
+        let data_man = open_data_man(db_dir, default_config());
+        let size = BLOOM_INDEX_LEVEL_SIZES[0];
+        for epoch in 0..(2 * size) {
+            let bloom = if epoch == size + 1 {
+                bloom_of(b"wanted")
+            } else {
+                bloom_of(b"other")
+            };
+            data_man.index_epoch_bloom(
+                epoch,
+                &H256::from_low_u64_be(epoch),
+                bloom,
+            );
+        }
+        let wanted = bloom_of(b"wanted");
+        let bloom_match = |bloom: &Bloom| bloom.contains_bloom(&wanted);
+
+        // The first range does not contain the logs.
+        assert_eq!(
+            data_man.skip_epochs_by_bloom_index(0, 3 * size, &bloom_match),
+            Some(size)
+        );
+        // The second range contains them.
+        assert_eq!(
+            data_man.skip_epochs_by_bloom_index(size, 3 * size, &bloom_match),
+            None
+        );
+        // Unaligned epochs and ranges beyond `to_epoch` are not skipped.
+        assert_eq!(
+            data_man.skip_epochs_by_bloom_index(1, 3 * size, &bloom_match),
+            None
+        );
+        assert_eq!(
+            data_man.skip_epochs_by_bloom_index(0, size - 2, &bloom_match),
+            None
+        );
+        // Incomplete ranges are not skipped.
+        assert_eq!(
+            data_man.skip_epochs_by_bloom_index(
+                2 * size,
+                3 * size,
+                &bloom_match
+            ),
+            None
+        );
+        data_man.close();
+    });
+}
+
+#[test]
+fn test_bloom_index_after_pivot_switch() {
+    with_db_dir("./data_man_bloom_index_pivot_test_db", |db_dir| {
+        let data_man = open_data_man(db_dir, default_config());
+        let size = BLOOM_INDEX_LEVEL_SIZES[0];
+        for epoch in 0..size {
+            data_man.index_epoch_bloom(
+                epoch,
+                &H256::from_low_u64_be(epoch),
+                bloom_of(b"old"),
+            );
+        }
+        let wanted = bloom_of(b"new");
+        let bloom_match = |bloom: &Bloom| bloom.contains_bloom(&wanted);
+        assert_eq!(
+            data_man.skip_epochs_by_bloom_index(0, size, &bloom_match),
+            Some(size)
+        );
+
+        // The new pivot block of epoch 1 was executed off the pivot chain,
+        // and its execution results are no longer available.
+        let new_pivot = H256::from_low_u64_be(size + 1);
+        data_man.index_executed_epoch_bloom(1, &new_pivot, &vec![new_pivot]);
+        assert!(data_man.epoch_bloom(1).is_none());
+        assert!(data_man.aggregated_bloom(0, 0).is_none());
+        assert_eq!(
+            data_man.skip_epochs_by_bloom_index(0, size, &bloom_match),
+            None
+        );
+
+        // With the stored execution results, the new logs are indexed.
+        let receipt = Receipt::new(
+            TRANSACTION_OUTCOME_SUCCESS,
+            U256::zero(),
+            vec![LogEntry {
+                address: Address::zero(),
+                topics: vec![],
+                data: vec![],
+            }],
+        );
+        let log_bloom = receipt.log_bloom;
+        data_man.insert_block_results(
+            new_pivot,
+            new_pivot,
+            Arc::new(vec![receipt]),
+            true,
+        );
+        data_man.index_executed_epoch_bloom(1, &new_pivot, &vec![new_pivot]);
+        let epoch_bloom = data_man.epoch_bloom(1).unwrap();
+        assert_eq!(epoch_bloom.epoch_hash, new_pivot);
+        assert_eq!(epoch_bloom.bloom, log_bloom);
+        let aggregated = data_man.aggregated_bloom(0, 0).unwrap();
+        assert!(aggregated.contains_bloom(&log_bloom));
+        let bloom_match = |bloom: &Bloom| bloom.contains_bloom(&log_bloom);
+        assert_eq!(
+            data_man.skip_epochs_by_bloom_index(0, size, &bloom_match),
+            None
+        );
+        data_man.close();
+    });
+}
+
+#[test]
+fn test_epoch_rewards_round_trip() {
+    with_db_dir("./data_man_epoch_rewards_test_db", |db_dir| {
+        let epoch_hash = H256::from_low_u64_be(1);
+        let rewards = vec![
+            BlockRewardResult {
+                block_hash: H256::from_low_u64_be(2),
+                author: Address::from_low_u64_be(1),
+                base_reward: U256::from(900),
+                anticone_penalty: U256::from(9),
+                tx_fee: U256::from(21000),
+            },
+            BlockRewardResult {
+                block_hash: epoch_hash,
+                author: Address::from_low_u64_be(2),
+                base_reward: U256::zero(),
+                anticone_penalty: U256::zero(),
+                tx_fee: U256::zero(),
+            },
+        ];
+        assert_eq!(rewards[0].total_reward(), U256::from(21891));
+        assert_eq!(rewards[1].total_reward(), U256::zero());
+
+        let data_man = open_data_man(db_dir, default_config());
+        assert_eq!(data_man.epoch_rewards_from_db(&epoch_hash), None);
+        data_man.insert_epoch_rewards_to_db(&epoch_hash, &rewards);
+        assert_eq!(data_man.epoch_rewards_from_db(&epoch_hash), Some(rewards));
+        assert_eq!(
+            data_man.epoch_rewards_from_db(&H256::from_low_u64_be(2)),
+            None
+        );
+        data_man.close();
+    });
+}
@@ -1,4 +1,4 @@
-use crate::{cache_manager::CacheManager, WORKER_COMPUTATION_PARALLELISM};
+use crate::cache_manager::CacheManager;
 use cfx_types::H256;
 use parking_lot::{Mutex, RwLock};
 use primitives::{
@@ -7,13 +7,20 @@ use primitives::{
 };
 use rlp::DecoderError;
 use std::{
+    cmp::max,
     collections::HashMap,
     sync::{mpsc::channel, Arc},
 };
 use threadpool::ThreadPool;
 
+/// The minimum number of transactions whose public keys are recovered in a
+/// batch, so that small batches are not dispatched to the worker pool.
+const MIN_RECOVER_BATCH_SIZE: usize = 8;
+
 pub struct TransactionDataManager {
     tx_cache: RwLock<HashMap<H256, Arc<SignedTransaction>>>,
+    /// The dedicated thread pool to recover the public keys of transactions
+    /// in batches. Its size is configurable.
     worker_pool: Arc<Mutex<ThreadPool>>,
     tx_cache_man: Mutex<CacheManager<H256>>,
 }
@@ -140,91 +147,46 @@ impl TransactionDataManager {
     /// Recover public key for `uncached_trans` and keep the corresponding index
     /// unchanged.
     ///
+    /// The transactions are split into batches of at least
+    /// `MIN_RECOVER_BATCH_SIZE` transactions, one per worker at most. The
+    /// calling thread recovers one batch while the worker pool recovers the
+    /// others.
+    ///
     /// Note that we release `tx_cache` lock during pubkey recovery to allow
     /// more parallelism, but we may recover a tx twice if it is received
     /// again before the recovery finishes.
     fn recover_uncached_tx(
-        &self, uncached_trans: Vec<(usize, TransactionWithSignature)>,
+        &self, mut uncached_trans: Vec<(usize, TransactionWithSignature)>,
     ) -> Result<Vec<(usize, Arc<SignedTransaction>)>, DecoderError> {
-        let mut recovered_trans = Vec::new();
-        if uncached_trans.len() < WORKER_COMPUTATION_PARALLELISM * 8 {
-            for (idx, tx) in uncached_trans {
-                if let Ok(public) = tx.recover_public() {
-                    recovered_trans.push((
-                        idx,
-                        Arc::new(SignedTransaction::new(public, tx.clone())),
-                    ));
-                } else {
-                    info!(
-                        "Unable to recover the public key of transaction {:?}",
-                        tx.hash()
-                    );
-                    return Err(DecoderError::Custom(
-                        "Cannot recover public key",
-                    ));
-                }
-            }
-        } else {
-            let tx_num = uncached_trans.len();
-            let tx_num_per_worker = tx_num / WORKER_COMPUTATION_PARALLELISM;
-            let mut remainder =
-                tx_num - (tx_num_per_worker * WORKER_COMPUTATION_PARALLELISM);
-            let mut start_idx = 0;
-            let mut end_idx = 0;
-            let mut unsigned_trans = Vec::new();
-
-            for tx in uncached_trans {
-                if start_idx == end_idx {
-                    // a new segment of transactions
-                    end_idx = start_idx + tx_num_per_worker;
-                    if remainder > 0 {
-                        end_idx += 1;
-                        remainder -= 1;
-                    }
-                    let unsigned_txes = Vec::new();
-                    unsigned_trans.push(unsigned_txes);
-                }
-
-                unsigned_trans.last_mut().unwrap().push(tx);
-
-                start_idx += 1;
-            }
-
-            let (sender, receiver) = channel();
-            let n_thread = unsigned_trans.len();
-            for unsigned_txes in unsigned_trans {
-                let sender = sender.clone();
-                self.worker_pool.lock().execute(move || {
-                    let mut signed_txes = Vec::new();
-                    for (idx, tx) in unsigned_txes {
-                        if let Ok(public) = tx.recover_public() {
-                            signed_txes.push((idx, Arc::new(SignedTransaction::new(
-                                public,
-                                tx.clone(),
-                            ))));
-                        } else {
-                            info!(
-                                "Unable to recover the public key of transaction {:?}",
-                                tx.hash()
-                            );
-                            break;
-                        }
-                    }
-                    sender.send(signed_txes).unwrap();
-                });
-            }
+        let parallelism = self.worker_pool.lock().max_count();
+        let batch_size = max(
+            MIN_RECOVER_BATCH_SIZE,
+            (uncached_trans.len() + parallelism - 1) / parallelism,
+        );
+        let mut batches = Vec::new();
+        while uncached_trans.len() > batch_size {
+            let rest = uncached_trans.split_off(batch_size);
+            batches.push(uncached_trans);
+            uncached_trans = rest;
+        }
 
-            let mut total_recovered_num = 0 as usize;
-            for tx_publics in receiver.iter().take(n_thread) {
-                total_recovered_num += tx_publics.len();
-                for (idx, tx) in tx_publics {
-                    recovered_trans.push((idx, tx));
-                }
-            }
-            if total_recovered_num != tx_num {
-                return Err(DecoderError::Custom("Cannot recover public key"));
-            }
+        let (sender, receiver) = channel();
+        let n_batches = batches.len();
+        for batch in batches {
+            let sender = sender.clone();
+            self.worker_pool.lock().execute(move || {
+                sender.send(recover_batch(batch)).ok();
+            });
         }
+        let mut recovered_trans = recover_batch(uncached_trans);
+        for batch_result in receiver.iter().take(n_batches) {
+            recovered_trans = recovered_trans.and_then(|mut recovered| {
+                recovered.extend(batch_result?);
+                Ok(recovered)
+            });
+        }
+        let recovered_trans = recovered_trans?;
+
         let mut tx_cache = self.tx_cache.write();
         let mut tx_cache_man = self.tx_cache_man.lock();
         for (_, tx) in &recovered_trans {
@@ -286,3 +248,92 @@ impl TransactionDataManager {
         tx_cache.shrink_to_fit();
     }
 }
+
+/// Recover the public keys of a batch of transactions, and fail if any of
+/// them can not be recovered.
+fn recover_batch(
+    batch: Vec<(usize, TransactionWithSignature)>,
+) -> Result<Vec<(usize, Arc<SignedTransaction>)>, DecoderError> {
+    let mut signed_trans = Vec::with_capacity(batch.len());
+    for (idx, tx) in batch {
+        match tx.recover_public() {
+            Ok(public) => signed_trans
+                .push((idx, Arc::new(SignedTransaction::new(public, tx)))),
+            Err(_) => {
+                info!(
+                    "Unable to recover the public key of transaction {:?}",
+                    tx.hash()
+                );
+                return Err(DecoderError::Custom("Cannot recover public key"));
+            }
+        }
+    }
+    Ok(signed_trans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransactionDataManager;
+    use cfx_types::{Address, U256};
+    use keylib::{Generator, Random};
+    use parking_lot::Mutex;
+    use primitives::{Action, Transaction, TransactionWithSignature};
+    use std::sync::Arc;
+    use threadpool::ThreadPool;
+
+    fn new_test_txs(count: usize) -> Vec<TransactionWithSignature> {
+        let sender = Random.generate().unwrap();
+        (0..count)
+            .map(|nonce| {
+                Transaction {
+                    nonce: U256::from(nonce),
+                    gas_price: U256::from(1),
+                    gas: U256::from(21000),
+                    action: Action::Call(Address::random()),
+                    value: U256::zero(),
+                    data: Vec::new(),
+                }
+                .sign(sender.secret())
+                .transaction
+            })
+            .collect()
+    }
+
+    fn new_tx_data_man(threads: usize) -> TransactionDataManager {
+        TransactionDataManager::new(
+            1000,
+            Arc::new(Mutex::new(ThreadPool::new(threads))),
+        )
+    }
+
+    #[test]
+    fn test_recover_in_batches() {
+        let tx_data_man = new_tx_data_man(4);
+        // Fewer transactions than a batch, and more than the workers.
+        for count in &[3, 100] {
+            let txs = new_test_txs(*count);
+            let recovered =
+                tx_data_man.recover_unsigned_tx_with_order(&txs).unwrap();
+            assert_eq!(recovered.len(), txs.len());
+            for (tx, recovered) in txs.iter().zip(&recovered) {
+                assert_eq!(recovered.transaction, *tx);
+                assert!(recovered.public.is_some());
+            }
+            // The recovered transactions are cached.
+            assert!(tx_data_man.recover_unsigned_tx(&txs).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_recover_invalid_signature() {
+        let tx_data_man = new_tx_data_man(2);
+        let mut txs = new_test_txs(50);
+        txs[37].r = U256::zero();
+        txs[37].s = U256::zero();
+        assert!(tx_data_man.recover_unsigned_tx_with_order(&txs).is_err());
+        // Nothing is cached if any transaction fails.
+        let valid_txs = txs[..1].to_vec();
+        let recovered = tx_data_man.recover_unsigned_tx(&valid_txs).unwrap();
+        assert_eq!(recovered.len(), 1);
+    }
+}
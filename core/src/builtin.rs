@@ -73,7 +73,7 @@ impl Linear {
 }
 
 /// A special pricing model for modular exponentiation.
-struct ModexpPricer {
+pub(crate) struct ModexpPricer {
     divisor: usize,
 }
 
@@ -87,11 +87,17 @@ impl Pricer for Linear {
 /// A alt_bn128_parinig pricing model. This computes a price using a base cost
 /// and a cost per pair.
 #[allow(dead_code)]
-struct AltBn128PairingPricer {
+pub(crate) struct AltBn128PairingPricer {
     base: usize,
     pair: usize,
 }
 
+impl AltBn128PairingPricer {
+    pub(crate) fn new(base: usize, pair: usize) -> AltBn128PairingPricer {
+        AltBn128PairingPricer { base, pair }
+    }
+}
+
 impl Pricer for AltBn128PairingPricer {
     fn cost(&self, input: &[u8]) -> U256 {
         let cost = U256::from(self.base)
@@ -154,6 +160,10 @@ impl Pricer for ModexpPricer {
 }
 
 impl ModexpPricer {
+    pub(crate) fn new(divisor: usize) -> ModexpPricer {
+        ModexpPricer { divisor }
+    }
+
     fn adjusted_exp_len(len: u64, exp_low: U256) -> u64 {
         let bit_index = if exp_low.is_zero() {
             0
@@ -215,6 +225,18 @@ impl Builtin {
     }
 }
 
+/// The names of the built-in contracts accepted by `builtin_factory`.
+pub const BUILTIN_NAMES: [&str; 8] = [
+    "identity",
+    "ecrecover",
+    "sha256",
+    "ripemd160",
+    "modexp",
+    "alt_bn128_add",
+    "alt_bn128_mul",
+    "alt_bn128_pairing",
+];
+
 /// Built-in instruction factory.
 pub fn builtin_factory(name: &str) -> Box<dyn Impl> {
     match name {
@@ -61,6 +61,15 @@ where T: Eq + Hash
         }
     }
 
+    /// Change the cache size limits, which take effect from the next garbage
+    /// collection.
+    pub fn set_cache_size(
+        &mut self, pref_cache_size: usize, max_cache_size: usize,
+    ) {
+        self.pref_cache_size = pref_cache_size;
+        self.max_cache_size = max_cache_size;
+    }
+
     pub fn note_used(&mut self, id: T) {
         if !self.cache_usage[0].contains(&id) {
             if let Some(c) = self
@@ -75,6 +84,17 @@ where T: Eq + Hash
         }
     }
 
+    /// The most recently used `limit` entries, roughly in the order of
+    /// recency.
+    pub fn hot_entries(&self, limit: usize) -> Vec<T>
+    where T: Clone {
+        self.cache_usage
+            .iter()
+            .flat_map(|ids| ids.iter().cloned())
+            .take(limit)
+            .collect()
+    }
+
     /// Collects unused objects from cache.
     /// First params is the current size of the cache.
     /// Second one is an with objects to remove. It should also return new size
@@ -137,3 +157,32 @@ impl CacheSize {
             + self.compact_blocks
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::CacheManager;
+
+    #[test]
+    fn test_hot_entries_in_order_of_recency() {
+        // The usage set is rotated once it has more than one entry.
+        let mut cache_man = CacheManager::new(80, 160, 10);
+        cache_man.note_used(1);
+        cache_man.note_used(2);
+        cache_man.collect_garbage(0, |_| 0);
+        cache_man.note_used(3);
+
+        assert_eq!(cache_man.hot_entries(1), vec![3]);
+        let mut entries = cache_man.hot_entries(10);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], 3);
+        entries[1..].sort();
+        assert_eq!(entries[1..], [1, 2]);
+
+        // A used entry becomes hot again.
+        cache_man.note_used(1);
+        let entries = cache_man.hot_entries(10);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2], 2);
+        assert!(cache_man.hot_entries(0).is_empty());
+    }
+}
@@ -3,37 +3,114 @@
 // See http://www.gnu.org/licenses/
 
 use hibitset::{BitSet, BitSetLike};
+use metrics::{register_meter_with_group, Gauge, Meter};
 use std::{
-    cmp::max,
+    cmp::{max, min},
     collections::{HashMap, HashSet},
+    sync::Arc,
 };
 
-const CACHE_INDEX_STRIDE: usize = 1000;
-const MAX_ANTICONE_SIZE: usize = 300;
+lazy_static! {
+    static ref ANTICONE_CACHE_HIT: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "anticone_cache_hit");
+    static ref ANTICONE_CACHE_MISS: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "anticone_cache_miss");
+    static ref ANTICONE_CACHE_EVICT: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "anticone_cache_evict");
+}
+
+/// In the adaptive mode, the max anticone size is this multiple of the
+/// average size of the recent anticone sets.
+const ADAPTIVE_ANTICONE_SIZE_FACTOR: f64 = 2.0;
+/// In the adaptive mode, the max anticone size is at most this multiple of
+/// the configured one, which bounds the memory of the cache.
+const ADAPTIVE_MAX_ANTICONE_SIZE_MULTIPLE: usize = 16;
+/// The weight of a new anticone size in the moving average.
+const ANTICONE_SIZE_AVERAGE_WEIGHT: f64 = 0.125;
 
-/// AnticoneCache keeps only the anticone set of the recent CACHE_INDEX_STRIDE
-/// blocks. It also removes a block anticone set from it if the set is larger
-/// than MAX_ANTICONE_SIZE
+/// AnticoneCache keeps only the anticone set of the recent `stride` blocks.
+/// It also removes a block anticone set from it if the set is larger than
+/// the max anticone size. In the adaptive mode, the max anticone size follows
+/// the observed anticone sizes, so that the anticone sets are still cached
+/// when the DAG is wide instead of being recomputed for every new block.
 pub struct AnticoneCache {
+    stride: usize,
+    /// The configured max anticone size, which is also the lower bound of
+    /// the max anticone size in the adaptive mode.
+    base_max_anticone_size: usize,
+    adaptive: bool,
+    max_anticone_size: usize,
+    /// The gauge of `max_anticone_size` owned by the consensus graph, which
+    /// is passed on to the cache of the next era.
+    max_anticone_size_gauge: Arc<dyn Gauge<usize>>,
+    average_anticone_size: f64,
     max_seen_index: usize,
     seq_number: u64,
     data: HashMap<usize, (HashSet<usize>, u64)>,
 }
 
 impl AnticoneCache {
-    pub fn new() -> Self {
+    pub fn new(
+        stride: usize, max_anticone_size: usize, adaptive: bool,
+        max_anticone_size_gauge: Arc<dyn Gauge<usize>>,
+    ) -> Self
+    {
+        max_anticone_size_gauge.update(max_anticone_size);
         Self {
+            stride,
+            base_max_anticone_size: max_anticone_size,
+            adaptive,
+            max_anticone_size,
+            max_anticone_size_gauge,
+            average_anticone_size: 0.0,
             max_seen_index: 0,
             seq_number: 0,
             data: HashMap::new(),
         }
     }
 
+    pub fn max_anticone_size_gauge(&self) -> Arc<dyn Gauge<usize>> {
+        self.max_anticone_size_gauge.clone()
+    }
+
+    fn observe_anticone_size(&mut self, anticone_size: usize) {
+        if !self.adaptive {
+            return;
+        }
+        self.average_anticone_size += (anticone_size as f64
+            - self.average_anticone_size)
+            * ANTICONE_SIZE_AVERAGE_WEIGHT;
+        let adaptive_size = (self.average_anticone_size
+            * ADAPTIVE_ANTICONE_SIZE_FACTOR)
+            as usize;
+        let max_anticone_size = max(
+            self.base_max_anticone_size,
+            min(
+                adaptive_size,
+                self.base_max_anticone_size
+                    * ADAPTIVE_MAX_ANTICONE_SIZE_MULTIPLE,
+            ),
+        );
+        if max_anticone_size < self.max_anticone_size {
+            // The sets cached under the larger limit are evicted, otherwise
+            // the memory of the cache stays at its peak.
+            let size_before_evict = self.data.len();
+            self.data
+                .retain(|_, (anticone, _)| anticone.len() <= max_anticone_size);
+            ANTICONE_CACHE_EVICT.mark(size_before_evict - self.data.len());
+        }
+        self.max_anticone_size = max_anticone_size;
+        self.max_anticone_size_gauge.update(max_anticone_size);
+    }
+
     pub fn update(&mut self, me: usize, anticone: &BitSet) {
         self.seq_number += 1;
         self.max_seen_index = max(self.max_seen_index, me);
-        // BitSet does not have len() method
-        if anticone.len() < MAX_ANTICONE_SIZE {
+        let anticone_size = anticone.len();
+        self.observe_anticone_size(anticone_size);
+        let max_anticone_size = self.max_anticone_size;
+        let stride = self.stride as u64;
+        if anticone_size < max_anticone_size {
             let mut tmp = HashSet::new();
             for index in anticone.iter() {
                 tmp.insert(index as usize);
@@ -41,22 +118,21 @@ impl AnticoneCache {
             self.data.insert(me, (tmp, self.seq_number));
         }
 
-        if anticone.len() < self.data.len() {
+        let size_before_evict = self.data.len();
+        if anticone_size < self.data.len() {
             for index in anticone.iter() {
                 let index_usize = index as usize;
                 if self.data.contains_key(&index_usize) {
                     let s = &mut self.data.get_mut(&index_usize).unwrap().0;
                     s.insert(me);
-                    if s.len() > MAX_ANTICONE_SIZE {
+                    if s.len() > max_anticone_size {
                         self.data.remove(&index_usize);
                     }
                 }
             }
-            if self.data.len() > 2 * CACHE_INDEX_STRIDE {
+            if self.data.len() > 2 * self.stride {
                 let seq_number = self.seq_number;
-                self.data.retain(|_, (_, k)| {
-                    (seq_number - *k <= CACHE_INDEX_STRIDE as u64)
-                });
+                self.data.retain(|_, (_, k)| (seq_number - *k <= stride));
             }
         } else {
             let seq_number = self.seq_number;
@@ -64,25 +140,120 @@ impl AnticoneCache {
                 if anticone.contains(*k as u32) {
                     v.0.insert(me);
                 }
-                (v.0.len() <= MAX_ANTICONE_SIZE)
-                    && (seq_number - v.1 <= CACHE_INDEX_STRIDE as u64)
+                (v.0.len() <= max_anticone_size) && (seq_number - v.1 <= stride)
             });
         }
+        ANTICONE_CACHE_EVICT.mark(size_before_evict - self.data.len());
     }
 
     pub fn get(&self, me: usize) -> Option<&HashSet<usize>> {
         if let Some(v) = self.data.get(&me) {
+            ANTICONE_CACHE_HIT.mark(1);
             Some(&v.0)
         } else {
+            ANTICONE_CACHE_MISS.mark(1);
             None
         }
     }
 
     pub fn intersect_update(&mut self, era_blockset: &HashSet<usize>) {
         let seq_number = self.seq_number;
+        let stride = self.stride as u64;
+        let size_before_evict = self.data.len();
         self.data.retain(|_, (s, seq)| {
             s.retain(|v| era_blockset.contains(v));
-            seq_number - *seq <= CACHE_INDEX_STRIDE as u64
+            seq_number - *seq <= stride
         });
+        ANTICONE_CACHE_EVICT.mark(size_before_evict - self.data.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AnticoneCache;
+    use hibitset::BitSet;
+    use metrics::{Gauge, GaugeUsize};
+    use std::sync::Arc;
+
+    fn new_cache(
+        stride: usize, max_anticone_size: usize, adaptive: bool,
+    ) -> AnticoneCache {
+        AnticoneCache::new(
+            stride,
+            max_anticone_size,
+            adaptive,
+            Arc::new(GaugeUsize::default()),
+        )
+    }
+
+    fn bitset(indices: &[u32]) -> BitSet {
+        let mut set = BitSet::new();
+        for index in indices {
+            set.add(*index);
+        }
+        set
+    }
+
+    #[test]
+    fn test_max_anticone_size() {
+        let mut cache = new_cache(10, 3, false);
+        cache.update(1, &bitset(&[]));
+        cache.update(2, &bitset(&[1]));
+        assert_eq!(cache.get(1).map(|s| s.len()), Some(1));
+        assert_eq!(cache.get(2).map(|s| s.len()), Some(1));
+
+        // Too large to be cached.
+        cache.update(3, &bitset(&[10, 11, 12]));
+        assert!(cache.get(3).is_none());
+    }
+
+    #[test]
+    fn test_stride() {
+        let mut cache = new_cache(2, 10, false);
+        for me in 1..=5 {
+            cache.update(me, &bitset(&[]));
+        }
+        assert!(cache.get(1).is_none());
+        assert!(cache.get(2).is_none());
+        for me in 3..=5 {
+            assert!(cache.get(me).is_some());
+        }
+    }
+
+    #[test]
+    fn test_adaptive_max_anticone_size() {
+        let mut cache = new_cache(100, 2, true);
+        let wide: Vec<u32> = (0..10).collect();
+        for me in 1000..1020 {
+            cache.update(me, &bitset(&wide));
+        }
+        // The limit grows with the recent anticone sizes.
+        assert!(cache.max_anticone_size > 10);
+        assert_eq!(
+            cache.max_anticone_size_gauge.value(),
+            cache.max_anticone_size
+        );
+        assert!(cache.get(1019).is_some());
+
+        // The limit shrinks back to the configured one after the DAG
+        // narrows, and the large sets cached before are evicted.
+        for me in 2000..2030 {
+            cache.update(me, &bitset(&[]));
+        }
+        assert_eq!(cache.max_anticone_size, 2);
+        assert_eq!(cache.max_anticone_size_gauge.value(), 2);
+        assert!(cache.get(1019).is_none());
+        assert!(cache.get(2029).is_some());
+    }
+
+    #[test]
+    fn test_adaptive_max_anticone_size_bound() {
+        let mut cache = new_cache(100, 2, true);
+        let wide: Vec<u32> = (0..1000).collect();
+        for me in 2000..2100 {
+            cache.update(me, &bitset(&wide));
+        }
+        assert_eq!(cache.max_anticone_size, 32);
+        assert!(cache.get(2099).is_none());
     }
 }
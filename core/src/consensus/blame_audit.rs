@@ -0,0 +1,366 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::ConsensusGraph;
+use cfx_types::H256;
+use primitives::BlockHeaderBuilder;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+
+/// The deferred roots committed by a pivot block, which are the roots of the
+/// epoch `deferred_state_epoch_count` epochs before it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeferredRoots {
+    pub state_root: H256,
+    pub receipts_root: H256,
+    pub logs_bloom_hash: H256,
+}
+
+/// The audit of the blame information of a pivot block.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlameAuditEntry {
+    pub height: u64,
+    pub pivot_hash: H256,
+    pub header_blame: u32,
+    pub expected_blame: u32,
+    /// The deferred roots in the block header.
+    pub header_roots: DeferredRoots,
+    /// The deferred roots the block header should have, computed from the
+    /// local execution results and the blame of its ancestors.
+    pub expected_roots: DeferredRoots,
+    /// The roots of the deferred epoch computed by the local execution.
+    pub executed_roots: DeferredRoots,
+    /// The blame of the next pivot block, whose miner blames this block if
+    /// it's not 0. `None` for the last pivot block.
+    pub next_pivot_blame: Option<u32>,
+    /// Whether the block header is correct according to the audit.
+    pub state_valid: bool,
+    /// The `state_valid` in the consensus graph.
+    pub graph_state_valid: Option<bool>,
+    pub mismatches: Vec<String>,
+}
+
+/// The outcome of `ConsensusGraph::audit_blame`.
+#[derive(Debug, Default, Serialize)]
+pub struct BlameAuditReport {
+    pub from: u64,
+    pub to: u64,
+    pub entries: Vec<BlameAuditEntry>,
+}
+
+impl BlameAuditReport {
+    pub fn is_consistent(&self) -> bool {
+        self.entries.iter().all(|entry| entry.mismatches.is_empty())
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json =
+            serde_json::to_value(self).expect("report is serializable");
+        json["consistent"] = self.is_consistent().into();
+        json.to_string()
+    }
+}
+
+impl BlameAuditEntry {
+    /// Check the audited correctness against the next pivot block, whose
+    /// miner executed the epochs independently: its blame is 0 if and only
+    /// if it found this block correct.
+    fn check_next_pivot_blame(&mut self) {
+        let next_blame = match self.next_pivot_blame {
+            Some(blame) => blame,
+            None => return,
+        };
+        if (next_blame == 0) != self.state_valid {
+            self.mismatches.push(format!(
+                "the next pivot block {} this block, but the audit finds it {}",
+                if next_blame == 0 {
+                    "endorses"
+                } else {
+                    "blames"
+                },
+                if self.state_valid {
+                    "correct"
+                } else {
+                    "incorrect"
+                }
+            ));
+        }
+    }
+}
+
+impl ConsensusGraph {
+    /// Audit the blame information of the pivot blocks with heights in
+    /// `[from, to]`. The blame and deferred roots which the header of each
+    /// pivot block should have are derived from the roots of the local
+    /// execution and compared with the header. The outcome is cross-checked
+    /// with the blame of the next pivot block, which is the verdict of
+    /// another miner on the same epochs, and with the consensus graph.
+    ///
+    /// The correctness of the pivot blocks before `from` is taken from the
+    /// consensus graph, or from the persisted blame verification outcomes.
+    pub fn audit_blame(
+        &self, from: u64, to: u64,
+    ) -> Result<BlameAuditReport, String> {
+        if from < self.deferred_state_epoch_count() || from > to {
+            return Err(format!(
+                "Invalid audit range [{}, {}], the deferred epoch count is {}",
+                from,
+                to,
+                self.deferred_state_epoch_count()
+            ));
+        }
+
+        let mut report = BlameAuditReport {
+            from,
+            to,
+            entries: Vec::new(),
+        };
+        // The audited correctness and the deferred roots of the pivot blocks
+        // in the range.
+        let mut audited: HashMap<u64, (bool, DeferredRoots)> = HashMap::new();
+        for height in from..=to {
+            let (pivot_hash, next_pivot_hash) = {
+                let inner = self.inner.read();
+                (
+                    inner.get_hash_from_epoch_number(height)?,
+                    inner.get_hash_from_epoch_number(height + 1).ok(),
+                )
+            };
+            let header =
+                self.data_man.block_header_by_hash(&pivot_hash).ok_or_else(
+                    || format!("Missing block header {:?}", pivot_hash),
+                )?;
+            let next_pivot_blame = next_pivot_hash
+                .and_then(|hash| self.data_man.block_header_by_hash(&hash))
+                .map(|header| header.blame());
+            let mut mismatches = Vec::new();
+
+            let roots = self
+                .data_man
+                .consensus_graph_execution_info_from_db(&pivot_hash)
+                .map(|info| DeferredRoots {
+                    state_root: info.original_deferred_state_root,
+                    receipts_root: info.original_deferred_receipt_root,
+                    logs_bloom_hash: info.original_deferred_logs_bloom_hash,
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "Deferred roots of pivot block {:?} at height {} are \
+                         not recorded",
+                        pivot_hash, height
+                    )
+                })?;
+
+            // Blame the consecutive incorrect ancestors, as in
+            // `compute_blame_and_state_with_execution_result`.
+            let mut blamed_roots = vec![roots];
+            let mut ancestor_height = height - 1;
+            loop {
+                let (valid, ancestor_roots) =
+                    match audited.get(&ancestor_height) {
+                        Some(audited) => *audited,
+                        None => self.ancestor_blame_info(ancestor_height)?,
+                    };
+                if valid {
+                    break;
+                }
+                if ancestor_height == 0 {
+                    return Err("The genesis block is blamed".into());
+                }
+                blamed_roots.push(ancestor_roots);
+                ancestor_height -= 1;
+            }
+            let expected_blame = blamed_roots.len() as u32 - 1;
+            let expected_roots = if expected_blame == 0 {
+                roots
+            } else {
+                DeferredRoots {
+                    state_root:
+                        BlockHeaderBuilder::compute_blame_state_root_vec_root(
+                            blamed_roots.iter().map(|r| r.state_root).collect(),
+                        ),
+                    receipts_root:
+                        BlockHeaderBuilder::compute_blame_state_root_vec_root(
+                            blamed_roots
+                                .iter()
+                                .map(|r| r.receipts_root)
+                                .collect(),
+                        ),
+                    logs_bloom_hash:
+                        BlockHeaderBuilder::compute_blame_state_root_vec_root(
+                            blamed_roots
+                                .iter()
+                                .map(|r| r.logs_bloom_hash)
+                                .collect(),
+                        ),
+                }
+            };
+
+            let header_roots = DeferredRoots {
+                state_root: *header.deferred_state_root(),
+                receipts_root: *header.deferred_receipts_root(),
+                logs_bloom_hash: *header.deferred_logs_bloom_hash(),
+            };
+            let state_valid = header.blame() == expected_blame
+                && header_roots == expected_roots;
+            let graph_state_valid = self.graph_state_valid(&pivot_hash);
+            if let Some(graph_state_valid) = graph_state_valid {
+                if graph_state_valid != state_valid {
+                    mismatches.push(format!(
+                        "state_valid={} in the consensus graph != audited {}",
+                        graph_state_valid, state_valid
+                    ));
+                }
+            }
+            audited.insert(height, (state_valid, roots));
+
+            let mut entry = BlameAuditEntry {
+                height,
+                pivot_hash,
+                header_blame: header.blame(),
+                expected_blame,
+                header_roots,
+                expected_roots,
+                executed_roots: roots,
+                next_pivot_blame,
+                state_valid,
+                graph_state_valid,
+                mismatches,
+            };
+            entry.check_next_pivot_blame();
+            report.entries.push(entry);
+        }
+
+        Ok(report)
+    }
+
+    /// The correctness and the recorded deferred roots of the pivot block at
+    /// `height`, which is not audited.
+    fn ancestor_blame_info(
+        &self, height: u64,
+    ) -> Result<(bool, DeferredRoots), String> {
+        let hash = self.inner.read().get_hash_from_epoch_number(height)?;
+        let state_valid = self
+            .graph_state_valid(&hash)
+            .or_else(|| self.data_man.blame_state_valid_from_db(&hash))
+            .ok_or_else(|| {
+                format!(
+                    "state_valid of pivot block {:?} at height {} is unknown",
+                    hash, height
+                )
+            })?;
+        let info = self
+            .data_man
+            .consensus_graph_execution_info_from_db(&hash)
+            .ok_or_else(|| {
+                format!(
+                    "Deferred roots of pivot block {:?} at height {} are not \
+                     recorded",
+                    hash, height
+                )
+            })?;
+        Ok((
+            state_valid,
+            DeferredRoots {
+                state_root: info.original_deferred_state_root,
+                receipts_root: info.original_deferred_receipt_root,
+                logs_bloom_hash: info.original_deferred_logs_bloom_hash,
+            },
+        ))
+    }
+
+    /// The `state_valid` of a block in the consensus graph, which is not set
+    /// for the blocks not recovered yet.
+    fn graph_state_valid(&self, hash: &H256) -> Option<bool> {
+        let inner = self.inner.read();
+        if self.pivot_block_state_valid_map.lock().contains_key(hash) {
+            return None;
+        }
+        inner
+            .hash_to_arena_indices
+            .get(hash)
+            .map(|index| inner.arena[*index].data.state_valid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlameAuditEntry, BlameAuditReport, DeferredRoots};
+    use cfx_types::H256;
+
+    fn entry(
+        state_valid: bool, next_pivot_blame: Option<u32>,
+    ) -> BlameAuditEntry {
+        let roots = DeferredRoots {
+            state_root: H256::from_low_u64_be(1),
+            receipts_root: H256::from_low_u64_be(2),
+            logs_bloom_hash: H256::from_low_u64_be(3),
+        };
+        BlameAuditEntry {
+            height: 10,
+            pivot_hash: H256::from_low_u64_be(10),
+            header_blame: 0,
+            expected_blame: 0,
+            header_roots: roots,
+            expected_roots: roots,
+            executed_roots: roots,
+            next_pivot_blame,
+            state_valid,
+            graph_state_valid: None,
+            mismatches: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_check_next_pivot_blame() {
+        for &(state_valid, next_pivot_blame, consistent) in &[
+            (true, Some(0), true),
+            (false, Some(1), true),
+            (false, Some(3), true),
+            (true, Some(1), false),
+            (false, Some(0), false),
+            (true, None, true),
+            (false, None, true),
+        ] {
+            let mut entry = entry(state_valid, next_pivot_blame);
+            entry.check_next_pivot_blame();
+            assert_eq!(
+                entry.mismatches.is_empty(),
+                consistent,
+                "state_valid={} next_pivot_blame={:?}",
+                state_valid,
+                next_pivot_blame
+            );
+        }
+    }
+
+    #[test]
+    fn test_report_to_json() {
+        let mut inconsistent = entry(true, Some(1));
+        inconsistent
+            .mismatches
+            .push("a \"quoted\"\nmismatch".into());
+        let report = BlameAuditReport {
+            from: 10,
+            to: 11,
+            entries: vec![entry(true, Some(0)), inconsistent],
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["from"], 10);
+        assert_eq!(json["consistent"], false);
+        assert_eq!(json["entries"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            json["entries"][1]["mismatches"][0],
+            "a \"quoted\"\nmismatch"
+        );
+        assert_eq!(json["entries"][0]["nextPivotBlame"], 0);
+        assert_eq!(
+            json["entries"][0]["headerRoots"]["receiptsRoot"],
+            serde_json::to_value(H256::from_low_u64_be(2)).unwrap()
+        );
+    }
+}
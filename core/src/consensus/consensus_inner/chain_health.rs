@@ -0,0 +1,222 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use metrics::{register_meter_with_group, Histogram, Meter, Sample};
+use parking_lot::RwLock;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+
+/// The number of latest blocks whose stats are aggregated.
+pub const CHAIN_HEALTH_WINDOW: usize = 1000;
+
+lazy_static! {
+    static ref ANTICONE_SIZE: Arc<dyn Histogram> = Sample::ExpDecay(0.015)
+        .register_with_group("consensus", "new_block_anticone_size", 1024);
+    static ref REFEREE_COUNT: Arc<dyn Histogram> = Sample::ExpDecay(0.015)
+        .register_with_group("consensus", "new_block_referee_count", 1024);
+    static ref PIVOT_REORG_DEPTH: Arc<dyn Histogram> = Sample::ExpDecay(0.015)
+        .register_with_group("consensus", "pivot_reorg_depth", 1024);
+    static ref FORK_BLOCK_METER: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "fork_block");
+    static ref PIVOT_SWITCH_METER: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "pivot_switch");
+    static ref ADAPTIVE_BLOCK_METER: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "adaptive_block");
+}
+
+struct BlockSample {
+    /// The timestamp in the block header, so that the rate of the blocks
+    /// recovered from the db isn't the rate they are loaded.
+    timestamp: u64,
+    anticone_size: usize,
+    referee_count: usize,
+    adaptive: bool,
+    /// The block does not extend the tip of the pivot chain.
+    forked: bool,
+    /// The number of pivot blocks retracted if the block switches the pivot
+    /// chain.
+    reorg_depth: Option<u64>,
+}
+
+/// The DAG health stats of the latest `CHAIN_HEALTH_WINDOW` blocks.
+#[derive(Clone, Debug, Default)]
+pub struct ChainHealth {
+    pub num_blocks: usize,
+    /// The number of blocks generated per second according to their
+    /// timestamps.
+    pub block_rate: f64,
+    pub avg_anticone_size: f64,
+    pub max_anticone_size: usize,
+    /// referee count -> number of blocks
+    pub referee_count_distribution: BTreeMap<usize, usize>,
+    /// The fraction of the blocks not extending the tip of the pivot chain,
+    /// including the pending and partially invalid ones.
+    pub fork_rate: f64,
+    /// The fraction of the blocks switching the pivot chain.
+    pub pivot_switch_rate: f64,
+    pub avg_reorg_depth: f64,
+    pub max_reorg_depth: u64,
+    /// The fraction of the blocks whose weights are adaptive.
+    pub adaptive_rate: f64,
+}
+
+/// ChainHealthMonitor aggregates the rolling stats of the new blocks, e.g.
+/// their anticone sizes and the pivot chain reorganizations they cause, so
+/// that the operators could detect the degradation of the network.
+pub struct ChainHealthMonitor {
+    samples: RwLock<VecDeque<BlockSample>>,
+}
+
+impl ChainHealthMonitor {
+    pub fn new() -> Self {
+        ChainHealthMonitor {
+            samples: RwLock::new(VecDeque::new()),
+        }
+    }
+
+    pub fn record_block(
+        &self, timestamp: u64, anticone_size: usize, referee_count: usize,
+        adaptive: bool, forked: bool, reorg_depth: Option<u64>,
+    )
+    {
+        ANTICONE_SIZE.update(anticone_size as u64);
+        REFEREE_COUNT.update(referee_count as u64);
+        if forked {
+            FORK_BLOCK_METER.mark(1);
+        }
+        if let Some(depth) = reorg_depth {
+            PIVOT_SWITCH_METER.mark(1);
+            PIVOT_REORG_DEPTH.update(depth);
+        }
+        if adaptive {
+            ADAPTIVE_BLOCK_METER.mark(1);
+        }
+
+        let mut samples = self.samples.write();
+        samples.push_back(BlockSample {
+            timestamp,
+            anticone_size,
+            referee_count,
+            adaptive,
+            forked,
+            reorg_depth,
+        });
+        while samples.len() > CHAIN_HEALTH_WINDOW {
+            samples.pop_front();
+        }
+    }
+
+    pub fn report(&self) -> ChainHealth {
+        let samples = self.samples.read();
+        let mut health = ChainHealth::default();
+        if samples.is_empty() {
+            return health;
+        }
+
+        let mut total_anticone_size = 0;
+        let mut num_forked = 0;
+        let mut num_adaptive = 0;
+        let mut num_switches = 0;
+        let mut total_reorg_depth = 0;
+        for sample in samples.iter() {
+            total_anticone_size += sample.anticone_size;
+            health.max_anticone_size =
+                health.max_anticone_size.max(sample.anticone_size);
+            *health
+                .referee_count_distribution
+                .entry(sample.referee_count)
+                .or_insert(0) += 1;
+            if sample.forked {
+                num_forked += 1;
+            }
+            if sample.adaptive {
+                num_adaptive += 1;
+            }
+            if let Some(depth) = sample.reorg_depth {
+                num_switches += 1;
+                total_reorg_depth += depth;
+                health.max_reorg_depth = health.max_reorg_depth.max(depth);
+            }
+        }
+
+        let n = samples.len() as f64;
+        health.num_blocks = samples.len();
+        health.avg_anticone_size = total_anticone_size as f64 / n;
+        health.fork_rate = num_forked as f64 / n;
+        health.adaptive_rate = num_adaptive as f64 / n;
+        health.pivot_switch_rate = num_switches as f64 / n;
+        if num_switches > 0 {
+            health.avg_reorg_depth =
+                total_reorg_depth as f64 / num_switches as f64;
+        }
+
+        // The timestamps of the blocks aren't ordered by their arrival.
+        let first = samples.iter().map(|s| s.timestamp).min().unwrap();
+        let last = samples.iter().map(|s| s.timestamp).max().unwrap();
+        if last > first {
+            health.block_rate =
+                (samples.len() - 1) as f64 / (last - first) as f64;
+        }
+
+        health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChainHealthMonitor, CHAIN_HEALTH_WINDOW};
+
+    #[test]
+    fn test_empty_report() {
+        let health = ChainHealthMonitor::new().report();
+        assert_eq!(health.num_blocks, 0);
+        assert_eq!(health.block_rate, 0.0);
+    }
+
+    #[test]
+    fn test_report() {
+        let monitor = ChainHealthMonitor::new();
+        // The blocks are received out of the order of their timestamps.
+        monitor.record_block(102, 0, 1, false, false, None);
+        monitor.record_block(100, 2, 2, true, true, None);
+        monitor.record_block(104, 1, 1, false, false, Some(3));
+        monitor.record_block(101, 5, 3, false, true, Some(1));
+        monitor.record_block(103, 0, 1, false, false, None);
+
+        let health = monitor.report();
+        assert_eq!(health.num_blocks, 5);
+        assert_eq!(health.block_rate, 1.0);
+        assert_eq!(health.avg_anticone_size, 8.0 / 5.0);
+        assert_eq!(health.max_anticone_size, 5);
+        assert_eq!(
+            health
+                .referee_count_distribution
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![(1, 3), (2, 1), (3, 1)]
+        );
+        assert_eq!(health.fork_rate, 2.0 / 5.0);
+        assert_eq!(health.pivot_switch_rate, 2.0 / 5.0);
+        assert_eq!(health.avg_reorg_depth, 2.0);
+        assert_eq!(health.max_reorg_depth, 3);
+        assert_eq!(health.adaptive_rate, 1.0 / 5.0);
+    }
+
+    #[test]
+    fn test_window() {
+        let monitor = ChainHealthMonitor::new();
+        monitor.record_block(0, 100, 1, false, true, None);
+        for i in 0..CHAIN_HEALTH_WINDOW as u64 {
+            monitor.record_block(i * 2, 0, 1, false, false, None);
+        }
+
+        let health = monitor.report();
+        assert_eq!(health.num_blocks, CHAIN_HEALTH_WINDOW);
+        assert_eq!(health.max_anticone_size, 0);
+        assert_eq!(health.fork_rate, 0.0);
+        assert_eq!(health.block_rate, 0.5);
+    }
+}
@@ -4,7 +4,7 @@
 
 use crate::consensus::{
     consensus_inner::{NULL, NULLU64},
-    ConsensusGraphInner, DEFERRED_STATE_EPOCH_COUNT,
+    ConsensusGraphInner,
 };
 use cfx_types::H256;
 use parking_lot::RwLock;
@@ -97,7 +97,12 @@ impl ConfirmationMeter {
         if epoch_num == NULLU64 {
             return None;
         }
+        self.confirmation_risk_by_epoch(epoch_num)
+    }
 
+    /// The estimated probability that the pivot block of epoch `epoch_num`
+    /// is reverted, or `None` if it is too recent to be estimated.
+    pub fn confirmation_risk_by_epoch(&self, epoch_num: u64) -> Option<f64> {
         if epoch_num == 0 {
             return Some(0.0);
         }
@@ -123,6 +128,19 @@ impl ConfirmationMeter {
         }
     }
 
+    /// The latest epoch whose confirmation risk is estimated and its risk.
+    pub fn latest_confirmation_risk(&self) -> Option<(u64, f64)> {
+        let finality = &self.inner.read().finality_manager;
+        if finality.risks_less_than.is_empty() {
+            return None;
+        }
+        let epoch_num = finality.lowest_epoch_num
+            + finality.risks_less_than.len() as u64
+            - 1;
+        let risk = finality.risks_less_than.iter().cloned().fold(0.0, f64::max);
+        Some((epoch_num, risk))
+    }
+
     fn confirmation_risk(
         &self, g_inner: &ConsensusGraphInner, w_0: i128, w_4: i128,
         epoch_num: u64,
@@ -194,14 +212,16 @@ impl ConfirmationMeter {
     }
 
     pub fn update_confirmation_risks(&self, g_inner: &ConsensusGraphInner) {
-        if g_inner.pivot_chain.len() > DEFERRED_STATE_EPOCH_COUNT as usize {
+        if g_inner.pivot_chain.len()
+            > g_inner.deferred_state_epoch_count() as usize
+        {
             let w_0 = g_inner
                 .weight_tree
                 .get(g_inner.cur_era_genesis_block_arena_index);
             let mut risks = VecDeque::new();
             let mut epoch_num = g_inner
                 .pivot_index_to_height(g_inner.pivot_chain.len())
-                - DEFERRED_STATE_EPOCH_COUNT;
+                - g_inner.deferred_state_epoch_count();
             let mut count = 0;
             while epoch_num > 0 && count < MAX_NUM_MAINTAINED_RISK {
                 let w_4 = self.get_total_weight_in_past();
@@ -226,3 +246,40 @@ impl ConfirmationMeter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfirmationMeter, MIN_MAINTAINED_RISK};
+
+    fn meter(lowest_epoch_num: u64, risks: Vec<f64>) -> ConfirmationMeter {
+        let meter = ConfirmationMeter::new();
+        {
+            let finality = &mut meter.inner.write().finality_manager;
+            finality.lowest_epoch_num = lowest_epoch_num;
+            finality.risks_less_than = risks.into_iter().collect();
+        }
+        meter
+    }
+
+    #[test]
+    fn test_latest_confirmation_risk() {
+        assert_eq!(ConfirmationMeter::new().latest_confirmation_risk(), None);
+
+        let meter = meter(5, vec![0.1, 0.3, 0.2]);
+        assert_eq!(meter.latest_confirmation_risk(), Some((7, 0.3)));
+        // The risk of an epoch is bounded by the risks of its ancestors.
+        assert_eq!(meter.confirmation_risk_by_epoch(7), Some(0.3));
+        assert_eq!(meter.confirmation_risk_by_epoch(5), Some(0.1));
+        assert_eq!(
+            meter.confirmation_risk_by_epoch(4),
+            Some(MIN_MAINTAINED_RISK)
+        );
+        assert_eq!(meter.confirmation_risk_by_epoch(8), None);
+
+        // The risks of the epochs before the checkpoint are dropped.
+        meter.reset_for_checkpoint(0, 7);
+        assert_eq!(meter.latest_confirmation_risk(), Some((7, 0.2)));
+        meter.reset_for_checkpoint(0, 8);
+        assert_eq!(meter.latest_confirmation_risk(), None);
+    }
+}
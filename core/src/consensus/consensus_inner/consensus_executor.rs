@@ -4,46 +4,54 @@
 
 use super::super::debug::*;
 use crate::{
-    block_data_manager::BlockDataManager,
-    consensus::ConsensusGraphInner,
-    executive::{ExecutionError, Executive},
-    machine::new_machine_with_builtin,
+    block_data_manager::{BlockDataManager, BlockRewardResult},
+    consensus::{ConsensusError, ConsensusGraphInner, ProfiledRwLock},
+    executive::{Executed, ExecutionError, ExecutionResult, Executive},
+    machine::Machine,
     parameters::{consensus::*, consensus_internal::*},
-    state::{CleanupMode, State},
+    state::{AccountDiff, CleanupMode, State},
     statedb::StateDb,
     storage::{
         state::StateTrait,
         state_manager::{SnapshotAndEpochIdRef, StateManagerTrait},
     },
-    vm::{Env, Spec},
+    vm::{self, Env, InvalidTransactionPolicy, Spec},
     vm_factory::VmFactory,
     SharedTransactionPool,
 };
 use cfx_types::{BigEndianHash, H256, KECCAK_EMPTY_BLOOM, U256, U512};
 use core::convert::TryFrom;
-use hash::KECCAK_EMPTY_LIST_RLP;
-use metrics::{register_meter_with_group, Meter, MeterTimer};
+use hash::{keccak, KECCAK_EMPTY_LIST_RLP};
+use lru_time_cache::LruCache;
+use metrics::{
+    register_meter_with_group, Gauge, GaugeUsize, Histogram, Meter, MeterTimer,
+    Sample,
+};
 use parity_bytes::ToPretty;
 use parking_lot::{Mutex, RwLock};
 use primitives::{
     receipt::{
-        Receipt, TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING,
+        Receipt, ReceiptDetails,
+        TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING,
         TRANSACTION_OUTCOME_EXCEPTION_WITH_NONCE_BUMPING,
         TRANSACTION_OUTCOME_SUCCESS,
     },
+    transaction::Action,
     Block, BlockHeaderBuilder, SignedTransaction, StateRootWithAuxInfo,
     TransactionAddress,
 };
+use rlp::RlpStream;
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
     convert::From,
     fmt::{Debug, Formatter},
     sync::{
-        atomic::{AtomicBool, Ordering::Relaxed},
-        mpsc::{channel, RecvError, Sender, TryRecvError},
+        atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed},
+        mpsc::{channel, Sender, TryRecvError},
         Arc,
     },
-    thread::{self, JoinHandle},
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
 };
 
 lazy_static! {
@@ -56,6 +64,141 @@ lazy_static! {
         );
     static ref GOOD_TPS_METER: Arc<dyn Meter> =
         register_meter_with_group("system_metrics", "good_tps");
+    static ref SKIPPED_TPS_METER: Arc<dyn Meter> =
+        register_meter_with_group("system_metrics", "skipped_tps");
+    static ref EXECUTION_QUEUE_DEPTH: Arc<dyn Gauge<usize>> =
+        GaugeUsize::register_with_group("consensus", "execution_queue_depth");
+    static ref EPOCH_EXECUTION_LATENCY: Arc<dyn Histogram> = Sample::ExpDecay(
+        0.015
+    )
+    .register_with_group("consensus", "epoch_execution_latency_ms", 1024);
+    static ref VIRTUAL_CALL_CACHE_HIT: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "virtual_call_cache_hit");
+    static ref VIRTUAL_CALL_CACHE_MISS: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "virtual_call_cache_miss");
+}
+
+/// The interval to check whether the execution queue has drained when
+/// new blocks are throttled by `ConsensusExecutor::wait_for_capacity`.
+const EXECUTION_QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The number of recent epochs whose skipped transaction stats are kept.
+const MAX_SKIPPED_TX_STATS_EPOCHS: usize = 1000;
+
+/// The number of recently queried epochs whose virtual call results are
+/// cached, and the number of cached results of each epoch.
+const MAX_VIRTUAL_CALL_CACHE_EPOCHS: usize = 64;
+const MAX_VIRTUAL_CALL_CACHE_SIZE_PER_EPOCH: usize = 256;
+
+type VirtualCallResult = Result<(Vec<u8>, U256), ConsensusError>;
+
+/// The results of virtual calls grouped by epoch and keyed by the digest of
+/// the transaction and its sender.
+struct VirtualCallCache {
+    epochs: LruCache<H256, HashMap<H256, VirtualCallResult>>,
+}
+
+impl VirtualCallCache {
+    fn new() -> Self {
+        VirtualCallCache {
+            epochs: LruCache::with_capacity(MAX_VIRTUAL_CALL_CACHE_EPOCHS),
+        }
+    }
+
+    /// Virtual call transactions are not signed, so their hashes are not
+    /// computed and the sender is hashed along with the unsigned content.
+    fn key(tx: &SignedTransaction) -> H256 {
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&tx.transaction.unsigned).append(&tx.sender);
+        keccak(stream.out())
+    }
+
+    fn get(
+        &mut self, epoch_id: &H256, key: &H256,
+    ) -> Option<VirtualCallResult> {
+        self.epochs
+            .get(epoch_id)
+            .and_then(|results| results.get(key))
+            .cloned()
+    }
+
+    /// Cache `result` if it's determined by the state of the epoch. The
+    /// errors of an unavailable state are transient, e.g. the epoch is being
+    /// executed, so they are not cached.
+    fn insert(
+        &mut self, epoch_id: &H256, key: H256, result: &VirtualCallResult,
+    ) {
+        match result {
+            Ok(_)
+            | Err(ConsensusError::InvalidTransaction(_))
+            | Err(ConsensusError::Reverted(_))
+            | Err(ConsensusError::OutOfGas { .. })
+            | Err(ConsensusError::ExecutionFailed(_)) => {}
+            Err(ConsensusError::InvalidEpoch(_))
+            | Err(ConsensusError::StateUnavailable(_))
+            | Err(ConsensusError::Other(_)) => return,
+        }
+        let mut results = self.epochs.remove(epoch_id).unwrap_or_default();
+        if results.len() < MAX_VIRTUAL_CALL_CACHE_SIZE_PER_EPOCH {
+            results.insert(key, result.clone());
+        }
+        self.epochs.insert(*epoch_id, results);
+    }
+
+    /// Drop the results of the epoch, whose state is recomputed.
+    fn invalidate(&mut self, epoch_id: &H256) { self.epochs.remove(epoch_id); }
+}
+
+/// The number of transactions in an epoch which are skipped without bumping
+/// the sender nonce, grouped by reason.
+#[derive(Clone, Debug, Default)]
+pub struct SkippedTransactionStats {
+    pub invalid_nonce: usize,
+    pub not_enough_base_gas: usize,
+    pub sender_must_exist: usize,
+    pub block_gas_limit_reached: usize,
+    pub other: usize,
+    /// Skipped because an earlier transaction in the same block is invalid
+    /// under `InvalidTransactionPolicy::Reject`.
+    pub rejected: usize,
+}
+
+impl SkippedTransactionStats {
+    pub fn total(&self) -> usize {
+        self.invalid_nonce
+            + self.not_enough_base_gas
+            + self.sender_must_exist
+            + self.block_gas_limit_reached
+            + self.other
+            + self.rejected
+    }
+
+    /// Record a transaction skipped because of `error`, and return whether it
+    /// is invalid in the sense of `InvalidTransactionPolicy`.
+    fn record(&mut self, error: &ExecutionError) -> bool {
+        match error {
+            ExecutionError::InvalidNonce { expected, got } => {
+                self.invalid_nonce += 1;
+                got > expected
+            }
+            ExecutionError::NotEnoughBaseGas { .. } => {
+                self.not_enough_base_gas += 1;
+                true
+            }
+            ExecutionError::SenderMustExist => {
+                self.sender_must_exist += 1;
+                true
+            }
+            ExecutionError::BlockGasLimitReached { .. } => {
+                self.block_gas_limit_reached += 1;
+                true
+            }
+            _ => {
+                self.other += 1;
+                true
+            }
+        }
+    }
 }
 
 /// The RewardExecutionInfo struct includes most information to compute rewards
@@ -126,6 +269,25 @@ impl EpochExecutionTask {
     }
 }
 
+/// The result of executing an epoch on the local pivot chain, see
+/// `ConsensusGraph::register_epoch_executed_observer`.
+#[derive(Clone, Debug)]
+pub struct ExecutedEpoch {
+    pub epoch_hash: H256,
+    pub epoch_number: u64,
+    /// The blocks of the epoch in execution order.
+    pub blocks: Vec<Arc<Block>>,
+    /// The receipts of the blocks in `blocks`.
+    pub receipts: Vec<Arc<Vec<Receipt>>>,
+    /// The accounts modified by the epoch, or `None` if the epoch is not
+    /// executed in this run but loaded from the database.
+    pub state_diff: Option<Vec<AccountDiff>>,
+}
+
+/// Callback notified in the execution thread after an epoch on the local
+/// pivot chain is executed and committed.
+pub type EpochExecutedObserver = Box<dyn Fn(&ExecutedEpoch) + Send + Sync>;
+
 /// `sender` is used to return the computed `(state_root, receipts_root,
 /// logs_bloom_hash)` to the thread who sends this task.
 #[derive(Debug)]
@@ -134,6 +296,39 @@ struct GetExecutionResultTask {
     pub sender: Sender<(StateRootWithAuxInfo, H256, H256)>,
 }
 
+/// The number of epoch execution tasks sent to the execution thread and not
+/// finished yet, and the bound above which new blocks are throttled.
+struct ExecutionQueueCounter {
+    queued: AtomicUsize,
+    /// 0 means unbounded.
+    max_len: usize,
+}
+
+impl ExecutionQueueCounter {
+    fn new(max_len: usize) -> Self {
+        ExecutionQueueCounter {
+            queued: AtomicUsize::new(0),
+            max_len,
+        }
+    }
+
+    fn on_enqueued(&self) {
+        let queued = self.queued.fetch_add(1, Relaxed) + 1;
+        EXECUTION_QUEUE_DEPTH.update(queued);
+    }
+
+    fn on_finished(&self) {
+        let queued = self.queued.fetch_sub(1, Relaxed) - 1;
+        EXECUTION_QUEUE_DEPTH.update(queued);
+    }
+
+    fn len(&self) -> usize { self.queued.load(Relaxed) }
+
+    fn is_overloaded(&self) -> bool {
+        self.max_len != 0 && self.len() >= self.max_len
+    }
+}
+
 /// ConsensusExecutor processes transaction execution tasks.
 pub struct ConsensusExecutor {
     /// The thread responsible for execution transactions
@@ -145,6 +340,11 @@ pub struct ConsensusExecutor {
     /// The state indicating whether the thread should be stopped
     stopped: AtomicBool,
 
+    /// The number of epoch execution tasks sent to `self.thread` and not
+    /// finished yet. New blocks are throttled until the execution thread
+    /// catches up if it exceeds its bound.
+    queue: ExecutionQueueCounter,
+
     /// The handler to provide functions to handle `ExecutionTask` and execute
     /// transactions It is used both asynchronously by `self.thread` and
     /// synchronously by the executor itself
@@ -156,14 +356,16 @@ pub struct ConsensusExecutor {
 impl ConsensusExecutor {
     pub fn start(
         tx_pool: SharedTransactionPool, data_man: Arc<BlockDataManager>,
-        vm: VmFactory, consensus_inner: Arc<RwLock<ConsensusGraphInner>>,
-        bench_mode: bool,
+        vm: VmFactory,
+        consensus_inner: Arc<ProfiledRwLock<ConsensusGraphInner>>,
+        bench_mode: bool, max_queue_len: usize, machine: Arc<Machine>,
     ) -> Arc<Self>
     {
         let handler = Arc::new(ConsensusExecutionHandler::new(
             tx_pool,
             data_man.clone(),
             vm,
+            machine,
         ));
         let (sender, receiver) = channel();
 
@@ -171,6 +373,7 @@ impl ConsensusExecutor {
             thread: Mutex::new(None),
             sender: Mutex::new(sender),
             stopped: AtomicBool::new(false),
+            queue: ExecutionQueueCounter::new(max_queue_len),
             handler: handler.clone(),
             bench_mode,
         };
@@ -179,52 +382,73 @@ impl ConsensusExecutor {
         // It receives blocks hashes from on_new_block and execute them
         let handle = thread::Builder::new()
             .name("Consensus Execution Worker".into())
-            .spawn(move || loop {
-                if executor_thread.stopped.load(Relaxed) {
-                    // The thread should be stopped. The rest tasks in the queue will be discarded.
-                    break;
-                }
-                let maybe_task = receiver.try_recv();
-                match maybe_task {
-                    Err(TryRecvError::Empty) => {
-                        // The channel is empty, so we try to optimistically
-                        // get later epochs to execute. Here we use `try_write` because some thread
-                        // may wait for execution results while holding the Consensus Inner lock,
-                        // if we wait on inner lock here we may get deadlock
-                        let maybe_optimistic_task = consensus_inner
-                            .try_write()
-                            .and_then(|mut inner|
-                                executor_thread.get_optimistic_execution_task(&mut *inner)
-                            );
-                        match maybe_optimistic_task {
-                            Some(task) => {
-                                debug!("Get optimistic_execution_task {:?}", task);
-                                handler.handle_epoch_execution(task)
-                            },
-                            None => {
-                                debug!("No optimistic tasks to execute, block for new tasks");
-                                //  Even optimistic tasks are all finished, so we block and wait for
-                                //  new execution tasks.
-                                //  New optimistic tasks will only exist if pivot_chain changes,
-                                //  and new tasks will be sent to `receiver` in this case, so this
-                                // waiting will not prevent new optimistic tasks from being executed
-                                if !handler.handle_recv_result(receiver.recv())
-                                {
-                                    break;
-                                }
+            .spawn(move || {
+                // The tasks received from the channel and not handled yet.
+                let mut pending = VecDeque::new();
+                loop {
+                    if executor_thread.stopped.load(Relaxed) {
+                        // The thread should be stopped. The rest tasks in the queue will be discarded.
+                        break;
+                    }
+                    // Move all the tasks in the channel to `pending`, so that the
+                    // best state can be computed ahead of historical epochs.
+                    let mut disconnected = false;
+                    loop {
+                        match receiver.try_recv() {
+                            Ok(task) => pending.push_back(task),
+                            Err(TryRecvError::Empty) => break,
+                            Err(TryRecvError::Disconnected) => {
+                                disconnected = true;
+                                break;
                             }
                         }
                     }
-                    maybe_error => {
-                        // Handle execution task in channel.
-                        // If `maybe_task` is Err, it can only be
-                        // `TryRecvError::Disconnected`, and it has the same
-                        // meaning as `RecvError` for `recv()`
-                        if !handler.handle_recv_result(
-                            maybe_error.map_err(|_| RecvError),
-                        ) {
+                    if let Some(task) = executor_thread.next_task(&mut pending) {
+                        let is_epoch = match task {
+                            ExecutionTask::ExecuteEpoch(_) => true,
+                            _ => false,
+                        };
+                        if !handler.handle_execution_work(task) {
                             break;
                         }
+                        if is_epoch {
+                            executor_thread.queue.on_finished();
+                        }
+                        continue;
+                    }
+                    if disconnected {
+                        warn!("Consensus Executor stopped by channel disconnected");
+                        break;
+                    }
+                    // The queue is empty, so we try to optimistically
+                    // get later epochs to execute. Here we use `try_write` because some thread
+                    // may wait for execution results while holding the Consensus Inner lock,
+                    // if we wait on inner lock here we may get deadlock
+                    let maybe_optimistic_task = consensus_inner
+                        .try_write_at("ConsensusExecutor::optimistic_execution")
+                        .and_then(|mut inner|
+                            executor_thread.get_optimistic_execution_task(&mut *inner)
+                        );
+                    match maybe_optimistic_task {
+                        Some(task) => {
+                            debug!("Get optimistic_execution_task {:?}", task);
+                            handler.handle_epoch_execution(task)
+                        },
+                        None => {
+                            debug!("No optimistic tasks to execute, block for new tasks");
+                            //  Even optimistic tasks are all finished, so we block and wait for
+                            //  new execution tasks.
+                            //  New optimistic tasks will only exist if pivot_chain changes,
+                            //  and new tasks will be sent to `receiver` in this case, so this
+                            // waiting will not prevent new optimistic tasks from being executed
+                            match receiver.recv() {
+                                Ok(task) => pending.push_back(task),
+                                Err(e) => {
+                                    warn!("Consensus Executor stopped by Err={:?}", e);
+                                    break;
+                                }
+                            }
+                        }
                     }
                 }
             })
@@ -271,6 +495,47 @@ impl ConsensusExecutor {
         }
     }
 
+    fn next_task(
+        &self, pending: &mut VecDeque<ExecutionTask>,
+    ) -> Option<ExecutionTask> {
+        pop_next_task(pending, |epoch_hash| {
+            self.parent_state_available(epoch_hash)
+        })
+    }
+
+    fn parent_state_available(&self, epoch_hash: &H256) -> bool {
+        match self.handler.data_man.block_header_by_hash(epoch_hash) {
+            Some(header) => {
+                self.handler.data_man.epoch_executed(header.parent_hash())
+            }
+            None => false,
+        }
+    }
+
+    /// The number of epochs waiting to be executed by the execution thread.
+    pub fn queue_len(&self) -> usize { self.queue.len() }
+
+    /// Return `true` if the execution queue exceeds its bound, in which case
+    /// new blocks should not be processed until it drains.
+    pub fn is_overloaded(&self) -> bool { self.queue.is_overloaded() }
+
+    /// Block until the execution queue is within its bound or the executor
+    /// is stopped. This must not be called while holding any lock that
+    /// other threads wait for, e.g. the consensus inner lock or the sync
+    /// graph lock.
+    pub fn wait_for_capacity(&self) {
+        if !self.is_overloaded() {
+            return;
+        }
+        debug!(
+            "Execution queue is full with {} epochs, wait for it to drain",
+            self.queue_len()
+        );
+        while self.is_overloaded() && !self.stopped.load(Relaxed) {
+            sleep(EXECUTION_QUEUE_POLL_INTERVAL);
+        }
+    }
+
     fn get_optimistic_execution_task(
         &self, inner: &mut ConsensusGraphInner,
     ) -> Option<EpochExecutionTask> {
@@ -453,11 +718,11 @@ impl ConsensusExecutor {
     }
 
     fn wait_and_compute_execution_info(
-        &self, me: usize, inner_lock: &RwLock<ConsensusGraphInner>,
+        &self, me: usize, inner_lock: &ProfiledRwLock<ConsensusGraphInner>,
     ) -> Result<(), String> {
         // We go up and find all states whose execution_infos are missing
         let waiting_blocks = inner_lock
-            .read()
+            .read_at("ConsensusExecutor::wait_and_compute_execution_info")
             .collect_blocks_missing_execution_info(me)?;
         // Now we wait without holding the inner lock
         // Note that we must use hash instead of index because once we release
@@ -473,7 +738,7 @@ impl ConsensusExecutor {
         // Now we need to wait for the execution information of all missing
         // blocks to come back
         inner_lock
-            .write()
+            .write_at("ConsensusExecutor::wait_and_compute_execution_info")
             .compute_execution_info_for_blocks(waiting_result)?;
         Ok(())
     }
@@ -504,13 +769,14 @@ impl ConsensusExecutor {
 
     pub fn get_blame_and_deferred_state_for_generation(
         &self, parent_block_hash: &H256,
-        inner_lock: &RwLock<ConsensusGraphInner>,
+        inner_lock: &ProfiledRwLock<ConsensusGraphInner>,
     ) -> Result<(u32, StateRootWithAuxInfo, H256, H256, H256), String>
     {
         let parent;
         let last_state_block;
         {
-            let inner = inner_lock.read();
+            let inner = inner_lock
+                .read_at("ConsensusExecutor::get_blame_and_deferred_state");
             let parent_opt = inner.hash_to_arena_indices.get(parent_block_hash);
             if parent_opt.is_none() {
                 return Err(
@@ -521,14 +787,15 @@ impl ConsensusExecutor {
             last_state_block = inner
                 .get_state_block_with_delay(
                     parent_block_hash,
-                    DEFERRED_STATE_EPOCH_COUNT as usize - 1,
+                    inner.deferred_state_epoch_count() as usize - 1,
                 )?
                 .clone();
         }
         let last_result = self.wait_for_result(last_state_block);
         self.wait_and_compute_execution_info(parent, inner_lock)?;
         {
-            let inner = &mut *inner_lock.write();
+            let inner = &mut *inner_lock
+                .write_at("ConsensusExecutor::get_blame_and_deferred_state");
             if inner.arena[parent].hash == *parent_block_hash {
                 Ok(inner.compute_blame_and_state_with_execution_result(
                     parent,
@@ -546,10 +813,16 @@ impl ConsensusExecutor {
     /// holding inner lock.
     pub fn enqueue_epoch(&self, task: EpochExecutionTask) -> bool {
         if !self.bench_mode {
-            self.sender
+            self.queue.on_enqueued();
+            let sent = self
+                .sender
                 .lock()
                 .send(ExecutionTask::ExecuteEpoch(task))
-                .is_ok()
+                .is_ok();
+            if !sent {
+                self.queue.on_finished();
+            }
+            sent
         } else {
             true
         }
@@ -562,12 +835,48 @@ impl ConsensusExecutor {
         }
     }
 
+    /// Execute the transactions of an executed epoch again to recompute the
+    /// receipts of its blocks, without changing the state or persisting
+    /// anything.
+    pub fn recompute_epoch_receipts(
+        &self, epoch_block_hashes: &Vec<H256>, start_block_number: u64,
+    ) -> Result<Vec<Arc<Vec<Receipt>>>, String> {
+        self.handler
+            .recompute_epoch_receipts(epoch_block_hashes, start_block_number)
+    }
+
+    /// Execute an executed epoch again, including its rewards, and compute
+    /// its state root without committing the state.
+    pub fn recompute_epoch_state_root(
+        &self, epoch_block_hashes: &Vec<H256>, start_block_number: u64,
+        reward_execution_info: &Option<RewardExecutionInfo>,
+    ) -> Result<StateRootWithAuxInfo, String>
+    {
+        self.handler.recompute_epoch_state_root(
+            epoch_block_hashes,
+            start_block_number,
+            reward_execution_info,
+        )
+    }
+
     pub fn call_virtual(
         &self, tx: &SignedTransaction, epoch_id: &H256,
-    ) -> Result<(Vec<u8>, U256), String> {
+    ) -> VirtualCallResult {
         self.handler.call_virtual(tx, epoch_id)
     }
 
+    pub fn estimate_gas(
+        &self, tx: &SignedTransaction, epoch_id: &H256, gas_upper_bound: U256,
+    ) -> Result<U256, ConsensusError> {
+        self.handler.estimate_gas(tx, epoch_id, gas_upper_bound)
+    }
+
+    pub fn skipped_transaction_stats(
+        &self, epoch_hash: &H256,
+    ) -> Option<SkippedTransactionStats> {
+        self.handler.skipped_transaction_stats(epoch_hash)
+    }
+
     pub fn stop(&self) {
         // `stopped` is used to allow the execution thread to stopped even the
         // queue is not empty and `ExecutionTask::Stop` has not been
@@ -688,12 +997,13 @@ impl ConsensusExecutor {
 
         // We need the state of the fork point to start executing the fork
         if start_chain_index != 0 {
-            let mut last_state_height =
-                if inner.get_pivot_height() > DEFERRED_STATE_EPOCH_COUNT {
-                    inner.get_pivot_height() - DEFERRED_STATE_EPOCH_COUNT
-                } else {
-                    0
-                };
+            let mut last_state_height = if inner.get_pivot_height()
+                > inner.deferred_state_epoch_count()
+            {
+                inner.get_pivot_height() - inner.deferred_state_epoch_count()
+            } else {
+                0
+            };
 
             last_state_height += 1;
             while last_state_height <= fork_height {
@@ -744,33 +1054,61 @@ pub struct ConsensusExecutionHandler {
     tx_pool: SharedTransactionPool,
     data_man: Arc<BlockDataManager>,
     pub vm: VmFactory,
+    /// The built-in contracts available to the transactions.
+    machine: Arc<Machine>,
+    skipped_tx_stats: Mutex<VecDeque<(H256, SkippedTransactionStats)>>,
+    virtual_call_cache: Mutex<VirtualCallCache>,
+    epoch_executed_observers: RwLock<Vec<EpochExecutedObserver>>,
 }
 
 impl ConsensusExecutionHandler {
     pub fn new(
         tx_pool: SharedTransactionPool, data_man: Arc<BlockDataManager>,
-        vm: VmFactory,
+        vm: VmFactory, machine: Arc<Machine>,
     ) -> Self
     {
         ConsensusExecutionHandler {
             tx_pool,
             data_man,
             vm,
+            machine,
+            skipped_tx_stats: Mutex::new(VecDeque::new()),
+            virtual_call_cache: Mutex::new(VirtualCallCache::new()),
+            epoch_executed_observers: RwLock::new(Vec::new()),
         }
     }
 
-    /// Return `false` if someting goes wrong, and we will break the working
-    /// loop. `maybe_task` should match results from `recv()`, so it does not
-    /// contain `Empty` case.
-    fn handle_recv_result(
-        &self, maybe_task: Result<ExecutionTask, RecvError>,
-    ) -> bool {
-        match maybe_task {
-            Ok(task) => self.handle_execution_work(task),
-            Err(e) => {
-                warn!("Consensus Executor stopped by Err={:?}", e);
-                false
-            }
+    pub fn register_epoch_executed_observer(
+        &self, observer: EpochExecutedObserver,
+    ) {
+        self.epoch_executed_observers.write().push(observer);
+    }
+
+    pub fn skipped_transaction_stats(
+        &self, epoch_hash: &H256,
+    ) -> Option<SkippedTransactionStats> {
+        self.skipped_tx_stats
+            .lock()
+            .iter()
+            .rev()
+            .find(|(hash, _)| hash == epoch_hash)
+            .map(|(_, stats)| stats.clone())
+    }
+
+    fn record_skipped_transactions(
+        &self, epoch_hash: H256, stats: SkippedTransactionStats,
+    ) {
+        if stats.total() > 0 {
+            debug!(
+                "skipped transactions in epoch {:?}: {:?}",
+                epoch_hash, stats
+            );
+            SKIPPED_TPS_METER.mark(stats.total());
+        }
+        let mut skipped_tx_stats = self.skipped_tx_stats.lock();
+        skipped_tx_stats.push_back((epoch_hash, stats));
+        if skipped_tx_stats.len() > MAX_SKIPPED_TX_STATS_EPOCHS {
+            skipped_tx_stats.pop_front();
         }
     }
 
@@ -789,6 +1127,7 @@ impl ConsensusExecutionHandler {
 
     fn handle_epoch_execution(&self, task: EpochExecutionTask) {
         let _timer = MeterTimer::time_func(CONSENSIS_EXECUTION_TIMER.as_ref());
+        let start = Instant::now();
         self.compute_epoch(
             &task.epoch_hash,
             &task.epoch_block_hashes,
@@ -797,6 +1136,7 @@ impl ConsensusExecutionHandler {
             task.on_local_pivot,
             &mut *task.debug_record.lock(),
         );
+        EPOCH_EXECUTION_LATENCY.update(start.elapsed().as_millis() as u64);
     }
 
     fn handle_get_result_task(&self, task: GetExecutionResultTask) {
@@ -854,12 +1194,28 @@ impl ConsensusExecutionHandler {
             )
         {
             if on_local_pivot {
+                // The epoch may have been executed off the pivot chain, so
+                // the bloom index may still hold the previous pivot block.
+                let epoch_number = self
+                    .data_man
+                    .block_header_by_hash(epoch_hash)
+                    .expect("headers exist")
+                    .height();
+                self.data_man.index_executed_epoch_bloom(
+                    epoch_number,
+                    epoch_hash,
+                    epoch_block_hashes,
+                );
                 self.tx_pool.set_best_executed_epoch(epoch_hash);
             }
             debug!("Skip execution in prefix {:?}", epoch_hash);
             return;
         }
 
+        // The state of this epoch is recomputed, so the virtual call results
+        // on its previous state are no longer valid.
+        self.virtual_call_cache.lock().invalidate(epoch_hash);
+
         // Get blocks in this epoch after skip checking
         let epoch_blocks = self
             .data_man
@@ -876,15 +1232,20 @@ impl ConsensusExecutionHandler {
             epoch_blocks.len()
         );
 
+        let parent_height = pivot_block.block_header.height() - 1;
+        let snapshot = self
+            .data_man
+            .storage_manager
+            .snapshot_of_epoch(parent_height);
         let mut state = State::new(
             StateDb::new(
                 self.data_man
                     .storage_manager
                     .get_state_for_next_epoch(
-                        // FIXME: delta height.
-                        SnapshotAndEpochIdRef::new(
+                        SnapshotAndEpochIdRef::new_with_snapshot(
                             pivot_block.block_header.parent_hash(),
-                            Some(pivot_block.block_header.height() - 1),
+                            &snapshot,
+                            parent_height,
                         ),
                     )
                     .expect("No db error")
@@ -894,7 +1255,7 @@ impl ConsensusExecutionHandler {
             0.into(),
             self.vm.clone(),
         );
-        self.process_epoch_transactions(
+        let epoch_receipts = self.process_epoch_transactions(
             &mut state,
             &epoch_blocks,
             start_block_number,
@@ -912,6 +1273,13 @@ impl ConsensusExecutionHandler {
             );
         }
 
+        let observers = self.epoch_executed_observers.read();
+        let state_diff = if on_local_pivot && !observers.is_empty() {
+            Some(state.dirty_accounts())
+        } else {
+            None
+        };
+
         // FIXME: We may want to propagate the error up
         let state_root = if on_local_pivot {
             state.commit_and_notify(*epoch_hash, &self.tx_pool).unwrap();
@@ -927,6 +1295,19 @@ impl ConsensusExecutionHandler {
             "compute_epoch: on_local_pivot={}, epoch={:?} state_root={:?} receipt_root={:?}, logs_bloom_hash={:?}",
             on_local_pivot, epoch_hash, state_root, epoch_execution_commitments.receipts_root, epoch_execution_commitments.logs_bloom_hash,
         );
+
+        if state_diff.is_some() {
+            let executed = ExecutedEpoch {
+                epoch_hash: *epoch_hash,
+                epoch_number: pivot_block.block_header.height(),
+                blocks: epoch_blocks.clone(),
+                receipts: epoch_receipts,
+                state_diff,
+            };
+            for observer in observers.iter() {
+                observer(&executed);
+            }
+        }
     }
 
     fn process_epoch_transactions(
@@ -935,137 +1316,28 @@ impl ConsensusExecutionHandler {
     ) -> Vec<Arc<Vec<Receipt>>>
     {
         let pivot_block = epoch_blocks.last().expect("Epoch not empty");
-        let spec = Spec::new_spec();
-        let machine = new_machine_with_builtin();
-        let mut epoch_receipts = Vec::with_capacity(epoch_blocks.len());
-        let mut to_pending = Vec::new();
-        let mut block_number = start_block_number;
-        for block in epoch_blocks.iter() {
-            let mut receipts = Vec::new();
-            debug!(
-                "process txs in block: hash={:?}, tx count={:?}",
-                block.hash(),
-                block.transactions.len()
-            );
-            let mut env = Env {
-                number: block_number,
-                author: block.block_header.author().clone(),
-                timestamp: block.block_header.timestamp(),
-                difficulty: block.block_header.difficulty().clone(),
-                gas_used: U256::zero(),
-                last_hashes: Arc::new(vec![]),
-                gas_limit: U256::from(block.block_header.gas_limit()),
-            };
-            block_number += 1;
-            let mut accumulated_fee: U256 = 0.into();
-            let mut n_invalid_nonce = 0;
-            let mut n_ok = 0;
-            let mut n_other = 0;
-            let mut cumulative_gas_used = U256::zero();
-            for (idx, transaction) in block.transactions.iter().enumerate() {
-                let mut tx_outcome_status =
-                    TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING;
-                let mut transaction_logs = Vec::new();
-                let mut nonce_increased = false;
-
-                let r = {
-                    Executive::new(state, &env, &machine, &spec)
-                        .transact(transaction, &mut nonce_increased)
-                };
-                // TODO Store fine-grained output status in receipts.
-                // Note now NotEnoughCash has
-                // outcome_status=TRANSACTION_OUTCOME_EXCEPTION,
-                // but its nonce is increased, which might need fixing.
-                match r {
-                    Err(ExecutionError::NotEnoughBaseGas {
-                        required: _,
-                        got: _,
-                    })
-                    | Err(ExecutionError::SenderMustExist {})
-                    | Err(ExecutionError::Internal(_)) => {
-                        warn!(
-                            "tx execution error: transaction={:?}, err={:?}",
-                            transaction, r
-                        );
-                    }
-                    Err(ExecutionError::InvalidNonce { expected, got }) => {
-                        // not inc nonce
-                        n_invalid_nonce += 1;
-                        trace!("tx execution InvalidNonce without inc_nonce: transaction={:?}, err={:?}", transaction.clone(), r);
-                        // Add future transactions back to pool if we are
-                        // not verifying forking chain
-                        if on_local_pivot && got > expected {
-                            trace!(
-                                "To re-add transaction ({:?}) to pending pool",
-                                transaction.clone()
-                            );
-                            to_pending.push(transaction.clone());
-                        }
-                    }
-                    Ok(ref executed) => {
-                        if executed.exception.is_some() {
-                            warn!(
-                                "tx execution error: transaction={:?}, err={:?}",
-                                transaction, r
-                            );
-                        } else {
-                            env.gas_used = executed.cumulative_gas_used;
-                            cumulative_gas_used = executed.cumulative_gas_used;
-                            n_ok += 1;
-                            GOOD_TPS_METER.mark(1);
-                            trace!("tx executed successfully: transaction={:?}, result={:?}, in block {:?}", transaction, executed, block.hash());
-                            accumulated_fee += executed.fee;
-                            transaction_logs = executed.logs.clone();
-                            tx_outcome_status = TRANSACTION_OUTCOME_SUCCESS;
-                        }
-                    }
-                    _ => {
-                        n_other += 1;
-                        trace!("tx executed: transaction={:?}, result={:?}, in block {:?}", transaction, r, block.hash());
-                    }
-                }
-
-                if nonce_increased
-                    && tx_outcome_status != TRANSACTION_OUTCOME_SUCCESS
-                {
-                    tx_outcome_status =
-                        TRANSACTION_OUTCOME_EXCEPTION_WITH_NONCE_BUMPING;
-                }
-
-                let receipt = Receipt::new(
-                    tx_outcome_status,
-                    cumulative_gas_used,
-                    transaction_logs,
-                );
-                receipts.push(receipt);
-
-                if on_local_pivot {
-                    let hash = transaction.hash();
-                    let tx_addr = TransactionAddress {
-                        block_hash: block.hash(),
-                        index: idx,
-                    };
-                    if tx_outcome_status
-                        != TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING
-                    {
-                        self.data_man
-                            .insert_transaction_address(&hash, &tx_addr);
-                    }
-                }
-            }
+        let (epoch_receipts, to_pending, skipped) = execute_epoch_transactions(
+            &self.machine,
+            state,
+            epoch_blocks,
+            start_block_number,
+        );
 
-            let block_receipts = Arc::new(receipts);
+        for (block, block_receipts) in epoch_blocks.iter().zip(&epoch_receipts)
+        {
             self.data_man.insert_block_results(
                 block.hash(),
                 pivot_block.hash(),
                 block_receipts.clone(),
                 on_local_pivot,
             );
-            epoch_receipts.push(block_receipts);
-            debug!(
-                "n_invalid_nonce={}, n_ok={}, n_other={}",
-                n_invalid_nonce, n_ok, n_other
-            );
+            if on_local_pivot {
+                self.insert_transaction_addresses(
+                    block,
+                    &pivot_block.hash(),
+                    block_receipts,
+                );
+            }
         }
 
         self.data_man.insert_epoch_execution_commitments(
@@ -1073,6 +1345,20 @@ impl ConsensusExecutionHandler {
             BlockHeaderBuilder::compute_block_receipts_root(&epoch_receipts),
             BlockHeaderBuilder::compute_block_logs_bloom_hash(&epoch_receipts),
         );
+        if on_local_pivot {
+            self.data_man.index_epoch_bloom(
+                pivot_block.block_header.height(),
+                &pivot_block.hash(),
+                BlockHeaderBuilder::compute_aggregated_bloom(
+                    epoch_receipts
+                        .iter()
+                        .flat_map(|receipts| receipts.iter())
+                        .map(|receipt| receipt.log_bloom)
+                        .collect(),
+                ),
+            );
+        }
+        self.record_skipped_transactions(pivot_block.hash(), skipped);
 
         if on_local_pivot {
             self.tx_pool.recycle_transactions(to_pending);
@@ -1082,6 +1368,113 @@ impl ConsensusExecutionHandler {
         epoch_receipts
     }
 
+    /// Execute the transactions of the epoch on the state of its parent,
+    /// which is neither committed nor modified, and return the receipts.
+    pub fn recompute_epoch_receipts(
+        &self, epoch_block_hashes: &Vec<H256>, start_block_number: u64,
+    ) -> Result<Vec<Arc<Vec<Receipt>>>, String> {
+        let (epoch_blocks, mut state) =
+            self.epoch_blocks_and_parent_state(epoch_block_hashes)?;
+        let (epoch_receipts, _, _) = execute_epoch_transactions(
+            &self.machine,
+            &mut state,
+            &epoch_blocks,
+            start_block_number,
+        );
+        Ok(epoch_receipts)
+    }
+
+    /// Execute the transactions and rewards of the epoch on the state of its
+    /// parent, and compute the state root without committing it.
+    pub fn recompute_epoch_state_root(
+        &self, epoch_block_hashes: &Vec<H256>, start_block_number: u64,
+        reward_execution_info: &Option<RewardExecutionInfo>,
+    ) -> Result<StateRootWithAuxInfo, String>
+    {
+        let (epoch_blocks, mut state) =
+            self.epoch_blocks_and_parent_state(epoch_block_hashes)?;
+        execute_epoch_transactions(
+            &self.machine,
+            &mut state,
+            &epoch_blocks,
+            start_block_number,
+        );
+        if let Some(reward_execution_info) = reward_execution_info {
+            self.process_rewards_and_fees(
+                &mut state,
+                reward_execution_info,
+                false, /* on_local_pivot */
+                &mut None,
+            );
+        }
+        state
+            .compute_state_root()
+            .map_err(|e| format!("Failed to compute state root: {:?}", e))
+    }
+
+    /// The blocks of the epoch and a state of its parent to execute them on.
+    fn epoch_blocks_and_parent_state(
+        &self, epoch_block_hashes: &Vec<H256>,
+    ) -> Result<(Vec<Arc<Block>>, State), String> {
+        let epoch_blocks = self
+            .data_man
+            .blocks_by_hash_list(
+                epoch_block_hashes,
+                false, /* update_cache */
+            )
+            .ok_or_else(|| "Some block bodies do not exist".to_string())?;
+        let pivot_block = epoch_blocks
+            .last()
+            .ok_or_else(|| "The epoch is empty".to_string())?;
+
+        let parent_height = pivot_block.block_header.height() - 1;
+        let snapshot = self
+            .data_man
+            .storage_manager
+            .snapshot_of_epoch(parent_height);
+        let state_db = match self
+            .data_man
+            .storage_manager
+            .get_state_for_next_epoch(SnapshotAndEpochIdRef::new_with_snapshot(
+                pivot_block.block_header.parent_hash(),
+                &snapshot,
+                parent_height,
+            )) {
+            Ok(Some(state)) => StateDb::new(state),
+            Ok(None) => {
+                return Err(format!(
+                    "State of the parent of epoch {:?} does not exist",
+                    pivot_block.hash()
+                ))
+            }
+            Err(e) => return Err(format!("Failed to read the state: {:?}", e)),
+        };
+        let state = State::new(state_db, 0.into(), self.vm.clone());
+        Ok((epoch_blocks, state))
+    }
+
+    /// Insert the addresses of the transactions in `block` executed in the
+    /// epoch `epoch_hash`, i.e. the ones whose nonces are bumped.
+    fn insert_transaction_addresses(
+        &self, block: &Block, epoch_hash: &H256, receipts: &[Receipt],
+    ) {
+        for (idx, (transaction, receipt)) in
+            block.transactions.iter().zip(receipts).enumerate()
+        {
+            if receipt.outcome_status
+                != TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING
+            {
+                let tx_addr = TransactionAddress {
+                    block_hash: block.hash(),
+                    index: idx,
+                    epoch_hash: *epoch_hash,
+                };
+                self.data_man
+                    .insert_transaction_address(&transaction.hash(), &tx_addr);
+            }
+        }
+    }
+
     /// `epoch_block_states` includes if a block is partial invalid and its
     /// anticone difficulty
     fn process_rewards_and_fees(
@@ -1101,11 +1494,19 @@ impl ConsensusExecutionHandler {
 
         let epoch_size = epoch_blocks.len();
         let mut epoch_block_total_rewards = Vec::with_capacity(epoch_size);
+        let mut epoch_block_rewards = Vec::with_capacity(epoch_size);
 
         // Base reward and anticone penalties.
         for (enum_idx, block) in epoch_blocks.iter().enumerate() {
             let no_reward = reward_info.epoch_block_no_reward[enum_idx];
 
+            let mut block_reward = BlockRewardResult {
+                block_hash: block.hash(),
+                author: *block.block_header.author(),
+                base_reward: U256::zero(),
+                anticone_penalty: U256::zero(),
+                tx_fee: U256::zero(),
+            };
             if no_reward {
                 epoch_block_total_rewards.push(U256::from(0));
                 if debug_record.is_some() {
@@ -1124,6 +1525,7 @@ impl ConsensusExecutionHandler {
                     );
                     0.into()
                 };
+                block_reward.base_reward = U256::try_from(reward).unwrap();
 
                 if debug_record.is_some() {
                     let debug_out = debug_record.as_mut().unwrap();
@@ -1148,6 +1550,8 @@ impl ConsensusExecutionHandler {
 
                     debug_assert!(reward > anticone_penalty);
                     reward -= anticone_penalty;
+                    block_reward.anticone_penalty =
+                        U256::try_from(anticone_penalty).unwrap();
 
                     if debug_record.is_some() {
                         let debug_out = debug_record.as_mut().unwrap();
@@ -1171,6 +1575,7 @@ impl ConsensusExecutionHandler {
                 debug_assert!(reward <= U512::from(U256::max_value()));
                 epoch_block_total_rewards.push(U256::try_from(reward).unwrap());
             }
+            epoch_block_rewards.push(block_reward);
         }
 
         // Tx fee for each block in this epoch
@@ -1264,6 +1669,7 @@ impl ConsensusExecutionHandler {
             // Add tx fee to reward.
             if let Some(fee) = block_tx_fees.get(&block_hash) {
                 *reward += *fee;
+                epoch_block_rewards[enum_idx].tx_fee = *fee;
                 if !debug_record.is_none() {
                     let debug_out = debug_record.as_mut().unwrap();
                     debug_out.tx_fees.push(BlockHashAuthorValue(
@@ -1292,6 +1698,13 @@ impl ConsensusExecutionHandler {
             }
         }
 
+        if on_local_pivot {
+            self.data_man.insert_epoch_rewards_to_db(
+                &reward_epoch_hash,
+                &epoch_block_rewards,
+            );
+        }
+
         debug!("Give rewards merged_reward={:?}", merged_rewards);
 
         for (address, reward) in merged_rewards {
@@ -1327,15 +1740,20 @@ impl ConsensusExecutionHandler {
             epoch_blocks.len(),
         );
         let pivot_block = epoch_blocks.last().expect("Not empty");
+        let parent_height = pivot_block.block_header.height() - 1;
+        let snapshot = self
+            .data_man
+            .storage_manager
+            .snapshot_of_epoch(parent_height);
         let mut state = State::new(
             StateDb::new(
                 self.data_man
                     .storage_manager
                     .get_state_for_next_epoch(
-                        // FIXME: delta height
-                        SnapshotAndEpochIdRef::new(
+                        SnapshotAndEpochIdRef::new_with_snapshot(
                             pivot_block.block_header.parent_hash(),
-                            Some(pivot_block.block_header.height() - 1),
+                            &snapshot,
+                            parent_height,
                         ),
                     )
                     .unwrap()
@@ -1355,23 +1773,96 @@ impl ConsensusExecutionHandler {
 
     pub fn call_virtual(
         &self, tx: &SignedTransaction, epoch_id: &H256,
-    ) -> Result<(Vec<u8>, U256), String> {
-        let spec = Spec::new_spec();
-        let machine = new_machine_with_builtin();
-        let mut state = State::new(
-            StateDb::new(
-                self.data_man
-                    .storage_manager
-                    .get_state_no_commit(SnapshotAndEpochIdRef::new(
-                        epoch_id, None,
-                    ))
-                    .unwrap()
-                    // Unwrapping is safe because the state exists.
-                    .unwrap(),
-            ),
-            0.into(),
-            self.vm.clone(),
+    ) -> VirtualCallResult {
+        let key = VirtualCallCache::key(tx);
+        if let Some(result) = self.virtual_call_cache.lock().get(epoch_id, &key)
+        {
+            VIRTUAL_CALL_CACHE_HIT.mark(1);
+            return result;
+        }
+        VIRTUAL_CALL_CACHE_MISS.mark(1);
+
+        let result = self.execute_virtual_call(tx, epoch_id);
+        self.virtual_call_cache.lock().insert(epoch_id, key, &result);
+        result
+    }
+
+    fn execute_virtual_call(
+        &self, tx: &SignedTransaction, epoch_id: &H256,
+    ) -> VirtualCallResult {
+        let state_db = self
+            .virtual_state_db(epoch_id)
+            .map_err(ConsensusError::StateUnavailable)?;
+        let executed =
+            self.transact_virtual(tx, epoch_id, state_db).map_err(|e| {
+                ConsensusError::InvalidTransaction(format!("{:?}", e))
+            })?;
+        match executed.exception {
+            None => Ok((executed.output, executed.gas_used)),
+            Some(vm::Error::Reverted) => {
+                Err(ConsensusError::Reverted(executed.output))
+            }
+            Some(e) => Err(ConsensusError::ExecutionFailed(format!("{:?}", e))),
+        }
+    }
+
+    /// Find the minimum gas with which the transaction executes successfully
+    /// by binary search, because the gas used in a successful execution may
+    /// not be enough as the gas limit, e.g. due to the 63/64 rule of calls.
+    /// The gas of `tx` is taken as the upper bound if it is not zero and
+    /// below `gas_upper_bound`, and the upper bound is capped by the gas the
+    /// sender can afford.
+    pub fn estimate_gas(
+        &self, tx: &SignedTransaction, epoch_id: &H256, gas_upper_bound: U256,
+    ) -> Result<U256, ConsensusError> {
+        let balance = self
+            .virtual_state_db(epoch_id)
+            .map_err(ConsensusError::StateUnavailable)?
+            .get_account(&tx.sender)
+            .map_err(|e| ConsensusError::StateUnavailable(e.to_string()))?
+            .map_or(U256::zero(), |account| account.balance);
+        let hi = max_estimated_gas(
+            tx.gas,
+            gas_upper_bound,
+            balance,
+            tx.value,
+            tx.gas_price,
         );
+        search_minimum_gas(hi, |gas| {
+            let mut tx = tx.clone();
+            tx.transaction.unsigned.gas = gas;
+            let state_db = self
+                .virtual_state_db(epoch_id)
+                .map_err(ConsensusError::StateUnavailable)?;
+            self.transact_virtual(&tx, epoch_id, state_db).map_err(|e| {
+                ConsensusError::InvalidTransaction(format!("{:?}", e))
+            })
+        })
+    }
+
+    /// Open the state of the executed epoch `epoch_id` for virtual calls,
+    /// which may have been pruned since the epoch is executed.
+    fn virtual_state_db(&self, epoch_id: &H256) -> Result<StateDb, String> {
+        match self
+            .data_man
+            .storage_manager
+            .get_state_no_commit(SnapshotAndEpochIdRef::new(epoch_id, None))
+        {
+            Ok(Some(state)) => Ok(StateDb::new(state)),
+            Ok(None) => Err(format!(
+                "State for epoch (hash={:?}) does not exist",
+                epoch_id
+            )),
+            Err(e) => Err(format!("Error to get state, err={:?}", e)),
+        }
+    }
+
+    fn transact_virtual(
+        &self, tx: &SignedTransaction, epoch_id: &H256, state_db: StateDb,
+    ) -> ExecutionResult<Executed> {
+        let spec = Spec::new_spec();
+        let machine = &*self.machine;
+        let mut state = State::new(state_db, 0.into(), self.vm.clone());
         let best_block_header = self.data_man.block_header_by_hash(epoch_id);
         trace!("best_block_header: {:?}", best_block_header);
         let time_stamp = match best_block_header {
@@ -1387,11 +1878,671 @@ impl ConsensusExecutionHandler {
             last_hashes: Arc::new(vec![]),
             gas_limit: tx.gas.clone(),
         };
-        let mut ex = Executive::new(&mut state, &env, &machine, &spec);
+        let mut ex = Executive::new(&mut state, &env, machine, &spec);
         let mut nonce_increased = false;
         let r = ex.transact(tx, &mut nonce_increased);
         trace!("Execution result {:?}", r);
-        r.map(|r| (r.output, r.gas_used))
-            .map_err(|e| format!("execution error: {:?}", e))
+        r
+    }
+}
+
+/// Execute the transactions of the epoch on `state` without persisting
+/// anything. Returns the receipts of the blocks, the future transactions
+/// to add back to the pool and the statistics of the skipped ones.
+fn execute_epoch_transactions(
+    machine: &Machine, state: &mut State, epoch_blocks: &Vec<Arc<Block>>,
+    start_block_number: u64,
+) -> (
+    Vec<Arc<Vec<Receipt>>>,
+    Vec<Arc<SignedTransaction>>,
+    SkippedTransactionStats,
+)
+{
+    let pivot_block = epoch_blocks.last().expect("Epoch not empty");
+    let spec = machine.spec_for_epoch(pivot_block.block_header.height());
+    let mut epoch_receipts = Vec::with_capacity(epoch_blocks.len());
+    let mut to_pending = Vec::new();
+    let mut skipped = SkippedTransactionStats::default();
+    let mut block_number = start_block_number;
+    for block in epoch_blocks.iter() {
+        let mut receipts = Vec::new();
+        debug!(
+            "process txs in block: hash={:?}, tx count={:?}",
+            block.hash(),
+            block.transactions.len()
+        );
+        let mut env = Env {
+            number: block_number,
+            author: block.block_header.author().clone(),
+            timestamp: block.block_header.timestamp(),
+            difficulty: block.block_header.difficulty().clone(),
+            gas_used: U256::zero(),
+            last_hashes: Arc::new(vec![]),
+            gas_limit: U256::from(block.block_header.gas_limit()),
+        };
+        block_number += 1;
+        let mut accumulated_fee: U256 = 0.into();
+        let mut n_invalid_nonce = 0;
+        let mut n_ok = 0;
+        let mut n_other = 0;
+        let mut cumulative_gas_used = U256::zero();
+        let mut rejected = false;
+        for transaction in block.transactions.iter() {
+            if rejected {
+                skipped.rejected += 1;
+                let mut receipt = Receipt::new(
+                    TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING,
+                    cumulative_gas_used,
+                    Vec::new(),
+                );
+                receipt.details = Some(ReceiptDetails::default());
+                receipts.push(receipt);
+                continue;
+            }
+
+            let mut tx_outcome_status =
+                TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING;
+            let mut transaction_logs = Vec::new();
+            let mut nonce_increased = false;
+            let mut receipt_details = ReceiptDetails::default();
+
+            let r = {
+                Executive::new(state, &env, machine, &spec)
+                    .transact(transaction, &mut nonce_increased)
+            };
+            // TODO Store fine-grained output status in receipts.
+            // Note now NotEnoughCash has
+            // outcome_status=TRANSACTION_OUTCOME_EXCEPTION,
+            // but its nonce is increased, which might need fixing.
+            match r {
+                Err(ExecutionError::NotEnoughBaseGas {
+                    required: _,
+                    got: _,
+                })
+                | Err(ExecutionError::SenderMustExist {})
+                | Err(ExecutionError::Internal(_)) => {
+                    warn!(
+                        "tx execution error: transaction={:?}, err={:?}",
+                        transaction, r
+                    );
+                }
+                Err(ExecutionError::InvalidNonce { expected, got }) => {
+                    // not inc nonce
+                    n_invalid_nonce += 1;
+                    trace!("tx execution InvalidNonce without inc_nonce: transaction={:?}, err={:?}", transaction.clone(), r);
+                    // Add future transactions back to pool if we are
+                    // not verifying forking chain
+                    if got > expected {
+                        trace!(
+                            "To re-add transaction ({:?}) to pending pool",
+                            transaction.clone()
+                        );
+                        to_pending.push(transaction.clone());
+                    }
+                }
+                Ok(ref executed) => {
+                    receipt_details.gas_charged = executed.gas;
+                    receipt_details.gas_refunded = executed.refunded;
+                    // The contract created by the transaction is the
+                    // last one created, unless the creation fails.
+                    if executed.exception.is_none()
+                        && transaction.action == Action::Create
+                    {
+                        receipt_details.contract_created =
+                            executed.contracts_created.last().cloned();
+                    }
+                    if let Some(vm::Error::Reverted) = executed.exception {
+                        receipt_details.revert_reason =
+                            executed.output.clone();
+                    }
+                    if executed.exception.is_some() {
+                        warn!(
+                            "tx execution error: transaction={:?}, err={:?}",
+                            transaction, r
+                        );
+                    } else {
+                        env.gas_used = executed.cumulative_gas_used;
+                        cumulative_gas_used = executed.cumulative_gas_used;
+                        n_ok += 1;
+                        GOOD_TPS_METER.mark(1);
+                        trace!("tx executed successfully: transaction={:?}, result={:?}, in block {:?}", transaction, executed, block.hash());
+                        accumulated_fee += executed.fee;
+                        transaction_logs = executed.logs.clone();
+                        tx_outcome_status = TRANSACTION_OUTCOME_SUCCESS;
+                    }
+                }
+                _ => {
+                    n_other += 1;
+                    trace!("tx executed: transaction={:?}, result={:?}, in block {:?}", transaction, r, block.hash());
+                }
+            }
+
+            if nonce_increased
+                && tx_outcome_status != TRANSACTION_OUTCOME_SUCCESS
+            {
+                tx_outcome_status =
+                    TRANSACTION_OUTCOME_EXCEPTION_WITH_NONCE_BUMPING;
+            }
+
+            if tx_outcome_status
+                == TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING
+            {
+                if let Err(ref e) = r {
+                    if skipped.record(e)
+                        && spec.invalid_tx_policy
+                            == InvalidTransactionPolicy::Reject
+                    {
+                        rejected = true;
+                    }
+                }
+            }
+
+            let mut receipt = Receipt::new(
+                tx_outcome_status,
+                cumulative_gas_used,
+                transaction_logs,
+            );
+            receipt.details = Some(receipt_details);
+            receipts.push(receipt);
+        }
+
+        epoch_receipts.push(Arc::new(receipts));
+        debug!(
+            "n_invalid_nonce={}, n_ok={}, n_other={}",
+            n_invalid_nonce, n_ok, n_other
+        );
+    }
+
+    (epoch_receipts, to_pending, skipped)
+}
+
+/// Pop the next task to handle from `pending`. An epoch on the local pivot
+/// chain whose parent state is available is handled ahead of the historical
+/// epochs queued before it, but never ahead of a `GetResult` task, which
+/// requires all the tasks before it to finish.
+fn pop_next_task<F>(
+    pending: &mut VecDeque<ExecutionTask>, parent_state_available: F,
+) -> Option<ExecutionTask>
+where F: Fn(&H256) -> bool {
+    let mut priority_index = None;
+    for (index, task) in pending.iter().enumerate() {
+        match task {
+            ExecutionTask::ExecuteEpoch(task) => {
+                if task.on_local_pivot {
+                    if parent_state_available(&task.epoch_hash) {
+                        priority_index = Some(index);
+                    }
+                    break;
+                }
+            }
+            _ => break,
+        }
+    }
+    match priority_index {
+        Some(index) => pending.remove(index),
+        None => pending.pop_front(),
+    }
+}
+
+/// The upper bound of gas to estimate with, which is `tx_gas` if it is not
+/// zero and below `gas_upper_bound`, capped by the gas the sender can afford
+/// with `balance` after paying `value`, so that the execution with the upper
+/// bound does not fail for not enough cash.
+fn max_estimated_gas(
+    tx_gas: U256, gas_upper_bound: U256, balance: U256, value: U256,
+    gas_price: U256,
+) -> U256
+{
+    let hi = if tx_gas.is_zero() || tx_gas > gas_upper_bound {
+        gas_upper_bound
+    } else {
+        tx_gas
+    };
+    if gas_price.is_zero() {
+        hi
+    } else {
+        std::cmp::min(hi, balance.saturating_sub(value) / gas_price)
+    }
+}
+
+/// Find the minimum gas no more than `hi` with which `execute` succeeds by
+/// binary search.
+fn search_minimum_gas<F>(
+    mut hi: U256, mut execute: F,
+) -> Result<U256, ConsensusError>
+where F: FnMut(U256) -> Result<Executed, ConsensusError> {
+    let executed = execute(hi)?;
+    match executed.exception {
+        None => {}
+        Some(vm::Error::Reverted) => {
+            return Err(ConsensusError::Reverted(executed.output));
+        }
+        Some(vm::Error::OutOfGas) => {
+            return Err(ConsensusError::OutOfGas { gas_limit: hi });
+        }
+        Some(e) => {
+            return Err(ConsensusError::ExecutionFailed(format!("{:?}", e)));
+        }
+    }
+
+    // The execution can not succeed with less gas than it uses.
+    let mut lo = executed.gas_used.saturating_sub(1.into());
+    while lo + 1 < hi {
+        let mid = (lo + hi) / 2;
+        let succeeded = match execute(mid) {
+            Ok(executed) => executed.exception.is_none(),
+            Err(e @ ConsensusError::StateUnavailable(_)) => return Err(e),
+            // e.g. not enough base gas
+            Err(_) => false,
+        };
+        if succeeded {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    Ok(hi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        execute_epoch_transactions, max_estimated_gas, pop_next_task,
+        search_minimum_gas, ConsensusError, EpochExecutionTask,
+        ExecutionQueueCounter, ExecutionTask, GetExecutionResultTask,
+        SkippedTransactionStats, VirtualCallCache,
+    };
+    use crate::{
+        executive::Executed,
+        machine::{new_machine_with_builtin, Machine},
+        state::CleanupMode,
+        storage::tests::new_state_manager_for_testing,
+        test_helpers::get_state_for_genesis_write,
+        vm,
+    };
+    use cfx_types::{Address, H256, U256};
+    use keylib::{Generator, Random};
+    use primitives::{
+        receipt::{
+            TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING,
+            TRANSACTION_OUTCOME_SUCCESS,
+        },
+        Action, Block, BlockHeaderBuilder, SignedTransaction, Transaction,
+        TransactionWithSignature,
+    };
+    use std::{
+        collections::VecDeque,
+        sync::{mpsc::channel, Arc},
+    };
+
+    fn epoch_task(epoch: u64, on_local_pivot: bool) -> ExecutionTask {
+        let epoch_hash = H256::from_low_u64_be(epoch);
+        ExecutionTask::ExecuteEpoch(EpochExecutionTask::new(
+            epoch_hash,
+            vec![epoch_hash],
+            0,
+            None,
+            on_local_pivot,
+            false,
+        ))
+    }
+
+    fn epoch_of(task: Option<ExecutionTask>) -> Option<u64> {
+        match task {
+            Some(ExecutionTask::ExecuteEpoch(task)) => {
+                Some(task.epoch_hash.to_low_u64_be())
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_next_task_priority() {
+        let mut pending: VecDeque<_> = vec![
+            epoch_task(1, false),
+            epoch_task(2, false),
+            epoch_task(3, true),
+            epoch_task(4, true),
+        ]
+        .into_iter()
+        .collect();
+        // Only the first pivot epoch is considered, and the tasks are
+        // handled in order if its parent state is not available.
+        let epoch_4 = H256::from_low_u64_be(4);
+        assert_eq!(
+            epoch_of(pop_next_task(&mut pending, |hash| *hash == epoch_4)),
+            Some(1)
+        );
+        // The first pivot epoch is handled ahead of the historical ones if
+        // its parent state is available.
+        assert_eq!(epoch_of(pop_next_task(&mut pending, |_| true)), Some(3));
+        assert_eq!(epoch_of(pop_next_task(&mut pending, |_| false)), Some(2));
+
+        // A pivot epoch is never handled ahead of a `GetResult` task.
+        let (sender, _receiver) = channel();
+        pending.push_front(ExecutionTask::GetResult(GetExecutionResultTask {
+            epoch_hash: H256::zero(),
+            sender,
+        }));
+        match pop_next_task(&mut pending, |_| true) {
+            Some(ExecutionTask::GetResult(_)) => {}
+            task => panic!("unexpected task {:?}", task),
+        }
+        assert_eq!(epoch_of(pop_next_task(&mut pending, |_| true)), Some(4));
+        assert!(pop_next_task(&mut pending, |_| true).is_none());
+    }
+
+    #[test]
+    fn test_execution_queue_counter() {
+        let counter = ExecutionQueueCounter::new(2);
+        counter.on_enqueued();
+        assert_eq!(counter.len(), 1);
+        assert!(!counter.is_overloaded());
+        counter.on_enqueued();
+        assert!(counter.is_overloaded());
+        counter.on_finished();
+        assert_eq!(counter.len(), 1);
+        assert!(!counter.is_overloaded());
+
+        // An unbounded queue is never overloaded.
+        let counter = ExecutionQueueCounter::new(0);
+        for _ in 0..100 {
+            counter.on_enqueued();
+        }
+        assert!(!counter.is_overloaded());
+    }
+
+    fn executed(exception: Option<vm::Error>, gas_used: u64) -> Executed {
+        Executed {
+            exception,
+            gas: U256::zero(),
+            gas_used: gas_used.into(),
+            refunded: U256::zero(),
+            fee: U256::zero(),
+            cumulative_gas_used: gas_used.into(),
+            logs: vec![],
+            contracts_created: vec![],
+            output: b"output".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_search_minimum_gas_with_63_64_rule() {
+        // The call needs 64000 gas to pass 63000 gas to its callee, but
+        // only uses 63000 gas.
+        let mut executions = 0;
+        let gas = search_minimum_gas(1000000.into(), |gas| {
+            executions += 1;
+            if gas >= 64000.into() {
+                Ok(executed(None, 63000))
+            } else {
+                Ok(executed(Some(vm::Error::OutOfGas), gas.as_u64()))
+            }
+        });
+        assert_eq!(gas, Ok(64000.into()));
+        assert!(executions < 20);
+
+        // The gas used is enough if the execution doesn't make calls.
+        let gas = search_minimum_gas(1000000.into(), |gas| {
+            if gas >= 21000.into() {
+                Ok(executed(None, 21000))
+            } else {
+                Err(ConsensusError::InvalidTransaction(
+                    "not enough base gas".into(),
+                ))
+            }
+        });
+        assert_eq!(gas, Ok(21000.into()));
+    }
+
+    #[test]
+    fn test_search_minimum_gas_failures() {
+        assert_eq!(
+            search_minimum_gas(1000000.into(), |_| Ok(executed(
+                Some(vm::Error::Reverted),
+                30000
+            ))),
+            Err(ConsensusError::Reverted(b"output".to_vec()))
+        );
+        assert_eq!(
+            search_minimum_gas(1000000.into(), |gas| Ok(executed(
+                Some(vm::Error::OutOfGas),
+                gas.as_u64()
+            ))),
+            Err(ConsensusError::OutOfGas {
+                gas_limit: 1000000.into()
+            })
+        );
+        assert_eq!(
+            search_minimum_gas(1000000.into(), |_| Err(
+                ConsensusError::StateUnavailable("pruned".into())
+            )),
+            Err(ConsensusError::StateUnavailable("pruned".into()))
+        );
+    }
+
+    #[test]
+    fn test_max_estimated_gas() {
+        let upper_bound = U256::from(3000000000u64);
+        // The sender can afford the upper bound.
+        assert_eq!(
+            max_estimated_gas(
+                0.into(),
+                upper_bound,
+                U256::from(10).pow(20.into()),
+                0.into(),
+                1.into()
+            ),
+            upper_bound
+        );
+        // The upper bound is capped by the balance left after the value.
+        assert_eq!(
+            max_estimated_gas(
+                0.into(),
+                upper_bound,
+                1000000.into(),
+                100000.into(),
+                10.into()
+            ),
+            90000.into()
+        );
+        // The gas of the transaction is taken if it is below the upper bound.
+        assert_eq!(
+            max_estimated_gas(
+                50000.into(),
+                upper_bound,
+                1000000.into(),
+                0.into(),
+                10.into()
+            ),
+            50000.into()
+        );
+        // Nothing is affordable if the value exceeds the balance.
+        assert_eq!(
+            max_estimated_gas(
+                0.into(),
+                upper_bound,
+                1000.into(),
+                2000.into(),
+                1.into()
+            ),
+            0.into()
+        );
+        // Free gas is not capped.
+        assert_eq!(
+            max_estimated_gas(
+                0.into(),
+                upper_bound,
+                0.into(),
+                0.into(),
+                0.into()
+            ),
+            upper_bound
+        );
+    }
+
+    /// Execute a block at `height` with a valid transaction, one with a future
+    /// nonce and another valid one from the same sender, and return the
+    /// outcome status of each transaction and the statistics of the skipped
+    /// ones.
+    fn execute_with_invalid_transaction(
+        machine: &Machine, height: u64,
+    ) -> (Vec<u8>, SkippedTransactionStats) {
+        let sender = Random.generate().unwrap();
+        let storage_manager = new_state_manager_for_testing();
+        let mut state = get_state_for_genesis_write(&storage_manager);
+        state
+            .add_balance(
+                &sender.address(),
+                &U256::from(10).pow(18.into()),
+                CleanupMode::NoEmpty,
+            )
+            .unwrap();
+        let transactions = [0, 5, 1]
+            .iter()
+            .map(|nonce| {
+                Arc::new(
+                    Transaction {
+                        nonce: U256::from(*nonce),
+                        gas_price: U256::from(1),
+                        gas: U256::from(21000),
+                        action: Action::Call(Address::random()),
+                        value: U256::from(1),
+                        data: Vec::new(),
+                    }
+                    .sign(sender.secret()),
+                )
+            })
+            .collect();
+        let header = BlockHeaderBuilder::new()
+            .with_height(height)
+            .with_gas_limit(1000000.into())
+            .build();
+        let block = Arc::new(Block::new(header, transactions));
+
+        let (epoch_receipts, to_pending, skipped) =
+            execute_epoch_transactions(machine, &mut state, &vec![block], 1);
+        // The future transaction is added back to the pool either way.
+        assert_eq!(to_pending.len(), 1);
+        assert_eq!(to_pending[0].nonce, 5.into());
+        let outcomes = epoch_receipts[0]
+            .iter()
+            .map(|receipt| receipt.outcome_status)
+            .collect();
+        (outcomes, skipped)
+    }
+
+    #[test]
+    fn test_invalid_transaction_skipped() {
+        // The reject height is not reached by default.
+        let machine = new_machine_with_builtin();
+        let (outcomes, skipped) =
+            execute_with_invalid_transaction(&machine, 100);
+        assert_eq!(
+            outcomes,
+            vec![
+                TRANSACTION_OUTCOME_SUCCESS,
+                TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING,
+                TRANSACTION_OUTCOME_SUCCESS,
+            ]
+        );
+        assert_eq!(skipped.invalid_nonce, 1);
+        assert_eq!(skipped.rejected, 0);
+        assert_eq!(skipped.total(), 1);
+    }
+
+    #[test]
+    fn test_invalid_transaction_rejected() {
+        let mut machine = new_machine_with_builtin();
+        machine.set_invalid_tx_reject_height(100);
+
+        // Before the reject height, only the invalid transaction is skipped.
+        let (outcomes, skipped) =
+            execute_with_invalid_transaction(&machine, 99);
+        assert_eq!(outcomes[2], TRANSACTION_OUTCOME_SUCCESS);
+        assert_eq!(skipped.rejected, 0);
+
+        // From the reject height, the rest of the block is skipped as well.
+        let (outcomes, skipped) =
+            execute_with_invalid_transaction(&machine, 100);
+        assert_eq!(
+            outcomes,
+            vec![
+                TRANSACTION_OUTCOME_SUCCESS,
+                TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING,
+                TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING,
+            ]
+        );
+        assert_eq!(skipped.invalid_nonce, 1);
+        assert_eq!(skipped.rejected, 1);
+        assert_eq!(skipped.total(), 2);
+    }
+
+    fn virtual_call(sender: Address, value: u64) -> SignedTransaction {
+        let tx = Transaction {
+            nonce: U256::zero(),
+            gas_price: U256::from(1),
+            gas: U256::from(21000),
+            action: Action::Call(Address::from_low_u64_be(1)),
+            value: U256::from(value),
+            data: Vec::new(),
+        };
+        let mut signed_tx = SignedTransaction::new_unsigned(
+            TransactionWithSignature::new_unsigned(tx),
+        );
+        signed_tx.sender = sender;
+        signed_tx
+    }
+
+    #[test]
+    fn test_virtual_call_cache_hit_and_invalidation() {
+        let mut cache = VirtualCallCache::new();
+        let epoch = H256::from_low_u64_be(1);
+        let other_epoch = H256::from_low_u64_be(2);
+        let sender = Address::from_low_u64_be(2);
+        let key = VirtualCallCache::key(&virtual_call(sender, 1));
+        let result = Ok((b"output".to_vec(), U256::from(21000)));
+
+        assert_eq!(cache.get(&epoch, &key), None);
+        cache.insert(&epoch, key, &result);
+        assert_eq!(cache.get(&epoch, &key), Some(result.clone()));
+        assert_eq!(cache.get(&other_epoch, &key), None);
+
+        // Re-executing the epoch drops its results.
+        cache.invalidate(&epoch);
+        assert_eq!(cache.get(&epoch, &key), None);
+    }
+
+    #[test]
+    fn test_virtual_call_cache_skips_transient_errors() {
+        let mut cache = VirtualCallCache::new();
+        let epoch = H256::from_low_u64_be(1);
+        let sender = Address::from_low_u64_be(2);
+        let key = VirtualCallCache::key(&virtual_call(sender, 1));
+
+        cache.insert(
+            &epoch,
+            key,
+            &Err(ConsensusError::StateUnavailable("pruned".into())),
+        );
+        assert_eq!(cache.get(&epoch, &key), None);
+        cache.insert(&epoch, key, &Err(ConsensusError::Other("other".into())));
+        assert_eq!(cache.get(&epoch, &key), None);
+
+        let reverted = Err(ConsensusError::Reverted(b"output".to_vec()));
+        cache.insert(&epoch, key, &reverted);
+        assert_eq!(cache.get(&epoch, &key), Some(reverted));
+    }
+
+    #[test]
+    fn test_virtual_call_cache_key() {
+        let sender = Address::from_low_u64_be(2);
+        let key = VirtualCallCache::key(&virtual_call(sender, 1));
+        assert_eq!(key, VirtualCallCache::key(&virtual_call(sender, 1)));
+        assert_ne!(
+            key,
+            VirtualCallCache::key(&virtual_call(Address::from_low_u64_be(3), 1))
+        );
+        assert_ne!(key, VirtualCallCache::key(&virtual_call(sender, 2)));
     }
 }
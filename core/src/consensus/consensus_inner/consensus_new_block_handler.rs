@@ -6,8 +6,10 @@ use crate::{
     block_data_manager::{BlockDataManager, BlockStatus, LocalBlockInfo},
     consensus::{
         consensus_inner::{
+            chain_health::ChainHealthMonitor,
             confirmation_meter::ConfirmationMeter,
             consensus_executor::{ConsensusExecutor, EpochExecutionTask},
+            misbehavior_tracker::{MisbehaviorKind, MisbehaviorTracker},
             ConsensusGraphInner, NULL, NULLU64,
         },
         debug::ComputeEpochDebugRecord,
@@ -24,7 +26,9 @@ use crate::{
 };
 use cfx_types::H256;
 use hibitset::{BitSet, BitSetLike, DrainableBitSet};
+use metrics::{register_meter_with_group, Meter};
 use parity_bytes::ToPretty;
+use parking_lot::Mutex;
 use primitives::{
     BlockHeader, BlockHeaderBuilder, SignedTransaction, StateRootWithAuxInfo,
 };
@@ -33,15 +37,68 @@ use std::{
     collections::{HashMap, HashSet, VecDeque},
     io::Write,
     mem,
+    ops::Range,
     sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
+lazy_static! {
+    static ref ANTICONE_RECOMPUTE: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "anticone_cache_recompute");
+}
+
 pub struct ConsensusNewBlockHandler {
     conf: ConsensusConfig,
     txpool: SharedTransactionPool,
     data_man: Arc<BlockDataManager>,
     executor: Arc<ConsensusExecutor>,
     statistics: SharedStatistics,
+    pub misbehavior_tracker: MisbehaviorTracker,
+    pub chain_health: ChainHealthMonitor,
+    /// The blocks moved off and onto the pivot chain since the last
+    /// `take_pivot_reorg`.
+    pivot_reorg: Mutex<PivotReorg>,
+}
+
+/// The blocks moved off and onto the pivot chain by pivot chain
+/// reorganizations.
+#[derive(Debug, Default)]
+pub struct PivotReorg {
+    /// The blocks in the retracted epochs that are not in any epoch of the
+    /// new pivot chain.
+    pub retracted_blocks: Vec<H256>,
+    /// The blocks in the epochs of the new pivot chain after the fork
+    /// points.
+    pub enacted_blocks: Vec<H256>,
+}
+
+impl PivotReorg {
+    pub fn is_empty(&self) -> bool {
+        self.retracted_blocks.is_empty() && self.enacted_blocks.is_empty()
+    }
+
+    /// Record a reorganization that retracts the blocks `retracted` and
+    /// enacts the blocks `enacted`, where the blocks retracted by earlier
+    /// reorganizations may be enacted again and vice versa.
+    fn record(&mut self, retracted: Vec<H256>, enacted: Vec<H256>) {
+        let enacted_set: HashSet<_> = enacted.iter().cloned().collect();
+        let retracted_set: HashSet<_> = retracted.iter().cloned().collect();
+
+        self.retracted_blocks
+            .retain(|hash| !enacted_set.contains(hash));
+        self.retracted_blocks.extend(
+            retracted
+                .into_iter()
+                .filter(|hash| !enacted_set.contains(hash)),
+        );
+        self.enacted_blocks
+            .retain(|hash| !retracted_set.contains(hash));
+        self.enacted_blocks.extend(
+            enacted
+                .into_iter()
+                .filter(|hash| !retracted_set.contains(hash)),
+        );
+    }
 }
 
 /// ConsensusNewBlockHandler contains all sub-routines for handling new arriving
@@ -60,6 +117,64 @@ impl ConsensusNewBlockHandler {
             data_man,
             executor,
             statistics,
+            misbehavior_tracker: MisbehaviorTracker::new(),
+            chain_health: ChainHealthMonitor::new(),
+            pivot_reorg: Mutex::new(Default::default()),
+        }
+    }
+
+    /// Take the blocks moved off and onto the pivot chain by pivot chain
+    /// reorganizations since the last call.
+    pub fn take_pivot_reorg(&self) -> PivotReorg {
+        mem::replace(&mut *self.pivot_reorg.lock(), Default::default())
+    }
+
+    /// Record the misbehavior of the author of block `me` and of the blocks
+    /// it blames.
+    fn record_misbehavior(
+        &self, inner: &ConsensusGraphInner, me: usize,
+        block_header: &BlockHeader, era_genesis_height: u64, fully_valid: bool,
+    )
+    {
+        let tracker = &self.misbehavior_tracker;
+        let hash = block_header.hash();
+
+        if !fully_valid {
+            tracker.record(
+                era_genesis_height,
+                *block_header.author(),
+                MisbehaviorKind::PartialInvalid,
+                hash,
+            );
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is before UNIX_EPOCH")
+            .as_secs();
+        if block_header.timestamp() > now + MISBEHAVIOR_TIMESTAMP_DEVIATION_SECS
+        {
+            tracker.record(
+                era_genesis_height,
+                *block_header.author(),
+                MisbehaviorKind::TimestampDeviation,
+                hash,
+            );
+        }
+
+        // a valid block blames the state of its `blame` nearest ancestors
+        if fully_valid {
+            let mut blamed = inner.arena[me].parent;
+            for _ in 0..block_header.blame() {
+                if blamed == NULL {
+                    break;
+                }
+                tracker.record_blamed(
+                    era_genesis_height,
+                    inner.arena[blamed].hash,
+                );
+                blamed = inner.arena[blamed].parent;
+            }
         }
     }
 
@@ -282,7 +397,7 @@ impl ConsensusNewBlockHandler {
 
         inner
             .data_man
-            .set_cur_consensus_era_genesis_hash(&cur_era_hash, &next_era_hash);
+            .advance_checkpoint(&cur_era_hash, &next_era_hash);
     }
 
     fn compute_anticone_bruteforce(
@@ -293,6 +408,7 @@ impl ConsensusNewBlockHandler {
             // This is genesis, so the anticone should be empty
             return BitSet::new();
         }
+        ANTICONE_RECOMPUTE.mark(1);
         let mut last_in_pivot = inner.arena[parent].last_pivot_in_past;
         for referee in &inner.arena[me].referees {
             last_in_pivot =
@@ -350,7 +466,10 @@ impl ConsensusNewBlockHandler {
         anticone
     }
 
-    fn compute_anticone(inner: &mut ConsensusGraphInner, me: usize) -> BitSet {
+    /// Return the anticone barrier of `me` and the size of its anticone.
+    fn compute_anticone(
+        inner: &mut ConsensusGraphInner, me: usize,
+    ) -> (BitSet, usize) {
         let parent = inner.arena[me].parent;
         debug_assert!(parent != NULL);
         debug_assert!(inner.arena[me].children.is_empty());
@@ -419,7 +538,7 @@ impl ConsensusNewBlockHandler {
             anticone.len()
         );
 
-        anticone_barrier
+        (anticone_barrier, anticone.len())
     }
 
     fn check_correct_parent_brutal(
@@ -657,7 +776,7 @@ impl ConsensusNewBlockHandler {
             debug_record.block_txs = blocks
                 .iter()
                 .map(|block| block.transactions.len())
-                .collect::<Vec<_>>();;
+                .collect::<Vec<_>>();
             debug_record.transactions = blocks
                 .iter()
                 .flat_map(|block| block.transactions.clone())
@@ -928,24 +1047,7 @@ impl ConsensusNewBlockHandler {
     fn should_form_checkpoint_at(
         &self, inner: &mut ConsensusGraphInner,
     ) -> usize {
-        // FIXME: We should use finality to implement this function
-        let best_height = inner.best_epoch_number();
-        if best_height <= inner.inner_conf.era_checkpoint_gap {
-            return inner.cur_era_genesis_block_arena_index;
-        }
-        let stable_height = best_height - inner.inner_conf.era_checkpoint_gap;
-        let stable_era_genesis_height =
-            inner.get_era_genesis_height(stable_height - 1, 0);
-        if stable_era_genesis_height < inner.inner_conf.era_epoch_count {
-            return inner.cur_era_genesis_block_arena_index;
-        }
-        let safe_era_height =
-            stable_era_genesis_height - inner.inner_conf.era_epoch_count;
-        if inner.cur_era_genesis_height > safe_era_height {
-            return inner.cur_era_genesis_block_arena_index;
-        }
-        let safe_era_pivot_index = inner.height_to_pivot_index(safe_era_height);
-        inner.pivot_chain[safe_era_pivot_index]
+        inner.ghast_rule.checkpoint_era_genesis(inner)
     }
 
     fn persist_terminals(&self, inner: &ConsensusGraphInner) {
@@ -1026,7 +1128,7 @@ impl ConsensusNewBlockHandler {
                     )
         };
 
-        let anticone_barrier =
+        let (anticone_barrier, anticone_size) =
             ConsensusNewBlockHandler::compute_anticone(inner, me);
 
         let weight_tuple = if anticone_barrier.len() >= ANTICONE_BARRIER_CAP {
@@ -1038,13 +1140,15 @@ impl ConsensusNewBlockHandler {
         self.update_lcts_initial(inner, me);
 
         let mut stable = true;
+        let mut adaptive = false;
         if !pending {
-            let (stable_v, adaptive) = inner.adaptive_weight(
+            let (stable_v, adaptive_v) = inner.adaptive_weight(
                 me,
                 &anticone_barrier,
                 weight_tuple.as_ref(),
             );
             stable = stable_v;
+            adaptive = adaptive_v;
 
             fully_valid = self.check_block_full_validity(
                 me,
@@ -1069,6 +1173,16 @@ impl ConsensusNewBlockHandler {
             }
         }
 
+        if !pending {
+            self.record_misbehavior(
+                inner,
+                me,
+                block_header,
+                era_genesis_height,
+                fully_valid,
+            );
+        }
+
         let block_status = if pending {
             BlockStatus::Pending
         } else if fully_valid {
@@ -1102,6 +1216,7 @@ impl ConsensusNewBlockHandler {
         let mut fork_at =
             inner.pivot_index_to_height(inner.pivot_chain.len() + 1);
         let old_pivot_chain_len = inner.pivot_chain.len();
+        let mut reorg_depth = None;
         if fully_valid && !pending {
             meter.aggregate_total_weight_in_past(my_weight);
 
@@ -1134,10 +1249,26 @@ impl ConsensusNewBlockHandler {
                         (prev_weight, &inner.arena[prev].hash),
                     ) {
                         // The new subtree is heavier, update pivot chain
+                        reorg_depth = Some(
+                            (old_pivot_chain_len
+                                - inner.height_to_pivot_index(fork_at))
+                                as u64,
+                        );
+                        let mut retracted = Vec::new();
                         for discarded_idx in inner
                             .pivot_chain
                             .split_off(inner.height_to_pivot_index(fork_at))
                         {
+                            if !inner.arena[discarded_idx].data.blockset_cleared
+                            {
+                                for index in &inner.arena[discarded_idx]
+                                    .data
+                                    .blockset_in_own_view_of_epoch
+                                {
+                                    retracted.push(inner.arena[*index].hash);
+                                }
+                            }
+                            retracted.push(inner.arena[discarded_idx].hash);
                             // Reset the epoch_number of the discarded fork
                             ConsensusNewBlockHandler::reset_epoch_number_in_epoch(
                                 inner,
@@ -1145,6 +1276,7 @@ impl ConsensusNewBlockHandler {
                             );
                             ConsensusNewBlockHandler::try_clear_blockset_in_own_view_of_epoch(inner, discarded_idx);
                         }
+                        let mut enacted = Vec::new();
                         let mut u = new;
                         loop {
                             if inner.arena[u].data.blockset_cleared {
@@ -1158,6 +1290,13 @@ impl ConsensusNewBlockHandler {
                                     inner.pivot_chain.len(),
                                 ) - 1,
                             );
+                            for index in &inner.arena[u]
+                                .data
+                                .blockset_in_own_view_of_epoch
+                            {
+                                enacted.push(inner.arena[*index].hash);
+                            }
+                            enacted.push(inner.arena[u].hash);
                             let mut heaviest = NULL;
                             let mut heaviest_weight = 0;
                             for index in &inner.arena[u].children {
@@ -1180,6 +1319,7 @@ impl ConsensusNewBlockHandler {
                             }
                             u = heaviest;
                         }
+                        self.pivot_reorg.lock().record(retracted, enacted);
                         pivot_changed = true;
                     } else {
                         // The previous subtree is still heavier, nothing is
@@ -1199,6 +1339,16 @@ impl ConsensusNewBlockHandler {
                     .hash
             );
         }
+        // Pending and partially invalid blocks never extend the pivot chain,
+        // so they're counted as forked blocks.
+        self.chain_health.record_block(
+            block_header.timestamp(),
+            anticone_size,
+            inner.arena[me].referees.len(),
+            adaptive,
+            !extend_pivot,
+            reorg_depth,
+        );
 
         // Now compute last_pivot_in_block and update pivot_metadata.
         // Note that we need to do this for partially invalid blocks to
@@ -1332,12 +1482,12 @@ impl ConsensusNewBlockHandler {
 
             let to_state_pos = if inner
                 .pivot_index_to_height(inner.pivot_chain.len())
-                < DEFERRED_STATE_EPOCH_COUNT
+                < inner.deferred_state_epoch_count()
             {
                 0
             } else {
                 inner.pivot_index_to_height(inner.pivot_chain.len())
-                    - DEFERRED_STATE_EPOCH_COUNT
+                    - inner.deferred_state_epoch_count()
                     + 1
             };
             inner.optimistic_executed_height = if to_state_pos > 0 {
@@ -1346,10 +1496,13 @@ impl ConsensusNewBlockHandler {
                 None
             };
             let mut state_at = fork_at;
-            if fork_at + DEFERRED_STATE_EPOCH_COUNT > old_pivot_chain_height {
-                if old_pivot_chain_height > DEFERRED_STATE_EPOCH_COUNT {
-                    state_at =
-                        old_pivot_chain_height - DEFERRED_STATE_EPOCH_COUNT + 1;
+            if fork_at + inner.deferred_state_epoch_count()
+                > old_pivot_chain_height
+            {
+                if old_pivot_chain_height > inner.deferred_state_epoch_count() {
+                    state_at = old_pivot_chain_height
+                        - inner.deferred_state_epoch_count()
+                        + 1;
                 } else {
                     state_at = 1;
                 }
@@ -1407,23 +1560,24 @@ impl ConsensusNewBlockHandler {
     /// It also recovers receipts_root and logs_bloom_hash in pivot chain.
     /// This function is only invoked from recover_graph_from_db with
     /// header_only being false.
-    pub fn construct_pivot_state(&self, inner: &mut ConsensusGraphInner) {
-        if inner.pivot_chain.len() < DEFERRED_STATE_EPOCH_COUNT as usize {
-            return;
-        }
+    /// Only pivot blocks at indices in `pivot_range` are processed, so that
+    /// callers can release the inner lock between batches. The range is
+    /// clamped to `construct_pivot_state_len()`.
+    pub fn construct_pivot_state(
+        &self, inner: &mut ConsensusGraphInner, pivot_range: Range<usize>,
+    ) {
+        let end = min(pivot_range.end, Self::construct_pivot_state_len(inner));
         // recover `EpochExecutionCommitments` from
         // `execution_info_cache` or recompute the state if it is not exist in
         // `execution_info_cache`
-        for pivot_index in
-            0..inner.pivot_chain.len() - DEFERRED_STATE_EPOCH_COUNT as usize + 1
-        {
+        for pivot_index in pivot_range.start..end {
             let arena_index = inner.pivot_chain[pivot_index];
             let pivot_hash = inner.arena[arena_index].hash;
             if pivot_hash == inner.data_man.true_genesis_block.hash() {
                 continue;
             }
             let exec_pivot_index =
-                pivot_index + DEFERRED_STATE_EPOCH_COUNT as usize;
+                pivot_index + inner.deferred_state_epoch_count() as usize;
             if exec_pivot_index < inner.pivot_chain.len()
                 && inner
                     .execution_info_cache
@@ -1502,4 +1656,41 @@ impl ConsensusNewBlockHandler {
             }
         }
     }
+
+    /// The number of pivot blocks whose state info is rebuilt by
+    /// `construct_pivot_state()`.
+    pub fn construct_pivot_state_len(inner: &ConsensusGraphInner) -> usize {
+        (inner.pivot_chain.len() + 1)
+            .saturating_sub(inner.deferred_state_epoch_count() as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PivotReorg;
+    use cfx_types::H256;
+
+    #[test]
+    fn test_pivot_reorg_record() {
+        let hash = H256::from_low_u64_be;
+        let mut reorg = PivotReorg::default();
+        assert!(reorg.is_empty());
+
+        // a reorganization that only enacts blocks is not empty
+        reorg.record(vec![], vec![hash(5)]);
+        assert!(!reorg.is_empty());
+        let mut reorg = PivotReorg::default();
+
+        // block 2 is retracted but enacted again in an epoch of the new pivot
+        // chain
+        reorg.record(vec![hash(1), hash(2)], vec![hash(2), hash(3)]);
+        assert_eq!(reorg.retracted_blocks, vec![hash(1)]);
+        assert_eq!(reorg.enacted_blocks, vec![hash(3)]);
+
+        // a later reorganization switches back, where block 3 is now off the
+        // pivot chain
+        reorg.record(vec![hash(3)], vec![hash(1), hash(4)]);
+        assert_eq!(reorg.retracted_blocks, vec![hash(3)]);
+        assert_eq!(reorg.enacted_blocks, vec![hash(1), hash(4)]);
+    }
 }
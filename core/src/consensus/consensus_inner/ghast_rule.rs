@@ -0,0 +1,100 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::{ConsensusGraphInner, ConsensusInnerConfig};
+use cfx_types::{U256, U512};
+use hibitset::BitSet;
+use std::convert::TryFrom;
+
+/// The subtree weights, inclusive weights and stable weights of all blocks
+/// in the current era, see `ConsensusGraphInner::compute_subtree_weights`.
+pub type SubtreeWeights = (Vec<i128>, Vec<i128>, Vec<i128>);
+
+/// The rules of GHAST which decide the weights of blocks, whether a block is
+/// adaptive and where checkpoints are formed. Every method has the rule of
+/// the Conflux protocol as its default, so that an experimental rule only
+/// overrides the decisions it changes and can be run with `sim::SimNetwork`
+/// without patching `ConsensusGraphInner`. The defaults only use the public
+/// interface of `ConsensusGraphInner`, so a rule can be implemented outside
+/// this crate. All nodes of a network must use the same rule.
+pub trait GhastRule: Send + Sync {
+    /// Whether a block is heavy, i.e. its pow quality is at least
+    /// `heavy_block_difficulty_ratio` times its difficulty.
+    fn is_heavy(
+        &self, inner_conf: &ConsensusInnerConfig, pow_quality: &U256,
+        difficulty: &U256,
+    ) -> bool
+    {
+        U512::from(pow_quality)
+            >= U512::from(inner_conf.heavy_block_difficulty_ratio)
+                * U512::from(difficulty)
+    }
+
+    /// The weight of a valid block. If a block is not adaptive, the weight is
+    /// its difficulty. If a block is adaptive, then for the heavy blocks, it
+    /// equals to the heavy block ratio times its difficulty. Otherwise, it is
+    /// zero.
+    fn block_weight(
+        &self, inner_conf: &ConsensusInnerConfig, difficulty: &U256,
+        is_heavy: bool, is_adaptive: bool,
+    ) -> i128
+    {
+        let difficulty = i128::try_from(difficulty.low_u128()).unwrap();
+        if !is_adaptive {
+            difficulty
+        } else if is_heavy {
+            inner_conf.heavy_block_difficulty_ratio as i128 * difficulty
+        } else {
+            0
+        }
+    }
+
+    /// Decide whether a new block with `parent` and `difficulty` is stable
+    /// and whether it should be adaptive, and return them in this order. The
+    /// blocks in `anticone_barrier` and their subtrees are not in the past
+    /// of the new block. `subtree_weights` is given if the barrier is too
+    /// large to use the link-cut trees of `inner`.
+    fn adaptive_weight(
+        &self, inner: &mut ConsensusGraphInner, parent: usize,
+        anticone_barrier: &BitSet, subtree_weights: Option<&SubtreeWeights>,
+        difficulty: i128,
+    ) -> (bool, bool)
+    {
+        inner.adaptive_weight_impl(
+            parent,
+            anticone_barrier,
+            subtree_weights,
+            difficulty,
+        )
+    }
+
+    /// The arena index of the era genesis block at which a new checkpoint
+    /// should be formed, or the current era genesis if no checkpoint should
+    /// be formed now.
+    fn checkpoint_era_genesis(&self, inner: &ConsensusGraphInner) -> usize {
+        // FIXME: We should use finality to implement this function
+        let best_height = inner.best_epoch_number();
+        if best_height <= inner.inner_conf.era_checkpoint_gap {
+            return inner.cur_era_genesis_block_arena_index;
+        }
+        let stable_height = best_height - inner.inner_conf.era_checkpoint_gap;
+        let stable_era_genesis_height =
+            inner.get_era_genesis_height(stable_height - 1, 0);
+        if stable_era_genesis_height < inner.inner_conf.era_epoch_count {
+            return inner.cur_era_genesis_block_arena_index;
+        }
+        let safe_era_height =
+            stable_era_genesis_height - inner.inner_conf.era_epoch_count;
+        if inner.get_cur_era_genesis_height() > safe_era_height {
+            return inner.cur_era_genesis_block_arena_index;
+        }
+        let safe_era_pivot_index = inner.height_to_pivot_index(safe_era_height);
+        inner.pivot_chain[safe_era_pivot_index]
+    }
+}
+
+/// The GHAST rule of the Conflux protocol.
+pub struct DefaultGhastRule;
+
+impl GhastRule for DefaultGhastRule {}
@@ -0,0 +1,299 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use cfx_types::{Address, H256};
+use parking_lot::RwLock;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    mem,
+};
+
+/// The number of latest eras whose evidence is kept in memory.
+pub const MAX_MAINTAINED_MISBEHAVIOR_ERAS: usize = 10;
+/// The maximum number of block hashes kept as evidence for each kind of
+/// misbehavior of a producer in an era. The counts are not limited.
+pub const MAX_MISBEHAVIOR_EVIDENCE_PER_KIND: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MisbehaviorKind {
+    /// The block is partially invalid, e.g. it chooses an incorrect parent.
+    PartialInvalid,
+    /// The block timestamp deviates too much from the local clock.
+    TimestampDeviation,
+    /// The block commits an incorrect deferred state and is blamed by its
+    /// descendants.
+    Blamed,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MisbehaviorEvidence {
+    pub count: u64,
+    pub block_hashes: Vec<H256>,
+}
+
+impl MisbehaviorEvidence {
+    fn add(&mut self, block_hash: H256) {
+        self.count += 1;
+        if self.block_hashes.len() < MAX_MISBEHAVIOR_EVIDENCE_PER_KIND {
+            self.block_hashes.push(block_hash);
+        }
+    }
+}
+
+/// The aggregated misbehavior evidence of a block producer in an era.
+#[derive(Clone, Debug, Default)]
+pub struct ProducerMisbehavior {
+    pub partial_invalid: MisbehaviorEvidence,
+    pub timestamp_deviation: MisbehaviorEvidence,
+    pub blamed: MisbehaviorEvidence,
+}
+
+impl ProducerMisbehavior {
+    pub fn total(&self) -> u64 {
+        self.partial_invalid.count
+            + self.timestamp_deviation.count
+            + self.blamed.count
+    }
+}
+
+#[derive(Default)]
+struct EraMisbehavior {
+    /// author -> evidence
+    producers: HashMap<Address, ProducerMisbehavior>,
+    /// The blocks blamed in the era, so that a block blamed by multiple
+    /// descendants is counted once.
+    blamed: HashSet<H256>,
+    /// The blamed blocks whose authors are not looked up yet.
+    unresolved_blamed: Vec<H256>,
+}
+
+/// MisbehaviorTracker aggregates the evidence of misbehaving block producers
+/// (identified by block author) per era, so that the community could make
+/// governance decisions based on it.
+pub struct MisbehaviorTracker {
+    /// era genesis height -> evidence
+    eras: RwLock<BTreeMap<u64, EraMisbehavior>>,
+}
+
+impl MisbehaviorTracker {
+    pub fn new() -> Self {
+        MisbehaviorTracker {
+            eras: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Record that the block `block_hash` is blamed. The author of the block
+    /// is looked up when the report is generated, so that it's not read from
+    /// the db while the consensus graph is locked.
+    pub fn record_blamed(&self, era_genesis_height: u64, block_hash: H256) {
+        let mut eras = self.eras.write();
+        let era = eras
+            .entry(era_genesis_height)
+            .or_insert_with(Default::default);
+        if era.blamed.insert(block_hash) {
+            debug!("Record misbehavior Blamed in block {:?}", block_hash);
+            era.unresolved_blamed.push(block_hash);
+        }
+        Self::remove_old_eras(&mut eras);
+    }
+
+    pub fn record(
+        &self, era_genesis_height: u64, author: Address, kind: MisbehaviorKind,
+        block_hash: H256,
+    )
+    {
+        debug!(
+            "Record misbehavior {:?} of author {:?} in block {:?}",
+            kind, author, block_hash
+        );
+
+        let mut eras = self.eras.write();
+        let producer = eras
+            .entry(era_genesis_height)
+            .or_insert_with(Default::default)
+            .producers
+            .entry(author)
+            .or_insert_with(Default::default);
+        match kind {
+            MisbehaviorKind::PartialInvalid => {
+                producer.partial_invalid.add(block_hash)
+            }
+            MisbehaviorKind::TimestampDeviation => {
+                producer.timestamp_deviation.add(block_hash)
+            }
+            MisbehaviorKind::Blamed => producer.blamed.add(block_hash),
+        }
+
+        Self::remove_old_eras(&mut eras);
+    }
+
+    fn remove_old_eras(eras: &mut BTreeMap<u64, EraMisbehavior>) {
+        while eras.len() > MAX_MAINTAINED_MISBEHAVIOR_ERAS {
+            let oldest = *eras.keys().next().unwrap();
+            eras.remove(&oldest);
+        }
+    }
+
+    /// Look up the authors of the blamed blocks recorded since the last call
+    /// with `author_of`, which is called without holding the lock.
+    fn resolve_blamed<F: Fn(&H256) -> Option<Address>>(&self, author_of: F) {
+        let unresolved: Vec<(u64, Vec<H256>)> = self
+            .eras
+            .write()
+            .iter_mut()
+            .filter(|(_, era)| !era.unresolved_blamed.is_empty())
+            .map(|(height, era)| {
+                (
+                    *height,
+                    mem::replace(&mut era.unresolved_blamed, Vec::new()),
+                )
+            })
+            .collect();
+        for (era_genesis_height, block_hashes) in unresolved {
+            for block_hash in block_hashes {
+                match author_of(&block_hash) {
+                    Some(author) => self.record(
+                        era_genesis_height,
+                        author,
+                        MisbehaviorKind::Blamed,
+                        block_hash,
+                    ),
+                    None => warn!(
+                        "Author of blamed block {:?} is unknown",
+                        block_hash
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Return the misbehaving producers of the era with the specified genesis
+    /// height, or of all maintained eras if `era_genesis_height` is `None`.
+    /// Producers are sorted by their total number of misbehaviors in
+    /// descending order. `author_of` looks up the authors of the blamed
+    /// blocks.
+    pub fn report<F: Fn(&H256) -> Option<Address>>(
+        &self, era_genesis_height: Option<u64>, author_of: F,
+    ) -> Vec<(u64, Address, ProducerMisbehavior)> {
+        self.resolve_blamed(author_of);
+        let eras = self.eras.read();
+        let mut report: Vec<_> = eras
+            .iter()
+            .filter(|(era, _)| match era_genesis_height {
+                Some(height) => **era == height,
+                None => true,
+            })
+            .flat_map(|(era, misbehavior)| {
+                misbehavior.producers.iter().map(
+                    move |(author, misbehavior)| {
+                        (*era, *author, misbehavior.clone())
+                    },
+                )
+            })
+            .collect();
+        report.sort_by(|a, b| b.2.total().cmp(&a.2.total()));
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        MisbehaviorKind, MisbehaviorTracker, MAX_MAINTAINED_MISBEHAVIOR_ERAS,
+    };
+    use cfx_types::{Address, H256};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_shared_blamed_ancestor() {
+        let tracker = MisbehaviorTracker::new();
+        let author = Address::from_low_u64_be(1);
+        let blamed = H256::from_low_u64_be(1);
+        // Two descendants blame the same ancestor.
+        tracker.record_blamed(0, blamed);
+        tracker.record_blamed(0, blamed);
+
+        let mut authors = HashMap::new();
+        authors.insert(blamed, author);
+        let report = tracker.report(None, |hash| authors.get(hash).cloned());
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].1, author);
+        assert_eq!(report[0].2.blamed.count, 1);
+        assert_eq!(report[0].2.blamed.block_hashes, vec![blamed]);
+
+        // The block is not counted again after it's reported.
+        tracker.record_blamed(0, blamed);
+        let report = tracker.report(None, |hash| authors.get(hash).cloned());
+        assert_eq!(report[0].2.blamed.count, 1);
+    }
+
+    #[test]
+    fn test_unknown_blamed_author() {
+        let tracker = MisbehaviorTracker::new();
+        tracker.record_blamed(0, H256::from_low_u64_be(1));
+        assert!(tracker.report(None, |_| None).is_empty());
+    }
+
+    #[test]
+    fn test_report() {
+        let tracker = MisbehaviorTracker::new();
+        let (honest, faulty) =
+            (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        tracker.record(
+            0,
+            honest,
+            MisbehaviorKind::TimestampDeviation,
+            H256::from_low_u64_be(1),
+        );
+        for i in 2..4 {
+            tracker.record(
+                0,
+                faulty,
+                MisbehaviorKind::PartialInvalid,
+                H256::from_low_u64_be(i),
+            );
+        }
+        tracker.record(
+            10,
+            faulty,
+            MisbehaviorKind::PartialInvalid,
+            H256::from_low_u64_be(4),
+        );
+
+        // The producers with more misbehaviors come first.
+        let report = tracker.report(Some(0), |_| None);
+        assert_eq!(
+            report
+                .iter()
+                .map(|(era, author, misbehavior)| (
+                    *era,
+                    *author,
+                    misbehavior.total()
+                ))
+                .collect::<Vec<_>>(),
+            vec![(0, faulty, 2), (0, honest, 1)]
+        );
+        assert_eq!(tracker.report(None, |_| None).len(), 3);
+        assert!(tracker.report(Some(5), |_| None).is_empty());
+    }
+
+    #[test]
+    fn test_old_eras_removed() {
+        let tracker = MisbehaviorTracker::new();
+        let author = Address::from_low_u64_be(1);
+        for era in 0..MAX_MAINTAINED_MISBEHAVIOR_ERAS as u64 + 1 {
+            tracker.record(
+                era,
+                author,
+                MisbehaviorKind::PartialInvalid,
+                H256::from_low_u64_be(era),
+            );
+        }
+        assert!(tracker.report(Some(0), |_| None).is_empty());
+        assert_eq!(
+            tracker.report(None, |_| None).len(),
+            MAX_MAINTAINED_MISBEHAVIOR_ERAS
+        );
+    }
+}
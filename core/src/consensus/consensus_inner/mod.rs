@@ -2,27 +2,37 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
+pub mod chain_health;
 pub mod confirmation_meter;
 pub mod consensus_executor;
 pub mod consensus_new_block_handler;
+pub mod ghast_rule;
+pub mod misbehavior_tracker;
+pub mod referee_selector;
 
+use self::ghast_rule::{GhastRule, SubtreeWeights};
 use crate::{
     block_data_manager::{
         BlockDataManager, ConsensusGraphExecutionInfo, EpochExecutionContext,
     },
-    consensus::{anticone_cache::AnticoneCache, pastset_cache::PastSetCache},
-    parameters::{consensus::*, consensus_internal::*},
+    consensus::{
+        anticone_cache::AnticoneCache, debug::TreeGraphBlock,
+        pastset_cache::PastSetCache,
+    },
+    parameters::{block::REFEREE_BOUND, consensus::*, consensus_internal::*},
     pow::{target_difficulty, ProofOfWorkConfig},
 };
-use cfx_types::{H256, U256, U512};
+use cfx_types::{H256, U256};
 use hibitset::{BitSet, BitSetLike};
 use link_cut_tree::{
     CaterpillarMinLinkCutTree, DefaultMinLinkCutTree, SizeMinLinkCutTree,
 };
+use metrics::{register_meter_with_group, Gauge, Meter};
 use parking_lot::Mutex;
 use primitives::{
-    receipt::Receipt, Block, BlockHeader, BlockHeaderBuilder,
-    StateRootWithAuxInfo, TransactionAddress,
+    receipt::{Receipt, TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING},
+    Block, BlockHeader, BlockHeaderBuilder, StateRootWithAuxInfo,
+    TransactionAddress,
 };
 use slab::Slab;
 use std::{
@@ -32,6 +42,11 @@ use std::{
     sync::Arc,
 };
 
+lazy_static! {
+    static ref PASTSET_RECOMPUTE: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "pastset_cache_recompute");
+}
+
 #[allow(dead_code)]
 const MAX_BLAME_RATIO_FOR_TRUST: f64 = 0.4;
 
@@ -51,11 +66,76 @@ pub struct ConsensusInnerConfig {
     pub era_epoch_count: u64,
     // FIXME: We should replace this to use confirmation risk instead
     pub era_checkpoint_gap: u64,
+    // The number of epochs by which the execution is deferred, i.e. a pivot
+    // block commits to the state of the epoch `deferred_state_epoch_count`
+    // epochs before it. It defaults to `DEFERRED_STATE_EPOCH_COUNT`, and all
+    // the nodes of a chain must agree on it.
+    pub deferred_state_epoch_count: u64,
     // Optimistic execution is the feature to execute ahead of the deferred
     // execution boundary. The goal is to pipeline the transaction
     // execution and the block packaging and verification.
     // optimistic_executed_height is the number of step to go ahead
     pub enable_optimistic_execution: bool,
+    // The anticone sets of the most recent `anticone_cache_stride` blocks are
+    // kept in memory, so that the anticone of a new block is computed from
+    // the one of its parent. An anticone set larger than
+    // `anticone_cache_max_anticone_size` is not kept, unless
+    // `adaptive_anticone_cache` is set and the recent anticone sets are large,
+    // in which case the limit grows with their sizes.
+    pub anticone_cache_stride: usize,
+    pub anticone_cache_max_anticone_size: usize,
+    pub adaptive_anticone_cache: bool,
+    // The number of pastsets of the forking points of the pivot chain kept in
+    // memory.
+    pub pastset_cache_capacity: usize,
+}
+
+impl ConsensusInnerConfig {
+    /// Check that the era parameters are consistent with the consensus
+    /// constants they depend on.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.adaptive_weight_alpha_den == 0 {
+            return Err("adaptive_weight_alpha_den should be positive".into());
+        }
+        if self.deferred_state_epoch_count == 0 {
+            return Err("deferred_state_epoch_count should be positive".into());
+        }
+        // The epochs executed and rewarded with an era genesis block as
+        // their base must be in the same era.
+        let min_era_epoch_count = self.deferred_state_epoch_count
+            + max(REWARD_EPOCH_COUNT, ERA_RECYCLE_TRANSACTION_DELAY);
+        if self.era_epoch_count < min_era_epoch_count {
+            return Err(format!(
+                "era_epoch_count should be at least {}, got {}",
+                min_era_epoch_count, self.era_epoch_count
+            ));
+        }
+        if self.anticone_cache_stride == 0
+            || self.anticone_cache_max_anticone_size == 0
+            || self.pastset_cache_capacity == 0
+        {
+            return Err(
+                "The sizes of the consensus caches should be positive".into()
+            );
+        }
+        // The terminals of a DAG as wide as the referee bound are all in the
+        // anticones of each other, and those anticones should be cached.
+        if self.anticone_cache_max_anticone_size < REFEREE_BOUND {
+            return Err(format!(
+                "anticone_cache_max_anticone_size should be at least {}, \
+                 got {}",
+                REFEREE_BOUND, self.anticone_cache_max_anticone_size
+            ));
+        }
+        // A checkpoint is only formed at an executed epoch.
+        if self.era_checkpoint_gap < self.deferred_state_epoch_count {
+            return Err(format!(
+                "era_checkpoint_gap should be at least {}, got {}",
+                self.deferred_state_epoch_count, self.era_checkpoint_gap
+            ));
+        }
+        Ok(())
+    }
 }
 
 pub struct ConsensusGraphNodeData {
@@ -338,6 +418,9 @@ pub struct ConsensusGraphInner {
     // optimistic_executed_height is the number of step to go ahead
     optimistic_executed_height: Option<u64>,
     pub inner_conf: ConsensusInnerConfig,
+    // The GHAST rule deciding the block weights, the adaptive blocks and the
+    // checkpoints.
+    pub ghast_rule: Arc<dyn GhastRule>,
     // The cache to store Anticone information of each node. This could be very
     // large so we periodically remove old ones in the cache.
     anticone_cache: AnticoneCache,
@@ -353,6 +436,10 @@ pub struct ConsensusGraphInner {
     /// `stable genesis` and `first_trusted_blame_block`.
     first_trusted_blame_block: H256,
     first_trusted_blame_block_height: u64,
+    /// The epochs whose computed state, receipts or logs bloom root does not
+    /// match the one in a header, with a description of the mismatch. They
+    /// are taken by `ConsensusGraph` to dump the diagnostic bundles.
+    pub state_root_mismatches: Vec<(H256, String)>,
 }
 
 pub struct ConsensusGraphNode {
@@ -379,7 +466,9 @@ pub struct ConsensusGraphNode {
 impl ConsensusGraphInner {
     pub fn with_era_genesis_block(
         pow_config: ProofOfWorkConfig, data_man: Arc<BlockDataManager>,
-        inner_conf: ConsensusInnerConfig, cur_era_genesis_block_hash: &H256,
+        inner_conf: ConsensusInnerConfig, ghast_rule: Arc<dyn GhastRule>,
+        anticone_cache_gauge: Arc<dyn Gauge<usize>>,
+        cur_era_genesis_block_hash: &H256,
         first_trusted_blame_block: Option<H256>,
     ) -> Self
     {
@@ -443,8 +532,14 @@ impl ConsensusGraphInner {
             current_difficulty: initial_difficulty.into(),
             data_man: data_man.clone(),
             inner_conf,
-            anticone_cache: AnticoneCache::new(),
-            pastset_cache: Default::default(),
+            ghast_rule,
+            anticone_cache: AnticoneCache::new(
+                inner_conf.anticone_cache_stride,
+                inner_conf.anticone_cache_max_anticone_size,
+                inner_conf.adaptive_anticone_cache,
+                anticone_cache_gauge,
+            ),
+            pastset_cache: PastSetCache::new(inner_conf.pastset_cache_capacity),
             execution_info_cache: HashMap::new(),
             sequence_number_of_block_entrance: 0,
             // TODO handle checkpoint in recovery
@@ -452,6 +547,7 @@ impl ConsensusGraphInner {
             old_era_block_set: Mutex::new(VecDeque::new()),
             first_trusted_blame_block,
             first_trusted_blame_block_height,
+            state_root_mismatches: Vec::new(),
         };
 
         // NOTE: Only genesis block will be first inserted into consensus graph
@@ -566,6 +662,12 @@ impl ConsensusGraphInner {
         inner
     }
 
+    /// The gauge of the anticone cache, which is passed on to the consensus
+    /// graph of the next era.
+    pub fn anticone_cache_gauge(&self) -> Arc<dyn Gauge<usize>> {
+        self.anticone_cache.max_anticone_size_gauge()
+    }
+
     pub fn persist_epoch_set_hashes(&self, pivot_index: usize) {
         let height = self.pivot_index_to_height(pivot_index);
         let arena_index = self.pivot_chain[pivot_index];
@@ -588,6 +690,11 @@ impl ConsensusGraphInner {
         self.pivot_chain[pivot_index]
     }
 
+    #[inline]
+    pub fn deferred_state_epoch_count(&self) -> u64 {
+        self.inner_conf.deferred_state_epoch_count
+    }
+
     #[inline]
     pub fn get_pivot_height(&self) -> u64 {
         self.cur_era_genesis_height + self.pivot_chain.len() as u64
@@ -638,8 +745,12 @@ impl ConsensusGraphInner {
         self.inclusive_weight_tree.lca(me, v)
     }
 
+    /// The height of the era genesis of a block whose parent is at
+    /// `parent_height - offset`.
     #[inline]
-    fn get_era_genesis_height(&self, parent_height: u64, offset: u64) -> u64 {
+    pub fn get_era_genesis_height(
+        &self, parent_height: u64, offset: u64,
+    ) -> u64 {
         let era_genesis_height = if parent_height > offset {
             (parent_height - offset) / self.inner_conf.era_epoch_count
                 * self.inner_conf.era_epoch_count
@@ -654,6 +765,11 @@ impl ConsensusGraphInner {
         self.cur_era_genesis_height
     }
 
+    #[inline]
+    pub fn get_cur_era_stable_height(&self) -> u64 {
+        self.cur_era_stable_height
+    }
+
     #[inline]
     fn get_era_genesis_block_with_parent(
         &self, parent: usize, offset: u64,
@@ -671,7 +787,9 @@ impl ConsensusGraphInner {
     }
 
     #[inline]
-    fn get_epoch_block_hashes(&self, epoch_arena_index: usize) -> Vec<H256> {
+    pub fn get_epoch_block_hashes(
+        &self, epoch_arena_index: usize,
+    ) -> Vec<H256> {
         self.arena[epoch_arena_index]
             .data
             .ordered_executable_epoch_blocks
@@ -681,7 +799,9 @@ impl ConsensusGraphInner {
     }
 
     #[inline]
-    fn get_epoch_start_block_number(&self, epoch_arena_index: usize) -> u64 {
+    pub(super) fn get_epoch_start_block_number(
+        &self, epoch_arena_index: usize,
+    ) -> u64 {
         let parent = self.arena[epoch_arena_index].parent;
 
         return self.arena[parent].past_num_blocks + 1;
@@ -705,7 +825,7 @@ impl ConsensusGraphInner {
     ) -> Option<usize> {
         // this is the earliest block we need to consider; blocks before `from`
         // cannot have any information about the state root of `pivot_index`
-        let from = pivot_index + DEFERRED_STATE_EPOCH_COUNT as usize;
+        let from = pivot_index + self.deferred_state_epoch_count() as usize;
 
         self.find_first_trusted_starting_from(from, blame_bound)
     }
@@ -808,7 +928,8 @@ impl ConsensusGraphInner {
     pub fn check_mining_adaptive_block(
         &mut self, parent_arena_index: usize, difficulty: U256,
     ) -> bool {
-        let (_stable, adaptive) = self.adaptive_weight_impl(
+        let (_stable, adaptive) = self.ghast_rule.clone().adaptive_weight(
+            self,
             parent_arena_index,
             &BitSet::new(),
             None,
@@ -948,10 +1069,11 @@ impl ConsensusGraphInner {
         (stable, adaptive)
     }
 
-    fn adaptive_weight_impl(
+    /// The adaptive weight decision of the default GHAST rule, which custom
+    /// rules may fall back to, see `GhastRule::adaptive_weight`.
+    pub fn adaptive_weight_impl(
         &mut self, parent_0: usize, anticone_barrier: &BitSet,
-        weight_tuple: Option<&(Vec<i128>, Vec<i128>, Vec<i128>)>,
-        difficulty: i128,
+        weight_tuple: Option<&SubtreeWeights>, difficulty: i128,
     ) -> (bool, bool)
     {
         if let Some((
@@ -1183,7 +1305,7 @@ impl ConsensusGraphInner {
     /// both for block generations and for block validations.
     fn adaptive_weight(
         &mut self, me: usize, anticone_barrier: &BitSet,
-        weight_tuple: Option<&(Vec<i128>, Vec<i128>, Vec<i128>)>,
+        weight_tuple: Option<&SubtreeWeights>,
     ) -> (bool, bool)
     {
         let parent = self.arena[me].parent;
@@ -1192,7 +1314,8 @@ impl ConsensusGraphInner {
         let difficulty =
             i128::try_from(self.arena[me].difficulty.low_u128()).unwrap();
 
-        self.adaptive_weight_impl(
+        self.ghast_rule.clone().adaptive_weight(
+            self,
             parent,
             anticone_barrier,
             weight_tuple,
@@ -1267,6 +1390,7 @@ impl ConsensusGraphInner {
     }
 
     fn compute_pastset_brutal(&mut self, me: usize) -> BitSet {
+        PASTSET_RECOMPUTE.mark(1);
         let mut path = Vec::new();
         let mut cur = me;
         while cur != NULL && self.pastset_cache.get(cur, false).is_none() {
@@ -1461,9 +1585,11 @@ impl ConsensusGraphInner {
     fn insert(&mut self, block_header: &BlockHeader) -> (usize, usize) {
         let hash = block_header.hash();
 
-        let is_heavy = U512::from(block_header.pow_quality)
-            >= U512::from(self.inner_conf.heavy_block_difficulty_ratio)
-                * U512::from(block_header.difficulty());
+        let is_heavy = self.ghast_rule.is_heavy(
+            &self.inner_conf,
+            &block_header.pow_quality,
+            block_header.difficulty(),
+        );
 
         let parent = if hash != self.data_man.genesis_block().hash() {
             self.hash_to_arena_indices
@@ -1862,11 +1988,7 @@ impl ConsensusGraphInner {
     pub fn executed_best_state_epoch_number(&self) -> u64 {
         let pivot_len = self.pivot_chain.len() as u64;
         let mut best_state_pivot_index =
-            if pivot_len < DEFERRED_STATE_EPOCH_COUNT {
-                0
-            } else {
-                pivot_len - DEFERRED_STATE_EPOCH_COUNT
-            };
+            pivot_len.saturating_sub(self.deferred_state_epoch_count());
         while best_state_pivot_index > 0 {
             if self.data_man.epoch_executed(
                 &self.arena[self.pivot_chain[best_state_pivot_index as usize]]
@@ -1886,11 +2008,7 @@ impl ConsensusGraphInner {
     /// state will be used.
     pub fn best_state_epoch_number(&self) -> u64 {
         let pivot_height = self.pivot_index_to_height(self.pivot_chain.len());
-        if pivot_height < DEFERRED_STATE_EPOCH_COUNT {
-            0
-        } else {
-            pivot_height - DEFERRED_STATE_EPOCH_COUNT
-        }
+        pivot_height.saturating_sub(self.deferred_state_epoch_count())
     }
 
     fn best_state_arena_index(&self) -> usize {
@@ -1936,6 +2054,45 @@ impl ConsensusGraphInner {
         self.cur_era_genesis_height + self.pivot_chain.len() as u64 - 1
     }
 
+    /// Collect the blocks whose heights are within `window` of the best
+    /// epoch for visualization, sorted by height.
+    pub fn tree_graph_blocks(&self, window: u64) -> Vec<TreeGraphBlock> {
+        let min_height = self.best_epoch_number().saturating_sub(window);
+        let mut blocks: Vec<_> = self
+            .arena
+            .iter()
+            .filter(|(_, node)| node.height >= min_height)
+            .map(|(index, node)| {
+                let is_pivot = node.height >= self.cur_era_genesis_height
+                    && self
+                        .pivot_chain
+                        .get(self.height_to_pivot_index(node.height))
+                        == Some(&index);
+                TreeGraphBlock {
+                    hash: node.hash,
+                    height: node.height,
+                    parent: self.arena.get(node.parent).map(|p| p.hash),
+                    referees: node
+                        .referees
+                        .iter()
+                        .map(|referee| self.arena[*referee].hash)
+                        .collect(),
+                    is_pivot,
+                    weight: self
+                        .block_weight(index, false /* inclusive */),
+                    subtree_weight: self.weight_tree.get(index),
+                    blame: self
+                        .data_man
+                        .block_header_by_hash(&node.hash)
+                        .map_or(0, |header| header.blame()),
+                    partial_invalid: node.data.partial_invalid,
+                }
+            })
+            .collect();
+        blocks.sort_by_key(|block| block.height);
+        blocks
+    }
+
     fn get_arena_index_from_epoch_number(
         &self, epoch_number: u64,
     ) -> Result<usize, String> {
@@ -2009,6 +2166,30 @@ impl ConsensusGraphInner {
         }
     }
 
+    /// The hash of the block at `index` of epoch `epoch_number` in the
+    /// execution order. The hashes of the epoch are only collected if the
+    /// epoch set has to be loaded from db.
+    pub fn block_hash_by_epoch_and_index(
+        &self, epoch_number: u64, index: usize,
+    ) -> Result<Option<H256>, String> {
+        match self.get_arena_index_from_epoch_number(epoch_number) {
+            Ok(pivot_arena_index)
+                if pivot_arena_index
+                    != self.cur_era_genesis_block_arena_index =>
+            {
+                Ok(self.arena[pivot_arena_index]
+                    .data
+                    .ordered_executable_epoch_blocks
+                    .get(index)
+                    .map(|arena_index| self.arena[*arena_index].hash))
+            }
+            _ => Ok(self
+                .block_hashes_by_epoch(epoch_number)?
+                .get(index)
+                .cloned()),
+        }
+    }
+
     fn epoch_hash(&self, epoch_number: u64) -> Option<H256> {
         let pivot_index = self.height_to_pivot_index(epoch_number);
         self.pivot_chain
@@ -2016,7 +2197,7 @@ impl ConsensusGraphInner {
             .map(|idx| self.arena[*idx].hash)
     }
 
-    fn get_epoch_hash_for_block(&self, hash: &H256) -> Option<H256> {
+    pub fn get_epoch_hash_for_block(&self, hash: &H256) -> Option<H256> {
         self.get_block_epoch_number(&hash)
             .and_then(|epoch_number| self.epoch_hash(epoch_number))
     }
@@ -2028,6 +2209,82 @@ impl ConsensusGraphInner {
             .collect()
     }
 
+    /// Check the consensus graph against the synchronization graph and the
+    /// persisted data, and return the discrepancies found, i.e.
+    /// 1. the `delivered` blocks (with their heights) which are graph-ready in
+    ///    the synchronization graph but not in the consensus graph;
+    /// 2. the persisted epoch sets of the last `max_epochs` epochs which differ
+    ///    from the ones in memory;
+    /// 3. `latest_inserted_block` if it is not reachable from the current era
+    ///    genesis through the parent edges.
+    pub fn check_consistency(
+        &self, delivered: &[(H256, u64)], latest_inserted_block: &H256,
+        max_epochs: usize,
+    ) -> Vec<String>
+    {
+        let mut discrepancies = Vec::new();
+
+        // The blocks before the current era genesis may have been removed
+        // from memory at the checkpoint.
+        for (hash, height) in delivered {
+            if *height >= self.cur_era_genesis_height
+                && !self.hash_to_arena_indices.contains_key(hash)
+            {
+                discrepancies.push(format!(
+                    "delivered block {:?} at height {} is not in consensus graph",
+                    hash, height
+                ));
+            }
+        }
+
+        // The epoch set of the era genesis is loaded from db, and only the
+        // epochs EPOCH_SET_PERSISTENCE_DELAY before the best one are
+        // persisted.
+        let persisted_end = self
+            .pivot_chain
+            .len()
+            .saturating_sub(EPOCH_SET_PERSISTENCE_DELAY as usize);
+        let persisted_start = max(1, persisted_end.saturating_sub(max_epochs));
+        for pivot_index in persisted_start..persisted_end {
+            let height = self.pivot_index_to_height(pivot_index);
+            let in_memory =
+                self.get_epoch_block_hashes(self.pivot_chain[pivot_index]);
+            match self.data_man.epoch_set_hashes_from_db(height) {
+                Some(ref persisted) if *persisted == in_memory => {}
+                Some(persisted) => discrepancies.push(format!(
+                    "epoch set of epoch {} is {:?} in memory but {:?} in db",
+                    height, in_memory, persisted
+                )),
+                None => discrepancies.push(format!(
+                    "epoch set of epoch {} is not persisted",
+                    height
+                )),
+            }
+        }
+
+        let mut reachable = false;
+        let mut index = self
+            .hash_to_arena_indices
+            .get(latest_inserted_block)
+            .cloned()
+            .unwrap_or(NULL);
+        while index != NULL {
+            if index == self.cur_era_genesis_block_arena_index {
+                reachable = true;
+                break;
+            }
+            index = self.arena[index].parent;
+        }
+        if !reachable {
+            discrepancies.push(format!(
+                "latest inserted block {:?} is not reachable from era genesis",
+                latest_inserted_block
+            ));
+        }
+
+        discrepancies
+    }
+
     pub fn get_block_epoch_number(&self, hash: &H256) -> Option<u64> {
         self.hash_to_arena_indices.get(hash).and_then(|index| {
             match self.arena[*index].data.epoch_number {
@@ -2109,9 +2366,7 @@ impl ConsensusGraphInner {
         &self, tx_hash: &H256,
     ) -> Option<(Receipt, TransactionAddress)> {
         trace!("Get receipt with tx_hash {}", tx_hash);
-        let address = self.data_man.transaction_address_by_hash(
-            tx_hash, false, /* update_cache */
-        )?;
+        let address = self.validated_transaction_address(tx_hash)?;
         // receipts should never be None if address is not None because
         let receipts = self.block_receipts_by_hash(
             &address.block_hash,
@@ -2126,6 +2381,55 @@ impl ConsensusGraphInner {
         ))
     }
 
+    /// Return the address of an executed transaction if it is executed in
+    /// an epoch on the current pivot chain. A stale address left by a pivot
+    /// chain reorganization is repaired if its block is executed in another
+    /// epoch, or removed if the transaction is not executed there.
+    fn validated_transaction_address(
+        &self, tx_hash: &H256,
+    ) -> Option<TransactionAddress> {
+        let address = self.data_man.transaction_address_by_hash(
+            tx_hash, false, /* update_cache */
+        )?;
+        let epoch_hash =
+            match self.get_epoch_hash_for_block(&address.block_hash) {
+                Some(epoch_hash) => epoch_hash,
+                // The block is before the current era genesis, whose epoch
+                // can not be changed any more.
+                None => return Some(address),
+            };
+        if epoch_hash == address.epoch_hash {
+            return Some(address);
+        }
+
+        debug!(
+            "Stale address {:?} of tx {:?}, the block is in epoch {:?}",
+            address, tx_hash, epoch_hash
+        );
+        let outcome_status = self
+            .data_man
+            .block_execution_result_by_hash_with_epoch(
+                &address.block_hash,
+                &epoch_hash,
+                false, /* update_cache */
+            )?
+            .receipts
+            .get(address.index)?
+            .outcome_status;
+        if outcome_status == TRANSACTION_OUTCOME_EXCEPTION_WITHOUT_NONCE_BUMPING
+        {
+            self.data_man.remove_transaction_address(tx_hash);
+            None
+        } else {
+            let address = TransactionAddress {
+                epoch_hash,
+                ..address
+            };
+            self.data_man.insert_transaction_address(tx_hash, &address);
+            Some(address)
+        }
+    }
+
     pub fn check_block_pivot_assumption(
         &self, pivot_hash: &H256, epoch: u64,
     ) -> Result<(), String> {
@@ -2137,28 +2441,18 @@ impl ConsensusGraphInner {
         Ok(())
     }
 
-    /// Compute the block weight following the GHAST algorithm:
-    /// For partially invalid block, the weight is always 0
-    /// If a block is not adaptive, the weight is its difficulty
-    /// If a block is adaptive, then for the heavy blocks, it equals to
-    /// the heavy block ratio. Otherwise, it is zero.
+    /// Compute the block weight following the GHAST rule. For partially
+    /// invalid block, the weight is always 0.
     fn block_weight(&self, me: usize, inclusive: bool) -> i128 {
         if self.arena[me].data.partial_invalid && !inclusive {
             return 0 as i128;
         }
-        let is_heavy = self.arena[me].is_heavy;
-        let is_adaptive = self.arena[me].adaptive;
-        if is_adaptive {
-            if is_heavy {
-                self.inner_conf.heavy_block_difficulty_ratio as i128
-                    * i128::try_from(self.arena[me].difficulty.low_u128())
-                        .unwrap()
-            } else {
-                0 as i128
-            }
-        } else {
-            i128::try_from(self.arena[me].difficulty.low_u128()).unwrap()
-        }
+        self.ghast_rule.block_weight(
+            &self.inner_conf,
+            &self.arena[me].difficulty,
+            self.arena[me].is_heavy,
+            self.arena[me].adaptive,
+        )
     }
 
     fn compute_blame_and_state_with_execution_result(
@@ -2301,6 +2595,24 @@ impl ConsensusGraphInner {
                 debug!("compute_execution_info_with_result(): Block {} state/blame is valid.", self.arena[me].hash);
             } else {
                 debug!("compute_execution_info_with_result(): Block {} state/blame is invalid! header blame {}, our blame {}, header state_root {}, our state root {}, header receipt_root {}, our receipt root {}, header logs_bloom_hash {}, our logs_bloom_hash {}.", self.arena[me].hash, block_header.blame(), blame, block_header.deferred_state_root(), deferred_state_root, block_header.deferred_receipts_root(), deferred_receipt_root, block_header.deferred_logs_bloom_hash(), deferred_logs_bloom_hash);
+                if blame == 0 && block_header.blame() == 0 {
+                    // The header commits to the state of the same epoch as
+                    // ours, so the mismatch comes from the execution.
+                    if let Ok(epoch_hash) = self.get_state_block_with_delay(
+                        &self.arena[parent].hash,
+                        self.deferred_state_epoch_count() as usize - 1,
+                    ) {
+                        let reason = format!(
+                            "block {:?}: header state_root {:?}, receipts_root {:?}, logs_bloom_hash {:?}",
+                            self.arena[me].hash,
+                            block_header.deferred_state_root(),
+                            block_header.deferred_receipts_root(),
+                            block_header.deferred_logs_bloom_hash()
+                        );
+                        let epoch_hash = *epoch_hash;
+                        self.state_root_mismatches.push((epoch_hash, reason));
+                    }
+                }
             }
 
             self.arena[me].data.state_valid = state_valid;
@@ -2538,7 +2850,7 @@ impl ConsensusGraphInner {
             let state_hash = self
                 .get_state_block_with_delay(
                     &cur_hash,
-                    DEFERRED_STATE_EPOCH_COUNT as usize,
+                    self.deferred_state_epoch_count() as usize,
                 )?
                 .clone();
             waiting_blocks.push((cur_hash, state_hash));
@@ -2580,3 +2892,100 @@ impl ConsensusGraphInner {
         self.arena[me].parent = NULL;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ConsensusInnerConfig;
+    use crate::parameters::{block::REFEREE_BOUND, consensus::*};
+
+    fn default_config() -> ConsensusInnerConfig {
+        ConsensusInnerConfig {
+            adaptive_weight_alpha_num: ADAPTIVE_WEIGHT_DEFAULT_ALPHA_NUM,
+            adaptive_weight_alpha_den: ADAPTIVE_WEIGHT_DEFAULT_ALPHA_DEN,
+            adaptive_weight_beta: ADAPTIVE_WEIGHT_DEFAULT_BETA,
+            heavy_block_difficulty_ratio: HEAVY_BLOCK_DEFAULT_DIFFICULTY_RATIO,
+            era_epoch_count: ERA_DEFAULT_EPOCH_COUNT,
+            era_checkpoint_gap: ERA_DEFAULT_CHECKPOINT_GAP,
+            deferred_state_epoch_count: DEFERRED_STATE_EPOCH_COUNT,
+            enable_optimistic_execution: false,
+            anticone_cache_stride: ANTICONE_CACHE_DEFAULT_STRIDE,
+            anticone_cache_max_anticone_size:
+                ANTICONE_CACHE_DEFAULT_MAX_ANTICONE_SIZE,
+            adaptive_anticone_cache: false,
+            pastset_cache_capacity: PASTSET_CACHE_DEFAULT_CAPACITY,
+        }
+    }
+
+    fn validate<F>(modify: F) -> Result<(), String>
+    where F: FnOnce(&mut ConsensusInnerConfig) {
+        let mut config = default_config();
+        modify(&mut config);
+        config.validate()
+    }
+
+    #[test]
+    fn test_validate_default() {
+        assert_eq!(validate(|_| {}), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_positive_parameters() {
+        assert!(validate(|c| c.adaptive_weight_alpha_den = 0).is_err());
+        assert!(validate(|c| c.deferred_state_epoch_count = 0).is_err());
+        assert!(validate(|c| c.anticone_cache_stride = 0).is_err());
+        assert!(validate(|c| c.pastset_cache_capacity = 0).is_err());
+    }
+
+    #[test]
+    fn test_validate_era_epoch_count() {
+        // The minimum follows the configured deferred epoch count.
+        assert_eq!(
+            validate(|c| {
+                c.deferred_state_epoch_count = 10;
+                c.era_epoch_count = 29;
+            }),
+            Err("era_epoch_count should be at least 30, got 29".into())
+        );
+        assert_eq!(
+            validate(|c| {
+                c.deferred_state_epoch_count = 10;
+                c.era_epoch_count = 30;
+            }),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_validate_era_checkpoint_gap() {
+        assert_eq!(
+            validate(|c| {
+                c.deferred_state_epoch_count = 10;
+                c.era_checkpoint_gap = 9;
+            }),
+            Err("era_checkpoint_gap should be at least 10, got 9".into())
+        );
+        assert_eq!(
+            validate(|c| {
+                c.deferred_state_epoch_count = 10;
+                c.era_checkpoint_gap = 10;
+            }),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_validate_referee_bound() {
+        assert_eq!(
+            validate(|c| c.anticone_cache_max_anticone_size = 0),
+            Err("The sizes of the consensus caches should be positive".into())
+        );
+        assert!(validate(|c| {
+            c.anticone_cache_max_anticone_size = REFEREE_BOUND - 1
+        })
+        .is_err());
+        assert_eq!(
+            validate(|c| c.anticone_cache_max_anticone_size = REFEREE_BOUND),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::ConsensusGraphInner;
+use cfx_types::H256;
+use std::sync::Arc;
+
+/// A terminal block which is not referenced by new blocks because there are
+/// more than `REFEREE_BOUND` terminals.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExcludedTerminal {
+    pub hash: H256,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RefereeSelection {
+    pub selected: Vec<H256>,
+    pub excluded: Vec<ExcludedTerminal>,
+}
+
+/// The policy to choose the terminals referenced by a new block when there
+/// are more terminals than a block can reference. Every terminal is given a
+/// priority and the terminals with the highest priorities are selected.
+pub trait RefereeSelector: Send + Sync {
+    /// What the priority of a terminal is, used to report why a terminal is
+    /// excluded.
+    fn priority_name(&self) -> &'static str;
+
+    /// The priority of the terminal block with arena index `index`.
+    fn priority(&self, inner: &ConsensusGraphInner, index: usize) -> i128;
+
+    /// Select at most `bound` blocks from `terminal_hashes`. The ties are
+    /// broken by block hashes so that the selection is deterministic.
+    fn select(
+        &self, inner: &ConsensusGraphInner, terminal_hashes: Vec<H256>,
+        bound: usize,
+    ) -> RefereeSelection
+    {
+        if terminal_hashes.len() <= bound {
+            return RefereeSelection {
+                selected: terminal_hashes,
+                excluded: Vec::new(),
+            };
+        }
+        let terminals = terminal_hashes
+            .into_iter()
+            .map(|hash| {
+                let index = *inner.hash_to_arena_indices.get(&hash).unwrap();
+                (self.priority(inner, index), hash)
+            })
+            .collect();
+        select_by_priority(self.priority_name(), terminals, bound)
+    }
+}
+
+/// Select at most `bound` terminals with the highest priorities from the
+/// `(priority, hash)` pairs in `terminals`, breaking the ties by hashes.
+fn select_by_priority(
+    priority_name: &str, mut terminals: Vec<(i128, H256)>, bound: usize,
+) -> RefereeSelection {
+    terminals.sort_by(|a, b| b.cmp(a));
+    let excluded = if terminals.len() > bound {
+        terminals.split_off(bound)
+    } else {
+        Vec::new()
+    };
+    let lowest_selected = terminals.last().map_or(0, |(p, _)| *p);
+    RefereeSelection {
+        selected: terminals.into_iter().map(|(_, hash)| hash).collect(),
+        excluded: excluded
+            .into_iter()
+            .map(|(priority, hash)| ExcludedTerminal {
+                hash,
+                reason: format!(
+                    "{} {} is not above {} of the selected terminals",
+                    priority_name, priority, lowest_selected
+                ),
+            })
+            .collect(),
+    }
+}
+
+/// Prefer the terminals whose LCA with the best block is higher, i.e. the
+/// terminals which fork from the pivot chain later.
+pub struct LcaHeightRefereeSelector;
+
+impl RefereeSelector for LcaHeightRefereeSelector {
+    fn priority_name(&self) -> &'static str { "lca height" }
+
+    fn priority(&self, inner: &ConsensusGraphInner, index: usize) -> i128 {
+        let best_index = *inner.pivot_chain.last().unwrap();
+        inner.arena[inner.lca(index, best_index)].height as i128
+    }
+}
+
+/// Prefer the terminals which are inserted into consensus earlier, so that
+/// old terminals are not left out forever.
+pub struct OldestFirstRefereeSelector;
+
+impl RefereeSelector for OldestFirstRefereeSelector {
+    fn priority_name(&self) -> &'static str { "negated sequence number" }
+
+    fn priority(&self, inner: &ConsensusGraphInner, index: usize) -> i128 {
+        -(inner.arena[index].data.sequence_number as i128)
+    }
+}
+
+/// Prefer the terminals with the largest total weight of their past sets and
+/// themselves.
+pub struct HeaviestFirstRefereeSelector;
+
+impl RefereeSelector for HeaviestFirstRefereeSelector {
+    fn priority_name(&self) -> &'static str { "weight" }
+
+    fn priority(&self, inner: &ConsensusGraphInner, index: usize) -> i128 {
+        inner.arena[index].past_weight
+            + inner.block_weight(index, false /* inclusive */)
+    }
+}
+
+/// Prefer the terminals with the smallest anticone sets. Since a terminal has
+/// no future blocks, its anticone is smaller if its past set is larger.
+pub struct MinAnticoneRefereeSelector;
+
+impl RefereeSelector for MinAnticoneRefereeSelector {
+    fn priority_name(&self) -> &'static str { "past set size" }
+
+    fn priority(&self, inner: &ConsensusGraphInner, index: usize) -> i128 {
+        inner.arena[index].past_num_blocks as i128
+    }
+}
+
+pub fn new_referee_selector(
+    name: &str,
+) -> Result<Arc<dyn RefereeSelector>, String> {
+    match name {
+        "lca_height" => Ok(Arc::new(LcaHeightRefereeSelector)),
+        "oldest_first" => Ok(Arc::new(OldestFirstRefereeSelector)),
+        "heaviest_first" => Ok(Arc::new(HeaviestFirstRefereeSelector)),
+        "min_anticone" => Ok(Arc::new(MinAnticoneRefereeSelector)),
+        _ => Err(format!("Unknown referee selection strategy {}", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{new_referee_selector, select_by_priority, ExcludedTerminal};
+    use cfx_types::H256;
+
+    #[test]
+    fn test_select_by_priority() {
+        let terminals = vec![
+            (1, H256::from_low_u64_be(1)),
+            (3, H256::from_low_u64_be(2)),
+            (2, H256::from_low_u64_be(3)),
+            (3, H256::from_low_u64_be(4)),
+        ];
+        let selection = select_by_priority("weight", terminals, 2);
+        // Ties are broken by the larger hash.
+        assert_eq!(
+            selection.selected,
+            vec![H256::from_low_u64_be(4), H256::from_low_u64_be(2)]
+        );
+        assert_eq!(
+            selection.excluded,
+            vec![
+                ExcludedTerminal {
+                    hash: H256::from_low_u64_be(3),
+                    reason: "weight 2 is not above 3 of the selected terminals"
+                        .into(),
+                },
+                ExcludedTerminal {
+                    hash: H256::from_low_u64_be(1),
+                    reason: "weight 1 is not above 3 of the selected terminals"
+                        .into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select_within_bound() {
+        let terminals =
+            vec![(1, H256::from_low_u64_be(1)), (2, H256::from_low_u64_be(2))];
+        let selection = select_by_priority("weight", terminals, 2);
+        assert_eq!(
+            selection.selected,
+            vec![H256::from_low_u64_be(2), H256::from_low_u64_be(1)]
+        );
+        assert!(selection.excluded.is_empty());
+    }
+
+    #[test]
+    fn test_new_referee_selector() {
+        for (name, priority_name) in &[
+            ("lca_height", "lca height"),
+            ("oldest_first", "negated sequence number"),
+            ("heaviest_first", "weight"),
+            ("min_anticone", "past set size"),
+        ] {
+            let selector = new_referee_selector(name).unwrap();
+            assert_eq!(selector.priority_name(), *priority_name);
+        }
+        assert!(new_referee_selector("random").is_err());
+    }
+}
@@ -2,7 +2,17 @@ use cfx_types::{Address, H256, U256};
 use parity_bytes::ToPretty;
 use primitives::{SignedTransaction, StateRootWithAuxInfo};
 use rlp::*;
-use std::{fmt::Display, sync::Arc, vec::Vec};
+use serde::Serializer;
+use serde_derive::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Write as FmtWrite},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    vec::Vec,
+};
 
 #[derive(Debug)]
 pub struct BlockHashAuthorValue<ValueType>(
@@ -145,3 +155,248 @@ impl Encodable for ComputeEpochDebugRecord {
         s.complete_unbounded_list();
     }
 }
+
+/// A block of the TreeGraph exported for visualization.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeGraphBlock {
+    pub hash: H256,
+    pub height: u64,
+    pub parent: Option<H256>,
+    pub referees: Vec<H256>,
+    pub is_pivot: bool,
+    /// The weight of the block itself.
+    #[serde(serialize_with = "serialize_display")]
+    pub weight: i128,
+    /// The total weight of the subtree rooted at the block.
+    #[serde(serialize_with = "serialize_display")]
+    pub subtree_weight: i128,
+    pub blame: u32,
+    pub partial_invalid: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TreeGraphFormat {
+    Dot,
+    Json,
+}
+
+impl FromStr for TreeGraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(TreeGraphFormat::Dot),
+            "json" => Ok(TreeGraphFormat::Json),
+            _ => Err(format!("Unknown tree graph format {}", s)),
+        }
+    }
+}
+
+/// Dump the blocks of the TreeGraph with their parent and referee edges into
+/// `path`. In the DOT format, pivot blocks are filled, referee edges are
+/// dotted and partially invalid blocks are drawn in red.
+pub fn export_tree_graph(
+    blocks: &[TreeGraphBlock], path: &Path, format: TreeGraphFormat,
+) -> Result<(), String> {
+    let content = match format {
+        TreeGraphFormat::Dot => tree_graph_to_dot(blocks),
+        TreeGraphFormat::Json => tree_graph_to_json(blocks),
+    };
+    fs::write(path, content)
+        .map_err(|e| format!("Failed to write {:?}: {:?}", path, e))
+}
+
+fn tree_graph_to_dot(blocks: &[TreeGraphBlock]) -> String {
+    let mut dot = String::new();
+    writeln!(dot, "digraph G {{").unwrap();
+    writeln!(dot, "rankdir=\"RL\";").unwrap();
+    writeln!(dot, "node [shape=box];").unwrap();
+    for block in blocks {
+        let mut attributes = format!(
+            "label=\"{:?}\\nheight={} blame={}\\nweight={} subtree={}\"",
+            block.hash,
+            block.height,
+            block.blame,
+            block.weight,
+            block.subtree_weight
+        );
+        if block.is_pivot {
+            attributes.push_str(", style=filled");
+        }
+        if block.partial_invalid {
+            attributes.push_str(", color=red");
+        }
+        writeln!(dot, "\"{:?}\" [{}];", block.hash, attributes).unwrap();
+        if let Some(parent) = &block.parent {
+            writeln!(dot, "\"{:?}\" -> \"{:?}\";", block.hash, parent).unwrap();
+        }
+        for referee in &block.referees {
+            writeln!(
+                dot,
+                "\"{:?}\" -> \"{:?}\" [style=dotted];",
+                block.hash, referee
+            )
+            .unwrap();
+        }
+    }
+    writeln!(dot, "}}").unwrap();
+    dot
+}
+
+fn tree_graph_to_json(blocks: &[TreeGraphBlock]) -> String {
+    serde_json::to_string(blocks).expect("tree graph is serializable")
+}
+
+/// Serialize the big numbers as decimal strings, which JSON numbers cannot
+/// represent precisely.
+fn serialize_display<T: Display, S: Serializer>(
+    value: &T, serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+/// The balance and nonce of an account.
+#[derive(Debug, Serialize)]
+pub struct BalanceAndNonce {
+    #[serde(serialize_with = "serialize_display")]
+    pub balance: U256,
+    #[serde(serialize_with = "serialize_display")]
+    pub nonce: U256,
+}
+
+/// The balance and nonce of an account touched in an epoch, before and
+/// after executing the epoch. `None` means the account does not exist.
+#[derive(Debug, Serialize)]
+pub struct EpochAccountDiff {
+    pub address: Address,
+    pub before: Option<BalanceAndNonce>,
+    pub after: Option<BalanceAndNonce>,
+}
+
+/// The outcome of a transaction executed in an epoch.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionDiagnostics {
+    pub transaction_hash: H256,
+    pub outcome_status: u8,
+    #[serde(serialize_with = "serialize_display")]
+    pub gas_used: U256,
+    /// The number of logs.
+    pub logs: usize,
+}
+
+/// A block of an executed epoch.
+#[derive(Debug, Serialize)]
+pub struct BlockDiagnostics {
+    pub hash: H256,
+    /// The number of transactions in the block.
+    pub transactions: usize,
+    /// The outcomes of the transactions in the block, which are empty if the
+    /// block is not executed in this epoch.
+    pub receipts: Vec<TransactionDiagnostics>,
+}
+
+/// The diagnostic information of an executed epoch, which helps to find out
+/// why its state or receipts root does not match the one in a header.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EpochDiagnostics {
+    pub epoch_hash: H256,
+    pub reason: Option<String>,
+    pub blocks: Vec<BlockDiagnostics>,
+    /// The accounts touched by the epoch, i.e. the transaction senders and
+    /// receivers and the block authors.
+    pub account_diffs: Vec<EpochAccountDiff>,
+    pub executor_config: BTreeMap<&'static str, String>,
+}
+
+/// Write the diagnostic bundle of an epoch as a JSON file named after the
+/// epoch hash in `dir`, and return its path.
+pub fn dump_epoch_diagnostics(
+    diagnostics: &EpochDiagnostics, dir: &Path,
+) -> Result<PathBuf, String> {
+    fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create {:?}: {:?}", dir, e))?;
+    let path = dir.join(format!("{}.json", diagnostics.epoch_hash.to_hex()));
+    fs::write(&path, epoch_diagnostics_to_json(diagnostics))
+        .map_err(|e| format!("Failed to write {:?}: {:?}", path, e))?;
+    Ok(path)
+}
+
+fn epoch_diagnostics_to_json(diagnostics: &EpochDiagnostics) -> String {
+    serde_json::to_string(diagnostics).expect("diagnostics are serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        epoch_diagnostics_to_json, tree_graph_to_dot, tree_graph_to_json,
+        BalanceAndNonce, BlockDiagnostics, EpochAccountDiff, EpochDiagnostics,
+        TreeGraphBlock,
+    };
+    use cfx_types::{Address, H256};
+
+    #[test]
+    fn test_tree_graph_export() {
+        let genesis = H256::from_low_u64_be(1);
+        let blocks = vec![TreeGraphBlock {
+            hash: H256::from_low_u64_be(2),
+            height: 1,
+            parent: Some(genesis),
+            referees: vec![H256::from_low_u64_be(3)],
+            is_pivot: true,
+            weight: 1,
+            subtree_weight: 1,
+            blame: 0,
+            partial_invalid: false,
+        }];
+
+        let dot = tree_graph_to_dot(&blocks);
+        assert!(dot.contains("style=filled"));
+        assert!(dot.contains("[style=dotted]"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&tree_graph_to_json(&blocks)).unwrap();
+        assert_eq!(json[0]["isPivot"], true);
+        assert_eq!(json[0]["weight"], "1");
+        assert_eq!(json[0]["parent"], serde_json::to_value(genesis).unwrap());
+    }
+
+    #[test]
+    fn test_epoch_diagnostics_json() {
+        let diagnostics = EpochDiagnostics {
+            epoch_hash: H256::from_low_u64_be(1),
+            reason: Some("state root \"mismatch\"\n".into()),
+            blocks: vec![BlockDiagnostics {
+                hash: H256::from_low_u64_be(1),
+                transactions: 0,
+                receipts: vec![],
+            }],
+            account_diffs: vec![EpochAccountDiff {
+                address: Address::zero(),
+                before: None,
+                after: Some(BalanceAndNonce {
+                    balance: 1.into(),
+                    nonce: 0.into(),
+                }),
+            }],
+            executor_config: vec![("bench_mode", "false".into())]
+                .into_iter()
+                .collect(),
+        };
+
+        let json: serde_json::Value =
+            serde_json::from_str(&epoch_diagnostics_to_json(&diagnostics))
+                .unwrap();
+        assert_eq!(json["reason"], "state root \"mismatch\"\n");
+        assert_eq!(json["blocks"][0]["transactions"], 0);
+        assert_eq!(json["blocks"][0]["receipts"], serde_json::json!([]));
+        assert_eq!(json["accountDiffs"][0]["before"], serde_json::Value::Null);
+        assert_eq!(
+            json["accountDiffs"][0]["after"],
+            serde_json::json!({"balance": "1", "nonce": "0"})
+        );
+        assert_eq!(json["executorConfig"]["bench_mode"], "false");
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::{
+    debug::{
+        dump_epoch_diagnostics, BalanceAndNonce, BlockDiagnostics,
+        EpochAccountDiff, EpochDiagnostics, TransactionDiagnostics,
+    },
+    ConsensusGraph,
+};
+use crate::statedb::StateDb;
+use cfx_types::{Address, H256};
+use primitives::Action;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+impl ConsensusGraph {
+    /// Collect the diagnostic bundle of the executed epoch with the given
+    /// pivot block hash, including its blocks, the receipts of their
+    /// transactions, the balance and nonce changes of the touched accounts
+    /// and the executor configuration, and write it into
+    /// `debug_dump_dir_invalid_state_root`. Return the path of the bundle.
+    pub fn dump_epoch_diagnostics(
+        &self, epoch_hash: &H256, reason: Option<String>,
+    ) -> Result<PathBuf, String> {
+        let block_hashes = {
+            let inner = self.inner.read();
+            let arena_index =
+                *inner.hash_to_arena_indices.get(epoch_hash).ok_or_else(
+                    || format!("Epoch {:?} is not in memory", epoch_hash),
+                )?;
+            inner.get_epoch_block_hashes(arena_index)
+        };
+        let parent_hash = *self
+            .data_man
+            .block_header_by_hash(epoch_hash)
+            .ok_or_else(|| format!("Block {:?} does not exist", epoch_hash))?
+            .parent_hash();
+        let state_after = self.get_state_db_by_epoch_hash(epoch_hash)?;
+        // The parent state may have been garbage collected.
+        let state_before = self.get_state_db_by_epoch_hash(&parent_hash).ok();
+
+        let mut blocks = Vec::new();
+        let mut touched = BTreeSet::new();
+        for hash in &block_hashes {
+            let block = self
+                .data_man
+                .block_by_hash(hash, false /* update_cache */)
+                .ok_or_else(|| format!("Block {:?} does not exist", hash))?;
+            touched.insert(*block.block_header.author());
+            for tx in &block.transactions {
+                touched.insert(tx.sender);
+                if let Action::Call(address) = &tx.action {
+                    touched.insert(*address);
+                }
+            }
+            let receipts = self
+                .data_man
+                .block_execution_result_by_hash_with_epoch(
+                    hash, epoch_hash, false, /* update_cache */
+                )
+                .map(|result| result.receipts.clone())
+                .unwrap_or_default();
+            let receipts = block
+                .transactions
+                .iter()
+                .zip(receipts.iter())
+                .map(|(tx, receipt)| TransactionDiagnostics {
+                    transaction_hash: tx.hash(),
+                    outcome_status: receipt.outcome_status,
+                    gas_used: receipt.gas_used,
+                    logs: receipt.logs.len(),
+                })
+                .collect();
+            blocks.push(BlockDiagnostics {
+                hash: *hash,
+                transactions: block.transactions.len(),
+                receipts,
+            });
+        }
+
+        let account_diffs = touched
+            .into_iter()
+            .map(|address| EpochAccountDiff {
+                address,
+                before: state_before
+                    .as_ref()
+                    .and_then(|state_db| balance_and_nonce(state_db, &address)),
+                after: balance_and_nonce(&state_after, &address),
+            })
+            .collect();
+
+        let diagnostics = EpochDiagnostics {
+            epoch_hash: *epoch_hash,
+            reason,
+            blocks,
+            account_diffs,
+            executor_config: self.executor_config().into_iter().collect(),
+        };
+        dump_epoch_diagnostics(
+            &diagnostics,
+            Path::new(&self.config.debug_dump_dir_invalid_state_root),
+        )
+    }
+
+    /// Execute the epoch with the given number again on the state of its
+    /// parent in the local storage, and return the recomputed state root
+    /// together with the state root committed by the pivot block
+    /// `deferred_state_epoch_count` epochs later.
+    pub fn verify_epoch_state(
+        &self, epoch_number: u64,
+    ) -> Result<(H256, H256), String> {
+        let (
+            block_hashes,
+            start_block_number,
+            reward_execution_info,
+            deferred_hash,
+        ) = {
+            let inner = &mut *self.inner.write();
+            // The epoch set of the era genesis is not in the consensus graph.
+            if epoch_number <= inner.get_cur_era_genesis_height() {
+                return Err(format!(
+                    "Epoch {} is before the current era",
+                    epoch_number
+                ));
+            }
+            let epoch_hash = inner.get_hash_from_epoch_number(epoch_number)?;
+            let deferred_hash = inner.get_hash_from_epoch_number(
+                epoch_number + self.deferred_state_epoch_count(),
+            )?;
+            let arena_index = inner.hash_to_arena_indices[&epoch_hash];
+            (
+                inner.get_epoch_block_hashes(arena_index),
+                inner.get_epoch_start_block_number(arena_index),
+                self.executor.get_reward_execution_info(inner, arena_index),
+                deferred_hash,
+            )
+        };
+        let committed = *self
+            .data_man
+            .block_header_by_hash(&deferred_hash)
+            .ok_or_else(|| format!("Missing block header {:?}", deferred_hash))?
+            .deferred_state_root();
+        let computed = self
+            .executor
+            .recompute_epoch_state_root(
+                &block_hashes,
+                start_block_number,
+                &reward_execution_info,
+            )?
+            .state_root
+            .compute_state_root_hash();
+        Ok((computed, committed))
+    }
+
+    fn executor_config(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("bench_mode", self.config.bench_mode.to_string()),
+            (
+                "enable_optimistic_execution",
+                self.config
+                    .inner_conf
+                    .enable_optimistic_execution
+                    .to_string(),
+            ),
+            (
+                "deferred_state_epoch_count",
+                self.deferred_state_epoch_count().to_string(),
+            ),
+            (
+                "max_execution_queue_len",
+                self.config.max_execution_queue_len.to_string(),
+            ),
+        ]
+    }
+}
+
+fn balance_and_nonce(
+    state_db: &StateDb, address: &Address,
+) -> Option<BalanceAndNonce> {
+    match state_db.get_account(address) {
+        Ok(Some(account)) => Some(BalanceAndNonce {
+            balance: account.balance,
+            nonce: account.nonce,
+        }),
+        _ => None,
+    }
+}
@@ -0,0 +1,341 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::consensus::ConsensusGraphInner;
+use cfx_types::H256;
+use std::{cmp::min, collections::HashMap, sync::Arc};
+
+/// The number of epochs in an `EpochBucket`. The epochs after the last full
+/// bucket are kept one by one, so extending the pivot chain only indexes the
+/// new epochs, and a bucket is sealed once when it is full.
+const EPOCH_INDEX_BUCKET_SIZE: usize = 256;
+
+struct EpochEntry {
+    pivot_hash: H256,
+    /// The executable blocks of the epoch in the execution order, the same
+    /// as those returned by `ConsensusGraphInner::block_hashes_by_epoch`.
+    hashes: Vec<H256>,
+}
+
+struct EpochBucket {
+    epochs: Vec<Arc<EpochEntry>>,
+    block_epochs: HashMap<H256, u64>,
+}
+
+/// An immutable index of the epochs on the pivot chain of the current era,
+/// which is built from `ConsensusGraphInner` after it's updated and swapped
+/// in as a whole, so the queries on epochs do not need the inner lock.
+///
+/// The era genesis epoch is not indexed except for the era genesis block
+/// itself, since its epoch set is only available in the db. The blocks which
+/// are in an epoch but not executable are not indexed either, so a block not
+/// found in the index still needs to be looked up in the inner.
+#[derive(Default)]
+pub struct EpochIndex {
+    era_genesis_height: u64,
+    era_genesis_hash: H256,
+    /// Epoch `era_genesis_height + 1 + i` is the `i % EPOCH_INDEX_BUCKET_SIZE`
+    /// epoch in `buckets[i / EPOCH_INDEX_BUCKET_SIZE]` if the bucket is full,
+    /// and in `tail` otherwise.
+    buckets: Vec<Arc<EpochBucket>>,
+    tail: Vec<Arc<EpochEntry>>,
+}
+
+impl EpochIndex {
+    /// Returns the index of the current pivot chain of `inner`, sharing the
+    /// epochs not changed since `self`.
+    pub fn updated(&self, inner: &ConsensusGraphInner) -> EpochIndex {
+        self.updated_with(
+            inner.get_cur_era_genesis_height(),
+            inner.arena[inner.cur_era_genesis_block_arena_index].hash,
+            inner.pivot_chain.len().saturating_sub(1),
+            |i| inner.arena[inner.pivot_chain[i + 1]].hash,
+            |i| {
+                inner.arena[inner.pivot_chain[i + 1]]
+                    .data
+                    .ordered_executable_epoch_blocks
+                    .iter()
+                    .map(|index| inner.arena[*index].hash)
+                    .collect()
+            },
+        )
+    }
+
+    /// Returns the index of a pivot chain of `epoch_count` epochs after the
+    /// era genesis, whose `i`-th epoch has the pivot block `pivot_hash(i)`
+    /// and the blocks `epoch_hashes(i)`. Only the epochs after the highest
+    /// pivot block in common with `self` are read with `epoch_hashes`.
+    fn updated_with<P, E>(
+        &self, era_genesis_height: u64, era_genesis_hash: H256,
+        epoch_count: usize, pivot_hash: P, epoch_hashes: E,
+    ) -> EpochIndex
+    where
+        P: Fn(usize) -> H256,
+        E: Fn(usize) -> Vec<H256>,
+    {
+        // The pivot blocks are the same below the highest common one, and so
+        // are their epochs.
+        let mut kept = 0;
+        if era_genesis_hash == self.era_genesis_hash
+            && era_genesis_height == self.era_genesis_height
+        {
+            kept = min(self.epoch_count(), epoch_count);
+            while kept > 0
+                && self.entry_at(kept - 1).pivot_hash != pivot_hash(kept - 1)
+            {
+                kept -= 1;
+            }
+        }
+
+        let kept_buckets = kept / EPOCH_INDEX_BUCKET_SIZE;
+        let mut index = EpochIndex {
+            era_genesis_height,
+            era_genesis_hash,
+            buckets: self.buckets[..kept_buckets].to_vec(),
+            tail: Vec::new(),
+        };
+        for i in kept_buckets * EPOCH_INDEX_BUCKET_SIZE..epoch_count {
+            let entry = if i < kept {
+                self.entry_at(i).clone()
+            } else {
+                Arc::new(EpochEntry {
+                    pivot_hash: pivot_hash(i),
+                    hashes: epoch_hashes(i),
+                })
+            };
+            index.push(entry);
+        }
+        index
+    }
+
+    /// Append the next epoch, and seal the tail into a bucket if it is full.
+    fn push(&mut self, entry: Arc<EpochEntry>) {
+        self.tail.push(entry);
+        if self.tail.len() < EPOCH_INDEX_BUCKET_SIZE {
+            return;
+        }
+        let first_epoch = self.era_genesis_height
+            + 1
+            + (self.buckets.len() * EPOCH_INDEX_BUCKET_SIZE) as u64;
+        let mut block_epochs = HashMap::new();
+        for (i, entry) in self.tail.iter().enumerate() {
+            let epoch_number = first_epoch + i as u64;
+            for hash in &entry.hashes {
+                block_epochs.insert(*hash, epoch_number);
+            }
+            block_epochs.insert(entry.pivot_hash, epoch_number);
+        }
+        let epochs = std::mem::replace(&mut self.tail, Vec::new());
+        self.buckets.push(Arc::new(EpochBucket {
+            epochs,
+            block_epochs,
+        }));
+    }
+
+    /// The epoch number of the block `hash`, or `None` if it is not
+    /// indexed.
+    pub fn block_epoch_number(&self, hash: &H256) -> Option<u64> {
+        if *hash == self.era_genesis_hash {
+            return Some(self.era_genesis_height);
+        }
+        // The recent blocks are more likely to be queried.
+        let first_tail_epoch = self.era_genesis_height
+            + 1
+            + (self.buckets.len() * EPOCH_INDEX_BUCKET_SIZE) as u64;
+        if let Some(i) = self.tail.iter().rposition(|entry| {
+            entry.pivot_hash == *hash || entry.hashes.contains(hash)
+        }) {
+            return Some(first_tail_epoch + i as u64);
+        }
+        self.buckets
+            .iter()
+            .rev()
+            .find_map(|bucket| bucket.block_epochs.get(hash).cloned())
+    }
+
+    /// The blocks of epoch `epoch_number` in the execution order, or `None`
+    /// if the epoch is not indexed.
+    pub fn epoch_hashes(&self, epoch_number: u64) -> Option<&Vec<H256>> {
+        let i = self.epoch_position(epoch_number)?;
+        Some(&self.entry_at(i).hashes)
+    }
+
+    /// The pivot block of epoch `epoch_number`, or `None` if the epoch is
+    /// not in the current era.
+    pub fn pivot_hash(&self, epoch_number: u64) -> Option<H256> {
+        if epoch_number == self.era_genesis_height {
+            return Some(self.era_genesis_hash);
+        }
+        self.epoch_position(epoch_number)
+            .map(|i| self.entry_at(i).pivot_hash)
+    }
+
+    fn epoch_count(&self) -> usize {
+        self.buckets.len() * EPOCH_INDEX_BUCKET_SIZE + self.tail.len()
+    }
+
+    fn epoch_position(&self, epoch_number: u64) -> Option<usize> {
+        if epoch_number <= self.era_genesis_height {
+            return None;
+        }
+        let i = (epoch_number - self.era_genesis_height - 1) as usize;
+        if i < self.epoch_count() {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    fn entry_at(&self, i: usize) -> &Arc<EpochEntry> {
+        let bucket = i / EPOCH_INDEX_BUCKET_SIZE;
+        if bucket < self.buckets.len() {
+            &self.buckets[bucket].epochs[i % EPOCH_INDEX_BUCKET_SIZE]
+        } else {
+            &self.tail[i - self.buckets.len() * EPOCH_INDEX_BUCKET_SIZE]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EpochIndex, EPOCH_INDEX_BUCKET_SIZE};
+    use cfx_types::H256;
+    use std::{cell::Cell, sync::Arc};
+
+    const ERA_GENESIS_HEIGHT: u64 = 100;
+
+    fn era_genesis() -> H256 { H256::from_low_u64_be(1) }
+
+    /// The pivot block of epoch `i` on `fork`.
+    fn pivot(fork: u64, i: usize) -> H256 {
+        H256::from_low_u64_be((fork << 32) + 2 * i as u64 + 2)
+    }
+
+    /// A non-pivot block in epoch `i` on `fork`.
+    fn non_pivot(fork: u64, i: usize) -> H256 {
+        H256::from_low_u64_be((fork << 32) + 2 * i as u64 + 3)
+    }
+
+    /// Update `index` to a chain which follows fork 0 below `fork_point`
+    /// and `fork` from it, and return the new index and the number of
+    /// epochs read.
+    fn update(
+        index: &EpochIndex, epoch_count: usize, fork_point: usize, fork: u64,
+    ) -> (EpochIndex, usize) {
+        let fork_of = |i: usize| if i < fork_point { 0 } else { fork };
+        let read = Cell::new(0);
+        let index = index.updated_with(
+            ERA_GENESIS_HEIGHT,
+            era_genesis(),
+            epoch_count,
+            |i| pivot(fork_of(i), i),
+            |i| {
+                read.set(read.get() + 1);
+                vec![non_pivot(fork_of(i), i), pivot(fork_of(i), i)]
+            },
+        );
+        (index, read.get())
+    }
+
+    fn check(
+        index: &EpochIndex, epoch_count: usize, fork_point: usize, fork: u64,
+    ) {
+        assert_eq!(index.pivot_hash(ERA_GENESIS_HEIGHT), Some(era_genesis()));
+        assert_eq!(
+            index.block_epoch_number(&era_genesis()),
+            Some(ERA_GENESIS_HEIGHT)
+        );
+        for i in 0..epoch_count {
+            let fork = if i < fork_point { 0 } else { fork };
+            let epoch_number = ERA_GENESIS_HEIGHT + 1 + i as u64;
+            assert_eq!(index.pivot_hash(epoch_number), Some(pivot(fork, i)));
+            assert_eq!(
+                index.epoch_hashes(epoch_number),
+                Some(&vec![non_pivot(fork, i), pivot(fork, i)])
+            );
+            assert_eq!(
+                index.block_epoch_number(&non_pivot(fork, i)),
+                Some(epoch_number)
+            );
+            assert_eq!(
+                index.block_epoch_number(&pivot(fork, i)),
+                Some(epoch_number)
+            );
+        }
+        let after = ERA_GENESIS_HEIGHT + 1 + epoch_count as u64;
+        assert_eq!(index.pivot_hash(after), None);
+        assert_eq!(index.epoch_hashes(after), None);
+    }
+
+    #[test]
+    fn test_extend_reads_only_new_epochs() {
+        let mut index = EpochIndex::default();
+        for epoch_count in 1..=EPOCH_INDEX_BUCKET_SIZE + 2 {
+            let (new_index, read) = update(&index, epoch_count, 0, 0);
+            assert_eq!(read, 1);
+            index = new_index;
+        }
+        assert_eq!(index.buckets.len(), 1);
+        assert_eq!(index.tail.len(), 2);
+        check(&index, EPOCH_INDEX_BUCKET_SIZE + 2, 0, 0);
+    }
+
+    #[test]
+    fn test_bucket_reuse() {
+        let epoch_count = 2 * EPOCH_INDEX_BUCKET_SIZE + 10;
+        let (index, read) = update(&EpochIndex::default(), epoch_count, 0, 0);
+        assert_eq!(read, epoch_count);
+
+        let (new_index, read) = update(&index, epoch_count + 1, 0, 0);
+        assert_eq!(read, 1);
+        for (old, new) in index.buckets.iter().zip(new_index.buckets.iter()) {
+            assert!(Arc::ptr_eq(old, new));
+        }
+        for (old, new) in index.tail.iter().zip(new_index.tail.iter()) {
+            assert!(Arc::ptr_eq(old, new));
+        }
+        check(&new_index, epoch_count + 1, 0, 0);
+    }
+
+    #[test]
+    fn test_reorg_rebuild() {
+        let epoch_count = 2 * EPOCH_INDEX_BUCKET_SIZE + 10;
+        let (index, _) = update(&EpochIndex::default(), epoch_count, 0, 0);
+
+        // A reorg into the second bucket unseals it, keeps its epochs before
+        // the fork point and only reads the epochs of the new fork.
+        let fork_point = EPOCH_INDEX_BUCKET_SIZE + 5;
+        let (new_index, read) = update(&index, epoch_count - 3, fork_point, 1);
+        assert_eq!(read, epoch_count - 3 - fork_point);
+        assert!(Arc::ptr_eq(&index.buckets[0], &new_index.buckets[0]));
+        assert!(!Arc::ptr_eq(&index.buckets[1], &new_index.buckets[1]));
+        assert!(Arc::ptr_eq(
+            &index.buckets[1].epochs[0],
+            &new_index.buckets[1].epochs[0]
+        ));
+        check(&new_index, epoch_count - 3, fork_point, 1);
+        // The blocks only on the old fork are no longer indexed.
+        assert_eq!(new_index.block_epoch_number(&pivot(0, fork_point)), None);
+        assert_eq!(
+            new_index.block_epoch_number(&non_pivot(0, epoch_count - 1)),
+            None
+        );
+
+        // A new era genesis rebuilds everything.
+        let index = new_index;
+        let new_index = index.updated_with(
+            ERA_GENESIS_HEIGHT,
+            H256::from_low_u64_be(u64::max_value()),
+            3,
+            |i| pivot(0, i),
+            |i| vec![pivot(0, i)],
+        );
+        assert_eq!(new_index.buckets.len(), 0);
+        assert_eq!(new_index.tail.len(), 3);
+        assert!(!Arc::ptr_eq(
+            &index.buckets[0].epochs[0],
+            &new_index.tail[0]
+        ));
+    }
+}
@@ -3,21 +3,52 @@
 // See http://www.gnu.org/licenses/
 
 mod anticone_cache;
+mod blame_audit;
 pub mod consensus_inner;
 mod debug;
+mod diagnostics;
+mod epoch_index;
 mod pastset_cache;
+mod profiled_lock;
+mod reindex;
+mod replay;
+mod stable_hash_verifier;
 
 use super::consensus::consensus_inner::{
     confirmation_meter::ConfirmationMeter,
     consensus_executor::ConsensusExecutor,
-    consensus_new_block_handler::ConsensusNewBlockHandler,
+    consensus_new_block_handler::{ConsensusNewBlockHandler, PivotReorg},
+    misbehavior_tracker::ProducerMisbehavior,
 };
-pub use crate::consensus::consensus_inner::{
-    ConsensusGraphInner, ConsensusInnerConfig,
+pub use crate::consensus::{
+    blame_audit::{BlameAuditEntry, BlameAuditReport, DeferredRoots},
+    consensus_inner::{
+        chain_health::ChainHealth,
+        consensus_executor::{
+            EpochExecutedObserver, ExecutedEpoch, SkippedTransactionStats,
+        },
+        ghast_rule::{DefaultGhastRule, GhastRule, SubtreeWeights},
+        referee_selector::{
+            new_referee_selector, ExcludedTerminal,
+            HeaviestFirstRefereeSelector, LcaHeightRefereeSelector,
+            MinAnticoneRefereeSelector, OldestFirstRefereeSelector,
+            RefereeSelection, RefereeSelector,
+        },
+        ConsensusGraphInner, ConsensusInnerConfig,
+    },
+    debug::TreeGraphFormat,
+    profiled_lock::{LockSiteReport, ProfiledRwLock},
+    reindex::ReindexedEpoch,
+    replay::ReplayReport,
+    stable_hash_verifier::{
+        StableHashTrustPolicy, StableHashVerificationConfig,
+    },
 };
 use crate::{
-    block_data_manager::BlockDataManager,
+    block_data_manager::{BlockDataManager, BlockRewardResult},
     bytes::Bytes,
+    consensus::epoch_index::EpochIndex,
+    machine::Machine,
     parameters::{block::REFEREE_BOUND, consensus::*, consensus_internal::*},
     pow::ProofOfWorkConfig,
     state::State,
@@ -26,22 +57,32 @@ use crate::{
     statistics::SharedStatistics,
     storage::{state_manager::StateManagerTrait, SnapshotAndEpochIdRef},
     transaction_pool::SharedTransactionPool,
+    verification::median_time_past_of,
     vm_factory::VmFactory,
 };
-use cfx_types::{Bloom, H160, H256, U256};
-use metrics::{register_meter_with_group, Meter, MeterTimer};
+use cfx_types::{Address, Bloom, H160, H256, U256};
+use metrics::{
+    register_meter_with_group, Gauge, GaugeUsize, Meter, MeterTimer,
+};
 use parking_lot::{Mutex, RwLock};
 use primitives::{
     filter::{Filter, FilterError},
     log_entry::{LocalizedLogEntry, LogEntry},
     receipt::Receipt,
-    EpochNumber, SignedTransaction, StateRootWithAuxInfo, TransactionAddress,
+    Account, Block, EpochNumber, ReceiptProof, SignedTransaction,
+    StateRootWithAuxInfo, TransactionAddress,
 };
 use rayon::prelude::*;
+use rustc_hex::ToHex;
 use std::{
-    cmp::Reverse,
-    collections::{HashMap, HashSet},
-    sync::Arc,
+    cmp::{max, min},
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, mem,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     thread::sleep,
     time::Duration,
 };
@@ -49,6 +90,30 @@ use std::{
 lazy_static! {
     static ref CONSENSIS_ON_NEW_BLOCK_TIMER: Arc<dyn Meter> =
         register_meter_with_group("timer", "consensus_on_new_block_timer");
+    static ref CONSTRUCT_PIVOT_STATE_PROGRESS: Arc<dyn Gauge<usize>> =
+        GaugeUsize::register_with_group(
+            "consensus",
+            "construct_pivot_state_progress"
+        );
+}
+
+/// The parameters of the gas price oracle, i.e. `ConsensusGraph::gas_price`,
+/// which can be changed at runtime.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GasPriceOracleConfig {
+    /// The maximum number of latest blocks to sample.
+    pub block_sample_size: usize,
+    /// The maximum number of transactions to sample.
+    pub transaction_sample_size: usize,
+}
+
+impl Default for GasPriceOracleConfig {
+    fn default() -> Self {
+        GasPriceOracleConfig {
+            block_sample_size: GAS_PRICE_BLOCK_SAMPLE_SIZE,
+            transaction_sample_size: GAS_PRICE_TRANSACTION_SAMPLE_SIZE,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -63,6 +128,32 @@ pub struct ConsensusConfig {
     pub bench_mode: bool,
     // The configuration used by inner data
     pub inner_conf: ConsensusInnerConfig,
+    // The number of pivot blocks processed by `construct_pivot_state` before
+    // releasing the inner lock, so that reads are not blocked for the whole
+    // recovery.
+    pub construct_pivot_state_batch_size: usize,
+    // The number of epochs queued for execution above which new blocks are
+    // not processed until the execution catches up. 0 means unbounded.
+    pub max_execution_queue_len: usize,
+    // The maximum gas tried when estimating the gas of a transaction.
+    pub estimate_gas_upper_bound: u64,
+    // The initial parameters of the gas price oracle.
+    pub gas_price_oracle: GasPriceOracleConfig,
+    // The GHAST rule of consensus, which is `DefaultGhastRule` except for
+    // experiments.
+    pub ghast_rule: Arc<dyn GhastRule>,
+    // The policy to choose the referees of new blocks when there are more
+    // than `REFEREE_BOUND` terminals.
+    pub referee_selector: Arc<dyn RefereeSelector>,
+    // How the stable hash recovered from the database is verified before the
+    // pivot chain is forced to it.
+    pub stable_hash_verification: StableHashVerificationConfig,
+    // Whether the time waiting for and holding the inner lock is recorded at
+    // each call site, which has a small cost on every acquisition.
+    pub profile_inner_lock: bool,
+    // The built-in contracts used to execute the transactions, which are
+    // defined by the genesis spec.
+    pub machine: Arc<Machine>,
 }
 
 #[derive(Debug)]
@@ -94,6 +185,26 @@ pub struct BestInformation {
     // bounded_terminal_block_hashes. This is just to save some space.
     pub terminal_block_hashes: Option<Vec<H256>>,
     pub bounded_terminal_block_hashes: Vec<H256>,
+    /// The terminals not in `bounded_terminal_block_hashes` and why they are
+    /// excluded by the referee selector.
+    pub excluded_terminals: Vec<ExcludedTerminal>,
+    /// The latest epoch whose confirmation risk is estimated and its risk,
+    /// i.e. the probability that its pivot block is reverted.
+    pub latest_confirmation_risk: Option<(u64, f64)>,
+    /// The median time past of the best block, see
+    /// `ConsensusGraph::median_time_past`.
+    pub median_time_past: Option<u64>,
+}
+
+/// A block in an epoch set, see `ConsensusGraph::get_epoch_blocks_with_info`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpochBlockInfo {
+    pub hash: H256,
+    /// Whether the block is the pivot block of the epoch.
+    pub is_pivot: bool,
+    /// The position of the block in the execution order of the epoch.
+    pub execution_index: usize,
+    pub referee_hashes: Vec<H256>,
 }
 
 /// ConsensusGraph is a layer on top of SynchronizationGraph. A SyncGraph
@@ -107,7 +218,8 @@ pub struct BestInformation {
 /// avoid executing too many execution reroll caused by transaction order
 /// oscillation. It defers the transaction execution for a few epochs.
 pub struct ConsensusGraph {
-    pub inner: Arc<RwLock<ConsensusGraphInner>>,
+    config: ConsensusConfig,
+    pub inner: Arc<ProfiledRwLock<ConsensusGraphInner>>,
     pub txpool: SharedTransactionPool,
     pub data_man: Arc<BlockDataManager>,
     executor: Arc<ConsensusExecutor>,
@@ -117,6 +229,9 @@ pub struct ConsensusGraph {
     /// Make sure that it is only modified when holding inner lock to prevent
     /// any inconsistency
     best_info: RwLock<Arc<BestInformation>>,
+    /// The epochs of the current pivot chain, which is swapped together with
+    /// `best_info` so that the epoch queries do not need the inner lock.
+    epoch_index: RwLock<Arc<EpochIndex>>,
     /// This is the hash of latest block inserted into consensus graph.
     /// Since the critical section is very short, a `Mutex` is enough.
     pub latest_inserted_block: Mutex<H256>,
@@ -126,11 +241,97 @@ pub struct ConsensusGraph {
     /// We use `Mutex` here because other thread will only modify it once and
     /// after that only current thread will operate this map.
     pub pivot_block_state_valid_map: Mutex<HashMap<H256, bool>>,
+    /// The checkpoints reported by the peers before the recovery from the
+    /// database, which are cross-checked with the recovered stable hash.
+    peer_checkpoints: Mutex<Option<Vec<H256>>>,
+    /// The stable hash recovered from the database and whether it's trusted.
+    verified_stable_hash: Mutex<Option<(H256, bool)>>,
     state_exposer: SharedStateExposer,
+    /// Observers notified after `best_info` is updated on new blocks, e.g.
+    /// for pushing confirmation events to RPC subscribers.
+    best_info_observers:
+        RwLock<BTreeMap<BestInfoObserverId, BestInfoObserver>>,
+    next_best_info_observer_id: AtomicUsize,
+    /// Observers notified with the blocks in the retracted epochs after the
+    /// pivot chain is reorganized.
+    reorg_observers: RwLock<Vec<PivotReorgObserver>>,
+    gas_price_oracle: RwLock<GasPriceOracleConfig>,
 }
 
 pub type SharedConsensusGraph = Arc<ConsensusGraph>;
 
+/// Callback notified with the latest `BestInformation` after a new block is
+/// inserted into the consensus graph.
+pub type BestInfoObserver = Box<dyn Fn(Arc<BestInformation>) + Send + Sync>;
+
+/// The id of a registered `BestInfoObserver`, used to unregister it.
+pub type BestInfoObserverId = usize;
+
+/// Callback notified with the hashes of the blocks in the epochs retracted
+/// from the pivot chain after a pivot chain reorganization.
+pub type PivotReorgObserver = Box<dyn Fn(&[H256]) + Send + Sync>;
+
+/// The reason why a query of the state of an epoch or a virtual call against
+/// it fails.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConsensusError {
+    /// The epoch doesn't exist or isn't executed yet.
+    InvalidEpoch(String),
+    /// The state of the epoch has been pruned or isn't available.
+    StateUnavailable(String),
+    /// The transaction can not be executed, e.g. the nonce is wrong or the
+    /// sender can not afford it.
+    InvalidTransaction(String),
+    /// The execution is reverted with the given output.
+    Reverted(Vec<u8>),
+    /// The execution runs out of gas with the given gas limit, e.g. the
+    /// upper bound of a gas estimation.
+    OutOfGas { gas_limit: U256 },
+    /// The execution fails with an exception other than the above ones.
+    ExecutionFailed(String),
+    /// Other errors, e.g. the queried account doesn't exist.
+    Other(String),
+}
+
+impl fmt::Display for ConsensusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConsensusError::InvalidEpoch(msg)
+            | ConsensusError::StateUnavailable(msg)
+            | ConsensusError::InvalidTransaction(msg)
+            | ConsensusError::ExecutionFailed(msg)
+            | ConsensusError::Other(msg) => f.write_str(msg),
+            ConsensusError::Reverted(output) => write!(
+                f,
+                "execution reverted with output 0x{}",
+                output.to_hex()
+            ),
+            ConsensusError::OutOfGas { gas_limit } => {
+                write!(f, "execution out of gas with gas limit {}", gas_limit)
+            }
+        }
+    }
+}
+
+impl ConsensusError {
+    /// A short description of the kind of the error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ConsensusError::InvalidEpoch(_) => "invalid epoch",
+            ConsensusError::StateUnavailable(_) => "state unavailable",
+            ConsensusError::InvalidTransaction(_) => "invalid transaction",
+            ConsensusError::Reverted(_) => "reverted",
+            ConsensusError::OutOfGas { .. } => "out of gas",
+            ConsensusError::ExecutionFailed(_) => "execution failed",
+            ConsensusError::Other(_) => "error",
+        }
+    }
+}
+
+impl From<ConsensusError> for String {
+    fn from(e: ConsensusError) -> String { format!("{}: {}", e.kind(), e) }
+}
+
 impl ConsensusGraph {
     /// Build the ConsensusGraph with a specific era genesis block and various
     /// other components. The execution will be skipped if bench_mode sets
@@ -142,24 +343,38 @@ impl ConsensusGraph {
         state_exposer: SharedStateExposer,
     ) -> Self
     {
-        let inner =
-            Arc::new(RwLock::new(ConsensusGraphInner::with_era_genesis_block(
+        let inner = Arc::new(ProfiledRwLock::new(
+            "consensus_inner_lock",
+            ConsensusGraphInner::with_era_genesis_block(
                 pow_config,
                 data_man.clone(),
                 conf.inner_conf.clone(),
+                conf.ghast_rule.clone(),
+                // Registered once by the consensus graph and passed on to the
+                // inner of each era.
+                GaugeUsize::register_with_group(
+                    "consensus",
+                    "anticone_cache_max_anticone_size",
+                ),
                 era_genesis_block_hash,
                 None,
-            )));
+            ),
+            conf.profile_inner_lock,
+        ));
         let executor = ConsensusExecutor::start(
             txpool.clone(),
             data_man.clone(),
             vm,
             inner.clone(),
             conf.bench_mode,
+            conf.max_execution_queue_len,
+            conf.machine.clone(),
         );
         let confirmation_meter = ConfirmationMeter::new();
+        let gas_price_oracle = RwLock::new(conf.gas_price_oracle);
 
         let graph = ConsensusGraph {
+            config: conf.clone(),
             inner,
             txpool: txpool.clone(),
             data_man: data_man.clone(),
@@ -170,11 +385,22 @@ impl ConsensusGraph {
             ),
             confirmation_meter,
             best_info: RwLock::new(Arc::new(Default::default())),
+            epoch_index: RwLock::new(Arc::new(Default::default())),
             latest_inserted_block: Mutex::new(*era_genesis_block_hash),
             pivot_block_state_valid_map: Mutex::new(Default::default()),
+            peer_checkpoints: Mutex::new(None),
+            verified_stable_hash: Mutex::new(None),
             state_exposer,
+            best_info_observers: RwLock::new(BTreeMap::new()),
+            next_best_info_observer_id: AtomicUsize::new(0),
+            reorg_observers: RwLock::new(Vec::new()),
+            gas_price_oracle,
         };
-        graph.update_best_info(&*graph.inner.read());
+        graph.update_best_info(
+            &*graph
+                .inner
+                .read_at("ConsensusGraph::with_era_genesis_block"),
+        );
         graph
             .txpool
             .notify_new_best_info(graph.best_info.read_recursive().clone());
@@ -205,7 +431,7 @@ impl ConsensusGraph {
 
     /// Compute the expected difficulty of a new block given its parent
     pub fn expected_difficulty(&self, parent_hash: &H256) -> U256 {
-        let inner = self.inner.read();
+        let inner = self.inner.read_at("ConsensusGraph::expected_difficulty");
         inner.expected_difficulty(parent_hash)
     }
 
@@ -224,8 +450,10 @@ impl ConsensusGraph {
         {
             sleep(Duration::from_millis(1));
         }
-        let best_state_block =
-            self.inner.read_recursive().best_state_block_hash();
+        let best_state_block = self
+            .inner
+            .read_recursive_at("ConsensusGraph::wait_for_generation")
+            .best_state_block_hash();
         self.executor.wait_for_result(best_state_block);
     }
 
@@ -264,7 +492,13 @@ impl ConsensusGraph {
     }
 
     pub fn get_block_epoch_number(&self, hash: &H256) -> Option<u64> {
-        self.inner.read_recursive().get_block_epoch_number(hash)
+        if let Some(epoch_number) = self.epoch_index().block_epoch_number(hash)
+        {
+            return Some(epoch_number);
+        }
+        self.inner
+            .read_recursive_at("ConsensusGraph::get_block_epoch_number")
+            .get_block_epoch_number(hash)
     }
 
     pub fn get_block_hashes_by_epoch(
@@ -272,16 +506,159 @@ impl ConsensusGraph {
     ) -> Result<Vec<H256>, String> {
         self.get_height_from_epoch_number(epoch_number)
             .and_then(|height| {
-                self.inner.read_recursive().block_hashes_by_epoch(height)
+                if let Some(hashes) = self.epoch_index().epoch_hashes(height) {
+                    return Ok(hashes.clone());
+                }
+                self.inner
+                    .read_recursive_at(
+                        "ConsensusGraph::get_block_hashes_by_epoch",
+                    )
+                    .block_hashes_by_epoch(height)
             })
     }
 
-    /// Get the average gas price of the last GAS_PRICE_TRANSACTION_SAMPLE_SIZE
-    /// blocks
+    /// The snapshot of the epochs of the pivot chain. The epochs not in it,
+    /// i.e. those before the current era, are still queried from the inner.
+    fn epoch_index(&self) -> Arc<EpochIndex> {
+        self.epoch_index.read_recursive().clone()
+    }
+
+    /// Get the blocks of an epoch in their execution order, with the pivot
+    /// block, which is always executed last, marked.
+    pub fn get_epoch_blocks_with_info(
+        &self, epoch_number: EpochNumber,
+    ) -> Result<Vec<EpochBlockInfo>, String> {
+        let block_hashes = self.get_block_hashes_by_epoch(epoch_number)?;
+        let pivot_index = block_hashes.len().saturating_sub(1);
+        block_hashes
+            .into_iter()
+            .enumerate()
+            .map(|(execution_index, hash)| {
+                let header =
+                    self.data_man.block_header_by_hash(&hash).ok_or_else(
+                        || format!("Block header {:?} does not exist", hash),
+                    )?;
+                Ok(EpochBlockInfo {
+                    hash,
+                    is_pivot: execution_index == pivot_index,
+                    execution_index,
+                    referee_hashes: header.referee_hashes().clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Get the pivot block of an epoch.
+    pub fn get_pivot_block_of_epoch(
+        &self, epoch_number: EpochNumber,
+    ) -> Result<Arc<Block>, ConsensusError> {
+        let height = self
+            .get_height_from_epoch_number(epoch_number)
+            .map_err(ConsensusError::InvalidEpoch)?;
+        let hash = self
+            .get_hash_from_epoch_number(EpochNumber::Number(height))
+            .map_err(ConsensusError::InvalidEpoch)?;
+        self.epoch_block_by_hash(height, &hash)
+    }
+
+    /// Get the block at `index` of an epoch in the execution order, where
+    /// the pivot block is the last one.
+    pub fn get_block_by_epoch_and_index(
+        &self, epoch_number: EpochNumber, index: usize,
+    ) -> Result<Arc<Block>, ConsensusError> {
+        let height = self
+            .get_height_from_epoch_number(epoch_number)
+            .map_err(ConsensusError::InvalidEpoch)?;
+        let hash = match self.epoch_index().epoch_hashes(height) {
+            Some(hashes) => hashes.get(index).cloned(),
+            None => self
+                .inner
+                .read_recursive_at(
+                    "ConsensusGraph::get_block_by_epoch_and_index",
+                )
+                .block_hash_by_epoch_and_index(height, index)
+                .map_err(ConsensusError::InvalidEpoch)?,
+        }
+        .ok_or_else(|| {
+            ConsensusError::Other(format!(
+                "Epoch {} has no block at index {}",
+                height, index
+            ))
+        })?;
+        self.epoch_block_by_hash(height, &hash)
+    }
+
+    /// Get the block `hash` in epoch `height`, whose body may have been
+    /// pruned.
+    fn epoch_block_by_hash(
+        &self, height: u64, hash: &H256,
+    ) -> Result<Arc<Block>, ConsensusError> {
+        if height != 0 && height < self.data_man.earliest_block_body_epoch() {
+            return Err(ConsensusError::StateUnavailable(format!(
+                "The block bodies of epoch {} have been pruned, the earliest epoch with block bodies is {}",
+                height,
+                self.data_man.earliest_block_body_epoch()
+            )));
+        }
+        self.data_man
+            .block_by_hash(hash, false /* update_cache */)
+            .ok_or_else(|| {
+                ConsensusError::StateUnavailable(format!(
+                    "The body of block {:?} is not available",
+                    hash
+                ))
+            })
+    }
+
+    /// The median of the timestamps of the pivot blocks of the
+    /// `MEDIAN_TIME_PAST_WINDOW` epochs up to an epoch. Unlike the timestamp
+    /// of a single block, it never goes backwards along the pivot chain
+    /// unless the majority of the blocks in the window lie about the time.
+    pub fn median_time_past(
+        &self, epoch_number: EpochNumber,
+    ) -> Result<u64, String> {
+        // The pivot chain before a pivot block is its parent chain, so only
+        // the pivot hash is read under the lock and the window is walked
+        // through the headers.
+        let hash = self.get_hash_from_epoch_number(epoch_number)?;
+        median_time_past_of(&hash, |hash| {
+            self.data_man.block_header_by_hash(hash)
+        })
+        .ok_or_else(|| {
+            format!("Block headers before {:?} are not available", hash)
+        })
+    }
+
+    /// Get the reward breakdown of the blocks in an epoch. The rewards of an
+    /// epoch are only available after the pivot block `REWARD_EPOCH_COUNT`
+    /// epochs later is executed.
+    pub fn get_block_rewards(
+        &self, epoch_number: EpochNumber,
+    ) -> Result<Vec<BlockRewardResult>, String> {
+        let epoch_hash = self.get_hash_from_epoch_number(epoch_number)?;
+        self.data_man
+            .epoch_rewards_from_db(&epoch_hash)
+            .ok_or_else(|| {
+                format!("Rewards of epoch {:?} are not computed", epoch_hash)
+            })
+    }
+
+    /// Change the parameters of the gas price oracle at runtime.
+    pub fn set_gas_price_oracle_config(&self, conf: GasPriceOracleConfig) {
+        *self.gas_price_oracle.write() = conf;
+    }
+
+    /// Get the median gas price of the latest transactions, sampling at most
+    /// `transaction_sample_size` transactions in the latest
+    /// `block_sample_size` blocks.
     pub fn gas_price(&self) -> Option<U256> {
-        let inner = self.inner.read();
+        let GasPriceOracleConfig {
+            block_sample_size,
+            transaction_sample_size,
+        } = *self.gas_price_oracle.read();
+        let inner = self.inner.read_at("ConsensusGraph::gas_price");
         let mut last_epoch_number = inner.best_epoch_number();
-        let mut number_of_blocks_to_sample = GAS_PRICE_BLOCK_SAMPLE_SIZE;
+        let mut number_of_blocks_to_sample = block_sample_size;
         let mut tx_hashes = HashSet::new();
         let mut prices = Vec::new();
 
@@ -289,7 +666,7 @@ impl ConsensusGraph {
             if number_of_blocks_to_sample == 0 || last_epoch_number == 0 {
                 break;
             }
-            if prices.len() == GAS_PRICE_TRANSACTION_SAMPLE_SIZE {
+            if prices.len() == transaction_sample_size {
                 break;
             }
             let mut hashes = inner
@@ -306,7 +683,7 @@ impl ConsensusGraph {
                 for tx in block.transactions.iter() {
                     if tx_hashes.insert(tx.hash()) {
                         prices.push(tx.gas_price().clone());
-                        if prices.len() == GAS_PRICE_TRANSACTION_SAMPLE_SIZE {
+                        if prices.len() == transaction_sample_size {
                             break;
                         }
                     }
@@ -328,16 +705,18 @@ impl ConsensusGraph {
 
     fn validate_stated_epoch(
         &self, epoch_number: &EpochNumber,
-    ) -> Result<(), String> {
+    ) -> Result<(), ConsensusError> {
         match epoch_number {
             EpochNumber::LatestMined => {
-                return Err("Latest mined epoch is not executed".into());
+                return Err(ConsensusError::InvalidEpoch(
+                    "Latest mined epoch is not executed".into(),
+                ));
             }
             EpochNumber::Number(num) => {
                 let latest_state_epoch =
                     self.executed_best_state_epoch_number();
                 if *num > latest_state_epoch {
-                    return Err(format!("Specified epoch {} is not executed, the latest state epoch is {}", num, latest_state_epoch));
+                    return Err(ConsensusError::InvalidEpoch(format!("Specified epoch {} is not executed, the latest state epoch is {}", num, latest_state_epoch)));
                 }
             }
             _ => {}
@@ -346,47 +725,102 @@ impl ConsensusGraph {
         Ok(())
     }
 
-    fn get_state_db_by_epoch_number(
+    /// Return the height and hash of the specified executed epoch, so that
+    /// later reads can be pinned to exactly this epoch even if the pivot
+    /// chain changes.
+    pub fn get_stated_epoch_hash(
         &self, epoch_number: EpochNumber,
-    ) -> Result<StateDb, String> {
+    ) -> Result<(u64, H256), ConsensusError> {
         self.validate_stated_epoch(&epoch_number)?;
-        let epoch_number = self.get_height_from_epoch_number(epoch_number)?;
-        let hash =
-            self.inner.read().get_hash_from_epoch_number(epoch_number)?;
+        let epoch_number = self
+            .get_height_from_epoch_number(epoch_number)
+            .map_err(ConsensusError::InvalidEpoch)?;
+        let hash = self
+            .inner
+            .read()
+            .get_hash_from_epoch_number(epoch_number)
+            .map_err(ConsensusError::InvalidEpoch)?;
+        Ok((epoch_number, hash))
+    }
+
+    /// The state of an epoch no longer available in the delta MPT is
+    /// materialized from the archive if `archive_snapshot_interval` is set in
+    /// the storage configuration.
+    fn get_state_db_by_epoch_hash(
+        &self, epoch_hash: &H256,
+    ) -> Result<StateDb, ConsensusError> {
+        let height = self
+            .data_man
+            .block_header_by_hash(epoch_hash)
+            .ok_or_else(|| {
+                ConsensusError::StateUnavailable(format!(
+                    "Epoch (hash={:?}) does not exist",
+                    epoch_hash
+                ))
+            })?
+            .height();
+        let snapshot = self.data_man.storage_manager.snapshot_of_epoch(height);
         let maybe_state = self
             .data_man
             .storage_manager
-            .get_state_no_commit(SnapshotAndEpochIdRef::new(&hash, None))
-            .map_err(|e| format!("Error to get state, err={:?}", e))?;
+            .get_state_no_commit(SnapshotAndEpochIdRef::new_with_snapshot(
+                epoch_hash, &snapshot, height,
+            ))
+            .map_err(|e| {
+                ConsensusError::StateUnavailable(format!(
+                    "Error to get state, err={:?}",
+                    e
+                ))
+            })?;
 
         let state = match maybe_state {
             Some(state) => state,
             None => {
-                return Err(format!(
-                    "State for epoch (number={:?} hash={:?}) does not exist",
-                    epoch_number, hash
-                )
-                .into())
+                return Err(ConsensusError::StateUnavailable(format!(
+                    "State for epoch (hash={:?}) does not exist",
+                    epoch_hash
+                )))
             }
         };
 
         Ok(StateDb::new(state))
     }
 
+    /// Check that the state of the executed epoch with the given hash is
+    /// still available, i.e. not pruned.
+    pub fn check_state_available(
+        &self, epoch_hash: &H256,
+    ) -> Result<(), ConsensusError> {
+        self.get_state_db_by_epoch_hash(epoch_hash).map(|_| ())
+    }
+
+    fn get_state_db_by_epoch_number(
+        &self, epoch_number: EpochNumber,
+    ) -> Result<StateDb, ConsensusError> {
+        let (_, epoch_hash) = self.get_stated_epoch_hash(epoch_number)?;
+        self.get_state_db_by_epoch_hash(&epoch_hash)
+    }
+
     /// Get the code of an address
     pub fn get_code(
         &self, address: H160, epoch_number: EpochNumber,
-    ) -> Result<Bytes, String> {
-        let state_db =
-            self.get_state_db_by_epoch_number(epoch_number.clone())?;
+    ) -> Result<Bytes, ConsensusError> {
+        let (_, epoch_hash) = self.get_stated_epoch_hash(epoch_number)?;
+        self.get_code_by_epoch_hash(address, &epoch_hash)
+    }
+
+    /// Get the code of an address in the executed epoch with the given hash
+    pub fn get_code_by_epoch_hash(
+        &self, address: H160, epoch_hash: &H256,
+    ) -> Result<Bytes, ConsensusError> {
+        let state_db = self.get_state_db_by_epoch_hash(epoch_hash)?;
         let acc = match state_db.get_account(&address) {
             Ok(Some(acc)) => acc,
             _ => {
-                return Err(format!(
-                    "Account {:?} epoch_number={:?} does not exist",
-                    address, epoch_number,
-                )
-                .into())
+                return Err(ConsensusError::Other(format!(
+                    "Account {:?} epoch_hash={:?} does not exist",
+                    address, epoch_hash,
+                )))
             }
         };
 
@@ -399,8 +833,17 @@ impl ConsensusGraph {
     /// Get the current balance of an address
     pub fn get_balance(
         &self, address: H160, epoch_number: EpochNumber,
-    ) -> Result<U256, String> {
-        let state_db = self.get_state_db_by_epoch_number(epoch_number)?;
+    ) -> Result<U256, ConsensusError> {
+        let (_, epoch_hash) = self.get_stated_epoch_hash(epoch_number)?;
+        self.get_balance_by_epoch_hash(address, &epoch_hash)
+    }
+
+    /// Get the balance of an address in the executed epoch with the given
+    /// hash
+    pub fn get_balance_by_epoch_hash(
+        &self, address: H160, epoch_hash: &H256,
+    ) -> Result<U256, ConsensusError> {
+        let state_db = self.get_state_db_by_epoch_hash(epoch_hash)?;
         Ok(if let Ok(maybe_acc) = state_db.get_account(&address) {
             maybe_acc.map_or(U256::zero(), |acc| acc.balance).into()
         } else {
@@ -408,17 +851,47 @@ impl ConsensusGraph {
         })
     }
 
+    /// Get the accounts of multiple addresses, reading the state of the
+    /// epoch only once. Nonexistent accounts are `None`.
+    pub fn get_accounts(
+        &self, addresses: &[H160], epoch_number: EpochNumber,
+    ) -> Result<Vec<Option<Account>>, ConsensusError> {
+        let state_db = self.get_state_db_by_epoch_number(epoch_number)?;
+        state_db.get_accounts(addresses).map_err(|e| {
+            ConsensusError::Other(format!("Error to get accounts, err={:?}", e))
+        })
+    }
+
+    /// Get multiple storage slots of an address, reading the state of the
+    /// epoch only once. Unset slots are zero.
+    pub fn get_storage_slots(
+        &self, address: H160, keys: &[H256], epoch_number: EpochNumber,
+    ) -> Result<Vec<H256>, ConsensusError> {
+        let state_db = self.get_state_db_by_epoch_number(epoch_number)?;
+        let values =
+            state_db.get_storage_values(&address, keys).map_err(|e| {
+                ConsensusError::Other(format!(
+                    "Error to get storage, err={:?}",
+                    e
+                ))
+            })?;
+        Ok(values
+            .into_iter()
+            .map(|maybe_value| maybe_value.unwrap_or_default())
+            .collect())
+    }
+
     /// Force the engine to recompute the deferred state root for a particular
     /// block given a delay.
     pub fn force_compute_blame_and_deferred_state_for_generation(
         &self, parent_block_hash: &H256,
     ) -> Result<(u32, StateRootWithAuxInfo, H256, H256, H256), String> {
         {
-            let inner = &mut *self.inner.write();
+            let inner = &mut *self.inner.write_at("ConsensusGraph::force_compute_blame_and_deferred_state_for_generation");
             let hash = inner
                 .get_state_block_with_delay(
                     parent_block_hash,
-                    DEFERRED_STATE_EPOCH_COUNT as usize - 1,
+                    self.deferred_state_epoch_count() as usize - 1,
                 )?
                 .clone();
             self.executor.compute_state_for_block(&hash, inner)?;
@@ -438,32 +911,84 @@ impl ConsensusGraph {
         )
     }
 
+    /// Expose the last `count` pivot block hashes together with the current
+    /// terminal block hashes through the state exposer. This is cheap enough
+    /// to be polled periodically by a watchdog.
+    pub fn expose_pivot_tail(&self, count: usize) {
+        let inner = self
+            .inner
+            .read_recursive_at("ConsensusGraph::expose_pivot_tail");
+        let pivot_len = inner.pivot_chain.len();
+        let pivot_tail = inner.pivot_chain[pivot_len - min(count, pivot_len)..]
+            .iter()
+            .map(|idx| inner.arena[*idx].hash)
+            .collect();
+        let best_epoch_number = inner.best_epoch_number();
+        let terminal_block_hashes = inner.terminal_hashes();
+        drop(inner);
+
+        let exposer = &mut self.state_exposer.write().consensus_graph;
+        exposer.best_epoch_number = best_epoch_number;
+        exposer.pivot_tail = pivot_tail;
+        exposer.terminal_block_hashes = terminal_block_hashes;
+    }
+
+    pub fn state_exposer(&self) -> &SharedStateExposer { &self.state_exposer }
+
+    /// The number of epochs by which the execution is deferred, see
+    /// `ConsensusInnerConfig::deferred_state_epoch_count`.
+    pub fn deferred_state_epoch_count(&self) -> u64 {
+        self.config.inner_conf.deferred_state_epoch_count
+    }
+
+    /// Refresh the exposed state of the execution queue and the transaction
+    /// pool, which are not updated along with the pivot chain.
+    pub fn update_state_exposer(&self) {
+        let execution_queue_depth = self.executor.queue_len();
+        let (ready_accounts, deferred, received, unpacked) =
+            self.txpool.stats();
+        let mut exposer = self.state_exposer.write();
+        exposer.consensus_graph.execution_queue_depth = execution_queue_depth;
+        let txpool = &mut exposer.transaction_pool;
+        txpool.ready_accounts = ready_accounts;
+        txpool.deferred = deferred;
+        txpool.received = received;
+        txpool.unpacked = unpacked;
+    }
+
     /// This function is called after a new block appended to the
     /// ConsensusGraph. Because BestInformation is often queried outside. We
     /// store a version of best_info outside the inner to prevent keep
     /// getting inner locks.
     pub fn update_best_info(&self, inner: &ConsensusGraphInner) {
+        let epoch_index = Arc::new(self.epoch_index().updated(inner));
+        *self.epoch_index.write() = epoch_index;
+
         let mut best_info = self.best_info.write();
         self.state_exposer.write().consensus_graph.best_block_hash =
             inner.best_block_hash();
 
         let terminal_hashes = inner.terminal_hashes();
-        let (terminal_block_hashes, bounded_terminal_block_hashes) =
+        let (terminal_block_hashes, bounded_terminal_block_hashes, excluded) =
             if terminal_hashes.len() > REFEREE_BOUND {
-                let mut tmp = Vec::new();
-                let best_idx = inner.pivot_chain.last().unwrap();
-                for hash in terminal_hashes.iter() {
-                    let a_idx = inner.hash_to_arena_indices.get(hash).unwrap();
-                    let a_lca = inner.lca(*a_idx, *best_idx);
-                    tmp.push((inner.arena[a_lca].height, hash));
+                let selection = self.config.referee_selector.select(
+                    inner,
+                    terminal_hashes.clone(),
+                    REFEREE_BOUND,
+                );
+                for excluded in &selection.excluded {
+                    debug!(
+                        "Exclude terminal {:?} from referees: {}",
+                        excluded.hash, excluded.reason
+                    );
                 }
-                tmp.sort_by(|a, b| Reverse(a.0).cmp(&Reverse(b.0)));
-                tmp.split_off(REFEREE_BOUND);
-                let bounded_hashes =
-                    tmp.iter().map(|(_, b)| (*b).clone()).collect();
-                (Some(terminal_hashes), bounded_hashes)
+                (
+                    Some(terminal_hashes),
+                    selection.selected,
+                    selection.excluded,
+                )
             } else {
-                (None, terminal_hashes)
+                (None, terminal_hashes, Vec::new())
             };
 
         *best_info = Arc::new(BestInformation {
@@ -472,6 +997,14 @@ impl ConsensusGraph {
             current_difficulty: inner.current_difficulty,
             terminal_block_hashes,
             bounded_terminal_block_hashes,
+            excluded_terminals: excluded,
+            latest_confirmation_risk: self
+                .confirmation_meter
+                .latest_confirmation_risk(),
+            median_time_past: median_time_past_of(
+                &inner.best_block_hash(),
+                |hash| self.data_man.block_header_by_hash(hash),
+            ),
         });
     }
 
@@ -494,8 +1027,9 @@ impl ConsensusGraph {
             None
         };
 
-        {
-            let inner = &mut *self.inner.write();
+        let state_root_mismatches = {
+            let inner =
+                &mut *self.inner.write_at("ConsensusGraph::on_new_block");
             if !ignore_body {
                 let block = block_opt.unwrap();
                 debug!(
@@ -551,16 +1085,302 @@ impl ConsensusGraph {
                 }
             }
 
-            self.update_best_info(inner);
-            if *hash == self.data_man.get_cur_consensus_era_stable_hash() {
+            if *hash == self.data_man.get_cur_consensus_era_stable_hash()
+                && self.is_stable_hash_trusted(hash)
+            {
                 inner.set_pivot_to_stable(hash);
             }
+            // Update after the pivot chain may be set to the stable block, so
+            // that the epoch index does not keep the old pivot chain.
+            self.update_best_info(inner);
+            mem::replace(&mut inner.state_root_mismatches, Vec::new())
+        };
+        if !self.config.debug_dump_dir_invalid_state_root.is_empty() {
+            for (epoch_hash, reason) in state_root_mismatches {
+                warn!(
+                    "Execution result of epoch {:?} mismatches {}",
+                    epoch_hash, reason
+                );
+                match self.dump_epoch_diagnostics(&epoch_hash, Some(reason)) {
+                    Ok(path) => warn!("Epoch diagnostics dumped to {:?}", path),
+                    Err(e) => warn!("Failed to dump epoch diagnostics: {}", e),
+                }
+            }
+        }
+        let pivot_reorg = self.new_block_handler.take_pivot_reorg();
+        if !pivot_reorg.is_empty() {
+            self.on_pivot_reorg(&pivot_reorg);
+        }
+        let best_info = self.best_info.read().clone();
+        self.txpool.notify_new_best_info(best_info.clone());
+        for observer in self.best_info_observers.read().values() {
+            observer(best_info.clone());
         }
-        self.txpool
-            .notify_new_best_info(self.best_info.read().clone());
         *self.latest_inserted_block.lock() = *hash;
     }
 
+    /// Block until the execution queue is within its bound, so that the
+    /// block processing is throttled if the execution falls behind. It must
+    /// not be called while holding the sync graph lock.
+    pub fn wait_for_execution_capacity(&self) {
+        self.executor.wait_for_capacity();
+    }
+
+    /// Register `observer` to be notified with the latest best information
+    /// every time a new block is inserted, until it's unregistered with the
+    /// returned id.
+    pub fn register_best_info_observer(
+        &self, observer: BestInfoObserver,
+    ) -> BestInfoObserverId {
+        let id = self
+            .next_best_info_observer_id
+            .fetch_add(1, Ordering::Relaxed);
+        self.best_info_observers.write().insert(id, observer);
+        id
+    }
+
+    /// Stop notifying the observer registered with `id`.
+    pub fn unregister_best_info_observer(&self, id: BestInfoObserverId) {
+        self.best_info_observers.write().remove(&id);
+    }
+
+    /// Register `observer` to be notified with the blocks in the retracted
+    /// epochs every time the pivot chain is reorganized.
+    pub fn register_reorg_observer(&self, observer: PivotReorgObserver) {
+        self.reorg_observers.write().push(observer);
+    }
+
+    /// Register `observer` to be notified in the execution thread every time
+    /// an epoch on the local pivot chain is executed.
+    pub fn register_epoch_executed_observer(
+        &self, observer: EpochExecutedObserver,
+    ) {
+        self.executor
+            .handler
+            .register_epoch_executed_observer(observer);
+    }
+
+    /// Load the blocks and receipts of an executed epoch from the database,
+    /// e.g. for the epochs executed before an observer is registered. The
+    /// state diff is not available.
+    pub fn executed_epoch(
+        &self, epoch_number: u64,
+    ) -> Result<ExecutedEpoch, String> {
+        if epoch_number > self.executed_best_state_epoch_number() {
+            return Err(format!("Epoch {} is not executed", epoch_number));
+        }
+        let epoch_hash =
+            self.get_hash_from_epoch_number(EpochNumber::Number(epoch_number))?;
+        let block_hashes =
+            self.get_block_hashes_by_epoch(EpochNumber::Number(epoch_number))?;
+        let blocks = self
+            .data_man
+            .blocks_by_hash_list(&block_hashes, false /* update_cache */)
+            .ok_or_else(|| {
+                format!("Blocks of epoch {} do not exist", epoch_number)
+            })?;
+        let receipts = block_hashes
+            .iter()
+            .map(|hash| {
+                self.data_man
+                    .block_execution_result_by_hash_with_epoch(
+                        hash,
+                        &epoch_hash,
+                        false, /* update_cache */
+                    )
+                    .map(|result| result.receipts)
+                    .ok_or_else(|| {
+                        format!("Receipts of block {:?} do not exist", hash)
+                    })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(ExecutedEpoch {
+            epoch_hash,
+            epoch_number,
+            blocks,
+            receipts,
+            state_diff: None,
+        })
+    }
+
+    /// Hand the transactions in the retracted blocks back to the transaction
+    /// pool, which re-validates them against the latest state, and notify
+    /// the reorg observers. The transactions also packed in the blocks of the
+    /// new pivot chain are not recycled.
+    fn on_pivot_reorg(&self, pivot_reorg: &PivotReorg) {
+        debug!(
+            "Pivot chain reorganized, {} blocks retracted, {} blocks enacted",
+            pivot_reorg.retracted_blocks.len(),
+            pivot_reorg.enacted_blocks.len()
+        );
+        let blocks_by_hash = |hashes: &[H256]| {
+            hashes
+                .iter()
+                .filter_map(|hash| {
+                    self.data_man
+                        .block_by_hash(hash, false /* update_cache */)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let enacted_txs: HashSet<H256> =
+            blocks_by_hash(&pivot_reorg.enacted_blocks)
+                .iter()
+                .flat_map(|block| block.transactions.iter().map(|tx| tx.hash()))
+                .collect();
+        let retracted_txs = blocks_by_hash(&pivot_reorg.retracted_blocks)
+            .iter()
+            .flat_map(|block| block.transactions.iter())
+            .filter(|tx| !enacted_txs.contains(&tx.hash()))
+            .cloned()
+            .collect();
+        self.txpool.recycle_retracted_transactions(retracted_txs);
+
+        for observer in self.reorg_observers.read().iter() {
+            observer(&pivot_reorg.retracted_blocks);
+        }
+    }
+
+    /// Export the persisted blame verification outcomes, i.e. the
+    /// `state_valid` derived from the blame information when recovering from
+    /// a checkpoint, of the pivot blocks in the current era as
+    /// `(height, hash, state_valid)` sorted by height.
+    pub fn blame_state_valid_checkpoint(&self) -> Vec<(u64, H256, bool)> {
+        let inner = self
+            .inner
+            .read_at("ConsensusGraph::blame_state_valid_checkpoint");
+        inner
+            .pivot_chain
+            .iter()
+            .filter_map(|index| {
+                let hash = inner.arena[*index].hash;
+                self.data_man.blame_state_valid_from_db(&hash).map(
+                    |state_valid| {
+                        (inner.arena[*index].height, hash, state_valid)
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Recompute the `state_valid` of the pivot blocks with heights in
+    /// `[from, to]` from the blame information in block headers, assuming
+    /// the state of the pivot block at height `to` is valid, as is the case
+    /// for the first trusted blame block. The results are cross-checked
+    /// against the persisted outcomes and the consensus graph. Return the
+    /// number of checked blocks.
+    pub fn verify_blame_chain(
+        &self, from: u64, to: u64,
+    ) -> Result<u64, String> {
+        if from > to {
+            return Err(format!("Invalid range [{}, {}]", from, to));
+        }
+        let inner = self.inner.read_at("ConsensusGraph::verify_blame_chain");
+        let recovering = self.pivot_block_state_valid_map.lock();
+        let mut hash = inner.get_hash_from_epoch_number(to)?;
+        let mut header = self
+            .data_man
+            .block_header_by_hash(&hash)
+            .ok_or_else(|| format!("Missing block header {:?}", hash))?;
+        // The number of blocks after the current one whose state is blamed.
+        let mut blamed = 0;
+        let mut checked = 0;
+
+        loop {
+            let height = header.height();
+            let expected = blamed == 0;
+            blamed = if expected { header.blame() } else { blamed - 1 };
+
+            if let Some(state_valid) =
+                self.data_man.blame_state_valid_from_db(&hash)
+            {
+                if state_valid != expected {
+                    return Err(format!(
+                        "Persisted state_valid={} of pivot block {:?} at height {} mismatches the blame chain",
+                        state_valid, hash, height
+                    ));
+                }
+            }
+            // The state_valid of blocks not recovered yet is not set.
+            if let (Some(index), false) = (
+                inner.hash_to_arena_indices.get(&hash),
+                recovering.contains_key(&hash),
+            ) {
+                if inner.arena[*index].data.state_valid != expected {
+                    return Err(format!(
+                        "state_valid of pivot block {:?} at height {} mismatches the blame chain",
+                        hash, height
+                    ));
+                }
+            }
+            checked += 1;
+
+            if height <= from || height == 0 {
+                break;
+            }
+            hash = *header.parent_hash();
+            header = self
+                .data_man
+                .block_header_by_hash(&hash)
+                .ok_or_else(|| format!("Missing block header {:?}", hash))?;
+        }
+
+        Ok(checked)
+    }
+
+    /// Dump the blocks within `window` epochs of the best epoch into `path`
+    /// in the specified format for visualization. Return the number of
+    /// exported blocks.
+    pub fn export_tree_graph(
+        &self, path: &Path, format: TreeGraphFormat, window: u64,
+    ) -> Result<usize, String> {
+        let blocks = self
+            .inner
+            .read_at("ConsensusGraph::export_tree_graph")
+            .tree_graph_blocks(window);
+        debug::export_tree_graph(&blocks, path, format)?;
+        Ok(blocks.len())
+    }
+
+    /// Get the misbehaving block producers of the era with the specified
+    /// genesis height, or of all recent eras if `era_genesis_height` is
+    /// `None`.
+    pub fn misbehavior_report(
+        &self, era_genesis_height: Option<u64>,
+    ) -> Vec<(u64, Address, ProducerMisbehavior)> {
+        self.new_block_handler.misbehavior_tracker.report(
+            era_genesis_height,
+            |hash| {
+                self.data_man
+                    .block_header_by_hash(hash)
+                    .map(|header| *header.author())
+            },
+        )
+    }
+
+    /// Get the DAG health stats aggregated over the recent blocks, e.g. the
+    /// average anticone size and the rate of pivot chain switches.
+    pub fn chain_health(&self) -> ChainHealth {
+        self.new_block_handler.chain_health.report()
+    }
+
+    /// Get the confirmation risk of block `hash` maintained by the
+    /// confirmation meter, if available.
+    pub fn confirmation_risk_by_hash(&self, hash: H256) -> Option<f64> {
+        let inner = self
+            .inner
+            .read_recursive_at("ConsensusGraph::confirmation_risk_by_hash");
+        self.confirmation_meter
+            .confirmation_risk_by_hash(&inner, hash)
+    }
+
+    /// Get the confirmation risk of the pivot block of `epoch_number`
+    /// maintained by the confirmation meter, if available.
+    pub fn confirmation_risk_by_epoch(&self, epoch_number: u64) -> Option<f64> {
+        self.confirmation_meter
+            .confirmation_risk_by_epoch(epoch_number)
+    }
+
     pub fn best_block_hash(&self) -> H256 {
         self.best_info.read_recursive().best_block_hash
     }
@@ -578,7 +1398,9 @@ impl ConsensusGraph {
     /// Note that the state may not exist, and the caller should wait for the
     /// result if the state is going to be used.
     pub fn best_state_epoch_number(&self) -> u64 {
-        self.inner.read_recursive().best_state_epoch_number()
+        self.inner
+            .read_recursive_at("ConsensusGraph::best_state_epoch_number")
+            .best_state_epoch_number()
     }
 
     pub fn get_hash_from_epoch_number(
@@ -586,7 +1408,12 @@ impl ConsensusGraph {
     ) -> Result<H256, String> {
         self.get_height_from_epoch_number(epoch_number)
             .and_then(|height| {
-                self.inner.read().get_hash_from_epoch_number(height)
+                if let Some(hash) = self.epoch_index().pivot_hash(height) {
+                    return Ok(hash);
+                }
+                self.inner
+                    .read_at("ConsensusGraph::get_hash_from_epoch_number")
+                    .get_hash_from_epoch_number(height)
             })
     }
 
@@ -595,7 +1422,9 @@ impl ConsensusGraph {
     ) -> Option<(SignedTransaction, Receipt, TransactionAddress)> {
         // We need to hold the inner lock to ensure that tx_address and receipts
         // are consistent
-        let inner = self.inner.read();
+        let inner = self
+            .inner
+            .read_at("ConsensusGraph::get_transaction_info_by_hash");
         if let Some((receipt, address)) =
             inner.get_transaction_receipt_with_address(hash)
         {
@@ -610,11 +1439,175 @@ impl ConsensusGraph {
         }
     }
 
+    /// Returns the receipt of the transaction `tx_hash`, its proof, and the
+    /// hash of the first trusted pivot block whose header commits the
+    /// receipts of the epoch of the transaction, directly or through the
+    /// vector of roots of the epochs it blames.
+    ///
+    /// The positions are collected under the inner lock, and the receipts
+    /// and roots are read from the database after it's released. The pivot
+    /// chain is checked again afterwards.
+    pub fn get_transaction_receipt_proof(
+        &self, tx_hash: &H256,
+    ) -> Result<(Receipt, ReceiptProof, H256), String> {
+        let address = self
+            .data_man
+            .transaction_address_by_hash(tx_hash, false /* update_cache */)
+            .ok_or_else(|| {
+                format!("Transaction {:?} is not executed", tx_hash)
+            })?;
+        let (epoch_number, epoch_hash, epoch_block_hashes, witness_hash) = {
+            let inner = self
+                .inner
+                .read_at("ConsensusGraph::get_transaction_receipt_proof");
+            let epoch_number = inner
+                .get_block_epoch_number(&address.block_hash)
+                .ok_or_else(|| {
+                    format!(
+                        "Block {:?} of transaction {:?} is not in an epoch of \
+                         the current era",
+                        address.block_hash, tx_hash
+                    )
+                })?;
+            let epoch_hash = inner.get_hash_from_epoch_number(epoch_number)?;
+            // The epoch set of the era genesis is only in the database.
+            let epoch_block_hashes =
+                if epoch_number == inner.get_cur_era_genesis_height() {
+                    None
+                } else {
+                    Some(inner.block_hashes_by_epoch(epoch_number)?)
+                };
+            let witness_index = inner
+                .find_first_index_with_correct_state_of(
+                    inner.height_to_pivot_index(epoch_number),
+                    None, /* blame_bound */
+                )
+                .ok_or_else(|| {
+                    format!(
+                        "The receipts of epoch {} are not committed by a \
+                         trusted header yet",
+                        epoch_number
+                    )
+                })?;
+            let witness_hash =
+                inner.arena[inner.pivot_chain[witness_index]].hash;
+            (epoch_number, epoch_hash, epoch_block_hashes, witness_hash)
+        };
+
+        let epoch_block_hashes = match epoch_block_hashes {
+            Some(hashes) => hashes,
+            None => self
+                .data_man
+                .epoch_set_hashes_from_db(epoch_number)
+                .ok_or_else(|| {
+                    format!("Missing the epoch set of epoch {}", epoch_number)
+                })?,
+        };
+        let block_index = epoch_block_hashes
+            .iter()
+            .position(|hash| *hash == address.block_hash)
+            .ok_or_else(|| {
+                format!(
+                    "Block {:?} is not in epoch {}",
+                    address.block_hash, epoch_number
+                )
+            })?;
+        let epoch_receipts = epoch_block_hashes
+            .iter()
+            .map(|hash| {
+                self.data_man
+                    .block_execution_result_by_hash_with_epoch(
+                        hash,
+                        &epoch_hash,
+                        false, /* update_cache */
+                    )
+                    .map(|result| result.receipts)
+                    .ok_or_else(|| format!("Missing receipts of {:?}", hash))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let receipt = epoch_receipts[block_index]
+            .get(address.index)
+            .cloned()
+            .ok_or_else(|| {
+            format!("Missing receipt of transaction {:?}", tx_hash)
+        })?;
+
+        // The roots committed by the witness, from the witness itself back to
+        // the earliest pivot block it blames, whose parents are the pivot
+        // blocks before them.
+        let witness = self
+            .data_man
+            .block_header_by_hash(&witness_hash)
+            .ok_or_else(|| {
+                format!("Missing block header {:?}", witness_hash)
+            })?;
+        let epoch_index = (witness.height()
+            - epoch_number
+            - self.deferred_state_epoch_count())
+            as usize;
+        let mut receipts_roots =
+            Vec::with_capacity(witness.blame() as usize + 1);
+        let mut header = witness.clone();
+        loop {
+            let info = self
+                .data_man
+                .consensus_graph_execution_info_from_db(&header.hash())
+                .ok_or_else(|| {
+                    format!(
+                        "The receipts root committed by {:?} is not computed \
+                         yet",
+                        header.hash()
+                    )
+                })?;
+            receipts_roots.push(info.original_deferred_receipt_root);
+            if receipts_roots.len() > witness.blame() as usize {
+                break;
+            }
+            header = self
+                .data_man
+                .block_header_by_hash(header.parent_hash())
+                .ok_or_else(|| {
+                format!("Missing block header {:?}", header.parent_hash())
+            })?;
+        }
+
+        {
+            let inner = self
+                .inner
+                .read_at("ConsensusGraph::get_transaction_receipt_proof");
+            if inner.get_hash_from_epoch_number(epoch_number).ok()
+                != Some(epoch_hash)
+                || inner.get_hash_from_epoch_number(witness.height()).ok()
+                    != Some(witness_hash)
+            {
+                return Err(format!(
+                    "The pivot chain has changed while proving the receipt of \
+                     transaction {:?}",
+                    tx_hash
+                ));
+            }
+        }
+
+        let proof = ReceiptProof::new(
+            &epoch_receipts,
+            block_index,
+            address.index,
+            receipts_roots,
+            epoch_index,
+        )
+        .ok_or_else(|| {
+            format!("Missing receipt of transaction {:?}", tx_hash)
+        })?;
+        Ok((receipt, proof, witness_hash))
+    }
+
     pub fn get_state_root_by_pivot_height(
         &self, pivot_height: u64,
     ) -> Option<H256> {
-        let inner = self.inner.read();
-        let height = pivot_height + DEFERRED_STATE_EPOCH_COUNT as u64;
+        let inner = self
+            .inner
+            .read_at("ConsensusGraph::get_state_root_by_pivot_height");
+        let height = pivot_height + self.deferred_state_epoch_count();
         let pivot_index = match height {
             h if h < inner.get_cur_era_genesis_height() => return None,
             h => inner.height_to_pivot_index(h),
@@ -634,17 +1627,23 @@ impl ConsensusGraph {
 
     pub fn transaction_count(
         &self, address: H160, epoch_number: EpochNumber,
-    ) -> Result<U256, String> {
+    ) -> Result<U256, ConsensusError> {
         let state_db = self.get_state_db_by_epoch_number(epoch_number)?;
         let state = State::new(state_db, 0.into(), Default::default());
-        state
-            .nonce(&address)
-            .map_err(|err| format!("Get transaction count error: {:?}", err))
+        state.nonce(&address).map_err(|err| {
+            ConsensusError::Other(format!(
+                "Get transaction count error: {:?}",
+                err
+            ))
+        })
     }
 
     /// Wait until the best state has been executed, and return the state
     pub fn get_best_state(&self) -> State {
-        let best_state_hash = self.inner.read().best_state_block_hash();
+        let best_state_hash = self
+            .inner
+            .read_at("ConsensusGraph::get_best_state")
+            .best_state_block_hash();
         self.executor.wait_for_result(best_state_hash);
         if let Ok(state) = self.data_man.storage_manager.get_state_no_commit(
             SnapshotAndEpochIdRef::new(&best_state_hash, None),
@@ -666,18 +1665,39 @@ impl ConsensusGraph {
     /// current checkpoint may not be counted since they will not be
     /// inserted into consensus in the recover process.
     pub fn block_count(&self) -> u64 {
-        self.inner.read_recursive().total_processed_block_count()
+        self.inner
+            .read_recursive_at("ConsensusGraph::block_count")
+            .total_processed_block_count()
+    }
+
+    /// Estimate the minimum gas with which a transaction succeeds against
+    /// the latest state.
+    pub fn estimate_gas(
+        &self, tx: &SignedTransaction,
+    ) -> Result<U256, ConsensusError> {
+        let epoch_id = self
+            .get_hash_from_epoch_number(EpochNumber::LatestState)
+            .map_err(ConsensusError::StateUnavailable)?;
+        self.executor.estimate_gas(
+            tx,
+            &epoch_id,
+            self.config.estimate_gas_upper_bound.into(),
+        )
     }
 
-    /// Estimate the gas of a transaction
-    pub fn estimate_gas(&self, tx: &SignedTransaction) -> Result<U256, String> {
-        self.call_virtual(tx, EpochNumber::LatestState)
-            .map(|(_, gas_used)| gas_used)
+    /// The statistics of the transactions skipped in the executed epoch with
+    /// the given pivot block hash, if it is executed recently by this node.
+    pub fn skipped_transaction_stats(
+        &self, epoch_hash: &H256,
+    ) -> Option<SkippedTransactionStats> {
+        self.executor.skipped_transaction_stats(epoch_hash)
     }
 
     pub fn logs(
         &self, filter: Filter,
     ) -> Result<Vec<LocalizedLogEntry>, FilterError> {
+        let earliest_block_body_epoch =
+            self.data_man.earliest_block_body_epoch();
         let block_hashes = if filter.block_hashes.is_none() {
             // at most best_epoch
             let from_epoch = match self
@@ -698,6 +1718,12 @@ impl ConsensusGraph {
                     to_epoch,
                 });
             }
+            // The true genesis block is never pruned.
+            if from_epoch < earliest_block_body_epoch && to_epoch > 0 {
+                return Err(FilterError::BlockBodiesPruned {
+                    earliest_block_body_epoch,
+                });
+            }
 
             let blooms = filter.bloom_possibilities();
             let bloom_match = |block_log_bloom: &Bloom| {
@@ -706,17 +1732,45 @@ impl ConsensusGraph {
                     .any(|bloom| block_log_bloom.contains_bloom(bloom))
             };
 
-            let inner = self.inner.read();
+            let inner = self.inner.read_at("ConsensusGraph::logs");
+            // After the pivot chain changes, the aggregated blooms miss the
+            // logs of the new pivot epochs until they are executed.
+            let aggregated_to_epoch =
+                min(to_epoch, inner.executed_best_state_epoch_number());
 
             let mut blocks = vec![];
-            for epoch_number in from_epoch..(to_epoch + 1) {
-                let epoch_hash = inner.arena
-                    [inner.get_pivot_block_arena_index(epoch_number)]
-                .hash;
-                for index in &inner.arena
-                    [inner.get_pivot_block_arena_index(epoch_number)]
-                .data
-                .ordered_executable_epoch_blocks
+            let mut epoch_number = from_epoch;
+            while epoch_number <= to_epoch {
+                if let Some(next_epoch) =
+                    self.data_man.skip_epochs_by_bloom_index(
+                        epoch_number,
+                        aggregated_to_epoch,
+                        &bloom_match,
+                    )
+                {
+                    epoch_number = next_epoch;
+                    continue;
+                }
+                let pivot_arena_index =
+                    inner.get_pivot_block_arena_index(epoch_number);
+                let epoch_hash = inner.arena[pivot_arena_index].hash;
+                // The bloom of an epoch is only usable if it's indexed for
+                // the current pivot block.
+                let epoch_mismatch =
+                    match self.data_man.epoch_bloom(epoch_number) {
+                        Some(epoch_bloom) => {
+                            epoch_bloom.epoch_hash == epoch_hash
+                                && !bloom_match(&epoch_bloom.bloom)
+                        }
+                        None => false,
+                    };
+                epoch_number += 1;
+                if epoch_mismatch {
+                    continue;
+                }
+                for index in &inner.arena[pivot_arena_index]
+                    .data
+                    .ordered_executable_epoch_blocks
                 {
                     let hash = inner.arena[*index].hash;
                     if let Some(block_log_bloom) = self
@@ -738,7 +1792,22 @@ impl ConsensusGraph {
 
             blocks
         } else {
-            filter.block_hashes.as_ref().unwrap().clone()
+            let block_hashes = filter.block_hashes.as_ref().unwrap().clone();
+            if earliest_block_body_epoch > 0 {
+                for hash in &block_hashes {
+                    if self.data_man.block_header_by_hash(hash).is_some()
+                        && self
+                            .data_man
+                            .block_by_hash(hash, false /* update_cache */)
+                            .is_none()
+                    {
+                        return Err(FilterError::BlockBodiesPruned {
+                            earliest_block_body_epoch,
+                        });
+                    }
+                }
+            }
+            block_hashes
         };
 
         Ok(self.logs_from_blocks(
@@ -766,7 +1835,7 @@ impl ConsensusGraph {
             .flat_map(move |blocks_chunk| {
                 blocks_chunk.into_par_iter()
                     .filter_map(|hash|
-                        self.inner.read().block_receipts_by_hash(&hash, false /* update_cache */).map(|r| (hash, (*r).clone()))
+                        self.inner.read_at("ConsensusGraph::logs_from_blocks").block_receipts_by_hash(&hash, false /* update_cache */).map(|r| (hash, (*r).clone()))
                     )
                     .filter_map(|(hash, receipts)| self.data_man.block_by_hash(&hash, false /* update_cache */).map(|b| (hash, receipts, b.transaction_hashes())))
                     .flat_map(|(hash, mut receipts, mut hashes)| {
@@ -817,17 +1886,50 @@ impl ConsensusGraph {
 
     pub fn call_virtual(
         &self, tx: &SignedTransaction, epoch: EpochNumber,
-    ) -> Result<(Vec<u8>, U256), String> {
+    ) -> Result<(Vec<u8>, U256), ConsensusError> {
         // only allow to call against stated epoch
-        self.validate_stated_epoch(&epoch)?;
-        let epoch_id = self.get_hash_from_epoch_number(epoch)?;
-        self.executor.call_virtual(tx, &epoch_id)
+        let (_, epoch_id) = self.get_stated_epoch_hash(epoch)?;
+        self.call_virtual_by_epoch_hash(tx, &epoch_id)
+    }
+
+    /// Execute the transaction against the state of the executed epoch with
+    /// the given hash.
+    pub fn call_virtual_by_epoch_hash(
+        &self, tx: &SignedTransaction, epoch_hash: &H256,
+    ) -> Result<(Vec<u8>, U256), ConsensusError> {
+        self.executor.call_virtual(tx, epoch_hash)
+    }
+
+    /// Return the statistics of waiting for and holding the inner lock at
+    /// the `limit` most contended call sites.
+    pub fn inner_lock_contention(&self, limit: usize) -> Vec<LockSiteReport> {
+        let mut reports = self.inner.contention_report();
+        reports.truncate(limit);
+        reports
+    }
+
+    /// Return the hash of the current era genesis.
+    pub fn current_era_genesis_hash(&self) -> H256 {
+        let inner = self
+            .inner
+            .read_recursive_at("ConsensusGraph::current_era_genesis_hash");
+        inner.arena[inner.cur_era_genesis_block_arena_index].hash
+    }
+
+    /// Return the height of the current era genesis. The pivot chain is
+    /// finalized up to it, so the epochs no later than it never change.
+    pub fn current_era_genesis_height(&self) -> u64 {
+        self.inner
+            .read_recursive_at("ConsensusGraph::current_era_genesis_height")
+            .get_cur_era_genesis_height()
     }
 
     // FIXME store this in BlockDataManager
     /// Return the sequence number of the current era genesis hash.
     pub fn current_era_genesis_seq_num(&self) -> u64 {
-        let inner = self.inner.read_recursive();
+        let inner = self
+            .inner
+            .read_recursive_at("ConsensusGraph::current_era_genesis_seq_num");
         inner.arena[inner.cur_era_genesis_block_arena_index]
             .data
             .sequence_number
@@ -852,19 +1954,30 @@ impl ConsensusGraph {
     ) -> Result<Vec<H256>, String> {
         self.get_height_from_epoch_number(epoch_number)
             .and_then(|height| {
-                self.inner.read_recursive().block_hashes_by_epoch(height)
+                if let Some(hashes) = self.epoch_index().epoch_hashes(height) {
+                    return Ok(hashes.clone());
+                }
+                self.inner
+                    .read_recursive_at("ConsensusGraph::block_hashes_by_epoch")
+                    .block_hashes_by_epoch(height)
             })
     }
 
     /// This function returns the set of blocks that are two eras farther from
     /// current era. They can be safely garbage collected.
     pub fn retrieve_old_era_blocks(&self) -> Option<H256> {
-        self.inner.read().old_era_block_set.lock().pop_front()
+        self.inner
+            .read_at("ConsensusGraph::retrieve_old_era_blocks")
+            .old_era_block_set
+            .lock()
+            .pop_front()
     }
 
     /// Find a trusted blame block for checkpoint
     pub fn get_trusted_blame_block(&self, stable_hash: &H256) -> Option<H256> {
-        let inner = self.inner.read();
+        let inner = self
+            .inner
+            .read_at("ConsensusGraph::get_trusted_blame_block");
         inner.get_trusted_blame_block(stable_hash)
     }
 
@@ -873,7 +1986,9 @@ impl ConsensusGraph {
     ) -> Option<u64> {
         // TODO(thegaram): change logic to work with arbitrary height, not just
         // the ones from the current era (i.e. use epoch instead of pivot index)
-        let inner = self.inner.read();
+        let inner = self
+            .inner
+            .read_at("ConsensusGraph::first_trusted_header_starting_from");
 
         // for now, make sure to avoid underflow
         let pivot_index = match height {
@@ -889,9 +2004,60 @@ impl ConsensusGraph {
     /// construct_pivot_state() rebuild pivot chain state info from db
     /// avoiding intermediate redundant computation triggered by
     /// on_new_block().
+    /// The work is done in batches of `construct_pivot_state_batch_size`
+    /// pivot blocks, and the inner lock is released between batches. If the
+    /// pivot chain changes between batches, the work is continued from the
+    /// fork point.
     pub fn construct_pivot_state(&self) {
-        let inner = &mut *self.inner.write();
-        self.new_block_handler.construct_pivot_state(inner);
+        let batch_size = max(self.config.construct_pivot_state_batch_size, 1);
+        // the hashes of the pivot blocks processed so far
+        let mut processed = Vec::new();
+
+        loop {
+            let (end, total) = {
+                let inner = &mut *self
+                    .inner
+                    .write_at("ConsensusGraph::construct_pivot_state");
+                let pivot_hash_at =
+                    |inner: &ConsensusGraphInner, index: usize| {
+                        let arena_index = inner.pivot_chain.get(index)?;
+                        Some(inner.arena[*arena_index].hash)
+                    };
+                let total =
+                    ConsensusNewBlockHandler::construct_pivot_state_len(inner);
+                let start = min(
+                    construct_pivot_state_resume_index(&processed, |index| {
+                        pivot_hash_at(&*inner, index)
+                    }),
+                    total,
+                );
+                if start < processed.len() {
+                    info!(
+                        "construct_pivot_state: pivot chain changed at {}, \
+                         continuing from there",
+                        start
+                    );
+                    processed.truncate(start);
+                }
+
+                let end = min(start + batch_size, total);
+                self.new_block_handler
+                    .construct_pivot_state(inner, start..end);
+                processed.extend(
+                    (start..end)
+                        .filter_map(|index| pivot_hash_at(&*inner, index)),
+                );
+                (end, total)
+            };
+
+            CONSTRUCT_PIVOT_STATE_PROGRESS.update(end);
+            info!("construct_pivot_state progress: {}/{}", end, total);
+            if end >= total {
+                break;
+            }
+        }
+
+        info!("construct_pivot_state finished");
     }
 
     pub fn best_info(&self) -> Arc<BestInformation> {
@@ -902,3 +2068,52 @@ impl ConsensusGraph {
 impl Drop for ConsensusGraph {
     fn drop(&mut self) { self.executor.stop(); }
 }
+
+/// The pivot index from which `construct_pivot_state()` continues, given the
+/// hashes of the pivot blocks `processed` in the previous batches, i.e. the
+/// index after the last processed block still on the pivot chain. The
+/// processed blocks are ancestors of each other, so the hash at the batch
+/// boundary is compared first.
+fn construct_pivot_state_resume_index<F>(
+    processed: &[H256], pivot_hash_at: F,
+) -> usize
+where F: Fn(usize) -> Option<H256> {
+    let mut index = processed.len();
+    while index > 0 && pivot_hash_at(index - 1) != Some(processed[index - 1]) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::construct_pivot_state_resume_index;
+    use cfx_types::H256;
+
+    #[test]
+    fn test_construct_pivot_state_resume_index() {
+        let hash = H256::from_low_u64_be;
+        let processed: Vec<_> = (0..5).map(hash).collect();
+        let resume = |pivot_chain: Vec<H256>| {
+            construct_pivot_state_resume_index(&processed, |index| {
+                pivot_chain.get(index).cloned()
+            })
+        };
+
+        // The pivot chain is extended.
+        assert_eq!(resume((0..8).map(hash).collect()), 5);
+        assert_eq!(resume((0..5).map(hash).collect()), 5);
+
+        // The pivot chain forks after the block at index 2.
+        let forked = vec![hash(0), hash(1), hash(2), hash(13), hash(14)];
+        assert_eq!(resume(forked), 3);
+
+        // The pivot chain is switched to a shorter fork.
+        assert_eq!(resume(vec![hash(0), hash(11)]), 1);
+        assert_eq!(resume(vec![]), 0);
+        assert_eq!(
+            construct_pivot_state_resume_index(&[], |_| Some(hash(0))),
+            0
+        );
+    }
+}
@@ -3,19 +3,40 @@
 // See http://www.gnu.org/licenses/
 
 use hibitset::BitSet;
-use std::collections::{HashMap, HashSet};
+use metrics::{register_meter_with_group, Meter};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
-const MAX_PASTSET_CACHE_CAP: usize = 256;
+lazy_static! {
+    static ref PASTSET_CACHE_HIT: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "pastset_cache_hit");
+    static ref PASTSET_CACHE_MISS: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "pastset_cache_miss");
+    static ref PASTSET_CACHE_EVICT: Arc<dyn Meter> =
+        register_meter_with_group("consensus", "pastset_cache_evict");
+}
 
-#[derive(Default)]
+/// PastSetCache keeps the pastsets of at most `capacity` blocks, and evicts
+/// the least recently used one when it is full.
 pub struct PastSetCache {
+    capacity: usize,
     cache: HashMap<usize, (BitSet, u64)>,
     entry: u64,
 }
 
 impl PastSetCache {
+    pub fn new(capacity: usize) -> Self {
+        PastSetCache {
+            capacity,
+            cache: HashMap::new(),
+            entry: 0,
+        }
+    }
+
     pub fn update(&mut self, me: usize, pastset: BitSet) {
-        if self.cache.len() == MAX_PASTSET_CACHE_CAP {
+        if self.cache.len() >= self.capacity {
             let mut evict = 0;
             let mut min_entry = self.entry;
             for (index, (_, entry)) in self.cache.iter() {
@@ -26,19 +47,27 @@ impl PastSetCache {
             }
             assert!(min_entry != self.entry);
             self.cache.remove(&evict);
+            PASTSET_CACHE_EVICT.mark(1);
         }
         self.cache.insert(me, (pastset, self.entry));
         self.entry += 1;
     }
 
+    /// Get the pastset of `me`. Only the lookups with `update_cache` are
+    /// counted as hits or misses, since the others only probe the cache while
+    /// a pastset is recomputed.
     pub fn get(&mut self, me: usize, update_cache: bool) -> Option<&BitSet> {
         if let Some(v) = self.cache.get_mut(&me) {
             if update_cache {
+                PASTSET_CACHE_HIT.mark(1);
                 v.1 = self.entry;
                 self.entry += 1;
             }
             Some(&v.0)
         } else {
+            if update_cache {
+                PASTSET_CACHE_MISS.mark(1);
+            }
             None
         }
     }
@@ -56,3 +85,48 @@ impl PastSetCache {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PastSetCache;
+    use hibitset::BitSet;
+    use std::collections::HashSet;
+
+    fn bitset(indices: &[u32]) -> BitSet {
+        let mut set = BitSet::new();
+        for index in indices {
+            set.add(*index);
+        }
+        set
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let mut cache = PastSetCache::new(2);
+        cache.update(1, bitset(&[0]));
+        cache.update(2, bitset(&[0, 1]));
+        // Only the lookups updating the cache refresh the entries.
+        assert!(cache.get(2, false).is_some());
+        assert!(cache.get(1, true).is_some());
+
+        cache.update(3, bitset(&[0, 1, 2]));
+        assert!(cache.get(2, false).is_none());
+        assert!(cache.get(1, false).is_some());
+        assert!(cache.get(3, false).is_some());
+    }
+
+    #[test]
+    fn test_intersect_update() {
+        let mut cache = PastSetCache::new(10);
+        cache.update(1, bitset(&[0]));
+        cache.update(2, bitset(&[0, 1]));
+        let outside_era_blockset: HashSet<usize> =
+            [0, 1].iter().cloned().collect();
+        cache.intersect_update(&outside_era_blockset);
+
+        assert!(cache.get(1, false).is_none());
+        let pastset = cache.get(2, false).unwrap();
+        assert!(!pastset.contains(0));
+        assert!(!pastset.contains(1));
+    }
+}
@@ -0,0 +1,382 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use metrics::{Histogram, Sample};
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// The call site of the lock acquisitions which are not labeled.
+pub const UNLABELED_SITE: &str = "unlabeled";
+
+/// A lock held longer than this is logged with a warning, along with the
+/// backtrace of the holder if the `lock_backtrace` feature is enabled.
+const LONG_HOLD_WARN_THRESHOLD: Duration = Duration::from_millis(500);
+
+/// A `RwLock` which records the time spent waiting for and holding the lock
+/// at each call site, so that the contention on the lock can be attributed to
+/// the code paths. The call site is given by the `*_at` methods, and the other
+/// methods are recorded as `UNLABELED_SITE`. Nothing is recorded unless the
+/// profiling is enabled.
+pub struct ProfiledRwLock<T> {
+    name: &'static str,
+    lock: RwLock<T>,
+    enabled: bool,
+    sites: RwLock<HashMap<&'static str, Arc<LockSiteStats>>>,
+}
+
+struct LockSiteStats {
+    acquisitions: AtomicU64,
+    /// The number of acquisitions which have to wait for the lock.
+    contended: AtomicU64,
+    total_wait_ns: AtomicU64,
+    max_wait_ns: AtomicU64,
+    total_hold_ns: AtomicU64,
+    max_hold_ns: AtomicU64,
+    wait_histogram: Arc<dyn Histogram>,
+}
+
+/// The lock statistics of a call site.
+#[derive(Clone, Debug)]
+pub struct LockSiteReport {
+    pub site: &'static str,
+    pub acquisitions: u64,
+    pub contended: u64,
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+    pub total_hold: Duration,
+    pub max_hold: Duration,
+}
+
+impl LockSiteStats {
+    fn new(lock_name: &str, site: &str) -> Self {
+        LockSiteStats {
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            total_wait_ns: AtomicU64::new(0),
+            max_wait_ns: AtomicU64::new(0),
+            total_hold_ns: AtomicU64::new(0),
+            max_hold_ns: AtomicU64::new(0),
+            wait_histogram: Sample::ExpDecay(0.015).register_with_group(
+                lock_name,
+                &format!("{}::wait_ns", site),
+                1024,
+            ),
+        }
+    }
+
+    fn record_wait(&self, wait: Option<Duration>) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if let Some(wait) = wait {
+            let wait_ns = wait.as_nanos() as u64;
+            self.contended.fetch_add(1, Ordering::Relaxed);
+            self.total_wait_ns.fetch_add(wait_ns, Ordering::Relaxed);
+            update_max(&self.max_wait_ns, wait_ns);
+            self.wait_histogram.update(wait_ns);
+        }
+    }
+
+    fn record_hold(&self, hold: Duration) {
+        let hold_ns = hold.as_nanos() as u64;
+        self.total_hold_ns.fetch_add(hold_ns, Ordering::Relaxed);
+        update_max(&self.max_hold_ns, hold_ns);
+    }
+
+    fn report(&self, site: &'static str) -> LockSiteReport {
+        let load = |v: &AtomicU64| v.load(Ordering::Relaxed);
+        LockSiteReport {
+            site,
+            acquisitions: load(&self.acquisitions),
+            contended: load(&self.contended),
+            total_wait: Duration::from_nanos(load(&self.total_wait_ns)),
+            max_wait: Duration::from_nanos(load(&self.max_wait_ns)),
+            total_hold: Duration::from_nanos(load(&self.total_hold_ns)),
+            max_hold: Duration::from_nanos(load(&self.max_hold_ns)),
+        }
+    }
+}
+
+fn update_max(max: &AtomicU64, value: u64) {
+    let mut current = max.load(Ordering::Relaxed);
+    while value > current {
+        match max.compare_exchange_weak(
+            current,
+            value,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => break,
+            Err(actual) => current = actual,
+        }
+    }
+}
+
+impl<T> ProfiledRwLock<T> {
+    /// `name` is used as the metrics group of the lock. The lock is a plain
+    /// `RwLock` if `enabled` is false.
+    pub fn new(name: &'static str, value: T, enabled: bool) -> Self {
+        ProfiledRwLock {
+            name,
+            lock: RwLock::new(value),
+            enabled,
+            sites: Default::default(),
+        }
+    }
+
+    pub fn read(&self) -> ProfiledReadGuard<T> { self.read_at(UNLABELED_SITE) }
+
+    pub fn read_recursive(&self) -> ProfiledReadGuard<T> {
+        self.read_recursive_at(UNLABELED_SITE)
+    }
+
+    pub fn write(&self) -> ProfiledWriteGuard<T> {
+        self.write_at(UNLABELED_SITE)
+    }
+
+    pub fn try_write(&self) -> Option<ProfiledWriteGuard<T>> {
+        self.try_write_at(UNLABELED_SITE)
+    }
+
+    pub fn read_at(&self, site: &'static str) -> ProfiledReadGuard<T> {
+        if !self.enabled {
+            return ProfiledReadGuard {
+                guard: self.lock.read(),
+                _hold: None,
+            };
+        }
+        let guard = match self.lock.try_read() {
+            Some(guard) => (guard, None),
+            None => {
+                let start = Instant::now();
+                (self.lock.read(), Some(start.elapsed()))
+            }
+        };
+        ProfiledReadGuard {
+            guard: guard.0,
+            _hold: self.hold_timer(site, guard.1),
+        }
+    }
+
+    pub fn read_recursive_at(
+        &self, site: &'static str,
+    ) -> ProfiledReadGuard<T> {
+        if !self.enabled {
+            return ProfiledReadGuard {
+                guard: self.lock.read_recursive(),
+                _hold: None,
+            };
+        }
+        let guard = match self.lock.try_read_recursive() {
+            Some(guard) => (guard, None),
+            None => {
+                let start = Instant::now();
+                (self.lock.read_recursive(), Some(start.elapsed()))
+            }
+        };
+        ProfiledReadGuard {
+            guard: guard.0,
+            _hold: self.hold_timer(site, guard.1),
+        }
+    }
+
+    pub fn write_at(&self, site: &'static str) -> ProfiledWriteGuard<T> {
+        if !self.enabled {
+            return ProfiledWriteGuard {
+                guard: self.lock.write(),
+                _hold: None,
+            };
+        }
+        let guard = match self.lock.try_write() {
+            Some(guard) => (guard, None),
+            None => {
+                let start = Instant::now();
+                (self.lock.write(), Some(start.elapsed()))
+            }
+        };
+        ProfiledWriteGuard {
+            guard: guard.0,
+            _hold: self.hold_timer(site, guard.1),
+        }
+    }
+
+    pub fn try_write_at(
+        &self, site: &'static str,
+    ) -> Option<ProfiledWriteGuard<T>> {
+        let guard = self.lock.try_write()?;
+        Some(ProfiledWriteGuard {
+            guard,
+            _hold: self.hold_timer(site, None),
+        })
+    }
+
+    /// The statistics of the call sites, the most contended first, i.e. in
+    /// the descending order of the total time waiting for the lock. It's
+    /// empty if the profiling is disabled.
+    pub fn contention_report(&self) -> Vec<LockSiteReport> {
+        let mut reports: Vec<LockSiteReport> = self
+            .sites
+            .read()
+            .iter()
+            .map(|(site, stats)| stats.report(site))
+            .collect();
+        reports.sort_by(|a, b| {
+            b.total_wait
+                .cmp(&a.total_wait)
+                .then_with(|| b.total_hold.cmp(&a.total_hold))
+        });
+        reports
+    }
+
+    fn site_stats(&self, site: &'static str) -> Arc<LockSiteStats> {
+        if let Some(stats) = self.sites.read().get(site) {
+            return stats.clone();
+        }
+        self.sites
+            .write()
+            .entry(site)
+            .or_insert_with(|| Arc::new(LockSiteStats::new(self.name, site)))
+            .clone()
+    }
+
+    fn hold_timer(
+        &self, site: &'static str, wait: Option<Duration>,
+    ) -> Option<HoldTimer> {
+        if !self.enabled {
+            return None;
+        }
+        let stats = self.site_stats(site);
+        stats.record_wait(wait);
+        Some(HoldTimer {
+            lock_name: self.name,
+            site,
+            stats,
+            acquired: Instant::now(),
+        })
+    }
+}
+
+/// Records the time holding the lock when dropped.
+struct HoldTimer {
+    lock_name: &'static str,
+    site: &'static str,
+    stats: Arc<LockSiteStats>,
+    acquired: Instant,
+}
+
+impl Drop for HoldTimer {
+    fn drop(&mut self) {
+        let hold = self.acquired.elapsed();
+        self.stats.record_hold(hold);
+        if hold >= LONG_HOLD_WARN_THRESHOLD {
+            warn!(
+                "{} held for {:?} at {}{}",
+                self.lock_name,
+                hold,
+                self.site,
+                holder_backtrace()
+            );
+        }
+    }
+}
+
+#[cfg(feature = "lock_backtrace")]
+fn holder_backtrace() -> String {
+    format!(", backtrace:\n{:?}", backtrace::Backtrace::new())
+}
+
+#[cfg(not(feature = "lock_backtrace"))]
+fn holder_backtrace() -> String { String::new() }
+
+pub struct ProfiledReadGuard<'a, T> {
+    guard: RwLockReadGuard<'a, T>,
+    /// Dropped after `guard`, i.e. after the lock is released.
+    _hold: Option<HoldTimer>,
+}
+
+pub struct ProfiledWriteGuard<'a, T> {
+    guard: RwLockWriteGuard<'a, T>,
+    _hold: Option<HoldTimer>,
+}
+
+impl<'a, T> Deref for ProfiledReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T { &*self.guard }
+}
+
+impl<'a, T> Deref for ProfiledWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T { &*self.guard }
+}
+
+impl<'a, T> DerefMut for ProfiledWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T { &mut *self.guard }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProfiledRwLock, UNLABELED_SITE};
+    use std::{sync::Arc, thread, time::Duration};
+
+    #[test]
+    fn test_disabled() {
+        let lock = ProfiledRwLock::new("test_disabled_lock", 0, false);
+        *lock.write_at("write") += 1;
+        assert_eq!(*lock.read_at("read"), 1);
+        assert!(lock.try_write().is_some());
+        assert!(lock.contention_report().is_empty());
+    }
+
+    #[test]
+    fn test_sites() {
+        let lock = ProfiledRwLock::new("test_sites_lock", 0, true);
+        for _ in 0..2 {
+            *lock.write_at("write") += 1;
+        }
+        assert_eq!(*lock.read(), 2);
+
+        let mut report = lock.contention_report();
+        report.sort_by_key(|site| site.site);
+        assert_eq!(
+            report
+                .iter()
+                .map(|site| (site.site, site.acquisitions, site.contended))
+                .collect::<Vec<_>>(),
+            vec![(UNLABELED_SITE, 1, 0), ("write", 2, 0)]
+        );
+    }
+
+    #[test]
+    fn test_contention() {
+        let lock =
+            Arc::new(ProfiledRwLock::new("test_contention_lock", 0, true));
+        let hold = Duration::from_millis(50);
+        let guard = lock.write_at("holder");
+        let waiter = {
+            let lock = lock.clone();
+            thread::spawn(move || *lock.write_at("waiter") += 1)
+        };
+        thread::sleep(hold);
+        drop(guard);
+        waiter.join().unwrap();
+
+        // The most contended site comes first.
+        let report = lock.contention_report();
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].site, "waiter");
+        assert_eq!(report[0].contended, 1);
+        assert!(report[0].max_wait > Duration::from_millis(0));
+        assert_eq!(report[1].site, "holder");
+        assert_eq!(report[1].contended, 0);
+        assert!(report[1].max_hold >= hold);
+    }
+}
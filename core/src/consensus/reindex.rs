@@ -0,0 +1,137 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::ConsensusGraph;
+use crate::storage::{state_manager::StateManagerTrait, SnapshotAndEpochIdRef};
+use primitives::BlockHeaderBuilder;
+
+/// How the indexes of an epoch are rebuilt by
+/// `ConsensusGraph::reindex_epoch`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReindexedEpoch {
+    /// The transaction addresses are rebuilt from the persisted receipts.
+    Replayed { transactions: usize },
+    /// The receipts are missing, so they are recomputed by executing the
+    /// epoch again on the state of its parent without committing it, and
+    /// persisted with the transaction addresses after they are checked
+    /// against the receipts root committed by the pivot chain.
+    Reexecuted,
+    /// The epoch set is not recorded, or the receipts are missing and the
+    /// epoch cannot be executed again, since it is not in the consensus
+    /// graph or the state of its parent is not available.
+    Unavailable,
+}
+
+impl ConsensusGraph {
+    /// Rebuild the transaction addresses and the receipts of the blocks in
+    /// the persisted epoch `epoch_number`. The receipts are replayed if they
+    /// are persisted for the epoch, and otherwise recomputed by executing
+    /// the epoch again if possible. The transaction addresses are only
+    /// written if `record_tx_address` is enabled. It fails if the recomputed
+    /// receipts mismatch the receipts root committed for the epoch.
+    pub fn reindex_epoch(
+        &self, epoch_number: u64,
+    ) -> Result<ReindexedEpoch, String> {
+        let block_hashes =
+            match self.data_man.epoch_set_hashes_from_db(epoch_number) {
+                Some(hashes) => hashes,
+                None => return Ok(ReindexedEpoch::Unavailable),
+            };
+        let epoch_hash = *block_hashes
+            .last()
+            .ok_or_else(|| format!("Epoch {} is empty", epoch_number))?;
+
+        let receipts = block_hashes
+            .iter()
+            .map(|hash| {
+                self.data_man
+                    .block_execution_result_by_hash_with_epoch(
+                        hash,
+                        &epoch_hash,
+                        false, /* update_cache */
+                    )
+                    .map(|result| result.receipts)
+            })
+            .collect::<Option<Vec<_>>>();
+        if let Some(receipts) = receipts {
+            let transactions = self.data_man.recover_transaction_addresses(
+                &epoch_hash,
+                &block_hashes,
+                &receipts,
+                false, /* update_cache */
+            )?;
+            return Ok(ReindexedEpoch::Replayed { transactions });
+        }
+
+        let parent_hash = *self
+            .data_man
+            .block_header_by_hash(&epoch_hash)
+            .ok_or_else(|| format!("Missing block header {:?}", epoch_hash))?
+            .parent_hash();
+        let parent_state_available = self
+            .data_man
+            .storage_manager
+            .contains_state(SnapshotAndEpochIdRef::new(&parent_hash, None))
+            .map_err(|e| format!("Failed to read the state: {:?}", e))?;
+        if !parent_state_available {
+            return Ok(ReindexedEpoch::Unavailable);
+        }
+        let (start_block_number, deferred_hash) = {
+            let inner = self.inner.read();
+            // The epoch set of the era genesis is not in the consensus graph.
+            if epoch_number <= inner.get_cur_era_genesis_height()
+                || inner.get_hash_from_epoch_number(epoch_number)
+                    != Ok(epoch_hash)
+            {
+                return Ok(ReindexedEpoch::Unavailable);
+            }
+            // The receipts root of the epoch is committed by the pivot block
+            // `deferred_state_epoch_count` epochs later.
+            let deferred_hash = match inner.get_hash_from_epoch_number(
+                epoch_number + self.deferred_state_epoch_count(),
+            ) {
+                Ok(hash) => hash,
+                Err(_) => return Ok(ReindexedEpoch::Unavailable),
+            };
+            let arena_index = inner.hash_to_arena_indices[&epoch_hash];
+            (
+                inner.get_epoch_start_block_number(arena_index),
+                deferred_hash,
+            )
+        };
+        let committed_root = *self
+            .data_man
+            .block_header_by_hash(&deferred_hash)
+            .ok_or_else(|| format!("Missing block header {:?}", deferred_hash))?
+            .deferred_receipts_root();
+
+        let receipts = self
+            .executor
+            .recompute_epoch_receipts(&block_hashes, start_block_number)?;
+        let computed_root =
+            BlockHeaderBuilder::compute_block_receipts_root(&receipts);
+        if computed_root != committed_root {
+            return Err(format!(
+                "Recomputed receipts root {:?} of epoch {} mismatches the committed {:?}",
+                computed_root, epoch_number, committed_root
+            ));
+        }
+
+        for (hash, block_receipts) in block_hashes.iter().zip(&receipts) {
+            self.data_man.insert_block_results(
+                *hash,
+                epoch_hash,
+                block_receipts.clone(),
+                true, /* persistent */
+            );
+        }
+        self.data_man.recover_transaction_addresses(
+            &epoch_hash,
+            &block_hashes,
+            &receipts,
+            false, /* update_cache */
+        )?;
+        Ok(ReindexedEpoch::Reexecuted)
+    }
+}
@@ -0,0 +1,244 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::ConsensusGraph;
+use crate::block_data_manager::db_manager::DBManager;
+use cfx_types::H256;
+use primitives::{BlockHeader, BlockHeaderBuilder};
+use std::{collections::HashSet, sync::Arc};
+
+/// The outcome of `ConsensusGraph::replay_from_db`.
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// The number of blocks inserted into the consensus graph.
+    pub replayed_blocks: usize,
+    /// The number of epochs whose pivot block, epoch set and state root are
+    /// compared with the recorded ones.
+    pub verified_epochs: u64,
+    /// Descriptions of the values that do not match the recorded ones.
+    pub mismatches: Vec<String>,
+}
+
+impl ReplayReport {
+    pub fn is_consistent(&self) -> bool { self.mismatches.is_empty() }
+
+    /// Compare the recomputed state roots with the deferred state root
+    /// committed in `witness`, the pivot block `deferred_state_epoch_count`
+    /// epochs after `epoch_number`. `roots[i]` is the recomputed state root of
+    /// epoch `epoch_number - i`, and the first `witness.blame() + 1` of them
+    /// are committed in the header.
+    fn check_state_root(
+        &mut self, epoch_number: u64, witness: &BlockHeader, roots: &[H256],
+    ) {
+        let blame = witness.blame() as usize;
+        if roots.len() <= blame {
+            self.mismatches.push(format!(
+                "Epoch {}: witness {:?} blames {} epochs before the era genesis",
+                epoch_number,
+                witness.hash(),
+                blame
+            ));
+            return;
+        }
+        let state_root = if blame == 0 {
+            roots[0]
+        } else {
+            BlockHeaderBuilder::compute_blame_state_root_vec_root(
+                roots[..=blame].to_vec(),
+            )
+        };
+        if state_root != *witness.deferred_state_root() {
+            self.mismatches.push(format!(
+                "Epoch {}: state root {:?} != committed {:?} in {:?}",
+                epoch_number,
+                state_root,
+                witness.deferred_state_root(),
+                witness.hash()
+            ));
+        }
+    }
+}
+
+impl ConsensusGraph {
+    /// Reprocess the blocks persisted in `source` from the era genesis up to
+    /// epoch `to` through the new block handler, and verify that the
+    /// recomputed pivot chain and epoch sets of the epochs in `[from, to]`
+    /// match the ones recorded in `source`, and that their recomputed state
+    /// roots match the deferred state roots committed in the headers.
+    ///
+    /// The consensus graph must be freshly constructed on a scratch database
+    /// with the same genesis as `source` and not connected to the network,
+    /// so that all the epochs are executed again while `source` is only
+    /// read. State roots are only verified for epochs at least
+    /// `deferred_state_epoch_count` epochs before `to`.
+    pub fn replay_from_db(
+        &self, source: &DBManager, from: u64, to: u64,
+    ) -> Result<ReplayReport, String> {
+        let (genesis_height, genesis_hash) = {
+            let inner = self.inner.read();
+            if inner.pivot_chain.len() != 1 {
+                return Err("Replay requires a fresh consensus graph".into());
+            }
+            (
+                inner.get_cur_era_genesis_height(),
+                inner.arena[inner.cur_era_genesis_block_arena_index].hash,
+            )
+        };
+        if from <= genesis_height || from > to {
+            return Err(format!(
+                "Invalid replay range [{}, {}], era genesis height is {}",
+                from, to, genesis_height
+            ));
+        }
+        if source.block_header_from_db(&genesis_hash).is_none() {
+            return Err(format!(
+                "Genesis {:?} is not recorded in the source database",
+                genesis_hash
+            ));
+        }
+
+        // The recorded epoch sets, indexed by `epoch_number - genesis_height`.
+        let mut recorded = vec![vec![genesis_hash]];
+        for epoch_number in genesis_height + 1..=to {
+            recorded.push(
+                source.epoch_set_hashes_from_db(epoch_number).ok_or_else(
+                    || format!("Epoch {} is not recorded", epoch_number),
+                )?,
+            );
+        }
+
+        let mut report = ReplayReport::default();
+        let mut inserted = HashSet::new();
+        for hash in recorded.iter().skip(1).flatten() {
+            if !inserted.insert(*hash) {
+                continue;
+            }
+            let block = source
+                .block_from_db(hash)
+                .ok_or_else(|| format!("Block {:?} is not persisted", hash))?;
+            self.data_man
+                .insert_block(Arc::new(block), true /* persistent */);
+            self.on_new_block(hash, false /* ignore_body */);
+            report.replayed_blocks += 1;
+        }
+
+        let deferred_state_epoch_count = self.deferred_state_epoch_count();
+        // The recomputed state roots, indexed like `recorded`.
+        let mut state_roots = Vec::new();
+        for epoch_number in genesis_height..=to {
+            let block_hashes = if epoch_number == genesis_height {
+                vec![genesis_hash]
+            } else {
+                match self.inner.read().block_hashes_by_epoch(epoch_number) {
+                    Ok(hashes) => hashes,
+                    Err(e) => {
+                        report
+                            .mismatches
+                            .push(format!("Epoch {}: {}", epoch_number, e));
+                        break;
+                    }
+                }
+            };
+            let offset = (epoch_number - genesis_height) as usize;
+            if epoch_number >= from {
+                let recorded = &recorded[offset];
+                if block_hashes.last() != recorded.last() {
+                    report.mismatches.push(format!(
+                        "Epoch {}: pivot block {:?} != recorded {:?}",
+                        epoch_number,
+                        block_hashes.last(),
+                        recorded.last()
+                    ));
+                } else if block_hashes != *recorded {
+                    report.mismatches.push(format!(
+                        "Epoch {}: epoch set {:?} != recorded {:?}",
+                        epoch_number, block_hashes, recorded
+                    ));
+                }
+                report.verified_epochs += 1;
+            }
+
+            // The execution of an epoch is triggered within the replay only
+            // if its deferred pivot block is not after `to`.
+            if epoch_number + deferred_state_epoch_count > to {
+                continue;
+            }
+            let pivot = *block_hashes.last().expect("epoch set not empty");
+            state_roots.push(
+                self.executor
+                    .wait_for_result(pivot)
+                    .0
+                    .state_root
+                    .compute_state_root_hash(),
+            );
+            if epoch_number < from {
+                continue;
+            }
+            let witness_hash = recorded
+                [offset + deferred_state_epoch_count as usize]
+                .last()
+                .cloned()
+                .expect("epoch set not empty");
+            let witness =
+                source.block_header_from_db(&witness_hash).ok_or_else(
+                    || format!("Header {:?} is not persisted", witness_hash),
+                )?;
+            let roots: Vec<_> = state_roots.iter().rev().cloned().collect();
+            report.check_state_root(epoch_number, &witness, &roots);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplayReport;
+    use cfx_types::H256;
+    use primitives::BlockHeaderBuilder;
+
+    #[test]
+    fn test_check_state_root() {
+        let roots = vec![
+            H256::from_low_u64_be(3),
+            H256::from_low_u64_be(2),
+            H256::from_low_u64_be(1),
+        ];
+
+        let mut report = ReplayReport::default();
+        let witness = BlockHeaderBuilder::new()
+            .with_deferred_state_root(roots[0])
+            .build();
+        report.check_state_root(10, &witness, &roots);
+        assert!(report.is_consistent());
+
+        // A witness blaming its parent commits the roots of two epochs.
+        let witness = BlockHeaderBuilder::new()
+            .with_blame(1)
+            .with_deferred_state_root(
+                BlockHeaderBuilder::compute_blame_state_root_vec_root(
+                    roots[..2].to_vec(),
+                ),
+            )
+            .build();
+        report.check_state_root(10, &witness, &roots);
+        assert!(report.is_consistent());
+
+        // A corrupted root is reported.
+        let mut corrupted = roots.clone();
+        corrupted[1] = H256::from_low_u64_be(4);
+        report.check_state_root(10, &witness, &corrupted);
+        assert_eq!(report.mismatches.len(), 1);
+
+        let witness = BlockHeaderBuilder::new()
+            .with_deferred_state_root(roots[1])
+            .build();
+        report.check_state_root(10, &witness, &roots);
+        assert_eq!(report.mismatches.len(), 2);
+
+        // The blamed epochs must be recomputed.
+        let witness = BlockHeaderBuilder::new().with_blame(3).build();
+        report.check_state_root(10, &witness, &roots);
+        assert_eq!(report.mismatches.len(), 3);
+    }
+}
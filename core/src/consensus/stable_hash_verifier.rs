@@ -0,0 +1,424 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::ConsensusGraph;
+use cfx_types::H256;
+use primitives::BlockHeader;
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+/// The number of the peers whose checkpoints must agree with the stable hash
+/// recovered from the database before the pivot chain is forced to it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StableHashTrustPolicy {
+    /// At least one peer agrees.
+    Any,
+    /// More peers agree than conflict.
+    Majority,
+    /// At least one peer agrees and no peer conflicts.
+    All,
+}
+
+impl FromStr for StableHashTrustPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "any" => Ok(StableHashTrustPolicy::Any),
+            "majority" => Ok(StableHashTrustPolicy::Majority),
+            "all" => Ok(StableHashTrustPolicy::All),
+            _ => Err(format!("Unknown stable hash trust policy {}", s)),
+        }
+    }
+}
+
+impl StableHashTrustPolicy {
+    fn is_satisfied(&self, agreed: usize, conflicted: usize) -> bool {
+        match self {
+            StableHashTrustPolicy::Any => agreed > 0,
+            StableHashTrustPolicy::Majority => agreed > conflicted,
+            StableHashTrustPolicy::All => agreed > 0 && conflicted == 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StableHashVerificationConfig {
+    pub trust_policy: StableHashTrustPolicy,
+    /// The number of the peers whose checkpoints are waited for before the
+    /// recovery from the database. 0 disables the cross-check with peers.
+    pub min_peers: usize,
+    /// The longest time to wait for the checkpoints of `min_peers` peers.
+    pub timeout: Duration,
+}
+
+impl Default for StableHashVerificationConfig {
+    fn default() -> Self {
+        StableHashVerificationConfig {
+            trust_policy: StableHashTrustPolicy::Majority,
+            min_peers: 0,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum PeerCheckpoint {
+    Agreed,
+    Conflicted,
+    /// The checkpoint is unknown locally or before the current era.
+    Unverifiable,
+}
+
+/// Return the pivot blocks from the era genesis to `stable`, after checking
+/// that they are linked by `header_by_hash` without blaming the era genesis.
+fn stable_ancestors<F>(
+    stable: &H256, stable_height: u64, era_genesis_hash: &H256,
+    era_genesis_height: u64, header_by_hash: F,
+) -> Result<Vec<H256>, String>
+where
+    F: Fn(&H256) -> Option<Arc<BlockHeader>>,
+{
+    let mut ancestors = Vec::new();
+    let mut hash = *stable;
+    let mut height = stable_height;
+    loop {
+        let header = header_by_hash(&hash).ok_or_else(|| {
+            format!("Header of ancestor {:?} not found", hash)
+        })?;
+        if header.hash() != hash || header.height() != height {
+            return Err(format!(
+                "Ancestor {:?} of height {} is corrupted: hash={:?} height={}",
+                hash,
+                height,
+                header.hash(),
+                header.height()
+            ));
+        }
+        if height > era_genesis_height
+            && height.saturating_sub(header.blame() as u64)
+                <= era_genesis_height
+        {
+            return Err(format!(
+                "Ancestor {:?} blames the era genesis {:?}",
+                hash, era_genesis_hash
+            ));
+        }
+        ancestors.push(hash);
+        if height <= era_genesis_height {
+            break;
+        }
+        hash = *header.parent_hash();
+        height -= 1;
+    }
+    if hash != *era_genesis_hash {
+        return Err(format!(
+            "Ancestor {:?} mismatches the era genesis {:?}",
+            hash, era_genesis_hash
+        ));
+    }
+    ancestors.reverse();
+    Ok(ancestors)
+}
+
+/// A checkpoint agrees if it is on the chain `ancestors` from the era genesis
+/// to the stable block, or if the stable block is its ancestor.
+fn check_peer_checkpoint<F>(
+    checkpoint: &H256, ancestors: &[H256], era_genesis_height: u64,
+    header_by_hash: F,
+) -> PeerCheckpoint
+where
+    F: Fn(&H256) -> Option<Arc<BlockHeader>>,
+{
+    let mut header = match header_by_hash(checkpoint) {
+        Some(header) => header,
+        None => return PeerCheckpoint::Unverifiable,
+    };
+    if header.height() < era_genesis_height {
+        return PeerCheckpoint::Unverifiable;
+    }
+
+    let offset = (header.height() - era_genesis_height) as usize;
+    if let Some(ancestor) = ancestors.get(offset) {
+        return if ancestor == checkpoint {
+            PeerCheckpoint::Agreed
+        } else {
+            PeerCheckpoint::Conflicted
+        };
+    }
+
+    let stable_height = era_genesis_height + ancestors.len() as u64 - 1;
+    while header.height() > stable_height {
+        header = match header_by_hash(header.parent_hash()) {
+            Some(header) => header,
+            None => return PeerCheckpoint::Unverifiable,
+        };
+    }
+    if Some(&header.hash()) == ancestors.last() {
+        PeerCheckpoint::Agreed
+    } else {
+        PeerCheckpoint::Conflicted
+    }
+}
+
+/// Check the classified peer checkpoints against `policy`.
+fn check_trust_policy(
+    policy: StableHashTrustPolicy, checkpoints: &[PeerCheckpoint],
+) -> Result<(), String> {
+    let count =
+        |expected| checkpoints.iter().filter(|c| **c == expected).count();
+    let agreed = count(PeerCheckpoint::Agreed);
+    let conflicted = count(PeerCheckpoint::Conflicted);
+    if checkpoints.is_empty() {
+        return Err("No peer reported its checkpoint in time".into());
+    }
+    if agreed == 0 && conflicted == 0 {
+        // All the checkpoints are newer than the local database, which is
+        // expected after a long downtime. The peers can neither confirm nor
+        // refute the stable block, so it's only trusted by its ancestors.
+        warn!(
+            "The checkpoints of all the {} peers are unknown locally, trust \
+             the stable hash verified against its ancestors",
+            checkpoints.len()
+        );
+        return Ok(());
+    }
+    if policy.is_satisfied(agreed, conflicted) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Trust policy {:?} is not satisfied: {} peers agreed and {} peers conflicted",
+            policy, agreed, conflicted
+        ))
+    }
+}
+
+impl ConsensusGraph {
+    pub fn stable_hash_verification_config(
+        &self,
+    ) -> &StableHashVerificationConfig {
+        &self.config.stable_hash_verification
+    }
+
+    /// Set the checkpoints reported by the peers, which are cross-checked
+    /// with the stable hash when it is recovered.
+    pub fn set_peer_checkpoints(&self, checkpoints: Vec<H256>) {
+        *self.peer_checkpoints.lock() = Some(checkpoints);
+    }
+
+    /// Whether the peer checkpoints are already collected, so that the
+    /// recovery of the blocks after the recovery of the headers doesn't wait
+    /// for them again.
+    pub fn has_peer_checkpoints(&self) -> bool {
+        self.peer_checkpoints.lock().is_some()
+    }
+
+    /// Verify the stable hash of the current era recovered from the database,
+    /// before the recovery forces the pivot chain to it. Its ancestors back
+    /// to the era genesis must be linked in the database without blaming the
+    /// era genesis, and the checkpoints reported by the peers must agree with
+    /// it as required by the trust policy. The headers are read without
+    /// holding the consensus lock, and the result is remembered for
+    /// `is_stable_hash_trusted`.
+    pub fn verify_recovered_stable_hash(&self) {
+        let stable = self.data_man.get_cur_consensus_era_stable_hash();
+        let result = self.verify_stable_hash(&stable);
+        if let Err(ref e) = result {
+            error!(
+                "Stable hash {:?} is not trusted, do not force the pivot chain to it: {}",
+                stable, e
+            );
+        }
+        *self.verified_stable_hash.lock() = Some((stable, result.is_ok()));
+    }
+
+    /// Whether `stable` is verified by `verify_recovered_stable_hash`.
+    pub(super) fn is_stable_hash_trusted(&self, stable: &H256) -> bool {
+        *self.verified_stable_hash.lock() == Some((*stable, true))
+    }
+
+    fn verify_stable_hash(&self, stable: &H256) -> Result<(), String> {
+        let (era_genesis_hash, era_genesis_height, stable_height) = {
+            let inner = self.inner.read();
+            (
+                inner.arena[inner.cur_era_genesis_block_arena_index].hash,
+                inner.get_cur_era_genesis_height(),
+                inner.get_cur_era_stable_height(),
+            )
+        };
+        let header_by_hash =
+            |hash: &H256| self.data_man.block_header_by_hash(hash);
+        let ancestors = stable_ancestors(
+            stable,
+            stable_height,
+            &era_genesis_hash,
+            era_genesis_height,
+            header_by_hash,
+        )?;
+
+        let conf = &self.config.stable_hash_verification;
+        if conf.min_peers == 0 {
+            return Ok(());
+        }
+        let checkpoints: Vec<_> = self
+            .peer_checkpoints
+            .lock()
+            .iter()
+            .flatten()
+            .map(|checkpoint| {
+                check_peer_checkpoint(
+                    checkpoint,
+                    &ancestors,
+                    era_genesis_height,
+                    header_by_hash,
+                )
+            })
+            .collect();
+        debug!(
+            "Stable hash {:?} checked against peer checkpoints {:?}",
+            stable, checkpoints
+        );
+        check_trust_policy(conf.trust_policy, &checkpoints)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_peer_checkpoint, check_trust_policy, stable_ancestors,
+        PeerCheckpoint, StableHashTrustPolicy,
+    };
+    use cfx_types::H256;
+    use primitives::{BlockHeader, BlockHeaderBuilder};
+    use std::{collections::HashMap, sync::Arc};
+
+    /// Return the headers of a chain of `len` blocks forking from `parent` at
+    /// `height`, keyed by their hashes, and the hashes in order.
+    fn chain(
+        parent: H256, height: u64, len: u64, nonce: u64,
+    ) -> (HashMap<H256, Arc<BlockHeader>>, Vec<H256>) {
+        let mut headers = HashMap::new();
+        let mut hashes = Vec::new();
+        let mut parent = parent;
+        for i in 0..len {
+            let mut header = BlockHeaderBuilder::new()
+                .with_parent_hash(parent)
+                .with_height(height + i)
+                .with_nonce(nonce)
+                .build();
+            header.compute_hash();
+            parent = header.hash();
+            hashes.push(parent);
+            headers.insert(parent, Arc::new(header));
+        }
+        (headers, hashes)
+    }
+
+    #[test]
+    fn test_trust_policy_is_satisfied() {
+        use StableHashTrustPolicy::*;
+        assert!(Any.is_satisfied(1, 5));
+        assert!(!Any.is_satisfied(0, 0));
+        assert!(Majority.is_satisfied(3, 2));
+        assert!(!Majority.is_satisfied(2, 2));
+        assert!(All.is_satisfied(1, 0));
+        assert!(!All.is_satisfied(5, 1));
+        assert!(!All.is_satisfied(0, 0));
+    }
+
+    #[test]
+    fn test_stable_ancestors() {
+        let (headers, hashes) = chain(H256::zero(), 10, 5, 0);
+        let header_by_hash = |hash: &H256| headers.get(hash).cloned();
+        assert_eq!(
+            stable_ancestors(&hashes[4], 14, &hashes[0], 10, header_by_hash),
+            Ok(hashes.clone())
+        );
+        // The recorded stable height mismatches the header.
+        assert!(stable_ancestors(
+            &hashes[4],
+            15,
+            &hashes[0],
+            10,
+            header_by_hash
+        )
+        .is_err());
+        // Another era genesis.
+        assert!(stable_ancestors(
+            &hashes[4],
+            14,
+            &hashes[1],
+            10,
+            header_by_hash
+        )
+        .is_err());
+
+        // A corrupted ancestor, whose header is stored under another hash.
+        let mut corrupted = headers.clone();
+        corrupted.insert(hashes[2], headers[&hashes[1]].clone());
+        assert!(stable_ancestors(&hashes[4], 14, &hashes[0], 10, |hash| {
+            corrupted.get(hash).cloned()
+        })
+        .is_err());
+
+        // A missing ancestor.
+        let mut missing = headers.clone();
+        missing.remove(&hashes[3]);
+        assert!(stable_ancestors(&hashes[4], 14, &hashes[0], 10, |hash| {
+            missing.get(hash).cloned()
+        })
+        .is_err());
+
+        // An ancestor blaming the era genesis.
+        let mut blamed = headers.clone();
+        let mut header = BlockHeaderBuilder::new()
+            .with_parent_hash(hashes[0])
+            .with_height(11)
+            .with_blame(1)
+            .build();
+        header.compute_hash();
+        let blaming = header.hash();
+        blamed.insert(blaming, Arc::new(header));
+        assert!(stable_ancestors(&blaming, 11, &hashes[0], 10, |hash| {
+            blamed.get(hash).cloned()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_check_peer_checkpoint() {
+        let (mut headers, hashes) = chain(H256::zero(), 10, 8, 0);
+        // The stable block is at height 13, and blocks 14..=17 extend it.
+        let ancestors = &hashes[..4];
+        let (fork, fork_hashes) = chain(hashes[1], 12, 4, 1);
+        headers.extend(fork);
+        let (old, old_hashes) = chain(H256::zero(), 5, 1, 2);
+        headers.extend(old);
+        let header_by_hash = |hash: &H256| headers.get(hash).cloned();
+        let check =
+            |hash| check_peer_checkpoint(hash, ancestors, 10, header_by_hash);
+
+        assert_eq!(check(&hashes[2]), PeerCheckpoint::Agreed);
+        assert_eq!(check(&hashes[7]), PeerCheckpoint::Agreed);
+        assert_eq!(check(&fork_hashes[0]), PeerCheckpoint::Conflicted);
+        assert_eq!(check(&fork_hashes[3]), PeerCheckpoint::Conflicted);
+        assert_eq!(check(&old_hashes[0]), PeerCheckpoint::Unverifiable);
+        assert_eq!(
+            check(&H256::from_low_u64_be(1)),
+            PeerCheckpoint::Unverifiable
+        );
+    }
+
+    #[test]
+    fn test_check_trust_policy() {
+        use PeerCheckpoint::*;
+        use StableHashTrustPolicy::*;
+        assert!(check_trust_policy(Majority, &[]).is_err());
+        // All the checkpoints are newer than the database.
+        assert!(check_trust_policy(All, &[Unverifiable, Unverifiable]).is_ok());
+        assert!(check_trust_policy(Majority, &[Agreed, Unverifiable]).is_ok());
+        assert!(check_trust_policy(Majority, &[Agreed, Conflicted]).is_err());
+        assert!(check_trust_policy(Any, &[Agreed, Conflicted]).is_ok());
+        assert!(check_trust_policy(All, &[Conflicted, Unverifiable]).is_err());
+    }
+}
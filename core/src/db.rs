@@ -37,8 +37,16 @@ pub const COL_BLOCKS: Option<u32> = Some(2);
 pub const COL_TX_ADDRESS: Option<u32> = Some(3);
 /// Column for Transaction Index
 pub const COL_EPOCH_NUMBER: Option<u32> = Some(4);
+/// Column for verified witness roots of light nodes
+pub const COL_LIGHT_WITNESSES: Option<u32> = Some(5);
+/// Column for the blame verification outcomes of pivot blocks
+pub const COL_BLAME_STATE_VALID: Option<u32> = Some(6);
+/// Column for the archived state changes and snapshots of archive nodes
+pub const COL_ARCHIVE_STATE: Option<u32> = Some(7);
+/// Column for the logs bloom index of pivot chain epochs
+pub const COL_BLOOM_INDEX: Option<u32> = Some(8);
 /// Number of columns in DB
-pub const NUM_COLUMNS: Option<u32> = Some(5);
+pub const NUM_COLUMNS: Option<u32> = Some(9);
 
 /// Modes for updating caches.
 #[derive(Clone, Copy)]
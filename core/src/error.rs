@@ -48,6 +48,8 @@ pub enum BlockError {
     UnknownParent(H256),
     /// Duplicate parent or referee hashes exist.
     DuplicateParentOrRefereeHashes(H256),
+    /// Blame header field blames the blocks at or before the genesis block.
+    InvalidBlame(OutOfBounds<u32>),
 }
 
 impl fmt::Display for BlockError {
@@ -100,6 +102,7 @@ impl fmt::Display for BlockError {
             DuplicateParentOrRefereeHashes(ref hash) => {
                 format!("Duplicate parent or referee hashes: {}", hash)
             }
+            InvalidBlame(ref oob) => format!("Invalid blame: {}", oob),
         };
 
         f.write_fmt(format_args!("Block error ({})", msg))
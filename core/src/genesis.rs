@@ -2,16 +2,304 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
+use crate::{
+    builtin::{
+        builtin_factory, AltBn128PairingPricer, Builtin, Linear, ModexpPricer,
+        Pricer, BUILTIN_NAMES,
+    },
+    machine::{new_machine_with_builtin, new_machine_with_builtins, Machine},
+};
 use cfx_types::{Address, U256};
 use keylib::KeyPair;
+use rustc_hex::FromHex;
 use secret_store::SecretStore;
+use serde_derive::Deserialize;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fs::File,
     io::{BufRead, BufReader, Read},
+    path::Path,
 };
 use toml::Value;
 
+/// An account created in the genesis state.
+#[derive(Clone, Debug, Default)]
+pub struct GenesisAccount {
+    pub balance: U256,
+    pub nonce: U256,
+    /// The code of a contract deployed in genesis.
+    pub code: Option<Vec<u8>>,
+}
+
+/// The genesis block and state of a network.
+#[derive(Clone, Debug)]
+pub struct GenesisSpec {
+    pub accounts: HashMap<Address, GenesisAccount>,
+    pub timestamp: u64,
+    pub difficulty: U256,
+    pub gas_limit: U256,
+    pub author: Address,
+    /// The `deferred_state_epoch_count` of the network, which the nodes
+    /// must be configured with if it's set.
+    pub deferred_state_epoch_count: Option<u64>,
+    /// The `chain_id` of the network, which the nodes must be configured with
+    /// if it's set.
+    pub chain_id: Option<u64>,
+    /// The built-in contracts of the network by their addresses, or `None`
+    /// for the default ones.
+    pub builtins: Option<BTreeMap<Address, BuiltinSpec>>,
+    /// The pivot block height from which the remaining transactions of a
+    /// block are not executed after an invalid one, or `None` for
+    /// `INVALID_TX_REJECT_HEIGHT`.
+    pub invalid_tx_reject_height: Option<u64>,
+}
+
+/// A built-in contract of the network.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuiltinSpec {
+    /// One of `builtin::BUILTIN_NAMES`.
+    pub name: String,
+    pub pricing: PricingSpec,
+    /// The block number from which the contract is active.
+    #[serde(default)]
+    pub activate_at: u64,
+}
+
+/// The gas pricing of a built-in contract.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum PricingSpec {
+    /// `base` plus `word` for each 32 bytes of the input.
+    Linear { base: usize, word: usize },
+    /// The pricing of EIP-198.
+    Modexp { divisor: usize },
+    /// `base` plus `pair` for each pair of points in the input.
+    AltBn128Pairing { base: usize, pair: usize },
+}
+
+impl BuiltinSpec {
+    fn builtin(&self) -> Builtin {
+        let pricer: Box<dyn Pricer> = match self.pricing {
+            PricingSpec::Linear { base, word } => {
+                Box::new(Linear::new(base, word))
+            }
+            PricingSpec::Modexp { divisor } => {
+                Box::new(ModexpPricer::new(divisor))
+            }
+            PricingSpec::AltBn128Pairing { base, pair } => {
+                Box::new(AltBn128PairingPricer::new(base, pair))
+            }
+        };
+        Builtin::new(pricer, builtin_factory(&self.name), self.activate_at)
+    }
+}
+
+impl GenesisSpec {
+    pub fn new(
+        balances: HashMap<Address, U256>, gas_limit: U256, author: Address,
+        difficulty: U256,
+    ) -> Self
+    {
+        GenesisSpec {
+            accounts: balances
+                .into_iter()
+                .map(|(address, balance)| {
+                    (
+                        address,
+                        GenesisAccount {
+                            balance,
+                            ..Default::default()
+                        },
+                    )
+                })
+                .collect(),
+            timestamp: 0,
+            difficulty,
+            gas_limit,
+            author,
+            deferred_state_epoch_count: None,
+            chain_id: None,
+            builtins: None,
+            invalid_tx_reject_height: None,
+        }
+    }
+
+    /// Add the accounts with `balances`, e.g. the ones in the
+    /// `genesis_accounts` file, which must not be in the spec already.
+    pub fn add_balances(
+        &mut self, balances: HashMap<Address, U256>,
+    ) -> Result<(), String> {
+        for (address, balance) in balances {
+            if self.accounts.contains_key(&address) {
+                return Err(format!(
+                    "account {:?} is in both the genesis spec and the \
+                     genesis accounts",
+                    address
+                ));
+            }
+            self.accounts.insert(
+                address,
+                GenesisAccount {
+                    balance,
+                    ..Default::default()
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// The machine with the built-in contracts of the network.
+    pub fn machine(&self) -> Machine {
+        let mut machine = match self.builtins {
+            Some(ref builtins) => new_machine_with_builtins(
+                builtins
+                    .iter()
+                    .map(|(address, builtin)| (*address, builtin.builtin()))
+                    .collect(),
+            ),
+            None => new_machine_with_builtin(),
+        };
+        if let Some(height) = self.invalid_tx_reject_height {
+            machine.set_invalid_tx_reject_height(height);
+        }
+        machine
+    }
+}
+
+/// The genesis spec file format, in which the numbers that may not fit in
+/// u64 are decimal strings, and the addresses and code are hex strings.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GenesisSpecFile {
+    #[serde(default)]
+    accounts: BTreeMap<String, GenesisAccountSpec>,
+    #[serde(default)]
+    timestamp: u64,
+    #[serde(default)]
+    difficulty: Option<String>,
+    gas_limit: String,
+    author: String,
+    #[serde(default)]
+    deferred_state_epoch_count: Option<u64>,
+    #[serde(default)]
+    chain_id: Option<u64>,
+    #[serde(default)]
+    builtins: Option<BTreeMap<String, BuiltinSpec>>,
+    #[serde(default)]
+    invalid_tx_reject_height: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GenesisAccountSpec {
+    balance: String,
+    #[serde(default)]
+    nonce: Option<String>,
+    #[serde(default)]
+    code: Option<String>,
+}
+
+fn parse_u256(name: &str, value: &str) -> Result<U256, String> {
+    U256::from_dec_str(value).map_err(|e| {
+        format!(
+            "failed to parse {}: value = {}, error = {:?}",
+            name, value, e
+        )
+    })
+}
+
+fn parse_address(value: &str) -> Result<Address, String> {
+    value.trim_start_matches("0x").parse().map_err(|e| {
+        format!(
+            "failed to parse address: value = {}, error = {:?}",
+            value, e
+        )
+    })
+}
+
+/// Load the genesis spec from a TOML file, or a JSON file if the file name
+/// ends with `.json`.
+pub fn load_spec_file(path: &str) -> Result<GenesisSpec, String> {
+    let mut content = String::new();
+    let mut file = File::open(path)
+        .map_err(|e| format!("failed to open file: {:?}", e))?;
+    file.read_to_string(&mut content)
+        .map_err(|e| format!("failed to read file content: {:?}", e))?;
+    let is_json = Path::new(path)
+        .extension()
+        .map_or(false, |extension| extension == "json");
+    parse_spec(&content, is_json)
+}
+
+fn parse_spec(content: &str, is_json: bool) -> Result<GenesisSpec, String> {
+    let spec_file: GenesisSpecFile = if is_json {
+        serde_json::from_str(content)
+            .map_err(|e| format!("failed to parse json file: {:?}", e))?
+    } else {
+        toml::from_str(content)
+            .map_err(|e| format!("failed to parse toml file: {:?}", e))?
+    };
+
+    let mut accounts = HashMap::new();
+    for (address, account) in spec_file.accounts {
+        let code = match account.code {
+            Some(code) => {
+                Some(code.trim_start_matches("0x").from_hex().map_err(|e| {
+                    format!(
+                        "failed to parse code of {}: error = {:?}",
+                        address, e
+                    )
+                })?)
+            }
+            None => None,
+        };
+        accounts.insert(
+            parse_address(&address)?,
+            GenesisAccount {
+                balance: parse_u256("balance", &account.balance)?,
+                nonce: match account.nonce {
+                    Some(nonce) => parse_u256("nonce", &nonce)?,
+                    None => U256::zero(),
+                },
+                code,
+            },
+        );
+    }
+
+    let builtins = match spec_file.builtins {
+        Some(builtins) => {
+            let mut parsed = BTreeMap::new();
+            for (address, builtin) in builtins {
+                if !BUILTIN_NAMES.contains(&builtin.name.as_str()) {
+                    return Err(format!(
+                        "unknown builtin {} at {}",
+                        builtin.name, address
+                    ));
+                }
+                parsed.insert(parse_address(&address)?, builtin);
+            }
+            Some(parsed)
+        }
+        None => None,
+    };
+
+    Ok(GenesisSpec {
+        accounts,
+        timestamp: spec_file.timestamp,
+        difficulty: match spec_file.difficulty {
+            Some(difficulty) => parse_u256("difficulty", &difficulty)?,
+            None => U256::zero(),
+        },
+        gas_limit: parse_u256("gas_limit", &spec_file.gas_limit)?,
+        author: parse_address(&spec_file.author)?,
+        deferred_state_epoch_count: spec_file.deferred_state_epoch_count,
+        chain_id: spec_file.chain_id,
+        builtins,
+        invalid_tx_reject_height: spec_file.invalid_tx_reject_height,
+    })
+}
+
 pub fn default(secret_store: &SecretStore) -> HashMap<Address, U256> {
     let balance = U256::from_dec_str("5000000000000000000000000000000000")
         .expect("Not overflow"); // 5*10^33
@@ -106,3 +394,197 @@ pub fn load_file(path: &String) -> Result<HashMap<Address, U256>, String> {
 
     Ok(accounts)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_spec, BuiltinSpec, PricingSpec};
+    use crate::vm::InvalidTransactionPolicy;
+    use cfx_types::{Address, U256};
+    use std::collections::HashMap;
+
+    const TOML_SPEC: &str = r#"
+        author = "000000000000000000000000000000000000000c"
+        gas_limit = "30000000"
+        timestamp = 100
+        chain_id = 2
+        invalid_tx_reject_height = 1000
+
+        [accounts.0x0000000000000000000000000000000000000001]
+        balance = "1000"
+
+        [accounts.0000000000000000000000000000000000000002]
+        balance = "2000"
+        nonce = "3"
+        code = "0x6080"
+
+        [builtins.0000000000000000000000000000000000000001]
+        name = "ecrecover"
+        pricing = { linear = { base = 3000, word = 0 } }
+
+        [builtins.0000000000000000000000000000000000000005]
+        name = "modexp"
+        pricing = { modexp = { divisor = 20 } }
+        activate_at = 10
+    "#;
+
+    #[test]
+    fn test_parse_toml() {
+        let spec = parse_spec(TOML_SPEC, false).unwrap();
+        assert_eq!(spec.author, Address::from_low_u64_be(12));
+        assert_eq!(spec.gas_limit, 30_000_000.into());
+        assert_eq!(spec.difficulty, U256::zero());
+        assert_eq!(spec.timestamp, 100);
+        assert_eq!(spec.chain_id, Some(2));
+        assert_eq!(spec.deferred_state_epoch_count, None);
+        assert_eq!(spec.invalid_tx_reject_height, Some(1000));
+
+        let account = &spec.accounts[&Address::from_low_u64_be(1)];
+        assert_eq!(account.balance, 1000.into());
+        assert_eq!(account.nonce, U256::zero());
+        assert_eq!(account.code, None);
+        let account = &spec.accounts[&Address::from_low_u64_be(2)];
+        assert_eq!(account.balance, 2000.into());
+        assert_eq!(account.nonce, 3.into());
+        assert_eq!(account.code, Some(vec![0x60, 0x80]));
+
+        let builtins = spec.builtins.as_ref().unwrap();
+        assert_eq!(
+            builtins[&Address::from_low_u64_be(5)],
+            BuiltinSpec {
+                name: "modexp".into(),
+                pricing: PricingSpec::Modexp { divisor: 20 },
+                activate_at: 10,
+            }
+        );
+
+        // Only the builtins in the spec are available, from their activation.
+        let machine = spec.machine();
+        assert_eq!(machine.builtins().len(), 2);
+        let ecrecover = machine.builtin(&Address::from_low_u64_be(1), 0);
+        assert_eq!(ecrecover.unwrap().cost(&[0; 128]), 3000.into());
+        assert!(machine.builtin(&Address::from_low_u64_be(2), 0).is_none());
+        assert!(machine.builtin(&Address::from_low_u64_be(5), 9).is_none());
+        assert!(machine.builtin(&Address::from_low_u64_be(5), 10).is_some());
+
+        assert_eq!(
+            machine.spec_for_epoch(999).invalid_tx_policy,
+            InvalidTransactionPolicy::Skip
+        );
+        assert_eq!(
+            machine.spec_for_epoch(1000).invalid_tx_policy,
+            InvalidTransactionPolicy::Reject
+        );
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let spec = parse_spec(
+            r#"{
+                "author": "0x000000000000000000000000000000000000000c",
+                "gas_limit": "30000000",
+                "difficulty": "100",
+                "deferred_state_epoch_count": 5,
+                "accounts": {
+                    "0000000000000000000000000000000000000001": {
+                        "balance": "1000"
+                    }
+                },
+                "builtins": {
+                    "0000000000000000000000000000000000000008": {
+                        "name": "alt_bn128_pairing",
+                        "pricing": {
+                            "alt_bn128_pairing": { "base": 100, "pair": 80 }
+                        }
+                    }
+                }
+            }"#,
+            true,
+        )
+        .unwrap();
+        assert_eq!(spec.difficulty, 100.into());
+        assert_eq!(spec.deferred_state_epoch_count, Some(5));
+        assert_eq!(spec.chain_id, None);
+        assert_eq!(spec.accounts.len(), 1);
+        let machine = spec.machine();
+        let pairing = machine.builtin(&Address::from_low_u64_be(8), 0);
+        assert_eq!(pairing.unwrap().cost(&[0; 384]), 260.into());
+    }
+
+    #[test]
+    fn test_default_builtins() {
+        let spec = parse_spec(
+            r#"
+                author = "000000000000000000000000000000000000000c"
+                gas_limit = "30000000"
+            "#,
+            false,
+        )
+        .unwrap();
+        assert!(spec.builtins.is_none());
+        assert_eq!(spec.machine().builtins().len(), 4);
+        assert_eq!(
+            spec.machine().spec_for_epoch(1_000_000).invalid_tx_policy,
+            InvalidTransactionPolicy::Skip
+        );
+    }
+
+    #[test]
+    fn test_invalid_spec() {
+        // Unknown fields are rejected.
+        assert!(parse_spec(
+            r#"
+                author = "000000000000000000000000000000000000000c"
+                gas_limit = "30000000"
+                chainid = 1
+            "#,
+            false,
+        )
+        .is_err());
+
+        // Unknown builtins are rejected.
+        assert!(parse_spec(
+            r#"
+                author = "000000000000000000000000000000000000000c"
+                gas_limit = "30000000"
+
+                [builtins.0000000000000000000000000000000000000001]
+                name = "blake2"
+                pricing = { linear = { base = 3000, word = 0 } }
+            "#,
+            false,
+        )
+        .is_err());
+
+        // Numbers are decimal strings.
+        assert!(parse_spec(
+            r#"
+                author = "000000000000000000000000000000000000000c"
+                gas_limit = "0x30000000"
+            "#,
+            false,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_add_balances() {
+        let mut spec = parse_spec(TOML_SPEC, false).unwrap();
+
+        let mut balances = HashMap::new();
+        balances.insert(Address::from_low_u64_be(3), U256::from(3000));
+        spec.add_balances(balances).unwrap();
+        assert_eq!(spec.accounts.len(), 3);
+        assert_eq!(
+            spec.accounts[&Address::from_low_u64_be(3)].balance,
+            3000.into()
+        );
+
+        let mut balances = HashMap::new();
+        balances.insert(Address::from_low_u64_be(1), U256::from(1));
+        assert!(spec.add_balances(balances).is_err());
+        assert_eq!(
+            spec.accounts[&Address::from_low_u64_be(1)].balance,
+            1000.into()
+        );
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::{
+    error::{BlockError, Error},
+    pow::{self, ProofOfWorkConfig},
+};
+use cfx_types::U256;
+use primitives::BlockHeader;
+use unexpected::{Mismatch, OutOfBounds};
+
+/// Verifies a sequence of consecutive pivot block headers without the
+/// consensus graph, e.g. for the relayers of the other chains and the light
+/// client, which only have the headers.
+///
+/// The difficulty is checked against the difficulty of the parent. At the
+/// end of each adjustment period, the target difficulty depends on the number
+/// of the blocks in the epochs of the period, which is not known from the
+/// pivot headers, so only the adjustment bound is checked.
+pub struct HeaderChainVerifier {
+    pow_config: ProofOfWorkConfig,
+}
+
+/// The result of `HeaderChainVerifier::verify`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifiedHeaderChain {
+    /// The heights of the headers with the correct deferred roots in the
+    /// ascending order, found by following the blame links from the last
+    /// header, which is trusted.
+    pub trusted_heights: Vec<u64>,
+}
+
+impl VerifiedHeaderChain {
+    /// The height of the first trusted header at or after `height`, which
+    /// has the correct deferred roots of the epoch `height` among the roots
+    /// it blames, as `ConsensusGraph::first_trusted_header_starting_from`.
+    pub fn witness_of(&self, height: u64) -> Option<u64> {
+        let i = match self.trusted_heights.binary_search(&height) {
+            Ok(i) => i,
+            Err(i) => i,
+        };
+        self.trusted_heights.get(i).cloned()
+    }
+}
+
+impl HeaderChainVerifier {
+    pub fn new(pow_config: ProofOfWorkConfig) -> Self {
+        HeaderChainVerifier { pow_config }
+    }
+
+    /// Verify `headers`, in which each header is the parent of the next one.
+    /// The first header is the anchor known by the caller, so its
+    /// difficulty is not checked.
+    pub fn verify(
+        &self, headers: &[BlockHeader],
+    ) -> Result<VerifiedHeaderChain, Error> {
+        for (i, header) in headers.iter().enumerate() {
+            self.verify_pow(header)?;
+            if header.blame() > 0 && header.blame() as u64 >= header.height() {
+                bail!(BlockError::InvalidBlame(OutOfBounds {
+                    min: None,
+                    max: Some(header.height().saturating_sub(1) as u32),
+                    found: header.blame(),
+                }));
+            }
+            if i > 0 {
+                self.verify_parent(&headers[i - 1], header)?;
+            }
+        }
+
+        // The blamed headers are skipped, as
+        // `ConsensusGraphInner::find_first_trusted_starting_from`.
+        let mut trusted_heights = Vec::new();
+        let mut next = headers.len();
+        while next > 0 {
+            let header = &headers[next - 1];
+            trusted_heights.push(header.height());
+            next = next.saturating_sub(header.blame() as usize + 1);
+        }
+        trusted_heights.reverse();
+
+        Ok(VerifiedHeaderChain { trusted_heights })
+    }
+
+    fn verify_pow(&self, header: &BlockHeader) -> Result<(), Error> {
+        if header.difficulty().is_zero() {
+            bail!(BlockError::InvalidDifficulty(OutOfBounds {
+                min: Some(1.into()),
+                max: None,
+                found: 0.into(),
+            }));
+        }
+        let pow_hash = pow::compute(header.nonce(), &header.problem_hash());
+        let boundary = pow::difficulty_to_boundary(header.difficulty());
+        if pow_hash >= boundary {
+            bail!(BlockError::InvalidProofOfWork(OutOfBounds {
+                min: None,
+                max: Some(boundary),
+                found: pow_hash,
+            }));
+        }
+        Ok(())
+    }
+
+    fn verify_parent(
+        &self, parent: &BlockHeader, header: &BlockHeader,
+    ) -> Result<(), Error> {
+        if header.parent_hash() != &parent.hash() {
+            bail!(BlockError::UnknownParent(*header.parent_hash()));
+        }
+        if header.height() != parent.height() + 1 {
+            bail!(BlockError::InvalidHeight(Mismatch {
+                expected: parent.height() + 1,
+                found: header.height(),
+            }));
+        }
+
+        // The same as `ConsensusGraphInner::expected_difficulty`, except at
+        // the end of the adjustment periods.
+        let period = self.pow_config.difficulty_adjustment_epoch_period;
+        let (min, max) = if parent.height() < period {
+            let initial: U256 = self.pow_config.initial_difficulty.into();
+            (initial, initial)
+        } else if parent.height() % period != 0 {
+            (*parent.difficulty(), *parent.difficulty())
+        } else {
+            self.pow_config.get_adjustment_bound(*parent.difficulty())
+        };
+        if *header.difficulty() < min || *header.difficulty() > max {
+            bail!(BlockError::InvalidDifficulty(OutOfBounds {
+                min: Some(min),
+                max: Some(max),
+                found: *header.difficulty(),
+            }));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeaderChainVerifier;
+    use crate::pow::{self, ProofOfWorkConfig};
+    use primitives::{BlockHeader, BlockHeaderBuilder};
+
+    fn mine(parent: Option<&BlockHeader>, blame: u32) -> BlockHeader {
+        let mut nonce = 0;
+        loop {
+            let mut header = BlockHeaderBuilder::new()
+                .with_parent_hash(
+                    parent.map_or(Default::default(), |p| p.hash()),
+                )
+                .with_height(parent.map_or(0, |p| p.height() + 1))
+                .with_difficulty(4.into())
+                .with_blame(blame)
+                .with_nonce(nonce)
+                .build();
+            header.compute_hash();
+            let pow_hash = pow::compute(nonce, &header.problem_hash());
+            if pow_hash < pow::difficulty_to_boundary(header.difficulty()) {
+                return header;
+            }
+            nonce += 1;
+        }
+    }
+
+    #[test]
+    fn test_verify_header_chain() {
+        let pow_config = ProofOfWorkConfig::new(
+            true,  /* test_mode */
+            false, /* use_stratum */
+            Some(4),
+            String::new(),
+            0,
+            None,
+        );
+        let verifier = HeaderChainVerifier::new(pow_config);
+
+        let mut headers = vec![mine(None, 0)];
+        for blame in &[0, 0, 1, 0, 2] {
+            let header = mine(headers.last(), *blame);
+            headers.push(header);
+        }
+        let verified = verifier.verify(&headers).unwrap();
+        // 5 blames 4 and 3, so the blame of 3 is not trusted.
+        assert_eq!(verified.trusted_heights, vec![0, 1, 2, 5]);
+        assert_eq!(verified.witness_of(1), Some(1));
+        assert_eq!(verified.witness_of(3), Some(5));
+        assert_eq!(verified.witness_of(6), None);
+
+        headers.remove(2);
+        assert!(verifier.verify(&headers).is_err());
+    }
+}
@@ -48,14 +48,18 @@ pub mod error;
 mod evm;
 pub mod executive;
 pub mod genesis;
+pub mod header_chain_verifier;
 mod parameters;
 #[macro_use]
 pub mod message;
 pub mod client;
 pub mod light_protocol;
 pub mod machine;
+pub mod migration;
 pub mod miner;
 pub mod pow;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
 pub(crate) mod snapshot;
 pub mod state;
 pub mod state_exposer;
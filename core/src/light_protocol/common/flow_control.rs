@@ -0,0 +1,170 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use std::{cmp, time::Instant};
+
+use crate::{
+    light_protocol::message::msgid,
+    message::MsgId,
+    parameters::light::{
+        BASE_REQUEST_COST, BLOCK_TX_COST, BLOOM_COST, EPOCH_COST, HEADER_COST,
+        MAX_CREDITS, RECEIPT_COST, RECHARGE_RATE, SEND_RAW_TX_COST,
+        STATE_ENTRY_COST, STATE_ROOT_COST, TX_COST, TX_INFO_COST, WITNESS_COST,
+    },
+};
+
+/// Cost of a request for `num_items` items in credits, or `None` if `msg_id`
+/// is not a request. Sending a transaction costs the same regardless.
+pub fn request_cost(msg_id: MsgId, num_items: usize) -> Option<u64> {
+    let item_cost = match msg_id {
+        msgid::SEND_RAW_TX => {
+            return Some(BASE_REQUEST_COST + SEND_RAW_TX_COST)
+        }
+        msgid::GET_STATE_ENTRIES => STATE_ENTRY_COST,
+        msgid::GET_STATE_ROOTS => STATE_ROOT_COST,
+        msgid::GET_BLOCK_HASHES_BY_EPOCH => EPOCH_COST,
+        msgid::GET_BLOCK_HEADERS => HEADER_COST,
+        msgid::GET_RECEIPTS => RECEIPT_COST,
+        msgid::GET_TXS => TX_COST,
+        msgid::GET_WITNESS_INFO => WITNESS_COST,
+        msgid::GET_BLOOMS => BLOOM_COST,
+        msgid::GET_BLOCK_TXS => BLOCK_TX_COST,
+        msgid::GET_TX_INFOS => TX_INFO_COST,
+        _ => return None,
+    };
+
+    Some(BASE_REQUEST_COST + item_cost * num_items as u64)
+}
+
+/// The largest number of items, at most `num_items`, that a request with
+/// `msg_id` can ask for without costing more than `credits`.
+pub fn affordable_items(
+    msg_id: MsgId, num_items: usize, credits: u64,
+) -> usize {
+    let per_item = match (request_cost(msg_id, 0), request_cost(msg_id, 1)) {
+        (Some(base), Some(one)) if credits >= base => (base, one - base),
+        _ => return 0,
+    };
+
+    match per_item {
+        (_, 0) => num_items,
+        (base, item) => cmp::min(num_items, ((credits - base) / item) as usize),
+    }
+}
+
+/// Credit-based flow control for requests sent by a single peer. Each request
+/// costs some credits; credits recharge linearly over time up to a maximum.
+pub struct FlowControl {
+    max_credits: u64,
+    recharge_rate: u64, // credits per second
+    credits: u64,
+    last_update: Instant,
+}
+
+impl FlowControl {
+    pub fn new(max_credits: u64, recharge_rate: u64) -> Self {
+        FlowControl {
+            max_credits,
+            recharge_rate,
+            credits: max_credits,
+            last_update: Instant::now(),
+        }
+    }
+
+    fn recharge(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - self.last_update;
+
+        let recharged = elapsed.as_secs() * self.recharge_rate
+            + elapsed.subsec_millis() as u64 * self.recharge_rate / 1000;
+
+        // only move `last_update` forward if we actually recharged, this way
+        // frequent calls still accumulate credits
+        if recharged > 0 {
+            self.credits = cmp::min(self.credits + recharged, self.max_credits);
+            self.last_update = now;
+        }
+    }
+
+    #[inline]
+    pub fn max_credits(&self) -> u64 { self.max_credits }
+
+    /// The credits available now.
+    pub fn credits(&mut self) -> u64 {
+        self.recharge();
+        self.credits
+    }
+
+    /// Deduct `cost` credits, or all of them if we do not have enough.
+    pub fn charge(&mut self, cost: u64) {
+        self.recharge();
+        self.credits = self.credits.saturating_sub(cost);
+    }
+
+    /// Deduct `cost` credits if we have enough, return `false` otherwise.
+    pub fn try_charge(&mut self, cost: u64) -> bool {
+        self.recharge();
+
+        if self.credits < cost {
+            return false;
+        }
+
+        self.credits -= cost;
+        true
+    }
+}
+
+impl Default for FlowControl {
+    fn default() -> Self { FlowControl::new(MAX_CREDITS, RECHARGE_RATE) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{affordable_items, request_cost, FlowControl};
+    use crate::{
+        light_protocol::message::msgid,
+        parameters::light::{BASE_REQUEST_COST, HEADER_COST, SEND_RAW_TX_COST},
+    };
+
+    #[test]
+    fn test_request_cost() {
+        assert_eq!(
+            request_cost(msgid::GET_BLOCK_HEADERS, 3),
+            Some(BASE_REQUEST_COST + 3 * HEADER_COST)
+        );
+        assert_eq!(
+            request_cost(msgid::SEND_RAW_TX, 3),
+            Some(BASE_REQUEST_COST + SEND_RAW_TX_COST)
+        );
+        assert_eq!(request_cost(msgid::STATUS_PONG, 1), None);
+    }
+
+    #[test]
+    fn test_affordable_items() {
+        let headers = msgid::GET_BLOCK_HEADERS;
+        let cost = request_cost(headers, 5).unwrap();
+        assert_eq!(affordable_items(headers, 10, cost), 5);
+        assert_eq!(affordable_items(headers, 10, cost + HEADER_COST - 1), 5);
+        assert_eq!(affordable_items(headers, 3, cost), 3);
+        assert_eq!(affordable_items(headers, 10, BASE_REQUEST_COST - 1), 0);
+        assert_eq!(affordable_items(msgid::STATUS_PONG, 10, cost), 0);
+
+        let raw_tx = msgid::SEND_RAW_TX;
+        let cost = request_cost(raw_tx, 1).unwrap();
+        assert_eq!(affordable_items(raw_tx, 10, cost), 10);
+        assert_eq!(affordable_items(raw_tx, 10, cost - 1), 0);
+    }
+
+    #[test]
+    fn test_charge() {
+        let mut flow_control = FlowControl::new(100, 0);
+        assert!(flow_control.try_charge(60));
+        assert!(!flow_control.try_charge(60));
+        assert_eq!(flow_control.credits(), 40);
+
+        flow_control.charge(60);
+        assert_eq!(flow_control.credits(), 0);
+        assert_eq!(flow_control.max_credits(), 100);
+    }
+}
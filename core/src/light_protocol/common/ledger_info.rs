@@ -12,7 +12,6 @@ use primitives::{
 use crate::{
     consensus::ConsensusGraph,
     light_protocol::{message::WitnessInfoWithHeight, Error, ErrorKind},
-    parameters::consensus::DEFERRED_STATE_EPOCH_COUNT,
     statedb::StateDb,
     storage::{
         state::{State, StateTrait},
@@ -56,6 +55,12 @@ impl LedgerInfo {
             .ok_or(ErrorKind::InternalError.into())
     }
 
+    /// The number of epochs by which the execution is deferred.
+    #[inline]
+    pub fn deferred_state_epoch_count(&self) -> u64 {
+        self.consensus.deferred_state_epoch_count()
+    }
+
     /// Get hash of block at `height` on the pivot chain, if it exists.
     #[inline]
     pub fn pivot_hash_of(&self, height: u64) -> Result<H256, Error> {
@@ -84,7 +89,8 @@ impl LedgerInfo {
     pub fn correct_deferred_state_root_hash_of(
         &self, height: u64,
     ) -> Result<H256, Error> {
-        let epoch = height.saturating_sub(DEFERRED_STATE_EPOCH_COUNT);
+        let epoch =
+            height.saturating_sub(self.consensus.deferred_state_epoch_count());
         let root = self.state_root_of(epoch)?;
         Ok(root.compute_state_root_hash())
     }
@@ -95,7 +101,8 @@ impl LedgerInfo {
     pub fn correct_deferred_receipts_root_hash_of(
         &self, height: u64,
     ) -> Result<H256, Error> {
-        let epoch = height.saturating_sub(DEFERRED_STATE_EPOCH_COUNT);
+        let epoch =
+            height.saturating_sub(self.consensus.deferred_state_epoch_count());
         let pivot = self.pivot_hash_of(epoch)?;
 
         self.consensus
@@ -111,7 +118,8 @@ impl LedgerInfo {
     pub fn correct_deferred_logs_root_hash_of(
         &self, height: u64,
     ) -> Result<H256, Error> {
-        let epoch = height.saturating_sub(DEFERRED_STATE_EPOCH_COUNT);
+        let epoch =
+            height.saturating_sub(self.consensus.deferred_state_epoch_count());
         let pivot = self.pivot_hash_of(epoch)?;
 
         self.consensus
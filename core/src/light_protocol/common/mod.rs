@@ -2,12 +2,14 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
+mod flow_control;
 mod ledger_info;
 mod peers;
 mod poll;
 mod timeout;
 mod unique_id;
 
+pub use flow_control::{affordable_items, request_cost, FlowControl};
 pub use ledger_info::LedgerInfo;
 pub use peers::{FullPeerState, LightPeerState, Peers};
 pub use poll::{poll_future, poll_stream};
@@ -11,11 +11,14 @@ use std::{
     sync::Arc,
 };
 
+use super::FlowControl;
 use crate::network::PeerId;
 
 #[derive(Default)]
 pub struct FullPeerState {
     pub best_epoch: u64,
+    // our estimate of the credits the peer allows us to spend
+    pub flow_control: FlowControl,
     pub handshake_completed: bool,
     pub protocol_version: u8,
     pub terminals: HashSet<H256>,
@@ -23,8 +26,10 @@ pub struct FullPeerState {
 
 #[derive(Default)]
 pub struct LightPeerState {
+    pub flow_control: FlowControl,
     pub handshake_completed: bool,
     pub protocol_version: u8,
+    pub queued_requests: usize,
 }
 
 #[derive(Default)]
@@ -39,6 +39,11 @@ error_chain! {
             display("Invalid bloom"),
         }
 
+        InvalidHeader {
+            description("Invalid header"),
+            display("Invalid header"),
+        }
+
         InvalidLedgerProof {
             description("Invalid ledger proof"),
             display("Invalid ledger proof"),
@@ -84,11 +89,26 @@ error_chain! {
             display("Pivot hash mismatch"),
         }
 
+        RequestTimeout(item: String) {
+            description("Request timeout"),
+            display("Timeout while waiting for {}", item),
+        }
+
+        RequestTooExpensive {
+            description("Request too expensive"),
+            display("Request costs more credits than allowed"),
+        }
+
         SendStatusFailed {
             description("Send status failed"),
             display("Send status failed"),
         }
 
+        TooManyRequests {
+            description("Too many requests"),
+            display("Too many requests"),
+        }
+
         UnableToProduceProof {
             description("Unable to produce proof"),
             display("Unable to produce proof"),
@@ -154,6 +174,9 @@ pub fn handle(io: &dyn NetworkContext, peer: PeerId, msg_id: MsgId, e: Error) {
         // where the pivot chain has not converged
         | ErrorKind::PivotHashMismatch
 
+        // NOTE: this is raised locally by the futures of the callers
+        | ErrorKind::RequestTimeout(_)
+
         // NOTE: in order to let other protocols run,
         // we should not disconnect on protocol failure
         | ErrorKind::SendStatusFailed
@@ -172,11 +195,15 @@ pub fn handle(io: &dyn NetworkContext, peer: PeerId, msg_id: MsgId, e: Error) {
         | ErrorKind::UnexpectedPeerType
         | ErrorKind::UnknownPeer => op = Some(UpdateNodeOperation::Failure),
 
-        ErrorKind::UnexpectedRequestId | ErrorKind::UnexpectedResponse => {
+        ErrorKind::RequestTooExpensive
+        | ErrorKind::TooManyRequests
+        | ErrorKind::UnexpectedRequestId
+        | ErrorKind::UnexpectedResponse => {
             op = Some(UpdateNodeOperation::Demotion)
         }
 
         ErrorKind::InvalidBloom
+        | ErrorKind::InvalidHeader
         | ErrorKind::InvalidLedgerProof
         | ErrorKind::InvalidMessageFormat
         | ErrorKind::InvalidReceipts
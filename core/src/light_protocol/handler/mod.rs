@@ -13,7 +13,7 @@ use std::sync::Arc;
 use crate::{
     consensus::ConsensusGraph,
     light_protocol::{
-        common::{FullPeerState, Peers, UniqueId},
+        common::{FlowControl, FullPeerState, Peers, UniqueId},
         handle_error,
         message::{
             msgid, BlockHashes as GetBlockHashesResponse,
@@ -31,6 +31,7 @@ use crate::{
     network::{NetworkContext, NetworkProtocolHandler, PeerId},
     parameters::light::{
         CATCH_UP_EPOCH_LAG_THRESHOLD, CLEANUP_PERIOD, SYNC_PERIOD,
+        WITNESS_HISTORY_EPOCHS,
     },
     sync::SynchronizationGraph,
 };
@@ -49,6 +50,22 @@ struct Statistics {
     latest_epoch: u64,
 }
 
+/// The configurable parameters of the light protocol handler.
+#[derive(Clone, Debug)]
+pub struct LightConfiguration {
+    /// The number of epochs for which the verified witness roots are kept
+    /// (both in memory and in the db).
+    pub witness_history_epochs: u64,
+}
+
+impl Default for LightConfiguration {
+    fn default() -> Self {
+        LightConfiguration {
+            witness_history_epochs: WITNESS_HISTORY_EPOCHS,
+        }
+    }
+}
+
 /// Handler is responsible for maintaining peer meta-information and
 /// dispatching messages to the query and sync sub-handlers.
 pub struct Handler {
@@ -92,7 +109,9 @@ pub struct Handler {
 impl Handler {
     pub fn new(
         consensus: Arc<ConsensusGraph>, graph: Arc<SynchronizationGraph>,
-    ) -> Self {
+        config: LightConfiguration,
+    ) -> Self
+    {
         let peers = Arc::new(Peers::new());
         let request_id_allocator = Arc::new(UniqueId::new());
 
@@ -101,10 +120,18 @@ impl Handler {
         // along with a Merkle-root for headers in each era.
         graph.recover_graph_from_db(true /* header_only */);
 
+        let witnesses = Arc::new(Witnesses::new(
+            consensus.clone(),
+            peers.clone(),
+            request_id_allocator.clone(),
+            config.witness_history_epochs,
+        ));
+
         let headers = Arc::new(Headers::new(
             graph.clone(),
             peers.clone(),
             request_id_allocator.clone(),
+            witnesses.clone(),
         ));
 
         let epochs = Epochs::new(
@@ -114,12 +141,6 @@ impl Handler {
             request_id_allocator.clone(),
         );
 
-        let witnesses = Arc::new(Witnesses::new(
-            consensus.clone(),
-            peers.clone(),
-            request_id_allocator.clone(),
-        ));
-
         let blooms = Blooms::new(
             peers.clone(),
             request_id_allocator.clone(),
@@ -340,6 +361,8 @@ impl Handler {
             let state = self.get_existing_peer_state(&peer)?;
             let mut state = state.write();
             state.best_epoch = status.best_epoch;
+            state.flow_control =
+                FlowControl::new(status.max_credits, status.recharge_rate);
             state.handshake_completed = true;
             state.protocol_version = status.protocol_version;
             state.terminals = status.terminals.into_iter().collect();
@@ -372,7 +395,7 @@ impl Handler {
         let resp: GetBlockHeadersResponse = rlp.as_val()?;
         info!("on_block_headers resp={:?}", resp);
 
-        self.headers.receive(resp.headers.into_iter());
+        self.headers.receive(resp.headers.into_iter())?;
 
         self.start_sync(io);
         Ok(())
@@ -391,12 +414,18 @@ impl Handler {
     }
 
     fn on_blooms(
-        &self, io: &dyn NetworkContext, _peer: PeerId, rlp: &Rlp,
+        &self, io: &dyn NetworkContext, peer: PeerId, rlp: &Rlp,
     ) -> Result<(), Error> {
         let resp: GetBloomsResponse = rlp.as_val()?;
         info!("on_blooms resp={:?}", resp);
 
-        self.blooms.receive(resp.blooms.into_iter())?;
+        self.blooms.record_response_size(
+            peer,
+            resp.blooms.len(),
+            rlp.as_raw().len(),
+        );
+
+        self.blooms.receive(peer, resp.blooms.into_iter())?;
 
         self.blooms.sync(io);
         Ok(())
@@ -428,24 +457,30 @@ impl Handler {
     }
 
     fn on_receipts(
-        &self, io: &dyn NetworkContext, _peer: PeerId, rlp: &Rlp,
+        &self, io: &dyn NetworkContext, peer: PeerId, rlp: &Rlp,
     ) -> Result<(), Error> {
         let resp: GetReceiptsResponse = rlp.as_val()?;
         info!("on_receipts resp={:?}", resp);
 
-        self.receipts.receive(resp.receipts.into_iter())?;
+        self.receipts.record_response_size(
+            peer,
+            resp.receipts.len(),
+            rlp.as_raw().len(),
+        );
+
+        self.receipts.receive(peer, resp.receipts.into_iter())?;
 
         self.receipts.sync(io);
         Ok(())
     }
 
     fn on_state_entries(
-        &self, io: &dyn NetworkContext, _peer: PeerId, rlp: &Rlp,
+        &self, io: &dyn NetworkContext, peer: PeerId, rlp: &Rlp,
     ) -> Result<(), Error> {
         let resp: GetStateEntriesResponse = rlp.as_val()?;
         info!("on_state_entries resp={:?}", resp);
 
-        self.state_entries.receive(resp.entries.into_iter())?;
+        self.state_entries.receive(peer, resp.entries.into_iter())?;
 
         self.state_entries.sync(io);
         Ok(())
@@ -476,25 +511,36 @@ impl Handler {
     }
 
     fn on_tx_infos(
-        &self, io: &dyn NetworkContext, _peer: PeerId, rlp: &Rlp,
+        &self, io: &dyn NetworkContext, peer: PeerId, rlp: &Rlp,
     ) -> Result<(), Error> {
         let resp: GetTxInfosResponse = rlp.as_val()?;
         info!("on_tx_infos resp={:?}", resp);
 
-        self.tx_infos.receive(resp.infos.into_iter())?;
+        self.tx_infos.receive(peer, resp.infos.into_iter())?;
 
         self.tx_infos.sync(io);
         Ok(())
     }
 
     fn on_witness_info(
-        &self, io: &dyn NetworkContext, _peer: PeerId, rlp: &Rlp,
+        &self, io: &dyn NetworkContext, peer: PeerId, rlp: &Rlp,
     ) -> Result<(), Error> {
         let resp: GetWitnessInfoResponse = rlp.as_val()?;
         info!("on_witness_info resp={:?}", resp);
 
+        self.witnesses.record_response_size(
+            peer,
+            resp.infos.len(),
+            rlp.as_raw().len(),
+        );
+
         self.witnesses.receive(resp.infos.into_iter())?;
 
+        // the blooms and receipts received before these witnesses can be
+        // validated now
+        self.blooms.validate_pending();
+        self.receipts.validate_pending();
+
         self.witnesses.sync(io);
         Ok(())
     }
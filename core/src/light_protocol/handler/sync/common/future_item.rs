@@ -7,36 +7,191 @@ extern crate lru_time_cache;
 
 use futures::{Async, Future, Poll};
 use lru_time_cache::LruCache;
-use parking_lot::RwLock;
-use std::{hash::Hash, sync::Arc};
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    hash::Hash,
+    sync::Arc,
+    time::Instant,
+};
 
-use crate::light_protocol::Error;
+use crate::light_protocol::{Error, ErrorKind};
 
-pub struct FutureItem<K, V> {
+/// The storage of the verified items that the futures wait for.
+pub trait VerifiedItems<K, V>: Send + Sync {
+    fn get_verified(&self, key: &K) -> Option<V>;
+}
+
+impl<K, V> VerifiedItems<K, V> for RwLock<LruCache<K, V>>
+where
+    K: Clone + Ord + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn get_verified(&self, key: &K) -> Option<V> {
+        self.write().get(key).cloned()
+    }
+}
+
+impl<K, V> VerifiedItems<K, V> for RwLock<HashMap<K, V>>
+where
+    K: Eq + Hash + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn get_verified(&self, key: &K) -> Option<V> {
+        self.read().get(key).cloned()
+    }
+}
+
+/// The number of futures waiting for each key. The keys whose futures are
+/// all dropped are abandoned, so that the sync manager stops requesting
+/// them.
+pub struct Waiters<K> {
+    counts: Mutex<HashMap<K, usize>>,
+    abandoned: Mutex<HashSet<K>>,
+}
+
+impl<K> Waiters<K>
+where K: Clone + Eq + Hash
+{
+    pub fn new() -> Self {
+        Waiters {
+            counts: Mutex::new(HashMap::new()),
+            abandoned: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn add(&self, key: &K) {
+        *self.counts.lock().entry(key.clone()).or_insert(0) += 1;
+        self.abandoned.lock().remove(key);
+    }
+
+    fn remove(&self, key: &K) {
+        let mut counts = self.counts.lock();
+        let last = match counts.get_mut(key) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+        if last {
+            counts.remove(key);
+            self.abandoned.lock().insert(key.clone());
+        }
+    }
+
+    /// Take the keys abandoned since the last call.
+    pub fn take_abandoned(&self) -> HashSet<K> {
+        std::mem::replace(&mut *self.abandoned.lock(), HashSet::new())
+    }
+}
+
+pub struct FutureItem<K, V>
+where K: Clone + Eq + Hash
+{
     key: K,
-    verified: Arc<RwLock<LruCache<K, V>>>,
+    verified: Arc<dyn VerifiedItems<K, V>>,
+    deadline: Option<Instant>,
+    waiters: Option<Arc<Waiters<K>>>,
 }
 
-impl<K, V> FutureItem<K, V> {
-    pub fn new(
-        key: K, verified: Arc<RwLock<LruCache<K, V>>>,
-    ) -> FutureItem<K, V> {
-        FutureItem { key, verified }
+impl<K, V> FutureItem<K, V>
+where K: Clone + Eq + Hash
+{
+    pub fn new<C>(key: K, verified: Arc<C>) -> FutureItem<K, V>
+    where C: VerifiedItems<K, V> + 'static {
+        FutureItem {
+            key,
+            verified,
+            deadline: None,
+            waiters: None,
+        }
+    }
+
+    /// Fail with `ErrorKind::RequestTimeout` if the item is not verified
+    /// before `deadline`.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Count this future as a waiter of its key until it is dropped.
+    pub fn with_waiters(mut self, waiters: Arc<Waiters<K>>) -> Self {
+        waiters.add(&self.key);
+        self.waiters = Some(waiters);
+        self
     }
 }
 
 impl<K, V> Future for FutureItem<K, V>
 where
-    K: Clone + Eq + Hash + Ord,
+    K: Clone + Debug + Eq + Hash,
     V: Clone,
 {
     type Error = Error;
     type Item = V;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        match self.verified.write().get(&self.key) {
-            None => Ok(Async::NotReady),
-            Some(item) => Ok(Async::Ready(item.clone())),
+        if let Some(item) = self.verified.get_verified(&self.key) {
+            return Ok(Async::Ready(item));
+        }
+
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                let key = format!("{:?}", self.key);
+                Err(ErrorKind::RequestTimeout(key).into())
+            }
+            _ => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<K, V> Drop for FutureItem<K, V>
+where K: Clone + Eq + Hash
+{
+    fn drop(&mut self) {
+        if let Some(ref waiters) = self.waiters {
+            waiters.remove(&self.key);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{FutureItem, Waiters};
+    use futures::{Async, Future};
+    use lru_time_cache::LruCache;
+    use parking_lot::RwLock;
+    use std::{sync::Arc, time::Instant};
+
+    #[test]
+    fn test_future_item() {
+        let verified = Arc::new(RwLock::new(LruCache::with_capacity(10)));
+        let waiters = Arc::new(Waiters::new());
+
+        let mut item =
+            FutureItem::new(1, verified.clone()).with_waiters(waiters.clone());
+        assert_eq!(item.poll().unwrap(), Async::NotReady);
+        verified.write().insert(1, 2);
+        assert_eq!(item.poll().unwrap(), Async::Ready(2));
+
+        let mut pending = FutureItem::<u64, u64>::new(2, verified.clone())
+            .with_waiters(waiters.clone());
+        let other = FutureItem::<u64, u64>::new(2, verified.clone())
+            .with_waiters(waiters.clone());
+        assert_eq!(pending.poll().unwrap(), Async::NotReady);
+
+        let mut expired = FutureItem::<u64, u64>::new(3, verified.clone())
+            .with_deadline(Instant::now());
+        assert!(expired.poll().is_err());
+
+        // the key is abandoned only when all its futures are dropped
+        drop(pending);
+        assert!(!waiters.take_abandoned().contains(&2));
+        drop(other);
+        assert!(waiters.take_abandoned().contains(&2));
+        drop(item);
+        assert!(waiters.take_abandoned().contains(&1));
+    }
+}
@@ -6,10 +6,12 @@ mod future_item;
 mod ledger_proof;
 mod missing_item;
 mod priority_queue;
+mod sync_engine;
 mod sync_manager;
 
-pub use future_item::FutureItem;
+pub use future_item::{FutureItem, Waiters};
 pub use ledger_proof::LedgerProof;
 pub use missing_item::{HasKey, KeyOrdered, KeyReverseOrdered, TimeOrdered};
 pub use priority_queue::PriorityQueue;
+pub use sync_engine::{ItemSync, SyncConfig, SyncEngine, Validated};
 pub use sync_manager::SyncManager;
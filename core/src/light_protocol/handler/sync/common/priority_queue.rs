@@ -57,6 +57,19 @@ where
             }
         }
     }
+
+    /// Keep only the values for which `f` returns true.
+    #[inline]
+    pub fn retain(&mut self, mut f: impl FnMut(&V) -> bool) {
+        let values = std::mem::replace(&mut self.values, BinaryHeap::new());
+        for value in values.into_vec() {
+            if f(&value) {
+                self.values.push(value);
+            } else {
+                self.keys.remove(&value.key());
+            }
+        }
+    }
 }
 
 impl<K, V> Extend<V> for PriorityQueue<K, V>
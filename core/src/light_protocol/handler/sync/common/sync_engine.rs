@@ -0,0 +1,615 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+extern crate lru_time_cache;
+
+use lru_time_cache::LruCache;
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    light_protocol::{
+        common::{FullPeerState, Peers, UniqueId},
+        handle_error, Error, ErrorKind,
+    },
+    message::{Message, MsgId},
+    network::{NetworkContext, PeerId},
+    parameters::light::{CACHE_TIMEOUT, MAX_REQUEST_RETRIES},
+};
+
+use super::{FutureItem, HasKey, SyncManager};
+
+/// The result of validating an item of a response.
+pub enum Validated<K, V> {
+    /// The verified items in the response.
+    Items(Vec<(K, V)>),
+
+    /// The response cannot be validated yet, e.g. before the roots it is
+    /// checked against are verified, so it is kept and validated later.
+    Deferred(K),
+}
+
+/// The item-specific parts of a `SyncEngine`: how the items are requested
+/// and how the responses are validated.
+pub trait ItemSync: Send + Sync {
+    type Key: Clone + Debug + Default + Eq + Hash + Ord + Send + Sync + 'static;
+
+    /// The items waiting to be requested, whose order is the priority of the
+    /// requests.
+    type Missing: Clone + Debug + HasKey<Self::Key> + Ord;
+
+    type Value: Clone + Send + Sync + 'static;
+
+    /// An item of the responses.
+    type Response: Debug;
+
+    fn missing(key: Self::Key) -> Self::Missing;
+
+    /// The key of the item requested that `response` answers, if known
+    /// without validating it.
+    fn key_of(response: &Self::Response) -> Option<Self::Key>;
+
+    fn request_message(
+        &self, request_id: u64, keys: Vec<Self::Key>,
+    ) -> Box<dyn Message>;
+
+    fn validate(
+        &self, peer: PeerId, response: &Self::Response,
+    ) -> Result<Validated<Self::Key, Self::Value>, Error>;
+}
+
+/// The parameters of a `SyncEngine`.
+pub struct SyncConfig {
+    /// The name of the items in the logs.
+    pub name: &'static str,
+    /// The id of the response messages, for reporting the invalid ones.
+    pub msg_id: MsgId,
+    /// The id of the request messages, for estimating their costs.
+    pub request_msg_id: MsgId,
+    pub max_in_flight: usize,
+    /// The maximum number of the responses kept until they can be
+    /// validated.
+    pub max_pending: usize,
+    pub batch_size: usize,
+    pub request_timeout: Duration,
+}
+
+#[derive(Debug)]
+struct Statistics {
+    cached: usize,
+    in_flight: usize,
+    pending: usize,
+    waiting: usize,
+}
+
+struct PendingResponse<R> {
+    peer: PeerId,
+    received_at: Instant,
+    response: R,
+}
+
+/// Requests, validates and caches the items of a kind on behalf of the
+/// callers waiting for them. The items timed out are requested again up to
+/// `MAX_REQUEST_RETRIES` times, and the items found invalid are not
+/// requested again from the peers sending them. The items that no peer can
+/// be requested for also count against the retries.
+pub struct SyncEngine<S: ItemSync> {
+    config: SyncConfig,
+
+    // the item-specific parts
+    item_sync: S,
+
+    // responses that cannot be validated yet
+    pending: RwLock<HashMap<S::Key, PendingResponse<S::Response>>>,
+
+    // peers whose pending responses are found invalid, which are penalized
+    // in the next sync
+    invalid_pending: Mutex<Vec<(PeerId, Error)>>,
+
+    // series of unique request ids
+    request_id_allocator: Arc<UniqueId>,
+
+    // number of times each item has been requested again
+    retries: RwLock<HashMap<S::Key, usize>>,
+
+    // sync and request manager
+    sync_manager: SyncManager<S::Key, S::Missing>,
+
+    // items received from full node
+    verified: Arc<RwLock<LruCache<S::Key, S::Value>>>,
+}
+
+impl<S: ItemSync> SyncEngine<S> {
+    pub fn new(
+        config: SyncConfig, item_sync: S, peers: Arc<Peers<FullPeerState>>,
+        request_id_allocator: Arc<UniqueId>,
+    ) -> Self
+    {
+        let cache = LruCache::with_expiry_duration(*CACHE_TIMEOUT);
+        let sync_manager = SyncManager::new(peers, config.request_msg_id);
+
+        SyncEngine {
+            config,
+            item_sync,
+            pending: RwLock::new(HashMap::new()),
+            invalid_pending: Mutex::new(Vec::new()),
+            request_id_allocator,
+            retries: RwLock::new(HashMap::new()),
+            sync_manager,
+            verified: Arc::new(RwLock::new(cache)),
+        }
+    }
+
+    #[inline]
+    fn get_statistics(&self) -> Statistics {
+        Statistics {
+            cached: self.verified.read().len(),
+            in_flight: self.sync_manager.num_in_flight(),
+            pending: self.pending.read().len(),
+            waiting: self.sync_manager.num_waiting(),
+        }
+    }
+
+    #[inline]
+    pub fn insert_verified(&self, key: S::Key, value: S::Value) {
+        self.verified.write().insert(key, value);
+    }
+
+    #[inline]
+    fn is_known(&self, key: &S::Key) -> bool {
+        self.verified.read().contains_key(key)
+            || self.pending.read().contains_key(key)
+    }
+
+    /// Request `key` in the next sync. The key is no longer requested once
+    /// all the futures returned for it are dropped.
+    #[inline]
+    pub fn request(&self, key: S::Key) -> FutureItem<S::Key, S::Value> {
+        let future = FutureItem::new(key.clone(), self.verified.clone())
+            .with_waiters(self.sync_manager.waiters());
+
+        if !self.is_known(&key) {
+            let missing = S::missing(key);
+            self.sync_manager.insert_waiting(std::iter::once(missing));
+        }
+
+        future
+    }
+
+    /// Request `key` from a random peer immediately.
+    #[inline]
+    pub fn request_now(
+        &self, io: &dyn NetworkContext, key: S::Key,
+    ) -> FutureItem<S::Key, S::Value> {
+        let future = FutureItem::new(key.clone(), self.verified.clone())
+            .with_waiters(self.sync_manager.waiters());
+
+        if !self.is_known(&key) {
+            let missing = std::iter::once(S::missing(key));
+            self.sync_manager.request_now(missing, |peer, keys| {
+                self.send_request(io, peer, keys)
+            });
+        }
+
+        future
+    }
+
+    /// Record the size of a response from `peer` for adapting the batch
+    /// size of the requests to it.
+    #[inline]
+    pub fn record_response_size(
+        &self, peer: PeerId, num_items: usize, bytes: usize,
+    ) {
+        self.sync_manager
+            .record_response_size(peer, num_items, bytes);
+    }
+
+    #[inline]
+    pub fn receive(
+        &self, peer: PeerId, responses: impl Iterator<Item = S::Response>,
+    ) -> Result<(), Error> {
+        for response in responses {
+            self.receive_one(peer, response, true /* allow_deferred */)?;
+        }
+
+        Ok(())
+    }
+
+    /// Receive a response that must be validated immediately, e.g. as a
+    /// part of another response.
+    #[inline]
+    pub fn receive_now(
+        &self, peer: PeerId, response: S::Response,
+    ) -> Result<(), Error> {
+        self.receive_one(peer, response, false /* allow_deferred */)
+    }
+
+    fn receive_one(
+        &self, peer: PeerId, response: S::Response, allow_deferred: bool,
+    ) -> Result<(), Error> {
+        info!("Validating {} {:?}", self.config.name, response);
+
+        match self.item_sync.validate(peer, &response) {
+            Ok(Validated::Items(items)) => {
+                self.insert_validated(items);
+                Ok(())
+            }
+            Ok(Validated::Deferred(key)) if allow_deferred => {
+                // only keep the responses to our requests, so that a peer
+                // cannot fill `pending` with unsolicited ones
+                if self.sync_manager.in_flight_peer(&key) != Some(peer) {
+                    warn!(
+                        "Unexpected {} {:?} from peer {:?}",
+                        self.config.name, key, peer
+                    );
+                    return Err(ErrorKind::UnexpectedResponse.into());
+                }
+
+                self.sync_manager.remove_in_flight(&key);
+                let mut pending = self.pending.write();
+                if pending.len() >= self.config.max_pending {
+                    debug!(
+                        "Too many pending {}s, dropping {:?}",
+                        self.config.name, key
+                    );
+                    drop(pending);
+                    self.retry(std::iter::once(S::missing(key)));
+                    return Ok(());
+                }
+
+                debug!("Deferred {} {:?}", self.config.name, key);
+                pending.insert(
+                    key,
+                    PendingResponse {
+                        peer,
+                        received_at: Instant::now(),
+                        response,
+                    },
+                );
+                Ok(())
+            }
+            Ok(Validated::Deferred(key)) => {
+                warn!("Unable to validate {} {:?} yet", self.config.name, key);
+                Err(ErrorKind::InternalError.into())
+            }
+            Err(e) => {
+                if let Some(key) = S::key_of(&response) {
+                    self.on_invalid(peer, key, false /* requested */);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    #[inline]
+    fn insert_validated(&self, items: Vec<(S::Key, S::Value)>) {
+        for (key, value) in items {
+            self.sync_manager.remove_in_flight(&key);
+            self.sync_manager.forget_bad_peers(&key);
+            self.retries.write().remove(&key);
+            self.verified.write().insert(key, value);
+        }
+    }
+
+    /// Request `key` again from the other peers than `peer`, which sent an
+    /// invalid response for it, if it has been requested by us.
+    #[inline]
+    fn on_invalid(&self, peer: PeerId, key: S::Key, requested: bool) {
+        let in_flight = self.sync_manager.remove_in_flight(&key);
+        self.sync_manager.blacklist(key.clone(), peer);
+        if requested || in_flight {
+            self.retry(std::iter::once(S::missing(key)));
+        }
+    }
+
+    /// Validate the pending responses again, e.g. after new roots are
+    /// verified.
+    pub fn validate_pending(&self) {
+        let pending =
+            std::mem::replace(&mut *self.pending.write(), HashMap::new());
+
+        for (key, pending) in pending {
+            let PendingResponse {
+                peer,
+                received_at,
+                response,
+            } = pending;
+
+            match self.item_sync.validate(peer, &response) {
+                Ok(Validated::Items(items)) => self.insert_validated(items),
+                Ok(Validated::Deferred(_))
+                    if received_at.elapsed() < *CACHE_TIMEOUT =>
+                {
+                    self.pending.write().insert(
+                        key,
+                        PendingResponse {
+                            peer,
+                            received_at,
+                            response,
+                        },
+                    );
+                }
+                Ok(Validated::Deferred(_)) => {
+                    debug!("Pending {} {:?} expired", self.config.name, key);
+                    self.retry(std::iter::once(S::missing(key)));
+                }
+                Err(e) => {
+                    warn!(
+                        "Invalid {} {:?} from peer {:?}: {:?}",
+                        self.config.name, key, peer, e
+                    );
+                    self.on_invalid(peer, key, true /* requested */);
+                    self.invalid_pending.lock().push((peer, e));
+                }
+            }
+        }
+    }
+
+    /// Request `items` again, except for the ones retried too many times.
+    fn retry(&self, items: impl Iterator<Item = S::Missing>) {
+        let mut retries = self.retries.write();
+
+        let items: Vec<_> = items
+            .filter(|item| {
+                let key = item.key();
+                let count = retries.entry(key.clone()).or_insert(0);
+                *count += 1;
+
+                if *count <= MAX_REQUEST_RETRIES {
+                    return true;
+                }
+
+                warn!(
+                    "Giving up {} {:?} after {} retries",
+                    self.config.name, key, MAX_REQUEST_RETRIES
+                );
+                retries.remove(&key);
+                self.sync_manager.forget_bad_peers(&key);
+                false
+            })
+            .collect();
+
+        self.sync_manager.insert_waiting(items.into_iter());
+    }
+
+    #[inline]
+    pub fn clean_up(&self) {
+        // remove timeout in-flight requests
+        let timeout = self.config.request_timeout;
+        let items = self.sync_manager.remove_timeout_requests(timeout);
+        self.retry(items.into_iter());
+
+        self.validate_pending();
+
+        // trigger cache cleanup
+        self.verified.write().get(&Default::default());
+    }
+
+    #[inline]
+    fn send_request(
+        &self, io: &dyn NetworkContext, peer: PeerId, keys: Vec<S::Key>,
+    ) -> Result<(), Error> {
+        info!(
+            "send_request peer={:?} {}s={:?}",
+            peer, self.config.name, keys
+        );
+
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let request_id = self.request_id_allocator.next();
+        let msg = self.item_sync.request_message(request_id, keys);
+
+        msg.send(io, peer)?;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn sync(&self, io: &dyn NetworkContext) {
+        info!(
+            "{} sync statistics: {:?}",
+            self.config.name,
+            self.get_statistics()
+        );
+
+        self.validate_pending();
+
+        // penalize the peers as if the invalid responses were just received
+        let invalid =
+            std::mem::replace(&mut *self.invalid_pending.lock(), vec![]);
+        for (peer, e) in invalid {
+            handle_error(io, peer, self.config.msg_id, e);
+        }
+
+        let unavailable = self.sync_manager.sync(
+            self.config.max_in_flight,
+            self.config.batch_size,
+            |peer, keys| self.send_request(io, peer, keys),
+        );
+        self.retry(unavailable.into_iter());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ItemSync, SyncConfig, SyncEngine, Validated};
+    use crate::{
+        light_protocol::{
+            common::{FullPeerState, Peers, UniqueId},
+            message::{msgid, GetBlooms},
+            Error, ErrorKind,
+        },
+        message::Message,
+        network::PeerId,
+        parameters::light::MAX_REQUEST_RETRIES,
+    };
+    use parking_lot::RwLock;
+    use std::{collections::HashSet, sync::Arc, time::Duration};
+
+    use super::super::KeyOrdered;
+
+    /// Items whose values must equal their keys, and can only be validated
+    /// once their keys are in `roots`.
+    #[derive(Default)]
+    struct MockSync {
+        roots: RwLock<HashSet<u64>>,
+    }
+
+    impl ItemSync for MockSync {
+        type Key = u64;
+        type Missing = KeyOrdered<u64>;
+        type Response = (u64, u64);
+        type Value = u64;
+
+        fn missing(key: u64) -> KeyOrdered<u64> { KeyOrdered::new(key) }
+
+        fn key_of(response: &(u64, u64)) -> Option<u64> { Some(response.0) }
+
+        fn request_message(
+            &self, request_id: u64, epochs: Vec<u64>,
+        ) -> Box<dyn Message> {
+            Box::new(GetBlooms { request_id, epochs })
+        }
+
+        fn validate(
+            &self, _peer: PeerId, response: &(u64, u64),
+        ) -> Result<Validated<u64, u64>, Error> {
+            let (key, value) = *response;
+            if !self.roots.read().contains(&key) {
+                return Ok(Validated::Deferred(key));
+            }
+            if key != value {
+                return Err(ErrorKind::InvalidBloom.into());
+            }
+            Ok(Validated::Items(vec![(key, value)]))
+        }
+    }
+
+    fn engine(peers: &[PeerId], max_pending: usize) -> SyncEngine<MockSync> {
+        let config = SyncConfig {
+            name: "mock",
+            msg_id: msgid::BLOOMS,
+            request_msg_id: msgid::GET_BLOOMS,
+            max_in_flight: 10,
+            max_pending,
+            batch_size: 10,
+            request_timeout: Duration::from_secs(10),
+        };
+        let all_peers = Arc::new(Peers::<FullPeerState>::new());
+        for peer in peers {
+            all_peers.insert(*peer);
+        }
+        SyncEngine::new(
+            config,
+            MockSync::default(),
+            all_peers,
+            Arc::new(UniqueId::new()),
+        )
+    }
+
+    fn request_from(engine: &SyncEngine<MockSync>, key: u64, peer: PeerId) {
+        engine
+            .sync_manager
+            .insert_in_flight(std::iter::once(KeyOrdered::new(key)), peer);
+    }
+
+    #[test]
+    fn test_deferred_only_from_requested_peer() {
+        let engine = engine(&[1, 2], 10);
+        let _future = engine.request(3);
+        request_from(&engine, 3, 1);
+
+        // not requested at all
+        match engine.receive(1, std::iter::once((4, 4))) {
+            Err(e) => match e.kind() {
+                ErrorKind::UnexpectedResponse => {}
+                kind => panic!("unexpected error {:?}", kind),
+            },
+            Ok(()) => panic!("unrequested response accepted"),
+        }
+        // requested from another peer
+        assert!(engine.receive(2, std::iter::once((3, 3))).is_err());
+        assert_eq!(engine.get_statistics().pending, 0);
+
+        assert!(engine.receive(1, std::iter::once((3, 3))).is_ok());
+        let stats = engine.get_statistics();
+        assert_eq!((stats.pending, stats.in_flight), (1, 0));
+
+        // validated once the root is known
+        engine.item_sync.roots.write().insert(3);
+        engine.validate_pending();
+        assert_eq!(engine.get_statistics().pending, 0);
+        assert_eq!(engine.verified.write().get(&3), Some(&3));
+    }
+
+    #[test]
+    fn test_pending_is_bounded() {
+        let engine = engine(&[1], 1);
+        request_from(&engine, 3, 1);
+        request_from(&engine, 4, 1);
+
+        assert!(engine.receive(1, vec![(3, 3), (4, 4)].into_iter()).is_ok());
+        let stats = engine.get_statistics();
+        assert_eq!((stats.pending, stats.waiting), (1, 1));
+    }
+
+    #[test]
+    fn test_invalid_pending_blacklists_peer() {
+        let engine = engine(&[1, 2], 10);
+        request_from(&engine, 3, 1);
+        assert!(engine.receive(1, std::iter::once((3, 4))).is_ok());
+
+        engine.item_sync.roots.write().insert(3);
+        engine.validate_pending();
+        assert!(engine.sync_manager.is_blacklisted(&3, &1));
+        // the peer is penalized in the next sync
+        let invalid = engine.invalid_pending.lock();
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].0, 1);
+        drop(invalid);
+        assert_eq!(engine.get_statistics().waiting, 1);
+        assert_eq!(*engine.retries.read().get(&3).unwrap(), 1);
+
+        // requested again from the other peer only
+        let requested = RwLock::new(vec![]);
+        let unavailable = engine.sync_manager.sync(10, 10, |peer, keys| {
+            requested.write().push((peer, keys));
+            Ok(())
+        });
+        assert!(unavailable.is_empty());
+        assert_eq!(*requested.read(), vec![(2, vec![3])]);
+    }
+
+    #[test]
+    fn test_blacklisted_by_all_peers_counts_as_retry() {
+        let engine = engine(&[1, 2], 10);
+        let _future = engine.request(3);
+        engine.sync_manager.blacklist(3, 1);
+        engine.sync_manager.blacklist(3, 2);
+
+        for retry in 1..=MAX_REQUEST_RETRIES {
+            let unavailable = engine.sync_manager.sync(10, 10, |_, _| {
+                panic!("blacklisted item requested");
+            });
+            assert_eq!(unavailable.len(), 1);
+            engine.retry(unavailable.into_iter());
+            assert_eq!(*engine.retries.read().get(&3).unwrap(), retry);
+            assert_eq!(engine.get_statistics().waiting, 1);
+        }
+
+        // given up, and the peers can be requested again later
+        let unavailable = engine.sync_manager.sync(10, 10, |_, _| Ok(()));
+        engine.retry(unavailable.into_iter());
+        assert_eq!(engine.get_statistics().waiting, 0);
+        assert!(engine.retries.read().is_empty());
+        assert!(!engine.sync_manager.is_blacklisted(&3, &1));
+    }
+}
@@ -4,8 +4,8 @@
 
 use parking_lot::RwLock;
 use std::{
-    cmp::Ord,
-    collections::HashMap,
+    cmp::{max, min, Ord},
+    collections::{HashMap, HashSet},
     fmt::Debug,
     hash::Hash,
     sync::Arc,
@@ -14,29 +14,82 @@ use std::{
 
 use crate::{
     light_protocol::{
-        common::{FullPeerState, Peers},
+        common::{affordable_items, request_cost, FullPeerState, Peers},
         Error,
     },
+    message::MsgId,
     network::PeerId,
+    parameters::light::{
+        MAX_REQUEST_BATCH_SIZE_FACTOR, TARGET_REQUEST_LATENCY,
+        TARGET_RESPONSE_BYTES,
+    },
 };
 
-use super::{HasKey, PriorityQueue};
+use super::{HasKey, PriorityQueue, Waiters};
 
 #[derive(Debug)]
 struct InFlightRequest<T> {
     pub item: T,
+    pub peer: PeerId,
     pub sent_at: Instant,
 }
 
 impl<T> InFlightRequest<T> {
-    pub fn new(item: T) -> Self {
+    pub fn new(item: T, peer: PeerId) -> Self {
         InFlightRequest {
             item,
+            peer,
             sent_at: Instant::now(),
         }
     }
 }
 
+/// The responsiveness of a peer, from which the batch size of the requests
+/// to it is derived.
+#[derive(Debug, Default)]
+struct PeerResponsiveness {
+    // smoothed latency of the responses in milliseconds
+    latency_ms: Option<u64>,
+
+    // smoothed size of the responses per item
+    bytes_per_item: Option<usize>,
+}
+
+impl PeerResponsiveness {
+    fn on_latency(&mut self, latency: Duration) {
+        let sample = latency.as_millis() as u64;
+        self.latency_ms = Some(match self.latency_ms {
+            Some(ms) => (ms * 7 + sample) / 8,
+            None => sample,
+        });
+    }
+
+    fn on_response(&mut self, num_items: usize, bytes: usize) {
+        let sample = bytes / max(num_items, 1);
+        self.bytes_per_item = Some(match self.bytes_per_item {
+            Some(b) => (b * 7 + sample) / 8,
+            None => sample,
+        });
+    }
+
+    /// Scale `batch_size` so that the responses arrive within
+    /// `TARGET_REQUEST_LATENCY` and are no larger than
+    /// `TARGET_RESPONSE_BYTES`.
+    fn batch_size(&self, batch_size: usize) -> usize {
+        let mut size = match self.latency_ms {
+            Some(ms) => {
+                let target_ms = TARGET_REQUEST_LATENCY.as_millis() as usize;
+                batch_size * target_ms / max(ms as usize, 1)
+            }
+            None => batch_size,
+        };
+        if let Some(bytes) = self.bytes_per_item {
+            size = min(size, TARGET_RESPONSE_BYTES / max(bytes, 1));
+        }
+        min(max(size, 1), batch_size * MAX_REQUEST_BATCH_SIZE_FACTOR)
+    }
+}
+
 pub struct SyncManager<Key, Item> {
     // headers requested but not received yet
     in_flight: RwLock<HashMap<Key, InFlightRequest<Item>>>,
@@ -46,6 +99,18 @@ pub struct SyncManager<Key, Item> {
 
     // priority queue of headers we need excluding the ones in `in_flight`
     waiting: RwLock<PriorityQueue<Key, Item>>,
+
+    // responsiveness of the peers requested, for adapting the batch size
+    responsiveness: RwLock<HashMap<PeerId, PeerResponsiveness>>,
+
+    // futures waiting for the items, if they are requested by callers
+    waiters: Arc<Waiters<Key>>,
+
+    // peers not to request the items from again, as they sent invalid ones
+    bad_peers: RwLock<HashMap<Key, HashSet<PeerId>>>,
+
+    // the id of the requests, for estimating their costs in credits
+    msg_id: MsgId,
 }
 
 impl<Key, Item> SyncManager<Key, Item>
@@ -53,7 +118,7 @@ where
     Key: Clone + Eq + Hash,
     Item: Debug + Clone + HasKey<Key> + Ord,
 {
-    pub fn new(peers: Arc<Peers<FullPeerState>>) -> Self {
+    pub fn new(peers: Arc<Peers<FullPeerState>>, msg_id: MsgId) -> Self {
         let in_flight = RwLock::new(HashMap::new());
         let waiting = RwLock::new(PriorityQueue::new());
 
@@ -61,25 +126,138 @@ where
             in_flight,
             peers,
             waiting,
+            responsiveness: RwLock::new(HashMap::new()),
+            waiters: Arc::new(Waiters::new()),
+            bad_peers: RwLock::new(HashMap::new()),
+            msg_id,
         }
     }
 
+    #[inline]
+    pub fn waiters(&self) -> Arc<Waiters<Key>> { self.waiters.clone() }
+
+    /// Stop requesting the items whose futures are all dropped.
+    #[inline]
+    pub fn remove_abandoned(&self) {
+        let abandoned = self.waiters.take_abandoned();
+        if !abandoned.is_empty() {
+            debug!("Removing {} abandoned items", abandoned.len());
+            self.waiting
+                .write()
+                .retain(|item| !abandoned.contains(&item.key()));
+
+            let mut bad_peers = self.bad_peers.write();
+            for key in &abandoned {
+                bad_peers.remove(key);
+            }
+        }
+    }
+
+    /// Do not request `key` from `peer` again.
+    #[inline]
+    pub fn blacklist(&self, key: Key, peer: PeerId) {
+        self.bad_peers.write().entry(key).or_default().insert(peer);
+    }
+
+    #[inline]
+    pub fn forget_bad_peers(&self, key: &Key) {
+        self.bad_peers.write().remove(key);
+    }
+
+    #[inline]
+    pub(super) fn is_blacklisted(&self, key: &Key, peer: &PeerId) -> bool {
+        self.bad_peers
+            .read()
+            .get(key)
+            .map_or(false, |peers| peers.contains(peer))
+    }
+
     #[inline]
     pub fn num_waiting(&self) -> usize { self.waiting.read().len() }
 
     #[inline]
     pub fn num_in_flight(&self) -> usize { self.in_flight.read().len() }
 
+    /// The peer that `key` is requested from, if it is in flight.
+    #[inline]
+    pub fn in_flight_peer(&self, key: &Key) -> Option<PeerId> {
+        self.in_flight.read().get(key).map(|req| req.peer)
+    }
+
     #[inline]
-    pub fn insert_in_flight<I>(&self, missing: I)
+    pub fn insert_in_flight<I>(&self, missing: I, peer: PeerId)
     where I: Iterator<Item = Item> {
-        let new = missing.map(|item| (item.key(), InFlightRequest::new(item)));
+        let new =
+            missing.map(|item| (item.key(), InFlightRequest::new(item, peer)));
         self.in_flight.write().extend(new);
     }
 
+    /// Remove the item received, and sample the latency of the peer it was
+    /// requested from. Returns false if the item is not in flight.
+    #[inline]
+    pub fn remove_in_flight(&self, key: &Key) -> bool {
+        let removed = self.in_flight.write().remove(&key);
+        match removed {
+            Some(req) => {
+                self.responsiveness
+                    .write()
+                    .entry(req.peer)
+                    .or_default()
+                    .on_latency(req.sent_at.elapsed());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Record the size of a response with `num_items` items from `peer`.
+    #[inline]
+    pub fn record_response_size(
+        &self, peer: PeerId, num_items: usize, bytes: usize,
+    ) {
+        self.responsiveness
+            .write()
+            .entry(peer)
+            .or_default()
+            .on_response(num_items, bytes);
+    }
+
+    /// The number of items to request from `peer` in a batch, adapted from
+    /// `batch_size` according to its responsiveness.
     #[inline]
-    pub fn remove_in_flight(&self, key: &Key) {
-        self.in_flight.write().remove(&key);
+    fn batch_size_of(&self, peer: &PeerId, batch_size: usize) -> usize {
+        match self.responsiveness.read().get(peer) {
+            Some(responsiveness) => responsiveness.batch_size(batch_size),
+            None => batch_size,
+        }
+    }
+
+    /// The number of items, at most `num_items`, that `peer` has enough
+    /// credits left to serve in a request, as estimated from the flow
+    /// control parameters it sent.
+    #[inline]
+    fn affordable_items_of(&self, peer: &PeerId, num_items: usize) -> usize {
+        match self.peers.get(peer) {
+            Some(state) => {
+                let credits = state.write().flow_control.credits();
+                affordable_items(self.msg_id, num_items, credits)
+            }
+            None => 0,
+        }
+    }
+
+    /// Deduct the cost of requesting `num_items` items from the credits we
+    /// estimate `peer` has left for us.
+    #[inline]
+    fn charge(&self, peer: &PeerId, num_items: usize) {
+        let cost = match request_cost(self.msg_id, num_items) {
+            Some(cost) => cost,
+            None => return,
+        };
+
+        if let Some(state) = self.peers.get(peer) {
+            state.write().flow_control.charge(cost);
+        }
     }
 
     #[inline]
@@ -117,44 +295,83 @@ where
         items
     }
 
+    /// Request the waiting items in batches from the peers. Returns the
+    /// items that all the peers sent invalid ones for, which are neither
+    /// requested nor waiting any more.
     pub fn sync(
         &self, max_in_flight: usize, batch_size: usize,
         request: impl Fn(PeerId, Vec<Key>) -> Result<(), Error>,
-    )
+    ) -> Vec<Item>
     {
+        self.remove_abandoned();
+
         // check if there are any peers available
-        if self.peers.is_empty() {
+        let peers = self.peers.all_peers_shuffled();
+        if peers.is_empty() {
             warn!("No peers available; aborting sync");
-            return;
+            return vec![];
         }
 
         // choose set of hashes to request
-        let num_to_request = max_in_flight - self.num_in_flight();
+        let num_to_request = max_in_flight.saturating_sub(self.num_in_flight());
 
         let items = match self.collect_to_request(num_to_request) {
-            ref hs if hs.is_empty() => return,
+            ref hs if hs.is_empty() => return vec![],
             hs => hs,
         };
 
-        // request items in batches from random peers
-        for batch in items.chunks(batch_size) {
-            let peer = match self.peers.random_peer() {
-                Some(peer) => peer,
-                None => {
-                    warn!("No peers available");
-                    self.insert_waiting(batch.to_owned().into_iter());
-
-                    // NOTE: cannot do early return as that way items
-                    // in subsequent batches would be lost
-                    continue;
-                }
-            };
+        // skip the items that all the peers sent invalid ones for
+        let (mut remaining, unavailable): (Vec<_>, Vec<_>) =
+            items.into_iter().partition(|item| {
+                peers
+                    .iter()
+                    .any(|peer| !self.is_blacklisted(&item.key(), peer))
+            });
+
+        // request items in batches from the peers in random order, each of
+        // which is sized according to the responsiveness of the peer and
+        // the credits it has left for us
+        let mut idle_peers = 0;
+
+        for peer in peers.iter().cycle() {
+            if remaining.is_empty() {
+                break;
+            }
+
+            // none of the peers can be requested any more for now
+            if idle_peers == peers.len() {
+                debug!("Peers out of credits, {} items wait", remaining.len());
+                self.insert_waiting(remaining.into_iter());
+                break;
+            }
+
+            // skip the items that the peer sent invalid ones for, which are
+            // requested from the other peers
+            let (mut batch, mut rest): (Vec<_>, Vec<_>) = remaining
+                .into_iter()
+                .partition(|item| !self.is_blacklisted(&item.key(), peer));
+            let peer = *peer;
+
+            let size = min(self.batch_size_of(&peer, batch_size), batch.len());
+            let size = self.affordable_items_of(&peer, size);
+
+            if size == 0 {
+                rest.extend(batch);
+                remaining = rest;
+                idle_peers += 1;
+                continue;
+            }
+            idle_peers = 0;
+
+            rest.extend(batch.split_off(size));
+            remaining = rest;
 
             let keys = batch.iter().map(|h| h.key()).collect();
 
             match request(peer, keys) {
                 Ok(_) => {
-                    self.insert_in_flight(batch.to_owned().into_iter());
+                    self.charge(&peer, batch.len());
+                    self.insert_in_flight(batch.into_iter(), peer);
                 }
                 Err(e) => {
                     warn!(
@@ -162,10 +379,12 @@ where
                         batch, peer, e
                     );
 
-                    self.insert_waiting(batch.to_owned().into_iter());
+                    self.insert_waiting(batch.into_iter());
                 }
             }
         }
+
+        unavailable
     }
 
     #[inline]
@@ -173,20 +392,26 @@ where
         let mut in_flight = self.in_flight.write();
 
         // collect timed-out requests
-        let items: Vec<_> = in_flight
+        let timed_out: Vec<_> = in_flight
             .iter()
             .filter_map(|(_hash, req)| match req.sent_at {
                 t if t.elapsed() < timeout => None,
-                _ => Some(req.item.clone()),
+                _ => Some((req.item.clone(), req.peer)),
             })
             .collect();
 
-        // remove requests from `in_flight`
-        for item in &items {
+        // remove requests from `in_flight`, and regard the timeout as the
+        // latency of the peers so that smaller batches are requested
+        let mut responsiveness = self.responsiveness.write();
+        for (item, peer) in &timed_out {
             in_flight.remove(&item.key());
+            responsiveness.entry(*peer).or_default().on_latency(timeout);
         }
 
-        items
+        // forget the disconnected peers
+        responsiveness.retain(|peer, _| self.peers.contains(peer));
+
+        timed_out.into_iter().map(|(item, _)| item).collect()
     }
 
     #[inline]
@@ -219,7 +444,10 @@ where
         let keys = items.iter().map(|h| h.key()).collect();
 
         match request(peer, keys) {
-            Ok(_) => self.insert_in_flight(items.into_iter()),
+            Ok(_) => {
+                self.charge(&peer, items.len());
+                self.insert_in_flight(items.into_iter(), peer);
+            }
             Err(e) => {
                 warn!("Failed to request {:?} from {:?}: {:?}", items, peer, e);
                 self.insert_waiting(items.into_iter());
@@ -227,3 +455,117 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{PeerResponsiveness, SyncManager};
+    use crate::{
+        light_protocol::{
+            common::{request_cost, FlowControl, FullPeerState, Peers},
+            handler::sync::common::KeyOrdered,
+            message::msgid,
+        },
+        parameters::light::{
+            MAX_REQUEST_BATCH_SIZE_FACTOR, TARGET_REQUEST_LATENCY,
+            TARGET_RESPONSE_BYTES,
+        },
+    };
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    fn manager_with_credits(
+        credits: &[u64],
+    ) -> SyncManager<u64, KeyOrdered<u64>> {
+        let peers = Arc::new(Peers::<FullPeerState>::new());
+        for (peer, max_credits) in credits.iter().enumerate() {
+            peers.insert(peer);
+            let state = peers.get(&peer).unwrap();
+            state.write().flow_control = FlowControl::new(*max_credits, 0);
+        }
+        SyncManager::new(peers, msgid::GET_BLOCK_HEADERS)
+    }
+
+    #[test]
+    fn test_sync_within_credits() {
+        let headers = msgid::GET_BLOCK_HEADERS;
+        let manager =
+            manager_with_credits(&[request_cost(headers, 3).unwrap()]);
+        manager.insert_waiting((0..10).map(KeyOrdered::new));
+
+        // only as many items as the peer has credits for are requested
+        let requested = Mutex::new(vec![]);
+        let unavailable = manager.sync(10, 10, |_, keys| {
+            requested.lock().push(keys.len());
+            Ok(())
+        });
+        assert!(unavailable.is_empty());
+        assert_eq!(*requested.lock(), vec![3]);
+        assert_eq!(manager.num_in_flight(), 3);
+        assert_eq!(manager.num_waiting(), 7);
+
+        // the credits are spent, so nothing is requested until they recharge
+        let unavailable = manager.sync(10, 10, |_, _| panic!("no credits"));
+        assert!(unavailable.is_empty());
+        assert_eq!(manager.num_in_flight(), 3);
+        assert_eq!(manager.num_waiting(), 7);
+    }
+
+    #[test]
+    fn test_sync_skips_peers_out_of_credits() {
+        let headers = msgid::GET_BLOCK_HEADERS;
+        let manager =
+            manager_with_credits(&[0, request_cost(headers, 10).unwrap()]);
+        manager.insert_waiting((0..10).map(KeyOrdered::new));
+
+        let requested = Mutex::new(vec![]);
+        manager.sync(10, 10, |peer, keys| {
+            requested.lock().push((peer, keys.len()));
+            Ok(())
+        });
+        assert_eq!(*requested.lock(), vec![(1, 10)]);
+        assert_eq!(manager.num_waiting(), 0);
+    }
+
+    #[test]
+    fn test_batch_size() {
+        let mut responsiveness = PeerResponsiveness::default();
+        assert_eq!(responsiveness.batch_size(10), 10);
+
+        // twice as slow as the target
+        responsiveness.on_latency(*TARGET_REQUEST_LATENCY * 2);
+        assert_eq!(responsiveness.batch_size(10), 5);
+
+        // the latency is smoothed
+        responsiveness.on_latency(*TARGET_REQUEST_LATENCY * 10);
+        let latency_ms = TARGET_REQUEST_LATENCY.as_millis() as u64;
+        assert_eq!(
+            responsiveness.latency_ms,
+            Some((latency_ms * 2 * 7 + latency_ms * 10) / 8)
+        );
+
+        // at least one item is requested
+        responsiveness.latency_ms = Some(u64::max_value() / 2);
+        assert_eq!(responsiveness.batch_size(10), 1);
+
+        // a fast peer is requested at most `MAX_REQUEST_BATCH_SIZE_FACTOR`
+        // times as many items
+        responsiveness.latency_ms = Some(1);
+        assert_eq!(
+            responsiveness.batch_size(10),
+            10 * MAX_REQUEST_BATCH_SIZE_FACTOR
+        );
+
+        // the responses are no larger than `TARGET_RESPONSE_BYTES`
+        responsiveness.on_response(2, TARGET_RESPONSE_BYTES);
+        assert_eq!(
+            responsiveness.bytes_per_item,
+            Some(TARGET_RESPONSE_BYTES / 2)
+        );
+        assert_eq!(responsiveness.batch_size(10), 2);
+        responsiveness.on_response(0, 0);
+        assert_eq!(
+            responsiveness.bytes_per_item,
+            Some(TARGET_RESPONSE_BYTES / 2 * 7 / 8)
+        );
+    }
+}
@@ -13,13 +13,13 @@ use std::{
 };
 
 use cfx_types::H256;
-use primitives::BlockHeader;
+use primitives::{BlockHeader, BlockHeaderBuilder, EpochNumber};
 
 use crate::{
     light_protocol::{
         common::{FullPeerState, Peers, UniqueId},
-        message::GetBlockHeaders,
-        Error,
+        message::{msgid, GetBlockHeaders},
+        Error, ErrorKind,
     },
     message::Message,
     network::{NetworkContext, PeerId},
@@ -30,12 +30,16 @@ use crate::{
     sync::SynchronizationGraph,
 };
 
-use super::common::{HasKey, SyncManager};
+use super::{
+    common::{HasKey, SyncManager},
+    Witnesses,
+};
 
 #[derive(Debug)]
 struct Statistics {
     duplicate_count: u64,
     in_flight: usize,
+    invalid_count: u64,
     waiting: usize,
 }
 
@@ -91,27 +95,37 @@ pub struct Headers {
     // shared synchronization graph
     graph: Arc<SynchronizationGraph>,
 
+    // number of headers that failed PoW or blame validation
+    invalid_count: AtomicU64,
+
     // series of unique request ids
     request_id_allocator: Arc<UniqueId>,
 
     // sync and request manager
     sync_manager: SyncManager<H256, MissingHeader>,
+
+    // witness sync manager
+    witnesses: Arc<Witnesses>,
 }
 
 impl Headers {
     pub fn new(
         graph: Arc<SynchronizationGraph>, peers: Arc<Peers<FullPeerState>>,
-        request_id_allocator: Arc<UniqueId>,
+        request_id_allocator: Arc<UniqueId>, witnesses: Arc<Witnesses>,
     ) -> Self
     {
         let duplicate_count = AtomicU64::new(0);
-        let sync_manager = SyncManager::new(peers.clone());
+        let invalid_count = AtomicU64::new(0);
+        let sync_manager =
+            SyncManager::new(peers.clone(), msgid::GET_BLOCK_HEADERS);
 
         Headers {
             duplicate_count,
             graph,
+            invalid_count,
             sync_manager,
             request_id_allocator,
+            witnesses,
         }
     }
 
@@ -123,6 +137,7 @@ impl Headers {
         Statistics {
             duplicate_count: self.duplicate_count.load(Ordering::Relaxed),
             in_flight: self.sync_manager.num_in_flight(),
+            invalid_count: self.invalid_count.load(Ordering::Relaxed),
             waiting: self.sync_manager.num_waiting(),
         }
     }
@@ -160,9 +175,78 @@ impl Headers {
         );
     }
 
-    pub fn receive<I>(&self, headers: I)
+    /// Validate the PoW quality, the blame range and the deferred roots of
+    /// `header`. The state of the current checkpoint is trusted, so a header
+    /// after the checkpoint cannot blame the checkpoint or any of its
+    /// ancestors.
+    fn validate_header(&self, header: &BlockHeader) -> Result<(), Error> {
+        let hash = header.hash();
+
+        if let Err(e) = self.graph.verification_config.verify_pow(header) {
+            debug!("Header {:?} has invalid PoW: {:?}", hash, e);
+            return Err(ErrorKind::InvalidHeader.into());
+        }
+
+        let height = header.height();
+        let blame = header.blame() as u64;
+
+        if blame > 0 && blame >= height {
+            debug!(
+                "Header {:?} blames beyond genesis (height={}, blame={})",
+                hash, height, blame
+            );
+            return Err(ErrorKind::InvalidHeader.into());
+        }
+
+        let (_, checkpoint) =
+            self.graph.get_genesis_hash_and_height_in_current_era();
+
+        if blame > 0 && height > checkpoint && height - blame <= checkpoint {
+            debug!(
+                "Header {:?} blames checkpoint {} (height={}, blame={})",
+                hash, checkpoint, height, blame
+            );
+            return Err(ErrorKind::InvalidHeader.into());
+        }
+
+        // The epochs of the deferred roots are only comparable with the
+        // verified ones if the header extends the pivot chain.
+        let parent = match self
+            .graph
+            .data_man
+            .block_header_by_hash(header.parent_hash())
+        {
+            Some(parent) => parent,
+            None => return Ok(()),
+        };
+
+        if !self.is_pivot(&parent) {
+            return Ok(());
+        }
+
+        validate_deferred_roots(
+            header,
+            &parent,
+            self.graph.consensus.deferred_state_epoch_count(),
+            |epoch| self.witnesses.root_hashes_of(epoch),
+        )
+    }
+
+    #[inline]
+    fn is_pivot(&self, header: &BlockHeader) -> bool {
+        let epoch = EpochNumber::Number(header.height());
+
+        match self.graph.consensus.get_hash_from_epoch_number(epoch) {
+            Ok(pivot) => pivot == header.hash(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn receive<I>(&self, headers: I) -> Result<(), Error>
     where I: Iterator<Item = BlockHeader> {
         let mut missing = HashSet::new();
+        let mut blaming = vec![];
+        let mut num_invalid = 0;
 
         // TODO(thegaram): validate header timestamps
         for header in headers {
@@ -177,6 +261,14 @@ impl Headers {
                 continue;
             }
 
+            // check PoW and blame before touching the graph
+            if let Err(e) = self.validate_header(&header) {
+                warn!("Received invalid header {:?}: {:?}", hash, e);
+                self.invalid_count.fetch_add(1, Ordering::Relaxed);
+                num_invalid += 1;
+                continue;
+            }
+
             // insert into graph
             let (valid, _) = self.graph.insert_block_header(
                 &mut header.clone(),
@@ -190,6 +282,11 @@ impl Headers {
                 continue;
             }
 
+            // blaming pivot headers are witnesses of earlier headers
+            if header.blame() > 0 && self.is_pivot(&header) {
+                blaming.push(header.height());
+            }
+
             // store missing dependencies
             missing.insert(*header.parent_hash());
 
@@ -200,6 +297,15 @@ impl Headers {
 
         let missing = missing.into_iter();
         self.request(missing, HashSource::Dependency);
+
+        // let the witness sync manager fetch the correct roots
+        self.witnesses.request(blaming.into_iter());
+
+        if num_invalid > 0 {
+            return Err(ErrorKind::InvalidHeader.into());
+        }
+
+        Ok(())
     }
 
     #[inline]
@@ -240,12 +346,109 @@ impl Headers {
     }
 }
 
+/// The deferred roots that a header at `height` with `blame` should have,
+/// computed from the verified roots of the epochs it covers, or `None` if
+/// they are not all verified yet.
+pub(super) fn expected_deferred_roots<F>(
+    height: u64, blame: u64, deferred_state_epoch_count: u64,
+    verified_roots_of: &F,
+) -> Option<(H256, H256, H256)>
+where
+    F: Fn(u64) -> Option<(H256, H256, H256)>,
+{
+    let roots = (0..=blame)
+        .map(|ii| {
+            let epoch = height
+                .saturating_sub(ii)
+                .saturating_sub(deferred_state_epoch_count);
+            verified_roots_of(epoch)
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    if blame == 0 {
+        return Some(roots[0]);
+    }
+
+    let vec_root = |hashes: Vec<H256>| {
+        BlockHeaderBuilder::compute_blame_state_root_vec_root(hashes)
+    };
+
+    Some((
+        vec_root(roots.iter().map(|r| r.0).collect()),
+        vec_root(roots.iter().map(|r| r.1).collect()),
+        vec_root(roots.iter().map(|r| r.2).collect()),
+    ))
+}
+
+#[inline]
+pub(super) fn deferred_roots_of(header: &BlockHeader) -> (H256, H256, H256) {
+    (
+        *header.deferred_state_root(),
+        *header.deferred_receipts_root(),
+        *header.deferred_logs_bloom_hash(),
+    )
+}
+
+/// Validate the blame and the deferred roots of `header` against the roots
+/// verified through witnesses, as far as they are available. A header must
+/// blame its parent if and only if the deferred roots of the parent are
+/// incorrect, and its own deferred roots must match the verified ones.
+fn validate_deferred_roots<F>(
+    header: &BlockHeader, parent: &BlockHeader, deferred_state_epoch_count: u64,
+    verified_roots_of: F,
+) -> Result<(), Error>
+where
+    F: Fn(u64) -> Option<(H256, H256, H256)>,
+{
+    let hash = header.hash();
+    let blame = header.blame() as u64;
+
+    if let Some(expected) = expected_deferred_roots(
+        parent.height(),
+        parent.blame() as u64,
+        deferred_state_epoch_count,
+        &verified_roots_of,
+    ) {
+        let parent_correct = deferred_roots_of(parent) == expected;
+
+        if parent_correct == (blame > 0) {
+            debug!(
+                "Header {:?} has invalid blame {} (parent correct: {})",
+                hash, blame, parent_correct
+            );
+            return Err(ErrorKind::InvalidHeader.into());
+        }
+    }
+
+    if let Some(expected) = expected_deferred_roots(
+        header.height(),
+        blame,
+        deferred_state_epoch_count,
+        &verified_roots_of,
+    ) {
+        if deferred_roots_of(header) != expected {
+            debug!(
+                "Header {:?} has invalid deferred roots: expected={:?}",
+                hash, expected
+            );
+            return Err(ErrorKind::InvalidHeader.into());
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{super::common::PriorityQueue, HashSource, MissingHeader};
+    use super::{
+        super::common::PriorityQueue, validate_deferred_roots, HashSource,
+        MissingHeader,
+    };
     use cfx_types::H256;
+    use primitives::{BlockHeader, BlockHeaderBuilder};
     use rand::Rng;
     use std::{
+        collections::HashMap,
         ops::Sub,
         time::{Duration, Instant},
     };
@@ -389,4 +592,103 @@ mod tests {
         assert_deep_equal(queue.pop(), Some(h0));
         assert_deep_equal(queue.pop(), None);
     }
+
+    const DEFERRED_STATE_EPOCH_COUNT: u64 = 5;
+
+    fn roots_of_epoch(epoch: u64) -> (H256, H256, H256) {
+        (
+            H256::from_low_u64_be(epoch * 3),
+            H256::from_low_u64_be(epoch * 3 + 1),
+            H256::from_low_u64_be(epoch * 3 + 2),
+        )
+    }
+
+    fn header(
+        height: u64, blame: u32, roots: (H256, H256, H256),
+    ) -> BlockHeader {
+        BlockHeaderBuilder::new()
+            .with_height(height)
+            .with_blame(blame)
+            .with_deferred_state_root(roots.0)
+            .with_deferred_receipts_root(roots.1)
+            .with_deferred_logs_bloom_hash(roots.2)
+            .build()
+    }
+
+    /// The roots of a header at `height` blaming its parent, i.e. aggregated
+    /// over the epochs of the header and its parent.
+    fn blaming_roots(height: u64) -> (H256, H256, H256) {
+        let epoch = height - DEFERRED_STATE_EPOCH_COUNT;
+        let (own, parent) = (roots_of_epoch(epoch), roots_of_epoch(epoch - 1));
+        let vec_root = |a: H256, b: H256| {
+            BlockHeaderBuilder::compute_blame_state_root_vec_root(vec![a, b])
+        };
+        (
+            vec_root(own.0, parent.0),
+            vec_root(own.1, parent.1),
+            vec_root(own.2, parent.2),
+        )
+    }
+
+    fn validate(
+        header: &BlockHeader, parent: &BlockHeader,
+        verified: &HashMap<u64, (H256, H256, H256)>,
+    ) -> bool
+    {
+        validate_deferred_roots(
+            header,
+            parent,
+            DEFERRED_STATE_EPOCH_COUNT,
+            |epoch| verified.get(&epoch).cloned(),
+        )
+        .is_ok()
+    }
+
+    #[test]
+    fn test_validate_deferred_roots() {
+        let roots: HashMap<_, _> =
+            (0..10).map(|epoch| (epoch, roots_of_epoch(epoch))).collect();
+        let forged = (H256::random(), H256::random(), H256::random());
+
+        // The parent has the correct roots of epoch 5.
+        let parent = header(10, 0, roots_of_epoch(5));
+        assert!(validate(&header(11, 0, roots_of_epoch(6)), &parent, &roots));
+
+        // Forged deferred roots.
+        assert!(!validate(&header(11, 0, forged), &parent, &roots));
+        assert!(!validate(&header(11, 0, roots_of_epoch(5)), &parent, &roots));
+
+        // Forged blame of a correct parent, even with consistent roots.
+        assert!(!validate(&header(11, 1, blaming_roots(11)), &parent, &roots));
+
+        // The parent has forged roots, so it must be blamed with the roots
+        // of both epochs.
+        let parent = header(10, 0, forged);
+        assert!(validate(&header(11, 1, blaming_roots(11)), &parent, &roots));
+        assert!(!validate(&header(11, 0, roots_of_epoch(6)), &parent, &roots));
+        assert!(!validate(&header(11, 1, roots_of_epoch(6)), &parent, &roots));
+
+        // A blamed parent is correct if its aggregated roots are.
+        let parent = header(10, 1, blaming_roots(10));
+        assert!(validate(&header(11, 0, roots_of_epoch(6)), &parent, &roots));
+        assert!(!validate(&header(11, 1, blaming_roots(11)), &parent, &roots));
+    }
+
+    #[test]
+    fn test_validate_deferred_roots_unverified() {
+        // Nothing can be checked without the verified roots.
+        let verified = HashMap::new();
+        let forged = (H256::random(), H256::random(), H256::random());
+        let parent = header(10, 0, forged);
+        assert!(validate(&header(11, 0, forged), &parent, &verified));
+        assert!(validate(&header(11, 1, forged), &parent, &verified));
+
+        // The parent is checked even if the epoch of the header is not
+        // verified yet.
+        let verified: HashMap<_, _> =
+            (0..6).map(|epoch| (epoch, roots_of_epoch(epoch))).collect();
+        let parent = header(10, 0, roots_of_epoch(5));
+        assert!(validate(&header(11, 0, forged), &parent, &verified));
+        assert!(!validate(&header(11, 1, forged), &parent, &verified));
+    }
 }
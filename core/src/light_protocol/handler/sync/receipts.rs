@@ -2,56 +2,87 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
-extern crate futures;
-extern crate lru_time_cache;
-
-use futures::Future;
-use lru_time_cache::LruCache;
-use parking_lot::RwLock;
 use std::sync::Arc;
 
 use crate::{
     light_protocol::{
         common::{FullPeerState, Peers, UniqueId},
-        message::{GetReceipts, ReceiptsWithEpoch},
+        message::{msgid, GetReceipts, ReceiptsWithEpoch},
         Error, ErrorKind,
     },
     message::Message,
     network::{NetworkContext, PeerId},
     parameters::light::{
-        CACHE_TIMEOUT, MAX_RECEIPTS_IN_FLIGHT, RECEIPT_REQUEST_BATCH_SIZE,
+        MAX_RECEIPTS_IN_FLIGHT, RECEIPT_REQUEST_BATCH_SIZE,
         RECEIPT_REQUEST_TIMEOUT,
     },
-    primitives::{BlockHeaderBuilder, Receipt},
+    primitives::Receipt,
 };
 
 use super::{
-    common::{FutureItem, KeyOrdered, SyncManager},
+    common::{
+        FutureItem, ItemSync, KeyOrdered, SyncConfig, SyncEngine, Validated,
+    },
     witnesses::Witnesses,
 };
 
-#[derive(Debug)]
-struct Statistics {
-    cached: usize,
-    in_flight: usize,
-    waiting: usize,
-}
-
 // prioritize higher epochs
 type MissingReceipts = KeyOrdered<u64>;
 
-pub struct Receipts {
-    // series of unique request ids
-    request_id_allocator: Arc<UniqueId>,
+struct ReceiptSync {
+    // witness sync manager
+    witnesses: Arc<Witnesses>,
+}
 
-    // sync and request manager
-    sync_manager: SyncManager<u64, MissingReceipts>,
+impl ItemSync for ReceiptSync {
+    type Key = u64;
+    type Missing = MissingReceipts;
+    type Response = ReceiptsWithEpoch;
+    type Value = Vec<Vec<Receipt>>;
 
-    // epoch receipts received from full node
-    verified: Arc<RwLock<LruCache<u64, Vec<Vec<Receipt>>>>>,
+    fn missing(epoch: u64) -> MissingReceipts { MissingReceipts::new(epoch) }
 
-    // witness sync manager
-    witnesses: Arc<Witnesses>,
+    fn key_of(response: &ReceiptsWithEpoch) -> Option<u64> {
+        Some(response.epoch)
+    }
+
+    fn request_message(
+        &self, request_id: u64, epochs: Vec<u64>,
+    ) -> Box<dyn Message> {
+        Box::new(GetReceipts { request_id, epochs })
+    }
+
+    fn validate(
+        &self, _peer: PeerId, response: &ReceiptsWithEpoch,
+    ) -> Result<Validated<u64, Vec<Vec<Receipt>>>, Error> {
+        let epoch = response.epoch;
+
+        // retrieve local receipts root
+        let local = match self.witnesses.root_hashes_of(epoch) {
+            Some((_, receipts_root, _)) => receipts_root,
+            None => return Ok(Validated::Deferred(epoch)),
+        };
+
+        // the received receipts root is computed from the raw receipts, which
+        // are only decoded after they are validated
+        let received = response.receipts_root();
+
+        // check
+        if received != local {
+            warn!(
+                "Receipt validation failed, epoch={}, received={:?}, local={:?}",
+                epoch, received, local
+            );
+            return Err(ErrorKind::InvalidReceipts.into());
+        }
+
+        Ok(Validated::Items(vec![(epoch, response.receipts()?)]))
+    }
+}
+
+pub struct Receipts {
+    // sync engine of the epoch receipts
+    engine: SyncEngine<ReceiptSync>,
 }
 
 impl Receipts {
@@ -60,144 +91,71 @@ impl Receipts {
         witnesses: Arc<Witnesses>,
     ) -> Self
     {
-        let sync_manager = SyncManager::new(peers.clone());
-
-        let cache = LruCache::with_expiry_duration(*CACHE_TIMEOUT);
-        let verified = Arc::new(RwLock::new(cache));
+        let config = SyncConfig {
+            name: "receipt",
+            msg_id: msgid::RECEIPTS,
+            request_msg_id: msgid::GET_RECEIPTS,
+            max_in_flight: MAX_RECEIPTS_IN_FLIGHT,
+            max_pending: MAX_RECEIPTS_IN_FLIGHT,
+            batch_size: RECEIPT_REQUEST_BATCH_SIZE,
+            request_timeout: *RECEIPT_REQUEST_TIMEOUT,
+        };
 
-        Receipts {
+        let engine = SyncEngine::new(
+            config,
+            ReceiptSync { witnesses },
+            peers,
             request_id_allocator,
-            sync_manager,
-            verified,
-            witnesses,
-        }
-    }
+        );
 
-    #[inline]
-    fn get_statistics(&self) -> Statistics {
-        Statistics {
-            cached: self.verified.read().len(),
-            in_flight: self.sync_manager.num_in_flight(),
-            waiting: self.sync_manager.num_waiting(),
-        }
+        Receipts { engine }
     }
 
+    /// Request the receipts of `epoch`. The epoch is no longer requested
+    /// once all the futures returned for it are dropped.
     #[inline]
-    pub fn request(
-        &self, epoch: u64,
-    ) -> impl Future<Item = Vec<Vec<Receipt>>, Error = Error> {
+    pub fn request(&self, epoch: u64) -> FutureItem<u64, Vec<Vec<Receipt>>> {
         if epoch == 0 {
-            self.verified.write().insert(0, vec![]);
+            self.engine.insert_verified(0, vec![]);
         }
 
-        if !self.verified.read().contains_key(&epoch) {
-            let missing = MissingReceipts::new(epoch);
-            self.sync_manager.insert_waiting(std::iter::once(missing));
-        }
+        self.engine.request(epoch)
+    }
 
-        FutureItem::new(epoch, self.verified.clone())
+    /// Record the size of a response from `peer` for adapting the batch
+    /// size of the requests to it.
+    #[inline]
+    pub fn record_response_size(
+        &self, peer: PeerId, num_items: usize, bytes: usize,
+    ) {
+        self.engine.record_response_size(peer, num_items, bytes);
     }
 
     #[inline]
     pub fn receive(
-        &self, receipts: impl Iterator<Item = ReceiptsWithEpoch>,
+        &self, peer: PeerId, receipts: impl Iterator<Item = ReceiptsWithEpoch>,
     ) -> Result<(), Error> {
-        for ReceiptsWithEpoch { epoch, receipts } in receipts {
-            info!("Validating receipts {:?} with epoch {}", receipts, epoch);
-            self.validate_receipts(epoch, &receipts)?;
-
-            self.verified.write().insert(epoch, receipts);
-            self.sync_manager.remove_in_flight(&epoch);
-        }
-
-        Ok(())
+        self.engine.receive(peer, receipts)
     }
 
+    /// Validate the receipts of `epoch` received from `peer` as a part of
+    /// another response, which requires the witness roots of `epoch`.
     #[inline]
     pub fn receive_single(
-        &self, epoch: u64, receipts: Vec<Vec<Receipt>>,
+        &self, peer: PeerId, epoch: u64, receipts: Vec<Vec<Receipt>>,
     ) -> Result<(), Error> {
-        let item = ReceiptsWithEpoch { epoch, receipts };
-        self.receive(std::iter::once(item))
+        let item = ReceiptsWithEpoch::new(epoch, &receipts);
+        self.engine.receive_now(peer, item)
     }
 
+    /// Validate the receipts received before the witness roots of their
+    /// epochs.
     #[inline]
-    pub fn clean_up(&self) {
-        // remove timeout in-flight requests
-        let timeout = *RECEIPT_REQUEST_TIMEOUT;
-        let receiptss = self.sync_manager.remove_timeout_requests(timeout);
-        self.sync_manager.insert_waiting(receiptss.into_iter());
-
-        // trigger cache cleanup
-        self.verified.write().get(&Default::default());
-    }
+    pub fn validate_pending(&self) { self.engine.validate_pending(); }
 
     #[inline]
-    fn send_request(
-        &self, io: &dyn NetworkContext, peer: PeerId, epochs: Vec<u64>,
-    ) -> Result<(), Error> {
-        info!("send_request peer={:?} epochs={:?}", peer, epochs);
-
-        if epochs.is_empty() {
-            return Ok(());
-        }
-
-        let msg: Box<dyn Message> = Box::new(GetReceipts {
-            request_id: self.request_id_allocator.next(),
-            epochs,
-        });
-
-        msg.send(io, peer)?;
-        Ok(())
-    }
+    pub fn clean_up(&self) { self.engine.clean_up(); }
 
     #[inline]
-    pub fn sync(&self, io: &dyn NetworkContext) {
-        info!("receipt sync statistics: {:?}", self.get_statistics());
-
-        self.sync_manager.sync(
-            MAX_RECEIPTS_IN_FLIGHT,
-            RECEIPT_REQUEST_BATCH_SIZE,
-            |peer, epochs| self.send_request(io, peer, epochs),
-        );
-    }
-
-    #[inline]
-    fn validate_receipts(
-        &self, epoch: u64, receipts: &Vec<Vec<Receipt>>,
-    ) -> Result<(), Error> {
-        // calculate received receipts root
-        // convert Vec<Vec<Receipt>> -> Vec<Arc<Vec<Receipt>>>
-        // for API compatibility
-        let rs = receipts
-            .clone()
-            .into_iter()
-            .map(|rs| Arc::new(rs))
-            .collect();
-
-        let received = BlockHeaderBuilder::compute_block_receipts_root(&rs);
-
-        // retrieve local receipts root
-        let local = match self.witnesses.root_hashes_of(epoch) {
-            Some((_, receipts_root, _)) => receipts_root,
-            None => {
-                warn!(
-                    "Receipt root not found, epoch={}, receipts={:?}",
-                    epoch, receipts
-                );
-                return Err(ErrorKind::InternalError.into());
-            }
-        };
-
-        // check
-        if received != local {
-            warn!(
-                "Receipt validation failed, received={:?}, local={:?}",
-                received, local
-            );
-            return Err(ErrorKind::InvalidBloom.into());
-        }
-
-        Ok(())
-    }
+    pub fn sync(&self, io: &dyn NetworkContext) { self.engine.sync(io); }
 }
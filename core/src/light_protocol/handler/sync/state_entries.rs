@@ -3,30 +3,26 @@
 // See http://www.gnu.org/licenses/
 
 extern crate futures;
-extern crate lru_time_cache;
 
 use futures::Future;
-use lru_time_cache::LruCache;
-use parking_lot::RwLock;
 use std::sync::Arc;
 
 use crate::{
     light_protocol::{
         common::{FullPeerState, Peers, UniqueId},
-        message::{GetStateEntries, StateEntryWithKey, StateKey},
+        message::{msgid, GetStateEntries, StateEntryWithKey, StateKey},
         Error, ErrorKind,
     },
     message::Message,
     network::{NetworkContext, PeerId},
     parameters::light::{
-        CACHE_TIMEOUT, MAX_STATE_ENTRIES_IN_FLIGHT,
-        STATE_ENTRY_REQUEST_BATCH_SIZE, STATE_ENTRY_REQUEST_TIMEOUT,
+        MAX_STATE_ENTRIES_IN_FLIGHT, STATE_ENTRY_REQUEST_BATCH_SIZE,
+        STATE_ENTRY_REQUEST_TIMEOUT,
     },
-    storage::StateProof,
 };
 
 use super::{
-    common::{FutureItem, SyncManager, TimeOrdered},
+    common::{ItemSync, SyncConfig, SyncEngine, TimeOrdered, Validated},
     state_roots::StateRoots,
 };
 
@@ -44,27 +40,58 @@ impl PartialOrd for StateKey {
     }
 }
 
-#[derive(Debug)]
-struct Statistics {
-    cached: usize,
-    in_flight: usize,
-    waiting: usize,
-}
-
 type MissingStateEntry = TimeOrdered<StateKey>;
 
-pub struct StateEntries {
-    // series of unique request ids
-    request_id_allocator: Arc<UniqueId>,
-
+struct StateEntrySync {
     // state_root sync manager
     state_roots: Arc<StateRoots>,
+}
+
+impl ItemSync for StateEntrySync {
+    type Key = StateKey;
+    type Missing = MissingStateEntry;
+    type Response = StateEntryWithKey;
+    type Value = StateEntry;
+
+    fn missing(key: StateKey) -> MissingStateEntry {
+        MissingStateEntry::new(key)
+    }
 
-    // sync and request manager
-    sync_manager: SyncManager<StateKey, MissingStateEntry>,
+    fn key_of(response: &StateEntryWithKey) -> Option<StateKey> {
+        Some(response.key.clone())
+    }
+
+    fn request_message(
+        &self, request_id: u64, keys: Vec<StateKey>,
+    ) -> Box<dyn Message> {
+        Box::new(GetStateEntries { request_id, keys })
+    }
+
+    fn validate(
+        &self, _peer: PeerId, response: &StateEntryWithKey,
+    ) -> Result<Validated<StateKey, StateEntry>, Error> {
+        let StateEntryWithKey { key, entry, proof } = response;
 
-    // state entries received from full node
-    verified: Arc<RwLock<LruCache<StateKey, StateEntry>>>,
+        // retrieve local state root
+        let root = match self.state_roots.state_root_of(key.epoch) {
+            Some(root) => root,
+            None => return Ok(Validated::Deferred(key.clone())),
+        };
+
+        // validate proof
+        let value = entry.as_ref().map(|v| &**v);
+        if !proof.is_valid_kv(&key.key, value, root) {
+            info!("Invalid proof");
+            return Err(ErrorKind::InvalidStateProof.into());
+        }
+
+        Ok(Validated::Items(vec![(key.clone(), entry.clone())]))
+    }
+}
+
+pub struct StateEntries {
+    // sync engine of the state entries
+    engine: SyncEngine<StateEntrySync>,
 }
 
 impl StateEntries {
@@ -73,125 +100,43 @@ impl StateEntries {
         request_id_allocator: Arc<UniqueId>,
     ) -> Self
     {
-        let sync_manager = SyncManager::new(peers.clone());
-
-        let cache = LruCache::with_expiry_duration(*CACHE_TIMEOUT);
-        let verified = Arc::new(RwLock::new(cache));
+        let config = SyncConfig {
+            name: "state entry",
+            msg_id: msgid::STATE_ENTRIES,
+            request_msg_id: msgid::GET_STATE_ENTRIES,
+            max_in_flight: MAX_STATE_ENTRIES_IN_FLIGHT,
+            max_pending: MAX_STATE_ENTRIES_IN_FLIGHT,
+            batch_size: STATE_ENTRY_REQUEST_BATCH_SIZE,
+            request_timeout: *STATE_ENTRY_REQUEST_TIMEOUT,
+        };
 
-        StateEntries {
+        let engine = SyncEngine::new(
+            config,
+            StateEntrySync { state_roots },
+            peers,
             request_id_allocator,
-            sync_manager,
-            verified,
-            state_roots,
-        }
-    }
+        );
 
-    #[inline]
-    fn get_statistics(&self) -> Statistics {
-        Statistics {
-            cached: self.verified.read().len(),
-            in_flight: self.sync_manager.num_in_flight(),
-            waiting: self.sync_manager.num_waiting(),
-        }
+        StateEntries { engine }
     }
 
     #[inline]
     pub fn request_now(
         &self, io: &dyn NetworkContext, epoch: u64, key: Vec<u8>,
     ) -> impl Future<Item = StateEntry, Error = Error> {
-        let key = StateKey { epoch, key };
-
-        if !self.verified.read().contains_key(&key) {
-            let missing = std::iter::once(MissingStateEntry::new(key.clone()));
-
-            self.sync_manager.request_now(missing, |peer, keys| {
-                self.send_request(io, peer, keys)
-            });
-        }
-
-        FutureItem::new(key, self.verified.clone())
+        self.engine.request_now(io, StateKey { epoch, key })
     }
 
     #[inline]
     pub fn receive(
-        &self, entries: impl Iterator<Item = StateEntryWithKey>,
+        &self, peer: PeerId, entries: impl Iterator<Item = StateEntryWithKey>,
     ) -> Result<(), Error> {
-        for StateEntryWithKey { key, entry, proof } in entries {
-            info!("Validating state entry {:?} with key {:?}", entry, key);
-            self.validate_state_entry(key.epoch, &key.key, &entry, proof)?;
-
-            self.verified.write().insert(key.clone(), entry);
-            self.sync_manager.remove_in_flight(&key);
-        }
-
-        Ok(())
-    }
-
-    #[inline]
-    pub fn clean_up(&self) {
-        // remove timeout in-flight requests
-        let timeout = *STATE_ENTRY_REQUEST_TIMEOUT;
-        let entries = self.sync_manager.remove_timeout_requests(timeout);
-        self.sync_manager.insert_waiting(entries.into_iter());
-
-        // trigger cache cleanup
-        self.verified.write().get(&Default::default());
+        self.engine.receive(peer, entries)
     }
 
     #[inline]
-    fn send_request(
-        &self, io: &dyn NetworkContext, peer: PeerId, keys: Vec<StateKey>,
-    ) -> Result<(), Error> {
-        info!("send_request peer={:?} keys={:?}", peer, keys);
-
-        if keys.is_empty() {
-            return Ok(());
-        }
-
-        let msg: Box<dyn Message> = Box::new(GetStateEntries {
-            request_id: self.request_id_allocator.next(),
-            keys,
-        });
-
-        msg.send(io, peer)?;
-        Ok(())
-    }
+    pub fn clean_up(&self) { self.engine.clean_up(); }
 
     #[inline]
-    pub fn sync(&self, io: &dyn NetworkContext) {
-        info!("state entry sync statistics: {:?}", self.get_statistics());
-
-        self.sync_manager.sync(
-            MAX_STATE_ENTRIES_IN_FLIGHT,
-            STATE_ENTRY_REQUEST_BATCH_SIZE,
-            |peer, keys| self.send_request(io, peer, keys),
-        );
-    }
-
-    #[inline]
-    fn validate_state_entry(
-        &self, epoch: u64, key: &Vec<u8>, value: &Option<Vec<u8>>,
-        proof: StateProof,
-    ) -> Result<(), Error>
-    {
-        // retrieve local state root
-        let root = match self.state_roots.state_root_of(epoch) {
-            Some(root) => root.clone(),
-            None => {
-                warn!(
-                    "State root not found, epoch={}, key={:?}, value={:?}, proof={:?}",
-                    epoch, key, value, proof
-                );
-                return Err(ErrorKind::InternalError.into());
-            }
-        };
-
-        // validate proof
-        if !proof.is_valid_kv(key, value.as_ref().map(|v| &**v), root) {
-            info!("Invalid proof");
-            return Err(ErrorKind::InvalidStateProof.into());
-        }
-
-        Ok(())
-    }
+    pub fn sync(&self, io: &dyn NetworkContext) { self.engine.sync(io); }
 }
@@ -14,7 +14,7 @@ use std::sync::Arc;
 use crate::{
     light_protocol::{
         common::{FullPeerState, Peers, UniqueId},
-        message::{GetStateRoots, StateRootWithEpoch},
+        message::{msgid, GetStateRoots, StateRootWithEpoch},
         Error, ErrorKind,
     },
     message::Message,
@@ -57,9 +57,9 @@ impl StateRoots {
     pub fn new(
         peers: Arc<Peers<FullPeerState>>, request_id_allocator: Arc<UniqueId>,
         witnesses: Arc<Witnesses>,
-    ) -> Self
-    {
-        let sync_manager = SyncManager::new(peers.clone());
+    ) -> Self {
+        let sync_manager =
+            SyncManager::new(peers.clone(), msgid::GET_STATE_ROOTS);
 
         let cache = LruCache::with_expiry_duration(*CACHE_TIMEOUT);
         let verified = Arc::new(RwLock::new(cache));
@@ -3,12 +3,9 @@
 // See http://www.gnu.org/licenses/
 
 extern crate futures;
-extern crate lru_time_cache;
 
 use cfx_types::H256;
 use futures::Future;
-use lru_time_cache::LruCache;
-use parking_lot::RwLock;
 use primitives::{Receipt, SignedTransaction, TransactionAddress};
 use std::sync::Arc;
 
@@ -16,35 +13,28 @@ use crate::{
     consensus::ConsensusGraph,
     light_protocol::{
         common::{FullPeerState, LedgerInfo, Peers, UniqueId},
-        message::{GetTxInfos, TxInfo},
+        message::{msgid, GetTxInfos, TxInfo},
         Error, ErrorKind,
     },
     message::Message,
     network::{NetworkContext, PeerId},
     parameters::light::{
-        CACHE_TIMEOUT, MAX_TX_INFOS_IN_FLIGHT, TX_INFO_REQUEST_BATCH_SIZE,
+        MAX_TX_INFOS_IN_FLIGHT, TX_INFO_REQUEST_BATCH_SIZE,
         TX_INFO_REQUEST_TIMEOUT,
     },
 };
 
 use super::{
-    common::{FutureItem, SyncManager, TimeOrdered},
+    common::{ItemSync, SyncConfig, SyncEngine, TimeOrdered, Validated},
     BlockTxs, Receipts,
 };
 
-#[derive(Debug)]
-struct Statistics {
-    cached: usize,
-    in_flight: usize,
-    waiting: usize,
-}
-
 // prioritize earlier requests
 type MissingTxInfo = TimeOrdered<H256>;
 
 type TxInfoValidated = (SignedTransaction, Receipt, TransactionAddress);
 
-pub struct TxInfos {
+struct TxInfoSync {
     // block tx sync manager
     block_txs: Arc<BlockTxs>,
 
@@ -53,15 +43,121 @@ pub struct TxInfos {
 
     // receipt sync manager
     receipts: Arc<Receipts>,
+}
 
-    // series of unique request ids
-    request_id_allocator: Arc<UniqueId>,
+impl ItemSync for TxInfoSync {
+    type Key = H256;
+    type Missing = MissingTxInfo;
+    type Response = TxInfo;
+    type Value = TxInfoValidated;
 
-    // sync and request manager
-    sync_manager: SyncManager<H256, MissingTxInfo>,
+    fn missing(hash: H256) -> MissingTxInfo { MissingTxInfo::new(hash) }
 
-    // block txs received from full node
-    verified: Arc<RwLock<LruCache<H256, TxInfoValidated>>>,
+    fn key_of(info: &TxInfo) -> Option<H256> {
+        info.block_txs.get(info.index).map(|tx| tx.hash())
+    }
+
+    fn request_message(
+        &self, request_id: u64, hashes: Vec<H256>,
+    ) -> Box<dyn Message> {
+        Box::new(GetTxInfos { request_id, hashes })
+    }
+
+    fn validate(
+        &self, peer: PeerId, info: &TxInfo,
+    ) -> Result<Validated<H256, TxInfoValidated>, Error> {
+        let TxInfo {
+            epoch,
+            block_hash,
+            index,
+            epoch_receipts,
+            block_txs,
+        } = info;
+        let (epoch, block_hash, index) = (*epoch, *block_hash, *index);
+
+        // the index of the requested tx must be within the block
+        if index >= block_txs.len() {
+            warn!(
+                "Tx index {} out of bounds in block {:?} (len = {})",
+                index,
+                block_hash,
+                block_txs.len()
+            );
+            return Err(ErrorKind::InvalidTxInfo.into());
+        }
+
+        // find index of block within epoch
+        let hashes = self.ledger.block_hashes_in(epoch)?;
+        let block_index = hashes.iter().position(|h| *h == block_hash);
+
+        let block_index = match block_index {
+            Some(index) => index,
+            None => {
+                warn!(
+                    "Block {:?} does not exist in epoch {} (hashes: {:?})",
+                    block_hash, epoch, hashes
+                );
+                return Err(ErrorKind::InvalidTxInfo.into());
+            }
+        };
+
+        // validate receipts
+        let receipts = epoch_receipts.clone();
+        self.receipts.receive_single(peer, epoch, receipts)?;
+
+        // validate block txs
+        let txs = block_txs.clone();
+        self.block_txs.receive_single(block_hash, txs)?;
+
+        // the receipts and txs are checked against the roots in the headers,
+        // but their shapes are still supplied by the peer
+        let block_receipts = match epoch_receipts.get(block_index) {
+            Some(receipts) => receipts,
+            None => {
+                warn!(
+                    "Block index {} out of bounds in epoch {} receipts \
+                     (len = {})",
+                    block_index,
+                    epoch,
+                    epoch_receipts.len()
+                );
+                return Err(ErrorKind::InvalidTxInfo.into());
+            }
+        };
+
+        if block_txs.len() != block_receipts.len() {
+            warn!(
+                "Block {:?} has {} txs but {} receipts",
+                block_hash,
+                block_txs.len(),
+                block_receipts.len()
+            );
+            return Err(ErrorKind::InvalidTxInfo.into());
+        }
+        let items = block_txs.iter().zip(block_receipts.iter());
+
+        // the pivot block is the last one in the epoch
+        let epoch_hash = *hashes.last().expect("epoch is not empty");
+        let validated = items
+            .enumerate()
+            .map(|(index, (tx, receipt))| {
+                let hash = tx.hash();
+                let address = TransactionAddress {
+                    block_hash,
+                    index,
+                    epoch_hash,
+                };
+                (hash, (tx.clone(), receipt.clone(), address))
+            })
+            .collect();
+
+        Ok(Validated::Items(validated))
+    }
+}
+
+pub struct TxInfos {
+    // sync engine of the tx infos
+    engine: SyncEngine<TxInfoSync>,
 }
 
 impl TxInfos {
@@ -71,137 +167,45 @@ impl TxInfos {
         receipts: Arc<Receipts>,
     ) -> Self
     {
-        let ledger = LedgerInfo::new(consensus.clone());
-        let sync_manager = SyncManager::new(peers.clone());
-
-        let cache = LruCache::with_expiry_duration(*CACHE_TIMEOUT);
-        let verified = Arc::new(RwLock::new(cache));
-
-        TxInfos {
+        let config = SyncConfig {
+            name: "tx info",
+            msg_id: msgid::TX_INFOS,
+            request_msg_id: msgid::GET_TX_INFOS,
+            max_in_flight: MAX_TX_INFOS_IN_FLIGHT,
+            max_pending: MAX_TX_INFOS_IN_FLIGHT,
+            batch_size: TX_INFO_REQUEST_BATCH_SIZE,
+            request_timeout: *TX_INFO_REQUEST_TIMEOUT,
+        };
+
+        let item_sync = TxInfoSync {
             block_txs,
-            ledger,
+            ledger: LedgerInfo::new(consensus),
             receipts,
-            request_id_allocator,
-            sync_manager,
-            verified,
-        }
-    }
+        };
 
-    #[inline]
-    fn get_statistics(&self) -> Statistics {
-        Statistics {
-            cached: self.verified.read().len(),
-            in_flight: self.sync_manager.num_in_flight(),
-            waiting: self.sync_manager.num_waiting(),
-        }
+        let engine =
+            SyncEngine::new(config, item_sync, peers, request_id_allocator);
+
+        TxInfos { engine }
     }
 
     #[inline]
     pub fn request_now(
         &self, io: &dyn NetworkContext, hash: H256,
     ) -> impl Future<Item = TxInfoValidated, Error = Error> {
-        if !self.verified.read().contains_key(&hash) {
-            let missing = std::iter::once(MissingTxInfo::new(hash));
-
-            self.sync_manager.request_now(missing, |peer, hashes| {
-                self.send_request(io, peer, hashes)
-            });
-        }
-
-        FutureItem::new(hash, self.verified.clone())
+        self.engine.request_now(io, hash)
     }
 
     #[inline]
     pub fn receive(
-        &self, infos: impl Iterator<Item = TxInfo>,
+        &self, peer: PeerId, infos: impl Iterator<Item = TxInfo>,
     ) -> Result<(), Error> {
-        for info in infos {
-            info!("Validating tx_info {:?}", info);
-
-            let TxInfo {
-                epoch,
-                block_hash,
-                index: _,
-                mut epoch_receipts,
-                block_txs,
-            } = info;
-
-            // find index of block within epoch
-            let hashes = self.ledger.block_hashes_in(epoch)?;
-            let block_index = hashes.iter().position(|h| *h == block_hash);
-
-            let block_index = match block_index {
-                Some(index) => index,
-                None => {
-                    warn!(
-                        "Block {:?} does not exist in epoch {} (hashes: {:?})",
-                        block_hash, epoch, hashes
-                    );
-                    return Err(ErrorKind::InvalidTxInfo.into());
-                }
-            };
-
-            // validate receipts
-            let receipts = epoch_receipts.clone();
-            self.receipts.receive_single(epoch, receipts)?;
-
-            // validate block txs
-            let txs = block_txs.clone();
-            self.block_txs.receive_single(block_hash, txs)?;
-
-            // `epoch_receipts` is valid and `block_hash` exists in epoch
-            assert!(block_index < epoch_receipts.len());
-            let block_receipts = epoch_receipts.swap_remove(block_index);
-
-            // `block_txs` is valid and `block_hash` exists in epoch
-            assert!(block_txs.len() == block_receipts.len());
-            let items = block_txs.into_iter().zip(block_receipts.into_iter());
-
-            for (index, (tx, receipt)) in items.enumerate() {
-                let hash = tx.hash();
-                let address = TransactionAddress { block_hash, index };
-                self.verified.write().insert(hash, (tx, receipt, address));
-                self.sync_manager.remove_in_flight(&hash);
-            }
-        }
-
-        Ok(())
-    }
-
-    #[inline]
-    pub fn clean_up(&self) {
-        let timeout = *TX_INFO_REQUEST_TIMEOUT;
-        let infos = self.sync_manager.remove_timeout_requests(timeout);
-        self.sync_manager.insert_waiting(infos.into_iter());
+        self.engine.receive(peer, infos)
     }
 
     #[inline]
-    fn send_request(
-        &self, io: &dyn NetworkContext, peer: PeerId, hashes: Vec<H256>,
-    ) -> Result<(), Error> {
-        info!("send_request peer={:?} hashes={:?}", peer, hashes);
-
-        if hashes.is_empty() {
-            return Ok(());
-        }
-
-        let msg: Box<dyn Message> = Box::new(GetTxInfos {
-            request_id: self.request_id_allocator.next(),
-            hashes,
-        });
-
-        msg.send(io, peer)?;
-        Ok(())
-    }
+    pub fn clean_up(&self) { self.engine.clean_up(); }
 
     #[inline]
-    pub fn sync(&self, io: &dyn NetworkContext) {
-        info!("tx info sync statistics: {:?}", self.get_statistics());
-
-        self.sync_manager.sync(
-            MAX_TX_INFOS_IN_FLIGHT,
-            TX_INFO_REQUEST_BATCH_SIZE,
-            |peer, hashes| self.send_request(io, peer, hashes),
-        );
-    }
+    pub fn sync(&self, io: &dyn NetworkContext) { self.engine.sync(io); }
 }
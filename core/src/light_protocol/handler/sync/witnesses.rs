@@ -4,28 +4,30 @@
 
 use cfx_types::H256;
 use parking_lot::RwLock;
-use std::{collections::HashMap, sync::Arc};
+use primitives::BlockHeader;
+use std::{collections::HashMap, ops::RangeInclusive, sync::Arc};
 
 use crate::{
+    block_data_manager::WitnessRoots,
     consensus::ConsensusGraph,
     light_protocol::{
         common::{FullPeerState, LedgerInfo, Peers, UniqueId},
-        message::{GetWitnessInfo, WitnessInfoWithHeight},
+        message::{msgid, GetWitnessInfo, WitnessInfoWithHeight},
         Error, ErrorKind,
     },
     message::Message,
     network::{NetworkContext, PeerId},
-    parameters::{
-        consensus::DEFERRED_STATE_EPOCH_COUNT,
-        light::{
-            BLAME_CHECK_OFFSET, MAX_WITNESSES_IN_FLIGHT,
-            NUM_WAITING_WITNESSES_THRESHOLD, WITNESS_REQUEST_BATCH_SIZE,
-            WITNESS_REQUEST_TIMEOUT,
-        },
+    parameters::light::{
+        BLAME_CHECK_OFFSET, MAX_WITNESSES_IN_FLIGHT,
+        NUM_WAITING_WITNESSES_THRESHOLD, WITNESS_REQUEST_BATCH_SIZE,
+        WITNESS_REQUEST_TIMEOUT,
     },
 };
 
-use super::common::{KeyReverseOrdered, LedgerProof, SyncManager};
+use super::{
+    common::{KeyReverseOrdered, LedgerProof, SyncManager},
+    headers::{deferred_roots_of, expected_deferred_roots},
+};
 
 #[derive(Debug)]
 struct Statistics {
@@ -41,6 +43,9 @@ pub struct Witnesses {
     // shared consensus graph
     consensus: Arc<ConsensusGraph>,
 
+    // number of epochs for which the verified roots are kept
+    history_epochs: u64,
+
     // latest header for which we have trusted information
     latest_verified_header: RwLock<u64>,
 
@@ -61,16 +66,20 @@ pub struct Witnesses {
 impl Witnesses {
     pub fn new(
         consensus: Arc<ConsensusGraph>, peers: Arc<Peers<FullPeerState>>,
-        request_id_allocator: Arc<UniqueId>,
+        request_id_allocator: Arc<UniqueId>, history_epochs: u64,
     ) -> Self
     {
-        let latest_verified_header = RwLock::new(0);
         let ledger = LedgerInfo::new(consensus.clone());
-        let sync_manager = SyncManager::new(peers.clone());
-        let verified = RwLock::new(HashMap::new());
+        let sync_manager =
+            SyncManager::new(peers.clone(), msgid::GET_WITNESS_INFO);
+        let (latest, verified) =
+            Self::load_from_db(&consensus, &ledger, history_epochs);
+        let latest_verified_header = RwLock::new(latest);
+        let verified = RwLock::new(verified);
 
         Witnesses {
             consensus,
+            history_epochs,
             latest_verified_header,
             ledger,
             request_id_allocator,
@@ -82,6 +91,104 @@ impl Witnesses {
     #[inline]
     pub fn latest_verified(&self) -> u64 { *self.latest_verified_header.read() }
 
+    /// Load the latest verified header and the roots verified before the
+    /// last shutdown so that we do not need to re-verify them. The roots
+    /// conflicting with the recovered pivot chain are dropped along with the
+    /// roots after them, and verified again.
+    fn load_from_db(
+        consensus: &ConsensusGraph, ledger: &LedgerInfo, history_epochs: u64,
+    ) -> (u64, HashMap<u64, (H256, H256, H256)>)
+    {
+        let data_man = &consensus.data_man;
+
+        // the recovered pivot chain might be shorter than before
+        let mut latest = match data_man.latest_verified_header_from_db() {
+            None => return (0, HashMap::new()),
+            Some(h) => std::cmp::min(h, consensus.best_epoch_number()),
+        };
+
+        let mut verified = HashMap::new();
+        let deferred_state_epoch_count = consensus.deferred_state_epoch_count();
+        let latest_epoch = latest.saturating_sub(deferred_state_epoch_count);
+        let first_epoch = latest_epoch.saturating_sub(history_epochs);
+
+        for epoch in first_epoch..=latest_epoch {
+            if let Some(roots) = data_man.witness_roots_from_db(epoch) {
+                verified.insert(
+                    epoch,
+                    (
+                        roots.state_root_hash,
+                        roots.receipts_root_hash,
+                        roots.logs_bloom_hash,
+                    ),
+                );
+            }
+        }
+
+        // the pivot chain might have changed since the roots were verified
+        if let Some(height) = first_stale_height(
+            first_epoch + deferred_state_epoch_count..=latest,
+            deferred_state_epoch_count,
+            |height| ledger.pivot_header_of(height).ok(),
+            |height| ledger.witness_of_header_at(height) != Some(height),
+            |epoch| verified.get(&epoch).cloned(),
+        ) {
+            let stale_epoch = height.saturating_sub(deferred_state_epoch_count);
+            warn!(
+                "Verified witness roots conflict with the pivot header at {}, \
+                 dropping the roots from epoch {}",
+                height, stale_epoch
+            );
+
+            verified.retain(|epoch, _| {
+                if *epoch < stale_epoch {
+                    return true;
+                }
+                data_man.remove_witness_roots_from_db(*epoch);
+                false
+            });
+
+            latest = height.saturating_sub(1);
+            data_man.insert_latest_verified_header_to_db(latest);
+        }
+
+        info!(
+            "Loaded {} verified witness roots, latest verified header: {}",
+            verified.len(),
+            latest
+        );
+
+        (latest, verified)
+    }
+
+    /// Store verified roots for `epoch` both in memory and in the db.
+    fn insert_verified(&self, epoch: u64, roots: (H256, H256, H256)) {
+        self.consensus.data_man.insert_witness_roots_to_db(
+            epoch,
+            &WitnessRoots {
+                state_root_hash: roots.0,
+                receipts_root_hash: roots.1,
+                logs_bloom_hash: roots.2,
+            },
+        );
+
+        self.verified.write().insert(epoch, roots);
+    }
+
+    /// Remove roots that fell out of the history window.
+    fn prune(&self, latest: u64) {
+        let latest_epoch =
+            latest.saturating_sub(self.ledger.deferred_state_epoch_count());
+
+        if latest_epoch < self.history_epochs {
+            return;
+        }
+
+        let epoch = latest_epoch - self.history_epochs;
+        self.verified.write().remove(&epoch);
+        self.consensus.data_man.remove_witness_roots_from_db(epoch);
+    }
+
     fn get_statistics(&self) -> Statistics {
         Statistics {
             in_flight: self.sync_manager.num_in_flight(),
@@ -122,15 +229,14 @@ impl Witnesses {
         assert!(receipts.len() == blooms.len());
 
         // handle valid hashes
-        let mut verified = self.verified.write();
-
         for ii in 0..state_roots.len() as u64 {
             // find corresponding epoch
             let height = witness - ii;
-            let epoch = height.saturating_sub(DEFERRED_STATE_EPOCH_COUNT);
+            let epoch =
+                height.saturating_sub(self.ledger.deferred_state_epoch_count());
 
             // store receipts root and logs bloom hash
-            verified.insert(
+            self.insert_verified(
                 epoch,
                 (
                     state_roots[ii as usize],
@@ -143,6 +249,16 @@ impl Witnesses {
         Ok(())
     }
 
+    /// Record the size of a response from `peer` for adapting the batch
+    /// size of the requests to it.
+    #[inline]
+    pub fn record_response_size(
+        &self, peer: PeerId, num_items: usize, bytes: usize,
+    ) {
+        self.sync_manager
+            .record_response_size(peer, num_items, bytes);
+    }
+
     pub fn receive<I>(&self, witnesses: I) -> Result<(), Error>
     where I: Iterator<Item = WitnessInfoWithHeight> {
         for item in witnesses {
@@ -215,7 +331,8 @@ impl Witnesses {
     //     b) we have received and validated the corresponding root
     #[inline]
     fn is_header_trusted(&self, height: u64) -> bool {
-        let epoch = height.saturating_sub(DEFERRED_STATE_EPOCH_COUNT);
+        let epoch =
+            height.saturating_sub(self.ledger.deferred_state_epoch_count());
         !self.is_blamed(height) || self.verified.read().contains_key(&epoch)
     }
 
@@ -226,19 +343,21 @@ impl Witnesses {
         };
 
         let mut latest = self.latest_verified_header.write();
-        let mut height = *latest + 1;
+        let start = *latest + 1;
+        let mut height = start;
 
         // iterate through all trusted pivot headers
         // TODO(thegaram): consider chain-reorg
         while height < best && self.is_header_trusted(height) {
             trace!("header {} is valid", height);
             let header = self.ledger.pivot_header_of(height)?;
-            let epoch = height.saturating_sub(DEFERRED_STATE_EPOCH_COUNT);
+            let epoch =
+                height.saturating_sub(self.ledger.deferred_state_epoch_count());
 
             // for blamed and blaming blocks, we've stored the correct roots in
             // the `on_witness_info` response handler
             if !self.is_blamed(height) && header.blame() == 0 {
-                self.verified.write().insert(
+                self.insert_verified(
                     epoch,
                     (
                         *header.deferred_state_root(),
@@ -250,6 +369,13 @@ impl Witnesses {
 
             *latest = height;
             height += 1;
+            self.prune(*latest);
+        }
+
+        if height > start {
+            self.consensus
+                .data_man
+                .insert_latest_verified_header_to_db(*latest);
         }
 
         Ok(())
@@ -290,3 +416,156 @@ impl Witnesses {
         Ok(())
     }
 }
+
+/// The first height in `heights` from which the headers of the pivot chain
+/// are not consistent with the verified roots, i.e. the first height covered
+/// by the first header whose deferred roots differ from the ones computed
+/// from the verified roots, or the first height without a header. The
+/// blamed headers and the headers covering the epochs without verified roots
+/// are not checked.
+fn first_stale_height<H, B, R>(
+    heights: RangeInclusive<u64>, deferred_state_epoch_count: u64,
+    header_at: H, is_blamed: B, verified_roots_of: R,
+) -> Option<u64>
+where
+    H: Fn(u64) -> Option<BlockHeader>,
+    B: Fn(u64) -> bool,
+    R: Fn(u64) -> Option<(H256, H256, H256)>,
+{
+    for height in heights {
+        if is_blamed(height) {
+            continue;
+        }
+
+        let header = match header_at(height) {
+            Some(header) => header,
+            None => return Some(height),
+        };
+
+        let blame = header.blame() as u64;
+        let expected = expected_deferred_roots(
+            height,
+            blame,
+            deferred_state_epoch_count,
+            &verified_roots_of,
+        );
+
+        match expected {
+            Some(roots) if roots != deferred_roots_of(&header) => {
+                return Some(height.saturating_sub(blame));
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::first_stale_height;
+    use cfx_types::H256;
+    use primitives::{BlockHeader, BlockHeaderBuilder};
+    use std::collections::HashMap;
+
+    const DEFERRED_STATE_EPOCH_COUNT: u64 = 5;
+
+    fn roots_of_epoch(epoch: u64) -> (H256, H256, H256) {
+        (
+            H256::from_low_u64_be(epoch * 3),
+            H256::from_low_u64_be(epoch * 3 + 1),
+            H256::from_low_u64_be(epoch * 3 + 2),
+        )
+    }
+
+    fn header(
+        height: u64, blame: u32, roots: (H256, H256, H256),
+    ) -> BlockHeader {
+        BlockHeaderBuilder::new()
+            .with_height(height)
+            .with_blame(blame)
+            .with_deferred_state_root(roots.0)
+            .with_deferred_receipts_root(roots.1)
+            .with_deferred_logs_bloom_hash(roots.2)
+            .build()
+    }
+
+    /// The pivot chain of heights `5..=20` with the correct roots, except
+    /// for the header at 11, which is blamed by the header at 12.
+    fn pivot_chain() -> HashMap<u64, BlockHeader> {
+        (DEFERRED_STATE_EPOCH_COUNT..=20)
+            .map(|height| {
+                let epoch = height - DEFERRED_STATE_EPOCH_COUNT;
+                let header = if height == 11 {
+                    header(height, 0, roots_of_epoch(100))
+                } else if height == 12 {
+                    let vec_root = |a: H256, b: H256| {
+                        BlockHeaderBuilder::compute_blame_state_root_vec_root(
+                            vec![a, b],
+                        )
+                    };
+                    let (own, parent) =
+                        (roots_of_epoch(epoch), roots_of_epoch(epoch - 1));
+                    header(
+                        height,
+                        1,
+                        (
+                            vec_root(own.0, parent.0),
+                            vec_root(own.1, parent.1),
+                            vec_root(own.2, parent.2),
+                        ),
+                    )
+                } else {
+                    header(height, 0, roots_of_epoch(epoch))
+                };
+                (height, header)
+            })
+            .collect()
+    }
+
+    fn first_stale(
+        chain: &HashMap<u64, BlockHeader>,
+        verified: &HashMap<u64, (H256, H256, H256)>,
+    ) -> Option<u64>
+    {
+        first_stale_height(
+            DEFERRED_STATE_EPOCH_COUNT..=20,
+            DEFERRED_STATE_EPOCH_COUNT,
+            |height| chain.get(&height).cloned(),
+            |height| height == 11,
+            |epoch| verified.get(&epoch).cloned(),
+        )
+    }
+
+    #[test]
+    fn test_first_stale_height() {
+        let chain = pivot_chain();
+        let mut verified: HashMap<_, _> =
+            (0..=15).map(|epoch| (epoch, roots_of_epoch(epoch))).collect();
+        assert_eq!(first_stale(&chain, &verified), None);
+
+        // The roots verified on another pivot chain conflict with the
+        // header of the epoch.
+        verified.insert(9, roots_of_epoch(200));
+        assert_eq!(first_stale(&chain, &verified), Some(14));
+
+        // The roots covered by a blaming header are stale from the first
+        // height it covers.
+        verified.insert(7, roots_of_epoch(200));
+        assert_eq!(first_stale(&chain, &verified), Some(11));
+
+        // The epochs without verified roots are not checked.
+        verified.remove(&6);
+        verified.remove(&7);
+        assert_eq!(first_stale(&chain, &verified), Some(14));
+    }
+
+    #[test]
+    fn test_first_stale_height_missing_header() {
+        let mut chain = pivot_chain();
+        let verified: HashMap<_, _> =
+            (0..=15).map(|epoch| (epoch, roots_of_epoch(epoch))).collect();
+        chain.remove(&17);
+        assert_eq!(first_stale(&chain, &verified), Some(17));
+    }
+}
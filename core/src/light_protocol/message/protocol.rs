@@ -3,6 +3,7 @@
 // See http://www.gnu.org/licenses/
 
 use cfx_types::{Bloom, H256};
+use hash::keccak;
 use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
 use rlp_derive::{RlpDecodable, RlpEncodable};
 
@@ -25,8 +26,10 @@ pub struct StatusPing {
 pub struct StatusPong {
     pub best_epoch: u64,
     pub genesis_hash: H256,
+    pub max_credits: u64,
     pub node_type: NodeType,
     pub protocol_version: u8,
+    pub recharge_rate: u64,
     pub terminals: Vec<H256>,
 }
 
@@ -70,35 +73,67 @@ pub struct GetReceipts {
     pub epochs: Vec<u64>,
 }
 
-#[derive(Clone, Debug, Default)]
+/// The receipts of the blocks in an epoch, which are kept as raw RLP until
+/// they are validated against the receipts root of the epoch, so that the
+/// invalid or unneeded ones are never materialized.
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct ReceiptsWithEpoch {
     pub epoch: u64,
-    pub receipts: Vec<Vec<PrimitiveReceipt>>,
+    receipts_rlp: Vec<u8>,
 }
 
-impl Encodable for ReceiptsWithEpoch {
-    fn rlp_append(&self, stream: &mut RlpStream) {
-        stream.begin_list(2);
-        stream.append(&self.epoch);
-
-        stream.begin_list(self.receipts.len());
-        for r in &self.receipts {
+impl ReceiptsWithEpoch {
+    pub fn new(epoch: u64, receipts: &Vec<Vec<PrimitiveReceipt>>) -> Self {
+        let mut stream = RlpStream::new_list(receipts.len());
+        for r in receipts {
             stream.append_list(r);
         }
+
+        ReceiptsWithEpoch {
+            epoch,
+            receipts_rlp: stream.out(),
+        }
+    }
+
+    /// The receipts root of the epoch, as computed by
+    /// `BlockHeaderBuilder::compute_block_receipts_root` from the decoded
+    /// receipts.
+    pub fn receipts_root(&self) -> H256 { keccak(&self.receipts_rlp) }
+
+    pub fn receipts(
+        &self,
+    ) -> Result<Vec<Vec<PrimitiveReceipt>>, DecoderError> {
+        Rlp::new(&self.receipts_rlp)
+            .iter()
+            .map(|block_receipts| block_receipts.as_list())
+            .collect()
+    }
+}
+
+impl Encodable for ReceiptsWithEpoch {
+    fn rlp_append(&self, stream: &mut RlpStream) {
+        stream
+            .begin_list(2)
+            .append(&self.epoch)
+            .append_raw(&self.receipts_rlp, 1);
     }
 }
 
 impl Decodable for ReceiptsWithEpoch {
     fn decode(rlp: &Rlp) -> Result<ReceiptsWithEpoch, DecoderError> {
-        let epoch = rlp.val_at(0)?;
+        if rlp.item_count()? != 2 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
 
-        let receipts = rlp
-            .at(1)?
-            .into_iter()
-            .map(|x| Ok(x.as_list()?))
-            .collect::<Result<_, _>>()?;
+        let receipts = rlp.at(1)?;
+        if !receipts.is_list() {
+            return Err(DecoderError::RlpExpectedToBeList);
+        }
 
-        Ok(ReceiptsWithEpoch { epoch, receipts })
+        Ok(ReceiptsWithEpoch {
+            epoch: rlp.val_at(0)?,
+            receipts_rlp: receipts.as_raw().to_vec(),
+        })
     }
 }
 
@@ -281,3 +316,52 @@ pub struct TxInfos {
     pub request_id: RequestId,
     pub infos: Vec<TxInfo>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ReceiptsWithEpoch;
+    use cfx_types::U256;
+    use primitives::{BlockHeaderBuilder, Receipt};
+    use rlp::{DecoderError, RlpStream};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_lazy_receipts() {
+        let receipts: Vec<Vec<Receipt>> = (0..3u64)
+            .map(|block| {
+                (0..block)
+                    .map(|tx| Receipt::new(0, U256::from(tx), vec![]))
+                    .collect()
+            })
+            .collect();
+
+        let item = ReceiptsWithEpoch::new(7, &receipts);
+        let decoded: ReceiptsWithEpoch = rlp::decode(&rlp::encode(&item))
+            .expect("receipts with epoch should decode");
+        assert_eq!(decoded, item);
+        assert_eq!(decoded.epoch, 7);
+        assert_eq!(decoded.receipts(), Ok(receipts.clone()));
+
+        // The root is computed without decoding the receipts.
+        let receipts = receipts.into_iter().map(Arc::new).collect();
+        assert_eq!(
+            decoded.receipts_root(),
+            BlockHeaderBuilder::compute_block_receipts_root(&receipts)
+        );
+
+        // Malformed receipts are only rejected when they are decoded.
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&7u64).begin_list(1).begin_list(1).append(&1u8);
+        let malformed: ReceiptsWithEpoch = rlp::decode(&stream.out())
+            .expect("the receipts are not decoded eagerly");
+        assert!(malformed.receipts().is_err());
+
+        // The receipts must be a list.
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&7u64).append(&1u8);
+        assert_eq!(
+            rlp::decode::<ReceiptsWithEpoch>(&stream.out()),
+            Err(DecoderError::RlpExpectedToBeList)
+        );
+    }
+}
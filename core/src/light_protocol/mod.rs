@@ -5,16 +5,18 @@
 mod common;
 mod error;
 mod handler;
+mod log_searcher;
 mod message;
 mod provider;
 mod query_service;
 
 use crate::network::ProtocolId;
 const LIGHT_PROTOCOL_ID: ProtocolId = *b"clp"; // Conflux Light Protocol
-const LIGHT_PROTOCOL_VERSION: u8 = 1;
+const LIGHT_PROTOCOL_VERSION: u8 = 2;
 
 use error::{handle as handle_error, Error, ErrorKind};
 
-pub use handler::Handler;
+pub use handler::{Handler, LightConfiguration};
+pub use log_searcher::LogSearcher;
 pub use provider::Provider;
 pub use query_service::QueryService;
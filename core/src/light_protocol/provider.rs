@@ -3,10 +3,14 @@
 // See http://www.gnu.org/licenses/
 
 use io::TimerToken;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use rand::Rng;
 use rlp::Rlp;
-use std::sync::{Arc, Weak};
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{Arc, Weak},
+};
 
 use cfx_types::H256;
 use primitives::{SignedTransaction, TransactionWithSignature};
@@ -14,7 +18,7 @@ use primitives::{SignedTransaction, TransactionWithSignature};
 use crate::{
     consensus::ConsensusGraph,
     light_protocol::{
-        common::{LedgerInfo, LightPeerState, Peers},
+        common::{request_cost, LedgerInfo, LightPeerState, Peers},
         handle_error,
         message::{
             msgid, BlockHashes as GetBlockHashesResponse,
@@ -39,12 +43,54 @@ use crate::{
         NetworkService, PeerId,
     },
     parameters::light::{
-        MAX_EPOCHS_TO_SEND, MAX_HEADERS_TO_SEND, MAX_TXS_TO_SEND,
+        MAX_CREDITS, MAX_EPOCHS_TO_SEND, MAX_HEADERS_TO_SEND,
+        MAX_QUEUED_REQUESTS_PER_PEER, MAX_TXS_TO_SEND, RECHARGE_RATE,
+        SERVE_QUEUE_PERIOD,
     },
     sync::SynchronizationGraph,
     TransactionPool,
 };
 
+const SERVE_QUEUE_TIMER: TimerToken = 0;
+
+/// A request we could not serve right away because the peer ran out of
+/// credits. Requests are served in the order of `priority`, then arrival.
+struct QueuedRequest {
+    cost: u64,
+    msg_id: MsgId,
+    peer: PeerId,
+    priority: u8,
+    raw: Vec<u8>,
+    seq: u64,
+}
+
+impl PartialEq for QueuedRequest {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for QueuedRequest {}
+
+impl PartialOrd for QueuedRequest {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedRequest {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // higher priority first, then lower sequence number first
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+#[derive(Default)]
+struct RequestQueue {
+    heap: BinaryHeap<QueuedRequest>,
+    next_seq: u64,
+}
+
 pub struct Provider {
     // shared consensus graph
     consensus: Arc<ConsensusGraph>,
@@ -62,6 +108,9 @@ pub struct Provider {
     // collection of all peers available
     peers: Peers<LightPeerState>,
 
+    // requests waiting for their senders to recharge credits
+    queue: Mutex<RequestQueue>,
+
     // shared transaction pool
     tx_pool: Arc<TransactionPool>,
 }
@@ -81,6 +130,7 @@ impl Provider {
             ledger,
             network,
             peers,
+            queue: Mutex::new(RequestQueue::default()),
             tx_pool,
         }
     }
@@ -128,26 +178,144 @@ impl Provider {
         Ok(())
     }
 
-    #[rustfmt::skip]
     fn dispatch_message(
         &self, io: &dyn NetworkContext, peer: PeerId, msg_id: MsgId, rlp: Rlp,
     ) -> Result<(), Error> {
         trace!("Dispatching message: peer={:?}, msg_id={:?}", peer, msg_id);
         self.validate_peer_state(peer, msg_id)?;
 
+        if msg_id == msgid::STATUS_PING {
+            return self.on_status(io, peer, &rlp);
+        }
+
+        let cost = Self::request_cost(msg_id, &rlp)?;
+        let state = self.get_existing_peer_state(&peer)?;
+        let mut state = state.write();
+
+        // the request could never be served, no matter how long it waits
+        if cost > state.flow_control.max_credits() {
+            warn!(
+                "Light peer {:?} sends a request costing {} credits",
+                peer, cost
+            );
+            return Err(ErrorKind::RequestTooExpensive.into());
+        }
+
+        if state.flow_control.try_charge(cost) {
+            // NOTE: release peer state lock before serving the request
+            drop(state);
+            return self.serve_request(io, peer, msg_id, &rlp);
+        }
+
+        if state.queued_requests >= MAX_QUEUED_REQUESTS_PER_PEER {
+            warn!("Light peer {:?} sends too many requests", peer);
+            return Err(ErrorKind::TooManyRequests.into());
+        }
+
+        debug!(
+            "Queue request from peer={:?}, msg_id={:?}, cost={}",
+            peer, msg_id, cost
+        );
+
+        state.queued_requests += 1;
+        let mut queue = self.queue.lock();
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+
+        queue.heap.push(QueuedRequest {
+            cost,
+            msg_id,
+            peer,
+            priority: Self::request_priority(msg_id),
+            raw: rlp.as_raw().to_vec(),
+            seq,
+        });
+
+        Ok(())
+    }
+
+    /// Cost of serving a request in credits. We only inspect the number of
+    /// items requested, the request itself is decoded when served.
+    fn request_cost(msg_id: MsgId, rlp: &Rlp) -> Result<u64, Error> {
+        let num_items = match msg_id {
+            msgid::SEND_RAW_TX => 1,
+            _ if request_cost(msg_id, 0).is_none() => {
+                return Err(ErrorKind::UnknownMessage.into())
+            }
+            // all other requests are encoded as [request_id, [items...]]
+            _ => rlp.at(1)?.item_count()?,
+        };
+
+        request_cost(msg_id, num_items)
+            .ok_or_else(|| ErrorKind::UnknownMessage.into())
+    }
+
+    /// Requests needed for light nodes to keep up with the chain are served
+    /// first, then on-demand queries, then bulk requests for log filtering.
+    #[inline]
+    fn request_priority(msg_id: MsgId) -> u8 {
         match msg_id {
-            msgid::STATUS_PING => self.on_status(io, peer, &rlp),
-            msgid::GET_STATE_ENTRIES => self.on_get_state_entries(io, peer, &rlp),
-            msgid::GET_STATE_ROOTS => self.on_get_state_roots(io, peer, &rlp),
-            msgid::GET_BLOCK_HASHES_BY_EPOCH => self.on_get_block_hashes_by_epoch(io, peer, &rlp),
-            msgid::GET_BLOCK_HEADERS => self.on_get_block_headers(io, peer, &rlp),
-            msgid::SEND_RAW_TX => self.on_send_raw_tx(io, peer, &rlp),
-            msgid::GET_RECEIPTS => self.on_get_receipts(io, peer, &rlp),
-            msgid::GET_TXS => self.on_get_txs(io, peer, &rlp),
-            msgid::GET_WITNESS_INFO => self.on_get_witness_info(io, peer, &rlp),
-            msgid::GET_BLOOMS => self.on_get_blooms(io, peer, &rlp),
-            msgid::GET_BLOCK_TXS => self.on_get_block_txs(io, peer, &rlp),
-            msgid::GET_TX_INFOS => self.on_get_tx_infos(io, peer, &rlp),
+            msgid::GET_BLOCK_HASHES_BY_EPOCH
+            | msgid::GET_BLOCK_HEADERS
+            | msgid::GET_WITNESS_INFO => 2,
+            msgid::GET_RECEIPTS | msgid::GET_BLOOMS | msgid::GET_BLOCK_TXS => 0,
+            _ => 1,
+        }
+    }
+
+    /// Serve queued requests whose senders have recharged enough credits.
+    fn serve_queued_requests(&self, io: &dyn NetworkContext) {
+        let mut deferred = vec![];
+
+        loop {
+            // NOTE: do not hold the queue lock while serving requests
+            let req = match self.queue.lock().heap.pop() {
+                Some(req) => req,
+                None => break,
+            };
+
+            // the peer might have disconnected in the meantime
+            let state = match self.peers.get(&req.peer) {
+                Some(state) => state,
+                None => continue,
+            };
+
+            let mut state = state.write();
+
+            if !state.flow_control.try_charge(req.cost) {
+                deferred.push(req);
+                continue;
+            }
+
+            state.queued_requests = state.queued_requests.saturating_sub(1);
+            drop(state);
+
+            let rlp = Rlp::new(&req.raw);
+            if let Err(e) = self.serve_request(io, req.peer, req.msg_id, &rlp)
+            {
+                handle_error(io, req.peer, req.msg_id, e);
+            }
+        }
+
+        self.queue.lock().heap.extend(deferred);
+    }
+
+    #[rustfmt::skip]
+    fn serve_request(
+        &self, io: &dyn NetworkContext, peer: PeerId, msg_id: MsgId, rlp: &Rlp,
+    ) -> Result<(), Error> {
+        match msg_id {
+            msgid::GET_STATE_ENTRIES => self.on_get_state_entries(io, peer, rlp),
+            msgid::GET_STATE_ROOTS => self.on_get_state_roots(io, peer, rlp),
+            msgid::GET_BLOCK_HASHES_BY_EPOCH => self.on_get_block_hashes_by_epoch(io, peer, rlp),
+            msgid::GET_BLOCK_HEADERS => self.on_get_block_headers(io, peer, rlp),
+            msgid::SEND_RAW_TX => self.on_send_raw_tx(io, peer, rlp),
+            msgid::GET_RECEIPTS => self.on_get_receipts(io, peer, rlp),
+            msgid::GET_TXS => self.on_get_txs(io, peer, rlp),
+            msgid::GET_WITNESS_INFO => self.on_get_witness_info(io, peer, rlp),
+            msgid::GET_BLOOMS => self.on_get_blooms(io, peer, rlp),
+            msgid::GET_BLOCK_TXS => self.on_get_block_txs(io, peer, rlp),
+            msgid::GET_TX_INFOS => self.on_get_tx_infos(io, peer, rlp),
             _ => Err(ErrorKind::UnknownMessage.into()),
         }
     }
@@ -238,8 +406,10 @@ impl Provider {
         let msg: Box<dyn Message> = Box::new(StatusPong {
             best_epoch: best_info.best_epoch_number,
             genesis_hash,
+            max_credits: MAX_CREDITS,
             node_type: NodeType::Full,
             protocol_version: LIGHT_PROTOCOL_VERSION,
+            recharge_rate: RECHARGE_RATE,
             terminals,
         });
 
@@ -441,7 +611,7 @@ impl Provider {
             .into_iter()
             .map(|e| self.ledger.receipts_of(e).map(|receipts| (e, receipts)))
             .filter_map(Result::ok)
-            .map(|(epoch, receipts)| ReceiptsWithEpoch { epoch, receipts })
+            .map(|(epoch, receipts)| ReceiptsWithEpoch::new(epoch, &receipts))
             .collect();
 
         let msg: Box<dyn Message> = Box::new(GetReceiptsResponse {
@@ -630,7 +800,10 @@ impl Provider {
 }
 
 impl NetworkProtocolHandler for Provider {
-    fn initialize(&self, _io: &dyn NetworkContext) {}
+    fn initialize(&self, io: &dyn NetworkContext) {
+        io.register_timer(SERVE_QUEUE_TIMER, *SERVE_QUEUE_PERIOD)
+            .expect("Error registering serve queue timer");
+    }
 
     fn on_message(&self, io: &dyn NetworkContext, peer: PeerId, raw: &[u8]) {
         trace!("on_message: peer={:?}, raw={:?}", peer, raw);
@@ -666,7 +839,11 @@ impl NetworkProtocolHandler for Provider {
         self.peers.remove(&peer);
     }
 
-    fn on_timeout(&self, _io: &dyn NetworkContext, _timer: TimerToken) {
-        // EMPTY
+    fn on_timeout(&self, io: &dyn NetworkContext, timer: TimerToken) {
+        trace!("Timeout: timer={:?}", timer);
+        match timer {
+            SERVE_QUEUE_TIMER => self.serve_queued_requests(io),
+            _ => warn!("Unknown timer {} triggered.", timer),
+        }
     }
 }
@@ -4,13 +4,13 @@
 
 extern crate futures;
 
-use cfx_types::{Bloom, H160, H256, KECCAK_EMPTY_BLOOM};
-use futures::{future, stream, Future, Stream};
-use std::{collections::BTreeSet, sync::Arc};
+use cfx_types::{H160, H256};
+use futures::{future, Future};
+use std::sync::Arc;
 
 use primitives::{
     filter::{Filter, FilterError},
-    log_entry::{LocalizedLogEntry, LogEntry},
+    log_entry::LocalizedLogEntry,
     Account, EpochNumber, Receipt, SignedTransaction, StateRoot,
     TransactionAddress,
 };
@@ -18,18 +18,16 @@ use primitives::{
 use crate::{
     consensus::ConsensusGraph,
     network::{NetworkContext, NetworkService},
-    parameters::{
-        consensus::DEFERRED_STATE_EPOCH_COUNT,
-        light::{LOG_FILTERING_LOOKAHEAD, MAX_POLL_TIME},
-    },
+    parameters::light::MAX_POLL_TIME,
     statedb::StorageKey,
     storage,
     sync::SynchronizationGraph,
 };
 
 use super::{
-    common::{poll_future, poll_stream, with_timeout, LedgerInfo},
-    Error, Handler as LightHandler, LIGHT_PROTOCOL_ID, LIGHT_PROTOCOL_VERSION,
+    common::{poll_future, poll_stream, with_timeout},
+    Error, Handler as LightHandler, LightConfiguration, LogSearcher,
+    LIGHT_PROTOCOL_ID, LIGHT_PROTOCOL_VERSION,
 };
 
 type TxInfo = (
@@ -47,8 +45,8 @@ pub struct QueryService {
     // light protocol handler
     handler: Arc<LightHandler>,
 
-    // helper API for retrieving ledger information
-    ledger: LedgerInfo,
+    // bloom-based log filtering
+    log_searcher: LogSearcher,
 
     // shared network service
     network: Arc<NetworkService>,
@@ -57,16 +55,17 @@ pub struct QueryService {
 impl QueryService {
     pub fn new(
         consensus: Arc<ConsensusGraph>, graph: Arc<SynchronizationGraph>,
-        network: Arc<NetworkService>,
+        network: Arc<NetworkService>, config: LightConfiguration,
     ) -> Self
     {
-        let handler = Arc::new(LightHandler::new(consensus.clone(), graph));
-        let ledger = LedgerInfo::new(consensus.clone());
+        let handler =
+            Arc::new(LightHandler::new(consensus.clone(), graph, config));
+        let log_searcher = LogSearcher::new(consensus.clone(), handler.clone());
 
         QueryService {
             consensus,
             handler,
-            ledger,
+            log_searcher,
             network,
         }
     }
@@ -113,40 +112,11 @@ impl QueryService {
         )
     }
 
-    fn retrieve_bloom<'a>(
-        &'a self, epoch: u64,
-    ) -> impl Future<Item = Bloom, Error = Error> + 'a {
-        trace!("retrieve_bloom epoch = {}", epoch);
-
-        with_timeout(
-            *MAX_POLL_TIME, /* timeout */
-            format!("Timeout while retrieving bloom for epoch {}", epoch), /* error */
-            self.handler.blooms.request(epoch),
-        )
-    }
-
-    fn retrieve_receipts<'a>(
-        &'a self, epoch: u64,
-    ) -> impl Future<Item = Vec<Vec<Receipt>>, Error = Error> + 'a {
-        trace!("retrieve_receipts epoch = {}", epoch);
-
-        with_timeout(
-            *MAX_POLL_TIME, /* timeout */
-            format!("Timeout while retrieving receipts for epoch {}", epoch), /* error */
-            self.handler.receipts.request(epoch),
-        )
-    }
-
-    fn retrieve_block_txs<'a>(
-        &'a self, hash: H256,
-    ) -> impl Future<Item = Vec<SignedTransaction>, Error = Error> + 'a {
-        trace!("retrieve_block_txs hash = {:?}", hash);
-
-        with_timeout(
-            *MAX_POLL_TIME, /* timeout */
-            format!("Timeout while retrieving block txs for block {}", hash), /* error */
-            self.handler.block_txs.request(hash),
-        )
+    #[inline]
+    fn get_height_from_epoch_number(
+        &self, epoch: EpochNumber,
+    ) -> Result<u64, FilterError> {
+        self.log_searcher.get_height_from_epoch_number(epoch)
     }
 
     fn retrieve_tx_info<'a>(
@@ -164,6 +134,35 @@ impl QueryService {
         )
     }
 
+    fn retrieve_tx<'a>(
+        &'a self, hash: H256,
+    ) -> impl Future<Item = SignedTransaction, Error = Error> + 'a {
+        trace!("retrieve_tx hash = {:?}", hash);
+
+        with_timeout(
+            *MAX_POLL_TIME,                                  /* timeout */
+            format!("Timeout while retrieving tx {}", hash), /* error */
+            self.with_io(|io| self.handler.txs.request_now(io, hash)),
+        )
+    }
+
+    /// Add the epoch and the state root of the block which executes the
+    /// transaction to its info.
+    fn complete_tx_info(
+        &self, info: (SignedTransaction, Receipt, TransactionAddress),
+    ) -> TxInfo {
+        let (tx, receipt, address) = info;
+
+        let hash = address.block_hash;
+        let epoch = self.consensus.get_block_epoch_number(&hash);
+
+        let root = epoch
+            .and_then(|e| self.handler.witnesses.root_hashes_of(e))
+            .map(|(state_root, _, _)| state_root);
+
+        (tx, receipt, address, epoch, root)
+    }
+
     fn account_key(root: &StateRoot, address: H160) -> Vec<u8> {
         let padding = storage::MultiVersionMerklePatriciaTrie::padding(
             &root.snapshot_root,
@@ -273,18 +272,9 @@ impl QueryService {
     pub fn get_tx_info(&self, hash: H256) -> Result<TxInfo, String> {
         info!("get_tx_info hash={:?}", hash);
 
-        let mut info = self.retrieve_tx_info(hash).map(|info| {
-            let (tx, receipt, address) = info;
-
-            let hash = address.block_hash;
-            let epoch = self.consensus.get_block_epoch_number(&hash);
-
-            let root = epoch
-                .and_then(|e| self.handler.witnesses.root_hashes_of(e))
-                .map(|(state_root, _, _)| state_root);
-
-            (tx, receipt, address, epoch, root)
-        });
+        let mut info = self
+            .retrieve_tx_info(hash)
+            .map(|info| self.complete_tx_info(info));
 
         match poll_future(&mut info) {
             Ok(info) => Ok(info),
@@ -322,20 +312,36 @@ impl QueryService {
         success
     }
 
+    /// Retrieve the transaction `hash` along with its verified info if it is
+    /// executed, or otherwise without the info. The transaction and its info
+    /// are requested at once, so that an unexecuted transaction is returned
+    /// as soon as the info request times out instead of being requested only
+    /// after that.
+    pub fn get_tx_with_info(
+        &self, hash: H256,
+    ) -> Result<(SignedTransaction, Option<TxInfo>), String> {
+        info!("get_tx_with_info hash={:?}", hash);
+
+        let info = self
+            .retrieve_tx_info(hash)
+            .map(|info| self.complete_tx_info(info));
+        let tx = self.retrieve_tx(hash);
+        let mut tx_with_info =
+            with_fallback_tx(info, tx, |info| info.0.clone());
+
+        match poll_future(&mut tx_with_info) {
+            Ok(tx_with_info) => Ok(tx_with_info),
+            Err(e) => {
+                warn!("Error while retrieving tx with info: {}", e);
+                Err(format!("{}", e))
+            }
+        }
+    }
+
     pub fn get_tx(&self, hash: H256) -> Result<SignedTransaction, String> {
         info!("get_tx hash={:?}", hash);
 
-        let mut tx = future::ok(hash).and_then(|hash| {
-            trace!("hash = {:?}", hash);
-
-            let tx = self.with_io(|io| self.handler.txs.request_now(io, hash));
-
-            with_timeout(
-                *MAX_POLL_TIME,                                  /* timeout */
-                format!("Timeout while retrieving tx {}", hash), /* error */
-                tx,
-            )
-        });
+        let mut tx = self.retrieve_tx(hash);
 
         match poll_future(&mut tx) {
             Ok(tx) => Ok(tx),
@@ -346,267 +352,12 @@ impl QueryService {
         }
     }
 
-    /// Apply filter to all logs within a receipt.
-    /// NOTE: `log.transaction_hash` is not known at this point,
-    /// so this field has to be filled later on.
-    fn filter_receipt_logs(
-        block_hash: H256, transaction_index: usize,
-        num_logs_remaining: &mut usize, mut logs: Vec<LogEntry>,
-        filter: Filter,
-    ) -> impl Iterator<Item = LocalizedLogEntry>
-    {
-        let num_logs = logs.len();
-
-        let log_base_index = *num_logs_remaining;
-        *num_logs_remaining -= num_logs;
-
-        // process logs in reverse order
-        logs.reverse();
-
-        logs.into_iter()
-            .enumerate()
-            .filter(move |(_, entry)| filter.matches(&entry))
-            .map(move |(ii, entry)| LocalizedLogEntry {
-                block_hash,
-                block_number: 0, // TODO
-                entry,
-                log_index: log_base_index - ii - 1,
-                transaction_hash: KECCAK_EMPTY_BLOOM, // will fill in later
-                transaction_index,
-                transaction_log_index: num_logs - ii - 1,
-            })
-    }
-
-    /// Apply filter to all receipts within a block.
-    fn filter_block_receipts(
-        hash: H256, mut receipts: Vec<Receipt>, filter: Filter,
-    ) -> impl Iterator<Item = LocalizedLogEntry> {
-        // number of receipts in this block
-        let num_receipts = receipts.len();
-
-        // number of logs in this block
-        let mut remaining = receipts.iter().fold(0, |s, r| s + r.logs.len());
-
-        // process block receipts in reverse order
-        receipts.reverse();
-
-        receipts.into_iter().map(|r| r.logs).enumerate().flat_map(
-            move |(ii, logs)| {
-                debug!("block_hash {:?} logs = {:?}", hash, logs);
-                Self::filter_receipt_logs(
-                    hash,
-                    num_receipts - ii - 1,
-                    &mut remaining,
-                    logs,
-                    filter.clone(),
-                )
-            },
-        )
-    }
-
-    /// Apply filter to all receipts within an epoch.
-    fn filter_epoch_receipts(
-        &self, epoch: u64, mut receipts: Vec<Vec<Receipt>>, filter: Filter,
-    ) -> Result<impl Iterator<Item = LocalizedLogEntry>, Error> {
-        // get epoch blocks in execution order
-        let mut hashes: Vec<H256> = self.ledger.block_hashes_in(epoch)?;
-
-        // process epoch receipts in reverse order
-        receipts.reverse();
-        hashes.reverse();
-
-        let matching = receipts.into_iter().zip(hashes).flat_map(
-            move |(receipts, hash)| {
-                trace!("block_hash {:?} receipts = {:?}", hash, receipts);
-                Self::filter_block_receipts(hash, receipts, filter.clone())
-            },
-        );
-
-        Ok(matching)
-    }
-
-    fn get_height_from_epoch_number(
-        &self, epoch: EpochNumber,
-    ) -> Result<u64, FilterError> {
-        // find highest epoch that we are able to verify based on witness info
-        let latest_verified = self.handler.witnesses.latest_verified();
-
-        let latest_verifiable = match latest_verified {
-            n if n >= DEFERRED_STATE_EPOCH_COUNT => {
-                n - DEFERRED_STATE_EPOCH_COUNT
-            }
-            _ => {
-                return Err(FilterError::UnableToVerify {
-                    epoch: 0,
-                    latest_verifiable: 0,
-                })
-            }
-        };
-
-        trace!(
-            "get_height_from_epoch_number epoch = {:?}, latest_verifiable = {}",
-            epoch,
-            latest_verifiable
-        );
-
-        match epoch {
-            EpochNumber::Earliest => Ok(0),
-            EpochNumber::LatestMined => Ok(latest_verifiable),
-            EpochNumber::LatestState => Ok(latest_verifiable),
-            EpochNumber::Number(n) if n <= latest_verifiable => Ok(n),
-            EpochNumber::Number(n) => Err(FilterError::UnableToVerify {
-                epoch: n,
-                latest_verifiable,
-            }),
-        }
-    }
-
-    fn get_filter_epochs(
-        &self, filter: &Filter,
-    ) -> Result<(Vec<u64>, Box<dyn Fn(H256) -> bool>), FilterError> {
-        match &filter.block_hashes {
-            None => {
-                let from_epoch = self
-                    .get_height_from_epoch_number(filter.from_epoch.clone())?;
-                let to_epoch =
-                    self.get_height_from_epoch_number(filter.to_epoch.clone())?;
-
-                if from_epoch > to_epoch {
-                    return Err(FilterError::InvalidEpochNumber {
-                        from_epoch,
-                        to_epoch,
-                    });
-                }
-
-                let epochs = (from_epoch..(to_epoch + 1)).rev().collect();
-                let block_filter = Box::new(|_| true);
-
-                Ok((epochs, block_filter))
-            }
-            Some(hashes) => {
-                // we use BTreeSet to make lookup efficient
-                let hashes: BTreeSet<_> = hashes.iter().cloned().collect();
-
-                // we use BTreeSet to ensure order and uniqueness
-                let mut epochs = BTreeSet::new();
-
-                for hash in &hashes {
-                    match self.consensus.get_block_epoch_number(&hash) {
-                        Some(epoch) => epochs.insert(epoch),
-                        None => {
-                            return Err(FilterError::UnknownBlock {
-                                hash: *hash,
-                            })
-                        }
-                    };
-                }
-
-                let epochs = epochs.into_iter().rev().collect();
-                let block_filter = Box::new(move |hash| hashes.contains(&hash));
-
-                Ok((epochs, block_filter))
-            }
-        }
-    }
-
     pub fn get_logs(
         &self, filter: Filter,
     ) -> Result<Vec<LocalizedLogEntry>, FilterError> {
         debug!("get_logs filter = {:?}", filter);
 
-        // find epochs and blocks to match against
-        let (epochs, block_filter) = self.get_filter_epochs(&filter)?;
-        debug!("Executing filter on epochs {:?}", epochs);
-
-        // construct blooms for matching epochs
-        let blooms = filter.bloom_possibilities();
-        let bloom_match = |block_log_bloom: &Bloom| {
-            blooms
-                .iter()
-                .any(|bloom| block_log_bloom.contains_bloom(bloom))
-        };
-
-        // set maximum to number of logs returned
-        let limit = filter.limit.unwrap_or(::std::usize::MAX) as u64;
-
-        // construct a stream object for log filtering
-        // we first retrieve the epoch blooms and try to match against them. for
-        // matching epochs, we retrieve the corresponding receipts and find the
-        // matching entries. finally, for each matching entry, we retrieve the
-        // block transactions so that we can add the tx hash. each of these is
-        // verified in the corresponding sync handler.
-        let mut stream =
-            // process epochs one by one
-            stream::iter_ok::<_, Error>(epochs)
-
-            // retrieve blooms
-            .map(|epoch| self.retrieve_bloom(epoch).map(move |bloom| (epoch, bloom)))
-
-            // we first request blooms for up to `LOG_FILTERING_LOOKAHEAD`
-            // epochs and then wait for them and process them one by one
-            // NOTE: we wrap our future in a future because we don't want to wait for the actual value yet
-            .map(future::ok)
-            .buffered(LOG_FILTERING_LOOKAHEAD)
-            .and_then(|x| x)
-
-            // find the epochs that match
-            .filter_map(|(epoch, bloom)| {
-                    debug!("Matching epoch {:?} bloom = {:?}", epoch, bloom);
-
-                    match bloom_match(&bloom) {
-                        true => Some(epoch),
-                        false => None,
-                    }
-                },
-            )
-
-            // retrieve receipts
-            .map(|epoch| self.retrieve_receipts(epoch).map(move |receipts| (epoch, receipts)))
-
-            // we first request receipts for up to `LOG_FILTERING_LOOKAHEAD`
-            // epochs and then wait for them and process them one by one
-            .map(future::ok)
-            .buffered(LOG_FILTERING_LOOKAHEAD)
-            .and_then(|x| x)
-
-            // filter logs in epoch
-            .and_then(|(epoch, receipts)| {
-                debug!("Filtering epoch {:?} receipts = {:?}", epoch, receipts);
-                let logs = self.filter_epoch_receipts(epoch, receipts, filter.clone())?;
-                Ok(stream::iter_ok(logs))
-            })
-
-            // Stream<Stream<Log>> -> Stream<Log>
-            .flatten()
-
-            .filter(|log| {
-                block_filter(log.block_hash)
-            })
-
-            // retrieve block txs
-            .map(|log| self.retrieve_block_txs(log.block_hash).map(move |txs| (log, txs)))
-
-            // we first request txs for up to `LOG_FILTERING_LOOKAHEAD`
-            // blocks and then wait for them and process them one by one
-            .map(future::ok)
-            .buffered(LOG_FILTERING_LOOKAHEAD)
-            .and_then(|x| x)
-
-            .map(|(mut log, txs)| {
-                debug!("processing log = {:?} txs = {:?}", log, txs);
-
-                // at this point, we're trying to retrieve a block tx based on verified
-                // bloom, receipt, and tx information. if all the verifications passed
-                // before, then we must have the corresponding tx in this block.
-                assert!(log.transaction_index < txs.len());
-
-                // set tx hash
-                log.transaction_hash = txs[log.transaction_index].hash();
-                log
-            })
-
-            // limit number of entries we need
-            .take(limit);
+        let mut stream = self.log_searcher.search(filter)?;
 
         // NOTE: eventually, we might want to extend our RPC with futures and
         // return the async stream directly. for now, we're offering a sync API
@@ -627,3 +378,75 @@ impl QueryService {
         Ok(matching)
     }
 }
+
+/// Resolve to the transaction of `info` along with the info, or to the one
+/// resolved by `tx` without the info if `info` fails, e.g. since the
+/// transaction is not executed yet. Both should already be requested, so
+/// that they are served concurrently.
+fn with_fallback_tx<T, I>(
+    info: impl Future<Item = I, Error = Error>,
+    tx: impl Future<Item = T, Error = Error>, tx_of: impl FnOnce(&I) -> T,
+) -> impl Future<Item = (T, Option<I>), Error = Error>
+{
+    info.then(move |info| match info {
+        Ok(info) => future::Either::A(future::ok((tx_of(&info), Some(info)))),
+        Err(e) => {
+            debug!("Retrieve tx without info: {}", e);
+            future::Either::B(tx.map(|tx| (tx, None)))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::with_fallback_tx;
+    use crate::light_protocol::{
+        common::{poll_future, Timeout},
+        Error, ErrorKind,
+    };
+    use futures::{future, Future};
+    use std::time::{Duration, Instant};
+
+    fn failed<T>(msg: &str) -> impl Future<Item = T, Error = Error> {
+        future::err(ErrorKind::Msg(msg.into()).into())
+    }
+
+    #[test]
+    fn test_with_fallback_tx() {
+        // The executed transaction is returned with its info.
+        let mut f = with_fallback_tx(
+            future::ok((1, "receipt")),
+            failed("tx not requested"),
+            |info| info.0,
+        );
+        assert_eq!(poll_future(&mut f).unwrap(), (1, Some((1, "receipt"))));
+
+        // The transaction without info is returned alone.
+        let mut f = with_fallback_tx(
+            failed::<(u64, &str)>("tx info timeout"),
+            future::ok(2),
+            |info| info.0,
+        );
+        assert_eq!(poll_future(&mut f).unwrap(), (2, None));
+
+        // Neither of them is found.
+        let mut f = with_fallback_tx(
+            failed::<(u64, &str)>("tx info timeout"),
+            failed::<u64>("tx timeout"),
+            |info| info.0,
+        );
+        assert!(poll_future(&mut f).is_err());
+    }
+
+    #[test]
+    fn test_with_fallback_tx_waits_once() {
+        // Both requests time out together rather than one after the other.
+        let timeout = Duration::from_millis(500);
+        let start = Instant::now();
+        let info = Timeout::<(u64, u64)>::after(timeout, "tx info".into());
+        let tx = Timeout::<u64>::after(timeout, "tx".into());
+        let mut f = with_fallback_tx(info, tx, |info| info.0);
+        assert!(poll_future(&mut f).is_err());
+        assert!(start.elapsed() < 2 * timeout);
+    }
+}
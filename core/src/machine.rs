@@ -5,7 +5,8 @@
 use super::builtin::Builtin;
 use crate::{
     builtin::{builtin_factory, Linear},
-    vm::Spec,
+    parameters::consensus::INVALID_TX_REJECT_HEIGHT,
+    vm::{InvalidTransactionPolicy, Spec},
 };
 use cfx_types::{Address, H256, U256};
 use primitives::BlockNumber;
@@ -67,6 +68,7 @@ pub struct Machine {
     params: CommonParams,
     builtins: Arc<BTreeMap<Address, Builtin>>,
     spec_rules: Option<Box<SpecCreationRules>>,
+    invalid_tx_reject_height: u64,
 }
 
 impl Machine {
@@ -87,6 +89,12 @@ impl Machine {
         self.spec_rules = Some(rules);
     }
 
+    /// Set the pivot block height from which `InvalidTransactionPolicy::Reject`
+    /// applies, see `INVALID_TX_REJECT_HEIGHT`.
+    pub fn set_invalid_tx_reject_height(&mut self, height: u64) {
+        self.invalid_tx_reject_height = height;
+    }
+
     /// Get the general parameters of the chain.
     pub fn params(&self) -> &CommonParams { &self.params }
 
@@ -98,6 +106,15 @@ impl Machine {
         spec
     }
 
+    /// Returns the spec to execute the epoch with the given pivot block height.
+    pub fn spec_for_epoch(&self, epoch_height: u64) -> Spec {
+        let mut spec = Spec::new_spec();
+        if epoch_height >= self.invalid_tx_reject_height {
+            spec.invalid_tx_policy = InvalidTransactionPolicy::Reject;
+        }
+        spec
+    }
+
     /// Builtin-contracts for the chain..
     pub fn builtins(&self) -> &BTreeMap<Address, Builtin> { &*self.builtins }
 }
@@ -107,6 +124,20 @@ pub fn new_machine() -> Machine {
         params: CommonParams::common_params(),
         builtins: Arc::new(BTreeMap::new()),
         spec_rules: None,
+        invalid_tx_reject_height: INVALID_TX_REJECT_HEIGHT,
+    }
+}
+
+/// The machine with the given built-in contracts, see
+/// `GenesisSpec::builtins`.
+pub fn new_machine_with_builtins(
+    builtins: BTreeMap<Address, Builtin>,
+) -> Machine {
+    Machine {
+        params: CommonParams::common_params(),
+        builtins: Arc::new(builtins),
+        spec_rules: None,
+        invalid_tx_reject_height: INVALID_TX_REJECT_HEIGHT,
     }
 }
 
@@ -148,5 +179,6 @@ pub fn new_machine_with_builtin() -> Machine {
         params: CommonParams::common_params(),
         builtins: Arc::new(btree),
         spec_rules: None,
+        invalid_tx_reject_height: INVALID_TX_REJECT_HEIGHT,
     }
 }
@@ -0,0 +1,760 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+//! Versioning of the database layout.
+//!
+//! The version of the layout of the columns is recorded in `COL_MISC`. On
+//! startup, the migrations from the recorded version to `DB_SCHEMA_VERSION`
+//! are executed in batches. The cursor of the running migration is committed
+//! together with each batch, so an interrupted migration is resumed from the
+//! last committed batch on the next startup.
+
+use crate::{
+    block_data_manager::{
+        db_manager::{
+            decode_pow_quality_record, is_block_execution_result_key,
+            is_block_header_key, pow_quality_key,
+        },
+        BlockExecutionResultWithEpoch, PowQualityRecord,
+    },
+    db::{COL_BLOCKS, COL_MISC},
+    ext_db::SystemDB,
+    verification::VerificationConfig,
+};
+use byteorder::{BigEndian, ByteOrder};
+use cfx_types::H256;
+use kvdb::{DBTransaction, KeyValueDB};
+use primitives::BlockHeader;
+use rlp::Rlp;
+
+/// The version of the database layout written by this build.
+///
+/// Version history:
+/// - 1: the layout before the versioning.
+/// - 2: the receipts are stored with their execution details, which are
+///   empty for the receipts executed before.
+/// - 3: the PoW quality of every block header is stored in a
+///   `PowQualityRecord`.
+pub const DB_SCHEMA_VERSION: u32 = 3;
+
+/// The version of the databases created before the layout is versioned.
+const UNVERSIONED_SCHEMA_VERSION: u32 = 1;
+
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+const MIGRATION_PROGRESS_KEY: &[u8] = b"schema_migration_progress";
+const DEFERRED_STATE_EPOCH_COUNT_KEY: &[u8] = b"deferred_state_epoch_count";
+
+/// The result of migrating one batch.
+pub struct MigrationStep {
+    /// The cursor to resume from, or `None` if the migration is finished.
+    pub next_cursor: Option<Vec<u8>>,
+    /// The number of entries migrated in the batch.
+    pub migrated: usize,
+}
+
+/// A migration of the database layout from `from_version()` to
+/// `from_version() + 1`.
+pub trait Migration: Send + Sync {
+    fn from_version(&self) -> u32;
+
+    fn description(&self) -> &'static str;
+
+    /// Migrates the batch starting from `cursor` by writing to `tx`, where
+    /// `None` is the start of the migration. The batch must only read the
+    /// entries not yet rewritten by the previous batches, since `tx` is
+    /// committed atomically with the returned cursor.
+    fn migrate_batch(
+        &self, db: &dyn KeyValueDB, cursor: Option<&[u8]>,
+        tx: &mut DBTransaction,
+    ) -> Result<MigrationStep, String>;
+}
+
+/// The migrations of all the previous versions, in any order.
+fn registered_migrations() -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(StoreReceiptDetails {
+            batch_size: MIGRATION_BATCH_SIZE,
+        }),
+        Box::new(BackfillPowQuality {
+            batch_size: MIGRATION_BATCH_SIZE,
+        }),
+    ]
+}
+
+/// The minimum number of entries migrated in a batch.
+const MIGRATION_BATCH_SIZE: usize = 10_000;
+
+/// Migrates the batch of the entries of `COL_BLOCKS` starting from `cursor`
+/// with `migrate_entry`, which returns whether the entry is migrated.
+///
+/// The keys are migrated in the ranges of their first two bytes, and the
+/// cursor is the two bytes of the next range, so that a batch is resumed
+/// without reading the keys migrated before.
+fn migrate_blocks_batch<F>(
+    db: &dyn KeyValueDB, cursor: Option<&[u8]>, batch_size: usize,
+    mut migrate_entry: F,
+) -> Result<MigrationStep, String>
+where
+    F: FnMut(&[u8], &[u8]) -> Result<bool, String>,
+{
+    let mut range = match cursor {
+        Some(cursor) if cursor.len() == 2 => BigEndian::read_u16(cursor) as u32,
+        Some(cursor) => {
+            return Err(format!("Invalid migration cursor {:?}", cursor))
+        }
+        None => 0,
+    };
+    let mut migrated = 0;
+    while range <= u16::max_value() as u32 {
+        if migrated >= batch_size {
+            let mut next_cursor = vec![0; 2];
+            BigEndian::write_u16(&mut next_cursor, range as u16);
+            return Ok(MigrationStep {
+                next_cursor: Some(next_cursor),
+                migrated,
+            });
+        }
+        let mut prefix = [0; 2];
+        BigEndian::write_u16(&mut prefix, range as u16);
+        for (key, value) in db
+            .iter_from_prefix(COL_BLOCKS, &prefix)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+        {
+            if migrate_entry(&key, &value)? {
+                migrated += 1;
+            }
+        }
+        range += 1;
+    }
+    Ok(MigrationStep {
+        next_cursor: None,
+        migrated,
+    })
+}
+
+/// Rewrites the execution results of blocks, so that the receipts are stored
+/// in the layout with the execution details. The details of the receipts
+/// executed before are not recoverable without executing them again, and are
+/// stored as missing rather than zeros.
+struct StoreReceiptDetails {
+    batch_size: usize,
+}
+
+impl Migration for StoreReceiptDetails {
+    fn from_version(&self) -> u32 { 1 }
+
+    fn description(&self) -> &'static str {
+        "store the execution details in receipts"
+    }
+
+    fn migrate_batch(
+        &self, db: &dyn KeyValueDB, cursor: Option<&[u8]>,
+        tx: &mut DBTransaction,
+    ) -> Result<MigrationStep, String>
+    {
+        migrate_blocks_batch(db, cursor, self.batch_size, |key, value| {
+            if !is_block_execution_result_key(key) {
+                return Ok(false);
+            }
+            let result: BlockExecutionResultWithEpoch =
+                Rlp::new(value).as_val().map_err(|e| {
+                    format!("Failed to decode block execution result {:?}", e)
+                })?;
+            tx.put(COL_BLOCKS, key, &rlp::encode(&result));
+            Ok(true)
+        })
+    }
+}
+
+/// Stores the PoW quality of the block headers without a valid
+/// `PowQualityRecord`, so that loading a header never writes the database.
+struct BackfillPowQuality {
+    batch_size: usize,
+}
+
+impl Migration for BackfillPowQuality {
+    fn from_version(&self) -> u32 { 2 }
+
+    fn description(&self) -> &'static str {
+        "store the PoW quality of block headers"
+    }
+
+    fn migrate_batch(
+        &self, db: &dyn KeyValueDB, cursor: Option<&[u8]>,
+        tx: &mut DBTransaction,
+    ) -> Result<MigrationStep, String>
+    {
+        migrate_blocks_batch(db, cursor, self.batch_size, |key, value| {
+            if !is_block_header_key(key) {
+                return Ok(false);
+            }
+            let record_key = pow_quality_key(&H256::from_slice(key));
+            let record = db.get(COL_BLOCKS, &record_key).map_err(|e| {
+                format!("Failed to read PoW quality record {:?}", e)
+            })?;
+            if record.map_or(false, |r| decode_pow_quality_record(&r).is_some())
+            {
+                return Ok(false);
+            }
+            let mut header: BlockHeader =
+                Rlp::new(value).as_val().map_err(|e| {
+                    format!("Failed to decode block header {:?}", e)
+                })?;
+            VerificationConfig::compute_header_pow_quality(&mut header);
+            tx.put(
+                COL_BLOCKS,
+                &record_key,
+                &rlp::encode(&PowQualityRecord::new(header.pow_quality)),
+            );
+            Ok(true)
+        })
+    }
+}
+
+/// Migrates the database to `DB_SCHEMA_VERSION`, and refuses to open a
+/// database written by a newer version.
+pub fn migrate(db: &SystemDB) -> Result<(), String> {
+    run_migrations(
+        &**db.key_value(),
+        &registered_migrations(),
+        DB_SCHEMA_VERSION,
+    )
+}
+
+/// Checks that the database is of `DB_SCHEMA_VERSION`, for the databases
+/// opened read-only which can not be migrated.
+pub fn check_schema_version(db: &SystemDB) -> Result<(), String> {
+    let kv = &**db.key_value();
+    let version = schema_version(kv)?.unwrap_or(UNVERSIONED_SCHEMA_VERSION);
+    if version != DB_SCHEMA_VERSION || migration_progress(kv)?.is_some() {
+        return Err(format!(
+            "Database schema version {} does not match {}, start a writable \
+             node on it to migrate",
+            version, DB_SCHEMA_VERSION
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that the database is written with the same
+/// `deferred_state_epoch_count`, since the execution results and blame
+/// information in it depend on it. The count is recorded on the first start,
+/// unless the database is opened read-only. It must be called after the
+/// database is migrated.
+pub fn check_deferred_state_epoch_count(
+    db: &SystemDB, deferred_state_epoch_count: u64, read_only: bool,
+) -> Result<(), String> {
+    check_recorded_deferred_state_epoch_count(
+        &**db.key_value(),
+        deferred_state_epoch_count,
+        read_only,
+    )
+}
+
+fn check_recorded_deferred_state_epoch_count(
+    db: &dyn KeyValueDB, deferred_state_epoch_count: u64, read_only: bool,
+) -> Result<(), String> {
+    match db.get(COL_MISC, DEFERRED_STATE_EPOCH_COUNT_KEY) {
+        Ok(Some(v)) if v.len() == 8 => {
+            let recorded = BigEndian::read_u64(&v);
+            if recorded != deferred_state_epoch_count {
+                return Err(format!(
+                    "The database is written with deferred_state_epoch_count \
+                     {}, which does not match the configured {}",
+                    recorded, deferred_state_epoch_count
+                ));
+            }
+            Ok(())
+        }
+        Ok(Some(v)) => {
+            Err(format!("Invalid deferred_state_epoch_count {:?}", v))
+        }
+        Ok(None) if read_only => Ok(()),
+        Ok(None) => {
+            let mut value = [0; 8];
+            BigEndian::write_u64(&mut value, deferred_state_epoch_count);
+            let mut tx = DBTransaction::new();
+            tx.put(COL_MISC, DEFERRED_STATE_EPOCH_COUNT_KEY, &value);
+            db.write(tx).map_err(|e| {
+                format!("Failed to write deferred_state_epoch_count {:?}", e)
+            })
+        }
+        Err(e) => {
+            Err(format!("Failed to read deferred_state_epoch_count {:?}", e))
+        }
+    }
+}
+
+fn run_migrations(
+    db: &dyn KeyValueDB, migrations: &[Box<dyn Migration>], target_version: u32,
+) -> Result<(), String> {
+    let mut version = match schema_version(db)? {
+        Some(version) => version,
+        None => {
+            // A new database is created with the current layout.
+            let version = if is_empty(db) {
+                target_version
+            } else {
+                UNVERSIONED_SCHEMA_VERSION
+            };
+            write_schema_version(db, version)?;
+            version
+        }
+    };
+    if version > target_version {
+        return Err(format!(
+            "Database schema version {} is newer than the supported version \
+             {}, please upgrade the node",
+            version, target_version
+        ));
+    }
+
+    while version < target_version {
+        let migration = migrations
+            .iter()
+            .find(|m| m.from_version() == version)
+            .ok_or_else(|| {
+                format!("No migration from database schema version {}", version)
+            })?;
+        let mut cursor = match migration_progress(db)? {
+            Some((progress_version, cursor)) if progress_version == version => {
+                info!(
+                    "Resuming database migration from version {}: {}",
+                    version,
+                    migration.description()
+                );
+                Some(cursor)
+            }
+            _ => {
+                info!(
+                    "Migrating database from version {}: {}",
+                    version,
+                    migration.description()
+                );
+                None
+            }
+        };
+
+        let mut migrated = 0;
+        loop {
+            let mut tx = DBTransaction::new();
+            let step = migration.migrate_batch(
+                db,
+                cursor.as_ref().map(|c| c.as_slice()),
+                &mut tx,
+            )?;
+            migrated += step.migrated;
+            match step.next_cursor {
+                Some(ref next_cursor) => {
+                    let mut progress = vec![0; 4];
+                    BigEndian::write_u32(&mut progress, version);
+                    progress.extend_from_slice(next_cursor);
+                    tx.put(COL_MISC, MIGRATION_PROGRESS_KEY, &progress);
+                }
+                None => {
+                    tx.delete(COL_MISC, MIGRATION_PROGRESS_KEY);
+                    put_schema_version(&mut tx, version + 1);
+                }
+            }
+            db.write(tx).map_err(|e| {
+                format!("Failed to write database migration {:?}", e)
+            })?;
+            info!(
+                "Database migration from version {}: {} entries migrated",
+                version, migrated
+            );
+            cursor = step.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        version += 1;
+    }
+    Ok(())
+}
+
+fn is_empty(db: &dyn KeyValueDB) -> bool { db.iter(COL_MISC).next().is_none() }
+
+fn schema_version(db: &dyn KeyValueDB) -> Result<Option<u32>, String> {
+    match db.get(COL_MISC, SCHEMA_VERSION_KEY) {
+        Ok(Some(v)) if v.len() == 4 => Ok(Some(BigEndian::read_u32(&v))),
+        Ok(Some(v)) => Err(format!("Invalid database schema version {:?}", v)),
+        Ok(None) => Ok(None),
+        Err(e) => Err(format!("Failed to read schema version {:?}", e)),
+    }
+}
+
+fn migration_progress(
+    db: &dyn KeyValueDB,
+) -> Result<Option<(u32, Vec<u8>)>, String> {
+    match db.get(COL_MISC, MIGRATION_PROGRESS_KEY) {
+        Ok(Some(v)) if v.len() >= 4 => {
+            Ok(Some((BigEndian::read_u32(&v[..4]), v[4..].to_vec())))
+        }
+        Ok(Some(v)) => {
+            Err(format!("Invalid database migration progress {:?}", v))
+        }
+        Ok(None) => Ok(None),
+        Err(e) => Err(format!("Failed to read migration progress {:?}", e)),
+    }
+}
+
+fn put_schema_version(tx: &mut DBTransaction, version: u32) {
+    let mut value = [0; 4];
+    BigEndian::write_u32(&mut value, version);
+    tx.put(COL_MISC, SCHEMA_VERSION_KEY, &value);
+}
+
+fn write_schema_version(
+    db: &dyn KeyValueDB, version: u32,
+) -> Result<(), String> {
+    let mut tx = DBTransaction::new();
+    put_schema_version(&mut tx, version);
+    db.write(tx)
+        .map_err(|e| format!("Failed to write schema version {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_data_manager::{
+        db_manager::block_execution_result_key, BlockExecutionResult,
+    };
+    use cfx_types::{Bloom, U256};
+    use elastic_array::ElasticArray128;
+    use kvdb::DBOp;
+    use parking_lot::RwLock;
+    use primitives::{BlockHeaderBuilder, Receipt};
+    use rlp::RlpStream;
+    use std::{
+        collections::BTreeMap,
+        io::Result as IoResult,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    #[derive(Default)]
+    struct MemoryDb {
+        columns: RwLock<BTreeMap<Option<u32>, BTreeMap<Vec<u8>, Vec<u8>>>>,
+    }
+
+    impl KeyValueDB for MemoryDb {
+        fn get(
+            &self, col: Option<u32>, key: &[u8],
+        ) -> IoResult<Option<ElasticArray128<u8>>> {
+            Ok(self
+                .columns
+                .read()
+                .get(&col)
+                .and_then(|c| c.get(key))
+                .map(|v| ElasticArray128::from_slice(v)))
+        }
+
+        fn get_by_prefix(
+            &self, col: Option<u32>, prefix: &[u8],
+        ) -> Option<Box<[u8]>> {
+            self.iter_from_prefix(col, prefix)
+                .next()
+                .map(|(_, value)| value)
+        }
+
+        fn write_buffered(&self, transaction: DBTransaction) {
+            let mut columns = self.columns.write();
+            for op in transaction.ops {
+                match op {
+                    DBOp::Insert { col, key, value } => {
+                        columns
+                            .entry(col)
+                            .or_default()
+                            .insert(key.to_vec(), value.to_vec());
+                    }
+                    DBOp::Delete { col, key } => {
+                        columns.entry(col).or_default().remove(&*key);
+                    }
+                }
+            }
+        }
+
+        fn flush(&self) -> IoResult<()> { Ok(()) }
+
+        fn iter<'a>(
+            &'a self, col: Option<u32>,
+        ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+            self.iter_from_prefix(col, &[])
+        }
+
+        fn iter_from_prefix<'a>(
+            &'a self, col: Option<u32>, prefix: &'a [u8],
+        ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+            let items: Vec<_> = self
+                .columns
+                .read()
+                .get(&col)
+                .into_iter()
+                .flat_map(|c| c.range(prefix.to_vec()..))
+                .take_while(|(k, _)| k.starts_with(prefix))
+                .map(|(k, v)| (k.clone().into(), v.clone().into()))
+                .collect();
+            Box::new(items.into_iter())
+        }
+
+        fn restore(&self, _new_db: &str) -> IoResult<()> { unreachable!() }
+    }
+
+    const COL_TEST: Option<u32> = Some(1);
+
+    /// Appends a byte to each value of `COL_TEST`, one entry per batch, and
+    /// fails at the entry `fail_at` if set.
+    struct AppendByte {
+        fail_at: Option<Vec<u8>>,
+    }
+
+    impl Migration for AppendByte {
+        fn from_version(&self) -> u32 { 1 }
+
+        fn description(&self) -> &'static str { "append a byte" }
+
+        fn migrate_batch(
+            &self, db: &dyn KeyValueDB, cursor: Option<&[u8]>,
+            tx: &mut DBTransaction,
+        ) -> std::result::Result<MigrationStep, String>
+        {
+            let mut entries = db
+                .iter(COL_TEST)
+                .filter(|(k, _)| cursor.map_or(true, |c| &**k >= c));
+            let (key, value) = match entries.next() {
+                Some(entry) => entry,
+                None => {
+                    return Ok(MigrationStep {
+                        next_cursor: None,
+                        migrated: 0,
+                    })
+                }
+            };
+            if self.fail_at.as_ref().map(|k| k.as_slice()) == Some(&*key) {
+                return Err("interrupted".into());
+            }
+            let mut value = value.to_vec();
+            value.push(0xff);
+            tx.put(COL_TEST, &key, &value);
+            Ok(MigrationStep {
+                next_cursor: entries.next().map(|(k, _)| k.to_vec()),
+                migrated: 1,
+            })
+        }
+    }
+
+    fn old_db() -> MemoryDb {
+        let db = MemoryDb::default();
+        let mut tx = DBTransaction::new();
+        tx.put(COL_MISC, b"best_epoch", &[0]);
+        for key in &[b"a", b"b", b"c"] {
+            tx.put(COL_TEST, *key, &[1]);
+        }
+        db.write(tx).unwrap();
+        db
+    }
+
+    #[test]
+    fn test_new_db_is_current_version() {
+        let db = MemoryDb::default();
+        run_migrations(&db, &[], 3).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn test_resume_interrupted_migration() {
+        let db = old_db();
+        let failing: Vec<Box<dyn Migration>> = vec![Box::new(AppendByte {
+            fail_at: Some(b"c".to_vec()),
+        })];
+        assert!(run_migrations(&db, &failing, 2).is_err());
+        assert_eq!(schema_version(&db).unwrap(), Some(1));
+        assert_eq!(migration_progress(&db).unwrap(), Some((1, b"c".to_vec())));
+
+        let migrations: Vec<Box<dyn Migration>> =
+            vec![Box::new(AppendByte { fail_at: None })];
+        run_migrations(&db, &migrations, 2).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), Some(2));
+        assert_eq!(migration_progress(&db).unwrap(), None);
+        for key in &[b"a", b"b", b"c"] {
+            assert_eq!(
+                &*db.get(COL_TEST, *key).unwrap().unwrap(),
+                &[1, 0xff][..]
+            );
+        }
+    }
+
+    #[test]
+    fn test_deferred_state_epoch_count_is_recorded() {
+        let db = MemoryDb::default();
+        run_migrations(&db, &[], 2).unwrap();
+        // Nothing is recorded in a read-only database.
+        check_recorded_deferred_state_epoch_count(&db, 3, true).unwrap();
+        check_recorded_deferred_state_epoch_count(&db, 5, false).unwrap();
+        check_recorded_deferred_state_epoch_count(&db, 5, false).unwrap();
+        check_recorded_deferred_state_epoch_count(&db, 5, true).unwrap();
+        assert!(
+            check_recorded_deferred_state_epoch_count(&db, 3, false).is_err()
+        );
+        assert!(
+            check_recorded_deferred_state_epoch_count(&db, 3, true).is_err()
+        );
+    }
+
+    /// Fails after migrating `batches` batches.
+    struct InterruptedMigration {
+        migration: StoreReceiptDetails,
+        batches: AtomicUsize,
+    }
+
+    impl Migration for InterruptedMigration {
+        fn from_version(&self) -> u32 { self.migration.from_version() }
+
+        fn description(&self) -> &'static str { self.migration.description() }
+
+        fn migrate_batch(
+            &self, db: &dyn KeyValueDB, cursor: Option<&[u8]>,
+            tx: &mut DBTransaction,
+        ) -> std::result::Result<MigrationStep, String>
+        {
+            if self.batches.fetch_sub(1, Ordering::SeqCst) == 0 {
+                return Err("interrupted".into());
+            }
+            self.migration.migrate_batch(db, cursor, tx)
+        }
+    }
+
+    /// Encodes an execution result in the layout before the receipts are
+    /// stored with the details.
+    fn legacy_execution_result(gas_used: u64) -> Vec<u8> {
+        let mut s = RlpStream::new_list(2);
+        s.append(&H256::zero());
+        s.begin_list(2)
+            .append_list(&[Receipt::new(0, gas_used.into(), vec![])])
+            .append(&Bloom::zero());
+        s.out()
+    }
+
+    #[test]
+    fn test_store_receipt_details() {
+        let db = old_db();
+        let hashes: Vec<_> = (1..=5).map(H256::repeat_byte).collect();
+        let mut tx = DBTransaction::new();
+        for (i, hash) in hashes.iter().enumerate() {
+            tx.put(
+                COL_BLOCKS,
+                &block_execution_result_key(hash),
+                &legacy_execution_result(i as u64),
+            );
+            // The other entries of blocks are left as they are.
+            tx.put(COL_BLOCKS, hash.as_bytes(), &[1]);
+        }
+        db.write(tx).unwrap();
+
+        let interrupted: Vec<Box<dyn Migration>> =
+            vec![Box::new(InterruptedMigration {
+                migration: StoreReceiptDetails { batch_size: 2 },
+                batches: AtomicUsize::new(1),
+            })];
+        assert!(run_migrations(&db, &interrupted, 2).is_err());
+        let (version, cursor) = migration_progress(&db).unwrap().unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(cursor, vec![2, 3]);
+
+        // The resumed migration only reads the entries not migrated.
+        let mut tx = DBTransaction::new();
+        let step = StoreReceiptDetails { batch_size: 10 }
+            .migrate_batch(&db, Some(&cursor), &mut tx)
+            .unwrap();
+        assert_eq!(step.migrated, 3);
+        assert!(step.next_cursor.is_none());
+
+        let migrations: Vec<Box<dyn Migration>> =
+            vec![Box::new(StoreReceiptDetails { batch_size: 2 })];
+        run_migrations(&db, &migrations, 2).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), Some(2));
+        assert_eq!(migration_progress(&db).unwrap(), None);
+        for (i, hash) in hashes.iter().enumerate() {
+            let value = db
+                .get(COL_BLOCKS, &block_execution_result_key(hash))
+                .unwrap()
+                .unwrap();
+            let receipt = Rlp::new(&value).at(1).unwrap().at(0).unwrap();
+            assert_eq!(receipt.item_count().unwrap(), 1);
+            assert_eq!(receipt.at(0).unwrap().item_count().unwrap(), 5);
+
+            let BlockExecutionResultWithEpoch(
+                _,
+                BlockExecutionResult { receipts, .. },
+            ) = Rlp::new(&value).as_val().unwrap();
+            assert_eq!(
+                receipts,
+                Arc::new(vec![Receipt::new(0, (i as u64).into(), vec![])])
+            );
+            // The details are missing rather than zeros.
+            assert!(receipts[0].details.is_none());
+            assert_eq!(
+                &*db.get(COL_BLOCKS, hash.as_bytes()).unwrap().unwrap(),
+                &[1][..]
+            );
+        }
+    }
+
+    #[test]
+    fn test_backfill_pow_quality() {
+        let db = old_db();
+        let headers: Vec<_> = (0..3u64)
+            .map(|nonce| {
+                BlockHeaderBuilder::new()
+                    .with_height(1)
+                    .with_nonce(nonce)
+                    .build()
+            })
+            .collect();
+        let mut tx = DBTransaction::new();
+        for header in &headers {
+            tx.put(COL_BLOCKS, header.hash().as_bytes(), &rlp::encode(header));
+        }
+        // A valid record is kept, and an undecodable one is replaced.
+        let kept = U256::from(7);
+        tx.put(
+            COL_BLOCKS,
+            &pow_quality_key(&headers[0].hash()),
+            &rlp::encode(&PowQualityRecord::new(kept)),
+        );
+        tx.put(COL_BLOCKS, &pow_quality_key(&headers[1].hash()), &[1]);
+        db.write(tx).unwrap();
+        write_schema_version(&db, 2).unwrap();
+
+        let migrations: Vec<Box<dyn Migration>> =
+            vec![Box::new(BackfillPowQuality { batch_size: 1 })];
+        run_migrations(&db, &migrations, 3).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), Some(3));
+        assert_eq!(migration_progress(&db).unwrap(), None);
+
+        let pow_quality_of = |header: &BlockHeader| {
+            let record = db
+                .get(COL_BLOCKS, &pow_quality_key(&header.hash()))
+                .unwrap()
+                .unwrap();
+            decode_pow_quality_record(&record).unwrap()
+        };
+        assert_eq!(pow_quality_of(&headers[0]), kept);
+        for header in &headers[1..] {
+            let mut expected = header.clone();
+            VerificationConfig::compute_header_pow_quality(&mut expected);
+            assert_eq!(pow_quality_of(header), expected.pow_quality);
+        }
+    }
+
+    #[test]
+    fn test_refuse_newer_version() {
+        let db = MemoryDb::default();
+        write_schema_version(&db, 2).unwrap();
+        assert!(run_migrations(&db, &[], 1).is_err());
+    }
+}
@@ -20,19 +20,35 @@
 
 //! Client-side stratum job dispatcher and mining notifier handler
 
-use crate::{miner::work_notify::NotifyWork, pow::ProofOfWorkSolution};
+use crate::{
+    miner::work_notify::NotifyWork,
+    pow::{
+        boundary_to_difficulty, validate, ProofOfWorkProblem,
+        ProofOfWorkSolution,
+    },
+};
 use cfx_stratum::{
     Error as StratumServiceError, JobDispatcher, PushWorkHandler,
     Stratum as StratumService,
 };
 use cfx_types::H256;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use std::{
+    collections::HashMap,
     fmt,
     net::{AddrParseError, SocketAddr},
     sync::{mpsc, Arc},
+    time::{Duration, Instant},
 };
 
+/// The max number of workers whose shares are tracked. Worker ids are chosen
+/// by the workers, so the least recently submitting ones are dropped once
+/// the limit is reached.
+const MAX_TRACKED_WORKERS: usize = 1024;
+/// The shares of the workers that haven't submitted for this long are
+/// dropped.
+const WORKER_SHARES_EXPIRY: Duration = Duration::from_secs(24 * 3600);
+
 /// Configures stratum server options.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Options {
@@ -96,33 +112,68 @@ impl fmt::Display for PayloadError {
     }
 }
 
+/// The shares submitted by a stratum worker.
+#[derive(Clone, Debug, Default)]
+pub struct ShareStats {
+    /// Shares that solve the current job
+    pub accepted: u64,
+    /// Shares of outdated jobs
+    pub stale: u64,
+    /// Shares that are malformed or do not solve the job
+    pub rejected: u64,
+}
+
+struct WorkerShares {
+    stats: ShareStats,
+    last_submit: Instant,
+}
+
 /// Job dispatcher for stratum service
 pub struct StratumJobDispatcher {
     solution_sender: Mutex<mpsc::Sender<ProofOfWorkSolution>>,
+    /// The job pushed to workers most recently
+    current_problem: RwLock<Option<ProofOfWorkProblem>>,
+    /// worker id -> shares
+    shares: RwLock<HashMap<String, WorkerShares>>,
 }
 
-impl JobDispatcher for StratumJobDispatcher {
-    fn submit(&self, payload: Vec<String>) -> Result<(), StratumServiceError> {
-        let payload = SubmitPayload::from_args(payload)
-            .map_err(|e| StratumServiceError::Dispatch(e.to_string()))?;
-
-        trace!(
-            target: "stratum",
-            "submit_work: Decoded: nonce={}, pow_hash={}",
-            payload.nonce,
-            payload.pow_hash,
-        );
+/// The outcome of checking a submitted share.
+enum ShareOutcome {
+    Accepted(ProofOfWorkSolution),
+    Stale(StratumServiceError),
+    Rejected(StratumServiceError),
+}
 
-        match self.solution_sender.lock().send(ProofOfWorkSolution {
-            nonce: payload.nonce,
-        }) {
-            Ok(_) => {}
-            Err(e) => {
-                warn!("{}", e);
+impl JobDispatcher for StratumJobDispatcher {
+    fn submit(
+        &self, worker_id: &str, payload: Vec<String>,
+    ) -> Result<(), StratumServiceError> {
+        // The share is validated before taking the lock, so that concurrent
+        // submissions do not wait for each other's PoW validation.
+        let outcome = self.check_share(worker_id, payload);
+        {
+            let mut shares = self.shares.write();
+            let stats =
+                Self::worker_stats(&mut shares, worker_id, Instant::now());
+            match &outcome {
+                ShareOutcome::Accepted(_) => stats.accepted += 1,
+                ShareOutcome::Stale(_) => stats.stale += 1,
+                ShareOutcome::Rejected(_) => stats.rejected += 1,
             }
         }
 
-        Ok(())
+        match outcome {
+            ShareOutcome::Accepted(solution) => {
+                match self.solution_sender.lock().send(solution) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("{}", e);
+                    }
+                }
+                Ok(())
+            }
+            ShareOutcome::Stale(e) | ShareOutcome::Rejected(e) => Err(e),
+        }
     }
 }
 
@@ -133,7 +184,94 @@ impl StratumJobDispatcher {
     ) -> StratumJobDispatcher {
         StratumJobDispatcher {
             solution_sender: Mutex::new(solution_sender),
+            current_problem: RwLock::new(None),
+            shares: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Check whether the share in `payload` solves the current job.
+    fn check_share(
+        &self, worker_id: &str, payload: Vec<String>,
+    ) -> ShareOutcome {
+        let payload = match SubmitPayload::from_args(payload) {
+            Ok(payload) => payload,
+            Err(e) => {
+                return ShareOutcome::Rejected(StratumServiceError::Dispatch(
+                    e.to_string(),
+                ));
+            }
+        };
+
+        trace!(
+            target: "stratum",
+            "submit_work: Decoded: worker={}, nonce={}, pow_hash={}",
+            worker_id,
+            payload.nonce,
+            payload.pow_hash,
+        );
+
+        let problem = match *self.current_problem.read() {
+            Some(problem) => problem,
+            None => return ShareOutcome::Rejected(StratumServiceError::NoWork),
+        };
+        if problem.block_hash != payload.pow_hash {
+            return ShareOutcome::Stale(StratumServiceError::Dispatch(
+                format!("stale share for job {:?}", payload.pow_hash),
+            ));
         }
+        let solution = ProofOfWorkSolution {
+            nonce: payload.nonce,
+        };
+        if !validate(&problem, &solution) {
+            return ShareOutcome::Rejected(StratumServiceError::Dispatch(
+                format!(
+                    "invalid nonce {} for job {:?}",
+                    payload.nonce, payload.pow_hash
+                ),
+            ));
+        }
+        ShareOutcome::Accepted(solution)
+    }
+
+    /// The shares of `worker_id` which submits at `now`. Expired workers are
+    /// dropped before tracking a new one, and so is the least recently
+    /// submitting worker if there are still too many.
+    fn worker_stats<'a>(
+        shares: &'a mut HashMap<String, WorkerShares>, worker_id: &str,
+        now: Instant,
+    ) -> &'a mut ShareStats
+    {
+        if !shares.contains_key(worker_id) {
+            shares.retain(|_, worker| {
+                now.duration_since(worker.last_submit) < WORKER_SHARES_EXPIRY
+            });
+            if shares.len() >= MAX_TRACKED_WORKERS {
+                let oldest = shares
+                    .iter()
+                    .min_by_key(|(_, worker)| worker.last_submit)
+                    .map(|(id, _)| id.clone());
+                if let Some(oldest) = oldest {
+                    shares.remove(&oldest);
+                }
+            }
+        }
+
+        let worker = shares.entry(worker_id.to_string()).or_insert_with(|| {
+            WorkerShares {
+                stats: Default::default(),
+                last_submit: now,
+            }
+        });
+        worker.last_submit = now;
+        &mut worker.stats
+    }
+
+    fn set_problem(&self, pow_hash: H256, boundary: H256) {
+        *self.current_problem.write() = Some(ProofOfWorkProblem {
+            block_hash: pow_hash,
+            difficulty: boundary_to_difficulty(&boundary),
+            boundary,
+        });
     }
 
     /// Serializes payload for stratum service
@@ -171,6 +309,8 @@ impl NotifyWork for Stratum {
     fn notify(&self, pow_hash: H256, boundary: H256) {
         trace!(target: "stratum", "Notify work");
 
+        self.dispatcher.set_problem(pow_hash, boundary);
+
         self.service.push_work_all(
             self.dispatcher.payload(pow_hash, boundary)
         ).unwrap_or_else(
@@ -180,6 +320,20 @@ impl NotifyWork for Stratum {
 }
 
 impl Stratum {
+    /// Return the shares submitted by each worker.
+    pub fn share_stats(&self) -> HashMap<String, ShareStats> {
+        let now = Instant::now();
+        self.dispatcher
+            .shares
+            .read()
+            .iter()
+            .filter(|(_, worker)| {
+                now.duration_since(worker.last_submit) < WORKER_SHARES_EXPIRY
+            })
+            .map(|(id, worker)| (id.clone(), worker.stats.clone()))
+            .collect()
+    }
+
     /// New stratum job dispatcher, given the miner, client and dedicated
     /// stratum service
     pub fn start(
@@ -204,3 +358,79 @@ impl Stratum {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        StratumJobDispatcher, MAX_TRACKED_WORKERS, WORKER_SHARES_EXPIRY,
+    };
+    use cfx_stratum::JobDispatcher;
+    use std::{
+        collections::HashMap,
+        sync::mpsc,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_rejected_shares() {
+        let (sender, _receiver) = mpsc::channel();
+        let dispatcher = StratumJobDispatcher::new(sender);
+
+        assert!(dispatcher.submit("worker", vec![]).is_err());
+        assert!(dispatcher
+            .submit("worker", vec!["0".into(), "0x0".into()])
+            .is_err());
+        let shares = dispatcher.shares.read();
+        assert_eq!(shares["worker"].stats.rejected, 2);
+        assert_eq!(shares["worker"].stats.accepted, 0);
+    }
+
+    #[test]
+    fn test_max_tracked_workers() {
+        let mut shares = HashMap::new();
+        let start = Instant::now();
+        for i in 0..MAX_TRACKED_WORKERS {
+            let now = start + Duration::from_secs(i as u64);
+            StratumJobDispatcher::worker_stats(
+                &mut shares,
+                &i.to_string(),
+                now,
+            )
+            .accepted += 1;
+        }
+        assert_eq!(shares.len(), MAX_TRACKED_WORKERS);
+
+        // Worker 0 submits again and is no longer the least recent one.
+        let mut now = start + Duration::from_secs(MAX_TRACKED_WORKERS as u64);
+        StratumJobDispatcher::worker_stats(&mut shares, "0", now).accepted += 1;
+        assert_eq!(shares.len(), MAX_TRACKED_WORKERS);
+
+        now += Duration::from_secs(1);
+        StratumJobDispatcher::worker_stats(&mut shares, "new", now);
+        assert_eq!(shares.len(), MAX_TRACKED_WORKERS);
+        assert!(shares.contains_key("new"));
+        assert!(!shares.contains_key("1"));
+        assert_eq!(shares["0"].stats.accepted, 2);
+    }
+
+    #[test]
+    fn test_expired_workers() {
+        let mut shares = HashMap::new();
+        let start = Instant::now();
+        StratumJobDispatcher::worker_stats(&mut shares, "old", start);
+        StratumJobDispatcher::worker_stats(
+            &mut shares,
+            "recent",
+            start + WORKER_SHARES_EXPIRY / 2,
+        );
+
+        StratumJobDispatcher::worker_stats(
+            &mut shares,
+            "new",
+            start + WORKER_SHARES_EXPIRY,
+        );
+        assert!(!shares.contains_key("old"));
+        assert!(shares.contains_key("recent"));
+        assert!(shares.contains_key("new"));
+    }
+}
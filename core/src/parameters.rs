@@ -16,6 +16,14 @@ pub mod consensus {
     pub const ERA_DEFAULT_EPOCH_COUNT: u64 = 50000;
     // FIXME: We should use finality to determine the checkpoint moment instead.
     pub const ERA_DEFAULT_CHECKPOINT_GAP: u64 = 50000;
+    // The default sizing of the anticone and pastset caches of consensus.
+    pub const ANTICONE_CACHE_DEFAULT_STRIDE: usize = 1000;
+    pub const ANTICONE_CACHE_DEFAULT_MAX_ANTICONE_SIZE: usize = 300;
+    pub const PASTSET_CACHE_DEFAULT_CAPACITY: usize = 256;
+    // The pivot block height from which the remaining transactions of a block
+    // are not executed after an invalid one. Before it, invalid transactions
+    // are skipped individually. Not activated yet.
+    pub const INVALID_TX_REJECT_HEIGHT: u64 = NULLU64;
 
     pub const NULL: usize = !0;
     pub const NULLU64: u64 = !0;
@@ -45,6 +53,12 @@ pub mod consensus_internal {
     // This is the cap of the size of `blockset_in_own_view_of_epoch`. If we
     // have more than this number, we will not store it in memory
     pub const BLOCKSET_IN_OWN_VIEW_OF_EPOCH_CAP: u64 = 1000;
+    // A block whose timestamp is ahead of the local clock by more than this
+    // number of seconds is reported as a misbehavior of its author.
+    pub const MISBEHAVIOR_TIMESTAMP_DEVIATION_SECS: u64 = 60;
+    // The median time past of an epoch is the median of the timestamps of
+    // the pivot blocks of this number of epochs up to it.
+    pub const MEDIAN_TIME_PAST_WINDOW: u64 = 11;
 
     // FIXME Use another method to prevent DDoS attacks if attackers control the
     // pivot chain A block can blame up to BLAME_BOUND ancestors that their
@@ -64,7 +78,17 @@ pub mod sync {
     /// terminals from peers when the node is in catch-up mode.
     pub const REQUEST_TERMINAL_EPOCH_LAG_THRESHOLD: u64 = 8;
 
-    pub const SYNCHRONIZATION_PROTOCOL_VERSION: u8 = 0x01;
+    /// The newest and oldest versions of the synchronization protocol whose
+    /// messages can be handled. Peers use the newest version both support.
+    pub const SYNCHRONIZATION_PROTOCOL_VERSION: u8 = 0x04;
+    pub const SYNCHRONIZATION_PROTOCOL_OLDEST_VERSION: u8 = 0x02;
+    /// The oldest protocol version whose `Status` carries the chain id.
+    pub const CHAIN_ID_PROTOCOL_VERSION: u8 = 0x02;
+    /// The oldest protocol version whose peers can decode
+    /// `DynamicCapability::ServeBlockBodiesFrom`.
+    pub const SERVE_BLOCK_BODIES_FROM_PROTOCOL_VERSION: u8 = 0x03;
+    /// The oldest protocol version whose peers handle `GetBlocksByEpoch`.
+    pub const GET_BLOCKS_BY_EPOCH_PROTOCOL_VERSION: u8 = 0x04;
     /// The max number of headers that are to be sent for header
     /// block request.
     pub const MAX_HEADERS_TO_SEND: u64 = 512;
@@ -74,6 +98,9 @@ pub mod sync {
     /// for request GetBlockHashesByEpoch
     pub const MAX_EPOCHS_TO_SEND: u64 = 128;
     pub const MAX_PACKET_SIZE: usize = 15 * 1024 * 1024 + 512 * 1024; // 15.5 MB
+    /// The max total size of the blocks requested in one response of
+    /// `GetBlocksByEpoch`, the rest being requested with the continuation.
+    pub const BLOCKS_BY_EPOCH_RESPONSE_MAX_BYTES: u64 = 4 * 1024 * 1024;
 
     /// The threshold controlling whether we should query local_block_info in
     /// disk when requesting block header or block. If the difference
@@ -89,6 +116,21 @@ pub mod sync {
             Duration::from_secs(1);
     }
     //const REQUEST_WAITING_TIME_BACKOFF: u32 = 2;
+
+    /// The timeout of a request sent to a peer is estimated as the round trip
+    /// time of the peer times REQUEST_TIMEOUT_RTT_MULTIPLIER, so that the
+    /// requests to the distant peers are not regarded as timeout too early.
+    pub const REQUEST_TIMEOUT_RTT_MULTIPLIER: u32 = 2;
+    /// The responses smaller than THROUGHPUT_SAMPLE_MIN_BYTES are not used to
+    /// measure the throughput of peers, since their time is dominated by the
+    /// latency.
+    pub const THROUGHPUT_SAMPLE_MIN_BYTES: usize = 64 * 1024;
+    /// The estimated sizes of the requested items, which are used to compute
+    /// the expected transfer time of the responses.
+    pub const ESTIMATED_HEADER_SIZE: usize = 512;
+    pub const ESTIMATED_COMPACT_BLOCK_SIZE: usize = 8 * 1024;
+    pub const ESTIMATED_BLOCK_SIZE: usize = 64 * 1024;
+    pub const ESTIMATED_TRANSACTION_SIZE: usize = 256;
 }
 
 pub mod pow {
@@ -140,6 +182,10 @@ pub mod light {
         pub static ref TX_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
         pub static ref TX_INFO_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
 
+        /// The batch size of the requests to a peer is adapted so that its
+        /// responses arrive within this latency.
+        pub static ref TARGET_REQUEST_LATENCY: Duration = Duration::from_millis(500);
+
         /// Maximum time period we wait for a response for an on-demand query.
         /// After this timeout has been reached, we try another peer or give up.
         pub static ref MAX_POLL_TIME: Duration = Duration::from_secs(4);
@@ -149,6 +195,9 @@ pub mod light {
 
         /// Items not accessed for this amount of time are removed from the cache.
         pub static ref CACHE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+        /// Frequency of serving queued light client requests on full nodes.
+        pub static ref SERVE_QUEUE_PERIOD: Duration = Duration::from_millis(100);
     }
 
     /// The threshold controlling whether a node is in catch-up mode.
@@ -169,6 +218,14 @@ pub mod light {
     pub const TX_REQUEST_BATCH_SIZE: usize = 30;
     pub const TX_INFO_REQUEST_BATCH_SIZE: usize = 30;
 
+    /// The batch sizes above are adapted to each peer, from 1 up to this
+    /// multiple of them, according to the latency and size of its responses.
+    pub const MAX_REQUEST_BATCH_SIZE_FACTOR: usize = 4;
+
+    /// The batch size of the requests to a peer is limited so that each of
+    /// its responses is about at most this size.
+    pub const TARGET_RESPONSE_BYTES: usize = 1024 * 1024;
+
     /// Maximum number of in-flight items at any given time.
     /// If we reach this limit, we will not request any more.
     pub const MAX_HEADERS_IN_FLIGHT: usize = 500;
@@ -181,6 +238,10 @@ pub mod light {
     pub const MAX_TXS_IN_FLIGHT: usize = 100;
     pub const MAX_TX_INFOS_IN_FLIGHT: usize = 100;
 
+    /// Maximum number of times an item is requested again after its request
+    /// times out or its response is invalid, before we give up on it.
+    pub const MAX_REQUEST_RETRIES: usize = 5;
+
     /// Maximum number of in-flight epoch requests at any given time.
     /// Similar to `MAX_HEADERS_IN_FLIGHT`. However, it is hard to match
     /// hash responses to epoch requests, so we count the requests instead.
@@ -209,6 +270,35 @@ pub mod light {
     /// there's always plenty of items in flight. This way, we can reduce idle
     /// time when we're waiting to recveive an item.
     pub const LOG_FILTERING_LOOKAHEAD: usize = 100;
+
+    /// Default number of epochs for which we keep verified witness roots
+    /// (both in memory and in the db). Roots of older epochs are pruned.
+    pub const WITNESS_HISTORY_EPOCHS: u64 = 20_000;
+
+    /// Flow control parameters announced to light peers during handshake.
+    /// Each light peer can spend at most `MAX_CREDITS` credits at once, and
+    /// its credits recharge by `RECHARGE_RATE` per second.
+    pub const MAX_CREDITS: u64 = 100_000;
+    pub const RECHARGE_RATE: u64 = 10_000;
+
+    /// Cost of serving a request: a base cost plus a cost for each item
+    /// requested. Requests touching the disk (receipts, blocks) are pricier.
+    pub const BASE_REQUEST_COST: u64 = 10;
+    pub const HEADER_COST: u64 = 10;
+    pub const EPOCH_COST: u64 = 20;
+    pub const WITNESS_COST: u64 = 50;
+    pub const BLOOM_COST: u64 = 20;
+    pub const RECEIPT_COST: u64 = 200;
+    pub const BLOCK_TX_COST: u64 = 200;
+    pub const STATE_ROOT_COST: u64 = 50;
+    pub const STATE_ENTRY_COST: u64 = 100;
+    pub const TX_COST: u64 = 20;
+    pub const TX_INFO_COST: u64 = 300;
+    pub const SEND_RAW_TX_COST: u64 = 100;
+
+    /// Maximum number of requests from a single light peer that we queue
+    /// when it runs out of credits. Beyond this, requests are rejected.
+    pub const MAX_QUEUED_REQUESTS_PER_PEER: usize = 16;
 }
 
 pub const WORKER_COMPUTATION_PARALLELISM: usize = 8;
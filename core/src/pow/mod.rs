@@ -8,7 +8,13 @@ use crate::{
 use cfx_types::{BigEndianHash, H256, U256, U512};
 use parking_lot::RwLock;
 use rlp::RlpStream;
-use std::{collections::HashMap, convert::TryFrom};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryFrom,
+};
+
+/// The number of latest adjustment periods kept in the difficulty history.
+pub const MAX_DIFFICULTY_HISTORY: usize = 1000;
 
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
 pub struct ProofOfWorkProblem {
@@ -87,6 +93,23 @@ impl ProofOfWorkConfig {
         U256::try_from(target).unwrap()
     }
 
+    /// Compute the difficulty of the next period from the block count and
+    /// the timespan of the current period, bounded by the adjustment limit.
+    pub fn next_difficulty(
+        &self, block_count: u64, timespan: u64, cur_difficulty: &U256,
+    ) -> U256 {
+        let target_diff =
+            self.target_difficulty(block_count, timespan, cur_difficulty);
+        let (lower, upper) = self.get_adjustment_bound(*cur_difficulty);
+        if target_diff > upper {
+            upper
+        } else if target_diff < lower {
+            lower
+        } else {
+            target_diff
+        }
+    }
+
     pub fn get_adjustment_bound(&self, diff: U256) -> (U256, U256) {
         let adjustment = diff / DIFFICULTY_ADJUSTMENT_FACTOR;
         let mut min_diff = diff - adjustment;
@@ -189,28 +212,102 @@ where
         assert!(max_time >= min_time);
     }
 
-    let mut target_diff = pow_config.target_difficulty(
+    let target_diff = pow_config.next_difficulty(
         block_count,
         max_time - min_time,
         &cur_difficulty,
     );
 
-    let (lower, upper) = pow_config.get_adjustment_bound(cur_difficulty);
-    if target_diff > upper {
-        target_diff = upper;
-    }
-    if target_diff < lower {
-        target_diff = lower;
-    }
-
     // Caching the computed target difficulty of this period.
     data_man
         .target_difficulty_manager
         .set(*cur_hash, target_diff);
+    data_man.target_difficulty_manager.record(DifficultyPeriod {
+        height: epoch,
+        block_hash: *cur_hash,
+        block_count,
+        timespan: max_time - min_time,
+        difficulty: cur_difficulty,
+        target_difficulty: target_diff,
+    });
 
     target_diff
 }
 
+/// Simulate the difficulty adjustment on a pivot chain whose blocks have the
+/// given timestamps, starting from the genesis block at index 0. Each epoch
+/// is assumed to contain only the pivot block. Return the difficulty of
+/// each block, which is computed in the same way as `target_difficulty`.
+pub fn simulate_difficulties(
+    pow_config: &ProofOfWorkConfig, timestamps: &[u64],
+) -> Result<Vec<U256>, String> {
+    if pow_config.difficulty_adjustment_epoch_period == 0 {
+        return Err("The difficulty adjustment period is zero".into());
+    }
+    let period = pow_config.difficulty_adjustment_epoch_period as usize;
+    let initial_difficulty: U256 = pow_config.initial_difficulty.into();
+    let mut difficulties: Vec<U256> = Vec::with_capacity(timestamps.len());
+
+    for height in 0..timestamps.len() {
+        if height <= period {
+            difficulties.push(initial_difficulty);
+            continue;
+        }
+
+        let parent = height - 1;
+        let parent_difficulty = difficulties[parent];
+        if parent % period != 0 {
+            difficulties.push(parent_difficulty);
+            continue;
+        }
+
+        // The genesis timestamp is ignored if it is zero.
+        let mut min_time = timestamps[parent - period];
+        if min_time == 0 {
+            min_time = timestamps[parent - period + 1];
+        }
+        let timespan = timestamps[parent].saturating_sub(min_time);
+        difficulties.push(pow_config.next_difficulty(
+            period as u64,
+            timespan,
+            &parent_difficulty,
+        ));
+    }
+
+    Ok(difficulties)
+}
+
+/// The statistics of an adjustment period, from which the difficulty of the
+/// next period is computed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DifficultyPeriod {
+    /// The height of the upper boundary block of the period.
+    pub height: u64,
+    /// The hash of the upper boundary block of the period.
+    pub block_hash: H256,
+    /// The number of blocks in the period.
+    pub block_count: u64,
+    /// The timespan of the period in seconds.
+    pub timespan: u64,
+    /// The difficulty of the period.
+    pub difficulty: U256,
+    /// The computed difficulty of the next period.
+    pub target_difficulty: U256,
+}
+
+impl DifficultyPeriod {
+    /// The estimated network hashrate (hashes per second) in the period.
+    pub fn estimated_hashrate(&self) -> U256 {
+        if self.timespan == 0 {
+            return U256::zero();
+        }
+        let hashrate = U512::from(self.difficulty)
+            * U512::from(self.block_count)
+            / U512::from(self.timespan);
+        U256::try_from(hashrate).unwrap_or(U256::max_value())
+    }
+}
+
 //FIXME: make entries replaceable
 struct TargetDifficultyCacheInner {
     cache: HashMap<H256, U256>,
@@ -250,14 +347,17 @@ impl TargetDifficultyCache {
 /// This is a data structure to cache the computed target difficulty
 /// of a adjustment period. Each element is indexed by the hash of
 /// the upper boundary block of the period.
+/// It also keeps the statistics of the latest periods for monitoring.
 pub struct TargetDifficultyManager {
     cache: TargetDifficultyCache,
+    history: RwLock<VecDeque<DifficultyPeriod>>,
 }
 
 impl TargetDifficultyManager {
     pub fn new() -> Self {
         TargetDifficultyManager {
             cache: TargetDifficultyCache::new(),
+            history: RwLock::new(VecDeque::new()),
         }
     }
 
@@ -266,4 +366,58 @@ impl TargetDifficultyManager {
     pub fn set(&self, hash: H256, difficulty: U256) {
         self.cache.set(hash, difficulty);
     }
+
+    pub fn record(&self, period: DifficultyPeriod) {
+        let mut history = self.history.write();
+        history.push_back(period);
+        while history.len() > MAX_DIFFICULTY_HISTORY {
+            history.pop_front();
+        }
+    }
+
+    /// Return the statistics of the latest `count` computed periods sorted
+    /// by height. Periods on forks of the pivot chain are also included.
+    pub fn history(&self, count: usize) -> Vec<DifficultyPeriod> {
+        let history = self.history.read();
+        let mut periods: Vec<_> = history
+            .iter()
+            .skip(history.len().saturating_sub(count))
+            .cloned()
+            .collect();
+        periods.sort_by_key(|period| period.height);
+        periods
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{simulate_difficulties, ProofOfWorkConfig};
+    use cfx_types::U256;
+
+    #[test]
+    fn test_simulate_difficulties() {
+        let mut pow_config =
+            ProofOfWorkConfig::new(false, false, None, String::new(), 0, None);
+        pow_config.initial_difficulty = 1000;
+        pow_config.difficulty_adjustment_epoch_period = 2;
+        pow_config.block_generation_period = 1000000;
+
+        // Blocks are generated twice as fast as the target.
+        let timestamps: Vec<u64> = (0..8).map(|i| i / 2 + 1).collect();
+        let difficulties =
+            simulate_difficulties(&pow_config, &timestamps).unwrap();
+        assert_eq!(difficulties.len(), 8);
+        assert_eq!(difficulties[2], U256::from(1000));
+        assert!(difficulties[3] > difficulties[2]);
+        assert_eq!(difficulties[4], difficulties[3]);
+        assert!(difficulties[5] > difficulties[4]);
+        assert_eq!(
+            simulate_difficulties(&pow_config, &timestamps),
+            Ok(difficulties)
+        );
+        assert_eq!(simulate_difficulties(&pow_config, &[]), Ok(vec![]));
+
+        pow_config.difficulty_adjustment_epoch_period = 0;
+        assert!(simulate_difficulties(&pow_config, &timestamps).is_err());
+    }
 }
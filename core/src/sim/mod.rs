@@ -0,0 +1,478 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+//! A deterministic in-process simulation of multiple nodes, which runs the
+//! consensus and synchronization graphs of every node in one process and
+//! connects them with a simulated network. The latency, drops and partitions
+//! of the network are controlled by the test, and all the random choices are
+//! drawn from a seeded rng, so that a schedule is replayed exactly with the
+//! same seed.
+
+#[cfg(test)]
+mod tests;
+
+use crate::{
+    consensus::{DefaultGhastRule, GhastRule},
+    parameters::consensus::{
+        ADAPTIVE_WEIGHT_DEFAULT_ALPHA_DEN, ADAPTIVE_WEIGHT_DEFAULT_ALPHA_NUM,
+        ADAPTIVE_WEIGHT_DEFAULT_BETA, ERA_DEFAULT_EPOCH_COUNT,
+        HEAVY_BLOCK_DEFAULT_DIFFICULTY_RATIO,
+    },
+    sync::{
+        utils::{
+            create_simple_block_impl,
+            initialize_synchronization_graph_with_ghast_rule,
+        },
+        SynchronizationGraph,
+    },
+    ConsensusGraph,
+};
+use cfx_types::{H256, U256};
+use primitives::{Block, EpochNumber};
+use rand::{ChaChaRng, Rng, RngCore, SeedableRng};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs,
+    sync::Arc,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// The max time to wait for consensus to process the inserted blocks.
+const CONSENSUS_TIMEOUT: Duration = Duration::from_secs(60);
+const CONSENSUS_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Clone)]
+pub struct SimConfig {
+    pub node_count: usize,
+    /// The seed of all the random choices of the simulation.
+    pub seed: u64,
+    /// The latency of a message is drawn uniformly from
+    /// `[min_latency, max_latency]` ticks.
+    pub min_latency: u64,
+    pub max_latency: u64,
+    /// The probability that a message is lost.
+    pub drop_probability: f64,
+    /// The ticks to wait before requesting a missing block again.
+    pub request_timeout: u64,
+    pub adaptive_weight_alpha_num: u64,
+    pub adaptive_weight_alpha_den: u64,
+    pub adaptive_weight_beta: u64,
+    pub heavy_block_difficulty_ratio: u64,
+    pub era_epoch_count: u64,
+    /// The GHAST rule of all nodes, which may be an experimental one.
+    pub ghast_rule: Arc<dyn GhastRule>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            node_count: 4,
+            seed: 0,
+            min_latency: 10,
+            max_latency: 100,
+            drop_probability: 0.0,
+            request_timeout: 500,
+            adaptive_weight_alpha_num: ADAPTIVE_WEIGHT_DEFAULT_ALPHA_NUM,
+            adaptive_weight_alpha_den: ADAPTIVE_WEIGHT_DEFAULT_ALPHA_DEN,
+            adaptive_weight_beta: ADAPTIVE_WEIGHT_DEFAULT_BETA,
+            heavy_block_difficulty_ratio: HEAVY_BLOCK_DEFAULT_DIFFICULTY_RATIO,
+            era_epoch_count: ERA_DEFAULT_EPOCH_COUNT,
+            ghast_rule: Arc::new(DefaultGhastRule),
+        }
+    }
+}
+
+enum SimMessage {
+    /// New blocks relayed to a peer, or blocks sent in response to
+    /// `GetBlocks`.
+    Blocks(Vec<Block>),
+    GetBlocks(Vec<H256>),
+    /// A timer of the receiving node to request a missing block again if it
+    /// hasn't arrived yet.
+    Retry(H256),
+}
+
+struct Envelope {
+    deliver_at: u64,
+    /// The send order, which breaks ties of `deliver_at` deterministically.
+    seq: u64,
+    from: usize,
+    to: usize,
+    message: SimMessage,
+}
+
+impl PartialEq for Envelope {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for Envelope {}
+
+impl PartialOrd for Envelope {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Envelope {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.deliver_at, self.seq).cmp(&(other.deliver_at, other.seq))
+    }
+}
+
+pub struct SimNode {
+    pub sync: Arc<SynchronizationGraph>,
+    pub consensus: Arc<ConsensusGraph>,
+    /// The blocks inserted into the graphs of this node.
+    blocks: HashMap<H256, Block>,
+    /// The received blocks whose parent or referees are still missing.
+    orphans: HashMap<H256, Block>,
+    /// The missing blocks which are requested from peers.
+    requested: HashSet<H256>,
+    /// The inserted blocks which may not be processed by consensus yet.
+    unprocessed: Vec<H256>,
+}
+
+impl SimNode {
+    fn new(db_dir: &str, conf: &SimConfig) -> Self {
+        let (sync, consensus, genesis) =
+            initialize_synchronization_graph_with_ghast_rule(
+                db_dir,
+                conf.adaptive_weight_alpha_den,
+                conf.adaptive_weight_alpha_num,
+                conf.adaptive_weight_beta,
+                conf.heavy_block_difficulty_ratio,
+                conf.era_epoch_count,
+                conf.ghast_rule.clone(),
+            );
+        let mut blocks = HashMap::new();
+        blocks.insert(genesis.hash(), genesis.as_ref().clone());
+        SimNode {
+            sync,
+            consensus,
+            blocks,
+            orphans: HashMap::new(),
+            requested: HashSet::new(),
+            unprocessed: Vec::new(),
+        }
+    }
+
+    fn knows(&self, hash: &H256) -> bool {
+        self.blocks.contains_key(hash) || self.orphans.contains_key(hash)
+    }
+
+    fn missing_dependencies(&self, block: &Block) -> Vec<H256> {
+        let header = &block.block_header;
+        std::iter::once(header.parent_hash())
+            .chain(header.referee_hashes().iter())
+            .filter(|hash| !self.blocks.contains_key(*hash))
+            .cloned()
+            .collect()
+    }
+
+    fn insert(&mut self, block: Block) {
+        let hash = block.hash();
+        self.sync.insert_block_header(
+            &mut block.block_header.clone(),
+            false, // need_to_verify
+            true,  // bench_mode
+            false, // insert_to_consensus
+            true,  // persistent
+        );
+        self.sync.insert_block(
+            block.clone(),
+            false, // need_to_verify
+            false, // persistent
+            false, // recover_from_db
+        );
+        self.requested.remove(&hash);
+        self.unprocessed.push(hash);
+        self.blocks.insert(hash, block);
+    }
+
+    /// Insert the orphans whose dependencies have all been inserted, and
+    /// return them in the insertion order.
+    fn insert_ready_orphans(&mut self) -> Vec<Block> {
+        let mut inserted = Vec::new();
+        loop {
+            let mut ready: Vec<H256> = self
+                .orphans
+                .iter()
+                .filter(|(_, block)| {
+                    self.missing_dependencies(block).is_empty()
+                })
+                .map(|(hash, _)| *hash)
+                .collect();
+            if ready.is_empty() {
+                return inserted;
+            }
+            // Iterating a `HashMap` isn't deterministic.
+            ready.sort();
+            for hash in ready {
+                let block = self.orphans.remove(&hash).unwrap();
+                inserted.push(block.clone());
+                self.insert(block);
+            }
+        }
+    }
+
+    /// Wait until consensus processes all the inserted blocks, so that the
+    /// pivot chain reflects them. It panics if a block is not processed in
+    /// `CONSENSUS_TIMEOUT`, e.g. if it's rejected by the synchronization
+    /// graph, instead of hanging the simulation.
+    fn wait_for_consensus(&mut self) {
+        let deadline = Instant::now() + CONSENSUS_TIMEOUT;
+        for hash in self.unprocessed.drain(..) {
+            while !self
+                .consensus
+                .inner
+                .read_recursive()
+                .hash_to_arena_indices
+                .contains_key(&hash)
+            {
+                if Instant::now() >= deadline {
+                    panic!(
+                        "Block {:?} is not processed by consensus in {:?}",
+                        hash, CONSENSUS_TIMEOUT
+                    );
+                }
+                sleep(CONSENSUS_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+/// The simulated nodes and the network connecting them. Time is measured in
+/// ticks and only advances in `run_for`, which delivers the messages in the
+/// order of their delivery time.
+pub struct SimNetwork {
+    conf: SimConfig,
+    nodes: Vec<SimNode>,
+    rng: ChaChaRng,
+    queue: BinaryHeap<Reverse<Envelope>>,
+    now: u64,
+    next_seq: u64,
+    /// The partition group of each node. Messages between nodes of different
+    /// groups are dropped.
+    groups: Vec<usize>,
+}
+
+impl SimNetwork {
+    /// Create the nodes with their databases under `db_dir`.
+    pub fn new(db_dir: &str, conf: SimConfig) -> Self {
+        fs::create_dir_all(db_dir).expect("Failed to create db dir");
+        let nodes = (0..conf.node_count)
+            .map(|i| SimNode::new(&format!("{}/node{}", db_dir, i), &conf))
+            .collect();
+        let mut seed = [0u8; 32];
+        seed[..8].copy_from_slice(&conf.seed.to_le_bytes());
+        SimNetwork {
+            groups: vec![0; conf.node_count],
+            rng: ChaChaRng::from_seed(seed),
+            conf,
+            nodes,
+            queue: BinaryHeap::new(),
+            now: 0,
+            next_seq: 0,
+        }
+    }
+
+    pub fn now(&self) -> u64 { self.now }
+
+    pub fn node_count(&self) -> usize { self.nodes.len() }
+
+    pub fn node(&mut self, node: usize) -> &SimNode {
+        self.nodes[node].wait_for_consensus();
+        &self.nodes[node]
+    }
+
+    /// A node chosen with the rng of the simulation, e.g. to decide which
+    /// node mines the next block.
+    pub fn random_node(&mut self) -> usize {
+        self.rng.gen_range(0, self.nodes.len())
+    }
+
+    pub fn set_latency(&mut self, min_latency: u64, max_latency: u64) {
+        self.conf.min_latency = min_latency;
+        self.conf.max_latency = max_latency;
+    }
+
+    pub fn set_drop_probability(&mut self, drop_probability: f64) {
+        self.conf.drop_probability = drop_probability;
+    }
+
+    /// Split the nodes into the given groups. A node not in any group is
+    /// isolated from all other nodes.
+    pub fn partition(&mut self, groups: &[&[usize]]) {
+        let node_count = self.nodes.len();
+        self.groups = (0..node_count).map(|i| groups.len() + i).collect();
+        for (group, nodes) in groups.iter().enumerate() {
+            for node in nodes.iter() {
+                self.groups[*node] = group;
+            }
+        }
+    }
+
+    pub fn heal(&mut self) { self.groups = vec![0; self.nodes.len()]; }
+
+    /// Mine a block at `node` as an honest miner, i.e. on its pivot chain
+    /// and referencing all its other tips, and broadcast it.
+    pub fn mine(&mut self, node: usize) -> H256 {
+        let best_info = self.node(node).consensus.get_best_info();
+        let parent = best_info.best_block_hash;
+        let referees = best_info
+            .bounded_terminal_block_hashes
+            .iter()
+            .filter(|hash| **hash != parent)
+            .cloned()
+            .collect();
+        self.mine_on(node, parent, referees, 1)
+    }
+
+    /// Mine a block at `node` with the given parent, referees and weight,
+    /// which is how adversarial miners are scripted, and broadcast it. The
+    /// parent and referees must be known by the node.
+    pub fn mine_on(
+        &mut self, node: usize, parent: H256, referees: Vec<H256>, weight: u32,
+    ) -> H256 {
+        let height = self.nodes[node].blocks[&parent].block_header.height();
+        let (hash, block) = create_simple_block_impl(
+            parent,
+            referees,
+            height + 1,
+            self.rng.next_u64(),
+            U256::from(10),
+            weight,
+        );
+        assert!(
+            self.nodes[node].missing_dependencies(&block).is_empty(),
+            "The dependencies of a mined block must be known by the miner"
+        );
+        self.nodes[node].insert(block.clone());
+        self.broadcast(node, None, vec![block]);
+        hash
+    }
+
+    /// Deliver the messages due in the next `ticks` ticks.
+    pub fn run_for(&mut self, ticks: u64) {
+        let until = self.now + ticks;
+        while self
+            .queue
+            .peek()
+            .map_or(false, |envelope| envelope.0.deliver_at <= until)
+        {
+            let envelope = self.queue.pop().unwrap().0;
+            self.now = envelope.deliver_at;
+            self.deliver(envelope);
+        }
+        self.now = until;
+    }
+
+    /// The pivot chain of `node` from genesis.
+    pub fn pivot_chain(&mut self, node: usize) -> Vec<H256> {
+        let consensus = &self.node(node).consensus;
+        (0..=consensus.best_epoch_number())
+            .map(|epoch| {
+                consensus
+                    .get_hash_from_epoch_number(EpochNumber::Number(epoch))
+                    .expect("epoch on the pivot chain")
+            })
+            .collect()
+    }
+
+    /// The hashes of the blocks inserted into the graphs of `node`.
+    pub fn block_hashes(&self, node: usize) -> HashSet<H256> {
+        self.nodes[node].blocks.keys().cloned().collect()
+    }
+
+    fn send(&mut self, from: usize, to: usize, message: SimMessage) {
+        if self.rng.gen::<f64>() < self.conf.drop_probability {
+            return;
+        }
+        let latency = self
+            .rng
+            .gen_range(self.conf.min_latency, self.conf.max_latency + 1);
+        self.schedule(from, to, self.now + latency, message);
+    }
+
+    fn schedule(
+        &mut self, from: usize, to: usize, deliver_at: u64, message: SimMessage,
+    ) {
+        self.queue.push(Reverse(Envelope {
+            deliver_at,
+            seq: self.next_seq,
+            from,
+            to,
+            message,
+        }));
+        self.next_seq += 1;
+    }
+
+    fn broadcast(
+        &mut self, from: usize, except: Option<usize>, blocks: Vec<Block>,
+    ) {
+        for to in 0..self.nodes.len() {
+            if to != from && Some(to) != except {
+                self.send(from, to, SimMessage::Blocks(blocks.clone()));
+            }
+        }
+    }
+
+    fn request(&mut self, node: usize, peer: usize, hash: H256) {
+        self.send(node, peer, SimMessage::GetBlocks(vec![hash]));
+        let retry_at = self.now + self.conf.request_timeout;
+        self.schedule(peer, node, retry_at, SimMessage::Retry(hash));
+    }
+
+    fn deliver(&mut self, envelope: Envelope) {
+        let Envelope {
+            from, to, message, ..
+        } = envelope;
+        match message {
+            SimMessage::Retry(hash) => {
+                if !self.nodes[to].knows(&hash) {
+                    self.request(to, from, hash);
+                }
+            }
+            _ if self.groups[from] != self.groups[to] => {}
+            SimMessage::Blocks(blocks) => self.on_blocks(to, from, blocks),
+            SimMessage::GetBlocks(hashes) => {
+                let blocks: Vec<Block> = hashes
+                    .iter()
+                    .filter_map(|hash| self.nodes[to].blocks.get(hash))
+                    .cloned()
+                    .collect();
+                if !blocks.is_empty() {
+                    self.send(to, from, SimMessage::Blocks(blocks));
+                }
+            }
+        }
+    }
+
+    fn on_blocks(&mut self, node: usize, peer: usize, blocks: Vec<Block>) {
+        let mut to_request = Vec::new();
+        for block in blocks {
+            if self.nodes[node].knows(&block.hash()) {
+                continue;
+            }
+            for hash in self.nodes[node].missing_dependencies(&block) {
+                if !self.nodes[node].knows(&hash)
+                    && self.nodes[node].requested.insert(hash)
+                {
+                    to_request.push(hash);
+                }
+            }
+            self.nodes[node].orphans.insert(block.hash(), block);
+        }
+        for hash in to_request {
+            self.request(node, peer, hash);
+        }
+
+        let inserted = self.nodes[node].insert_ready_orphans();
+        if !inserted.is_empty() {
+            self.broadcast(node, Some(peer), inserted);
+        }
+    }
+}
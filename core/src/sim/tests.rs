@@ -0,0 +1,157 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::{SimConfig, SimNetwork};
+use crate::consensus::{
+    consensus_inner::{ConsensusGraphInner, ConsensusInnerConfig},
+    GhastRule, SubtreeWeights,
+};
+use cfx_types::{H256, U256};
+use hibitset::BitSet;
+use std::{
+    fs,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Mine `rounds` blocks at random nodes over a lossy network, and return the
+/// pivot chains of all nodes after the network recovers.
+fn run_random_schedule(
+    db_dir: &str, seed: u64, rounds: usize,
+) -> Vec<Vec<H256>> {
+    let mut sim = SimNetwork::new(
+        db_dir,
+        SimConfig {
+            node_count: 3,
+            seed,
+            drop_probability: 0.1,
+            ..Default::default()
+        },
+    );
+    for _ in 0..rounds {
+        let node = sim.random_node();
+        sim.mine(node);
+        sim.run_for(50);
+    }
+    // Every tip is referenced by a new block, so that the blocks which were
+    // dropped are requested by all nodes.
+    sim.set_drop_probability(0.0);
+    for node in 0..sim.node_count() {
+        sim.mine(node);
+    }
+    sim.run_for(10000);
+    (0..sim.node_count()).map(|i| sim.pivot_chain(i)).collect()
+}
+
+#[test]
+fn test_partition_converges_after_heal() {
+    let db_dir = "./sim_partition_test.db";
+    {
+        let mut sim = SimNetwork::new(db_dir, SimConfig::default());
+        sim.partition(&[&[0, 1, 2], &[3]]);
+        for i in 0..20 {
+            sim.mine(i % 3);
+            sim.mine(3);
+            sim.run_for(100);
+        }
+        assert_ne!(sim.pivot_chain(0), sim.pivot_chain(3));
+
+        sim.heal();
+        sim.mine(0);
+        sim.mine(3);
+        sim.run_for(10000);
+
+        let blocks = sim.block_hashes(0);
+        let pivot_chain = sim.pivot_chain(0);
+        // 20 blocks of the majority and 20 blocks of the isolated node, plus
+        // genesis and the blocks mined after healing.
+        assert_eq!(blocks.len(), 43);
+        for i in 1..sim.node_count() {
+            assert_eq!(sim.block_hashes(i), blocks);
+            assert_eq!(sim.pivot_chain(i), pivot_chain);
+        }
+    }
+    fs::remove_dir_all(db_dir).unwrap();
+}
+
+#[test]
+fn test_replay_with_same_seed() {
+    let db_dir = "./sim_replay_test.db";
+    let first = run_random_schedule(&format!("{}/first", db_dir), 7, 30);
+    let second = run_random_schedule(&format!("{}/second", db_dir), 7, 30);
+    assert_eq!(first, second);
+    for pivot_chain in &first[1..] {
+        assert_eq!(pivot_chain, &first[0]);
+    }
+    fs::remove_dir_all(db_dir).unwrap();
+}
+
+/// A rule giving every block the same weight and never forming checkpoints,
+/// which only uses the public interface of the consensus graph.
+#[derive(Default)]
+struct UnitWeightRule {
+    weighted_blocks: AtomicUsize,
+    adaptive_decisions: AtomicUsize,
+}
+
+impl GhastRule for UnitWeightRule {
+    fn block_weight(
+        &self, _inner_conf: &ConsensusInnerConfig, _difficulty: &U256,
+        _is_heavy: bool, _is_adaptive: bool,
+    ) -> i128
+    {
+        self.weighted_blocks.fetch_add(1, Ordering::Relaxed);
+        1
+    }
+
+    fn adaptive_weight(
+        &self, inner: &mut ConsensusGraphInner, parent: usize,
+        anticone_barrier: &BitSet, subtree_weights: Option<&SubtreeWeights>,
+        difficulty: i128,
+    ) -> (bool, bool)
+    {
+        self.adaptive_decisions.fetch_add(1, Ordering::Relaxed);
+        inner.adaptive_weight_impl(
+            parent,
+            anticone_barrier,
+            subtree_weights,
+            difficulty,
+        )
+    }
+
+    fn checkpoint_era_genesis(&self, inner: &ConsensusGraphInner) -> usize {
+        inner.cur_era_genesis_block_arena_index
+    }
+}
+
+#[test]
+fn test_custom_ghast_rule() {
+    let db_dir = "./sim_custom_rule_test.db";
+    let rule = Arc::new(UnitWeightRule::default());
+    {
+        let mut sim = SimNetwork::new(
+            db_dir,
+            SimConfig {
+                node_count: 3,
+                ghast_rule: rule.clone(),
+                ..Default::default()
+            },
+        );
+        for i in 0..30 {
+            sim.mine(i % 3);
+            sim.run_for(50);
+        }
+        sim.run_for(10000);
+
+        let pivot_chain = sim.pivot_chain(0);
+        for i in 1..sim.node_count() {
+            assert_eq!(sim.pivot_chain(i), pivot_chain);
+        }
+    }
+    assert!(rule.weighted_blocks.load(Ordering::Relaxed) > 0);
+    assert!(rule.adaptive_decisions.load(Ordering::Relaxed) > 0);
+    fs::remove_dir_all(db_dir).unwrap();
+}
@@ -98,6 +98,10 @@ impl OverlayAccount {
 
     pub fn code_hash(&self) -> H256 { self.code_hash.clone() }
 
+    pub fn storage_changes(&self) -> &HashMap<H256, H256> {
+        &self.storage_changes
+    }
+
     pub fn code_size(&self) -> Option<usize> { self.code_size.clone() }
 
     pub fn code(&self) -> Option<Arc<Bytes>> {
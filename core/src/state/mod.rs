@@ -42,6 +42,16 @@ pub enum CleanupMode<'a> {
     TrackTouched(&'a mut HashSet<Address>),
 }
 
+/// An account modified by the state since the last commit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountDiff {
+    pub address: Address,
+    /// The new account, or `None` if it is deleted.
+    pub account: Option<Account>,
+    /// The storage entries written, sorted by key.
+    pub storage_changes: Vec<(H256, H256)>,
+}
+
 pub struct State<'a> {
     db: StateDb<'a>,
 
@@ -252,10 +262,52 @@ impl<'a> State<'a> {
         }
     }
 
+    /// The accounts modified since the last commit, sorted by address.
+    pub fn dirty_accounts(&self) -> Vec<AccountDiff> {
+        let accounts = self.cache.borrow();
+        let mut diffs = accounts
+            .iter()
+            .filter(|&(_, entry)| entry.is_dirty())
+            .map(|(address, entry)| {
+                let mut storage_changes = entry
+                    .account
+                    .as_ref()
+                    .map(|account| {
+                        account
+                            .storage_changes()
+                            .iter()
+                            .map(|(k, v)| (*k, *v))
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default();
+                storage_changes.sort();
+                AccountDiff {
+                    address: *address,
+                    account: entry.account.as_ref().map(|a| a.as_account()),
+                    storage_changes,
+                }
+            })
+            .collect::<Vec<_>>();
+        diffs.sort_by(|a, b| a.address.cmp(&b.address));
+        diffs
+    }
+
     pub fn commit(
         &mut self, epoch_id: EpochId,
     ) -> DbResult<StateRootWithAuxInfo> {
         debug!("Commit epoch {}", epoch_id);
+        self.write_dirty_accounts()?;
+        Ok(self.db.commit(epoch_id)?)
+    }
+
+    /// Compute the state root with the dirty accounts without committing the
+    /// state.
+    pub fn compute_state_root(&mut self) -> DbResult<StateRootWithAuxInfo> {
+        self.write_dirty_accounts()?;
+        Ok(self.db.compute_state_root()?)
+    }
+
+    fn write_dirty_accounts(&mut self) -> DbResult<()> {
         assert!(self.checkpoints.borrow().is_empty());
 
         let mut accounts = self.cache.borrow_mut();
@@ -274,7 +326,7 @@ impl<'a> State<'a> {
                 self.db.delete(&self.db.account_key(address))?;
             }
         }
-        Ok(self.db.commit(epoch_id)?)
+        Ok(())
     }
 
     pub fn commit_and_notify(
@@ -312,6 +364,7 @@ impl<'a> State<'a> {
             }
         }
         let result = self.db.commit(epoch_id)?;
+        txpool.notify_touched_accounts(&epoch_id, sorted_dirty_addresses);
         {
             let txpool_clone = txpool.clone();
             std::thread::Builder::new()
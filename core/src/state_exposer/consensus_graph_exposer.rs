@@ -3,9 +3,18 @@
 // See http://www.gnu.org/licenses/
 
 use cfx_types::H256;
+use serde_derive::Serialize;
 
-#[derive(Default)]
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
 /// This struct maintains some inner state of consensus graph.
 pub struct ConsensusGraphExposer {
     pub best_block_hash: H256,
+    /// The epoch number of the last hash in `pivot_tail`.
+    pub best_epoch_number: u64,
+    /// The hashes of the last few pivot blocks, oldest first.
+    pub pivot_tail: Vec<H256>,
+    pub terminal_block_hashes: Vec<H256>,
+    /// The number of epochs waiting in the execution queue.
+    pub execution_queue_depth: usize,
 }
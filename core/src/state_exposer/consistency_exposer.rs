@@ -0,0 +1,18 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use serde_derive::Serialize;
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+/// This struct maintains the results of the consistency checks between the
+/// synchronization graph and the consensus graph.
+pub struct ConsistencyExposer {
+    /// The number of the checks done.
+    pub checks: u64,
+    /// The total number of the discrepancies found in all the checks.
+    pub total_discrepancies: u64,
+    /// The discrepancies found in the last check.
+    pub last_discrepancies: Vec<String>,
+}
@@ -3,31 +3,49 @@
 // See http://www.gnu.org/licenses/
 
 mod consensus_graph_exposer;
+mod consistency_exposer;
 mod network_exposer;
 mod sync_graph_exposer;
+mod transaction_pool_exposer;
 
-use self::{
+pub use self::{
     consensus_graph_exposer::ConsensusGraphExposer,
-    network_exposer::NetworkExposer, sync_graph_exposer::SyncGraphExposer,
+    consistency_exposer::ConsistencyExposer, network_exposer::NetworkExposer,
+    sync_graph_exposer::SyncGraphExposer,
+    transaction_pool_exposer::TransactionPoolExposer,
 };
 
 use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use serde_derive::Serialize;
 use std::sync::Arc;
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct StateExposerInner {
     pub consensus_graph: ConsensusGraphExposer,
     pub sync_graph: SyncGraphExposer,
+    pub transaction_pool: TransactionPoolExposer,
+    #[serde(skip)]
     pub network: NetworkExposer,
+    pub consistency: ConsistencyExposer,
 }
 
 impl StateExposerInner {
     pub fn new() -> Self {
         Self {
             consensus_graph: Default::default(),
-            sync_graph: SyncGraphExposer {},
+            sync_graph: Default::default(),
+            transaction_pool: Default::default(),
             network: NetworkExposer {},
+            consistency: Default::default(),
         }
     }
+
+    /// Serialize the exposed state into a JSON object, which is used by the
+    /// test framework to make assertions on the node.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("exposed state is serializable")
+    }
 }
 
 pub type SharedStateExposer = Arc<StateExposer>;
@@ -51,4 +69,7 @@ impl StateExposer {
     pub fn write(&self) -> RwLockWriteGuard<StateExposerInner> {
         self.inner.write()
     }
+
+    /// Take a consistent snapshot of the exposed state as JSON.
+    pub fn snapshot(&self) -> String { self.inner.read().to_json() }
 }
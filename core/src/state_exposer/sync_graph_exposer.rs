@@ -2,5 +2,21 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
+use cfx_types::H256;
+use serde_derive::Serialize;
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
 /// This struct maintains some inner state of synchronization graph.
-pub struct SyncGraphExposer {}
+pub struct SyncGraphExposer {
+    /// The hashes of the not graph-ready blocks whose dependencies are
+    /// missing, i.e. the roots of the not graph-ready subgraph.
+    pub not_ready_frontier: Vec<H256>,
+    pub not_ready_block_count: usize,
+    /// The numbers of in-flight epoch, header, block and transaction
+    /// requests.
+    pub inflight_epochs: usize,
+    pub inflight_headers: usize,
+    pub inflight_blocks: usize,
+    pub inflight_transactions: usize,
+}
@@ -0,0 +1,16 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use serde_derive::Serialize;
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+/// This struct maintains some inner state of transaction pool.
+pub struct TransactionPoolExposer {
+    /// The number of accounts with a transaction ready to be packed.
+    pub ready_accounts: usize,
+    pub deferred: usize,
+    pub received: usize,
+    pub unpacked: usize,
+}
@@ -113,6 +113,48 @@ impl<'a> StateDb<'a> {
         Ok(Some(account))
     }
 
+    /// Get the accounts of multiple addresses in one batched storage read.
+    pub fn get_accounts(
+        &self, addresses: &[Address],
+    ) -> Result<Vec<Option<Account>>> {
+        let keys: Vec<_> = addresses
+            .iter()
+            .map(|address| self.account_key(address))
+            .collect();
+        let access_keys: Vec<_> = keys.iter().map(|key| key.as_ref()).collect();
+        let raws = self.storage.get_many(&access_keys)?;
+
+        let mut accounts = Vec::with_capacity(addresses.len());
+        for (address, maybe_raw) in addresses.iter().zip(raws) {
+            accounts.push(match maybe_raw {
+                None => None,
+                Some(raw) => Some(Account::new_from_rlp(address, &raw)?),
+            });
+        }
+        Ok(accounts)
+    }
+
+    /// Get multiple storage values of an address in one batched storage read.
+    pub fn get_storage_values(
+        &self, address: &Address, keys: &[H256],
+    ) -> Result<Vec<Option<H256>>> {
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key| self.storage_key(address, key.as_ref()))
+            .collect();
+        let access_keys: Vec<_> = keys.iter().map(|key| key.as_ref()).collect();
+        let raws = self.storage.get_many(&access_keys)?;
+
+        let mut values = Vec::with_capacity(raws.len());
+        for maybe_raw in raws {
+            values.push(match maybe_raw {
+                None => None,
+                Some(raw) => Some(::rlp::decode::<H256>(raw.as_ref())?),
+            });
+        }
+        Ok(values)
+    }
+
     pub fn get_raw(&self, key: &StorageKey) -> Result<Option<Box<[u8]>>> {
         let r = Ok(self.storage.get(key.as_ref())?);
         trace!("get_raw key={:?}, value={:?}", key.as_ref(), r);
@@ -177,3 +219,67 @@ impl<'a> StateDb<'a> {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::StateDb;
+    use crate::storage::{new_storage_manager_for_testing, StorageManagerTrait};
+    use cfx_types::{Address, H256, U256};
+    use primitives::Account;
+
+    #[test]
+    fn test_get_accounts_and_storage_values() {
+        let storage_manager = new_storage_manager_for_testing();
+        let mut state_db =
+            StateDb::new(storage_manager.get_state_for_genesis_write());
+        let address = Address::from_low_u64_be(1);
+        let missing_address = Address::from_low_u64_be(2);
+        let account = Account::new_empty_with_balance(
+            &address,
+            &U256::from(100),
+            &U256::from(7),
+        );
+        let account_key = state_db.account_key(&address);
+        state_db.set(&account_key, &account).unwrap();
+        let slot = H256::from_low_u64_be(1);
+        let value = H256::from_low_u64_be(42);
+        let storage_key = state_db.storage_key(&address, slot.as_ref());
+        state_db.set(&storage_key, &value).unwrap();
+        let malformed_slot = H256::from_low_u64_be(2);
+        let malformed_key =
+            state_db.storage_key(&address, malformed_slot.as_ref());
+        state_db.set_raw(&malformed_key, vec![0xff, 0xff].into()).unwrap();
+
+        let accounts = state_db
+            .get_accounts(&[address, missing_address, address])
+            .unwrap();
+        assert_eq!(accounts.len(), 3);
+        assert!(accounts[1].is_none());
+        for index in &[0, 2] {
+            let account = accounts[*index].as_ref().unwrap();
+            assert_eq!(account.address, address);
+            assert_eq!(account.balance, U256::from(100));
+            assert_eq!(account.nonce, U256::from(7));
+        }
+
+        assert_eq!(
+            state_db
+                .get_storage_values(
+                    &address,
+                    &[H256::from_low_u64_be(3), slot, slot]
+                )
+                .unwrap(),
+            vec![None, Some(value), Some(value)]
+        );
+        assert_eq!(
+            state_db.get_storage_values(&missing_address, &[slot]).unwrap(),
+            vec![None]
+        );
+        // A value which is not RLP of a hash fails the whole batch.
+        assert!(state_db
+            .get_storage_values(&address, &[slot, malformed_slot])
+            .is_err());
+
+        state_db.commit(H256::from_low_u64_be(1)).unwrap();
+    }
+}
@@ -0,0 +1,501 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+/// The archived record of a committed epoch. The key-value changes of the
+/// epoch are stored separately under the `archive_changes_` key.
+#[derive(RlpEncodable, RlpDecodable)]
+struct ArchivedEpoch {
+    parent_epoch_id: EpochId,
+    /// The number of epochs since the latest epoch on the path to this epoch
+    /// chosen to archive a snapshot, which is 0 if this epoch is chosen.
+    distance: u64,
+    /// Whether the snapshot of this epoch is completely archived. It's only
+    /// set after the snapshot is written in the background, so that a state
+    /// is never materialized from a partially written snapshot.
+    has_snapshot: bool,
+}
+
+/// A key-value change of an epoch. A deletion is an explicit tombstone, so
+/// that it's not confused with setting an empty value.
+#[derive(RlpEncodable, RlpDecodable)]
+struct ArchivedChange {
+    key: Vec<u8>,
+    deleted: bool,
+    value: Vec<u8>,
+}
+
+/// The key-value changes of an epoch, where `None` means deletion.
+pub type ArchiveChanges = BTreeMap<Vec<u8>, Option<Box<[u8]>>>;
+
+struct SnapshotJob {
+    epoch_id: EpochId,
+    state: DeltaMptInserter,
+}
+
+/// Archives the key-value changes of every committed epoch, and the full
+/// key-values of every `snapshot_interval` epochs, so that the state of any
+/// archived epoch can be materialized after its delta MPT root is no longer
+/// available, by composing the nearest snapshot before it with the changes of
+/// the epochs in between.
+///
+/// The changes are archived on commit, while the snapshots are written by a
+/// background thread to keep the full iteration of the state off the commit
+/// path. Until the snapshot of an epoch is written, the states after it are
+/// materialized from an earlier snapshot.
+pub struct ArchiveStateManager {
+    db: Arc<dyn KeyValueDB>,
+    snapshot_interval: u64,
+    snapshot_sender: Mutex<mpsc::Sender<SnapshotJob>>,
+    /// The number of queued or running snapshot jobs.
+    pending_snapshots: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl ArchiveStateManager {
+    /// The maximum number of snapshot key-values written in a db transaction.
+    const SNAPSHOT_WRITE_BATCH_SIZE: usize = 10_000;
+
+    pub fn new(db: Arc<dyn KeyValueDB>, snapshot_interval: u64) -> Self {
+        let (snapshot_sender, snapshot_receiver) = mpsc::channel();
+        let pending_snapshots = Arc::new((Mutex::new(0), Condvar::new()));
+
+        let worker_db = db.clone();
+        let worker_pending = pending_snapshots.clone();
+        thread::Builder::new()
+            .name("Archive snapshot".into())
+            .spawn(move || {
+                // The thread exits when the manager is dropped.
+                for job in snapshot_receiver {
+                    if let Err(e) = Self::write_snapshot(
+                        &*worker_db,
+                        &job.epoch_id,
+                        &job.state,
+                    ) {
+                        warn!(
+                            "Failed to archive the state snapshot of epoch \
+                             {:?}: {}",
+                            job.epoch_id, e
+                        );
+                    }
+                    let (count, finished) = &*worker_pending;
+                    *count.lock() -= 1;
+                    finished.notify_all();
+                }
+            })
+            .expect("Failed to start the archive snapshot thread");
+
+        Self {
+            db,
+            snapshot_interval: cmp::max(snapshot_interval, 1),
+            snapshot_sender: Mutex::new(snapshot_sender),
+            pending_snapshots,
+        }
+    }
+
+    fn epoch_key(prefix: &str, epoch_id: &EpochId) -> Vec<u8> {
+        [prefix.as_bytes(), epoch_id.as_ref()].concat()
+    }
+
+    fn snapshot_key(epoch_id: &EpochId, access_key: &[u8]) -> Vec<u8> {
+        [
+            "archive_snapshot_".as_bytes(),
+            epoch_id.as_ref(),
+            access_key,
+        ]
+        .concat()
+    }
+
+    fn load_epoch(
+        db: &dyn KeyValueDB, epoch_id: &EpochId,
+    ) -> Result<Option<ArchivedEpoch>> {
+        match db.get(
+            COL_ARCHIVE_STATE,
+            &Self::epoch_key("archive_epoch_", epoch_id),
+        )? {
+            Some(raw) => Ok(Some(rlp::decode(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn load_changes(&self, epoch_id: &EpochId) -> Result<Vec<ArchivedChange>> {
+        match self.db.get(
+            COL_ARCHIVE_STATE,
+            &Self::epoch_key("archive_changes_", epoch_id),
+        )? {
+            Some(raw) => Ok(rlp::decode_list(&raw)),
+            None => Err(ErrorKind::DbValueError.into()),
+        }
+    }
+
+    /// Archive the committed state of `epoch_id`. `changes` are the key-value
+    /// changes from the state of `parent_epoch_id`. The full key-values in
+    /// `state` are archived as a snapshot in the background if the parent
+    /// isn't archived or the latest snapshot is `snapshot_interval` epochs
+    /// away.
+    pub fn record_epoch(
+        &self, epoch_id: &EpochId, parent_epoch_id: Option<&EpochId>,
+        changes: &ArchiveChanges, state: DeltaMptInserter,
+    ) -> Result<()>
+    {
+        let maybe_parent = match parent_epoch_id {
+            Some(parent_epoch_id) => {
+                Self::load_epoch(&*self.db, parent_epoch_id)?
+            }
+            None => None,
+        };
+        let distance = match maybe_parent {
+            Some(parent) if parent.distance + 1 < self.snapshot_interval => {
+                parent.distance + 1
+            }
+            _ => 0,
+        };
+        // An epoch committed again keeps its archived snapshot.
+        let has_snapshot = Self::load_epoch(&*self.db, epoch_id)?
+            .map_or(false, |epoch| epoch.has_snapshot);
+
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|(key, value)| ArchivedChange {
+                key: key.clone(),
+                deleted: value.is_none(),
+                value: value.as_ref().map_or(vec![], |value| value.to_vec()),
+            })
+            .collect();
+        let mut transaction = DBTransaction::new();
+        transaction.put(
+            COL_ARCHIVE_STATE,
+            &Self::epoch_key("archive_changes_", epoch_id),
+            &rlp::encode_list(&changes),
+        );
+        transaction.put(
+            COL_ARCHIVE_STATE,
+            &Self::epoch_key("archive_epoch_", epoch_id),
+            &rlp::encode(&ArchivedEpoch {
+                parent_epoch_id: parent_epoch_id.cloned().unwrap_or_default(),
+                distance,
+                has_snapshot,
+            }),
+        );
+        self.db.write(transaction)?;
+
+        if distance == 0 && !has_snapshot {
+            self.queue_snapshot(epoch_id, state);
+        }
+        Ok(())
+    }
+
+    /// Archive the full key-values of the committed state of `epoch_id` as a
+    /// snapshot in the background, so that the states of the later epochs
+    /// are materialized from it. It does nothing if the snapshot of the epoch
+    /// is already archived.
+    pub fn snapshot_epoch(
+        &self, epoch_id: &EpochId, state: DeltaMptInserter,
+    ) -> Result<()> {
+        match Self::load_epoch(&*self.db, epoch_id)? {
+            Some(ref epoch) if epoch.has_snapshot => {}
+            _ => self.queue_snapshot(epoch_id, state),
+        }
+        Ok(())
+    }
+
+    fn queue_snapshot(&self, epoch_id: &EpochId, state: DeltaMptInserter) {
+        *self.pending_snapshots.0.lock() += 1;
+        let job = SnapshotJob {
+            epoch_id: *epoch_id,
+            state,
+        };
+        if self.snapshot_sender.lock().send(job).is_err() {
+            *self.pending_snapshots.0.lock() -= 1;
+            warn!(
+                "The archive snapshot thread has exited, the snapshot of \
+                 epoch {:?} is not archived",
+                epoch_id
+            );
+        }
+    }
+
+    /// Write the snapshot of `epoch_id` and then mark it in the epoch record.
+    fn write_snapshot(
+        db: &dyn KeyValueDB, epoch_id: &EpochId, state: &DeltaMptInserter,
+    ) -> Result<()> {
+        let mut transaction = DBTransaction::new();
+        state.iterate(SnapshotWriter {
+            db,
+            epoch_id,
+            transaction: &mut transaction,
+        })?;
+        db.write(mem::replace(&mut transaction, DBTransaction::new()))?;
+
+        // A checkpoint may be snapshotted without being recorded, in which
+        // case there are no changes to follow from it.
+        let epoch = match Self::load_epoch(db, epoch_id)? {
+            Some(epoch) => ArchivedEpoch {
+                has_snapshot: true,
+                ..epoch
+            },
+            None => ArchivedEpoch {
+                parent_epoch_id: Default::default(),
+                distance: 0,
+                has_snapshot: true,
+            },
+        };
+        transaction.put(
+            COL_ARCHIVE_STATE,
+            &Self::epoch_key("archive_epoch_", epoch_id),
+            &rlp::encode(&epoch),
+        );
+        db.write(transaction)?;
+        debug!("Archived the state snapshot of epoch {:?}", epoch_id);
+        Ok(())
+    }
+
+    /// Block until the queued snapshots are written or `timeout` elapses.
+    /// Returns whether all the snapshots are written.
+    #[cfg(test)]
+    pub fn wait_for_snapshots(&self, timeout: Duration) -> bool {
+        let (count, finished) = &*self.pending_snapshots;
+        let mut count = count.lock();
+        while *count > 0 {
+            if finished.wait_for(&mut count, timeout).timed_out() {
+                break;
+            }
+        }
+        *count == 0
+    }
+
+    /// Materialize the state of `epoch_id` from the archive, or return `None`
+    /// if the epoch isn't archived or no snapshot before it is archived yet.
+    pub fn get_archived_state(
+        &self, epoch_id: &EpochId,
+    ) -> Result<Option<ArchivedState>> {
+        let mut changes = HashMap::new();
+        let mut epoch_id = *epoch_id;
+        loop {
+            let epoch = match Self::load_epoch(&*self.db, &epoch_id)? {
+                Some(epoch) => epoch,
+                None => return Ok(None),
+            };
+            if epoch.has_snapshot {
+                return Ok(Some(ArchivedState {
+                    db: self.db.clone(),
+                    snapshot_epoch_id: epoch_id,
+                    changes,
+                }));
+            }
+            // Changes of later epochs override the earlier ones.
+            for change in self.load_changes(&epoch_id)? {
+                changes.entry(change.key).or_insert_with(|| {
+                    if change.deleted {
+                        None
+                    } else {
+                        Some(change.value.into_boxed_slice())
+                    }
+                });
+            }
+            epoch_id = epoch.parent_epoch_id;
+        }
+    }
+}
+
+/// The state of an epoch materialized from the archive.
+pub struct ArchivedState {
+    db: Arc<dyn KeyValueDB>,
+    snapshot_epoch_id: EpochId,
+    changes: HashMap<Vec<u8>, Option<Box<[u8]>>>,
+}
+
+impl ArchivedState {
+    pub fn get(&self, access_key: &[u8]) -> Result<Option<Box<[u8]>>> {
+        match self.changes.get(access_key) {
+            Some(value) => Ok(value.clone()),
+            None => Ok(self
+                .db
+                .get(
+                    COL_ARCHIVE_STATE,
+                    &ArchiveStateManager::snapshot_key(
+                        &self.snapshot_epoch_id,
+                        access_key,
+                    ),
+                )?
+                .map(|value| value.into_vec().into_boxed_slice())),
+        }
+    }
+}
+
+/// Writes the key-values of a snapshot in batches, leaving the last batch in
+/// `transaction`.
+struct SnapshotWriter<'a> {
+    db: &'a dyn KeyValueDB,
+    epoch_id: &'a EpochId,
+    transaction: &'a mut DBTransaction,
+}
+
+impl<'a> KVInserter<(Vec<u8>, Box<[u8]>)> for SnapshotWriter<'a> {
+    fn push(&mut self, v: (Vec<u8>, Box<[u8]>)) -> Result<()> {
+        let (key, value) = v;
+        self.transaction.put(
+            COL_ARCHIVE_STATE,
+            &ArchiveStateManager::snapshot_key(self.epoch_id, &key),
+            &value,
+        );
+        if self.transaction.ops.len()
+            >= ArchiveStateManager::SNAPSHOT_WRITE_BATCH_SIZE
+        {
+            self.db
+                .write(mem::replace(self.transaction, DBTransaction::new()))?;
+        }
+        Ok(())
+    }
+}
+
+use super::{
+    errors::*,
+    multi_version_merkle_patricia_trie::merkle_patricia_trie::cow_node_ref::KVInserter,
+    storage_manager::storage_manager::DeltaMptInserter,
+};
+use crate::db::COL_ARCHIVE_STATE;
+use kvdb::{DBTransaction, KeyValueDB};
+use parking_lot::{Condvar, Mutex};
+use primitives::EpochId;
+use rlp_derive::{RlpDecodable, RlpEncodable};
+#[cfg(test)]
+use std::time::Duration;
+use std::{
+    cmp,
+    collections::{BTreeMap, HashMap},
+    mem,
+    sync::{mpsc, Arc},
+    thread,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            super::{
+                state::StateTrait,
+                state_manager::{SnapshotAndEpochIdRef, StateManagerTrait},
+                tests::{
+                    new_archive_state_manager_for_testing, MemoryDbForStateTest,
+                },
+            },
+            state_manager::StateManager,
+        },
+        ArchiveStateManager, ArchivedState,
+    };
+    use primitives::EpochId;
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    fn epoch_id(i: u8) -> EpochId {
+        let mut epoch_id = EpochId::default();
+        epoch_id.as_bytes_mut()[0] = i;
+        epoch_id
+    }
+
+    /// Commit epoch `epoch` after `parent`, setting the keys with a value and
+    /// deleting the others.
+    fn commit_epoch(
+        manager: &StateManager, parent: Option<u8>, epoch: u8,
+        changes: &[(&str, Option<&str>)],
+    )
+    {
+        let mut state = match parent {
+            None => manager.get_state_for_genesis_write(),
+            Some(parent) => manager
+                .get_state_for_next_epoch(SnapshotAndEpochIdRef::new(
+                    &epoch_id(parent),
+                    None,
+                ))
+                .unwrap()
+                .unwrap(),
+        };
+        for (key, value) in changes {
+            match value {
+                Some(value) => {
+                    state.set(key.as_bytes(), value.as_bytes().into()).unwrap()
+                }
+                None => {
+                    state.delete(key.as_bytes()).unwrap();
+                }
+            }
+        }
+        state.compute_state_root().unwrap();
+        state.commit(epoch_id(epoch)).unwrap();
+    }
+
+    fn archived_value(
+        manager: &StateManager, epoch: u8, key: &str,
+    ) -> Option<String> {
+        manager
+            .archive
+            .as_ref()
+            .unwrap()
+            .get_archived_state(&epoch_id(epoch))
+            .unwrap()
+            .expect("the epoch is archived")
+            .get(key.as_bytes())
+            .unwrap()
+            .map(|value| String::from_utf8(value.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_materialize_archived_states() {
+        let manager = new_archive_state_manager_for_testing(3);
+        commit_epoch(&manager, None, 1, &[("a", Some("1")), ("b", Some("2"))]);
+        commit_epoch(&manager, Some(1), 2, &[("a", Some("3")), ("b", None)]);
+        commit_epoch(&manager, Some(2), 3, &[("b", Some("4"))]);
+        // The snapshot interval is reached.
+        commit_epoch(&manager, Some(3), 4, &[("a", None)]);
+        commit_epoch(&manager, Some(4), 5, &[("c", Some("5"))]);
+
+        let archive = manager.archive.as_ref().unwrap();
+        assert!(archive.wait_for_snapshots(Duration::from_secs(10)));
+        for (epoch, has_snapshot) in
+            &[(1, true), (2, false), (3, false), (4, true), (5, false)]
+        {
+            let epoch = ArchiveStateManager::load_epoch(
+                &*archive.db,
+                &epoch_id(*epoch),
+            )
+            .unwrap()
+            .unwrap();
+            assert_eq!(epoch.has_snapshot, *has_snapshot);
+        }
+
+        let expected: &[(u8, [Option<&str>; 3])] = &[
+            (1, [Some("1"), Some("2"), None]),
+            (2, [Some("3"), None, None]),
+            (3, [Some("3"), Some("4"), None]),
+            (4, [None, Some("4"), None]),
+            (5, [None, Some("4"), Some("5")]),
+        ];
+        for (epoch, values) in expected {
+            for (key, value) in ["a", "b", "c"].iter().zip(values.iter()) {
+                assert_eq!(
+                    archived_value(&manager, *epoch, key),
+                    value.map(String::from),
+                    "epoch {} key {:?}",
+                    epoch,
+                    key
+                );
+            }
+        }
+
+        assert!(archive.get_archived_state(&epoch_id(6)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_deletion_is_not_empty_value() {
+        let mut changes = HashMap::new();
+        changes.insert(b"empty".to_vec(), Some(vec![].into_boxed_slice()));
+        changes.insert(b"deleted".to_vec(), None);
+        let state = ArchivedState {
+            db: Arc::new(MemoryDbForStateTest::default()),
+            snapshot_epoch_id: Default::default(),
+            changes,
+        };
+        assert_eq!(state.get(b"empty").unwrap(), Some(vec![].into()));
+        assert_eq!(state.get(b"deleted").unwrap(), None);
+        assert_eq!(state.get(b"unchanged").unwrap(), None);
+    }
+}
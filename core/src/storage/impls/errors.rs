@@ -85,5 +85,10 @@ error_chain! {
             description("Trie node not found when loading Snapshot MPT."),
             display("Trie node not found when loading Snapshot MPT."),
         }
+
+        ArchivedStateReadOnly {
+            description("State materialized from the archive is read-only."),
+            display("State materialized from the archive is read-only."),
+        }
     }
 }
@@ -2,6 +2,7 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
+pub(super) mod archive_state;
 pub(super) mod errors;
 pub(super) mod multi_version_merkle_patricia_trie;
 pub(self) mod owned_node_set;
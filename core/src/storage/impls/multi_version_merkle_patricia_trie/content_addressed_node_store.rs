@@ -0,0 +1,424 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+/// Indexes the committed trie nodes of a Delta MPT by merkle hash in the delta
+/// db, so that a trie node recreated with identical content in a later commit
+/// reuses the committed row instead of being written again. Each index entry
+/// keeps the row together with the reference count of the trie nodes
+/// committed into it, so that the lookup for deduplication also reads the
+/// reference count. A row is deleted once its last reference is released,
+/// together with its reference to the external value of the trie node.
+///
+/// An instance collects the index entries read and updated in a single
+/// commit, which are written with the commit transaction.
+#[derive(Default)]
+pub struct ContentAddressedNodeStore {
+    /// The index entries read or updated in this commit, where `None` is an
+    /// entry known to be absent.
+    entries: HashMap<MerkleHash, Option<IndexEntry>>,
+    /// The entries to write with the commit.
+    updated: HashSet<MerkleHash>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct IndexEntry {
+    db_key: DeltaMptDbKey,
+    ref_count: u64,
+    /// The external value referenced by the row.
+    external_value: Option<ExternalValueRef>,
+}
+
+impl Encodable for IndexEntry {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match &self.external_value {
+            None => {
+                s.begin_list(2).append(&self.db_key).append(&self.ref_count);
+            }
+            Some(value_ref) => {
+                s.begin_list(4)
+                    .append(&self.db_key)
+                    .append(&self.ref_count)
+                    .append(&value_ref.hash)
+                    .append(&value_ref.len);
+            }
+        }
+    }
+}
+
+impl Decodable for IndexEntry {
+    fn decode(rlp: &Rlp) -> std::result::Result<Self, DecoderError> {
+        let external_value = match rlp.item_count()? {
+            2 => None,
+            4 => Some(ExternalValueRef {
+                hash: rlp.val_at(2)?,
+                len: rlp.val_at(3)?,
+            }),
+            _ => return Err(DecoderError::RlpIncorrectListLen),
+        };
+        Ok(IndexEntry {
+            db_key: rlp.val_at(0)?,
+            ref_count: rlp.val_at(1)?,
+            external_value,
+        })
+    }
+}
+
+impl ContentAddressedNodeStore {
+    fn index_key(merkle: &MerkleHash) -> Vec<u8> {
+        ["node_db_key_for_merkle_".as_bytes(), merkle.as_ref()].concat()
+    }
+
+    /// The index entry of `merkle`, which is read from `db` at most once in a
+    /// commit.
+    fn entry(
+        &mut self, db: &dyn DeltaDbTrait, merkle: &MerkleHash,
+    ) -> Result<Option<IndexEntry>> {
+        if let Some(entry) = self.entries.get(merkle) {
+            return Ok(*entry);
+        }
+        let entry = match db.get(&Self::index_key(merkle))? {
+            None => None,
+            Some(value) => Some(
+                Rlp::new(&value)
+                    .as_val()
+                    .map_err(|_| Error::from(ErrorKind::DbValueError))?,
+            ),
+        };
+        self.entries.insert(*merkle, entry);
+        Ok(entry)
+    }
+
+    /// The committed row of the trie node with the given merkle hash.
+    pub fn get_db_key(
+        &mut self, db: &dyn DeltaDbTrait, merkle: &MerkleHash,
+    ) -> Result<Option<DeltaMptDbKey>> {
+        Ok(self.entry(db, merkle)?.map(|entry| entry.db_key))
+    }
+
+    /// The number of trie nodes committed into the row of the trie node with
+    /// the given merkle hash.
+    pub fn ref_count(
+        &mut self, db: &dyn DeltaDbTrait, merkle: &MerkleHash,
+    ) -> Result<u64> {
+        Ok(self.entry(db, merkle)?.map_or(0, |entry| entry.ref_count))
+    }
+
+    /// Index a trie node written into a new row, which references
+    /// `external_value` if the value of the trie node is stored externally.
+    pub fn add_node(
+        &mut self, merkle: &MerkleHash, db_key: DeltaMptDbKey,
+        external_value: Option<ExternalValueRef>,
+    )
+    {
+        self.entries.insert(
+            *merkle,
+            Some(IndexEntry {
+                db_key,
+                ref_count: 1,
+                external_value,
+            }),
+        );
+        self.updated.insert(*merkle);
+    }
+
+    /// Add a reference to the row of an identical trie node, which is found
+    /// by `get_db_key`.
+    pub fn add_ref(&mut self, merkle: &MerkleHash) {
+        if let Some(Some(entry)) = self.entries.get_mut(merkle) {
+            entry.ref_count += 1;
+            self.updated.insert(*merkle);
+        }
+    }
+
+    /// Release a reference to the row of the trie node with the given merkle
+    /// hash. The row is deleted with the commit once no trie node references
+    /// it.
+    pub fn remove_ref(
+        &mut self, db: &dyn DeltaDbTrait, merkle: &MerkleHash,
+    ) -> Result<()> {
+        let entry = match self.entry(db, merkle)? {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        let entry = IndexEntry {
+            ref_count: entry.ref_count.saturating_sub(1),
+            ..entry
+        };
+        self.entries.insert(*merkle, Some(entry));
+        self.updated.insert(*merkle);
+        Ok(())
+    }
+
+    /// Put the updated index entries into the commit transaction, and delete
+    /// the rows no longer referenced, releasing their external values in
+    /// `external_values`. The caller must hold the commit lock so that the
+    /// entries read from the db aren't updated concurrently.
+    pub fn write(
+        self, transaction: &mut DeltaDbTransactionTraitObj,
+        external_values: &mut ExternalValueStore,
+    ) -> Result<()>
+    {
+        for merkle in &self.updated {
+            let entry = match self.entries.get(merkle) {
+                Some(Some(entry)) => entry,
+                _ => continue,
+            };
+            let index_key = Self::index_key(merkle);
+            if entry.ref_count == 0 {
+                transaction.delete(&index_key)?;
+                transaction.delete_with_number_key(entry.db_key.into())?;
+                transaction.delete(format!("cm{}", entry.db_key).as_bytes())?;
+                if let Some(value_ref) = &entry.external_value {
+                    external_values.remove_ref(value_ref);
+                }
+            } else {
+                transaction.put(&index_key, &rlp::encode(entry))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reference counts the values stored externally in the delta db, so that a
+/// value is deleted once no committed row references it. The count of a value
+/// is kept under "ev_ref_count_" followed by its hash.
+///
+/// An instance collects the references added and released in a single commit,
+/// and reads the count of each value from the db once when it's written.
+#[derive(Default)]
+pub struct ExternalValueStore {
+    /// The change of the reference count of each value in this commit.
+    ref_count_deltas: HashMap<H256, i64>,
+    /// The values referenced in this commit, which are written if they aren't
+    /// in the db yet.
+    values: HashMap<H256, Vec<u8>>,
+}
+
+impl ExternalValueStore {
+    fn ref_count_key(hash: &H256) -> Vec<u8> {
+        ["ev_ref_count_".as_bytes(), hash.as_ref()].concat()
+    }
+
+    /// Add a reference to an external value from a committed row. `value` is
+    /// set when the value is externalized in this commit.
+    pub fn add_ref(
+        &mut self, value_ref: &ExternalValueRef, value: Option<&[u8]>,
+    ) {
+        *self.ref_count_deltas.entry(value_ref.hash).or_insert(0) += 1;
+        if let Some(value) = value {
+            self.values
+                .entry(value_ref.hash)
+                .or_insert_with(|| value.to_vec());
+        }
+    }
+
+    /// Release a reference to an external value from a deleted row.
+    pub fn remove_ref(&mut self, value_ref: &ExternalValueRef) {
+        *self.ref_count_deltas.entry(value_ref.hash).or_insert(0) -= 1;
+    }
+
+    /// Put the updated reference counts and the new values into the commit
+    /// transaction, and delete the values no longer referenced.
+    pub fn write(
+        self, db: &dyn DeltaDbTrait,
+        transaction: &mut DeltaDbTransactionTraitObj,
+    ) -> Result<()>
+    {
+        for (hash, delta) in &self.ref_count_deltas {
+            if *delta == 0 {
+                continue;
+            }
+            let ref_count_key = Self::ref_count_key(hash);
+            let ref_count: u64 = match db.get(&ref_count_key)? {
+                None => 0,
+                Some(value) => Rlp::new(&value)
+                    .as_val()
+                    .map_err(|_| Error::from(ErrorKind::DbValueError))?,
+            };
+            // A value without a count in the db was stored before its
+            // references were counted, and is never deleted.
+            if ref_count == 0 && *delta < 0 {
+                continue;
+            }
+            let value_key = ExternalValueRef {
+                hash: *hash,
+                len: 0,
+            }
+            .db_key();
+            let new_ref_count = ref_count as i64 + delta;
+            if new_ref_count <= 0 {
+                transaction.delete(&ref_count_key)?;
+                transaction.delete(&value_key)?;
+                continue;
+            }
+            transaction
+                .put(&ref_count_key, &rlp::encode(&(new_ref_count as u64)))?;
+            if ref_count == 0 {
+                if let Some(value) = self.values.get(hash) {
+                    transaction.put(&value_key, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::merkle_patricia_trie::trie_node::ExternalValueRef,
+        ContentAddressedNodeStore, ExternalValueStore,
+    };
+    use crate::storage::{
+        impls::storage_db::kvdb_rocksdb::KvdbRocksdb,
+        storage_db::key_value_db::*, tests::MemoryDbForStateTest,
+    };
+    use primitives::MerkleHash;
+    use std::sync::Arc;
+
+    fn commit<F: FnOnce(&mut ContentAddressedNodeStore)>(
+        db: &KvdbRocksdb, f: F,
+    ) {
+        let mut node_store = ContentAddressedNodeStore::default();
+        f(&mut node_store);
+        let mut transaction = db.start_transaction_dyn(true).unwrap();
+        node_store
+            .write(&mut *transaction, &mut ExternalValueStore::default())
+            .unwrap();
+        transaction.commit(db.as_any()).unwrap();
+    }
+
+    fn commit_external_values<
+        F: FnOnce(&mut ContentAddressedNodeStore, &mut ExternalValueStore),
+    >(
+        db: &KvdbRocksdb, f: F,
+    ) {
+        let mut node_store = ContentAddressedNodeStore::default();
+        let mut external_values = ExternalValueStore::default();
+        f(&mut node_store, &mut external_values);
+        let mut transaction = db.start_transaction_dyn(true).unwrap();
+        node_store
+            .write(&mut *transaction, &mut external_values)
+            .unwrap();
+        external_values.write(db, &mut *transaction).unwrap();
+        transaction.commit(db.as_any()).unwrap();
+    }
+
+    #[test]
+    fn test_ref_count() {
+        let db = KvdbRocksdb {
+            kvdb: Arc::new(MemoryDbForStateTest::default()),
+            col: None,
+        };
+        let merkle = MerkleHash::repeat_byte(1);
+        let db_key = 3;
+
+        commit(&db, |node_store| {
+            assert_eq!(node_store.get_db_key(&db, &merkle).unwrap(), None);
+            node_store.add_node(&merkle, db_key, None);
+            // The row written in this commit is found before it is written.
+            assert_eq!(
+                node_store.get_db_key(&db, &merkle).unwrap(),
+                Some(db_key)
+            );
+            node_store.add_ref(&merkle);
+        });
+        let mut transaction = db.start_transaction_dyn(true).unwrap();
+        transaction
+            .put_with_number_key(db_key.into(), &[1])
+            .unwrap();
+        transaction.put(b"cm3", &[2]).unwrap();
+        transaction.commit(db.as_any()).unwrap();
+
+        commit(&db, |node_store| {
+            assert_eq!(
+                node_store.get_db_key(&db, &merkle).unwrap(),
+                Some(db_key)
+            );
+            assert_eq!(node_store.ref_count(&db, &merkle).unwrap(), 2);
+            node_store.add_ref(&merkle);
+            node_store.remove_ref(&db, &merkle).unwrap();
+            node_store.remove_ref(&db, &merkle).unwrap();
+            assert_eq!(node_store.ref_count(&db, &merkle).unwrap(), 1);
+        });
+        assert!(db.get_with_number_key(db_key.into()).unwrap().is_some());
+
+        // The row is deleted with the last reference.
+        commit(&db, |node_store| {
+            node_store.remove_ref(&db, &merkle).unwrap();
+        });
+        commit(&db, |node_store| {
+            assert_eq!(node_store.get_db_key(&db, &merkle).unwrap(), None);
+            assert_eq!(node_store.ref_count(&db, &merkle).unwrap(), 0);
+        });
+        assert!(db.get_with_number_key(db_key.into()).unwrap().is_none());
+        assert!(db.get(b"cm3").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_external_value_ref_count() {
+        let db = KvdbRocksdb {
+            kvdb: Arc::new(MemoryDbForStateTest::default()),
+            col: None,
+        };
+        let value = vec![7u8; 100];
+        let value_ref = ExternalValueRef::new(&value);
+        let merkles = [MerkleHash::repeat_byte(1), MerkleHash::repeat_byte(2)];
+
+        // Two rows reference the value externalized in the first commit.
+        commit_external_values(&db, |node_store, external_values| {
+            external_values.add_ref(&value_ref, Some(&value));
+            node_store.add_node(&merkles[0], 1, Some(value_ref));
+        });
+        assert_eq!(
+            db.get(&value_ref.db_key()).unwrap(),
+            Some(value.clone().into_boxed_slice())
+        );
+        commit_external_values(&db, |node_store, external_values| {
+            external_values.add_ref(&value_ref, None);
+            node_store.add_node(&merkles[1], 2, Some(value_ref));
+        });
+
+        // The value is kept while a row references it.
+        commit_external_values(&db, |node_store, _| {
+            node_store.remove_ref(&db, &merkles[0]).unwrap();
+        });
+        assert_eq!(
+            db.get(&value_ref.db_key()).unwrap(),
+            Some(value.clone().into_boxed_slice())
+        );
+
+        // The index entry keeps the reference from the row to the value, which
+        // is deleted with the last row.
+        commit_external_values(&db, |node_store, _| {
+            node_store.remove_ref(&db, &merkles[1]).unwrap();
+        });
+        assert!(db.get(&value_ref.db_key()).unwrap().is_none());
+
+        // A value referenced again after it's deleted is written again.
+        commit_external_values(&db, |_, external_values| {
+            external_values.add_ref(&value_ref, Some(&value));
+        });
+        assert_eq!(
+            db.get(&value_ref.db_key()).unwrap(),
+            Some(value.into_boxed_slice())
+        );
+    }
+}
+
+use super::{
+    super::{
+        super::storage_db::{
+            delta_db_manager::{DeltaDbTrait, DeltaDbTransactionTraitObj},
+            key_value_db::*,
+        },
+        errors::*,
+    },
+    merkle_patricia_trie::trie_node::ExternalValueRef,
+    node_ref_map::DeltaMptDbKey,
+};
+use cfx_types::H256;
+use primitives::MerkleHash;
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+use std::collections::{HashMap, HashSet};
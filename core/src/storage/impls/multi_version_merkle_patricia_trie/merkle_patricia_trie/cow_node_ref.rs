@@ -258,7 +258,9 @@ impl CowNodeRef {
                 assert_eq!(key_prefix.end_mask(), 0);
                 values.push((
                     key_prefix.path_slice().to_vec(),
-                    guarded_trie_node.as_ref().as_ref().value_clone().unwrap(),
+                    trie.get_node_memory_manager()
+                        .value_clone(guarded_trie_node.as_ref().as_ref(), db)?
+                        .unwrap(),
                 ));
             }
 
@@ -317,7 +319,7 @@ impl CowNodeRef {
         commit_transaction: &mut AtomicCommitTransaction<Transaction>,
         cache_manager: &mut CacheManagerDeltaMpt,
         allocator_ref: AllocatorRefRefDeltaMpt,
-        children_merkle_map: &mut ChildrenMerkleMap,
+        children_merkle_map: &mut ChildrenMerkleMap, depth: u8,
     ) -> Result<()>
     {
         for (_i, node_ref_mut) in trie_node.children_table.iter_mut() {
@@ -338,6 +340,7 @@ impl CowNodeRef {
                     cache_manager,
                     allocator_ref,
                     children_merkle_map,
+                    depth + 1,
                 );
 
                 if commit_result.is_ok() {
@@ -354,14 +357,23 @@ impl CowNodeRef {
     }
 
     fn set_merkle(
-        &mut self, children_merkles: MaybeMerkleTableRef,
-        trie_node: &mut TrieNodeDeltaMpt,
-    ) -> MerkleHash
+        &mut self, trie: &DeltaMpt, children_merkles: MaybeMerkleTableRef,
+        trie_node: &mut TrieNodeDeltaMpt, db: &mut DeltaDbOwnedReadTraitObj,
+    ) -> Result<MerkleHash>
     {
+        // The merkle hash is computed from the value itself even if it's
+        // stored externally. The value is kept in the dirty trie node, so that
+        // it isn't loaded again until the trie node is committed.
+        if let Some(value_ref) = trie_node.external_value_ref() {
+            let value = trie
+                .get_node_memory_manager()
+                .load_external_value(db, &value_ref)?;
+            trie_node.replace_value_valid(value);
+        }
         let path_merkle = trie_node.compute_merkle(children_merkles);
         trie_node.set_merkle(&path_merkle);
 
-        path_merkle
+        Ok(path_merkle)
     }
 
     fn uncached_children_count(
@@ -408,7 +420,12 @@ impl CowNodeRef {
                 depth,
             )?;
 
-            let merkle = self.set_merkle(children_merkles.as_ref(), trie_node);
+            let merkle = self.set_merkle(
+                trie,
+                children_merkles.as_ref(),
+                trie_node,
+                db,
+            )?;
 
             Ok(merkle)
         } else {
@@ -570,7 +587,9 @@ impl CowNodeRef {
             assert_eq!(key_prefix.end_mask(), 0);
             values.push((
                 key_prefix.path_slice().to_vec(),
-                guarded_trie_node.as_ref().as_ref().value_clone().unwrap(),
+                trie.get_node_memory_manager()
+                    .value_clone(guarded_trie_node.as_ref().as_ref(), db)?
+                    .unwrap(),
             ))?;
         }
 
@@ -609,7 +628,7 @@ impl CowNodeRef {
         Ok(())
     }
 
-    /// Recursively commit dirty nodes.
+    /// Recursively commit dirty nodes. The root node has depth 0.
     pub fn commit_dirty_recursively<
         Transaction: BorrowMut<DeltaDbTransactionTraitObj>,
     >(
@@ -618,7 +637,7 @@ impl CowNodeRef {
         commit_transaction: &mut AtomicCommitTransaction<Transaction>,
         cache_manager: &mut CacheManagerDeltaMpt,
         allocator_ref: AllocatorRefRefDeltaMpt,
-        children_merkle_map: &mut ChildrenMerkleMap,
+        children_merkle_map: &mut ChildrenMerkleMap, depth: u8,
     ) -> Result<bool>
     {
         if self.owned {
@@ -630,16 +649,55 @@ impl CowNodeRef {
                 cache_manager,
                 allocator_ref,
                 children_merkle_map,
+                depth,
             )?;
 
+            let merkle = trie_node.get_merkle().clone();
+            let maybe_dedup_db_key = match &mut commit_transaction.node_store {
+                Some(node_store) => {
+                    trie.get_deduplicated_db_key(node_store, &merkle)?
+                }
+                None => None,
+            };
+            if let Some(db_key) = maybe_dedup_db_key {
+                self.commit_deduplicated(
+                    db_key,
+                    &merkle,
+                    trie,
+                    owned_node_set,
+                    commit_transaction,
+                    cache_manager,
+                    allocator_ref,
+                    children_merkle_map,
+                )?;
+                return Ok(true);
+            }
+
             let db_key = commit_transaction.info.row_number.value;
+            let (rlp_bytes, external_value_ref) =
+                Self::trie_node_rlp_bytes(trie_node, commit_transaction)?;
             commit_transaction
                 .transaction
                 .borrow_mut()
                 .put_with_number_key(
                     commit_transaction.info.row_number.value.into(),
-                    trie_node.rlp_bytes().as_slice(),
+                    rlp_bytes.as_slice(),
                 )?;
+            commit_transaction.stats.nodes_written += 1;
+            commit_transaction.stats.trie_node_bytes += rlp_bytes.len() as u64;
+            if trie_node.has_value() {
+                commit_transaction.stats.value_node_depths.push(depth);
+            }
+            if let Some(node_store) = &mut commit_transaction.node_store {
+                node_store.add_node(&merkle, db_key, external_value_ref);
+            }
+            // The committed trie node is kept in the cache with the reference
+            // to the external value instead of the value.
+            if let Some(value_ref) = external_value_ref {
+                if trie_node.external_value_ref().is_none() {
+                    trie_node.replace_value_with_external_ref(&value_ref);
+                }
+            }
             commit_transaction.info.row_number =
                 commit_transaction.info.row_number.get_next()?;
 
@@ -647,11 +705,28 @@ impl CowNodeRef {
                 NodeRefDeltaMpt::Dirty { index } => *index,
                 _ => unsafe { unreachable_unchecked() },
             };
-            if let Some(children_merkles) = children_merkle_map.remove(&slot) {
-                commit_transaction.transaction.borrow_mut().put(
-                    format!("cm{}", db_key).as_bytes(),
-                    &children_merkles.rlp_bytes(),
-                )?;
+            // The children merkles are written in the same transaction as the
+            // trie node. The entry is kept in `children_merkle_map` until the
+            // transaction is committed, so that a failed commit doesn't lose
+            // them. A node committed without children merkles clears any
+            // leftover record under its db key, which would otherwise be
+            // picked up as the children merkles of this node.
+            match children_merkle_map.get(&slot) {
+                Some(children_merkles) => {
+                    let rlp_bytes = children_merkles.rlp_bytes();
+                    commit_transaction
+                        .transaction
+                        .borrow_mut()
+                        .put(format!("cm{}", db_key).as_bytes(), &rlp_bytes)?;
+                    commit_transaction.stats.children_merkle_bytes +=
+                        rlp_bytes.len() as u64;
+                }
+                None => {
+                    commit_transaction
+                        .transaction
+                        .borrow_mut()
+                        .delete(format!("cm{}", db_key).as_bytes())?;
+                }
             }
 
             let committed_node_ref = NodeRefDeltaMpt::Committed { db_key };
@@ -678,6 +753,86 @@ impl CowNodeRef {
         }
     }
 
+    /// The rlp of the trie node to commit, and the reference to its value if
+    /// it's stored externally. A value longer than the external value
+    /// threshold is written under its hash with the commit, and the trie node
+    /// is encoded with the reference to it.
+    fn trie_node_rlp_bytes<
+        Transaction: BorrowMut<DeltaDbTransactionTraitObj>,
+    >(
+        trie_node: &TrieNodeDeltaMpt,
+        commit_transaction: &mut AtomicCommitTransaction<Transaction>,
+    ) -> Result<(Vec<u8>, Option<ExternalValueRef>)>
+    {
+        if let Some(value_ref) = trie_node.external_value_ref() {
+            commit_transaction.external_values.add_ref(&value_ref, None);
+            return Ok((trie_node.rlp_bytes(), Some(value_ref)));
+        }
+        let threshold = match commit_transaction.external_value_threshold {
+            Some(threshold) => threshold,
+            None => return Ok((trie_node.rlp_bytes(), None)),
+        };
+        match trie_node.value_as_slice() {
+            MptValue::Some(value) if value.len() > threshold => {
+                let value_ref = ExternalValueRef::new(value);
+                commit_transaction
+                    .external_values
+                    .add_ref(&value_ref, Some(value));
+                commit_transaction.stats.external_value_bytes +=
+                    value.len() as u64;
+                Ok((
+                    trie_node.rlp_bytes_with_external_value(&value_ref),
+                    Some(value_ref),
+                ))
+            }
+            _ => Ok((trie_node.rlp_bytes(), None)),
+        }
+    }
+
+    /// Commit the dirty node into the row of an identical committed trie
+    /// node, whose children merkles are also the same, instead of writing a
+    /// new row.
+    fn commit_deduplicated<
+        Transaction: BorrowMut<DeltaDbTransactionTraitObj>,
+    >(
+        &mut self, db_key: DeltaMptDbKey, merkle: &MerkleHash, trie: &DeltaMpt,
+        owned_node_set: &mut OwnedNodeSet,
+        commit_transaction: &mut AtomicCommitTransaction<Transaction>,
+        cache_manager: &mut CacheManagerDeltaMpt,
+        allocator_ref: AllocatorRefRefDeltaMpt,
+        children_merkle_map: &mut ChildrenMerkleMap,
+    ) -> Result<()>
+    {
+        if let Some(node_store) = &mut commit_transaction.node_store {
+            node_store.add_ref(merkle);
+        }
+        commit_transaction.stats.nodes_deduplicated += 1;
+
+        let slot = match &self.node_ref {
+            NodeRefDeltaMpt::Dirty { index } => *index,
+            _ => unsafe { unreachable_unchecked() },
+        };
+        children_merkle_map.remove(&slot);
+
+        let committed_node_ref = NodeRefDeltaMpt::Committed { db_key };
+        if cache_manager.query(db_key) {
+            // The row is already cached, so the dirty copy is dropped.
+            owned_node_set.remove(&self.node_ref);
+            allocator_ref.remove(slot as usize)?;
+        } else {
+            owned_node_set.insert(committed_node_ref.clone(), None);
+            owned_node_set.remove(&self.node_ref);
+            cache_manager.insert_to_node_ref_map_and_call_cache_access(
+                db_key,
+                slot,
+                trie.get_node_memory_manager(),
+            )?;
+        }
+        self.node_ref = committed_node_ref;
+
+        Ok(())
+    }
+
     pub fn cow_merge_path(
         self, trie: &DeltaMpt, owned_node_set: &mut OwnedNodeSet,
         trie_node: GuardedMaybeOwnedTrieNodeAsCowCallParam,
@@ -733,15 +888,24 @@ impl CowNodeRef {
     /// When the node is unowned, it doesn't make sense to do copy-on-write
     /// creation because the new node will be deleted immediately.
     pub unsafe fn delete_value_unchecked_followed_by_node_deletion(
-        &mut self, mut trie_node: GuardedMaybeOwnedTrieNodeAsCowCallParam,
-    ) -> Box<[u8]> {
+        &mut self, node_memory_manager: &NodeMemoryManagerDeltaMpt,
+        mut trie_node: GuardedMaybeOwnedTrieNodeAsCowCallParam,
+        db: &mut DeltaDbOwnedReadTraitObj,
+    ) -> Result<Box<[u8]>>
+    {
         if self.owned {
-            trie_node
+            let value_ref = trie_node.as_ref().as_ref().external_value_ref();
+            let value = trie_node
                 .as_mut()
                 .owned_as_mut_unchecked()
-                .delete_value_unchecked()
+                .delete_value_unchecked();
+            Ok(node_memory_manager
+                .resolve_value(MptValue::Some(value), value_ref, db)?
+                .unwrap())
         } else {
-            trie_node.as_ref().as_ref().value_clone().unwrap()
+            Ok(node_memory_manager
+                .value_clone(trie_node.as_ref().as_ref(), db)?
+                .unwrap())
         }
     }
 
@@ -781,9 +945,11 @@ impl CowNodeRef {
         &mut self, node_memory_manager: &NodeMemoryManagerDeltaMpt,
         owned_node_set: &mut OwnedNodeSet,
         trie_node: GuardedMaybeOwnedTrieNodeAsCowCallParam,
+        db: &mut DeltaDbOwnedReadTraitObj,
     ) -> Result<Box<[u8]>>
     {
-        self.cow_modify_with_operation(
+        let value_ref = trie_node.as_ref().as_ref().external_value_ref();
+        let value = self.cow_modify_with_operation(
             node_memory_manager,
             &node_memory_manager.get_allocator(),
             owned_node_set,
@@ -799,18 +965,23 @@ impl CowNodeRef {
                     read_only_trie_node.value_clone().unwrap(),
                 )
             },
-        )
+        )?;
+        Ok(node_memory_manager
+            .resolve_value(MptValue::Some(value), value_ref, db)?
+            .unwrap())
     }
 
     pub fn cow_replace_value_valid(
         &mut self, node_memory_manager: &NodeMemoryManagerDeltaMpt,
         owned_node_set: &mut OwnedNodeSet,
         trie_node: GuardedMaybeOwnedTrieNodeAsCowCallParam, value: Box<[u8]>,
+        db: &mut DeltaDbOwnedReadTraitObj,
     ) -> Result<MptValue<Box<[u8]>>>
     {
         let value_to_take = Cell::new(Some(value));
+        let old_value_ref = trie_node.as_ref().as_ref().external_value_ref();
 
-        self.cow_modify_with_operation(
+        let old_value = self.cow_modify_with_operation(
             node_memory_manager,
             &node_memory_manager.get_allocator(),
             owned_node_set,
@@ -831,7 +1002,8 @@ impl CowNodeRef {
                     read_only_trie_node.value_clone(),
                 )
             },
-        )
+        )?;
+        node_memory_manager.resolve_value(old_value, old_value_ref, db)
     }
 
     /// If owned, run f_owned on trie node; otherwise run f_ref on the read-only
@@ -910,11 +1082,13 @@ use super::{
         },
         guarded_value::GuardedValue,
         node_memory_manager::*,
+        node_ref_map::DeltaMptDbKey,
         AtomicCommitTransaction, DeltaMpt, UnsafeCellExtension,
     },
     children_table::*,
     merkle::*,
     mpt_value::MptValue,
+    trie_node::ExternalValueRef,
     *,
 };
 use parking_lot::MutexGuard;
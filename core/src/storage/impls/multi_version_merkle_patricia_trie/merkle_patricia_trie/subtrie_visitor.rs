@@ -171,7 +171,9 @@ impl<'trie, 'db: 'trie> SubTrieVisitor<'trie, 'db> {
             // create proof node
             proof_nodes.push({
                 let merkle_hash = trie_node.get_merkle().clone();
-                let maybe_value = trie_node.value_clone().into_option();
+                let maybe_value = node_memory_manager
+                    .value_clone(&trie_node, &mut **self.db.get_mut())?
+                    .into_option();
                 let compressed_path = trie_node.compressed_path_ref().into();
 
                 let children = trie_node.children_table.clone();
@@ -196,7 +198,10 @@ impl<'trie, 'db: 'trie> SubTrieVisitor<'trie, 'db> {
 
         Ok(match maybe_trie_node {
             None => None,
-            Some(trie_node) => trie_node.value_clone().into_option(),
+            Some(trie_node) => self
+                .node_memory_manager()
+                .value_clone(&trie_node, &mut **self.db.get_mut())?
+                .into_option(),
         })
     }
 
@@ -213,7 +218,10 @@ impl<'trie, 'db: 'trie> SubTrieVisitor<'trie, 'db> {
                     return Ok(Some(trie_node.get_merkle().clone()));
                 }
 
-                let maybe_value = trie_node.value_clone().into_option();
+                let maybe_value = self
+                    .node_memory_manager()
+                    .value_clone(&trie_node, &mut **self.db.get_mut())?
+                    .into_option();
 
                 let merkles = {
                     let children_table = trie_node.children_table.clone();
@@ -261,8 +269,10 @@ impl<'trie, 'db: 'trie> SubTrieVisitor<'trie, 'db> {
                         let trie_node = GuardedValue::take(trie_node_ref);
                         let value = unsafe {
                             node_cow.delete_value_unchecked_followed_by_node_deletion(
+                                &node_memory_manager,
                                 trie_node,
-                            )
+                                &mut **self.db.get_mut(),
+                            )?
                         };
                         node_cow.delete_node(
                             node_memory_manager,
@@ -276,7 +286,12 @@ impl<'trie, 'db: 'trie> SubTrieVisitor<'trie, 'db> {
                     } => {
                         // The current node is going to be merged with its only
                         // child after the value deletion.
-                        let value = trie_node_ref.value_clone().unwrap();
+                        let value = node_memory_manager
+                            .value_clone(
+                                &trie_node_ref,
+                                &mut **self.db.get_mut(),
+                            )?
+                            .unwrap();
 
                         let trie_node = GuardedValue::take(trie_node_ref);
                         let merged_node_cow = node_cow.cow_merge_path(
@@ -299,6 +314,7 @@ impl<'trie, 'db: 'trie> SubTrieVisitor<'trie, 'db> {
                                 &node_memory_manager,
                                 self.owned_node_set.get_mut(),
                                 trie_node,
+                                &mut **self.db.get_mut(),
                             )?
                         };
 
@@ -601,6 +617,7 @@ impl<'trie, 'db: 'trie> SubTrieVisitor<'trie, 'db> {
                     self.owned_node_set.get_mut(),
                     trie_node,
                     value,
+                    &mut **self.db.get_mut(),
                 )?;
 
                 Ok((node_ref_changed, node_cow.into_child().unwrap()))
@@ -15,6 +15,8 @@ use super::{
     walk::*,
     WrappedCreateFrom,
 };
+use crate::hash::keccak;
+use cfx_types::H256;
 use primitives::{MerkleHash, MERKLE_NULL_NODE};
 use rlp::*;
 use std::{
@@ -310,6 +312,26 @@ fn test_mem_optimized_trie_node_size() {
     assert_ne!(std::mem::size_of::<Entry<TrieNodeDeltaMpt>>(), 80)
 }
 
+#[test]
+fn test_external_value_ref_rlp() {
+    let value = vec![1u8; 100];
+    let value_ref = ExternalValueRef::new(&value);
+    let mut node = TrieNodeDeltaMpt::default();
+    node.replace_value_valid(value.into_boxed_slice());
+
+    let decoded: TrieNodeDeltaMpt =
+        rlp::decode(&node.rlp_bytes_with_external_value(&value_ref)).unwrap();
+    assert_eq!(decoded.external_value_ref(), Some(value_ref));
+    assert!(decoded.has_value());
+
+    // A value of the same size as an encoded reference stays in place.
+    let mut node = TrieNodeDeltaMpt::default();
+    node.replace_value_valid(vec![2u8; ExternalValueRef::ENCODED_SIZE].into());
+    let decoded: TrieNodeDeltaMpt = rlp::decode(&node.rlp_bytes()).unwrap();
+    assert_eq!(decoded.external_value_ref(), None);
+    assert_eq!(decoded.value_as_slice(), node.value_as_slice());
+}
+
 make_parallel_field_maybe_in_place_byte_array_memory_manager!(
     MemOptimizedTrieNodePathMemoryManager<CacheAlgoDataT> where <CacheAlgoDataT: CacheAlgoDataTrait>,
     MemOptimizedTrieNode<CacheAlgoDataT>,
@@ -332,12 +354,10 @@ impl<CacheAlgoDataT: CacheAlgoDataTrait> Clone
     for MemOptimizedTrieNode<CacheAlgoDataT>
 {
     fn clone(&self) -> Self {
-        Self::new(
-            self.merkle_hash.clone(),
-            self.children_table.clone(),
-            self.value_clone().into_option(),
-            self.compressed_path_ref().into(),
-        )
+        // Copying all fields keeps a reference to an external value as is.
+        let mut ret = unsafe { self.copy_and_replace_fields(None, None, None) };
+        ret.merkle_hash = self.merkle_hash.clone();
+        ret
     }
 }
 
@@ -356,11 +376,56 @@ unsafe impl<CacheAlgoDataT: CacheAlgoDataTrait> Sync
 {
 }
 
+/// The reference to a large value which is stored in the db under its hash
+/// instead of in the trie node. The trie node keeps the reference in place of
+/// the value, and the merkle hash is still computed from the value itself.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExternalValueRef {
+    pub hash: H256,
+    pub len: u32,
+}
+
+impl ExternalValueRef {
+    pub const ENCODED_SIZE: usize = 36;
+
+    pub fn new(value: &[u8]) -> Self {
+        Self {
+            hash: keccak(value),
+            len: value.len() as u32,
+        }
+    }
+
+    /// The db key of the value, ev stands for external value.
+    pub fn db_key(&self) -> Vec<u8> {
+        let mut key = b"ev".to_vec();
+        key.extend_from_slice(&self.hash[..]);
+        key
+    }
+
+    fn to_bytes(&self) -> Box<[u8]> {
+        let mut bytes = self.hash[..].to_vec();
+        bytes.extend_from_slice(&self.len.to_be_bytes());
+        bytes.into_boxed_slice()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&bytes[32..Self::ENCODED_SIZE]);
+        Self {
+            hash: H256::from_slice(&bytes[..32]),
+            len: u32::from_be_bytes(len),
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 struct TrieNodeValueSizeFieldConverter {}
 
 impl TrieNodeValueSizeFieldConverter {
-    const MAX_VALUE_SIZE: usize = 0xfffffffe;
+    /// The flag in the value size field to indicate that the trie node holds
+    /// an `ExternalValueRef` instead of the value.
+    const EXTERNAL_VALUE_FLAG: u32 = 0x80000000;
+    const MAX_VALUE_SIZE: usize = 0x7fffffff;
     /// A special value to use in Delta Mpt to indicate that the value is
     /// deleted.
     ///
@@ -368,6 +433,11 @@ impl TrieNodeValueSizeFieldConverter {
     /// in serialized trie node and in methods manipulating value for trie
     /// node / MPT.
     const VALUE_TOMBSTONE: u32 = 0xffffffff;
+
+    fn is_external(size_field: u32) -> bool {
+        size_field != Self::VALUE_TOMBSTONE
+            && size_field & Self::EXTERNAL_VALUE_FLAG != 0
+    }
 }
 
 impl SizeFieldConverterTrait<u32> for TrieNodeValueSizeFieldConverter {
@@ -377,7 +447,7 @@ impl SizeFieldConverterTrait<u32> for TrieNodeValueSizeFieldConverter {
         if *size_field == Self::VALUE_TOMBSTONE {
             0
         } else {
-            (*size_field) as usize
+            (*size_field & !Self::EXTERNAL_VALUE_FLAG) as usize
         }
     }
 
@@ -399,6 +469,15 @@ impl<CacheAlgoDataT: CacheAlgoDataTrait> MemOptimizedTrieNode<CacheAlgoDataT> {
             MaybeInPlaceByteArray::copy_from(path_slice, path_slice.len());
     }
 
+    /// The size of the value bytes kept in the trie node.
+    fn value_bytes_size(&self) -> usize {
+        TrieNodeValueSizeFieldConverter::get(&self.value_size)
+    }
+
+    /// Clone the value kept in the trie node. When the value is stored
+    /// externally, the encoded `ExternalValueRef` is returned, therefore the
+    /// callers which need the value should use
+    /// `NodeMemoryManager::value_clone` instead.
     pub fn value_clone(&self) -> MptValue<Box<[u8]>> {
         let size = self.value_size;
         if size == 0 {
@@ -406,10 +485,29 @@ impl<CacheAlgoDataT: CacheAlgoDataTrait> MemOptimizedTrieNode<CacheAlgoDataT> {
         } else if size == TrieNodeValueSizeFieldConverter::VALUE_TOMBSTONE {
             MptValue::TombStone
         } else {
-            MptValue::Some(self.value.get_slice(size as usize).into())
+            MptValue::Some(self.value.get_slice(self.value_bytes_size()).into())
         }
     }
 
+    pub fn external_value_ref(&self) -> Option<ExternalValueRef> {
+        if TrieNodeValueSizeFieldConverter::is_external(self.value_size) {
+            Some(ExternalValueRef::from_bytes(
+                self.value.get_slice(self.value_bytes_size()),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Replace the value kept in the trie node with the reference to it, once
+    /// the value is written into the db.
+    pub fn replace_value_with_external_ref(
+        &mut self, value_ref: &ExternalValueRef,
+    ) {
+        self.replace_value_valid(value_ref.to_bytes());
+        self.value_size |= TrieNodeValueSizeFieldConverter::EXTERNAL_VALUE_FLAG;
+    }
+
     /// Take value out of self.
     /// This method can only be called by replace_value / delete_value because
     /// empty node must be removed and path compression must be maintained.
@@ -422,8 +520,9 @@ impl<CacheAlgoDataT: CacheAlgoDataTrait> MemOptimizedTrieNode<CacheAlgoDataT> {
             if size == TrieNodeValueSizeFieldConverter::VALUE_TOMBSTONE {
                 maybe_value = MptValue::TombStone
             } else {
-                maybe_value =
-                    MptValue::Some(self.value.into_boxed_slice(size as usize));
+                maybe_value = MptValue::Some(
+                    self.value.into_boxed_slice(self.value_bytes_size()),
+                );
             }
             self.value_size = 0;
         }
@@ -484,7 +583,7 @@ impl<CacheAlgoDataT: CacheAlgoDataTrait> TrieNodeTrait
         } else if size == TrieNodeValueSizeFieldConverter::VALUE_TOMBSTONE {
             MptValue::TombStone
         } else {
-            MptValue::Some(self.value.get_slice(size as usize))
+            MptValue::Some(self.value.get_slice(self.value_bytes_size()))
         }
     }
 
@@ -618,7 +717,7 @@ impl<CacheAlgoDataT: CacheAlgoDataTrait> MemOptimizedTrieNode<CacheAlgoDataT> {
                 None => {}
             },
             None => {
-                let value_size = self.value_size as usize;
+                let value_size = self.value_bytes_size();
                 ret.value_size = self.value_size;
                 ret.value = MaybeInPlaceByteArray::copy_from(
                     self.value.get_slice(value_size),
@@ -705,6 +804,16 @@ impl<CacheAlgoDataT: CacheAlgoDataTrait> MemOptimizedTrieNode<CacheAlgoDataT> {
     pub fn set_merkle(&mut self, merkle: &MerkleHash) {
         self.merkle_hash = merkle.clone();
     }
+
+    /// The rlp of the trie node to write into db, where the value is
+    /// replaced by `value_ref` if it's stored externally.
+    pub fn rlp_bytes_with_external_value(
+        &self, value_ref: &ExternalValueRef,
+    ) -> Vec<u8> {
+        let mut node = self.clone();
+        node.replace_value_with_external_ref(value_ref);
+        node.rlp_bytes()
+    }
 }
 
 impl<CacheAlgoDataT: CacheAlgoDataTrait> EntryTrait
@@ -762,8 +871,19 @@ impl<CacheAlgoDataT: CacheAlgoDataTrait> Encodable
     fn rlp_append(&self, s: &mut RlpStream) {
         s.begin_unbounded_list()
             .append(self.get_merkle())
-            .append(&self.get_children_table_ref().to_ref())
-            .append(&self.value_as_slice().into_option());
+            .append(&self.get_children_table_ref().to_ref());
+        // An external value is encoded as a list of two items, which can't be
+        // confused with the encoding of an optional value.
+        match self.external_value_ref() {
+            Some(value_ref) => {
+                s.begin_list(2)
+                    .append(&value_ref.hash)
+                    .append(&value_ref.len);
+            }
+            None => {
+                s.append(&self.value_as_slice().into_option());
+            }
+        }
 
         let compressed_path_ref = self.compressed_path_ref();
         if compressed_path_ref.path_size() > 0 {
@@ -784,6 +904,21 @@ impl<CacheAlgoDataT: CacheAlgoDataTrait> Decodable
             rlp.val_at(3)?
         };
 
+        let value_rlp = rlp.at(2)?;
+        if value_rlp.item_count()? == 2 {
+            let mut node = MemOptimizedTrieNode::new(
+                MerkleHash::from_slice(rlp.val_at::<Vec<u8>>(0)?.as_slice()),
+                rlp.val_at::<ChildrenTableManagedDeltaMpt>(1)?.into(),
+                None,
+                compressed_path,
+            );
+            node.replace_value_with_external_ref(&ExternalValueRef {
+                hash: value_rlp.val_at(0)?,
+                len: value_rlp.val_at(1)?,
+            });
+            return Ok(node);
+        }
+
         Ok(MemOptimizedTrieNode::new(
             MerkleHash::from_slice(rlp.val_at::<Vec<u8>>(0)?.as_slice()),
             rlp.val_at::<ChildrenTableManagedDeltaMpt>(1)?.into(),
@@ -3,6 +3,7 @@
 // See http://www.gnu.org/licenses/
 
 pub mod cache;
+pub(super) mod content_addressed_node_store;
 pub mod guarded_value;
 pub(in super::super) mod merkle_patricia_trie;
 pub(in super::super) mod node_memory_manager;
@@ -17,6 +18,7 @@ mod slab;
 pub use self::{
     node_memory_manager::{TrieNodeDeltaMpt, TrieNodeDeltaMptCell},
     node_ref_map::DEFAULT_NODE_MAP_SIZE,
+    slab::SlabUsage,
 };
 pub use merkle_patricia_trie::trie_proof::TrieProof;
 
@@ -33,6 +35,29 @@ pub struct AtomicCommitTransaction<
 > {
     pub info: MutexGuard<'a, AtomicCommit>,
     pub transaction: Transaction,
+    pub stats: CommitStats,
+    /// Set if trie nodes are deduplicated by merkle hash.
+    pub node_store: Option<ContentAddressedNodeStore>,
+    /// The values longer than it are stored outside of the trie nodes.
+    pub external_value_threshold: Option<usize>,
+    /// The references to the external values added and released by the
+    /// commit.
+    pub external_values: ExternalValueStore,
+}
+
+/// Statistics of the rows written into the delta db by a commit.
+#[derive(Default)]
+pub struct CommitStats {
+    pub nodes_written: u64,
+    pub trie_node_bytes: u64,
+    pub children_merkle_bytes: u64,
+    pub external_value_bytes: u64,
+    /// The number of committed trie nodes which reuse the row of an identical
+    /// trie node instead of being written.
+    pub nodes_deduplicated: u64,
+    /// The depths of the committed trie nodes with values, i.e. the modified
+    /// leaves. The root node has depth 0.
+    pub value_node_depths: Vec<u8>,
 }
 
 pub struct MultiVersionMerklePatriciaTrie {
@@ -62,6 +87,9 @@ pub struct MultiVersionMerklePatriciaTrie {
     // trigger the compiler warning.
     delta_mpts_releaser: DeltaDbReleaser,
     commit_lock: Mutex<AtomicCommit>,
+    /// Whether committed trie nodes are deduplicated by merkle hash.
+    dedup_trie_nodes: bool,
+    external_value_threshold: Option<usize>,
 }
 
 unsafe impl Sync for MultiVersionMerklePatriciaTrie {}
@@ -88,6 +116,14 @@ impl MultiVersionMerklePatriciaTrie {
         Ok(AtomicCommitTransaction {
             info: self.commit_lock.lock(),
             transaction: self.db.start_transaction_dyn(true)?,
+            stats: Default::default(),
+            node_store: if self.dedup_trie_nodes {
+                Some(Default::default())
+            } else {
+                None
+            },
+            external_value_threshold: self.external_value_threshold,
+            external_values: Default::default(),
         })
     }
 
@@ -109,6 +145,7 @@ impl MultiVersionMerklePatriciaTrie {
                 conf.cache_start_size,
                 conf.cache_size,
                 conf.idle_size,
+                conf.slab_max_idle_size,
                 conf.node_map_size,
                 LRU::<RLFUPosT, DeltaMptDbKey>::new(conf.cache_size),
             ),
@@ -121,6 +158,8 @@ impl MultiVersionMerklePatriciaTrie {
             commit_lock: Mutex::new(AtomicCommit {
                 row_number: RowNumber { value: row_number },
             }),
+            dedup_trie_nodes: conf.dedup_trie_nodes,
+            external_value_threshold: conf.external_value_threshold,
         }
     }
 
@@ -208,6 +247,10 @@ impl MultiVersionMerklePatriciaTrie {
     }
 
     pub fn log_usage(&self) { self.node_memory_manager.log_usage(); }
+
+    pub fn allocator_usage(&self) -> SlabUsage {
+        self.node_memory_manager.allocator_usage()
+    }
 }
 
 // Utility function.
@@ -233,11 +276,34 @@ impl MultiVersionMerklePatriciaTrie {
     }
 
     pub fn db_commit(&self) -> &dyn Any { (*self.db).as_any() }
+
+    /// The committed row of an identical trie node with the given merkle hash.
+    pub fn get_deduplicated_db_key(
+        &self, node_store: &mut ContentAddressedNodeStore, merkle: &MerkleHash,
+    ) -> Result<Option<DeltaMptDbKey>> {
+        node_store.get_db_key(&*self.db, merkle)
+    }
+
+    /// Put the external values referenced by a commit into its transaction,
+    /// and delete the ones no longer referenced.
+    pub fn write_external_values(
+        &self, external_values: ExternalValueStore,
+        transaction: &mut DeltaDbTransactionTraitObj,
+    ) -> Result<()>
+    {
+        external_values.write(&*self.db, transaction)
+    }
 }
 
 use self::{
-    cache::algorithm::lru::LRU, merkle_patricia_trie::*,
-    node_memory_manager::*, node_ref_map::DeltaMptDbKey, row_number::*,
+    cache::algorithm::lru::LRU,
+    content_addressed_node_store::{
+        ContentAddressedNodeStore, ExternalValueStore,
+    },
+    merkle_patricia_trie::*,
+    node_memory_manager::*,
+    node_ref_map::DeltaMptDbKey,
+    row_number::*,
 };
 use super::{
     super::storage_db::delta_db_manager::{
@@ -37,6 +37,15 @@ pub type CacheManagerDeltaMpt =
 
 impl CacheIndexTrait for DeltaMptDbKey {}
 
+lazy_static! {
+    static ref TRIE_NODE_SLAB_CAPACITY: Arc<dyn Gauge<usize>> =
+        GaugeUsize::register_with_group("storage", "trie_node_slab_capacity");
+    static ref TRIE_NODE_SLAB_USED: Arc<dyn Gauge<usize>> =
+        GaugeUsize::register_with_group("storage", "trie_node_slab_used");
+    static ref TRIE_NODE_SLAB_RELEASED: Arc<dyn Meter> =
+        register_meter_with_group("storage", "trie_node_slab_released");
+}
+
 // TODO: On performance, each access may requires a lock because of calling
 // TODO: cache algorithm & cache eviction & TrieNode slab alloc/delete
 // TODO: & noderefmap update. The read & write can not be easily broken
@@ -99,6 +108,11 @@ pub struct NodeMemoryManager<
     /// Unless size limit reached, there should be at lease idle_size available
     /// after each resize.
     idle_size: u32,
+    /// The initial number of nodes, below which the allocator never shrinks.
+    start_size: u32,
+    /// The allocator is shrunk when there are more idle slots than it.
+    max_idle_size: Option<u32>,
+    shrink_check_counter: AtomicUsize,
     /// Always get the read lock for allocator first because resizing requires
     /// write lock and it could be very slow, which we don't want to wait
     /// for inside critical section.
@@ -116,6 +130,7 @@ pub struct NodeMemoryManager<
     uncached_leaf_db_loads: AtomicUsize,
     pub compute_merkle_db_loads: AtomicUsize,
     children_merkle_db_loads: AtomicUsize,
+    external_value_db_loads: AtomicUsize,
 }
 
 #[allow(unused)]
@@ -150,6 +165,9 @@ impl<
     /// leaf node. This assumption is for delta_trie.
     pub const MAX_TRIE_NODES_MEM_ONLY: u32 = 27_600_000;
     pub const R_LFU_FACTOR: f64 = 4.0;
+    /// Shrinking scans the whole allocator, therefore it's only tried once
+    /// every this number of calls to `shrink`.
+    pub const SHRINK_CHECK_INTERVAL: usize = 64;
     pub const START_CAPACITY: u32 = 1_000_000;
 }
 
@@ -163,16 +181,20 @@ impl<
 {
     pub fn new(
         cache_start_size: u32, cache_size: u32, idle_size: u32,
-        node_map_size: u32, cache_algorithm: CacheAlgorithmT,
+        max_idle_size: Option<u32>, node_map_size: u32,
+        cache_algorithm: CacheAlgorithmT,
     ) -> Self
     {
         let size_limit = cache_size + idle_size;
+        let start_size = cache_start_size + idle_size;
         Self {
             size_limit,
             idle_size,
+            start_size,
+            max_idle_size,
+            shrink_check_counter: Default::default(),
             allocator: RwLock::new(
-                Slab::with_capacity((cache_start_size + idle_size) as usize)
-                    .into(),
+                Slab::with_capacity(start_size as usize).into(),
             ),
             cache: Mutex::new(CacheManager {
                 node_ref_map: NodeRefMapDeltaMpt::new(node_map_size),
@@ -184,6 +206,7 @@ impl<
             uncached_leaf_load_times: Default::default(),
             compute_merkle_db_loads: Default::default(),
             children_merkle_db_loads: Default::default(),
+            external_value_db_loads: Default::default(),
         }
     }
 
@@ -217,6 +240,45 @@ impl<
         Ok(())
     }
 
+    /// Release the idle slots of the allocator when there are more than
+    /// `max_idle_size` of them after the last used slot, e.g. after the nodes
+    /// of a big commit are evicted from cache, so that the peak memory isn't
+    /// kept forever. The vacant slots before the last used one can't be
+    /// released. The allocator keeps at least `idle_size` idle slots and its
+    /// initial size.
+    pub fn shrink(&self) {
+        let max_idle_size = match self.max_idle_size {
+            Some(max_idle_size) => max_idle_size as usize,
+            None => return,
+        };
+        if self.shrink_check_counter.fetch_add(1, Ordering::Relaxed)
+            % Self::SHRINK_CHECK_INTERVAL
+            != 0
+        {
+            return;
+        }
+        {
+            let usage = self.get_allocator().usage();
+            if usage.capacity - usage.initialized <= max_idle_size {
+                return;
+            }
+        }
+        let mut allocator_mut = self.allocator.write();
+        let min_capacity = max(
+            self.start_size as usize,
+            allocator_mut.len() + self.idle_size as usize,
+        );
+        let released = allocator_mut.shrink_to(min_capacity);
+        TRIE_NODE_SLAB_RELEASED.mark(released);
+        debug!(
+            "trie node allocator shrunk by {}, usage {:?}",
+            released,
+            allocator_mut.usage()
+        );
+    }
+
+    pub fn allocator_usage(&self) -> SlabUsage { self.get_allocator().usage() }
+
     pub fn log_uncached_key_access(&self, db_load_count: i32) {
         if db_load_count != 0 {
             self.uncached_leaf_db_loads
@@ -308,6 +370,42 @@ impl<
         Ok(Some(table))
     }
 
+    pub fn load_external_value(
+        &self, db: &mut DeltaDbOwnedReadTraitObj, value_ref: &ExternalValueRef,
+    ) -> Result<Box<[u8]>> {
+        self.external_value_db_loads.fetch_add(1, Ordering::Relaxed);
+        match db.get_mut(&value_ref.db_key())? {
+            Some(value) if value.len() == value_ref.len as usize => Ok(value),
+            _ => Err(ErrorKind::DbValueError.into()),
+        }
+    }
+
+    /// Clone the value of the trie node, loading it from db if it's stored
+    /// externally.
+    pub fn value_clone(
+        &self, trie_node: &TrieNodeDeltaMpt, db: &mut DeltaDbOwnedReadTraitObj,
+    ) -> Result<MptValue<Box<[u8]>>> {
+        self.resolve_value(
+            trie_node.value_clone(),
+            trie_node.external_value_ref(),
+            db,
+        )
+    }
+
+    /// Resolve the value cloned or taken out of a trie node, which is the
+    /// encoded `value_ref` if `value_ref` is set.
+    pub fn resolve_value(
+        &self, value: MptValue<Box<[u8]>>, value_ref: Option<ExternalValueRef>,
+        db: &mut DeltaDbOwnedReadTraitObj,
+    ) -> Result<MptValue<Box<[u8]>>> {
+        match value_ref {
+            Some(value_ref) => {
+                Ok(MptValue::Some(self.load_external_value(db, &value_ref)?))
+            }
+            None => Ok(value),
+        }
+    }
+
     /// This method is currently unused but kept for future use and for the sake
     /// of completeness.
     #[allow(dead_code)]
@@ -671,14 +769,20 @@ impl<
         let cache_manager = self.cache.lock();
         cache_manager.node_ref_map.log_usage();
         cache_manager.cache_algorithm.log_usage("trie node cache ");
-        let allocator_ref = self.get_allocator();
+        let usage = self.allocator_usage();
+        TRIE_NODE_SLAB_CAPACITY.update(usage.capacity);
+        TRIE_NODE_SLAB_USED.update(usage.used);
         debug!(
             "trie node allocator: max allowed size: {}, \
-             configured idle_size: {}, size: {}, allocated: {}",
+             configured idle_size: {}, size: {}, allocated: {}, \
+             initialized: {}, occupancy: {:.3}, fragmentation: {:.3}",
             self.size_limit,
             self.idle_size,
-            allocator_ref.capacity(),
-            allocator_ref.len()
+            usage.capacity,
+            usage.used,
+            usage.initialized,
+            usage.occupancy(),
+            usage.fragmentation()
         );
         debug!(
             "number of nodes loaded from db {}",
@@ -700,6 +804,10 @@ impl<
             "number of db loads for children merkles {}",
             self.children_merkle_db_loads.load(Ordering::Relaxed)
         );
+        debug!(
+            "number of db loads for external values {}",
+            self.external_value_db_loads.load(Ordering::Relaxed)
+        );
     }
 }
 
@@ -822,17 +930,24 @@ use super::{
         CacheIndexTrait, CacheStoreUtil,
     },
     guarded_value::*,
-    merkle_patricia_trie::{children_table::*, *},
+    merkle_patricia_trie::{
+        children_table::*, mpt_value::MptValue, trie_node::ExternalValueRef, *,
+    },
     node_ref_map::*,
-    slab::Slab,
+    slab::{Slab, SlabUsage},
     UnsafeCellExtension,
 };
+use metrics::{register_meter_with_group, Gauge, GaugeUsize, Meter};
 use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard};
 use primitives::MerkleHash;
 use rlp::*;
 use std::{
     cell::UnsafeCell,
+    cmp::max,
     hint::unreachable_unchecked,
     mem,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
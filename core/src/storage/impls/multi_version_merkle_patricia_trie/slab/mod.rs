@@ -114,8 +114,8 @@ use super::{
 };
 use parking_lot::Mutex;
 use std::{
-    cell::UnsafeCell, fmt, iter::IntoIterator, marker::PhantomData, mem, ops,
-    ptr, slice,
+    cell::UnsafeCell, cmp::max, fmt, iter::IntoIterator, marker::PhantomData,
+    mem, ops, ptr, slice,
 };
 
 /// Pre-allocated storage for a uniform data type.
@@ -146,6 +146,38 @@ pub struct Slab<T, E: EntryTrait<EntryType = T> = Entry<T>> {
     value_type: PhantomData<T>,
 }
 
+/// The occupancy of a slab.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SlabUsage {
+    /// The number of slots allocated.
+    pub capacity: usize,
+    /// The number of slots up to the last one used since the last shrink.
+    pub initialized: usize,
+    /// The number of stored values.
+    pub used: usize,
+}
+
+impl SlabUsage {
+    /// The fraction of the allocated slots which store values.
+    pub fn occupancy(&self) -> f64 {
+        if self.capacity == 0 {
+            0.0
+        } else {
+            self.used as f64 / self.capacity as f64
+        }
+    }
+
+    /// The fraction of the initialized slots which are vacant. These slots
+    /// are reused by new values but can't be released by shrinking.
+    pub fn fragmentation(&self) -> f64 {
+        if self.initialized == 0 {
+            0.0
+        } else {
+            (self.initialized - self.used) as f64 / self.initialized as f64
+        }
+    }
+}
+
 #[derive(Default)]
 struct AllocRelatedFields {
     // Number of Filled elements currently in the slab
@@ -572,6 +604,76 @@ impl<T, E: EntryTrait<EntryType = T>> Slab<T, E> {
         self.entries.shrink_to_fit();
     }
 
+    /// Release the vacant slots at the end of the slab while keeping the
+    /// capacity at least `min_capacity`, and return the number of released
+    /// slots.
+    ///
+    /// Since values are not moved, the slab cannot shrink past the last stored
+    /// value. The vacant slots are relinked in ascending order, so that new
+    /// values fill the beginning of the slab first and the vacant slots gather
+    /// at the end, where a later shrink can release them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate slab;
+    /// # use slab::*;
+    /// let mut slab = Slab::with_capacity(10);
+    ///
+    /// for i in 0..6 {
+    ///     slab.insert(i);
+    /// }
+    ///
+    /// slab.remove(1);
+    /// slab.remove(4);
+    /// slab.remove(5);
+    ///
+    /// assert_eq!(slab.shrink_to(2), 6);
+    /// assert_eq!(slab.insert(6), 1);
+    /// ```
+    pub fn shrink_to(&mut self, min_capacity: usize) -> usize {
+        let capacity = self.capacity();
+        let mut size_initialized = self.alloc_fields.get_mut().size_initialized;
+        while size_initialized > 0
+            && self.entries[size_initialized - 1].is_vacant()
+        {
+            size_initialized -= 1;
+        }
+        let mut next = size_initialized;
+        for key in (0..size_initialized).rev() {
+            if self.entries[key].is_vacant() {
+                self.entries[key] = E::from_vacant_index(next);
+                next = key;
+            }
+        }
+        let alloc_fields = self.alloc_fields.get_mut();
+        alloc_fields.size_initialized = size_initialized;
+        alloc_fields.next = next;
+
+        let new_capacity = max(size_initialized, min_capacity);
+        if new_capacity >= capacity {
+            return 0;
+        }
+        self.resize_down(capacity, new_capacity);
+        self.entries.shrink_to_fit();
+        // The allocator may leave room for a few more entries, which must be
+        // filled because the entries are always kept full.
+        let len = self.entries.len();
+        let capacity_after_shrink = self.capacity();
+        self.resize_up(len, capacity_after_shrink);
+        capacity - capacity_after_shrink
+    }
+
+    /// Return the occupancy of the slab.
+    pub fn usage(&self) -> SlabUsage {
+        let alloc_fields = self.alloc_fields.lock();
+        SlabUsage {
+            capacity: self.capacity(),
+            initialized: alloc_fields.size_initialized,
+            used: alloc_fields.used,
+        }
+    }
+
     /// Clear the slab of all values.
     ///
     /// # Examples
@@ -1056,3 +1158,52 @@ impl<'a, T, E: EntryTrait<EntryType = T>> Iterator for IterMut<'a, T, E> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Slab, SlabUsage};
+
+    #[test]
+    fn test_shrink_to_relinks_vacant_slots() {
+        let mut slab = Slab::<u32>::with_capacity(10);
+        for i in 0..6 {
+            assert_eq!(slab.insert(i).unwrap(), i as usize);
+        }
+        // The vacant slots are linked in the order of removal, i.e. 5, 4, 1.
+        for key in &[1, 4, 5] {
+            slab.remove(*key).unwrap();
+        }
+
+        let released = slab.shrink_to(2);
+        assert!(slab.capacity() >= 4);
+        assert_eq!(released, 10 - slab.capacity());
+        assert_eq!(
+            slab.usage(),
+            SlabUsage {
+                capacity: slab.capacity(),
+                initialized: 4,
+                used: 3,
+            }
+        );
+
+        // The vacant slots are reused in ascending order after the shrink.
+        assert_eq!(slab.insert(6).unwrap(), 1);
+        assert_eq!(slab.insert(7).unwrap(), 4);
+        assert_eq!(slab.usage().initialized, 5);
+        assert_eq!(slab[1], 6);
+        assert_eq!(slab[4], 7);
+    }
+
+    #[test]
+    fn test_shrink_to_min_capacity() {
+        let mut slab = Slab::<u32>::with_capacity(10);
+        for i in 0..3 {
+            slab.insert(i).unwrap();
+        }
+        let capacity = slab.capacity();
+        // The slab isn't shrunk below the minimum capacity.
+        assert_eq!(slab.shrink_to(capacity), 0);
+        assert_eq!(slab.capacity(), capacity);
+        assert_eq!(slab.insert(3).unwrap(), 3);
+    }
+}
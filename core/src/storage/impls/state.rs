@@ -18,6 +18,15 @@ pub struct State<'a> {
     /// Children merkle hashes. Only used for committing and computing
     /// merkle root. It will be cleared after being committed.
     children_merkle_map: ChildrenMerkleMap,
+
+    /// The epoch of the base state, if this state is for the next epoch.
+    parent_epoch_id: Option<EpochId>,
+    /// The key-value changes to archive on commit. Only tracked when the
+    /// archive is enabled.
+    archive_changes: Option<ArchiveChanges>,
+    /// Set if the state is materialized from the archive, in which case the
+    /// tries are empty and the state is read-only.
+    archived_state: Option<ArchivedState>,
 }
 
 impl<'a> State<'a> {
@@ -32,9 +41,28 @@ impl<'a> State<'a> {
             owned_node_set: Some(Default::default()),
             dirty: false,
             children_merkle_map: ChildrenMerkleMap::new(),
+            parent_epoch_id: None,
+            archive_changes: manager.archive.as_ref().map(|_| BTreeMap::new()),
+            archived_state: None,
         }
     }
 
+    pub(super) fn new_archived(
+        manager: &'a StateManager, state_trees: StateTrees,
+        archived_state: ArchivedState,
+    ) -> Self
+    {
+        let mut state = Self::new(manager, state_trees);
+        state.archive_changes = None;
+        state.archived_state = Some(archived_state);
+        state
+    }
+
+    pub(super) fn with_parent_epoch_id(mut self, epoch_id: EpochId) -> Self {
+        self.parent_epoch_id = Some(epoch_id);
+        self
+    }
+
     fn get_from_delta(
         &self, mpt: &'a DeltaMpt, maybe_root_node: Option<NodeRefDeltaMpt>,
         access_key: &[u8], with_proof: bool,
@@ -72,6 +100,33 @@ impl<'a> State<'a> {
         }
     }
 
+    /// Look up the keys which don't have a value yet in a delta MPT. All
+    /// lookups share one visitor and db read handle, and the keys are sorted
+    /// so that the trie nodes on their common paths are still cached when
+    /// visited again.
+    fn get_many_from_delta(
+        &self, mpt: &'a DeltaMpt, maybe_root_node: Option<NodeRefDeltaMpt>,
+        sorted_keys: &[(usize, &[u8])], values: &mut [Option<Box<[u8]>>],
+    ) -> Result<()>
+    {
+        let root_node = match maybe_root_node {
+            None => return Ok(()),
+            Some(root_node) => root_node,
+        };
+        // Get won't create any new nodes so it's fine to pass an empty
+        // owned_node_set.
+        let mut empty_owned_node_set: Option<OwnedNodeSet> =
+            Some(Default::default());
+        let mut visitor =
+            SubTrieVisitor::new(mpt, root_node, &mut empty_owned_node_set)?;
+        for (index, access_key) in sorted_keys {
+            if values[*index].is_none() {
+                values[*index] = visitor.get(access_key)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_from_snapshot(
         &self, access_key: &[u8],
     ) -> Result<Option<Box<[u8]>>> {
@@ -81,6 +136,14 @@ impl<'a> State<'a> {
     fn get_from_all_tries(
         &self, access_key: &[u8], with_proof: bool,
     ) -> Result<(Option<Box<[u8]>>, StateProof)> {
+        // Proofs aren't available without the tries.
+        if let Some(archived_state) = &self.archived_state {
+            return Ok((
+                archived_state.get(access_key)?,
+                StateProof::default(),
+            ));
+        }
+
         let (maybe_value, maybe_delta_proof) = self.get_from_delta(
             &self.delta_trie,
             self.delta_trie_root.clone(),
@@ -134,7 +197,9 @@ impl<'a> Drop for State<'a> {
 }
 
 impl<'a> StateTrait for State<'a> {
-    fn does_exist(&self) -> bool { self.get_delta_root_node().is_some() }
+    fn does_exist(&self) -> bool {
+        self.get_delta_root_node().is_some() || self.archived_state.is_some()
+    }
 
     fn get_padding(&self) -> &KeyPadding { &self.delta_trie.padding }
 
@@ -165,8 +230,43 @@ impl<'a> StateTrait for State<'a> {
         self.get_from_all_tries(access_key, true)
     }
 
+    fn get_many(
+        &self, access_keys: &[&[u8]],
+    ) -> Result<Vec<Option<Box<[u8]>>>> {
+        if let Some(archived_state) = &self.archived_state {
+            return access_keys
+                .iter()
+                .map(|access_key| archived_state.get(access_key))
+                .collect();
+        }
+
+        let mut sorted_keys: Vec<_> =
+            access_keys.iter().cloned().enumerate().collect();
+        sorted_keys.sort_by_key(|(_, access_key)| *access_key);
+        let mut values = vec![None; access_keys.len()];
+        self.get_many_from_delta(
+            &self.delta_trie,
+            self.delta_trie_root.clone(),
+            &sorted_keys,
+            &mut values,
+        )?;
+        if let Some(intermediate_trie) = &self.intermediate_trie {
+            self.get_many_from_delta(
+                intermediate_trie,
+                self.intermediate_trie_root.clone(),
+                &sorted_keys,
+                &mut values,
+            )?;
+        }
+        // TODO: get from snapshot
+        Ok(values)
+    }
+
     fn set(&mut self, access_key: &[u8], value: Box<[u8]>) -> Result<()> {
-        self.pre_modification();
+        self.pre_modification()?;
+        if let Some(changes) = &mut self.archive_changes {
+            changes.insert(access_key.into(), Some(value.clone()));
+        }
 
         let root_node = self.get_or_create_root_node()?;
         self.delta_trie_root = SubTrieVisitor::new(
@@ -181,7 +281,10 @@ impl<'a> StateTrait for State<'a> {
     }
 
     fn delete(&mut self, access_key: &[u8]) -> Result<Option<Box<[u8]>>> {
-        self.pre_modification();
+        self.pre_modification()?;
+        if let Some(changes) = &mut self.archive_changes {
+            changes.insert(access_key.into(), None);
+        }
 
         match self.get_delta_root_node() {
             None => Ok(None),
@@ -202,7 +305,7 @@ impl<'a> StateTrait for State<'a> {
     fn delete_all(
         &mut self, access_key_prefix: &[u8],
     ) -> Result<Option<Vec<(Vec<u8>, Box<[u8]>)>>> {
-        self.pre_modification();
+        self.pre_modification()?;
 
         match self.get_delta_root_node() {
             None => Ok(None),
@@ -215,6 +318,13 @@ impl<'a> StateTrait for State<'a> {
                 .delete_all(access_key_prefix, access_key_prefix)?;
                 self.delta_trie_root =
                     root_node.map(|maybe_node| maybe_node.into());
+                if let (Some(changes), Some(deleted)) =
+                    (&mut self.archive_changes, &deleted)
+                {
+                    for (key, _) in deleted {
+                        changes.insert(key.clone(), None);
+                    }
+                }
                 Ok(deleted)
             }
         }
@@ -256,12 +366,19 @@ impl<'a> StateTrait for State<'a> {
         let commit_result = self.do_db_commit(epoch_id);
         if commit_result.is_err() {
             self.revert();
+        } else {
+            self.archive_epoch(epoch_id);
+            self.delta_trie.get_node_memory_manager().shrink();
         }
         commit_result
     }
 
     fn revert(&mut self) {
         self.dirty = false;
+        self.children_merkle_map.clear();
+        if let Some(changes) = &mut self.archive_changes {
+            changes.clear();
+        }
 
         // Free all modified nodes.
         let owned_node_set = self.owned_node_set.as_ref().unwrap();
@@ -274,11 +391,40 @@ impl<'a> StateTrait for State<'a> {
 }
 
 impl<'a> State<'a> {
-    fn pre_modification(&mut self) {
+    fn pre_modification(&mut self) -> Result<()> {
+        if self.archived_state.is_some() {
+            return Err(ErrorKind::ArchivedStateReadOnly.into());
+        }
         if !self.dirty {
             self.dirty = true
         }
         self.delta_trie.get_node_memory_manager().enlarge().ok();
+        Ok(())
+    }
+
+    /// Archive the committed state. A failure only leaves the epoch out of
+    /// the archive, so that the next archived epoch starts a new snapshot.
+    fn archive_epoch(&mut self, epoch_id: EpochId) {
+        let archive = match &self.manager.archive {
+            Some(archive) => archive,
+            None => return,
+        };
+        let changes = match &mut self.archive_changes {
+            Some(changes) => mem::replace(changes, BTreeMap::new()),
+            None => return,
+        };
+        let state = DeltaMptInserter {
+            mpt: self.delta_trie.clone(),
+            maybe_root_node: self.delta_trie_root.clone(),
+        };
+        if let Err(e) = archive.record_epoch(
+            &epoch_id,
+            self.parent_epoch_id.as_ref(),
+            &changes,
+            state,
+        ) {
+            warn!("Failed to archive the state of epoch {:?}: {}", epoch_id, e);
+        }
     }
 
     fn get_delta_root_node(&self) -> Option<NodeRefDeltaMpt> {
@@ -385,12 +531,28 @@ impl<'a> State<'a> {
                             .lock(),
                         &allocator,
                         &mut self.children_merkle_map,
+                        0, /* depth */
                     );
-                    self.children_merkle_map.clear();
                     self.delta_trie_root =
                         cow_root.into_child().map(|r| r.into());
                     result?;
 
+                    if let Some(node_store) =
+                        commit_transaction.node_store.take()
+                    {
+                        node_store.write(
+                            &mut *commit_transaction.transaction,
+                            &mut commit_transaction.external_values,
+                        )?;
+                    }
+                    self.delta_trie.write_external_values(
+                        std::mem::replace(
+                            &mut commit_transaction.external_values,
+                            Default::default(),
+                        ),
+                        &mut *commit_transaction.transaction,
+                    )?;
+
                     // TODO: check the guarantee of underlying db on transaction
                     // TODO: failure. may have to commit last_row_number
                     // TODO: separately in worst case.
@@ -428,6 +590,11 @@ impl<'a> State<'a> {
                 commit_transaction
                     .transaction
                     .commit(self.delta_trie.db_commit())?;
+                report_commit_stats(&commit_transaction.stats);
+                // Children merkles are only dropped from memory once the
+                // transaction persisting them is committed. On failure they
+                // are dropped in `revert` together with the dirty nodes.
+                self.children_merkle_map.clear();
 
                 self.manager.number_committed_nodes.fetch_add(
                     (commit_transaction.info.row_number.value
@@ -460,19 +627,61 @@ impl<'a> State<'a> {
     }
 }
 
+lazy_static! {
+    static ref COMMIT_NODES_WRITTEN: Arc<dyn Histogram> = Sample::ExpDecay(
+        0.015
+    )
+    .register_with_group("storage", "commit_nodes_written", 1024);
+    static ref COMMIT_TRIE_NODE_BYTES: Arc<dyn Meter> =
+        register_meter_with_group("storage", "commit_trie_node_bytes");
+    static ref COMMIT_CHILDREN_MERKLE_BYTES: Arc<dyn Meter> =
+        register_meter_with_group("storage", "commit_children_merkle_bytes");
+    static ref COMMIT_EXTERNAL_VALUE_BYTES: Arc<dyn Meter> =
+        register_meter_with_group("storage", "commit_external_value_bytes");
+    static ref COMMIT_NODES_DEDUPLICATED: Arc<dyn Meter> =
+        register_meter_with_group("storage", "commit_nodes_deduplicated");
+    static ref COMMIT_VALUE_NODE_DEPTH: Arc<dyn Histogram> = Sample::ExpDecay(
+        0.015
+    )
+    .register_with_group("storage", "commit_value_node_depth", 1024);
+}
+
+fn report_commit_stats(stats: &CommitStats) {
+    COMMIT_NODES_WRITTEN.update(stats.nodes_written);
+    COMMIT_TRIE_NODE_BYTES.mark(stats.trie_node_bytes as usize);
+    COMMIT_CHILDREN_MERKLE_BYTES.mark(stats.children_merkle_bytes as usize);
+    COMMIT_EXTERNAL_VALUE_BYTES.mark(stats.external_value_bytes as usize);
+    COMMIT_NODES_DEDUPLICATED.mark(stats.nodes_deduplicated as usize);
+    for depth in &stats.value_node_depths {
+        COMMIT_VALUE_NODE_DEPTH.update(*depth as u64);
+    }
+    debug!(
+        "State commit: {} trie nodes, {} bytes, {} children merkle bytes, {} \
+         external value bytes, {} deduplicated trie nodes",
+        stats.nodes_written,
+        stats.trie_node_bytes,
+        stats.children_merkle_bytes,
+        stats.external_value_bytes,
+        stats.nodes_deduplicated
+    );
+}
+
 use super::{
     super::{state::*, state_manager::*, storage_db::*},
+    archive_state::{ArchiveChanges, ArchivedState},
     errors::*,
     multi_version_merkle_patricia_trie::{
         merkle_patricia_trie::{children_table::VanillaChildrenTable, *},
         node_memory_manager::ActualSlabIndex,
-        DeltaMpt, TrieProof,
+        CommitStats, DeltaMpt, TrieProof,
     },
     owned_node_set::OwnedNodeSet,
     state_manager::*,
     state_proof::StateProof,
+    storage_manager::storage_manager::DeltaMptInserter,
 };
 use crate::statedb::KeyPadding;
+use metrics::{register_meter_with_group, Histogram, Meter, Sample};
 use primitives::{
     EpochId, MerkleHash, StateRoot, StateRootWithAuxInfo, MERKLE_NULL_NODE,
 };
@@ -480,5 +689,6 @@ use std::{
     cell::UnsafeCell,
     collections::BTreeMap,
     hint::unreachable_unchecked,
+    mem,
     sync::{atomic::Ordering, Arc},
 };
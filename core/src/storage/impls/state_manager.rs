@@ -26,6 +26,7 @@ pub struct StateManager {
     pub db: Arc<SystemDB>,
     storage_manager: Arc<StorageManager>,
     pub number_committed_nodes: AtomicUsize,
+    pub(super) archive: Option<ArchiveStateManager>,
 }
 
 impl StateManager {
@@ -53,6 +54,9 @@ impl StateManager {
 
         let storage_manager =
             Arc::new(StorageManager::new(DeltaDbManager::new(db.clone())));
+        let archive = conf.archive_snapshot_interval.map(|interval| {
+            ArchiveStateManager::new(db.key_value().clone(), interval)
+        });
 
         // FIXME: move the commit_lock into delta_mpt, along with the row_number
         // FIXME: reading into the new_delta_mpt method.
@@ -68,6 +72,7 @@ impl StateManager {
             db,
             storage_manager,
             number_committed_nodes: Default::default(),
+            archive,
         }
     }
 
@@ -79,12 +84,35 @@ impl StateManager {
         initial_difficulty: U256,
     ) -> Block
     {
+        self.initialize_with_spec(&GenesisSpec::new(
+            genesis_accounts,
+            genesis_gas_limit,
+            test_net_version,
+            initial_difficulty,
+        ))
+    }
+
+    /// Commit the genesis state described by `spec`, and return the genesis
+    /// block.
+    pub fn initialize_with_spec(&self, spec: &GenesisSpec) -> Block {
         let mut state = StateDb::new(self.get_state_for_genesis_write());
 
-        for (addr, balance) in genesis_accounts {
-            let account =
-                Account::new_empty_with_balance(&addr, &balance, &0.into());
-            state.set(&state.account_key(&addr), &account).unwrap();
+        for (addr, genesis_account) in &spec.accounts {
+            let mut account = Account::new_empty_with_balance(
+                addr,
+                &genesis_account.balance,
+                &genesis_account.nonce,
+            );
+            if let Some(code) = &genesis_account.code {
+                account.code_hash = keccak(code);
+                state
+                    .set_raw(
+                        &state.code_key(addr, &account.code_hash),
+                        code.clone().into_boxed_slice(),
+                    )
+                    .unwrap();
+            }
+            state.set(&state.account_key(addr), &account).unwrap();
         }
 
         let state_root = state.compute_state_root().unwrap();
@@ -94,9 +122,10 @@ impl StateManager {
                     state_root.state_root.compute_state_root_hash(),
                 )
                 .with_deferred_state_root_with_aux_info(state_root)
-                .with_gas_limit(genesis_gas_limit)
-                .with_author(test_net_version)
-                .with_difficulty(initial_difficulty)
+                .with_gas_limit(spec.gas_limit)
+                .with_author(spec.author)
+                .with_difficulty(spec.difficulty)
+                .with_timestamp(spec.timestamp)
                 .build(),
             Vec::new(),
         );
@@ -106,6 +135,11 @@ impl StateManager {
         genesis
     }
 
+    /// The occupancy of the slab holding the trie nodes in memory.
+    pub fn trie_node_allocator_usage(&self) -> SlabUsage {
+        self.delta_trie.allocator_usage()
+    }
+
     pub fn log_usage(&self) {
         self.delta_trie.log_usage();
         info!(
@@ -114,6 +148,23 @@ impl StateManager {
         );
     }
 
+    /// The available snapshots and the epochs based on them, in the order of
+    /// their heights, starting with the empty snapshot of the genesis.
+    pub fn snapshots(&self) -> Vec<SnapshotInfo> {
+        let mut snapshots = vec![SnapshotInfo::empty()];
+        snapshots.extend(self.storage_manager.get_snapshot_infos());
+        snapshots
+    }
+
+    /// The snapshot which the state of the epoch at `height` is based on.
+    pub fn snapshot_of_epoch(&self, height: u64) -> SnapshotInfo {
+        self.snapshots()
+            .into_iter()
+            .filter(|snapshot| snapshot.epoch_range().contains(&height))
+            .last()
+            .unwrap_or_else(SnapshotInfo::empty)
+    }
+
     /// This is unsafe because if state for `epoch_id` does not exist, it'll
     /// panic.
     pub unsafe fn get_state_readonly_assumed_existence(
@@ -157,6 +208,51 @@ impl StateManager {
         }
     }
 
+    /// Materialize the state of an epoch from the archive if it is enabled
+    /// and the epoch is archived.
+    fn get_archived_state(
+        &self, epoch_id: &SnapshotAndEpochIdRef,
+    ) -> Result<Option<State>> {
+        let archive = match &self.archive {
+            Some(archive) => archive,
+            None => return Ok(None),
+        };
+        let archived_state =
+            match archive.get_archived_state(epoch_id.epoch_id)? {
+                Some(archived_state) => archived_state,
+                None => return Ok(None),
+            };
+        match self.storage_manager.get_snapshot(&MERKLE_NULL_NODE)? {
+            None => Ok(None),
+            Some(snapshot) => Ok(Some(State::new_archived(
+                self,
+                (snapshot, None, None, self.delta_trie.clone(), None),
+                archived_state,
+            ))),
+        }
+    }
+
+    /// Snapshot the state of a new checkpoint, i.e. an era genesis. The delta
+    /// MPT is not shifted to a new snapshot yet, so only the archive, if it's
+    /// enabled, takes a snapshot at the checkpoint.
+    pub fn snapshot_checkpoint(&self, epoch_id: &EpochId) -> Result<()> {
+        let archive = match &self.archive {
+            Some(archive) => archive,
+            None => return Ok(()),
+        };
+        let root_node = self.delta_trie.get_state_root_node_ref(epoch_id)?;
+        if root_node.is_none() {
+            return Err(ErrorKind::DbValueError.into());
+        }
+        archive.snapshot_epoch(
+            epoch_id,
+            DeltaMptInserter {
+                mpt: self.delta_trie.clone(),
+                maybe_root_node: root_node,
+            },
+        )
+    }
+
     pub fn get_state_trees_for_next_epoch(
         &self, parent_epoch_id: &SnapshotAndEpochIdRef,
     ) -> Result<Option<StateTrees>> {
@@ -207,7 +303,8 @@ impl StateManagerTrait for StateManager {
     ) -> Result<Option<State>> {
         let maybe_state_trees = self.get_state_trees(&epoch_id)?;
         match maybe_state_trees {
-            None => Ok(None),
+            // The delta MPT root of the epoch is no longer available.
+            None => self.get_archived_state(&epoch_id),
             Some(state_trees) => Ok(Some(State::new(self, state_trees))),
         }
     }
@@ -235,7 +332,10 @@ impl StateManagerTrait for StateManager {
             self.get_state_trees_for_next_epoch(&parent_epoch_id)?;
         match maybe_state_trees {
             None => Ok(None),
-            Some(state_trees) => Ok(Some(State::new(self, state_trees))),
+            Some(state_trees) => Ok(Some(
+                State::new(self, state_trees)
+                    .with_parent_epoch_id(*parent_epoch_id.epoch_id),
+            )),
         }
     }
 
@@ -265,6 +365,7 @@ use super::{
         snapshot_manager::SnapshotManagerTrait, state::*, state_manager::*,
         storage_db::*,
     },
+    archive_state::ArchiveStateManager,
     errors::*,
     multi_version_merkle_patricia_trie::{
         merkle_patricia_trie::NodeRefDeltaMpt, *,
@@ -273,10 +374,14 @@ use super::{
         delta_db_manager_rocksdb::DeltaDbManagerRocksdb,
         snapshot_db_manager_sqlite::SnapshotDbManagerSqlite,
     },
-    storage_manager::storage_manager::StorageManager,
+    storage_manager::storage_manager::{DeltaMptInserter, StorageManager},
+};
+use crate::{
+    ext_db::SystemDB, genesis::GenesisSpec, snapshot::snapshot::Snapshot,
+    statedb::StateDb,
 };
-use crate::{ext_db::SystemDB, snapshot::snapshot::Snapshot, statedb::StateDb};
 use cfx_types::{Address, U256};
+use hash::keccak;
 use primitives::{
     Account, Block, BlockHeaderBuilder, EpochId, MerkleHash, MERKLE_NULL_NODE,
 };
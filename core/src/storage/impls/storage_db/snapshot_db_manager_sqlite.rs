@@ -6,7 +6,7 @@ use parity_bytes::ToPretty;
 
 pub struct SnapshotDbManagerSqlite {
     // TODO: persistent in db.
-    epoch_to_snapshot_root: RwLock<HashMap<EpochId, MerkleHash>>,
+    epoch_to_snapshot: RwLock<HashMap<EpochId, SnapshotInfo>>,
     snapshot_path: String,
     // FIXME: add an command line option to assert that this method made
     // successfully cow_copy and print error messages if it fails.
@@ -16,7 +16,7 @@ pub struct SnapshotDbManagerSqlite {
 impl SnapshotDbManagerSqlite {
     pub fn new(snapshot_path: String) -> Self {
         Self {
-            epoch_to_snapshot_root: Default::default(),
+            epoch_to_snapshot: Default::default(),
             snapshot_path: snapshot_path + "/sqlite",
             force_cow: true,
         }
@@ -162,9 +162,15 @@ impl SnapshotDbManagerTrait for SnapshotDbManagerSqlite {
                     &temp_db_name,
                     &self.get_snapshot_db_path(&new_snapshot_root),
                 )?;
-                self.epoch_to_snapshot_root
-                    .write()
-                    .insert(snapshot_epoch_id, new_snapshot_root);
+                self.epoch_to_snapshot.write().insert(
+                    snapshot_epoch_id,
+                    SnapshotInfo {
+                        snapshot_root: new_snapshot_root,
+                        previous_snapshot_root: *old_snapshot_root,
+                        snapshot_epoch_id: Some(snapshot_epoch_id),
+                        height: height as u64,
+                    },
+                );
 
                 Ok(self.get_snapshot(&new_snapshot_root)?.unwrap())
             }
@@ -188,16 +194,26 @@ impl SnapshotDbManagerTrait for SnapshotDbManagerSqlite {
     fn get_snapshot_by_epoch_id(
         &self, epoch_id: &EpochId,
     ) -> Result<Option<Self::SnapshotDb>> {
-        match self.epoch_to_snapshot_root.read().get(epoch_id) {
+        match self.epoch_to_snapshot.read().get(epoch_id) {
             None => Ok(None),
-            Some(snapshot_root) => self.get_snapshot(snapshot_root),
+            Some(snapshot) => self.get_snapshot(&snapshot.snapshot_root),
         }
     }
+
+    fn get_snapshot_infos(&self) -> Vec<SnapshotInfo> {
+        let mut snapshots: Vec<_> =
+            self.epoch_to_snapshot.read().values().cloned().collect();
+        snapshots.sort_by_key(|snapshot| snapshot.height);
+        snapshots
+    }
 }
 
 use super::{
     super::{
-        super::storage_db::{SnapshotDbManagerTrait, SnapshotDbTrait},
+        super::{
+            state_manager::SnapshotInfo,
+            storage_db::{SnapshotDbManagerTrait, SnapshotDbTrait},
+        },
         errors::*,
         storage_manager::DeltaMptInserter,
     },
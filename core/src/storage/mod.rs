@@ -23,6 +23,7 @@ pub use self::{
         errors::{Error, ErrorKind, Result},
         multi_version_merkle_patricia_trie::{
             guarded_value::GuardedValue, MultiVersionMerklePatriciaTrie,
+            SlabUsage,
         },
         storage_db::{
             kvdb_rocksdb::KvdbRocksdb, kvdb_sqlite::KvdbSqlite,
@@ -31,7 +32,7 @@ pub use self::{
     },
     state::{State as Storage, StateTrait as StorageTrait},
     state_manager::{
-        SnapshotAndEpochIdRef, StateManager as StorageManager,
+        SnapshotAndEpochIdRef, SnapshotInfo, StateManager as StorageManager,
         StateManagerTrait as StorageManagerTrait,
     },
     storage_db::KeyValueDbTrait,
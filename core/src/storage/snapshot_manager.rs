@@ -34,6 +34,11 @@ pub trait SnapshotManagerTrait: GetSnapshotDbManager {
             .get_snapshot_by_epoch_id(epoch_id)
     }
 
+    fn get_snapshot_infos(&self) -> Vec<SnapshotInfo>
+    where Self: Sized {
+        self.get_snapshot_db_manager().get_snapshot_infos()
+    }
+
     fn remove_old_pivot_snapshot(
         &self, snapshot_root: &MerkleHash,
     ) -> Result<()>;
@@ -54,6 +59,7 @@ pub trait GetSnapshotDbManager {
 
 use super::{
     impls::{errors::*, storage_manager::storage_manager::DeltaMptInserter},
+    state_manager::SnapshotInfo,
     storage_db::{snapshot_db::*, snapshot_db_manager::*},
 };
 use primitives::{EpochId, MerkleHash};
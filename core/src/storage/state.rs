@@ -32,6 +32,10 @@ pub trait StateTrait {
     fn get_with_proof(
         &self, access_key: &[u8],
     ) -> Result<(Option<Box<[u8]>>, StateProof)>;
+    /// Get the values of multiple keys, in the order of `access_keys`, with
+    /// a single traversal setup per trie.
+    fn get_many(&self, access_keys: &[&[u8]])
+        -> Result<Vec<Option<Box<[u8]>>>>;
     fn set(&mut self, access_key: &[u8], value: Box<[u8]>) -> Result<()>;
     fn delete(&mut self, access_key: &[u8]) -> Result<Option<Box<[u8]>>>;
     // Delete everything prefixed by access_key and return deleted key value
@@ -8,6 +8,40 @@ pub use super::impls::state_manager::StateManager;
 
 pub type SharedStateManager = Arc<StateManager>;
 
+/// A snapshot of the state, on top of which the states of the epochs in
+/// `epoch_range()` are kept in the delta MPT.
+#[derive(Clone, Debug)]
+pub struct SnapshotInfo {
+    pub snapshot_root: MerkleHash,
+    pub previous_snapshot_root: MerkleHash,
+    /// The epoch the snapshot is taken at, or `None` for the empty snapshot
+    /// which the genesis state is based on.
+    pub snapshot_epoch_id: Option<EpochId>,
+    /// The height of the snapshot epoch.
+    pub height: u64,
+}
+
+impl SnapshotInfo {
+    pub fn empty() -> Self {
+        Self {
+            snapshot_root: MERKLE_NULL_NODE,
+            previous_snapshot_root: MERKLE_NULL_NODE,
+            snapshot_epoch_id: None,
+            height: 0,
+        }
+    }
+
+    /// The heights of the epochs whose states are based on the snapshot,
+    /// unless a later snapshot is taken before the delta MPT is full.
+    pub fn epoch_range(&self) -> RangeInclusive<u64> {
+        let first = match self.snapshot_epoch_id {
+            None => self.height,
+            Some(_) => self.height + 1,
+        };
+        first..=self.height + SNAPSHOT_EPOCHS_CAPACITY
+    }
+}
+
 #[derive(Debug)]
 pub struct SnapshotAndEpochIdRef<'a> {
     pub snapshot_root: &'a MerkleHash,
@@ -60,6 +94,20 @@ impl<'a> SnapshotAndEpochIdRef<'a> {
         }
     }
 
+    /// The epoch at `height` in `snapshot`, which is resolved by
+    /// `StateManager::snapshot_of_epoch`.
+    pub fn new_with_snapshot(
+        epoch_id: &'a EpochId, snapshot: &'a SnapshotInfo, height: u64,
+    ) -> Self {
+        Self {
+            snapshot_root: &snapshot.snapshot_root,
+            previous_snapshot_root: &snapshot.previous_snapshot_root,
+            intermediate_delta_epoch_id: &MERKLE_NULL_NODE,
+            epoch_id,
+            delta_height: Some(height - snapshot.height),
+        }
+    }
+
     #[allow(unused)]
     pub fn new_for_next_epoch(
         epoch_id: &'a EpochId, state_root: &'a StateRootWithAuxInfo,
@@ -106,6 +154,21 @@ pub struct StorageConfiguration {
     pub idle_size: u32,
     pub node_map_size: u32,
     pub recent_lfu_factor: f64,
+    /// Archive the state of every committed epoch with a full snapshot every
+    /// given number of epochs, so that the states no longer available in the
+    /// delta MPT can still be read. `None` disables the archive.
+    pub archive_snapshot_interval: Option<u64>,
+    /// Deduplicate the committed trie nodes by merkle hash, so that a trie
+    /// node recreated with identical content isn't written again.
+    pub dedup_trie_nodes: bool,
+    /// Store the values longer than the threshold in the delta db under their
+    /// hashes instead of in the trie nodes, so that large contract storage
+    /// values don't inflate the trie nodes. `None` keeps all values in the
+    /// trie nodes.
+    pub external_value_threshold: Option<usize>,
+    /// Shrink the trie node allocator, which otherwise only grows, when it
+    /// has more idle slots than this after a commit. `None` never shrinks.
+    pub slab_max_idle_size: Option<u32>,
 }
 
 impl Default for StorageConfiguration {
@@ -116,15 +179,19 @@ impl Default for StorageConfiguration {
             idle_size: defaults::DEFAULT_IDLE_SIZE,
             node_map_size: defaults::MAX_CACHED_TRIE_NODES_R_LFU_COUNTER,
             recent_lfu_factor: defaults::DEFAULT_RECENT_LFU_FACTOR,
+            archive_snapshot_interval: None,
+            dedup_trie_nodes: false,
+            external_value_threshold: None,
+            slab_max_idle_size: None,
         }
     }
 }
 
 use super::{
-    impls::{defaults, errors::*},
+    impls::{defaults, errors::*, state_manager::SNAPSHOT_EPOCHS_CAPACITY},
     state::State,
 };
 // FIXME: snapshot... wire format?
 use crate::snapshot::snapshot::Snapshot;
 use primitives::{EpochId, MerkleHash, StateRootWithAuxInfo, MERKLE_NULL_NODE};
-use std::sync::Arc;
+use std::{ops::RangeInclusive, sync::Arc};
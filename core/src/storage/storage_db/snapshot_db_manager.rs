@@ -21,11 +21,16 @@ pub trait SnapshotDbManagerTrait {
         &self, snapshot_root: &MerkleHash,
     ) -> Result<Option<Self::SnapshotDb>>;
     fn destroy_snapshot(&self, snapshot_root: &MerkleHash) -> Result<()>;
+    /// The snapshots created by merging, in the order of their heights.
+    fn get_snapshot_infos(&self) -> Vec<SnapshotInfo>;
 }
 
 use super::{
-    super::impls::{
-        errors::*, storage_manager::storage_manager::DeltaMptInserter,
+    super::{
+        impls::{
+            errors::*, storage_manager::storage_manager::DeltaMptInserter,
+        },
+        state_manager::SnapshotInfo,
     },
     snapshot_db::*,
 };
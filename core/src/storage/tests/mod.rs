@@ -8,8 +8,9 @@ mod state;
 use super::state_manager::StateManager;
 use crate::{ext_db::SystemDB, storage::state_manager::StorageConfiguration};
 use elastic_array::ElasticArray128;
-use kvdb::{DBTransaction, KeyValueDB};
-use std::{io::Result, sync::Arc};
+use kvdb::{DBOp, DBTransaction, KeyValueDB};
+use parking_lot::RwLock;
+use std::{collections::BTreeMap, io::Result, sync::Arc};
 
 #[derive(Default)]
 pub struct FakeDbForStateTest {}
@@ -48,15 +49,122 @@ impl KeyValueDB for FakeDbForStateTest {
     fn restore(&self, _new_db: &str) -> Result<()> { unreachable!() }
 }
 
+/// A db keeping the written key-values in memory, for the tests which read
+/// them back.
+#[derive(Default)]
+pub struct MemoryDbForStateTest {
+    columns: RwLock<BTreeMap<Option<u32>, BTreeMap<Vec<u8>, Vec<u8>>>>,
+}
+
+impl KeyValueDB for MemoryDbForStateTest {
+    fn get(
+        &self, col: Option<u32>, key: &[u8],
+    ) -> Result<Option<ElasticArray128<u8>>> {
+        Ok(self
+            .columns
+            .read()
+            .get(&col)
+            .and_then(|column| column.get(key))
+            .map(|value| ElasticArray128::from_slice(value)))
+    }
+
+    fn get_by_prefix(
+        &self, col: Option<u32>, prefix: &[u8],
+    ) -> Option<Box<[u8]>> {
+        self.iter_from_prefix(col, prefix)
+            .next()
+            .map(|(_, value)| value)
+    }
+
+    fn write_buffered(&self, transaction: DBTransaction) {
+        let mut columns = self.columns.write();
+        for op in transaction.ops {
+            match op {
+                DBOp::Insert { col, key, value } => {
+                    columns
+                        .entry(col)
+                        .or_default()
+                        .insert(key.to_vec(), value.to_vec());
+                }
+                DBOp::Delete { col, key } => {
+                    columns.entry(col).or_default().remove(&*key);
+                }
+            }
+        }
+    }
+
+    fn flush(&self) -> Result<()> { Ok(()) }
+
+    fn iter<'a>(
+        &'a self, col: Option<u32>,
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        self.iter_from_prefix(col, &[])
+    }
+
+    fn iter_from_prefix<'a>(
+        &'a self, col: Option<u32>, prefix: &'a [u8],
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        let items: Vec<_> = self
+            .columns
+            .read()
+            .get(&col)
+            .into_iter()
+            .flat_map(|column| column.range(prefix.to_vec()..))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone().into(), value.clone().into()))
+            .collect();
+        Box::new(items.into_iter())
+    }
+
+    fn restore(&self, _new_db: &str) -> Result<()> { unreachable!() }
+}
+
+/// A state manager on a db in memory which stores the values longer than
+/// `threshold` outside of the trie nodes.
+pub fn new_external_value_state_manager_for_testing(
+    threshold: usize,
+) -> StateManager {
+    StateManager::new(
+        Arc::new(SystemDB::new(Arc::new(MemoryDbForStateTest::default()))),
+        StorageConfiguration {
+            dedup_trie_nodes: true,
+            external_value_threshold: Some(threshold),
+            ..storage_configuration_for_testing()
+        },
+    )
+}
+
+fn storage_configuration_for_testing() -> StorageConfiguration {
+    StorageConfiguration {
+        cache_start_size: 1_000_000,
+        cache_size: 20_000_000,
+        idle_size: 200_000,
+        node_map_size: 20_000_000,
+        recent_lfu_factor: 4.0,
+        archive_snapshot_interval: None,
+        dedup_trie_nodes: false,
+        external_value_threshold: None,
+        slab_max_idle_size: None,
+    }
+}
+
 pub fn new_state_manager_for_testing() -> StateManager {
     StateManager::new(
         Arc::new(SystemDB::new(Arc::new(FakeDbForStateTest::default()))),
+        storage_configuration_for_testing(),
+    )
+}
+
+/// A state manager on a db in memory which archives the state of every
+/// committed epoch, with a snapshot every `snapshot_interval` epochs.
+pub fn new_archive_state_manager_for_testing(
+    snapshot_interval: u64,
+) -> StateManager {
+    StateManager::new(
+        Arc::new(SystemDB::new(Arc::new(MemoryDbForStateTest::default()))),
         StorageConfiguration {
-            cache_start_size: 1_000_000,
-            cache_size: 20_000_000,
-            idle_size: 200_000,
-            node_map_size: 20_000_000,
-            recent_lfu_factor: 4.0,
+            archive_snapshot_interval: Some(snapshot_interval),
+            ..storage_configuration_for_testing()
         },
     )
 }
@@ -48,6 +48,36 @@ fn test_empty_genesis_block() {
         .unwrap();
 }
 
+#[test]
+fn test_snapshot_of_epoch() {
+    let state_manager = new_state_manager_for_testing();
+
+    let snapshots = state_manager.snapshots();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].snapshot_epoch_id, None);
+    assert!(snapshots[0].epoch_range().contains(&0));
+
+    let mut genesis_epoch_id = H256::default();
+    genesis_epoch_id.as_bytes_mut()[0] = 1;
+    {
+        let mut genesis_state = state_manager.get_state_for_genesis_write();
+        genesis_state.compute_state_root().unwrap();
+
+        genesis_state.commit(genesis_epoch_id).unwrap();
+    }
+
+    let snapshot = state_manager.snapshot_of_epoch(0);
+    assert_eq!(snapshot.snapshot_root, MERKLE_NULL_NODE);
+    assert!(state_manager
+        .get_state_no_commit(SnapshotAndEpochIdRef::new_with_snapshot(
+            &genesis_epoch_id,
+            &snapshot,
+            0,
+        ))
+        .unwrap()
+        .is_some());
+}
+
 #[test]
 fn test_set_get() {
     let mut rng = get_rng_for_test();
@@ -105,7 +135,7 @@ fn test_get_set_at_second_commit() {
             .expect("Failed to insert key.");
     }
 
-    let mut epoch_id_0 = H256::default();;
+    let mut epoch_id_0 = H256::default();
     epoch_id_0.as_bytes_mut()[0] = 1;
     state_0.compute_state_root().unwrap();
     state_0.commit(epoch_id_0).unwrap();
@@ -166,7 +196,7 @@ fn test_get_set_at_second_commit() {
         assert_eq!(equal, true);
     }
 
-    let mut epoch_id_1 = H256::default();;
+    let mut epoch_id_1 = H256::default();
     epoch_id_1.as_bytes_mut()[0] = 2;
     state_1.compute_state_root().unwrap();
     state_1.commit(epoch_id_1).unwrap();
@@ -563,14 +593,153 @@ fn test_proofs() {
     }
 }
 
+/// Set a short key and a long key under it with values of `value_len` bytes,
+/// and overwrite half of the long keys in the second epoch. Returns the state
+/// roots of both epochs.
+fn set_values_in_two_epochs(
+    state_manager: &StateManager, value_len: usize,
+) -> Vec<StateRootWithAuxInfo> {
+    let mut state_roots = Vec::new();
+    let mut state = state_manager.get_state_for_genesis_write();
+    for i in 0..100u8 {
+        state.set(&[i, 1], vec![i; value_len].into()).unwrap();
+        state
+            .set(&[i, 1, 2, 3], vec![!i; value_len].into())
+            .unwrap();
+    }
+    let mut epoch_id_0 = H256::default();
+    epoch_id_0.as_bytes_mut()[0] = 1;
+    state_roots.push(state.compute_state_root().unwrap());
+    state.commit(epoch_id_0).unwrap();
+
+    // The trie nodes of the short keys are copied for the modified long keys
+    // under them.
+    let mut state = state_manager
+        .get_state_for_next_epoch(SnapshotAndEpochIdRef::new(&epoch_id_0, None))
+        .unwrap()
+        .unwrap();
+    for i in (0..100u8).step_by(2) {
+        state.set(&[i, 1, 2, 3], vec![i; value_len].into()).unwrap();
+    }
+    let mut epoch_id_1 = H256::default();
+    epoch_id_1.as_bytes_mut()[0] = 2;
+    state_roots.push(state.compute_state_root().unwrap());
+    state.commit(epoch_id_1).unwrap();
+
+    let state = state_manager
+        .get_state_no_commit(SnapshotAndEpochIdRef::new(&epoch_id_1, None))
+        .unwrap()
+        .unwrap();
+    for i in 0..100u8 {
+        assert_eq!(
+            state.get(&[i, 1]).unwrap(),
+            Some(vec![i; value_len].into_boxed_slice())
+        );
+        let value = if i % 2 == 0 { i } else { !i };
+        assert_eq!(
+            state.get(&[i, 1, 2, 3]).unwrap(),
+            Some(vec![value; value_len].into_boxed_slice())
+        );
+    }
+
+    state_roots
+}
+
+#[test]
+fn test_external_values() {
+    let value_len = 100;
+    let state_roots = set_values_in_two_epochs(
+        &new_external_value_state_manager_for_testing(value_len - 1),
+        value_len,
+    );
+    // The merkle hashes don't depend on where the values are stored.
+    assert_eq!(
+        state_roots,
+        set_values_in_two_epochs(&new_state_manager_for_testing(), value_len)
+    );
+}
+
+#[test]
+fn test_get_many() {
+    let state_manager = new_state_manager_for_testing();
+    let only_intermediate = [1u8, 1];
+    let both = [1u8, 2];
+    let only_delta = [2u8, 1];
+    let missing = [3u8, 1];
+
+    let mut epoch_id_0 = H256::default();
+    epoch_id_0.as_bytes_mut()[0] = 1;
+    let mut state_0 = state_manager.get_state_for_genesis_write();
+    state_0.set(&only_intermediate, vec![1].into()).unwrap();
+    state_0.set(&both, vec![2].into()).unwrap();
+    state_0.compute_state_root().unwrap();
+    state_0.commit(epoch_id_0).unwrap();
+
+    // The state of epoch 1 is written from scratch, so that it doesn't have
+    // the keys of epoch 0.
+    let mut epoch_id_1 = H256::default();
+    epoch_id_1.as_bytes_mut()[0] = 2;
+    let mut state_1 = state_manager.get_state_for_genesis_write();
+    state_1.set(&both, vec![3].into()).unwrap();
+    state_1.set(&only_delta, vec![4].into()).unwrap();
+    state_1.compute_state_root().unwrap();
+    state_1.commit(epoch_id_1).unwrap();
+
+    // Read epoch 0 as the intermediate trie and epoch 1 as the delta trie.
+    let (snapshot, _, _, delta_trie, intermediate_root) = state_manager
+        .get_state_trees(&SnapshotAndEpochIdRef::new(&epoch_id_0, None))
+        .unwrap()
+        .unwrap();
+    let delta_root = state_manager
+        .get_state_trees(&SnapshotAndEpochIdRef::new(&epoch_id_1, None))
+        .unwrap()
+        .unwrap()
+        .4;
+    let state = State::new(
+        &state_manager,
+        (
+            snapshot,
+            Some(delta_trie.clone()),
+            intermediate_root,
+            delta_trie,
+            delta_root,
+        ),
+    );
+
+    // The keys are neither sorted nor unique.
+    let keys: Vec<&[u8]> = vec![
+        &missing[..],
+        &only_delta[..],
+        &both[..],
+        &only_intermediate[..],
+        &both[..],
+        &missing[..],
+    ];
+    let values = state.get_many(&keys).unwrap();
+    let expected: Vec<Option<Box<[u8]>>> = vec![
+        None,
+        Some(vec![4].into()),
+        Some(vec![3].into()),
+        Some(vec![1].into()),
+        Some(vec![3].into()),
+        None,
+    ];
+    assert_eq!(values, expected);
+    for (key, value) in keys.iter().zip(&values) {
+        assert_eq!(&state.get(key).unwrap(), value);
+    }
+    assert!(state.get_many(&[]).unwrap().is_empty());
+}
+
 use super::{
     super::{
         impls::multi_version_merkle_patricia_trie::merkle_patricia_trie::CompressedPathRaw,
         state::*, state_manager::*,
     },
+    new_external_value_state_manager_for_testing,
     new_state_manager_for_testing,
 };
 use cfx_types::H256;
-use primitives::StateRoot;
+use primitives::{StateRoot, StateRootWithAuxInfo, MERKLE_NULL_NODE};
 use rand::{ChaChaRng, Rng, SeedableRng};
 use std::{mem, sync::Arc, thread};
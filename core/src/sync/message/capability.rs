@@ -17,6 +17,13 @@ pub enum DynamicCapability {
     TxRelay(bool),                 // provide tx relay
     ServeHeaders(bool),            // provide block header downloads
     ServeCheckpoint(Option<H256>), // provide checkpoint downloads
+    /// The protocol version negotiated with the peer, which is set at
+    /// handshake and can't be changed by the peer. A required version is
+    /// satisfied by the peers of the same or newer versions.
+    ProtocolVersion(u8),
+    /// The earliest epoch whose block bodies and receipts are served. The
+    /// peers which don't announce it serve all the blocks they have.
+    ServeBlockBodiesFrom(u64),
 }
 
 impl DynamicCapability {
@@ -25,6 +32,8 @@ impl DynamicCapability {
             DynamicCapability::TxRelay(_) => 0,
             DynamicCapability::ServeHeaders(_) => 1,
             DynamicCapability::ServeCheckpoint(_) => 2,
+            DynamicCapability::ProtocolVersion(_) => 3,
+            DynamicCapability::ServeBlockBodiesFrom(_) => 4,
         }
     }
 
@@ -56,6 +65,8 @@ impl Encodable for DynamicCapability {
             DynamicCapability::TxRelay(enabled) => s.append(enabled),
             DynamicCapability::ServeHeaders(enabled) => s.append(enabled),
             DynamicCapability::ServeCheckpoint(cp) => s.append(cp),
+            DynamicCapability::ProtocolVersion(version) => s.append(version),
+            DynamicCapability::ServeBlockBodiesFrom(epoch) => s.append(epoch),
         };
     }
 }
@@ -70,6 +81,8 @@ impl Decodable for DynamicCapability {
             0 => Ok(DynamicCapability::TxRelay(rlp.val_at(1)?)),
             1 => Ok(DynamicCapability::ServeHeaders(rlp.val_at(1)?)),
             2 => Ok(DynamicCapability::ServeCheckpoint(rlp.val_at(1)?)),
+            4 => Ok(DynamicCapability::ServeBlockBodiesFrom(rlp.val_at(1)?)),
+            // The negotiated protocol version isn't announced by peers.
             _ => Err(DecoderError::Custom("invalid capability code")),
         }
     }
@@ -77,7 +90,7 @@ impl Decodable for DynamicCapability {
 
 #[derive(Debug, Default)]
 pub struct DynamicCapabilitySet {
-    caps: [Option<DynamicCapability>; 3],
+    caps: [Option<DynamicCapability>; 5],
 }
 
 impl DynamicCapabilitySet {
@@ -86,9 +99,24 @@ impl DynamicCapabilitySet {
     }
 
     pub fn contains(&self, cap: DynamicCapability) -> bool {
-        match self.caps[cap.code() as usize].as_ref() {
-            Some(cur_cap) => cur_cap == &cap,
-            None => return false,
+        match (self.caps[cap.code() as usize].as_ref(), cap) {
+            (
+                Some(DynamicCapability::ProtocolVersion(version)),
+                DynamicCapability::ProtocolVersion(required),
+            ) => *version >= required,
+            (Some(cur_cap), _) => cur_cap == &cap,
+            (None, _) => false,
+        }
+    }
+
+    /// Whether the block bodies in epoch `epoch_number` are served.
+    pub fn serves_block_bodies_of(&self, epoch_number: u64) -> bool {
+        let code = DynamicCapability::ServeBlockBodiesFrom(0).code();
+        match self.caps[code as usize] {
+            Some(DynamicCapability::ServeBlockBodiesFrom(earliest)) => {
+                earliest <= epoch_number
+            }
+            _ => true,
         }
     }
 }
@@ -114,3 +142,45 @@ impl Handleable for DynamicCapabilityChange {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{DynamicCapability, DynamicCapabilitySet};
+    use rlp::{decode, encode};
+
+    #[test]
+    fn test_protocol_version() {
+        let mut caps = DynamicCapabilitySet::default();
+        assert!(!caps.contains(DynamicCapability::ProtocolVersion(0)));
+
+        caps.insert(DynamicCapability::ProtocolVersion(3));
+        assert!(caps.contains(DynamicCapability::ProtocolVersion(2)));
+        assert!(caps.contains(DynamicCapability::ProtocolVersion(3)));
+        assert!(!caps.contains(DynamicCapability::ProtocolVersion(4)));
+    }
+
+    #[test]
+    fn test_rlp() {
+        let cap = DynamicCapability::ServeBlockBodiesFrom(10);
+        assert_eq!(decode::<DynamicCapability>(&encode(&cap)), Ok(cap));
+        // The negotiated protocol version can't be announced.
+        let cap = DynamicCapability::ProtocolVersion(3);
+        assert!(decode::<DynamicCapability>(&encode(&cap)).is_err());
+    }
+
+    #[test]
+    fn test_serves_block_bodies_of() {
+        let mut caps = DynamicCapabilitySet::default();
+        // The peers which don't announce it serve all block bodies.
+        assert!(caps.serves_block_bodies_of(0));
+
+        caps.insert(DynamicCapability::ServeBlockBodiesFrom(10));
+        assert!(!caps.serves_block_bodies_of(9));
+        assert!(caps.serves_block_bodies_of(10));
+        assert!(caps.serves_block_bodies_of(11));
+
+        // The latest announcement replaces the previous one.
+        caps.insert(DynamicCapability::ServeBlockBodiesFrom(20));
+        assert!(!caps.serves_block_bodies_of(10));
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::{
+    message::MsgId,
+    sync::{message::Context, Error},
+};
+use network::ProtocolId;
+use rlp::Rlp;
+use std::collections::HashMap;
+
+/// Decodes the RLP encoded message of the given id and handles it. Returns
+/// `Ok(false)` if the message id is unknown in the protocol version.
+pub type MessageDecoder = fn(MsgId, &Context, &Rlp) -> Result<bool, Error>;
+
+/// The message decoders of the supported protocol versions. A new protocol
+/// version may change the formats of existing messages or add new messages,
+/// while the messages from the peers that negotiated an older version are
+/// still decoded in the formats of that version.
+#[derive(Default)]
+pub struct MessageCodecRegistry {
+    decoders: HashMap<(ProtocolId, u8), MessageDecoder>,
+}
+
+impl MessageCodecRegistry {
+    pub fn register(
+        &mut self, protocol: ProtocolId, version: u8, decoder: MessageDecoder,
+    ) {
+        self.decoders.insert((protocol, version), decoder);
+    }
+
+    /// The registered versions of `protocol` in ascending order, which are
+    /// announced to peers for version negotiation.
+    pub fn versions(&self, protocol: ProtocolId) -> Vec<u8> {
+        let mut versions: Vec<u8> = self
+            .decoders
+            .keys()
+            .filter(|(p, _)| *p == protocol)
+            .map(|(_, version)| *version)
+            .collect();
+        versions.sort();
+        versions
+    }
+
+    /// Handle a message with the decoder of the protocol version negotiated
+    /// with the peer. Messages of unregistered versions are treated as
+    /// unknown messages.
+    pub fn handle(
+        &self, protocol: ProtocolId, version: u8, msg_id: MsgId, ctx: &Context,
+        rlp: &Rlp,
+    ) -> Result<bool, Error>
+    {
+        match self.decoders.get(&(protocol, version)) {
+            Some(decoder) => decoder(msg_id, ctx, rlp),
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MessageCodecRegistry;
+    use crate::{
+        message::MsgId,
+        sync::{message::Context, Error},
+    };
+    use rlp::Rlp;
+
+    fn decoder(_: MsgId, _: &Context, _: &Rlp) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    #[test]
+    fn test_versions() {
+        let mut registry = MessageCodecRegistry::default();
+        assert!(registry.versions(*b"cfx").is_empty());
+
+        registry.register(*b"cfx", 4, decoder);
+        registry.register(*b"cfx", 2, decoder);
+        registry.register(*b"cfx", 3, decoder);
+        registry.register(*b"clp", 1, decoder);
+        assert_eq!(registry.versions(*b"cfx"), vec![2, 3, 4]);
+        assert_eq!(registry.versions(*b"clp"), vec![1]);
+
+        // Registering a version again replaces its decoder.
+        registry.register(*b"cfx", 3, decoder);
+        assert_eq!(registry.versions(*b"cfx"), vec![2, 3, 4]);
+    }
+}
@@ -10,7 +10,7 @@ use crate::{
             Context, GetBlockHashesResponse, Handleable, Key, KeyContainer,
         },
         request_manager::Request,
-        Error, ProtocolConfiguration,
+        Error, ProtocolConfiguration, SynchronizationPeerState,
     },
 };
 use rlp_derive::{RlpDecodable, RlpEncodable};
@@ -49,6 +49,19 @@ impl Request for GetBlockHashesByEpoch {
     fn resend(&self) -> Option<Box<dyn Request>> {
         Some(Box::new(self.clone()))
     }
+
+    fn is_served_by(&self, peer: &SynchronizationPeerState) -> bool {
+        self.epochs.iter().all(|epoch| *epoch <= peer.best_epoch)
+    }
+
+    fn is_kept_by(&self, peer: &SynchronizationPeerState) -> bool {
+        match peer.checkpoint_epoch {
+            Some(checkpoint) => {
+                self.epochs.iter().all(|epoch| *epoch >= checkpoint)
+            }
+            None => false,
+        }
+    }
 }
 
 impl Handleable for GetBlockHashesByEpoch {
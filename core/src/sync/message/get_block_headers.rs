@@ -4,7 +4,7 @@
 
 use crate::{
     message::{HasRequestId, Message, RequestId},
-    parameters::sync::MAX_HEADERS_TO_SEND,
+    parameters::sync::{ESTIMATED_HEADER_SIZE, MAX_HEADERS_TO_SEND},
     sync::{
         message::{
             Context, GetBlockHeadersResponse, Handleable, Key, KeyContainer,
@@ -32,6 +32,10 @@ impl Request for GetBlockHeaders {
         conf.headers_request_timeout
     }
 
+    fn expected_response_size(&self) -> usize {
+        self.hashes.len() * ESTIMATED_HEADER_SIZE
+    }
+
     fn on_removed(&self, inflight_keys: &KeyContainer) {
         let mut inflight_keys = inflight_keys.write(self.msg_id());
         for hash in self.hashes.iter() {
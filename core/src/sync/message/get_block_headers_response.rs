@@ -4,9 +4,7 @@
 
 use crate::{
     message::RequestId,
-    parameters::{
-        block::ACCEPTABLE_TIME_DRIFT, sync::LOCAL_BLOCK_INFO_QUERY_THRESHOLD,
-    },
+    parameters::sync::LOCAL_BLOCK_INFO_QUERY_THRESHOLD,
     sync::{
         message::{
             metrics::BLOCK_HEADER_HANDLE_TIMER, Context, GetBlockHeaders,
@@ -125,8 +123,11 @@ impl GetBlockHeadersResponse {
                 continue;
             }
             // check timestamp drift
-            if ctx.manager.graph.verification_config.verify_timestamp {
-                if header.timestamp() > now_timestamp + ACCEPTABLE_TIME_DRIFT {
+            let verification_config = &ctx.manager.graph.verification_config;
+            if verification_config.verify_timestamp {
+                if header.timestamp()
+                    > now_timestamp + verification_config.acceptable_time_drift
+                {
                     ctx.manager.future_blocks.insert(header.clone());
                     continue;
                 }
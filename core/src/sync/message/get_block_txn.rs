@@ -4,6 +4,7 @@
 
 use crate::{
     message::{Message, RequestId},
+    parameters::sync::ESTIMATED_TRANSACTION_SIZE,
     sync::{
         message::{Context, GetBlockTxnResponse, Handleable, KeyContainer},
         request_manager::Request,
@@ -30,6 +31,10 @@ impl Request for GetBlockTxn {
         conf.blocks_request_timeout
     }
 
+    fn expected_response_size(&self) -> usize {
+        self.indexes.len() * ESTIMATED_TRANSACTION_SIZE
+    }
+
     fn on_removed(&self, _inflight_keys: &KeyContainer) {}
 
     fn with_inflight(&mut self, _inflight_keys: &KeyContainer) {
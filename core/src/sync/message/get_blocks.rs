@@ -4,26 +4,53 @@
 
 use crate::{
     message::{Message, RequestId},
-    parameters::sync::MAX_PACKET_SIZE,
+    parameters::sync::{ESTIMATED_BLOCK_SIZE, MAX_PACKET_SIZE},
     sync::{
         message::{
             Context, GetBlocksResponse, GetBlocksWithPublicResponse,
-            Handleable, Key, KeyContainer,
+            Handleable, Key, KeyContainer, LazyBlock,
         },
         request_manager::Request,
-        Error, ErrorKind, ProtocolConfiguration,
+        Error, ErrorKind, ProtocolConfiguration, SynchronizationPeerState,
     },
 };
 use cfx_types::H256;
 use primitives::Block;
-use rlp_derive::{RlpDecodable, RlpEncodable};
-use std::{any::Any, time::Duration};
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+use std::{any::Any, sync::Arc, time::Duration};
 
-#[derive(Debug, PartialEq, Default, Clone, RlpDecodable, RlpEncodable)]
+#[derive(Debug, PartialEq, Default, Clone)]
 pub struct GetBlocks {
     pub request_id: RequestId,
     pub with_public: bool,
     pub hashes: Vec<H256>,
+    /// The lowest height of the requested blocks known locally, which is the
+    /// earliest epoch they can be in. It is only used to choose the peers
+    /// serving their bodies and is not sent.
+    pub min_epoch: Option<u64>,
+}
+
+impl Encodable for GetBlocks {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(3)
+            .append(&self.request_id)
+            .append(&self.with_public)
+            .append_list(&self.hashes);
+    }
+}
+
+impl Decodable for GetBlocks {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 3 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        Ok(GetBlocks {
+            request_id: rlp.val_at(0)?,
+            with_public: rlp.val_at(1)?,
+            hashes: rlp.list_at(2)?,
+            min_epoch: None,
+        })
+    }
 }
 
 impl Request for GetBlocks {
@@ -35,6 +62,10 @@ impl Request for GetBlocks {
         conf.blocks_request_timeout
     }
 
+    fn expected_response_size(&self) -> usize {
+        self.hashes.len() * ESTIMATED_BLOCK_SIZE
+    }
+
     fn on_removed(&self, inflight_keys: &KeyContainer) {
         let mut inflight_keys = inflight_keys.write(self.msg_id());
         for hash in self.hashes.iter() {
@@ -52,10 +83,16 @@ impl Request for GetBlocks {
     fn resend(&self) -> Option<Box<dyn Request>> {
         Some(Box::new(self.clone()))
     }
+
+    fn is_served_by(&self, peer: &SynchronizationPeerState) -> bool {
+        self.min_epoch.map_or(true, |epoch| {
+            peer.capabilities.serves_block_bodies_of(epoch)
+        })
+    }
 }
 
 impl GetBlocks {
-    fn get_blocks(&self, ctx: &Context, with_public: bool) -> Vec<Block> {
+    fn get_blocks(&self, ctx: &Context, with_public: bool) -> Vec<Arc<Block>> {
         let mut blocks = Vec::new();
         let mut packet_size_left = MAX_PACKET_SIZE;
 
@@ -69,7 +106,7 @@ impl GetBlocks {
 
                 if packet_size_left >= block_size {
                     packet_size_left -= block_size;
-                    blocks.push(block.as_ref().clone());
+                    blocks.push(block);
                 } else {
                     break;
                 }
@@ -80,11 +117,11 @@ impl GetBlocks {
     }
 
     fn send_response_with_public(
-        &self, ctx: &Context, blocks: Vec<Block>,
+        &self, ctx: &Context, blocks: Vec<Arc<Block>>,
     ) -> Result<(), Error> {
         let mut response = GetBlocksWithPublicResponse {
             request_id: self.request_id,
-            blocks,
+            blocks: blocks.iter().map(|b| b.as_ref().clone()).collect(),
         };
 
         while let Err(e) = ctx.send_response(&response) {
@@ -99,7 +136,7 @@ impl GetBlocks {
         Ok(())
     }
 
-    fn is_oversize_packet_err(e: &Error) -> bool {
+    pub(super) fn is_oversize_packet_err(e: &Error) -> bool {
         match e.kind() {
             ErrorKind::Network(kind) => match kind {
                 network::ErrorKind::OversizedPacket => true,
@@ -110,11 +147,11 @@ impl GetBlocks {
     }
 
     fn send_response(
-        &self, ctx: &Context, blocks: Vec<Block>,
+        &self, ctx: &Context, blocks: Vec<Arc<Block>>,
     ) -> Result<(), Error> {
         let mut response = GetBlocksResponse {
             request_id: self.request_id,
-            blocks,
+            blocks: blocks.into_iter().map(LazyBlock::from).collect(),
         };
 
         while let Err(e) = ctx.send_response(&response) {
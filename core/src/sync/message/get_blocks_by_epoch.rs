@@ -0,0 +1,399 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::{
+    message::{Message, RequestId},
+    parameters::sync::{
+        GET_BLOCKS_BY_EPOCH_PROTOCOL_VERSION, MAX_EPOCHS_TO_SEND,
+        MAX_PACKET_SIZE,
+    },
+    sync::{
+        message::{
+            Context, DynamicCapability, GetBlocks, GetBlocksByEpochResponse,
+            Handleable, Key, KeyContainer, LazyBlock,
+        },
+        request_manager::Request,
+        Error, ProtocolConfiguration, SynchronizationPeerState,
+    },
+};
+use cfx_types::H256;
+use rlp_derive::{RlpDecodable, RlpEncodable};
+use std::{any::Any, cmp, time::Duration};
+
+/// Request all the blocks of the epochs `[from_epoch, to_epoch]`. The
+/// responder sends the blocks in the epoch order, stopping when `max_bytes`
+/// is reached, and returns the position to continue from in the response.
+#[derive(Debug, PartialEq, Clone, RlpDecodable, RlpEncodable)]
+pub struct GetBlocksByEpoch {
+    pub request_id: RequestId,
+    pub from_epoch: u64,
+    /// The index of the first block to send in the blocks of `from_epoch`,
+    /// which is not 0 if the epoch is partially received.
+    pub from_index: u64,
+    pub to_epoch: u64,
+    /// The maximum total size of the blocks in the response.
+    pub max_bytes: u64,
+}
+
+impl Request for GetBlocksByEpoch {
+    fn as_message(&self) -> &dyn Message { self }
+
+    fn as_any(&self) -> &dyn Any { self }
+
+    fn timeout(&self, conf: &ProtocolConfiguration) -> Duration {
+        conf.blocks_request_timeout
+    }
+
+    fn expected_response_size(&self) -> usize { self.max_bytes as usize }
+
+    fn on_removed(&self, inflight_keys: &KeyContainer) {
+        let mut inflight_keys = inflight_keys.write(self.msg_id());
+        for epoch in self.from_epoch..=self.to_epoch {
+            inflight_keys.remove(&Key::Num(epoch));
+        }
+    }
+
+    /// Keep the first range of consecutive epochs not in flight, since the
+    /// requested epochs must be consecutive.
+    fn with_inflight(&mut self, inflight_keys: &KeyContainer) {
+        let mut inflight_keys = inflight_keys.write(self.msg_id());
+        let mut from = self.from_epoch;
+        while from <= self.to_epoch && !inflight_keys.insert(Key::Num(from)) {
+            from += 1;
+        }
+        if from > self.to_epoch {
+            self.from_epoch = from;
+            return;
+        }
+        let mut to = from;
+        while to < self.to_epoch && inflight_keys.insert(Key::Num(to + 1)) {
+            to += 1;
+        }
+        if from != self.from_epoch {
+            self.from_epoch = from;
+            self.from_index = 0;
+        }
+        self.to_epoch = to;
+    }
+
+    fn is_empty(&self) -> bool { self.from_epoch > self.to_epoch }
+
+    fn resend(&self) -> Option<Box<dyn Request>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn required_capability(&self) -> Option<DynamicCapability> {
+        Some(DynamicCapability::ProtocolVersion(
+            GET_BLOCKS_BY_EPOCH_PROTOCOL_VERSION,
+        ))
+    }
+
+    fn is_served_by(&self, peer: &SynchronizationPeerState) -> bool {
+        peer.capabilities
+            .contains(self.required_capability().unwrap())
+            && peer.capabilities.serves_block_bodies_of(self.from_epoch)
+            && self.to_epoch <= peer.best_epoch
+    }
+
+    fn is_kept_by(&self, peer: &SynchronizationPeerState) -> bool {
+        match peer.checkpoint_epoch {
+            Some(checkpoint) => self.from_epoch >= checkpoint,
+            None => false,
+        }
+    }
+}
+
+impl GetBlocksByEpoch {
+    /// Whether `(next_epoch, next_index)` is a valid continuation in the
+    /// response, which must make progress and not go beyond `to_epoch`.
+    pub fn is_valid_continuation(
+        &self, next_epoch: u64, next_index: u64,
+    ) -> bool {
+        if next_epoch == self.from_epoch {
+            next_index > self.from_index
+        } else if next_epoch == self.to_epoch + 1 {
+            next_index == 0
+        } else {
+            next_epoch > self.from_epoch && next_epoch <= self.to_epoch
+        }
+    }
+
+    /// Collect the blocks from `(from_epoch, from_index)` in the epoch order,
+    /// until `max_bytes` or the end of `to_epoch` is reached. `block_by_hash`
+    /// returns the block and its size, and the missing blocks are skipped.
+    ///
+    /// Returns the blocks, the position of each block, and the position of
+    /// the first block not collected.
+    fn collect_blocks<T>(
+        &self, to_epoch: u64, max_bytes: usize,
+        hashes_by_epoch: impl Fn(u64) -> Option<Vec<H256>>,
+        block_by_hash: impl Fn(&H256) -> Option<(T, usize)>,
+    ) -> (Vec<T>, Vec<(u64, u64)>, (u64, u64))
+    {
+        let mut blocks = Vec::new();
+        let mut positions = Vec::new();
+        let mut size_left = max_bytes;
+        let (mut next_epoch, mut next_index) =
+            (self.from_epoch, self.from_index);
+        'epochs: while next_epoch <= to_epoch {
+            let hashes = match hashes_by_epoch(next_epoch) {
+                Some(hashes) => hashes,
+                None => break,
+            };
+            while (next_index as usize) < hashes.len() {
+                let hash = &hashes[next_index as usize];
+                if let Some((block, block_size)) = block_by_hash(hash) {
+                    // Always send at least one block so that the requester
+                    // makes progress.
+                    if block_size > size_left && !blocks.is_empty() {
+                        break 'epochs;
+                    }
+                    size_left = size_left.saturating_sub(block_size);
+                    blocks.push(block);
+                    positions.push((next_epoch, next_index));
+                }
+                next_index += 1;
+            }
+            next_epoch += 1;
+            next_index = 0;
+        }
+        (blocks, positions, (next_epoch, next_index))
+    }
+}
+
+/// Halve `blocks` whose response is oversized, and return the position to
+/// continue from. `positions[i]` is the position of `blocks[i]`. A single
+/// block exceeding the packet size is skipped since it can't be sent at all,
+/// and `None` is returned if there is no block to truncate.
+fn truncate_oversized<T>(
+    blocks: &mut Vec<T>, positions: &[(u64, u64)],
+) -> Option<(u64, u64)> {
+    match blocks.len() {
+        0 => None,
+        1 => {
+            let (epoch, index) = positions[0];
+            blocks.clear();
+            Some((epoch, index + 1))
+        }
+        count => {
+            let block_count = count / 2;
+            blocks.truncate(block_count);
+            Some(positions[block_count])
+        }
+    }
+}
+
+impl Handleable for GetBlocksByEpoch {
+    fn handle(self, ctx: &Context) -> Result<(), Error> {
+        let max_bytes = cmp::min(self.max_bytes as usize, MAX_PACKET_SIZE);
+        let to_epoch = cmp::min(
+            self.to_epoch,
+            self.from_epoch.saturating_add(MAX_EPOCHS_TO_SEND - 1),
+        );
+
+        let graph = &ctx.manager.graph;
+        let (blocks, positions, (next_epoch, next_index)) = self
+            .collect_blocks(
+                to_epoch,
+                max_bytes,
+                |epoch| graph.get_block_hashes_by_epoch(epoch).ok(),
+                |hash| {
+                    graph.block_by_hash(hash).map(|block| {
+                        let block_size = block.approximated_rlp_size();
+                        (LazyBlock::from(block), block_size)
+                    })
+                },
+            );
+
+        let mut response = GetBlocksByEpochResponse {
+            request_id: self.request_id,
+            blocks,
+            next_epoch,
+            next_index,
+        };
+
+        while let Err(e) = ctx.send_response(&response) {
+            if !GetBlocks::is_oversize_packet_err(&e) {
+                return Err(e.into());
+            }
+            if response.blocks.len() == 1 {
+                warn!(
+                    "Skip the block {:?} exceeding the packet size",
+                    response.blocks[0].hash()
+                );
+            }
+            match truncate_oversized(&mut response.blocks, &positions) {
+                Some((epoch, index)) => {
+                    response.next_epoch = epoch;
+                    response.next_index = index;
+                }
+                None => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{truncate_oversized, GetBlocksByEpoch};
+    use crate::sync::{
+        message::{msgid, Key, KeyContainer},
+        request_manager::Request,
+    };
+    use cfx_types::H256;
+
+    fn request(
+        from_epoch: u64, from_index: u64, to_epoch: u64,
+    ) -> GetBlocksByEpoch {
+        GetBlocksByEpoch {
+            request_id: 0,
+            from_epoch,
+            from_index,
+            to_epoch,
+            max_bytes: 0,
+        }
+    }
+
+    /// Epoch `e` has `e + 1` blocks, and the hash of block `i` in epoch `e`
+    /// is `e * 100 + i`. The size of every block is 10 bytes.
+    fn collect(
+        req: &GetBlocksByEpoch, max_bytes: usize,
+    ) -> (Vec<u64>, Vec<(u64, u64)>, (u64, u64)) {
+        req.collect_blocks(
+            req.to_epoch,
+            max_bytes,
+            |epoch| {
+                Some(
+                    (0..=epoch)
+                        .map(|i| H256::from_low_u64_be(epoch * 100 + i))
+                        .collect(),
+                )
+            },
+            |hash| Some((hash.to_low_u64_be(), 10)),
+        )
+    }
+
+    #[test]
+    fn test_collect_blocks_truncated_at_max_bytes() {
+        let req = request(1, 0, 3);
+        let (blocks, positions, next) = collect(&req, 35);
+        assert_eq!(blocks, vec![100, 101, 200]);
+        assert_eq!(positions, vec![(1, 0), (1, 1), (2, 0)]);
+        assert_eq!(next, (2, 1));
+        assert!(req.is_valid_continuation(next.0, next.1));
+
+        // At least one block is sent.
+        let (blocks, _, next) = collect(&req, 5);
+        assert_eq!(blocks, vec![100]);
+        assert_eq!(next, (1, 1));
+
+        // All the blocks fit.
+        let (blocks, _, next) = collect(&req, 1000);
+        assert_eq!(blocks.len(), 9);
+        assert_eq!(next, (4, 0));
+    }
+
+    #[test]
+    fn test_collect_blocks_resumed_at_from_index() {
+        let (blocks, positions, next) = collect(&request(2, 1, 3), 35);
+        assert_eq!(blocks, vec![201, 202, 300]);
+        assert_eq!(positions, vec![(2, 1), (2, 2), (3, 0)]);
+        assert_eq!(next, (3, 1));
+
+        // The rest of the epoch is empty.
+        let (blocks, _, next) = collect(&request(2, 3, 3), 1000);
+        assert_eq!(blocks, vec![300, 301, 302, 303]);
+        assert_eq!(next, (4, 0));
+    }
+
+    #[test]
+    fn test_collect_blocks_missing() {
+        let req = request(1, 0, 3);
+        let (blocks, positions, next) = req.collect_blocks(
+            req.to_epoch,
+            1000,
+            |epoch| {
+                if epoch < 3 {
+                    Some(vec![H256::from_low_u64_be(epoch); 2])
+                } else {
+                    None
+                }
+            },
+            |hash| {
+                let epoch = hash.to_low_u64_be();
+                if epoch == 1 {
+                    None
+                } else {
+                    Some((epoch, 10))
+                }
+            },
+        );
+        // The missing blocks are skipped, and the unavailable epochs stop
+        // the collection.
+        assert_eq!(blocks, vec![2, 2]);
+        assert_eq!(positions, vec![(2, 0), (2, 1)]);
+        assert_eq!(next, (3, 0));
+    }
+
+    #[test]
+    fn test_truncate_oversized() {
+        let positions = vec![(1, 0), (1, 1), (2, 0), (2, 1)];
+        let mut blocks = vec![100, 101, 200, 201];
+        assert_eq!(truncate_oversized(&mut blocks, &positions), Some((2, 0)));
+        assert_eq!(blocks, vec![100, 101]);
+        assert_eq!(truncate_oversized(&mut blocks, &positions), Some((1, 1)));
+        assert_eq!(blocks, vec![100]);
+        // The single block exceeding the packet size is skipped.
+        assert_eq!(truncate_oversized(&mut blocks, &positions), Some((1, 1)));
+        assert!(blocks.is_empty());
+        assert_eq!(truncate_oversized(&mut blocks, &positions), None);
+    }
+
+    #[test]
+    fn test_is_valid_continuation() {
+        let req = request(2, 3, 5);
+        assert!(req.is_valid_continuation(2, 4));
+        assert!(req.is_valid_continuation(3, 0));
+        assert!(req.is_valid_continuation(6, 0));
+        // No progress.
+        assert!(!req.is_valid_continuation(2, 3));
+        // Backwards.
+        assert!(!req.is_valid_continuation(2, 2));
+        assert!(!req.is_valid_continuation(1, 10));
+        // Beyond the requested epochs.
+        assert!(!req.is_valid_continuation(6, 1));
+        assert!(!req.is_valid_continuation(7, 0));
+    }
+
+    #[test]
+    fn test_with_inflight() {
+        let inflight_keys = KeyContainer::default();
+        let mut first = request(1, 2, 3);
+        first.with_inflight(&inflight_keys);
+        assert_eq!(first, request(1, 2, 3));
+
+        // The epochs in flight at the beginning are skipped, and the
+        // position in the first epoch is reset.
+        let mut req = request(2, 1, 6);
+        req.with_inflight(&inflight_keys);
+        assert_eq!(req, request(4, 0, 6));
+
+        // Only the first range of consecutive epochs is kept.
+        first.on_removed(&inflight_keys);
+        req.on_removed(&inflight_keys);
+        inflight_keys
+            .write(msgid::GET_BLOCKS_BY_EPOCH)
+            .insert(Key::Num(3));
+        let mut req = request(1, 1, 5);
+        req.with_inflight(&inflight_keys);
+        assert_eq!(req, request(1, 1, 2));
+
+        // All the epochs are in flight.
+        let mut req = request(2, 0, 3);
+        req.with_inflight(&inflight_keys);
+        assert!(req.is_empty());
+    }
+}
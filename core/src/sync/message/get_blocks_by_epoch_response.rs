@@ -0,0 +1,107 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::{
+    message::RequestId,
+    sync::{
+        message::{
+            metrics::BLOCK_HANDLE_TIMER, Context, GetBlocksByEpoch, Handleable,
+            LazyBlock,
+        },
+        synchronization_protocol_handler::RecoverPublicTask,
+        Error, ErrorKind,
+    },
+};
+use cfx_types::H256;
+use metrics::MeterTimer;
+use rlp_derive::{RlpDecodable, RlpEncodable};
+use std::collections::HashSet;
+
+#[derive(Debug, PartialEq, Default, RlpDecodable, RlpEncodable)]
+pub struct GetBlocksByEpochResponse {
+    pub request_id: RequestId,
+    pub blocks: Vec<LazyBlock>,
+    /// The continuation token, i.e. the position of the first block not
+    /// sent. All the requested blocks are sent if `next_epoch` is larger
+    /// than the requested `to_epoch`.
+    pub next_epoch: u64,
+    pub next_index: u64,
+}
+
+impl Handleable for GetBlocksByEpochResponse {
+    fn handle(self, ctx: &Context) -> Result<(), Error> {
+        let _timer = MeterTimer::time_func(BLOCK_HANDLE_TIMER.as_ref());
+
+        debug!(
+            "on_blocks_by_epoch_response, get block hashes {:?}, next=({}, {})",
+            self.blocks
+                .iter()
+                .map(|b| b.block_header.hash())
+                .collect::<Vec<H256>>(),
+            self.next_epoch,
+            self.next_index,
+        );
+
+        let message = ctx.match_request(self.request_id)?;
+        let req = message.downcast_ref::<GetBlocksByEpoch>(
+            ctx.io,
+            &ctx.manager.request_manager,
+            true,
+        )?;
+
+        // A response making no progress is treated as a mismatch, so that
+        // the request is sent again.
+        if !req.is_valid_continuation(self.next_epoch, self.next_index) {
+            ctx.manager
+                .request_manager
+                .remove_mismatch_request(ctx.io, &message);
+            bail!(ErrorKind::InvalidMessageFormat);
+        }
+
+        // The blocks are not known before they are received, and they are
+        // verified when inserted into the sync graph.
+        let mut blocks = Vec::new();
+        let mut received = HashSet::new();
+        let mut decode_error = None;
+        for lazy_block in self.blocks {
+            let hash = lazy_block.hash();
+            if ctx.manager.graph.contains_block(&hash) {
+                continue;
+            }
+            match lazy_block.into_block() {
+                Ok(block) => {
+                    received.insert(hash);
+                    blocks.push(block);
+                }
+                Err(e) => {
+                    warn!("Failed to decode block {:?}: {:?}", hash, e);
+                    decode_error = Some(e);
+                }
+            }
+        }
+
+        ctx.manager.request_manager.epoch_blocks_received(
+            ctx.io,
+            req,
+            ctx.peer,
+            self.next_epoch,
+            self.next_index,
+        );
+
+        ctx.manager.recover_public_queue.dispatch(
+            ctx.io,
+            RecoverPublicTask::new(blocks, received, ctx.peer, false),
+        );
+
+        // try requesting some more epochs
+        ctx.manager.start_sync(ctx.io);
+
+        // The blocks decoded are still handled, while the peer sending the
+        // malformed ones is penalized.
+        match decode_error {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+}
@@ -7,7 +7,7 @@ use crate::{
     sync::{
         message::{
             metrics::BLOCK_HANDLE_TIMER, Context, GetBlocks, GetCompactBlocks,
-            Handleable,
+            Handleable, LazyBlock,
         },
         synchronization_protocol_handler::RecoverPublicTask,
         Error,
@@ -23,7 +23,7 @@ use std::collections::HashSet;
 #[derive(Debug, PartialEq, Default, RlpDecodable, RlpEncodable)]
 pub struct GetBlocksResponse {
     pub request_id: RequestId,
-    pub blocks: Vec<Block>,
+    pub blocks: Vec<LazyBlock>,
 }
 
 impl Handleable for GetBlocksResponse {
@@ -41,9 +41,9 @@ impl Handleable for GetBlocksResponse {
         for block in &self.blocks {
             debug!("transaction received by block: ratio=1");
             debug!(
-                "new block received: block_header={:?}, tx_count={}, block_size={}",
+                "new block received: block_header={:?}, tx_count={:?}, block_size={}",
                 block.block_header,
-                block.transactions.len(),
+                block.transaction_count(),
                 block.size(),
             );
         }
@@ -60,17 +60,39 @@ impl Handleable for GetBlocksResponse {
             .cloned()
             .collect();
 
+        // Only decode the transactions of blocks that are requested and not
+        // received yet.
+        let mut blocks = Vec::new();
+        let mut decode_error = None;
+        for lazy_block in self.blocks {
+            let hash = lazy_block.hash();
+            if !requested_blocks.contains(&hash) {
+                warn!("Response has not requested block {:?}", hash);
+                continue;
+            }
+            if ctx.manager.graph.contains_block(&hash) {
+                continue;
+            }
+            match lazy_block.into_block() {
+                Ok(block) => blocks.push(block),
+                Err(e) => {
+                    warn!("Failed to decode block {:?}: {:?}", hash, e);
+                    decode_error = Some(e);
+                }
+            }
+        }
+
         ctx.manager.recover_public_queue.dispatch(
             ctx.io,
-            RecoverPublicTask::new(
-                self.blocks,
-                requested_blocks,
-                ctx.peer,
-                false,
-            ),
+            RecoverPublicTask::new(blocks, requested_blocks, ctx.peer, false),
         );
 
-        Ok(())
+        // The blocks decoded are still handled, while the peer sending the
+        // malformed ones is penalized.
+        match decode_error {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
     }
 }
 
@@ -4,24 +4,50 @@
 
 use crate::{
     message::{Message, RequestId},
-    parameters::sync::{MAX_BLOCKS_TO_SEND, MAX_HEADERS_TO_SEND},
+    parameters::sync::{
+        ESTIMATED_COMPACT_BLOCK_SIZE, MAX_BLOCKS_TO_SEND, MAX_HEADERS_TO_SEND,
+    },
     sync::{
         message::{
             msgid, Context, GetBlocks, GetCompactBlocksResponse, Handleable,
             Key, KeyContainer,
         },
         request_manager::Request,
-        Error, ProtocolConfiguration,
+        Error, ProtocolConfiguration, SynchronizationPeerState,
     },
 };
 use cfx_types::H256;
-use rlp_derive::{RlpDecodable, RlpEncodable};
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
 use std::{any::Any, time::Duration};
 
-#[derive(Debug, PartialEq, Default, RlpDecodable, RlpEncodable)]
+#[derive(Debug, PartialEq, Default)]
 pub struct GetCompactBlocks {
     pub request_id: RequestId,
     pub hashes: Vec<H256>,
+    /// The earliest epoch the requested blocks can be in, as in `GetBlocks`,
+    /// which is not sent.
+    pub min_epoch: Option<u64>,
+}
+
+impl Encodable for GetCompactBlocks {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(2)
+            .append(&self.request_id)
+            .append_list(&self.hashes);
+    }
+}
+
+impl Decodable for GetCompactBlocks {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 2 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        Ok(GetCompactBlocks {
+            request_id: rlp.val_at(0)?,
+            hashes: rlp.list_at(1)?,
+            min_epoch: None,
+        })
+    }
 }
 
 impl Request for GetCompactBlocks {
@@ -33,6 +59,10 @@ impl Request for GetCompactBlocks {
         conf.blocks_request_timeout
     }
 
+    fn expected_response_size(&self) -> usize {
+        self.hashes.len() * ESTIMATED_COMPACT_BLOCK_SIZE
+    }
+
     fn on_removed(&self, inflight_keys: &KeyContainer) {
         let mut inflight_keys = inflight_keys.write(msgid::GET_BLOCKS);
         for hash in self.hashes.iter() {
@@ -52,8 +82,15 @@ impl Request for GetCompactBlocks {
             request_id: 0,
             with_public: true,
             hashes: self.hashes.iter().cloned().collect(),
+            min_epoch: self.min_epoch,
         }))
     }
+
+    fn is_served_by(&self, peer: &SynchronizationPeerState) -> bool {
+        self.min_epoch.map_or(true, |epoch| {
+            peer.capabilities.serves_block_bodies_of(epoch)
+        })
+    }
 }
 
 impl Handleable for GetCompactBlocks {
@@ -17,15 +17,20 @@ pub struct Context<'a> {
     pub io: &'a dyn NetworkContext,
     pub peer: PeerId,
     pub manager: &'a SynchronizationProtocolHandler,
+    /// The size of the handled message in bytes.
+    pub message_size: usize,
 }
 
 impl<'a> Context<'a> {
     pub fn match_request(
         &self, request_id: u64,
     ) -> Result<RequestMessage, Error> {
-        self.manager
-            .request_manager
-            .match_request(self.io, self.peer, request_id)
+        self.manager.request_manager.match_request(
+            self.io,
+            self.peer,
+            request_id,
+            self.message_size,
+        )
     }
 
     pub fn send_response(&self, response: &dyn Message) -> Result<(), Error> {
@@ -30,6 +30,7 @@ impl Default for KeyContainer {
             Some(Default::default());
         keys[msgid::GET_BLOCK_HEADERS as usize] = Some(Default::default());
         keys[msgid::GET_BLOCKS as usize] = Some(Default::default());
+        keys[msgid::GET_BLOCKS_BY_EPOCH as usize] = Some(Default::default());
         keys[msgid::GET_TRANSACTIONS as usize] = Some(Default::default());
 
         KeyContainer { keys }
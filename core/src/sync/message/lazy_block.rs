@@ -0,0 +1,124 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use cfx_types::H256;
+use primitives::{
+    Block, BlockHeader, SignedTransaction, TransactionWithSignature,
+};
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+use std::sync::Arc;
+
+/// A block whose header is decoded eagerly while its transactions are kept
+/// as raw RLP until `into_block` is called. It has the same RLP format as
+/// `Block`, so that blocks that are not requested or already received can be
+/// discarded during block floods without materializing their transactions.
+#[derive(Debug, PartialEq)]
+pub struct LazyBlock {
+    pub block_header: BlockHeader,
+    transactions: LazyTransactions,
+}
+
+#[derive(Debug, PartialEq)]
+enum LazyTransactions {
+    /// The raw RLP of the transactions received from a peer.
+    Raw(Vec<u8>),
+    /// The transactions of a local block, which are encoded directly into
+    /// the message when it is sent instead of into an intermediate buffer.
+    Local(Arc<Block>),
+}
+
+impl LazyBlock {
+    pub fn hash(&self) -> H256 { self.block_header.hash() }
+
+    /// The RLP size of the block.
+    pub fn size(&self) -> usize {
+        match &self.transactions {
+            LazyTransactions::Raw(rlp) => {
+                self.block_header.approximated_rlp_size() + rlp.len()
+            }
+            LazyTransactions::Local(block) => block.approximated_rlp_size(),
+        }
+    }
+
+    pub fn transaction_count(&self) -> Result<usize, DecoderError> {
+        match &self.transactions {
+            LazyTransactions::Raw(rlp) => Rlp::new(rlp).item_count(),
+            LazyTransactions::Local(block) => Ok(block.transactions.len()),
+        }
+    }
+
+    /// Decode the transactions and build the `Block`. As with `Block::decode`,
+    /// the public keys of transactions are not recovered.
+    pub fn into_block(self) -> Result<Block, DecoderError> {
+        let rlp_size = self.size();
+        let transactions_rlp = match self.transactions {
+            LazyTransactions::Raw(rlp) => rlp,
+            LazyTransactions::Local(block) => {
+                return Ok(Arc::try_unwrap(block)
+                    .unwrap_or_else(|block| block.as_ref().clone()));
+            }
+        };
+
+        let transactions = Rlp::new(&transactions_rlp)
+            .as_list::<TransactionWithSignature>()?
+            .into_iter()
+            .map(|tx| Arc::new(SignedTransaction::new_unsigned(tx)))
+            .collect();
+
+        Ok(Block::new_with_rlp_size(
+            self.block_header,
+            transactions,
+            Some(rlp_size),
+            None,
+        ))
+    }
+}
+
+impl From<Arc<Block>> for LazyBlock {
+    fn from(block: Arc<Block>) -> Self {
+        LazyBlock {
+            block_header: block.block_header.clone(),
+            transactions: LazyTransactions::Local(block),
+        }
+    }
+}
+
+impl Encodable for LazyBlock {
+    fn rlp_append(&self, stream: &mut RlpStream) {
+        match &self.transactions {
+            LazyTransactions::Raw(rlp) => {
+                stream
+                    .begin_list(2)
+                    .append(&self.block_header)
+                    .append_raw(rlp, 1);
+            }
+            LazyTransactions::Local(block) => {
+                stream.append(block.as_ref());
+            }
+        }
+    }
+}
+
+impl Decodable for LazyBlock {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        if rlp.as_raw().len() != rlp.payload_info()?.total() {
+            return Err(DecoderError::RlpIsTooBig);
+        }
+        if rlp.item_count()? != 2 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+
+        let transactions = rlp.at(1)?;
+        if !transactions.is_list() {
+            return Err(DecoderError::RlpExpectedToBeList);
+        }
+
+        Ok(LazyBlock {
+            block_header: rlp.val_at(0)?,
+            transactions: LazyTransactions::Raw(
+                transactions.as_raw().to_vec(),
+            ),
+        })
+    }
+}
@@ -51,6 +51,8 @@ build_msgid! {
 
     GET_CHECKPOINT_BLAME_STATE_REQUEST = 0x1d
     GET_CHECKPOINT_BLAME_STATE_RESPONSE = 0x1e
+    GET_BLOCKS_BY_EPOCH = 0x1f
+    GET_BLOCKS_BY_EPOCH_RESPONSE = 0x20
 
     INVALID = 0xff
 }
@@ -71,6 +73,7 @@ build_msg_impl! { GetCompactBlocksResponse, msgid::GET_CMPCT_BLOCKS_RESPONSE, "G
 build_msg_impl! { GetBlockTxn, msgid::GET_BLOCK_TXN, "GetBlockTxn" }
 build_msg_impl! { DynamicCapabilityChange, msgid::DYNAMIC_CAPABILITY_CHANGE, "DynamicCapabilityChange" }
 build_msg_impl! { GetBlockHashesByEpoch, msgid::GET_BLOCK_HASHES_BY_EPOCH, "GetBlockHashesByEpoch" }
+build_msg_impl! { GetBlocksByEpoch, msgid::GET_BLOCKS_BY_EPOCH, "GetBlocksByEpoch" }
 
 // normal priority and size-sensitive message types
 impl Message for Transactions {
@@ -103,6 +106,16 @@ impl Message for GetBlocksWithPublicResponse {
     fn is_size_sensitive(&self) -> bool { self.blocks.len() > 0 }
 }
 
+impl Message for GetBlocksByEpochResponse {
+    fn as_any(&self) -> &dyn Any { self }
+
+    fn msg_id(&self) -> MsgId { msgid::GET_BLOCKS_BY_EPOCH_RESPONSE }
+
+    fn msg_name(&self) -> &'static str { "GetBlocksByEpochResponse" }
+
+    fn is_size_sensitive(&self) -> bool { self.blocks.len() > 0 }
+}
+
 impl Message for GetBlockTxnResponse {
     fn as_any(&self) -> &dyn Any { self }
 
@@ -122,7 +135,7 @@ impl Message for TransactionDigests {
 
     fn is_size_sensitive(&self) -> bool { self.len() > 1 }
 
-    fn priority(&self) -> SendQueuePriority { SendQueuePriority::Normal }
+    fn priority(&self) -> SendQueuePriority { SendQueuePriority::Low }
 }
 
 impl Message for GetTransactions {
@@ -152,6 +165,7 @@ build_has_request_id_impl! { GetBlockHashesByEpoch }
 build_has_request_id_impl! { GetBlockHeaders }
 build_has_request_id_impl! { GetBlockHeadersResponse }
 build_has_request_id_impl! { GetBlocks }
+build_has_request_id_impl! { GetBlocksByEpoch }
 build_has_request_id_impl! { GetBlockTxn }
 build_has_request_id_impl! { GetCompactBlocks }
 build_has_request_id_impl! { GetTransactions }
@@ -182,6 +196,12 @@ pub fn handle_rlp_message(
         msgid::GET_BLOCKS_WITH_PUBLIC_RESPONSE => {
             rlp.as_val::<GetBlocksWithPublicResponse>()?.handle(&ctx)?;
         }
+        msgid::GET_BLOCKS_BY_EPOCH => {
+            rlp.as_val::<GetBlocksByEpoch>()?.handle(&ctx)?;
+        }
+        msgid::GET_BLOCKS_BY_EPOCH_RESPONSE => {
+            rlp.as_val::<GetBlocksByEpochResponse>()?.handle(&ctx)?;
+        }
         msgid::GET_TERMINAL_BLOCK_HASHES => {
             rlp.as_val::<GetTerminalBlockHashes>()?.handle(&ctx)?;
         }
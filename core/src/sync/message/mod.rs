@@ -3,6 +3,7 @@
 // See http://www.gnu.org/licenses/
 
 mod capability;
+mod codec;
 mod get_block_hashes_by_epoch;
 mod get_block_hashes_response;
 mod get_block_headers;
@@ -10,6 +11,8 @@ mod get_block_headers_response;
 mod get_block_txn;
 mod get_block_txn_response;
 mod get_blocks;
+mod get_blocks_by_epoch;
+mod get_blocks_by_epoch_response;
 mod get_blocks_response;
 mod get_compact_blocks;
 mod get_compact_blocks_response;
@@ -17,6 +20,7 @@ mod get_terminal_block_hashes;
 mod get_terminal_block_hashes_response;
 mod handleable;
 mod keys;
+mod lazy_block;
 mod message;
 mod metrics;
 mod new_block;
@@ -28,6 +32,7 @@ pub use self::{
     capability::{
         DynamicCapability, DynamicCapabilityChange, DynamicCapabilitySet,
     },
+    codec::{MessageCodecRegistry, MessageDecoder},
     get_block_hashes_by_epoch::GetBlockHashesByEpoch,
     get_block_hashes_response::GetBlockHashesResponse,
     get_block_headers::GetBlockHeaders,
@@ -35,6 +40,8 @@ pub use self::{
     get_block_txn::GetBlockTxn,
     get_block_txn_response::GetBlockTxnResponse,
     get_blocks::GetBlocks,
+    get_blocks_by_epoch::GetBlocksByEpoch,
+    get_blocks_by_epoch_response::GetBlocksByEpochResponse,
     get_blocks_response::{GetBlocksResponse, GetBlocksWithPublicResponse},
     get_compact_blocks::GetCompactBlocks,
     get_compact_blocks_response::GetCompactBlocksResponse,
@@ -42,6 +49,7 @@ pub use self::{
     get_terminal_block_hashes_response::GetTerminalBlockHashesResponse,
     handleable::{Context, Handleable},
     keys::{Key, KeyContainer},
+    lazy_block::LazyBlock,
     message::{handle_rlp_message, msgid},
     new_block::NewBlock,
     new_block_hashes::NewBlockHashes,
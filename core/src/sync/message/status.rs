@@ -2,23 +2,109 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
-use crate::sync::{
-    message::{
-        handleable::{Context, Handleable},
-        DynamicCapability,
+use crate::{
+    parameters::sync::{
+        CHAIN_ID_PROTOCOL_VERSION, SERVE_BLOCK_BODIES_FROM_PROTOCOL_VERSION,
+        SYNCHRONIZATION_PROTOCOL_OLDEST_VERSION,
+    },
+    sync::{
+        message::{
+            handleable::{Context, Handleable},
+            DynamicCapability,
+        },
+        Error, ErrorKind, SynchronizationPeerState,
     },
-    Error, ErrorKind, SynchronizationPeerState,
 };
 use cfx_types::H256;
-use rlp_derive::{RlpDecodable, RlpEncodable};
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
 use std::{collections::HashSet, time::Instant};
 
-#[derive(Debug, PartialEq, RlpDecodable, RlpEncodable)]
+/// The sync state of a node, which is sent at handshake and then periodically
+/// as the heartbeat.
+#[derive(Debug, PartialEq)]
 pub struct Status {
+    /// The newest protocol version supported by the sender.
     pub protocol_version: u8,
     pub genesis_hash: H256,
+    /// The chain id of the sender, `None` if sent by the nodes older than
+    /// `CHAIN_ID_PROTOCOL_VERSION`.
+    pub chain_id: Option<u64>,
     pub best_epoch: u64,
     pub terminal_block_hashes: Vec<H256>,
+    /// The genesis of the current era of the sender, `None` if sent by the
+    /// nodes which don't exchange it.
+    pub checkpoint_hash: Option<H256>,
+    /// The earliest epoch whose block bodies are served by the sender.
+    pub earliest_block_body_epoch: u64,
+}
+
+impl Status {
+    /// The network layer negotiates the highest protocol version announced
+    /// by both sides, which shouldn't be newer than the sender supports or
+    /// older than we support.
+    fn negotiated_protocol_version(
+        &self, negotiated: Option<u8>,
+    ) -> Option<u8> {
+        negotiated.filter(|version| {
+            *version >= SYNCHRONIZATION_PROTOCOL_OLDEST_VERSION
+                && *version <= self.protocol_version
+        })
+    }
+}
+
+impl Encodable for Status {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(if self.chain_id.is_some() { 7 } else { 6 })
+            .append(&self.protocol_version)
+            .append(&self.genesis_hash);
+        if let Some(chain_id) = self.chain_id {
+            s.append(&chain_id);
+        }
+        s.append(&self.best_epoch)
+            .append_list(&self.terminal_block_hashes)
+            .append(&self.checkpoint_hash.unwrap_or_default())
+            .append(&self.earliest_block_body_epoch);
+    }
+}
+
+impl Decodable for Status {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        let protocol_version: u8 = rlp.val_at(0)?;
+        // The chain id follows the genesis hash since
+        // `CHAIN_ID_PROTOCOL_VERSION`.
+        let (chain_id, offset) =
+            if protocol_version >= CHAIN_ID_PROTOCOL_VERSION {
+                (Some(rlp.val_at(2)?), 3)
+            } else {
+                (None, 2)
+            };
+        // The older nodes don't send the last 2 fields.
+        let (checkpoint_hash, earliest_block_body_epoch) =
+            match rlp.item_count()?.checked_sub(offset) {
+                Some(2) => (None, 0),
+                Some(4) => {
+                    let checkpoint_hash: H256 = rlp.val_at(offset + 2)?;
+                    (
+                        if checkpoint_hash.is_zero() {
+                            None
+                        } else {
+                            Some(checkpoint_hash)
+                        },
+                        rlp.val_at(offset + 3)?,
+                    )
+                }
+                _ => return Err(DecoderError::RlpIncorrectListLen),
+            };
+        Ok(Status {
+            protocol_version,
+            genesis_hash: rlp.val_at(1)?,
+            chain_id,
+            best_epoch: rlp.val_at(offset)?,
+            terminal_block_hashes: rlp.list_at(offset + 1)?,
+            checkpoint_hash,
+            earliest_block_body_epoch,
+        })
+    }
 }
 
 impl Handleable for Status {
@@ -34,13 +120,28 @@ impl Handleable for Status {
             return Err(ErrorKind::Invalid.into());
         }
 
+        let chain_id = ctx.manager.protocol_config.chain_id;
+        if let Some(peer_chain_id) = self.chain_id {
+            if chain_id != peer_chain_id {
+                debug!(
+                    "Peer {:?} chain id mismatches (ours: {}, theirs: {})",
+                    ctx.peer, chain_id, peer_chain_id
+                );
+                return Err(ErrorKind::Invalid.into());
+            }
+        }
+
         let mut latest: HashSet<H256> =
             self.terminal_block_hashes.iter().cloned().collect();
+        // The era genesis is a pivot block, so its epoch is its height.
+        let checkpoint_epoch = self.checkpoint_hash.and_then(|hash| {
+            ctx.manager.graph.block_height_by_hash(&hash)
+        });
 
         if let Ok(peer_info) = ctx.manager.syn.get_peer_info(&ctx.peer) {
             let latest_updated = {
                 let mut peer_info = peer_info.write();
-                if peer_info.protocol_version != self.protocol_version {
+                if peer_info.protocol_version > self.protocol_version {
                     warn!("Protocol versions do not match");
                     return Err(ErrorKind::Invalid.into());
                 }
@@ -48,6 +149,15 @@ impl Handleable for Status {
 
                 let updated = self.best_epoch != peer_info.best_epoch
                     || latest != peer_info.latest_block_hashes;
+                peer_info.checkpoint_hash = self.checkpoint_hash;
+                peer_info.checkpoint_epoch = checkpoint_epoch;
+                if self.earliest_block_body_epoch > 0 {
+                    peer_info.capabilities.insert(
+                        DynamicCapability::ServeBlockBodiesFrom(
+                            self.earliest_block_body_epoch,
+                        ),
+                    );
+                }
 
                 // NOTE: we need to update best_epoch even if it's smaller than
                 // the previous value, otherwise sync will get stuck in tests
@@ -69,16 +179,32 @@ impl Handleable for Status {
                 return Err(ErrorKind::UnknownPeer.into());
             }
 
+            let negotiated = ctx.io.get_protocol_version(ctx.peer);
+            let protocol_version = match self
+                .negotiated_protocol_version(negotiated)
+            {
+                Some(version) => version,
+                None => {
+                    debug!(
+                        "Peer {:?} negotiated unsupported protocol version {:?}, newest version {}",
+                        ctx.peer, negotiated, self.protocol_version
+                    );
+                    return Err(ErrorKind::Invalid.into());
+                }
+            };
+
             latest.extend(
                 ctx.manager.graph.initial_missed_block_hashes.lock().drain(),
             );
 
             let mut peer_state = SynchronizationPeerState {
                 id: ctx.peer,
-                protocol_version: self.protocol_version,
+                protocol_version,
                 genesis_hash,
                 best_epoch: self.best_epoch,
                 latest_block_hashes: latest,
+                checkpoint_hash: self.checkpoint_hash,
+                checkpoint_epoch,
                 received_transaction_count: 0,
                 heartbeat: Instant::now(),
                 capabilities: Default::default(),
@@ -88,19 +214,140 @@ impl Handleable for Status {
             peer_state
                 .capabilities
                 .insert(DynamicCapability::TxRelay(true));
+            peer_state
+                .capabilities
+                .insert(DynamicCapability::ProtocolVersion(protocol_version));
+            if self.earliest_block_body_epoch > 0 {
+                peer_state.capabilities.insert(
+                    DynamicCapability::ServeBlockBodiesFrom(
+                        self.earliest_block_body_epoch,
+                    ),
+                );
+            }
 
             debug!(
                 "New peer (pv={:?}, gh={:?})",
-                self.protocol_version, self.genesis_hash
+                protocol_version, self.genesis_hash
             );
 
             debug!("Peer {:?} connected", ctx.peer);
             ctx.manager.syn.peer_connected(ctx.peer, peer_state);
             ctx.manager.request_manager.on_peer_connected(ctx.peer);
 
+            // Tell the new peer not to request the pruned block bodies.
+            let earliest_block_body_epoch =
+                ctx.manager.graph.data_man.earliest_block_body_epoch();
+            if earliest_block_body_epoch > 0
+                && protocol_version >= SERVE_BLOCK_BODIES_FROM_PROTOCOL_VERSION
+            {
+                DynamicCapability::ServeBlockBodiesFrom(
+                    earliest_block_body_epoch,
+                )
+                .broadcast_with_peers(ctx.io, vec![ctx.peer]);
+            }
+
             ctx.manager.start_sync(ctx.io);
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Status;
+    use crate::parameters::sync::{
+        CHAIN_ID_PROTOCOL_VERSION, SYNCHRONIZATION_PROTOCOL_OLDEST_VERSION,
+        SYNCHRONIZATION_PROTOCOL_VERSION,
+    };
+    use cfx_types::H256;
+    use rlp::RlpStream;
+
+    fn status(protocol_version: u8) -> Status {
+        Status {
+            protocol_version,
+            genesis_hash: H256::zero(),
+            chain_id: Some(0),
+            best_epoch: 0,
+            terminal_block_hashes: vec![],
+            checkpoint_hash: None,
+            earliest_block_body_epoch: 0,
+        }
+    }
+
+    #[test]
+    fn test_negotiated_protocol_version() {
+        let newest = SYNCHRONIZATION_PROTOCOL_VERSION;
+        let oldest = SYNCHRONIZATION_PROTOCOL_OLDEST_VERSION;
+        let status = status(newest);
+
+        assert_eq!(
+            status.negotiated_protocol_version(Some(newest)),
+            Some(newest)
+        );
+        assert_eq!(
+            status.negotiated_protocol_version(Some(oldest)),
+            Some(oldest)
+        );
+        // Not negotiated by the network layer.
+        assert_eq!(status.negotiated_protocol_version(None), None);
+        // Older than we support.
+        assert_eq!(status.negotiated_protocol_version(Some(oldest - 1)), None);
+        // Newer than the peer announced.
+        assert_eq!(status.negotiated_protocol_version(Some(newest + 1)), None);
+    }
+
+    #[test]
+    fn test_negotiated_protocol_version_older_peer() {
+        let oldest = SYNCHRONIZATION_PROTOCOL_OLDEST_VERSION;
+        let status = status(oldest);
+
+        assert_eq!(
+            status.negotiated_protocol_version(Some(oldest)),
+            Some(oldest)
+        );
+        assert_eq!(
+            status.negotiated_protocol_version(Some(
+                SYNCHRONIZATION_PROTOCOL_VERSION + 1
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rlp() {
+        let mut status = status(SYNCHRONIZATION_PROTOCOL_VERSION);
+        status.chain_id = Some(7);
+        status.best_epoch = 10;
+        status.terminal_block_hashes = vec![H256::from_low_u64_be(1)];
+        status.checkpoint_hash = Some(H256::from_low_u64_be(2));
+        status.earliest_block_body_epoch = 3;
+        assert_eq!(rlp::decode::<Status>(&rlp::encode(&status)), Ok(status));
+    }
+
+    #[test]
+    fn test_rlp_without_chain_id() {
+        let version = CHAIN_ID_PROTOCOL_VERSION - 1;
+        let mut s = RlpStream::new_list(4);
+        s.append(&version)
+            .append(&H256::zero())
+            .append(&10u64)
+            .append_list(&[H256::from_low_u64_be(1)]);
+        let decoded = rlp::decode::<Status>(&s.out()).unwrap();
+        assert_eq!(decoded.protocol_version, version);
+        assert_eq!(decoded.chain_id, None);
+        assert_eq!(decoded.best_epoch, 10);
+        assert_eq!(
+            decoded.terminal_block_hashes,
+            vec![H256::from_low_u64_be(1)]
+        );
+
+        // The chain id is required since `CHAIN_ID_PROTOCOL_VERSION`.
+        let mut s = RlpStream::new_list(4);
+        s.append(&CHAIN_ID_PROTOCOL_VERSION)
+            .append(&H256::zero())
+            .append(&10u64)
+            .append_list(&[H256::from_low_u64_be(1)]);
+        assert!(rlp::decode::<Status>(&s.out()).is_err());
+    }
+}
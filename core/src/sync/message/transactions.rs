@@ -4,6 +4,7 @@
 
 use crate::{
     message::{Message, RequestId},
+    parameters::sync::ESTIMATED_TRANSACTION_SIZE,
     sync::{
         message::{
             metrics::TX_HANDLE_TIMER, Context, DynamicCapability, Handleable,
@@ -216,6 +217,10 @@ impl Request for GetTransactions {
         conf.transaction_request_timeout
     }
 
+    fn expected_response_size(&self) -> usize {
+        (self.indices.len() + self.tx_ids.len()) * ESTIMATED_TRANSACTION_SIZE
+    }
+
     fn on_removed(&self, inflight_keys: &KeyContainer) {
         let mut inflight_keys = inflight_keys.write(self.msg_id());
         for tx_id in self.tx_ids.iter() {
@@ -18,6 +18,7 @@ pub mod utils;
 
 pub use self::{
     error::{Error, ErrorKind},
+    message::{GetBlocksResponse, LazyBlock},
     state::RangedManifest,
     synchronization_graph::{
         SharedSynchronizationGraph, SyncGraphStatistics, SynchronizationGraph,
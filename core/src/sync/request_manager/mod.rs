@@ -1,14 +1,16 @@
 use super::{
     synchronization_protocol_handler::ProtocolConfiguration,
-    synchronization_state::SynchronizationState,
+    synchronization_state::{SynchronizationPeerState, SynchronizationState},
 };
 use crate::{
-    parameters::sync::REQUEST_START_WAITING_TIME,
+    parameters::sync::{
+        BLOCKS_BY_EPOCH_RESPONSE_MAX_BYTES, REQUEST_START_WAITING_TIME,
+    },
     sync::{
         message::{
             msgid, GetBlockHashesByEpoch, GetBlockHeaders, GetBlockTxn,
-            GetBlocks, GetCompactBlocks, GetTransactions, Key, KeyContainer,
-            TransactionDigests,
+            GetBlocks, GetBlocksByEpoch, GetCompactBlocks, GetTransactions,
+            Key, KeyContainer, TransactionDigests,
         },
         Error,
     },
@@ -106,9 +108,17 @@ impl RequestManager {
     }
 
     pub fn num_epochs_in_flight(&self) -> u64 {
-        self.inflight_keys
+        (self
+            .inflight_keys
             .read(msgid::GET_BLOCK_HASHES_BY_EPOCH)
-            .len() as u64
+            .len()
+            + self.inflight_keys.read(msgid::GET_BLOCKS_BY_EPOCH).len())
+            as u64
+    }
+
+    /// The number of in-flight items of the given request type.
+    pub fn num_inflight_keys(&self, msg_type: u8) -> usize {
+        self.inflight_keys.read(msg_type).len()
     }
 
     /// Send request to remote peer with delay mechanism. If failed,
@@ -191,9 +201,28 @@ impl RequestManager {
         self.request_with_delay(io, Box::new(request), peer_id, None);
     }
 
+    /// Request all the blocks of the epochs `[from_epoch, to_epoch]`.
+    pub fn request_blocks_by_epoch(
+        &self, io: &dyn NetworkContext, peer_id: Option<PeerId>,
+        from_epoch: u64, to_epoch: u64,
+    )
+    {
+        let _timer = MeterTimer::time_func(REQUEST_MANAGER_TIMER.as_ref());
+
+        let request = GetBlocksByEpoch {
+            request_id: 0,
+            from_epoch,
+            from_index: 0,
+            to_epoch,
+            max_bytes: BLOCKS_BY_EPOCH_RESPONSE_MAX_BYTES,
+        };
+
+        self.request_with_delay(io, Box::new(request), peer_id, None);
+    }
+
     pub fn request_blocks(
         &self, io: &dyn NetworkContext, peer_id: Option<PeerId>,
-        hashes: Vec<H256>, with_public: bool,
+        hashes: Vec<H256>, with_public: bool, min_epoch: Option<u64>,
     )
     {
         let _timer = MeterTimer::time_func(REQUEST_MANAGER_TIMER.as_ref());
@@ -202,6 +231,7 @@ impl RequestManager {
             request_id: 0,
             with_public,
             hashes,
+            min_epoch,
         };
 
         self.request_with_delay(io, Box::new(request), peer_id, None);
@@ -282,7 +312,7 @@ impl RequestManager {
 
     pub fn request_compact_blocks(
         &self, io: &dyn NetworkContext, peer_id: Option<PeerId>,
-        hashes: Vec<H256>,
+        hashes: Vec<H256>, min_epoch: Option<u64>,
     )
     {
         let _timer = MeterTimer::time_func(REQUEST_MANAGER_TIMER.as_ref());
@@ -290,6 +320,7 @@ impl RequestManager {
         let request = GetCompactBlocks {
             request_id: 0,
             hashes,
+            min_epoch,
         };
 
         self.request_with_delay(io, Box::new(request), peer_id, None);
@@ -311,14 +342,37 @@ impl RequestManager {
         self.request_with_delay(io, Box::new(request), Some(peer_id), None);
     }
 
+    /// Choose a random peer serving `request` that satisfies `predicate`,
+    /// preferring the peers that keep all the requested items and then the
+    /// peers that are not congested.
+    fn choose_peer<F>(
+        &self, io: &dyn NetworkContext, request: &dyn Request, predicate: F,
+    ) -> Option<PeerId>
+    where F: Fn(&SynchronizationPeerState) -> bool {
+        let choose = |kept_only: bool| {
+            let peers = self.syn.get_random_peers_satisfying(
+                usize::max_value(),
+                |peer| {
+                    predicate(peer)
+                        && request.is_served_by(peer)
+                        && (!kept_only || request.is_kept_by(peer))
+                },
+            );
+            peers
+                .iter()
+                .find(|peer| !io.is_peer_congested(**peer))
+                .or_else(|| peers.first())
+                .cloned()
+        };
+        choose(true).or_else(|| choose(false))
+    }
+
     pub fn send_request_again(
         &self, io: &dyn NetworkContext, msg: &RequestMessage,
     ) {
         debug!("send_request_again, request={:?}", msg.request);
         if let Some(request) = msg.request.resend() {
-            let chosen_peer = self
-                .syn
-                .get_random_peer_with_cap(request.required_capability());
+            let chosen_peer = self.choose_peer(io, &*request, |_| true);
             debug!("send_request_again with new request, peer={:?}, new request={:?}", chosen_peer, request);
             self.request_with_delay(io, request, chosen_peer, msg.delay);
         }
@@ -331,12 +385,19 @@ impl RequestManager {
         self.send_request_again(io, req);
     }
 
-    // Match request with given response.
+    // Match request with given response of `response_size` bytes.
     // No need to let caller handle request resending.
     pub fn match_request(
         &self, io: &dyn NetworkContext, peer_id: PeerId, request_id: u64,
-    ) -> Result<RequestMessage, Error> {
-        self.request_handler.match_request(io, peer_id, request_id)
+        response_size: usize,
+    ) -> Result<RequestMessage, Error>
+    {
+        self.request_handler.match_request(
+            io,
+            peer_id,
+            request_id,
+            Some(response_size),
+        )
     }
 
     /// Remove inflight keys when a header is received.
@@ -377,8 +438,12 @@ impl RequestManager {
             missing_headers
         };
         if !missing_headers.is_empty() {
-            let chosen_peer = self.syn.get_random_peer(&HashSet::new());
-            self.request_block_headers(io, chosen_peer, missing_headers);
+            let request = GetBlockHeaders {
+                request_id: 0,
+                hashes: missing_headers,
+            };
+            let chosen_peer = self.choose_peer(io, &request, |_| true);
+            self.request_with_delay(io, Box::new(request), chosen_peer, None);
         }
     }
 
@@ -415,9 +480,40 @@ impl RequestManager {
             missing_epochs
         };
         if !missing_epochs.is_empty() {
-            let chosen_peer = self.syn.get_random_peer(&HashSet::new());
-            self.request_epoch_hashes(io, chosen_peer, missing_epochs);
+            let request = GetBlockHashesByEpoch {
+                request_id: 0,
+                epochs: missing_epochs,
+            };
+            let chosen_peer = self.choose_peer(io, &request, |_| true);
+            self.request_with_delay(io, Box::new(request), chosen_peer, None);
+        }
+    }
+
+    /// Remove the epochs of `req` from inflight keys when its response is
+    /// received, and request the rest of the epochs from `peer` with the
+    /// continuation `(next_epoch, next_index)`, which makes progress.
+    pub fn epoch_blocks_received(
+        &self, io: &dyn NetworkContext, req: &GetBlocksByEpoch, peer: PeerId,
+        next_epoch: u64, next_index: u64,
+    )
+    {
+        debug!(
+            "epoch_blocks_received: req={:?} next=({}, {})",
+            req, next_epoch, next_index
+        );
+        req.on_removed(&self.inflight_keys);
+        if next_epoch > req.to_epoch {
+            return;
         }
+
+        let request = GetBlocksByEpoch {
+            request_id: 0,
+            from_epoch: next_epoch,
+            from_index: next_index,
+            to_epoch: req.to_epoch,
+            max_bytes: req.max_bytes,
+        };
+        self.request_with_delay(io, Box::new(request), Some(peer), None);
     }
 
     /// Remove from inflight keys when a block is received.
@@ -429,7 +525,7 @@ impl RequestManager {
     pub fn blocks_received(
         &self, io: &dyn NetworkContext, req_hashes: HashSet<H256>,
         mut received_blocks: HashSet<H256>, ask_full_block: bool,
-        peer: Option<PeerId>, with_public: bool,
+        peer: Option<PeerId>, with_public: bool, min_epoch: Option<u64>,
     )
     {
         let _timer = MeterTimer::time_func(REQUEST_MANAGER_TIMER.as_ref());
@@ -463,18 +559,23 @@ impl RequestManager {
             // and a full block is reconstructed, but the full block
             // is incorrect. We should ask the same peer for the
             // full block instead of choosing a random peer.
-            let chosen_peer =
-                peer.or_else(|| self.syn.get_random_peer(&HashSet::new()));
-            if ask_full_block {
-                self.request_blocks(
-                    io,
-                    chosen_peer,
-                    missing_blocks,
+            let request: Box<dyn Request> = if ask_full_block {
+                Box::new(GetBlocks {
+                    request_id: 0,
                     with_public,
-                );
+                    hashes: missing_blocks,
+                    min_epoch,
+                })
             } else {
-                self.request_compact_blocks(io, chosen_peer, missing_blocks);
-            }
+                Box::new(GetCompactBlocks {
+                    request_id: 0,
+                    hashes: missing_blocks,
+                    min_epoch,
+                })
+            };
+            let chosen_peer =
+                peer.or_else(|| self.choose_peer(io, &*request, |_| true));
+            self.request_with_delay(io, request, chosen_peer, None);
         }
     }
 
@@ -546,11 +647,9 @@ impl RequestManager {
                 break;
             }
 
-            let maybe_peer = req.peer.or_else(|| {
-                self.syn.get_random_peer_with_cap(
-                    req.request.0.required_capability(),
-                )
-            });
+            let maybe_peer = req
+                .peer
+                .or_else(|| self.choose_peer(io, &*req.request.0, |_| true));
             let chosen_peer = match maybe_peer {
                 Some(p) => p,
                 None => {
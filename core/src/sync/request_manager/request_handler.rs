@@ -1,18 +1,21 @@
 use crate::{
     message::{HasRequestId, Message},
+    parameters::sync::{
+        REQUEST_TIMEOUT_RTT_MULTIPLIER, THROUGHPUT_SAMPLE_MIN_BYTES,
+    },
     sync::{
         message::{DynamicCapability, KeyContainer},
         msg_sender::send_message,
         request_manager::RequestManager,
         synchronization_protocol_handler::ProtocolConfiguration,
-        Error, ErrorKind,
+        Error, ErrorKind, SynchronizationPeerState,
     },
 };
 use network::{NetworkContext, PeerId, UpdateNodeOperation};
 use parking_lot::Mutex;
 use std::{
     any::Any,
-    cmp::Ordering,
+    cmp::{max, min, Ordering},
     collections::{BinaryHeap, HashMap, HashSet, VecDeque},
     fmt::Debug,
     mem,
@@ -62,15 +65,21 @@ impl RequestHandler {
     //      No need to let caller handle request resending;
     // 2. UnknownPeer:
     //      No need to let caller handle request resending;
+    //
+    // `response_size` is the size of the response in bytes, or `None` if the
+    // request is matched because of timeout.
     pub fn match_request(
         &self, io: &dyn NetworkContext, peer_id: PeerId, request_id: u64,
-    ) -> Result<RequestMessage, Error> {
+        response_size: Option<usize>,
+    ) -> Result<RequestMessage, Error>
+    {
         let mut peers = self.peers.lock();
         let mut requests_queue = self.requests_queue.lock();
         if let Some(peer) = peers.get_mut(&peer_id) {
             peer.match_request(
                 io,
                 request_id,
+                response_size,
                 &mut *requests_queue,
                 &self.protocol_config,
             )
@@ -111,6 +120,7 @@ impl RequestHandler {
 
         request.set_request_id(request_id);
         let message = request.as_message();
+        let send_time = Instant::now();
         if send_message(io, peer, message).is_err() {
             return Err(request);
         }
@@ -121,7 +131,10 @@ impl RequestHandler {
             peer,
             request_id,
             &msg,
+            send_time,
             &self.protocol_config,
+            io.get_peer_rtt(peer),
+            peer_info.throughput,
         ));
         peer_info.append_inflight_request(request_id, msg, timed_req.clone());
         requests_queue.push(timed_req);
@@ -159,9 +172,12 @@ impl RequestHandler {
         let mut timeout_requests = Vec::new();
         let mut peers_to_disconnect = HashSet::new();
         for sync_req in self.get_timeout_sync_requests() {
-            if let Ok(req) =
-                self.match_request(io, sync_req.peer_id, sync_req.request_id)
-            {
+            if let Ok(req) = self.match_request(
+                io,
+                sync_req.peer_id,
+                sync_req.request_id,
+                None, /* response_size */
+            ) {
                 let peer_id = sync_req.peer_id;
                 if let Some(request_container) =
                     self.peers.lock().get_mut(&peer_id)
@@ -205,6 +221,9 @@ struct RequestContainer {
     pub max_inflight_request_count: u64,
     pub pending_requests: VecDeque<RequestMessage>,
     pub timeout_statistics: VecDeque<u64>,
+    /// The smoothed throughput of the responses from the peer in bytes per
+    /// second, or `None` if no large response is received yet.
+    pub throughput: Option<f64>,
 }
 
 impl RequestContainer {
@@ -236,6 +255,32 @@ impl RequestContainer {
         }
     }
 
+    /// Update the throughput of the peer with a response of `response_size`
+    /// bytes received `elapsed` after the request is sent. The round trip
+    /// time is excluded from `elapsed`, and the small responses are ignored
+    /// since their time is dominated by the latency.
+    fn on_response(
+        &mut self, response_size: usize, elapsed: Duration,
+        rtt: Option<Duration>,
+    )
+    {
+        if response_size < THROUGHPUT_SAMPLE_MIN_BYTES {
+            return;
+        }
+        let transfer_ms = elapsed
+            .checked_sub(rtt.unwrap_or_default())
+            .unwrap_or_default()
+            .as_millis();
+        if transfer_ms == 0 {
+            return;
+        }
+        let sample = response_size as f64 * 1000.0 / transfer_ms as f64;
+        self.throughput = Some(match self.throughput {
+            Some(throughput) => throughput * 0.75 + sample * 0.25,
+            None => sample,
+        });
+    }
+
     /// If new request will be allowed to send, advance the request id now,
     /// otherwise, actual new request id will be given to this request
     /// when it is moved from pending to inflight queue.
@@ -298,6 +343,7 @@ impl RequestContainer {
     //      resending for caller;
     pub fn match_request(
         &mut self, io: &dyn NetworkContext, request_id: u64,
+        response_size: Option<usize>,
         requests_queue: &mut BinaryHeap<Arc<TimedSyncRequests>>,
         protocol_config: &ProtocolConfiguration,
     ) -> Result<RequestMessage, Error>
@@ -308,10 +354,18 @@ impl RequestContainer {
                 .timed_req
                 .removed
                 .store(true, AtomicOrdering::Relaxed);
+            if let Some(response_size) = response_size {
+                self.on_response(
+                    response_size,
+                    removed_req.timed_req.send_time.elapsed(),
+                    io.get_peer_rtt(self.peer_id),
+                );
+            }
             while self.has_pending_requests() {
                 if let Some(new_request_id) = self.get_next_request_id() {
                     let mut pending_msg = self.pop_pending_request().unwrap();
                     pending_msg.set_request_id(new_request_id);
+                    let send_time = Instant::now();
                     let send_res =
                         send_message(io, self.peer_id, pending_msg.get_msg());
 
@@ -325,7 +379,10 @@ impl RequestContainer {
                         self.peer_id,
                         new_request_id,
                         &pending_msg,
+                        send_time,
                         protocol_config,
+                        io.get_peer_rtt(self.peer_id),
+                        self.throughput,
                     ));
                     self.append_inflight_request(
                         new_request_id,
@@ -370,8 +427,13 @@ pub trait Request: Send + Debug + HasRequestId {
     fn as_message(&self) -> &dyn Message;
     /// Support to downcast trait to concrete request type.
     fn as_any(&self) -> &dyn Any;
-    /// Request timeout for resend purpose.
+    /// Base request timeout for resend purpose, which is extended by the
+    /// round trip time of the peer and the expected transfer time of the
+    /// response. See `request_timeout`.
     fn timeout(&self, conf: &ProtocolConfiguration) -> Duration;
+    /// The estimated size of the response in bytes, which is transferred at
+    /// the throughput of the peer.
+    fn expected_response_size(&self) -> usize { 0 }
 
     /// Cleanup the inflight request items when peer disconnected or invalid
     /// message received.
@@ -396,6 +458,22 @@ pub trait Request: Send + Debug + HasRequestId {
 
     /// Required peer capability to send this request
     fn required_capability(&self) -> Option<DynamicCapability> { None }
+
+    /// Whether the requested items are available from `peer` according to
+    /// the sync state it announces, which is used to choose the peer to
+    /// send the request to.
+    fn is_served_by(&self, peer: &SynchronizationPeerState) -> bool {
+        match self.required_capability() {
+            Some(cap) => peer.capabilities.contains(cap),
+            None => true,
+        }
+    }
+
+    /// Whether `peer` is known to keep all the requested items, e.g. they
+    /// are not earlier than its checkpoint. Such peers are preferred among
+    /// the ones serving the request, since the items before the checkpoint
+    /// of a peer may be missing if it has synced from a later checkpoint.
+    fn is_kept_by(&self, _peer: &SynchronizationPeerState) -> bool { true }
 }
 
 #[derive(Debug)]
@@ -439,6 +517,7 @@ impl RequestMessage {
 #[derive(Debug)]
 pub struct TimedSyncRequests {
     pub peer_id: PeerId,
+    pub send_time: Instant,
     pub timeout_time: Instant,
     pub request_id: u64,
     pub removed: AtomicBool,
@@ -446,11 +525,12 @@ pub struct TimedSyncRequests {
 
 impl TimedSyncRequests {
     pub fn new(
-        peer_id: PeerId, timeout: Duration, request_id: u64,
+        peer_id: PeerId, send_time: Instant, timeout: Duration, request_id: u64,
     ) -> TimedSyncRequests {
         TimedSyncRequests {
             peer_id,
-            timeout_time: Instant::now() + timeout,
+            send_time,
+            timeout_time: send_time + timeout,
             request_id,
             removed: AtomicBool::new(false),
         }
@@ -458,14 +538,51 @@ impl TimedSyncRequests {
 
     pub fn from_request(
         peer_id: PeerId, request_id: u64, msg: &RequestMessage,
-        conf: &ProtocolConfiguration,
+        send_time: Instant, conf: &ProtocolConfiguration,
+        rtt: Option<Duration>, throughput: Option<f64>,
     ) -> TimedSyncRequests
     {
-        let timeout = msg.request.timeout(conf);
-        TimedSyncRequests::new(peer_id, timeout, request_id)
+        let timeout = request_timeout(&*msg.request, conf, rtt, throughput);
+        TimedSyncRequests::new(peer_id, send_time, timeout, request_id)
     }
 }
 
+/// The timeout of `request` sent to a peer whose round trip time is `rtt` and
+/// throughput is `throughput` bytes per second.
+///
+/// The timeout is the round trip time of the peer, or the base timeout of the
+/// request if `rtt` is unknown, plus the expected transfer time of the
+/// response if `throughput` is known. It is clamped to be at least the base
+/// timeout, which is lowered to `conf.min_request_timeout` if configured, and
+/// at most `conf.max_request_timeout`.
+pub fn request_timeout(
+    request: &dyn Request, conf: &ProtocolConfiguration, rtt: Option<Duration>,
+    throughput: Option<f64>,
+) -> Duration
+{
+    let base = request.timeout(conf);
+    let floor = match conf.min_request_timeout {
+        Some(min_request_timeout) => min(min_request_timeout, base),
+        None => base,
+    };
+    let latency = match rtt {
+        Some(rtt) => rtt * REQUEST_TIMEOUT_RTT_MULTIPLIER,
+        None => base,
+    };
+    let transfer = match throughput {
+        Some(throughput) if throughput > 0.0 => {
+            let transfer_ms =
+                request.expected_response_size() as f64 * 1000.0 / throughput;
+            Duration::from_millis(
+                transfer_ms.min(conf.max_request_timeout.as_millis() as f64)
+                    as u64,
+            )
+        }
+        _ => Duration::default(),
+    };
+    max(floor, min(latency + transfer, conf.max_request_timeout))
+}
+
 impl Ord for TimedSyncRequests {
     fn cmp(&self, other: &Self) -> Ordering {
         other.timeout_time.cmp(&self.timeout_time)
@@ -482,3 +599,240 @@ impl PartialEq for TimedSyncRequests {
         self.timeout_time == other.timeout_time
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{request_timeout, Request, RequestContainer};
+    use crate::sync::{
+        message::{
+            DynamicCapability, GetBlockHashesByEpoch, GetBlocks,
+            GetCompactBlocks,
+        },
+        ProtocolConfiguration, SynchronizationPeerState,
+    };
+    use cfx_types::H256;
+    use std::time::{Duration, Instant};
+
+    fn protocol_config(
+        min_request_timeout: Option<Duration>,
+    ) -> ProtocolConfiguration {
+        ProtocolConfiguration {
+            send_tx_period: Duration::from_millis(1300),
+            check_request_period: Duration::from_millis(5000),
+            block_cache_gc_period: Duration::from_millis(5000),
+            headers_request_timeout: Duration::from_secs(10),
+            blocks_request_timeout: Duration::from_secs(30),
+            transaction_request_timeout: Duration::from_secs(30),
+            max_request_timeout: Duration::from_secs(120),
+            min_request_timeout,
+            consistency_check_period: None,
+            tx_maintained_for_peer_timeout: Duration::from_secs(600),
+            max_inflight_request_count: 64,
+            received_tx_index_maintain_timeout: Duration::from_secs(600),
+            request_block_with_public: false,
+            max_trans_count_received_in_catch_up: 60000,
+            min_peers_propagation: 8,
+            max_peers_propagation: 128,
+            future_block_buffer_capacity: 32768,
+            max_download_state_peers: 8,
+            test_mode: false,
+            chain_id: 0,
+        }
+    }
+
+    /// A request of `count` blocks, whose expected response size is
+    /// `count * ESTIMATED_BLOCK_SIZE` (64 KB).
+    fn get_blocks(count: u64) -> GetBlocks {
+        GetBlocks {
+            request_id: 0,
+            with_public: false,
+            hashes: (0..count).map(H256::from_low_u64_be).collect(),
+            min_epoch: None,
+        }
+    }
+
+    fn peer_state(
+        best_epoch: u64, checkpoint_epoch: Option<u64>,
+        earliest_block_body_epoch: Option<u64>,
+    ) -> SynchronizationPeerState
+    {
+        let mut state = SynchronizationPeerState {
+            id: 0,
+            protocol_version: 0,
+            genesis_hash: H256::zero(),
+            best_epoch,
+            latest_block_hashes: Default::default(),
+            checkpoint_hash: checkpoint_epoch.map(H256::from_low_u64_be),
+            checkpoint_epoch,
+            received_transaction_count: 0,
+            heartbeat: Instant::now(),
+            capabilities: Default::default(),
+            notified_capabilities: Default::default(),
+        };
+        if let Some(epoch) = earliest_block_body_epoch {
+            state
+                .capabilities
+                .insert(DynamicCapability::ServeBlockBodiesFrom(epoch));
+        }
+        state
+    }
+
+    #[test]
+    fn test_request_timeout_unknown_peer() {
+        let conf = protocol_config(Some(Duration::from_secs(1)));
+        // Without the round trip time, the base timeout is used.
+        assert_eq!(
+            request_timeout(&get_blocks(1), &conf, None, None),
+            Duration::from_secs(30)
+        );
+        // 10 blocks of 64 KB at 64 KB/s take 10 seconds to transfer.
+        assert_eq!(
+            request_timeout(&get_blocks(10), &conf, None, Some(65536.0)),
+            Duration::from_secs(40)
+        );
+    }
+
+    #[test]
+    fn test_request_timeout_floor() {
+        let rtt = Some(Duration::from_millis(100));
+
+        // The base timeout is the lower bound by default.
+        let conf = protocol_config(None);
+        assert_eq!(
+            request_timeout(&get_blocks(1), &conf, rtt, None),
+            Duration::from_secs(30)
+        );
+
+        // The configured minimum lowers the timeout of a close peer.
+        let conf = protocol_config(Some(Duration::from_secs(1)));
+        assert_eq!(
+            request_timeout(&get_blocks(1), &conf, rtt, None),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            request_timeout(&get_blocks(10), &conf, rtt, Some(65536.0)),
+            Duration::from_millis(10_200)
+        );
+
+        // The configured minimum never raises the base timeout.
+        let conf = protocol_config(Some(Duration::from_secs(60)));
+        assert_eq!(
+            request_timeout(&get_blocks(1), &conf, rtt, None),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn test_request_timeout_ceiling() {
+        let conf = protocol_config(Some(Duration::from_secs(1)));
+        let rtt = Some(Duration::from_millis(100));
+        assert_eq!(
+            request_timeout(&get_blocks(1000), &conf, rtt, Some(65536.0)),
+            Duration::from_secs(120)
+        );
+        assert_eq!(
+            request_timeout(&get_blocks(1), &conf, rtt, Some(0.0)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            request_timeout(
+                &get_blocks(1),
+                &conf,
+                Some(Duration::from_secs(100)),
+                None
+            ),
+            Duration::from_secs(120)
+        );
+    }
+
+    #[test]
+    fn test_on_response() {
+        let mut container = RequestContainer::default();
+        let rtt = Some(Duration::from_millis(100));
+
+        // Small responses are ignored.
+        container.on_response(1000, Duration::from_secs(1), rtt);
+        assert_eq!(container.throughput, None);
+
+        // The round trip time is excluded from the transfer time.
+        container.on_response(1_000_000, Duration::from_millis(1100), rtt);
+        assert_eq!(container.throughput, Some(1_000_000.0));
+
+        // Responses received within the round trip time are ignored.
+        container.on_response(1_000_000, Duration::from_millis(50), rtt);
+        assert_eq!(container.throughput, Some(1_000_000.0));
+
+        // The new samples are smoothed.
+        container.on_response(2_000_000, Duration::from_millis(1000), None);
+        assert_eq!(container.throughput, Some(1_250_000.0));
+    }
+
+    #[test]
+    fn test_block_bodies_served_by() {
+        let archive = peer_state(100, None, None);
+        let pruned = peer_state(100, None, Some(50));
+
+        // The blocks with unknown heights can be requested from any peer.
+        let mut request = get_blocks(1);
+        assert!(request.is_served_by(&archive));
+        assert!(request.is_served_by(&pruned));
+
+        request.min_epoch = Some(49);
+        assert!(request.is_served_by(&archive));
+        assert!(!request.is_served_by(&pruned));
+        request.min_epoch = Some(50);
+        assert!(request.is_served_by(&pruned));
+
+        let compact = GetCompactBlocks {
+            request_id: 0,
+            hashes: vec![H256::zero()],
+            min_epoch: Some(49),
+        };
+        assert!(compact.is_served_by(&archive));
+        assert!(!compact.is_served_by(&pruned));
+        // The full blocks requested after a failure are still served from
+        // the same epochs.
+        let resent = compact.resend().unwrap();
+        assert!(resent.is_served_by(&archive));
+        assert!(!resent.is_served_by(&pruned));
+    }
+
+    #[test]
+    fn test_block_requests_rlp_without_min_epoch() {
+        let mut request = get_blocks(2);
+        request.min_epoch = Some(10);
+        let decoded: GetBlocks = rlp::decode(&rlp::encode(&request)).unwrap();
+        assert_eq!(decoded.hashes, request.hashes);
+        assert_eq!(decoded.min_epoch, None);
+
+        let compact = GetCompactBlocks {
+            request_id: 1,
+            hashes: vec![H256::zero()],
+            min_epoch: Some(10),
+        };
+        let decoded: GetCompactBlocks =
+            rlp::decode(&rlp::encode(&compact)).unwrap();
+        assert_eq!(decoded.request_id, 1);
+        assert_eq!(decoded.hashes, compact.hashes);
+        assert_eq!(decoded.min_epoch, None);
+    }
+
+    #[test]
+    fn test_epoch_hashes_served_and_kept_by() {
+        let request = GetBlockHashesByEpoch {
+            request_id: 0,
+            epochs: vec![10, 11],
+        };
+
+        // The epochs after the best epoch of a peer are not served.
+        assert!(!request.is_served_by(&peer_state(10, Some(5), None)));
+        assert!(request.is_served_by(&peer_state(11, Some(5), None)));
+
+        // The epochs from the checkpoint of a peer are kept, and the ones
+        // before it may be missing.
+        assert!(request.is_kept_by(&peer_state(20, Some(10), None)));
+        assert!(!request.is_kept_by(&peer_state(20, Some(11), None)));
+        // Peers with unknown checkpoints are not known to keep them.
+        assert!(!request.is_kept_by(&peer_state(20, None, None)));
+    }
+}
@@ -4,7 +4,6 @@
 
 use crate::{
     block_data_manager::ConsensusGraphExecutionInfo,
-    parameters::consensus::DEFERRED_STATE_EPOCH_COUNT,
     sync::{
         message::{Context, DynamicCapability},
         state::{
@@ -395,6 +394,8 @@ impl SnapshotChunkSync {
         let inner = self.inner.read();
         let mut hash = inner.trusted_blame_block;
         let mut hashes = Vec::new();
+        let deferred_state_epoch_count =
+            sync_handler.graph.consensus.deferred_state_epoch_count() as usize;
         for i in 0..inner.state_blame_vec.len() {
             hashes.push(hash);
             sync_handler
@@ -410,16 +411,14 @@ impl SnapshotChunkSync {
                             .bloom_blame_vec[i],
                     },
                 );
-            if i >= DEFERRED_STATE_EPOCH_COUNT as usize {
+            if i >= deferred_state_epoch_count {
                 sync_handler
                     .graph
                     .data_man
                     .insert_epoch_execution_commitments(
                         hashes[i],
-                        inner.receipt_blame_vec
-                            [i - DEFERRED_STATE_EPOCH_COUNT as usize],
-                        inner.bloom_blame_vec
-                            [i - DEFERRED_STATE_EPOCH_COUNT as usize],
+                        inner.receipt_blame_vec[i - deferred_state_epoch_count],
+                        inner.bloom_blame_vec[i - deferred_state_epoch_count],
                     )
             }
             let block = sync_handler
@@ -471,7 +470,8 @@ impl SnapshotChunkSync {
         }
         // check checkpoint position in `state_blame_vec`
         let offset = trusted_blame_block.height()
-            - (checkpoint.height() + DEFERRED_STATE_EPOCH_COUNT);
+            - (checkpoint.height()
+                + ctx.manager.graph.consensus.deferred_state_epoch_count());
         if offset as usize >= state_blame_vec.len() {
             return None;
         }
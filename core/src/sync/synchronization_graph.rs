@@ -128,6 +128,10 @@ pub struct SynchronizationGraphInner {
     children_by_hash: HashMap<H256, Vec<usize>>,
     referrers_by_hash: HashMap<H256, Vec<usize>>,
     pub pow_config: ProofOfWorkConfig,
+    /// The height from which the timestamp of a block must be after the
+    /// median time past of its parent, see
+    /// `VerificationConfig::median_time_past_activation_height`.
+    pub median_time_past_activation_height: Option<u64>,
     /// The indices of blocks whose graph_status is not GRAPH_READY.
     /// It may consider not header-graph-ready in phases
     /// `CatchUpRecoverBlockHeaderFromDB` and `CatchUpSyncBlockHeader`.
@@ -142,6 +146,7 @@ pub struct SynchronizationGraphInner {
 impl SynchronizationGraphInner {
     pub fn with_genesis_block(
         genesis_header: Arc<BlockHeader>, pow_config: ProofOfWorkConfig,
+        median_time_past_activation_height: Option<u64>,
         data_man: Arc<BlockDataManager>,
     ) -> Self
     {
@@ -153,6 +158,7 @@ impl SynchronizationGraphInner {
             children_by_hash: HashMap::new(),
             referrers_by_hash: HashMap::new(),
             pow_config,
+            median_time_past_activation_height,
             not_ready_blocks_frontier: UnreadyBlockFrontier::new(),
             not_ready_blocks_count: 0,
             old_era_blocks_frontier: Default::default(),
@@ -430,7 +436,10 @@ impl SynchronizationGraphInner {
                 }
             }
 
-            if parent_header_graph_ready && referee_header_graph_ready {
+            if parent_header_graph_ready
+                && referee_header_graph_ready
+                && self.median_time_past_ready(*index)
+            {
                 // do check
                 let r = self.verify_header_graph_ready_block(*index);
                 if r.is_err() {
@@ -608,6 +617,36 @@ impl SynchronizationGraphInner {
             && !node_me.referees.iter().any(|&referee| {
                 self.arena[referee].graph_status < BLOCK_HEADER_GRAPH_READY
             })
+            && self.median_time_past_ready(index)
+    }
+
+    fn median_time_past_active(&self, height: u64) -> bool {
+        self.median_time_past_activation_height
+            .map_or(false, |activation_height| height >= activation_height)
+    }
+
+    /// The median time past of the parent of the block, or `None` if some
+    /// headers in the window are not available.
+    fn parent_median_time_past(&self, index: usize) -> Option<u64> {
+        median_time_past_of(
+            self.arena[index].block_header.parent_hash(),
+            |hash| match self.hash_to_arena_indices.get(hash) {
+                Some(ancestor) => {
+                    Some(self.arena[*ancestor].block_header.clone())
+                }
+                None => self.data_man.block_header_by_hash(hash),
+            },
+        )
+    }
+
+    /// Whether the median time past rule can be checked for the block. The
+    /// headers of the ancestors of a header-graph-ready block are persisted
+    /// when they become ready, so the block is only deferred if they are
+    /// missing in the database, and it's retried with the other unready
+    /// blocks.
+    fn median_time_past_ready(&self, index: usize) -> bool {
+        !self.median_time_past_active(self.arena[index].block_header.height())
+            || self.parent_median_time_past(index).is_some()
     }
 
     pub fn new_to_be_block_graph_ready(&self, index: usize) -> bool {
@@ -708,6 +747,31 @@ impl SynchronizationGraphInner {
             )));
         }
 
+        // From the activation height, the timestamp must be after the median
+        // time past of the parent, so that the time of the chain keeps moving
+        // forward even if a few blocks lie about it.
+        if self.median_time_past_active(epoch) {
+            let median = self
+                .parent_median_time_past(index)
+                .expect("checked by median_time_past_ready");
+            if my_timestamp <= median {
+                warn!(
+                    "Invalid timestamp: block {:?} timestamp {} is not after \
+                     the median time past {}",
+                    self.arena[index].block_header.hash(),
+                    my_timestamp,
+                    median
+                );
+                return Err(From::from(BlockError::InvalidTimestamp(
+                    OutOfBounds {
+                        max: None,
+                        min: Some(UNIX_EPOCH + Duration::from_secs(median + 1)),
+                        found: UNIX_EPOCH + Duration::from_secs(my_timestamp),
+                    },
+                )));
+            }
+        }
+
         // Verify the gas limit is respected
         let machine = new_machine_with_builtin();
         let gas_limit_divisor = machine.params().gas_limit_bound_divisor;
@@ -920,6 +984,7 @@ impl SynchronizationGraph {
             SynchronizationGraphInner::with_genesis_block(
                 Arc::new(data_man.genesis_block().block_header.clone()),
                 pow_config,
+                verification_config.median_time_past_activation_height,
                 data_man.clone(),
             ),
         ));
@@ -944,6 +1009,7 @@ impl SynchronizationGraph {
             .spawn(move || loop {
                 match consensus_receiver.recv() {
                     Ok((hash, ignore_body)) => {
+                        consensus.wait_for_execution_capacity();
                         consensus.on_new_block(&hash, ignore_body)
                     }
                     Err(_) => break,
@@ -1006,6 +1072,7 @@ impl SynchronizationGraph {
     /// information stored in db.
     pub fn recover_graph_from_db(&self, header_only: bool) {
         info!("Start fast recovery of the block DAG from database");
+        self.consensus.verify_recovered_stable_hash();
 
         // Recover the initial sequence number in consensus graph
         // based on the sequence number of genesis block in db.
@@ -1134,8 +1201,12 @@ impl SynchronizationGraph {
             // Rebuild pivot chain state info.
             self.consensus.construct_pivot_state();
         }
-        self.consensus
-            .update_best_info(&*self.consensus.inner.read());
+        self.consensus.update_best_info(
+            &*self
+                .consensus
+                .inner
+                .read_at("SynchronizationGraph::recover_graph_from_db"),
+        );
         self.consensus
             .txpool
             .notify_new_best_info(self.consensus.best_info());
@@ -1623,6 +1694,40 @@ impl SynchronizationGraph {
 
     pub fn log_statistics(&self) { self.statistics.log_statistics(); }
 
+    /// Cross-check the graph-ready blocks, the epoch sets of the last
+    /// `max_epochs` epochs and `latest_inserted_block` with the consensus
+    /// graph and the persisted data, and return the discrepancies found.
+    /// This is used to catch silent divergence in long tests.
+    ///
+    /// It should only be called in the normal phase, in which the graph-ready
+    /// blocks are sent to the consensus graph in order. Nothing is checked if
+    /// some graph-ready blocks are still queued to the consensus graph.
+    pub fn check_consistency(&self, max_epochs: usize) -> Vec<String> {
+        let delivered: Vec<(H256, u64)> = {
+            // No block becomes graph-ready while the lock is held.
+            let inner = self.inner.read();
+            if *self.latest_graph_ready_block.lock()
+                != *self.consensus.latest_inserted_block.lock()
+            {
+                return Vec::new();
+            }
+            inner
+                .arena
+                .iter()
+                .filter(|(_, node)| node.graph_status == BLOCK_GRAPH_READY)
+                .map(|(_, node)| {
+                    (node.block_header.hash(), node.block_header.height())
+                })
+                .collect()
+        };
+        let latest_inserted_block =
+            *self.consensus.latest_inserted_block.lock();
+        self.consensus
+            .inner
+            .read_at("SynchronizationGraph::check_consistency")
+            .check_consistency(&delivered, &latest_inserted_block, max_epochs)
+    }
+
     pub fn update_total_weight_in_past(&self) {
         self.consensus.update_total_weight_in_past();
     }
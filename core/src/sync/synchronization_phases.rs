@@ -10,7 +10,8 @@ use crate::{
         state::{SnapshotChunkSync, Status},
         synchronization_protocol_handler::SynchronizationProtocolHandler,
         synchronization_state::SynchronizationState,
-        SharedSynchronizationGraph, SynchronizationGraphInner,
+        SharedSynchronizationGraph, SynchronizationGraph,
+        SynchronizationGraphInner,
     },
 };
 use network::NetworkContext;
@@ -24,6 +25,42 @@ use std::{
     thread, time,
 };
 
+/// Wait until `min_peers` peers report their checkpoints or the timeout
+/// elapses, and hand the checkpoints to the consensus graph, which
+/// cross-checks them with the stable hash recovered from the database. The
+/// checkpoints are only collected once, so that a full node doesn't wait for
+/// them again when it recovers the blocks after the headers.
+fn collect_peer_checkpoints(
+    syn: &SynchronizationState, graph: &SynchronizationGraph,
+) {
+    let conf = graph.consensus.stable_hash_verification_config();
+    if conf.min_peers == 0 || graph.consensus.has_peer_checkpoints() {
+        return;
+    }
+
+    let start = time::Instant::now();
+    loop {
+        let checkpoints: Vec<_> = syn
+            .peers
+            .read()
+            .values()
+            .filter_map(|peer| peer.read().checkpoint_hash)
+            .collect();
+        if checkpoints.len() >= conf.min_peers
+            || start.elapsed() >= conf.timeout
+        {
+            info!(
+                "Collected the checkpoints of {} peers: {:?}",
+                checkpoints.len(),
+                checkpoints
+            );
+            graph.consensus.set_peer_checkpoints(checkpoints);
+            return;
+        }
+        thread::sleep(time::Duration::from_millis(100));
+    }
+}
+
 ///
 /// Archive node goes through the following phases:
 ///     CatchUpRecoverBlockFromDB --> CatchUpSyncBlock --> Normal
@@ -215,14 +252,16 @@ impl SynchronizationPhaseTrait for CatchUpRecoverBlockHeaderFromDbPhase {
 
     fn start(
         &self, _io: &dyn NetworkContext,
-        _sync_handler: &SynchronizationProtocolHandler,
+        sync_handler: &SynchronizationProtocolHandler,
     )
     {
         info!("start phase {:?}", self.name());
         self.recovered.store(false, AtomicOrdering::SeqCst);
         let recovered = self.recovered.clone();
         let graph = self.graph.clone();
+        let syn = sync_handler.syn.clone();
         std::thread::spawn(move || {
+            collect_peer_checkpoints(&syn, &graph);
             graph.recover_graph_from_db(true /* header_only */);
             recovered.store(true, AtomicOrdering::SeqCst);
             info!("finish recover header graph from db");
@@ -411,7 +450,7 @@ impl SynchronizationPhaseTrait for CatchUpRecoverBlockFromDbPhase {
 
     fn start(
         &self, _io: &dyn NetworkContext,
-        _sync_handler: &SynchronizationProtocolHandler,
+        sync_handler: &SynchronizationProtocolHandler,
     )
     {
         info!("start phase {:?}", self.name());
@@ -427,7 +466,11 @@ impl SynchronizationPhaseTrait for CatchUpRecoverBlockFromDbPhase {
                 thread::sleep(time::Duration::from_millis(100));
             }
             // Now, we can safely acquire the lock of consensus graph.
-            let old_consensus_inner = &mut *self.graph.consensus.inner.write();
+            let old_consensus_inner = &mut *self
+                .graph
+                .consensus
+                .inner
+                .write_at("CatchUpRecoverBlockFromDbPhase::start");
             // We should assign a new instance_id here since we construct a new
             // consensus graph.
             old_sync_inner.data_man.initialize_instance_id();
@@ -470,6 +513,11 @@ impl SynchronizationPhaseTrait for CatchUpRecoverBlockFromDbPhase {
                             old_consensus_inner.arena[cur].hash,
                             i == 0,
                         );
+                        // Keep the outcome for auditing.
+                        self.graph.data_man.insert_blame_state_valid_to_db(
+                            &old_consensus_inner.arena[cur].hash,
+                            i == 0,
+                        );
                         cur = old_consensus_inner.arena[cur].parent;
                         if cur == NULL {
                             break;
@@ -483,6 +531,8 @@ impl SynchronizationPhaseTrait for CatchUpRecoverBlockFromDbPhase {
                     old_consensus_inner.pow_config.clone(),
                     self.graph.data_man.clone(),
                     old_consensus_inner.inner_conf.clone(),
+                    old_consensus_inner.ghast_rule.clone(),
+                    old_consensus_inner.anticone_cache_gauge(),
                     &cur_era_genesis_hash,
                     trusted_blame_block,
                 );
@@ -494,6 +544,7 @@ impl SynchronizationPhaseTrait for CatchUpRecoverBlockFromDbPhase {
                     .block_header_by_hash(&cur_era_genesis_hash)
                     .expect("era genesis exists"),
                 old_sync_inner.pow_config.clone(),
+                old_sync_inner.median_time_past_activation_height,
                 old_sync_inner.data_man.clone(),
             );
             *old_sync_inner = new_sync_inner;
@@ -519,9 +570,11 @@ impl SynchronizationPhaseTrait for CatchUpRecoverBlockFromDbPhase {
         self.recovered.store(false, AtomicOrdering::SeqCst);
         let recovered = self.recovered.clone();
         let graph = self.graph.clone();
+        let syn = sync_handler.syn.clone();
         std::thread::Builder::new()
             .name("recover_blocks".into())
             .spawn(move || {
+                collect_peer_checkpoints(&syn, &graph);
                 graph.recover_graph_from_db(false /* header_only */);
                 recovered.store(true, AtomicOrdering::SeqCst);
                 info!("finish recover block graph from db");
@@ -611,5 +664,8 @@ impl SynchronizationPhaseTrait for NormalSyncPhase {
     {
         info!("start phase {:?}", self.name());
         sync_handler.request_missing_terminals(io);
+        // the best state is available now, so that we can verify the nonces
+        // of transactions in journal.
+        sync_handler.graph.consensus.txpool.replay_journal();
     }
 }
@@ -6,18 +6,20 @@ use super::{
     msg_sender::{send_message, NULL},
     random,
     request_manager::RequestManager,
-    Error, ErrorKind, SharedSynchronizationGraph, SynchronizationState,
+    Error, ErrorKind, SharedSynchronizationGraph, SynchronizationPeerState,
+    SynchronizationState,
 };
 use crate::{
     block_data_manager::BlockStatus,
     light_protocol::Provider as LightProvider,
     message::{decode_msg, HasRequestId, Message, MsgId},
     parameters::sync::*,
+    state_exposer::SyncGraphExposer,
     sync::{
         message::{
             handle_rlp_message, msgid, Context, DynamicCapability,
-            GetBlockHeadersResponse, NewBlockHashes, Status,
-            TransactionDigests,
+            GetBlockHeadersResponse, MessageCodecRegistry, NewBlockHashes,
+            Status, TransactionDigests,
         },
         state::SnapshotChunkSync,
         synchronization_phases::{SyncPhaseType, SynchronizationPhaseManager},
@@ -28,7 +30,8 @@ use io::TimerToken;
 use metrics::{register_meter_with_group, Meter};
 use network::{
     throttling::THROTTLING_SERVICE, Error as NetworkError, HandlerWorkType,
-    NetworkContext, NetworkProtocolHandler, PeerId, UpdateNodeOperation,
+    NetworkContext, NetworkProtocolHandler, PeerId, ProtocolId,
+    UpdateNodeOperation,
 };
 use parking_lot::{Mutex, RwLock};
 use primitives::{Block, BlockHeader, SignedTransaction};
@@ -58,6 +61,12 @@ const CHECK_PEER_HEARTBEAT_TIMER: TimerToken = 6;
 const CHECK_FUTURE_BLOCK_TIMER: TimerToken = 7;
 const EXPIRE_BLOCK_GC_TIMER: TimerToken = 8;
 const HEARTBEAT_TIMER: TimerToken = 9;
+const STATE_EXPOSER_TIMER: TimerToken = 10;
+const CONSISTENCY_CHECK_TIMER: TimerToken = 11;
+
+/// The number of the latest persisted epochs whose epoch sets are checked in
+/// each consistency check.
+const CONSISTENCY_CHECK_EPOCHS: usize = 100;
 
 const MAX_TXS_BYTES_TO_PROPAGATE: usize = 1024 * 1024; // 1MB
 
@@ -211,6 +220,8 @@ impl FutureBlockContainer {
     }
 }
 
+pub const SYNCHRONIZATION_PROTOCOL_ID: ProtocolId = *b"cfx";
+
 pub struct SynchronizationProtocolHandler {
     pub protocol_config: ProtocolConfiguration,
     pub graph: SharedSynchronizationGraph,
@@ -232,6 +243,9 @@ pub struct SynchronizationProtocolHandler {
 
     // provider for serving light protocol queries
     light_provider: Arc<LightProvider>,
+
+    // message decoders of the supported protocol versions
+    message_codecs: MessageCodecRegistry,
 }
 
 #[derive(Clone)]
@@ -242,6 +256,13 @@ pub struct ProtocolConfiguration {
     pub headers_request_timeout: Duration,
     pub blocks_request_timeout: Duration,
     pub transaction_request_timeout: Duration,
+    pub max_request_timeout: Duration,
+    /// The lower bound of the request timeouts of the peers with known round
+    /// trip time, or `None` to use the base timeouts of the requests.
+    pub min_request_timeout: Option<Duration>,
+    /// The period of checking the consistency between the synchronization
+    /// graph and the consensus graph, or `None` to disable the check.
+    pub consistency_check_period: Option<Duration>,
     pub tx_maintained_for_peer_timeout: Duration,
     pub max_inflight_request_count: u64,
     pub received_tx_index_maintain_timeout: Duration,
@@ -252,6 +273,7 @@ pub struct ProtocolConfiguration {
     pub future_block_buffer_capacity: usize,
     pub max_download_state_peers: usize,
     pub test_mode: bool,
+    pub chain_id: u64,
 }
 
 impl SynchronizationProtocolHandler {
@@ -273,6 +295,18 @@ impl SynchronizationProtocolHandler {
             protocol_config.max_download_state_peers,
         ));
 
+        // All the supported versions share the message formats for now.
+        let mut message_codecs = MessageCodecRegistry::default();
+        for version in SYNCHRONIZATION_PROTOCOL_OLDEST_VERSION
+            ..=SYNCHRONIZATION_PROTOCOL_VERSION
+        {
+            message_codecs.register(
+                SYNCHRONIZATION_PROTOCOL_ID,
+                version,
+                handle_rlp_message,
+            );
+        }
+
         Self {
             protocol_config,
             graph: sync_graph.clone(),
@@ -297,6 +331,28 @@ impl SynchronizationProtocolHandler {
             ),
             state_sync,
             light_provider,
+            message_codecs,
+        }
+    }
+
+    /// The protocol versions announced to peers for version negotiation.
+    pub fn protocol_versions(&self) -> Vec<u8> {
+        self.message_codecs.versions(SYNCHRONIZATION_PROTOCOL_ID)
+    }
+
+    /// The protocol version negotiated with `peer`. Local messages are in
+    /// the current version.
+    fn peer_protocol_version(
+        &self, io: &dyn NetworkContext, peer: PeerId,
+    ) -> u8 {
+        if peer == NULL {
+            return SYNCHRONIZATION_PROTOCOL_VERSION;
+        }
+        match self.syn.get_peer_info(&peer) {
+            Ok(peer_info) => peer_info.read().protocol_version,
+            Err(_) => io
+                .get_protocol_version(peer)
+                .unwrap_or(SYNCHRONIZATION_PROTOCOL_VERSION),
         }
     }
 
@@ -356,13 +412,21 @@ impl SynchronizationProtocolHandler {
             }
         }
 
+        let version = self.peer_protocol_version(io, peer);
         let ctx = Context {
             peer,
             io,
             manager: self,
+            message_size: rlp.as_raw().len(),
         };
 
-        if !handle_rlp_message(msg_id, &ctx, &rlp)? {
+        if !self.message_codecs.handle(
+            SYNCHRONIZATION_PROTOCOL_ID,
+            version,
+            msg_id,
+            &ctx,
+            &rlp,
+        )? {
             warn!("Unknown message: peer={:?} msgid={:?}", peer, msg_id);
             io.disconnect_peer(
                 peer,
@@ -588,8 +652,6 @@ impl SynchronizationProtocolHandler {
                 cmp::min(until, best_of_this_peer + 1)
             };
 
-            let epochs = (from..until).collect::<Vec<u64>>();
-
             debug!(
                 "requesting epochs [{}..{}]/{:?} from peer {:?}",
                 from,
@@ -598,7 +660,17 @@ impl SynchronizationProtocolHandler {
                 peer
             );
 
-            self.request_manager.request_epoch_hashes(io, peer, epochs);
+            if self.request_blocks_by_epoch(peer.unwrap(), from) {
+                self.request_manager.request_blocks_by_epoch(
+                    io,
+                    peer,
+                    from,
+                    until - 1,
+                );
+            } else {
+                let epochs = (from..until).collect::<Vec<u64>>();
+                self.request_manager.request_epoch_hashes(io, peer, epochs);
+            }
             *latest_requested = until - 1;
         }
 
@@ -608,6 +680,26 @@ impl SynchronizationProtocolHandler {
         );
     }
 
+    /// Whether the blocks of the epochs from `from` are requested with
+    /// `GetBlocksByEpoch` from `peer`, instead of requesting the epoch hashes
+    /// and then the headers and the blocks.
+    fn request_blocks_by_epoch(&self, peer: PeerId, from: u64) -> bool {
+        if !self.need_requesting_blocks() || self.request_block_need_public() {
+            return false;
+        }
+        match self.syn.get_peer_info(&peer) {
+            Ok(peer_info) => {
+                let peer_info = peer_info.read();
+                peer_info.capabilities.contains(
+                    DynamicCapability::ProtocolVersion(
+                        GET_BLOCKS_BY_EPOCH_PROTOCOL_VERSION,
+                    ),
+                ) && peer_info.capabilities.serves_block_bodies_of(from)
+            }
+            Err(_) => false,
+        }
+    }
+
     pub fn request_block_headers(
         &self, io: &dyn NetworkContext, peer: Option<usize>,
         mut header_hashes: Vec<H256>, ignore_db: bool,
@@ -670,6 +762,7 @@ impl SynchronizationProtocolHandler {
                 peer: NULL,
                 io,
                 manager: self,
+                message_size: 0,
             };
 
             ctx.send_response(&block_headers_resp)
@@ -830,8 +923,16 @@ impl SynchronizationProtocolHandler {
         Status {
             protocol_version: SYNCHRONIZATION_PROTOCOL_VERSION,
             genesis_hash: self.graph.data_man.true_genesis_block.hash(),
+            chain_id: Some(self.protocol_config.chain_id),
             best_epoch: best_info.best_epoch_number,
             terminal_block_hashes: terminal_hashes,
+            checkpoint_hash: Some(
+                self.graph.consensus.current_era_genesis_hash(),
+            ),
+            earliest_block_body_epoch: self
+                .graph
+                .data_man
+                .earliest_block_body_epoch(),
         }
     }
 
@@ -1082,8 +1183,85 @@ impl SynchronizationProtocolHandler {
 
     fn cache_gc(&self) { self.graph.data_man.cache_gc() }
 
+    /// Prune the old block bodies if only the recent ones are kept, and tell
+    /// the peers not to request the pruned ones.
+    fn prune_block_bodies(&self, io: &dyn NetworkContext) {
+        let best_epoch_number = self.graph.consensus.best_epoch_number();
+        let earliest_epoch =
+            match self.graph.data_man.prune_block_bodies(best_epoch_number) {
+                Some(epoch) => epoch,
+                None => return,
+            };
+        let peers = self
+            .syn
+            .peers
+            .read()
+            .iter()
+            .filter(|(_, state)| {
+                state.read().capabilities.contains(
+                    DynamicCapability::ProtocolVersion(
+                        SERVE_BLOCK_BODIES_FROM_PROTOCOL_VERSION,
+                    ),
+                )
+            })
+            .map(|(peer, _)| *peer)
+            .collect();
+        DynamicCapability::ServeBlockBodiesFrom(earliest_epoch)
+            .broadcast_with_peers(io, peers);
+    }
+
     fn log_statistics(&self) { self.graph.log_statistics(); }
 
+    /// Refresh the exposed state of the synchronization graph, transaction
+    /// pool and execution queue.
+    fn update_state_exposer(&self) {
+        let (not_ready_frontier, not_ready_block_count) = {
+            let inner = self.graph.inner.read();
+            let frontier = inner
+                .not_ready_blocks_frontier
+                .get_frontier()
+                .iter()
+                .map(|index| inner.arena[*index].block_header.hash())
+                .collect();
+            (frontier, inner.not_ready_blocks_count)
+        };
+        let request_manager = &self.request_manager;
+        let sync_graph = SyncGraphExposer {
+            not_ready_frontier,
+            not_ready_block_count,
+            inflight_epochs: request_manager
+                .num_inflight_keys(msgid::GET_BLOCK_HASHES_BY_EPOCH),
+            inflight_headers: request_manager
+                .num_inflight_keys(msgid::GET_BLOCK_HEADERS),
+            inflight_blocks: request_manager
+                .num_inflight_keys(msgid::GET_BLOCKS),
+            inflight_transactions: request_manager
+                .num_inflight_keys(msgid::GET_TRANSACTIONS),
+        };
+        self.graph.consensus.state_exposer().write().sync_graph = sync_graph;
+        self.graph.consensus.update_state_exposer();
+    }
+
+    /// Check the consistency between the synchronization graph and the
+    /// consensus graph, and log and expose the discrepancies found.
+    fn check_consistency(&self) {
+        if self.phase_manager.get_current_phase().phase_type()
+            != SyncPhaseType::Normal
+        {
+            return;
+        }
+        let discrepancies =
+            self.graph.check_consistency(CONSISTENCY_CHECK_EPOCHS);
+        for discrepancy in &discrepancies {
+            error!("Consistency check failed: {}", discrepancy);
+        }
+        let mut exposer = self.graph.consensus.state_exposer().write();
+        let consistency = &mut exposer.consistency;
+        consistency.checks += 1;
+        consistency.total_discrepancies += discrepancies.len() as u64;
+        consistency.last_discrepancies = discrepancies;
+    }
+
     fn update_total_weight_in_past(&self) {
         self.graph.update_total_weight_in_past();
     }
@@ -1137,8 +1315,10 @@ impl SynchronizationProtocolHandler {
         if catch_up_mode {
             self.request_blocks(io, peer_id, hashes);
         } else {
+            let min_epoch = self.min_block_body_epoch(hashes.iter());
+            let peer_id = self.choose_block_body_peer(peer_id, min_epoch);
             self.request_manager
-                .request_compact_blocks(io, peer_id, hashes);
+                .request_compact_blocks(io, peer_id, hashes, min_epoch);
         }
     }
 
@@ -1151,14 +1331,49 @@ impl SynchronizationProtocolHandler {
         // Blocks may have been inserted into sync graph before as dependent
         // blocks
         hashes.retain(|h| !self.graph.contains_block(h));
+        let min_epoch = self.min_block_body_epoch(hashes.iter());
+        let peer_id = self.choose_block_body_peer(peer_id, min_epoch);
         self.request_manager.request_blocks(
             io,
             peer_id,
             hashes,
             self.request_block_need_public(),
+            min_epoch,
         );
     }
 
+    /// The earliest epoch whose bodies are requested with the blocks in
+    /// `hashes`. A block is in an epoch no earlier than its height, so it is
+    /// the lowest height of the blocks whose headers are known.
+    fn min_block_body_epoch<'a, I>(&self, hashes: I) -> Option<u64>
+    where I: Iterator<Item = &'a H256> {
+        hashes
+            .filter_map(|hash| self.graph.block_height_by_hash(hash))
+            .min()
+    }
+
+    /// Keep `peer_id` if it serves the block bodies from epoch `min_epoch`,
+    /// and otherwise choose a random peer which does.
+    fn choose_block_body_peer(
+        &self, peer_id: Option<PeerId>, min_epoch: Option<u64>,
+    ) -> Option<PeerId> {
+        let min_epoch = match min_epoch {
+            Some(epoch) => epoch,
+            None => return peer_id,
+        };
+        let serves = |peer: &SynchronizationPeerState| {
+            peer.capabilities.serves_block_bodies_of(min_epoch)
+        };
+        if let Some(peer) = peer_id {
+            if let Ok(peer_info) = self.syn.get_peer_info(&peer) {
+                if serves(&*peer_info.read()) {
+                    return peer_id;
+                }
+            }
+        }
+        self.syn.get_random_peer_satisfying(serves)
+    }
+
     /// Try to get the block from db. Return `true` if the block exists in db or
     /// is inserted before. Handle the block if its seq_num is less
     /// than that of the current era genesis.
@@ -1242,6 +1457,7 @@ impl SynchronizationProtocolHandler {
         peer: Option<PeerId>,
     )
     {
+        let min_epoch = self.min_block_body_epoch(req_hashes.iter());
         self.request_manager.blocks_received(
             io,
             req_hashes,
@@ -1249,6 +1465,7 @@ impl SynchronizationProtocolHandler {
             ask_full_block,
             peer,
             self.request_block_need_public(),
+            min_epoch,
         )
     }
 
@@ -1301,6 +1518,12 @@ impl NetworkProtocolHandler for SynchronizationProtocolHandler {
         .expect("Error registering CHECK_FUTURE_BLOCK_TIMER");
         io.register_timer(EXPIRE_BLOCK_GC_TIMER, Duration::from_secs(60 * 15))
             .expect("Error registering EXPIRE_BLOCK_GC_TIMER");
+        io.register_timer(STATE_EXPOSER_TIMER, Duration::from_millis(1000))
+            .expect("Error registering STATE_EXPOSER_TIMER");
+        if let Some(period) = self.protocol_config.consistency_check_period {
+            io.register_timer(CONSISTENCY_CHECK_TIMER, period)
+                .expect("Error registering CONSISTENCY_CHECK_TIMER");
+        }
     }
 
     fn send_local_message(&self, io: &dyn NetworkContext, message: Vec<u8>) {
@@ -1385,6 +1608,7 @@ impl NetworkProtocolHandler for SynchronizationProtocolHandler {
             BLOCK_CACHE_GC_TIMER => {
                 self.cache_gc();
                 self.graph.try_remove_old_era_blocks_from_disk();
+                self.prune_block_bodies(io);
             }
             CHECK_CATCH_UP_MODE_TIMER => {
                 self.update_sync_phase(io);
@@ -1392,6 +1616,12 @@ impl NetworkProtocolHandler for SynchronizationProtocolHandler {
             LOG_STATISTIC_TIMER => {
                 self.log_statistics();
             }
+            STATE_EXPOSER_TIMER => {
+                self.update_state_exposer();
+            }
+            CONSISTENCY_CHECK_TIMER => {
+                self.check_consistency();
+            }
             TOTAL_WEIGHT_IN_PAST_TIMER => {
                 self.update_total_weight_in_past();
             }
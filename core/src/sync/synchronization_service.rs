@@ -7,10 +7,11 @@ use super::{
 };
 use crate::{
     light_protocol::Provider as LightProvider,
-    parameters::sync::SYNCHRONIZATION_PROTOCOL_VERSION,
     sync::{
         synchronization_phases::SyncPhaseType,
-        synchronization_protocol_handler::ProtocolConfiguration,
+        synchronization_protocol_handler::{
+            ProtocolConfiguration, SYNCHRONIZATION_PROTOCOL_ID,
+        },
         SynchronizationPhaseTrait,
     },
 };
@@ -44,7 +45,7 @@ impl SynchronizationService {
         SynchronizationService {
             network,
             protocol_handler: sync_handler,
-            protocol: *b"cfx",
+            protocol: SYNCHRONIZATION_PROTOCOL_ID,
         }
     }
 
@@ -71,7 +72,7 @@ impl SynchronizationService {
         self.network.register_protocol(
             self.protocol_handler.clone(),
             self.protocol,
-            &[SYNCHRONIZATION_PROTOCOL_VERSION],
+            &self.protocol_handler.protocol_versions(),
         )?;
         Ok(())
     }
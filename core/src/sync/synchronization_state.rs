@@ -23,6 +23,10 @@ pub struct SynchronizationPeerState {
     pub genesis_hash: H256,
     pub best_epoch: u64,
     pub latest_block_hashes: HashSet<H256>,
+    /// The genesis of the current era of the peer, if announced.
+    pub checkpoint_hash: Option<H256>,
+    /// The epoch of `checkpoint_hash`, if its header is known locally.
+    pub checkpoint_epoch: Option<u64>,
 
     /// The following fields are used to control how to handle
     /// transaction propagation for nodes in catch-up mode.
@@ -2,13 +2,37 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
-use crate::sync::{
-    utils::{create_simple_block_impl, initialize_synchronization_graph},
-    SynchronizationGraphNode,
+use crate::{
+    consensus::{
+        ConsensusError, DefaultGhastRule, EpochBlockInfo, ReindexedEpoch,
+    },
+    parameters::{
+        consensus::DEFERRED_STATE_EPOCH_COUNT,
+        consensus_internal::{
+            BASE_MINING_REWARD, CONFLUX_TOKEN, REWARD_EPOCH_COUNT,
+        },
+    },
+    sync::{
+        message::LazyBlock,
+        utils::{
+            create_simple_block_impl, create_simple_pivot_chain,
+            initialize_executed_synchronization_graph,
+            initialize_synchronization_graph,
+            initialize_synchronization_graph_with_params, insert_simple_blocks,
+        },
+        SynchronizationGraphNode,
+    },
+    transaction_pool::DEFAULT_MAX_BLOCK_GAS_LIMIT,
+    ConsensusGraph,
+};
+use cfx_types::{Address, BigEndianHash, H256, U256};
+use keylib::{public_to_address, Generator, Random};
+use primitives::{
+    Action, Block, BlockHeaderBuilder, EpochNumber, SignedTransaction,
+    Transaction,
 };
-use cfx_types::{BigEndianHash, H256, U256};
-use primitives::Block;
 use std::{
+    collections::HashMap,
     fs,
     sync::Arc,
     thread::sleep,
@@ -231,3 +255,573 @@ fn test_remove_expire_blocks() {
         sleep(Duration::from_millis(300));
     }
 }
+
+#[test]
+fn test_lazy_block_decoding() {
+    let sender = Random.generate().unwrap();
+    let transactions = (0..10)
+        .map(|nonce| {
+            Arc::new(
+                Transaction {
+                    nonce: U256::from(nonce),
+                    gas_price: U256::from(1),
+                    gas: U256::from(21000),
+                    action: Action::Call(Address::random()),
+                    value: U256::from(100),
+                    data: Vec::new(),
+                }
+                .sign(sender.secret()),
+            )
+        })
+        .collect();
+    let (_, block) = create_simple_block_impl(
+        H256::default(),
+        vec![],
+        1,
+        0,
+        U256::from(10),
+        1,
+    );
+    let block = Block::new(block.block_header, transactions);
+    let encoded = rlp::encode(&block);
+
+    let lazy_block: LazyBlock = rlp::decode(&encoded).unwrap();
+    assert_eq!(lazy_block.hash(), block.hash());
+    assert_eq!(lazy_block.transaction_count(), Ok(10));
+    assert_eq!(rlp::encode(&lazy_block), encoded);
+
+    let decoded = lazy_block.into_block().unwrap();
+    let expected: Block = rlp::decode(&encoded).unwrap();
+    assert_eq!(decoded.transactions, expected.transactions);
+
+    // A local block is sent without re-encoding it into a lazy one first.
+    let local_block = LazyBlock::from(Arc::new(block));
+    assert_eq!(local_block.hash(), expected.hash());
+    assert_eq!(local_block.transaction_count(), Ok(10));
+    assert_eq!(rlp::encode(&local_block), encoded);
+    assert_eq!(local_block.into_block().unwrap().hash(), expected.hash());
+}
+
+#[test]
+fn test_non_default_deferred_state_epoch_count() {
+    let db_dir = "./deferred_state_epoch_count_test_db";
+    let _ = fs::remove_dir_all(db_dir);
+    {
+        let (sync, consensus, genesis_block) =
+            initialize_synchronization_graph_with_params(
+                db_dir,
+                1,
+                1,
+                1,
+                1,
+                50000,
+                2, /* deferred_state_epoch_count */
+                Arc::new(DefaultGhastRule),
+            );
+        assert_eq!(consensus.deferred_state_epoch_count(), 2);
+        assert_eq!(consensus.data_man.deferred_state_epoch_count(), 2);
+
+        create_simple_pivot_chain(&sync, genesis_block.hash(), 6);
+        while consensus.best_epoch_number() < 6 {
+            sleep(Duration::from_millis(1));
+        }
+
+        // The next pivot block commits to the state of the epoch 2 epochs
+        // before it.
+        assert_eq!(consensus.best_epoch_number(), 6);
+        assert_eq!(consensus.best_state_epoch_number(), 5);
+    }
+    let _ = fs::remove_dir_all(db_dir);
+}
+
+#[test]
+fn test_reindex_unavailable_epoch() {
+    let db_dir = "./reindex_unavailable_epoch_test_db";
+    let _ = fs::remove_dir_all(db_dir);
+    {
+        let (sync, consensus, genesis_block) =
+            initialize_synchronization_graph(db_dir, 1, 1, 1, 1, 50000);
+        create_simple_pivot_chain(&sync, genesis_block.hash(), 3);
+        while consensus.best_epoch_number() < 3 {
+            sleep(Duration::from_millis(1));
+        }
+
+        // The epoch does not exist.
+        assert_eq!(
+            consensus.reindex_epoch(100),
+            Ok(ReindexedEpoch::Unavailable)
+        );
+        // The receipts are not computed in bench mode, and the epoch cannot
+        // be executed again without the state of its parent.
+        assert_eq!(consensus.reindex_epoch(3), Ok(ReindexedEpoch::Unavailable));
+    }
+    let _ = fs::remove_dir_all(db_dir);
+}
+
+/// Build a block on `parent` with the deferred state computed by
+/// `consensus`, so that the state is valid when the block is executed.
+fn create_executed_block(
+    consensus: &ConsensusGraph, parent: H256, height: u64,
+    transactions: Vec<Arc<SignedTransaction>>,
+) -> Block
+{
+    let (
+        blame,
+        state_root_with_aux_info,
+        state_root,
+        receipts_root,
+        logs_bloom_hash,
+    ) = consensus
+        .force_compute_blame_and_deferred_state_for_generation(&parent)
+        .unwrap();
+    let mut header = BlockHeaderBuilder::new()
+        .with_transactions_root(Block::compute_transaction_root(&transactions))
+        .with_parent_hash(parent)
+        .with_height(height)
+        .with_blame(blame)
+        .with_deferred_state_root_with_aux_info(state_root_with_aux_info)
+        .with_deferred_state_root(state_root)
+        .with_deferred_receipts_root(receipts_root)
+        .with_deferred_logs_bloom_hash(logs_bloom_hash)
+        .with_difficulty(U256::from(10))
+        .with_nonce(height)
+        .with_gas_limit(DEFAULT_MAX_BLOCK_GAS_LIMIT.into())
+        .build();
+    header.compute_hash();
+    header.pow_quality = U256::from(10);
+    Block::new(header, transactions)
+}
+
+#[test]
+fn test_reindex_executed_epoch() {
+    let db_dir = "./reindex_executed_epoch_test_db";
+    let _ = fs::remove_dir_all(db_dir);
+    {
+        let sender = Random.generate().unwrap();
+        let mut genesis_accounts = HashMap::new();
+        genesis_accounts.insert(
+            public_to_address(sender.public()),
+            U256::from(1_000_000_000_000_000_000u64),
+        );
+        let (sync, consensus, genesis_block) =
+            initialize_executed_synchronization_graph(db_dir, genesis_accounts);
+        let tx = Arc::new(
+            Transaction {
+                nonce: U256::zero(),
+                gas_price: U256::from(1),
+                gas: U256::from(21000),
+                value: U256::from(1),
+                action: Action::Call(Address::from_low_u64_be(1)),
+                data: vec![],
+            }
+            .sign(sender.secret()),
+        );
+
+        // The transaction is executed in epoch 1, whose receipts root is
+        // committed by the pivot block `DEFERRED_STATE_EPOCH_COUNT` epochs
+        // later.
+        let last_epoch = 1 + DEFERRED_STATE_EPOCH_COUNT;
+        let mut parent = genesis_block.hash();
+        let mut hashes = Vec::new();
+        for height in 1..=last_epoch {
+            let transactions =
+                if height == 1 { vec![tx.clone()] } else { vec![] };
+            let block = create_executed_block(
+                &consensus,
+                parent,
+                height,
+                transactions,
+            );
+            parent = block.hash();
+            hashes.push(parent);
+            insert_simple_blocks(&sync, vec![block]);
+            while consensus.best_epoch_number() < height {
+                sleep(Duration::from_millis(1));
+            }
+        }
+
+        let data_man = &consensus.data_man;
+        let epoch_hash = hashes[0];
+        let address = data_man.transaction_address_by_hash(&tx.hash(), false);
+        assert_eq!(address.as_ref().map(|a| a.block_hash), Some(epoch_hash));
+        let receipts = data_man
+            .block_execution_result_by_hash_with_epoch(
+                &epoch_hash,
+                &epoch_hash,
+                false, /* update_cache */
+            )
+            .unwrap()
+            .receipts;
+        let committed_root = *data_man
+            .block_header_by_hash(&hashes[DEFERRED_STATE_EPOCH_COUNT as usize])
+            .unwrap()
+            .deferred_receipts_root();
+        assert_eq!(
+            BlockHeaderBuilder::compute_block_receipts_root(&vec![
+                receipts.clone()
+            ]),
+            committed_root
+        );
+
+        // The transaction addresses are rebuilt from the receipts.
+        data_man.remove_transaction_address(&tx.hash());
+        assert!(data_man
+            .transaction_address_by_hash(&tx.hash(), false)
+            .is_none());
+        assert_eq!(
+            consensus.reindex_epoch(1),
+            Ok(ReindexedEpoch::Replayed { transactions: 1 })
+        );
+        assert_eq!(
+            data_man.transaction_address_by_hash(&tx.hash(), false),
+            address
+        );
+
+        // The receipts are recomputed if they are missing as well.
+        data_man.remove_transaction_address(&tx.hash());
+        data_man.remove_block_results(&epoch_hash, true /* remove_db */);
+        assert!(data_man
+            .block_execution_result_by_hash_with_epoch(
+                &epoch_hash,
+                &epoch_hash,
+                false, /* update_cache */
+            )
+            .is_none());
+        assert_eq!(consensus.reindex_epoch(1), Ok(ReindexedEpoch::Reexecuted));
+        assert_eq!(
+            data_man.transaction_address_by_hash(&tx.hash(), false),
+            address
+        );
+        let recomputed = data_man
+            .block_execution_result_by_hash_with_epoch(
+                &epoch_hash,
+                &epoch_hash,
+                false, /* update_cache */
+            )
+            .unwrap()
+            .receipts;
+        assert_eq!(recomputed, receipts);
+        assert_eq!(
+            BlockHeaderBuilder::compute_block_receipts_root(&vec![recomputed]),
+            committed_root
+        );
+    }
+    let _ = fs::remove_dir_all(db_dir);
+}
+
+#[test]
+fn test_get_epoch_blocks_with_info() {
+    let db_dir = "./get_epoch_blocks_with_info_test_db";
+    let _ = fs::remove_dir_all(db_dir);
+    {
+        let (sync, consensus, genesis_block) =
+            initialize_synchronization_graph(db_dir, 1, 1, 1, 1, 50000);
+        let genesis = genesis_block.hash();
+        let (b1, block1) =
+            create_simple_block_impl(genesis, vec![], 1, 1, U256::from(10), 1);
+        let (side, side_block) =
+            create_simple_block_impl(genesis, vec![], 1, 2, U256::from(10), 1);
+        let (b2, block2) =
+            create_simple_block_impl(b1, vec![side], 2, 3, U256::from(10), 1);
+        insert_simple_blocks(&sync, vec![block1, side_block, block2]);
+        while consensus.best_epoch_number() < 2 {
+            sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(
+            consensus.get_epoch_blocks_with_info(EpochNumber::Number(1)),
+            Ok(vec![EpochBlockInfo {
+                hash: b1,
+                is_pivot: true,
+                execution_index: 0,
+                referee_hashes: vec![],
+            }])
+        );
+        // The referenced side block is executed before the pivot block.
+        assert_eq!(
+            consensus.get_epoch_blocks_with_info(EpochNumber::Number(2)),
+            Ok(vec![
+                EpochBlockInfo {
+                    hash: side,
+                    is_pivot: false,
+                    execution_index: 0,
+                    referee_hashes: vec![],
+                },
+                EpochBlockInfo {
+                    hash: b2,
+                    is_pivot: true,
+                    execution_index: 1,
+                    referee_hashes: vec![side],
+                },
+            ])
+        );
+        assert!(consensus
+            .get_epoch_blocks_with_info(EpochNumber::Number(100))
+            .is_err());
+    }
+    let _ = fs::remove_dir_all(db_dir);
+}
+
+#[test]
+fn test_get_block_rewards() {
+    let db_dir = "./get_block_rewards_test_db";
+    let _ = fs::remove_dir_all(db_dir);
+    {
+        let sender = Random.generate().unwrap();
+        let mut genesis_accounts = HashMap::new();
+        genesis_accounts.insert(
+            public_to_address(sender.public()),
+            U256::from(1_000_000_000_000_000_000u64),
+        );
+        let (sync, consensus, genesis_block) =
+            initialize_executed_synchronization_graph(db_dir, genesis_accounts);
+        let tx = Arc::new(
+            Transaction {
+                nonce: U256::zero(),
+                gas_price: U256::from(1),
+                gas: U256::from(21000),
+                value: U256::from(1),
+                action: Action::Call(Address::from_low_u64_be(1)),
+                data: vec![],
+            }
+            .sign(sender.secret()),
+        );
+
+        // The rewards of epoch 1 are given when the pivot block
+        // `REWARD_EPOCH_COUNT` epochs later is executed, whose state is
+        // computed for the last block.
+        let reward_epoch = 1 + REWARD_EPOCH_COUNT;
+        let last_epoch = reward_epoch + DEFERRED_STATE_EPOCH_COUNT;
+        let mut parent = genesis_block.hash();
+        let mut hashes = Vec::new();
+        for height in 1..=last_epoch {
+            let transactions =
+                if height == 1 { vec![tx.clone()] } else { vec![] };
+            let block = create_executed_block(
+                &consensus,
+                parent,
+                height,
+                transactions,
+            );
+            parent = block.hash();
+            hashes.push(parent);
+            insert_simple_blocks(&sync, vec![block]);
+            while consensus.best_epoch_number() < height {
+                sleep(Duration::from_millis(1));
+            }
+        }
+
+        let base_reward =
+            U256::from(BASE_MINING_REWARD) * U256::from(CONFLUX_TOKEN);
+        let rewards =
+            consensus.get_block_rewards(EpochNumber::Number(1)).unwrap();
+        assert_eq!(rewards.len(), 1);
+        let reward = &rewards[0];
+        assert_eq!(reward.block_hash, hashes[0]);
+        assert_eq!(reward.author, Address::zero());
+        assert_eq!(reward.base_reward, base_reward);
+        assert_eq!(reward.anticone_penalty, U256::zero());
+        assert_eq!(reward.tx_fee, U256::from(21000));
+        assert_eq!(reward.total_reward(), base_reward + U256::from(21000));
+        // No other epoch has been rewarded in the state of the rewarding
+        // epoch, so the author has received exactly the total reward.
+        assert_eq!(
+            consensus.get_balance_by_epoch_hash(
+                Address::zero(),
+                &hashes[reward_epoch as usize - 1]
+            ),
+            Ok(reward.total_reward())
+        );
+        assert!(consensus.get_block_rewards(EpochNumber::Number(2)).is_err());
+    }
+    let _ = fs::remove_dir_all(db_dir);
+}
+
+#[test]
+fn test_get_block_by_epoch_and_index() {
+    let db_dir = "./get_block_by_epoch_and_index_test_db";
+    let _ = fs::remove_dir_all(db_dir);
+    {
+        let (sync, consensus, genesis_block) =
+            initialize_synchronization_graph(db_dir, 1, 1, 1, 1, 50000);
+        let genesis = genesis_block.hash();
+        let (b1, block1) =
+            create_simple_block_impl(genesis, vec![], 1, 1, U256::from(10), 1);
+        let (side, side_block) =
+            create_simple_block_impl(genesis, vec![], 1, 2, U256::from(10), 1);
+        let (b2, block2) =
+            create_simple_block_impl(b1, vec![side], 2, 3, U256::from(10), 1);
+        insert_simple_blocks(&sync, vec![block1, side_block, block2]);
+        while consensus.best_epoch_number() < 2 {
+            sleep(Duration::from_millis(1));
+        }
+
+        let epoch = EpochNumber::Number(2);
+        assert_eq!(
+            consensus
+                .get_pivot_block_of_epoch(epoch.clone())
+                .unwrap()
+                .hash(),
+            b2
+        );
+        // The referenced side block is executed before the pivot block.
+        assert_eq!(
+            consensus
+                .get_block_by_epoch_and_index(epoch.clone(), 0)
+                .unwrap()
+                .hash(),
+            side
+        );
+        assert_eq!(
+            consensus
+                .get_block_by_epoch_and_index(epoch.clone(), 1)
+                .unwrap()
+                .hash(),
+            b2
+        );
+        match consensus.get_block_by_epoch_and_index(epoch.clone(), 2) {
+            Err(ConsensusError::Other(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        match consensus.get_pivot_block_of_epoch(EpochNumber::Number(100)) {
+            Err(ConsensusError::InvalidEpoch(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        // The block body is missing, e.g. it has been pruned.
+        consensus
+            .data_man
+            .remove_block_body(&side, true /* remove_db */);
+        match consensus.get_block_by_epoch_and_index(epoch, 0) {
+            Err(ConsensusError::StateUnavailable(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+    let _ = fs::remove_dir_all(db_dir);
+}
+
+#[test]
+fn test_verify_unavailable_epoch_state() {
+    let db_dir = "./verify_unavailable_epoch_state_test_db";
+    let _ = fs::remove_dir_all(db_dir);
+    {
+        let (sync, consensus, genesis_block) =
+            initialize_synchronization_graph(db_dir, 1, 1, 1, 1, 50000);
+        create_simple_pivot_chain(&sync, genesis_block.hash(), 3);
+        while consensus.best_epoch_number() < 3 {
+            sleep(Duration::from_millis(1));
+        }
+
+        // The epoch set of the era genesis is not in the consensus graph.
+        assert!(consensus.verify_epoch_state(0).is_err());
+        // The state root of the epoch is not committed by any block yet.
+        assert!(consensus.verify_epoch_state(1).is_err());
+        assert!(consensus.verify_epoch_state(100).is_err());
+    }
+    let _ = fs::remove_dir_all(db_dir);
+}
+
+#[test]
+fn test_verify_blame_chain() {
+    let db_dir = "./verify_blame_chain_test_db";
+    let _ = fs::remove_dir_all(db_dir);
+    {
+        let (sync, consensus, genesis_block) =
+            initialize_synchronization_graph(db_dir, 1, 1, 1, 1, 50000);
+        // The block at height 4 blames the states of its parent and
+        // grandparent.
+        let blames = vec![0, 0, 0, 2];
+        let mut hashes = vec![genesis_block.hash()];
+        for (height, blame) in (1..).zip(blames) {
+            let mut builder = BlockHeaderBuilder::new();
+            let mut header = builder
+                .with_parent_hash(*hashes.last().unwrap())
+                .with_height(height)
+                .with_nonce(height)
+                .with_difficulty(U256::from(10))
+                .with_blame(blame)
+                .build();
+            header.compute_hash();
+            header.pow_quality = U256::from(10);
+            let block = Block::new(header, vec![]);
+            hashes.push(block.hash());
+            insert_simple_blocks(&sync, vec![block]);
+        }
+        while consensus.best_epoch_number() < 4 {
+            sleep(Duration::from_millis(1));
+        }
+        // Skip the state_valid computed by the consensus graph as if the
+        // blocks were being recovered from a checkpoint.
+        for hash in &hashes {
+            consensus
+                .pivot_block_state_valid_map
+                .lock()
+                .insert(*hash, true);
+        }
+
+        assert!(consensus.verify_blame_chain(4, 1).is_err());
+        assert_eq!(consensus.verify_blame_chain(1, 4), Ok(4));
+        assert_eq!(consensus.verify_blame_chain(3, 4), Ok(2));
+
+        let expected = vec![true, true, false, false, true];
+        for (hash, state_valid) in hashes.iter().zip(&expected) {
+            consensus
+                .data_man
+                .insert_blame_state_valid_to_db(hash, *state_valid);
+        }
+        assert_eq!(consensus.verify_blame_chain(0, 4), Ok(5));
+        // The state of the block at height 3 is blamed, so it cannot end a
+        // trusted blame chain.
+        let err = consensus.verify_blame_chain(0, 3).unwrap_err();
+        assert!(err.contains(&format!("{:?} at height 3", hashes[3])));
+
+        consensus
+            .data_man
+            .insert_blame_state_valid_to_db(&hashes[2], true);
+        assert!(consensus.verify_blame_chain(0, 4).is_err());
+    }
+    let _ = fs::remove_dir_all(db_dir);
+}
+
+#[test]
+fn test_check_consistency() {
+    let db_dir = "./check_consistency_test_db";
+    let _ = fs::remove_dir_all(db_dir);
+    {
+        let (sync, consensus, genesis_block) =
+            initialize_synchronization_graph(db_dir, 1, 1, 1, 1, 50000);
+        // Build a chain long enough for some epoch sets to be persisted.
+        let hashes =
+            create_simple_pivot_chain(&sync, genesis_block.hash(), 110);
+        let parent = *hashes.last().unwrap();
+        while *consensus.latest_inserted_block.lock() != parent {
+            sleep(Duration::from_millis(1));
+        }
+        assert_eq!(sync.check_consistency(100), Vec::<String>::new());
+
+        let missing = H256::from_low_u64_be(1);
+        {
+            let inner = consensus.inner.read();
+            let discrepancies =
+                inner.check_consistency(&[(missing, 5)], &parent, 100);
+            assert_eq!(discrepancies.len(), 1);
+            assert!(discrepancies[0].contains("not in consensus graph"));
+
+            let discrepancies = inner.check_consistency(&[], &missing, 100);
+            assert_eq!(discrepancies.len(), 1);
+            assert!(discrepancies[0].contains("not reachable"));
+        }
+
+        // Tamper with a persisted epoch set.
+        consensus
+            .data_man
+            .insert_epoch_set_hashes_to_db(5, &vec![missing]);
+        let discrepancies = sync.check_consistency(100);
+        assert_eq!(discrepancies.len(), 1);
+        assert!(discrepancies[0].starts_with("epoch set of epoch 5 "));
+        // Only the epoch sets of the last `max_epochs` persisted epochs are
+        // checked.
+        assert_eq!(sync.check_consistency(5), Vec::<String>::new());
+    }
+    let _ = fs::remove_dir_all(db_dir);
+}
@@ -1,10 +1,20 @@
 use crate::{
     block_data_manager::{BlockDataManager, DataManagerConfiguration, DbType},
     cache_config::CacheConfig,
-    consensus::{ConsensusConfig, ConsensusInnerConfig},
+    consensus::{
+        ConsensusConfig, ConsensusInnerConfig, DefaultGhastRule, GhastRule,
+        LcaHeightRefereeSelector,
+    },
     db::NUM_COLUMNS,
+    machine::new_machine_with_builtin,
     parameters::{
-        consensus::ERA_DEFAULT_CHECKPOINT_GAP, WORKER_COMPUTATION_PARALLELISM,
+        block::{ACCEPTABLE_TIME_DRIFT, VALID_TIME_DRIFT},
+        consensus::{
+            ANTICONE_CACHE_DEFAULT_MAX_ANTICONE_SIZE,
+            ANTICONE_CACHE_DEFAULT_STRIDE, DEFERRED_STATE_EPOCH_COUNT,
+            ERA_DEFAULT_CHECKPOINT_GAP, PASTSET_CACHE_DEFAULT_CAPACITY,
+        },
+        WORKER_COMPUTATION_PARALLELISM,
     },
     pow::ProofOfWorkConfig,
     state_exposer::{SharedStateExposer, StateExposer},
@@ -72,10 +82,131 @@ pub fn create_simple_block(
     )
 }
 
+/// Insert the headers and bodies of `blocks` into `sync` in order, without
+/// verifying them.
+pub fn insert_simple_blocks(sync: &SynchronizationGraph, blocks: Vec<Block>) {
+    for block in blocks {
+        sync.insert_block_header(
+            &mut block.block_header.clone(),
+            false, // need_to_verify
+            true,  // bench_mode
+            false, // insert_to_consensus
+            true,  // persistent
+        );
+        sync.insert_block(
+            block, false, // need_to_verify
+            true,  // persistent
+            false, // recover_from_db
+        );
+    }
+}
+
+/// Build a pivot chain of `length` blocks on top of `parent_hash`, insert it
+/// into `sync` and return the hashes of the new blocks.
+pub fn create_simple_pivot_chain(
+    sync: &SynchronizationGraph, parent_hash: H256, length: u64,
+) -> Vec<H256> {
+    let parent_height =
+        sync.block_header_by_hash(&parent_hash).unwrap().height();
+    let mut parent = parent_hash;
+    let mut hashes = Vec::new();
+    let mut blocks = Vec::new();
+    for height in parent_height + 1..=parent_height + length {
+        let (hash, block) = create_simple_block_impl(
+            parent,
+            vec![],
+            height,
+            height,
+            U256::from(10),
+            1,
+        );
+        hashes.push(hash);
+        blocks.push(block);
+        parent = hash;
+    }
+    insert_simple_blocks(sync, blocks);
+    hashes
+}
+
 pub fn initialize_synchronization_graph(
     db_dir: &str, alpha_den: u64, alpha_num: u64, beta: u64, h: u64,
     era_epoch_count: u64,
 ) -> (Arc<SynchronizationGraph>, Arc<ConsensusGraph>, Arc<Block>)
+{
+    initialize_synchronization_graph_with_ghast_rule(
+        db_dir,
+        alpha_den,
+        alpha_num,
+        beta,
+        h,
+        era_epoch_count,
+        Arc::new(DefaultGhastRule),
+    )
+}
+
+pub fn initialize_synchronization_graph_with_ghast_rule(
+    db_dir: &str, alpha_den: u64, alpha_num: u64, beta: u64, h: u64,
+    era_epoch_count: u64, ghast_rule: Arc<dyn GhastRule>,
+) -> (Arc<SynchronizationGraph>, Arc<ConsensusGraph>, Arc<Block>)
+{
+    initialize_synchronization_graph_with_params(
+        db_dir,
+        alpha_den,
+        alpha_num,
+        beta,
+        h,
+        era_epoch_count,
+        DEFERRED_STATE_EPOCH_COUNT,
+        ghast_rule,
+    )
+}
+
+pub fn initialize_synchronization_graph_with_params(
+    db_dir: &str, alpha_den: u64, alpha_num: u64, beta: u64, h: u64,
+    era_epoch_count: u64, deferred_state_epoch_count: u64,
+    ghast_rule: Arc<dyn GhastRule>,
+) -> (Arc<SynchronizationGraph>, Arc<ConsensusGraph>, Arc<Block>)
+{
+    initialize_synchronization_graph_impl(
+        db_dir,
+        alpha_den,
+        alpha_num,
+        beta,
+        h,
+        era_epoch_count,
+        deferred_state_epoch_count,
+        ghast_rule,
+        HashMap::new(),
+        true, /* bench_mode */
+    )
+}
+
+/// Initialize a synchronization graph whose blocks are executed and whose
+/// transaction addresses are recorded, with `genesis_accounts` funded in the
+/// genesis block.
+pub fn initialize_executed_synchronization_graph(
+    db_dir: &str, genesis_accounts: HashMap<Address, U256>,
+) -> (Arc<SynchronizationGraph>, Arc<ConsensusGraph>, Arc<Block>) {
+    initialize_synchronization_graph_impl(
+        db_dir,
+        1,
+        1,
+        1,
+        1,
+        50000,
+        DEFERRED_STATE_EPOCH_COUNT,
+        Arc::new(DefaultGhastRule),
+        genesis_accounts,
+        false, /* bench_mode */
+    )
+}
+
+fn initialize_synchronization_graph_impl(
+    db_dir: &str, alpha_den: u64, alpha_num: u64, beta: u64, h: u64,
+    era_epoch_count: u64, deferred_state_epoch_count: u64,
+    ghast_rule: Arc<dyn GhastRule>,
+    mut genesis_accounts: HashMap<Address, U256>, bench_mode: bool,
+) -> (Arc<SynchronizationGraph>, Arc<ConsensusGraph>, Arc<Block>)
 {
     let ledger_db = db::open_database(
         db_dir,
@@ -100,7 +231,6 @@ pub fn initialize_synchronization_graph(
         StorageConfiguration::default(),
     ));
 
-    let mut genesis_accounts = HashMap::new();
     genesis_accounts.insert(
         Address::from_str("0000000000000000000000000000000000000008").unwrap(),
         U256::from(0),
@@ -120,14 +250,30 @@ pub fn initialize_synchronization_graph(
         storage_manager,
         worker_thread_pool,
         DataManagerConfiguration::new(
-            false,  /* do not record transaction address */
-            250000, /* max cached tx count */
+            !bench_mode, /* record transaction address if executed */
+            250000,      /* max cached tx count */
             DbType::Rocksdb,
+            None, /* keep all block bodies */
+            deferred_state_epoch_count,
+            None, /* no cache warm-up */
         ),
     ));
 
-    let txpool =
-        Arc::new(TransactionPool::with_capacity(500_000, data_man.clone()));
+    let verification_config = VerificationConfig::new(
+        true,
+        1,
+        None,
+        None,
+        VALID_TIME_DRIFT,
+        ACCEPTABLE_TIME_DRIFT,
+    );
+    let txpool = Arc::new(TransactionPool::with_capacity(
+        500_000,
+        10,
+        1024 * 1024 * 1024,
+        data_man.clone(),
+        verification_config,
+    ));
     let statistics = Arc::new(Statistics::new());
     let state_exposer = SharedStateExposer::new(StateExposer::new());
 
@@ -151,10 +297,24 @@ pub fn initialize_synchronization_graph(
                 heavy_block_difficulty_ratio: h,
                 era_epoch_count,
                 era_checkpoint_gap: ERA_DEFAULT_CHECKPOINT_GAP,
+                deferred_state_epoch_count,
                 enable_optimistic_execution: false,
+                anticone_cache_stride: ANTICONE_CACHE_DEFAULT_STRIDE,
+                anticone_cache_max_anticone_size:
+                    ANTICONE_CACHE_DEFAULT_MAX_ANTICONE_SIZE,
+                adaptive_anticone_cache: false,
+                pastset_cache_capacity: PASTSET_CACHE_DEFAULT_CAPACITY,
             },
-            bench_mode: true, /* Set bench_mode to true so that we skip
-                               * execution */
+            bench_mode, /* Set bench_mode to true so that we skip execution */
+            construct_pivot_state_batch_size: 1000,
+            max_execution_queue_len: 0,
+            estimate_gas_upper_bound: DEFAULT_MAX_BLOCK_GAS_LIMIT,
+            gas_price_oracle: Default::default(),
+            ghast_rule,
+            referee_selector: Arc::new(LcaHeightRefereeSelector),
+            stable_hash_verification: Default::default(),
+            profile_inner_lock: false,
+            machine: Arc::new(new_machine_with_builtin()),
         },
         vm.clone(),
         txpool.clone(),
@@ -164,7 +324,6 @@ pub fn initialize_synchronization_graph(
         state_exposer.clone(),
     ));
 
-    let verification_config = VerificationConfig::new(true);
     let sync = Arc::new(SynchronizationGraph::new(
         consensus.clone(),
         verification_config,
@@ -1,11 +1,129 @@
 use crate::{statedb::StateDb, storage::Storage};
 use cfx_types::Address;
-use primitives::Account;
-use std::collections::hash_map::HashMap;
+use metrics::{Counter, CounterUsize, Gauge, GaugeUsize};
+use parking_lot::Mutex;
+use primitives::{Account, EpochId};
+use std::{
+    collections::hash_map::{Entry, HashMap},
+    sync::Arc,
+};
+
+lazy_static! {
+    static ref STATE_CACHE_HIT_COUNTER: Arc<dyn Counter<usize>> =
+        CounterUsize::register_with_group("txpool", "state_cache_hit");
+    static ref STATE_CACHE_MISS_COUNTER: Arc<dyn Counter<usize>> =
+        CounterUsize::register_with_group("txpool", "state_cache_miss");
+    static ref STATE_CACHE_INVALIDATED_COUNTER: Arc<dyn Counter<usize>> =
+        CounterUsize::register_with_group("txpool", "state_cache_invalidated");
+    static ref STATE_CACHE_SIZE_GAUGE: Arc<dyn Gauge<usize>> =
+        GaugeUsize::register_with_group("txpool", "state_cache_size");
+}
+
+/// The maximum number of accounts kept in the `StateCache`.
+const STATE_CACHE_CAPACITY: usize = 200_000;
+/// The maximum number of executed epochs whose touched accounts are kept
+/// until the cache advances to them.
+const MAX_PENDING_EPOCHS: usize = 1_000;
+
+/// The accounts of the best executed state read by the pool, which are kept
+/// across the validations until the accounts are touched by the execution of
+/// a later epoch. The accounts not existing in the state are cached as
+/// `None`.
+pub struct StateCache {
+    epoch: EpochId,
+    accounts: HashMap<Address, Option<Account>>,
+    /// The parent pivot epoch and the touched accounts of the epochs executed
+    /// and not yet advanced to.
+    pending: HashMap<EpochId, (EpochId, Vec<Address>)>,
+}
+
+impl StateCache {
+    pub fn new(epoch: EpochId) -> Self {
+        StateCache {
+            epoch,
+            accounts: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Records the accounts touched by the execution of `epoch` on the pivot
+    /// chain.
+    pub fn record_touched(
+        &mut self, epoch: EpochId, parent: EpochId, touched: Vec<Address>,
+    ) {
+        if self.pending.len() >= MAX_PENDING_EPOCHS {
+            // The epochs pending for long are no longer on the pivot chain,
+            // and the cache is cleared if they are needed.
+            self.pending.clear();
+        }
+        self.pending.insert(epoch, (parent, touched));
+    }
+
+    /// Moves the cache to the state of `epoch`, invalidating the accounts
+    /// touched by the epochs executed since the current one. The cache is
+    /// cleared if `epoch` is not a descendant of the current one through the
+    /// recorded epochs, e.g. when the pivot chain is switched.
+    pub fn advance(&mut self, epoch: EpochId) {
+        if epoch == self.epoch {
+            return;
+        }
+        let mut path = Vec::new();
+        let mut current = epoch;
+        while current != self.epoch {
+            match self.pending.get(&current) {
+                Some((parent, _)) => {
+                    path.push(current);
+                    current = *parent;
+                }
+                None => {
+                    path.clear();
+                    break;
+                }
+            }
+        }
+
+        if path.is_empty() || self.accounts.len() > STATE_CACHE_CAPACITY {
+            STATE_CACHE_INVALIDATED_COUNTER.inc(self.accounts.len());
+            self.accounts.clear();
+        }
+        for executed in &path {
+            let (_, touched) = self.pending.remove(executed).unwrap();
+            for address in &touched {
+                if self.accounts.remove(address).is_some() {
+                    STATE_CACHE_INVALIDATED_COUNTER.inc(1);
+                }
+            }
+        }
+        self.epoch = epoch;
+        STATE_CACHE_SIZE_GAUGE.update(self.accounts.len());
+    }
+
+    fn get(
+        &self, epoch: &EpochId, address: &Address,
+    ) -> Option<&Option<Account>> {
+        if *epoch != self.epoch {
+            return None;
+        }
+        self.accounts.get(address)
+    }
+
+    fn insert(
+        &mut self, epoch: &EpochId, address: &Address, account: Option<Account>,
+    ) {
+        if *epoch != self.epoch || self.accounts.len() >= STATE_CACHE_CAPACITY
+        {
+            return;
+        }
+        self.accounts.insert(*address, account);
+    }
+}
 
 pub struct AccountCache<'storage> {
     pub accounts: HashMap<Address, Account>,
     pub storage: StateDb<'storage>,
+    /// The `StateCache` shared across validations, and the epoch of
+    /// `storage`.
+    shared: Option<(&'storage Mutex<StateCache>, EpochId)>,
 }
 
 impl<'storage> AccountCache<'storage> {
@@ -13,19 +131,96 @@ impl<'storage> AccountCache<'storage> {
         AccountCache {
             accounts: HashMap::new(),
             storage: StateDb::new(storage),
+            shared: None,
+        }
+    }
+
+    /// Creates the cache of the state of `epoch`, which reads through
+    /// `state_cache` if it is at the same epoch.
+    pub fn with_state_cache(
+        storage: Storage<'storage>, epoch: EpochId,
+        state_cache: &'storage Mutex<StateCache>,
+    ) -> Self
+    {
+        AccountCache {
+            accounts: HashMap::new(),
+            storage: StateDb::new(storage),
+            shared: Some((state_cache, epoch)),
         }
     }
 
     pub fn get_account_mut(
         &mut self, address: &Address,
     ) -> Option<&mut Account> {
-        if !self.accounts.contains_key(&address) {
-            let account =
-                self.storage.get_account(&address).ok().and_then(|x| x);
+        if let Entry::Vacant(entry) = self.accounts.entry(*address) {
+            let account = match self.shared {
+                Some((state_cache, ref epoch)) => {
+                    let cached =
+                        state_cache.lock().get(epoch, address).cloned();
+                    match cached {
+                        Some(account) => {
+                            STATE_CACHE_HIT_COUNTER.inc(1);
+                            account
+                        }
+                        None => {
+                            STATE_CACHE_MISS_COUNTER.inc(1);
+                            let account = self.storage.get_account(address);
+                            if let Ok(ref account) = account {
+                                state_cache.lock().insert(
+                                    epoch,
+                                    address,
+                                    account.clone(),
+                                );
+                            }
+                            account.ok().and_then(|x| x)
+                        }
+                    }
+                }
+                None => self.storage.get_account(address).ok().and_then(|x| x),
+            };
             if let Some(account) = account {
-                self.accounts.insert((*address).clone(), account);
+                entry.insert(account);
             }
         }
         self.accounts.get_mut(&address)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::StateCache;
+    use cfx_types::{Address, H256};
+    use primitives::Account;
+
+    #[test]
+    fn test_state_cache_advance() {
+        let (e0, e1, e2, e3) = (
+            H256::from_low_u64_be(0),
+            H256::from_low_u64_be(1),
+            H256::from_low_u64_be(2),
+            H256::from_low_u64_be(3),
+        );
+        let (alice, bob) =
+            (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        let mut cache = StateCache::new(e0);
+        cache.insert(&e0, &alice, None);
+        cache.insert(
+            &e0,
+            &bob,
+            Some(Account::new_empty_with_balance(&bob, &0.into(), &0.into())),
+        );
+        // Only the current epoch is cached.
+        cache.insert(&e1, &alice, None);
+        assert!(cache.get(&e1, &alice).is_none());
+
+        cache.record_touched(e1, e0, vec![alice]);
+        cache.record_touched(e2, e1, vec![]);
+        cache.advance(e2);
+        assert!(cache.get(&e2, &alice).is_none());
+        assert!(cache.get(&e2, &bob).is_some());
+
+        // `e3` is not recorded, e.g. on another pivot chain.
+        cache.advance(e3);
+        assert!(cache.get(&e3, &bob).is_none());
+    }
+}
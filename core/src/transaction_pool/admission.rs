@@ -0,0 +1,434 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use cfx_types::{Address, U256};
+use primitives::{Action, SignedTransaction, TransactionWithSignature};
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+use std::{collections::BTreeSet, sync::Arc};
+
+/// The state of the pool which the admission of a transaction depends on.
+pub struct AdmissionContext {
+    /// The number of transactions in the pool in percent of its capacity.
+    pub fullness_percent: usize,
+}
+
+/// A rule to admit the transactions into the pool, in addition to the ones
+/// always verified by the pool. `check_unsigned` is evaluated before the
+/// public keys of the senders are recovered, so the rules not depending on
+/// the sender should be checked there to save the recovery of rejected
+/// transactions.
+pub trait AdmissionFilter: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn check_unsigned(
+        &self, _tx: &TransactionWithSignature, _context: &AdmissionContext,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn check_signed(&self, _tx: &SignedTransaction) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// The built-in admission rules, which can be changed at runtime. The rules
+/// left empty admit all the transactions.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AdmissionPolicy {
+    pub max_gas: Option<U256>,
+    pub max_data_size: Option<usize>,
+    pub sender_blacklist: BTreeSet<Address>,
+    /// Only admit the transactions of these senders if set.
+    pub sender_whitelist: Option<BTreeSet<Address>>,
+    pub recipient_blacklist: BTreeSet<Address>,
+    /// Only admit the calls to these recipients if set. Contract creations
+    /// are not restricted.
+    pub recipient_whitelist: Option<BTreeSet<Address>>,
+    /// The minimum gas prices by the fullness of the pool, as pairs of the
+    /// fullness in percent and the minimum gas price from that fullness.
+    pub min_gas_price_schedule: Vec<(usize, U256)>,
+}
+
+impl AdmissionPolicy {
+    /// The minimum gas price to admit a transaction at `fullness_percent`.
+    fn min_gas_price(&self, fullness_percent: usize) -> Option<U256> {
+        self.min_gas_price_schedule
+            .iter()
+            .filter(|(fullness, _)| *fullness <= fullness_percent)
+            .max_by_key(|(fullness, _)| *fullness)
+            .map(|(_, gas_price)| *gas_price)
+    }
+}
+
+impl AdmissionFilter for AdmissionPolicy {
+    fn name(&self) -> &'static str { "policy" }
+
+    fn check_unsigned(
+        &self, tx: &TransactionWithSignature, context: &AdmissionContext,
+    ) -> Result<(), String> {
+        if let Some(max_gas) = self.max_gas {
+            if tx.gas > max_gas {
+                return Err(format!(
+                    "transaction gas {} exceeds the admitted value {}",
+                    tx.gas, max_gas
+                ));
+            }
+        }
+
+        if let Some(max_data_size) = self.max_data_size {
+            if tx.data.len() > max_data_size {
+                return Err(format!(
+                    "transaction data of {} bytes exceeds the admitted size {}",
+                    tx.data.len(),
+                    max_data_size
+                ));
+            }
+        }
+
+        if let Action::Call(ref recipient) = tx.action {
+            if self.recipient_blacklist.contains(recipient) {
+                return Err(format!(
+                    "recipient {:?} is blacklisted",
+                    recipient
+                ));
+            }
+            if let Some(ref whitelist) = self.recipient_whitelist {
+                if !whitelist.contains(recipient) {
+                    return Err(format!(
+                        "recipient {:?} is not whitelisted",
+                        recipient
+                    ));
+                }
+            }
+        }
+
+        if let Some(min_gas_price) =
+            self.min_gas_price(context.fullness_percent)
+        {
+            if tx.gas_price < min_gas_price {
+                return Err(format!(
+                    "transaction gas price {} less than {} when the pool is \
+                     {}% full",
+                    tx.gas_price, min_gas_price, context.fullness_percent
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_signed(&self, tx: &SignedTransaction) -> Result<(), String> {
+        if self.sender_blacklist.contains(&tx.sender) {
+            return Err(format!("sender {:?} is blacklisted", tx.sender));
+        }
+        if let Some(ref whitelist) = self.sender_whitelist {
+            if !whitelist.contains(&tx.sender) {
+                return Err(format!(
+                    "sender {:?} is not whitelisted",
+                    tx.sender
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Append `value` as a list of zero or one item.
+fn append_optional<T, F>(s: &mut RlpStream, value: Option<&T>, append: F)
+where F: Fn(&mut RlpStream, &T) {
+    match value {
+        Some(value) => {
+            s.begin_list(1);
+            append(s, value);
+        }
+        None => {
+            s.begin_list(0);
+        }
+    }
+}
+
+/// Decode the list of zero or one item appended by `append_optional`.
+fn decode_optional<T, F>(
+    rlp: &Rlp, decode: F,
+) -> Result<Option<T>, DecoderError>
+where F: Fn(&Rlp) -> Result<T, DecoderError> {
+    match rlp.item_count()? {
+        0 => Ok(None),
+        1 => Ok(Some(decode(&rlp.at(0)?)?)),
+        _ => Err(DecoderError::RlpIncorrectListLen),
+    }
+}
+
+fn append_addresses(s: &mut RlpStream, addresses: &BTreeSet<Address>) {
+    s.begin_list(addresses.len());
+    for address in addresses {
+        s.append(address);
+    }
+}
+
+fn decode_addresses(rlp: &Rlp) -> Result<BTreeSet<Address>, DecoderError> {
+    Ok(rlp.as_list::<Address>()?.into_iter().collect())
+}
+
+impl Encodable for AdmissionPolicy {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(7);
+        append_optional(s, self.max_gas.as_ref(), |s, max_gas| {
+            s.append(max_gas);
+        });
+        append_optional(s, self.max_data_size.as_ref(), |s, max_data_size| {
+            s.append(&(*max_data_size as u64));
+        });
+        append_addresses(s, &self.sender_blacklist);
+        append_optional(s, self.sender_whitelist.as_ref(), append_addresses);
+        append_addresses(s, &self.recipient_blacklist);
+        append_optional(s, self.recipient_whitelist.as_ref(), append_addresses);
+        s.begin_list(self.min_gas_price_schedule.len());
+        for (fullness, gas_price) in &self.min_gas_price_schedule {
+            s.begin_list(2);
+            s.append(&(*fullness as u64));
+            s.append(gas_price);
+        }
+    }
+}
+
+impl Decodable for AdmissionPolicy {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 7 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        let mut min_gas_price_schedule = Vec::new();
+        for entry in rlp.at(6)?.iter() {
+            let fullness: u64 = entry.val_at(0)?;
+            min_gas_price_schedule.push((fullness as usize, entry.val_at(1)?));
+        }
+        Ok(AdmissionPolicy {
+            max_gas: decode_optional(&rlp.at(0)?, |rlp| rlp.as_val())?,
+            max_data_size: decode_optional(&rlp.at(1)?, |rlp| {
+                rlp.as_val::<u64>().map(|size| size as usize)
+            })?,
+            sender_blacklist: decode_addresses(&rlp.at(2)?)?,
+            sender_whitelist: decode_optional(&rlp.at(3)?, decode_addresses)?,
+            recipient_blacklist: decode_addresses(&rlp.at(4)?)?,
+            recipient_whitelist: decode_optional(
+                &rlp.at(5)?,
+                decode_addresses,
+            )?,
+            min_gas_price_schedule,
+        })
+    }
+}
+
+/// The built-in policy followed by the custom filters, which are evaluated
+/// in order until one of them rejects the transaction.
+#[derive(Clone, Default)]
+pub struct AdmissionFilterChain {
+    policy: AdmissionPolicy,
+    filters: Vec<Arc<dyn AdmissionFilter>>,
+}
+
+impl AdmissionFilterChain {
+    pub fn policy(&self) -> &AdmissionPolicy { &self.policy }
+
+    pub fn set_policy(&mut self, policy: AdmissionPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn add_filter(&mut self, filter: Arc<dyn AdmissionFilter>) {
+        self.filters.push(filter);
+    }
+
+    pub fn check_unsigned(
+        &self, tx: &TransactionWithSignature, context: &AdmissionContext,
+    ) -> Result<(), String> {
+        self.policy.check_unsigned(tx, context)?;
+        for filter in &self.filters {
+            filter
+                .check_unsigned(tx, context)
+                .map_err(|e| format!("{}: {}", filter.name(), e))?;
+        }
+        Ok(())
+    }
+
+    pub fn check_signed(&self, tx: &SignedTransaction) -> Result<(), String> {
+        self.policy.check_signed(tx)?;
+        for filter in &self.filters {
+            filter
+                .check_signed(tx)
+                .map_err(|e| format!("{}: {}", filter.name(), e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AdmissionContext, AdmissionFilter, AdmissionFilterChain,
+        AdmissionPolicy,
+    };
+    use cfx_types::{Address, U256};
+    use keylib::{Generator, KeyPair, Random};
+    use primitives::{Action, SignedTransaction, Transaction};
+    use std::{collections::BTreeSet, sync::Arc};
+
+    fn new_test_tx(
+        sender: &KeyPair, gas_price: u64, action: Action,
+    ) -> SignedTransaction {
+        Transaction {
+            nonce: U256::zero(),
+            gas_price: gas_price.into(),
+            gas: 50000.into(),
+            action,
+            value: U256::zero(),
+            data: vec![0; 100],
+        }
+        .sign(sender.secret())
+    }
+
+    fn context(fullness_percent: usize) -> AdmissionContext {
+        AdmissionContext { fullness_percent }
+    }
+
+    struct RejectCreations;
+
+    impl AdmissionFilter for RejectCreations {
+        fn name(&self) -> &'static str { "no_create" }
+
+        fn check_unsigned(
+            &self, tx: &primitives::TransactionWithSignature,
+            _context: &AdmissionContext,
+        ) -> Result<(), String>
+        {
+            match tx.action {
+                Action::Create => Err("contract creation".into()),
+                Action::Call(_) => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_policy() {
+        let sender = Random.generate().unwrap();
+        let tx = new_test_tx(&sender, 1, Action::Create);
+        let policy = AdmissionPolicy::default();
+        assert!(policy.check_unsigned(&tx, &context(100)).is_ok());
+        assert!(policy.check_signed(&tx).is_ok());
+    }
+
+    #[test]
+    fn test_unsigned_rules() {
+        let sender = Random.generate().unwrap();
+        let recipient = Address::from_low_u64_be(1);
+        let tx = new_test_tx(&sender, 10, Action::Call(recipient));
+
+        let mut policy = AdmissionPolicy::default();
+        policy.max_gas = Some(40000.into());
+        assert!(policy.check_unsigned(&tx, &context(0)).is_err());
+
+        let mut policy = AdmissionPolicy::default();
+        policy.max_data_size = Some(99);
+        assert!(policy.check_unsigned(&tx, &context(0)).is_err());
+        policy.max_data_size = Some(100);
+        assert!(policy.check_unsigned(&tx, &context(0)).is_ok());
+
+        let mut policy = AdmissionPolicy::default();
+        policy.recipient_blacklist.insert(recipient);
+        assert!(policy.check_unsigned(&tx, &context(0)).is_err());
+
+        // The whitelist of recipients doesn't restrict contract creations.
+        let mut policy = AdmissionPolicy::default();
+        policy.recipient_whitelist =
+            Some(vec![Address::from_low_u64_be(2)].into_iter().collect());
+        assert!(policy.check_unsigned(&tx, &context(0)).is_err());
+        let creation = new_test_tx(&sender, 10, Action::Create);
+        assert!(policy.check_unsigned(&creation, &context(0)).is_ok());
+    }
+
+    #[test]
+    fn test_min_gas_price_schedule() {
+        let sender = Random.generate().unwrap();
+        let mut policy = AdmissionPolicy::default();
+        policy.min_gas_price_schedule = vec![(80, 100.into()), (50, 10.into())];
+
+        let tx = new_test_tx(&sender, 20, Action::Create);
+        assert!(policy.check_unsigned(&tx, &context(49)).is_ok());
+        assert!(policy.check_unsigned(&tx, &context(50)).is_ok());
+        assert!(policy.check_unsigned(&tx, &context(80)).is_err());
+
+        let tx = new_test_tx(&sender, 5, Action::Create);
+        assert!(policy.check_unsigned(&tx, &context(49)).is_ok());
+        assert!(policy.check_unsigned(&tx, &context(50)).is_err());
+    }
+
+    #[test]
+    fn test_sender_rules() {
+        let sender = Random.generate().unwrap();
+        let other = Random.generate().unwrap();
+        let tx = new_test_tx(&sender, 1, Action::Create);
+
+        let mut policy = AdmissionPolicy::default();
+        policy.sender_blacklist.insert(sender.address());
+        assert!(policy.check_signed(&tx).is_err());
+
+        let mut policy = AdmissionPolicy::default();
+        policy.sender_whitelist =
+            Some(vec![other.address()].into_iter().collect());
+        assert!(policy.check_signed(&tx).is_err());
+        policy
+            .sender_whitelist
+            .as_mut()
+            .unwrap()
+            .insert(sender.address());
+        assert!(policy.check_signed(&tx).is_ok());
+    }
+
+    #[test]
+    fn test_filter_chain() {
+        let sender = Random.generate().unwrap();
+        let call =
+            new_test_tx(&sender, 1, Action::Call(Address::from_low_u64_be(1)));
+        let creation = new_test_tx(&sender, 1, Action::Create);
+
+        let mut chain = AdmissionFilterChain::default();
+        chain.add_filter(Arc::new(RejectCreations));
+        assert!(chain.check_unsigned(&call, &context(0)).is_ok());
+        let err = chain.check_unsigned(&creation, &context(0)).unwrap_err();
+        assert!(err.starts_with("no_create: "));
+
+        // The policy is evaluated before the custom filters, and replacing it
+        // keeps the filters.
+        let mut policy = AdmissionPolicy::default();
+        policy.sender_blacklist.insert(sender.address());
+        chain.set_policy(policy.clone());
+        assert_eq!(chain.policy(), &policy);
+        assert!(chain.check_signed(&call).is_err());
+        assert!(chain.check_unsigned(&creation, &context(0)).is_err());
+    }
+
+    #[test]
+    fn test_policy_rlp() {
+        let policy = AdmissionPolicy::default();
+        assert_eq!(
+            rlp::decode::<AdmissionPolicy>(&rlp::encode(&policy)),
+            Ok(policy)
+        );
+
+        let addresses: BTreeSet<Address> =
+            (1..4).map(Address::from_low_u64_be).collect();
+        let policy = AdmissionPolicy {
+            max_gas: Some(1_000_000.into()),
+            max_data_size: Some(1024),
+            sender_blacklist: addresses.clone(),
+            sender_whitelist: Some(BTreeSet::new()),
+            recipient_blacklist: BTreeSet::new(),
+            recipient_whitelist: Some(addresses),
+            min_gas_price_schedule: vec![(50, 10.into()), (80, 100.into())],
+        };
+        assert_eq!(
+            rlp::decode::<AdmissionPolicy>(&rlp::encode(&policy)),
+            Ok(policy)
+        );
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use primitives::{SignedTransaction, TransactionWithSignature};
+use rustc_hex::{FromHex, ToHex};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// Append-only journal of the transactions in the transaction pool, so that
+/// pending transactions survive restarts. Each line is a hex-encoded RLP
+/// transaction. Once the journal has grown to twice `max_size` records, it is
+/// rewritten with at most `max_size` transactions that are still in the pool.
+pub struct TransactionJournal {
+    path: PathBuf,
+    max_size: usize,
+    file: Option<File>,
+    size: usize,
+    loaded: bool,
+}
+
+impl TransactionJournal {
+    pub fn new(path: PathBuf, max_size: usize) -> Self {
+        TransactionJournal {
+            path,
+            max_size,
+            file: None,
+            size: 0,
+            loaded: false,
+        }
+    }
+
+    /// Whether the journal should be rewritten. Rotating only after it has
+    /// doubled avoids rewriting the whole file on every append once the pool
+    /// holds more than `max_size` transactions.
+    pub fn should_rotate(&self) -> bool {
+        self.loaded && self.size >= 2 * self.max_size
+    }
+
+    /// Load all transactions in the journal. Corrupted records, e.g. the last
+    /// record written partially before a crash, are skipped. The journal is
+    /// only loaded once, later calls return nothing.
+    pub fn load(&mut self) -> Vec<TransactionWithSignature> {
+        if self.loaded {
+            return Vec::new();
+        }
+        self.loaded = true;
+
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut txs = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("Failed to read tx pool journal: {:?}", e);
+                    break;
+                }
+            };
+
+            let tx = line
+                .trim()
+                .from_hex()
+                .map_err(|e| format!("{:?}", e))
+                .and_then(|bytes| {
+                    rlp::decode(&bytes).map_err(|e| format!("{:?}", e))
+                });
+            match tx {
+                Ok(tx) => txs.push(tx),
+                Err(e) => {
+                    debug!("Skip corrupted tx pool journal record: {}", e)
+                }
+            }
+        }
+
+        self.size += txs.len();
+        txs
+    }
+
+    pub fn append(&mut self, txs: &[Arc<SignedTransaction>]) {
+        if txs.is_empty() {
+            return;
+        }
+
+        if self.file.is_none() {
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+            {
+                Ok(file) => self.file = Some(file),
+                Err(e) => {
+                    warn!("Failed to open tx pool journal: {:?}", e);
+                    return;
+                }
+            }
+        }
+
+        let mut content = String::new();
+        for tx in txs {
+            content.push_str(&rlp::encode(&tx.transaction).to_hex());
+            content.push('\n');
+        }
+
+        match self.file.as_mut().unwrap().write_all(content.as_bytes()) {
+            Ok(_) => self.size += txs.len(),
+            Err(e) => warn!("Failed to write tx pool journal: {:?}", e),
+        }
+    }
+
+    /// Rewrite the journal with `txs`, which should be ordered by nonce for
+    /// each sender, so that keeping the first `max_size` transactions keeps a
+    /// nonce prefix of every sender. The journal is not rotated before it is
+    /// loaded, as the transactions in it are not replayed yet.
+    pub fn rotate(&mut self, mut txs: Vec<Arc<SignedTransaction>>) {
+        if !self.loaded {
+            debug!("Skip rotating tx pool journal before it is loaded");
+            return;
+        }
+        txs.truncate(self.max_size);
+
+        let mut tmp_path = self.path.clone();
+        tmp_path.set_extension("tmp");
+        self.file = None;
+        self.size = 0;
+
+        let mut content = String::new();
+        for tx in &txs {
+            content.push_str(&rlp::encode(&tx.transaction).to_hex());
+            content.push('\n');
+        }
+
+        let result = fs::write(&tmp_path, content)
+            .and_then(|_| fs::rename(&tmp_path, &self.path));
+        match result {
+            Ok(_) => self.size = txs.len(),
+            Err(e) => warn!("Failed to rotate tx pool journal: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransactionJournal;
+    use cfx_types::{Address, U256};
+    use keylib::{Generator, KeyPair, Random};
+    use primitives::{Action, SignedTransaction, Transaction};
+    use std::{env, fs, io::Write, path::PathBuf, sync::Arc};
+
+    fn new_test_tx(sender: &KeyPair, nonce: usize) -> Arc<SignedTransaction> {
+        Arc::new(
+            Transaction {
+                nonce: U256::from(nonce),
+                gas_price: U256::from(1),
+                gas: U256::from(50000),
+                action: Action::Call(Address::random()),
+                value: U256::zero(),
+                data: Vec::new(),
+            }
+            .sign(sender.secret()),
+        )
+    }
+
+    /// Run `f` with the path of a fresh journal, which is removed afterwards.
+    fn with_journal_path<F: FnOnce(PathBuf)>(name: &str, f: F) {
+        let path = env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        f(path.clone());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_append_and_load() {
+        with_journal_path("tx_pool_journal_append_test", |path| {
+            let sender = Random.generate().unwrap();
+            let txs: Vec<_> = (0..3).map(|i| new_test_tx(&sender, i)).collect();
+            let mut journal = TransactionJournal::new(path.clone(), 10);
+            journal.append(&txs[..2]);
+            journal.append(&txs[2..]);
+
+            let mut journal = TransactionJournal::new(path, 10);
+            let loaded = journal.load();
+            assert_eq!(loaded.len(), 3);
+            for (tx, loaded) in txs.iter().zip(&loaded) {
+                assert_eq!(tx.transaction, *loaded);
+            }
+            // The journal is only loaded once.
+            assert!(journal.load().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_load_skips_corrupted_records() {
+        with_journal_path("tx_pool_journal_corrupt_test", |path| {
+            let sender = Random.generate().unwrap();
+            let txs: Vec<_> = (0..2).map(|i| new_test_tx(&sender, i)).collect();
+            let mut journal = TransactionJournal::new(path.clone(), 10);
+            journal.append(&txs);
+            drop(journal);
+            // A record written partially before a crash.
+            let mut file =
+                fs::OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(b"f8").unwrap();
+
+            let loaded = TransactionJournal::new(path, 10).load();
+            assert_eq!(loaded.len(), 2);
+            assert_eq!(loaded[1], txs[1].transaction);
+        });
+    }
+
+    #[test]
+    fn test_rotate() {
+        with_journal_path("tx_pool_journal_rotate_test", |path| {
+            let sender = Random.generate().unwrap();
+            let txs: Vec<_> = (0..4).map(|i| new_test_tx(&sender, i)).collect();
+            let mut journal = TransactionJournal::new(path.clone(), 2);
+            journal.append(&txs);
+
+            // The transactions in the journal are not replayed yet.
+            assert!(!journal.should_rotate());
+            journal.rotate(vec![txs[3].clone()]);
+            let loaded = TransactionJournal::new(path.clone(), 2).load();
+            assert_eq!(loaded.len(), 4);
+
+            assert_eq!(journal.load().len(), 4);
+            assert!(journal.should_rotate());
+            // The lowest nonces are kept.
+            journal.rotate(txs.clone());
+            assert!(!journal.should_rotate());
+            let loaded = TransactionJournal::new(path, 2).load();
+            assert_eq!(loaded, vec![
+                txs[0].transaction.clone(),
+                txs[1].transaction.clone()
+            ]);
+
+            journal.append(&txs[2..3]);
+            assert!(!journal.should_rotate());
+            journal.append(&txs[3..]);
+            assert!(journal.should_rotate());
+        });
+    }
+}
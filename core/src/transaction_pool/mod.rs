@@ -2,32 +2,56 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
+mod admission;
 mod impls;
 
 #[cfg(test)]
 mod test_treap;
 
 mod account_cache;
+mod journal;
 mod nonce_pool;
+mod packing_strategy;
 mod transaction_pool_inner;
 
 extern crate rand;
 
-pub use self::impls::TreapMap;
+pub use self::{
+    admission::{
+        AdmissionContext, AdmissionFilter, AdmissionFilterChain,
+        AdmissionPolicy,
+    },
+    impls::TreapMap,
+    packing_strategy::{
+        new_packing_strategy, GasPriceStrategy, PackingQueue, PackingStrategy,
+        ReadyTransactions, SenderRoundRobinStrategy, WeightedRandomStrategy,
+    },
+    transaction_pool_inner::EvictionStats,
+};
 use crate::{
     block_data_manager::BlockDataManager, consensus::BestInformation,
-    executive, vm,
+    executive, verification::VerificationConfig, vm,
 };
-use account_cache::AccountCache;
+use account_cache::{AccountCache, StateCache};
 use cfx_types::{Address, H256, U256};
+use journal::TransactionJournal;
+use keylib::public_to_address;
 use metrics::{
-    register_meter_with_group, Gauge, GaugeUsize, Meter, MeterTimer,
+    register_meter_with_group, Counter, CounterUsize, Gauge, GaugeUsize, Meter,
+    MeterTimer,
 };
 use parking_lot::{Mutex, RwLock};
 use primitives::{
     Account, Action, EpochId, SignedTransaction, TransactionWithSignature,
 };
-use std::{collections::hash_map::HashMap, mem, ops::DerefMut, sync::Arc};
+use std::{
+    collections::hash_map::HashMap,
+    mem,
+    ops::DerefMut,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use transaction_pool_inner::TransactionPoolInner;
 
 lazy_static! {
@@ -49,42 +73,159 @@ lazy_static! {
         register_meter_with_group("timer", "tx_pool::insert_new_tx");
     static ref TX_POOL_RECOVER_TIMER: Arc<dyn Meter> =
         register_meter_with_group("timer", "tx_pool::recover_public");
+    static ref REORG_RECYCLED_COUNTER: Arc<dyn Counter<usize>> =
+        CounterUsize::register_with_group("txpool", "reorg_recycled");
+    static ref REORG_DROPPED_COUNTER: Arc<dyn Counter<usize>> =
+        CounterUsize::register_with_group("txpool", "reorg_dropped");
+    static ref ADMISSION_REJECTED_COUNTER: Arc<dyn Counter<usize>> =
+        CounterUsize::register_with_group("txpool", "admission_rejected");
 }
 
 pub const DEFAULT_MIN_TRANSACTION_GAS_PRICE: u64 = 1;
 pub const DEFAULT_MAX_TRANSACTION_GAS_LIMIT: u64 = 100_000_000;
 pub const DEFAULT_MAX_BLOCK_GAS_LIMIT: u64 = 30_000 * 100_000;
+/// Local transactions are propagated again with this interval until they are
+/// executed or removed from pool.
+const LOCAL_TRANSACTION_REBROADCAST_INTERVAL: Duration =
+    Duration::from_secs(60);
 
 pub struct TransactionPool {
     inner: RwLock<TransactionPoolInner>,
     to_propagate_trans: Arc<RwLock<HashMap<H256, Arc<SignedTransaction>>>>,
     pub data_man: Arc<BlockDataManager>,
     spec: vm::Spec,
+    verification_config: VerificationConfig,
     best_executed_epoch: Mutex<EpochId>,
     consensus_best_info: Mutex<Arc<BestInformation>>,
     set_tx_requests: Mutex<Vec<Arc<SignedTransaction>>>,
     recycle_tx_requests: Mutex<Vec<Arc<SignedTransaction>>>,
+    reorg_tx_requests: Mutex<Vec<Arc<SignedTransaction>>>,
+    journal: Mutex<Option<TransactionJournal>>,
+    last_local_rebroadcast: Mutex<Instant>,
+    /// The admission rules, which are replaced as a whole when changed so
+    /// that the insertion doesn't hold the lock while recovering the senders.
+    admission: RwLock<Arc<AdmissionFilterChain>>,
+    state_cache: Mutex<StateCache>,
 }
 
 pub type SharedTransactionPool = Arc<TransactionPool>;
 
 impl TransactionPool {
     pub fn with_capacity(
-        capacity: usize, data_man: Arc<BlockDataManager>,
-    ) -> Self {
+        capacity: usize, min_replace_bump_percent: u64, memory_budget: usize,
+        data_man: Arc<BlockDataManager>,
+        verification_config: VerificationConfig,
+    ) -> Self
+    {
         let genesis_hash = data_man.genesis_block.hash();
+        let mut admission = AdmissionFilterChain::default();
+        if let Some(policy) = data_man.txpool_admission_policy_from_db() {
+            info!("Loaded tx pool admission policy {:?}", policy);
+            admission.set_policy(policy);
+        }
         TransactionPool {
-            inner: RwLock::new(TransactionPoolInner::with_capacity(capacity)),
+            inner: RwLock::new(TransactionPoolInner::with_capacity(
+                capacity,
+                min_replace_bump_percent,
+                memory_budget,
+            )),
             to_propagate_trans: Arc::new(RwLock::new(HashMap::new())),
             data_man,
             spec: vm::Spec::new_spec(),
+            verification_config,
             best_executed_epoch: Mutex::new(genesis_hash),
             consensus_best_info: Mutex::new(Arc::new(Default::default())),
             set_tx_requests: Mutex::new(Default::default()),
             recycle_tx_requests: Mutex::new(Default::default()),
+            reorg_tx_requests: Mutex::new(Default::default()),
+            journal: Mutex::new(None),
+            last_local_rebroadcast: Mutex::new(Instant::now()),
+            admission: RwLock::new(Arc::new(admission)),
+            state_cache: Mutex::new(StateCache::new(genesis_hash)),
         }
     }
 
+    /// Persist the transactions inserted into pool in the journal at `path`,
+    /// which holds at most `max_size` transactions. The transactions already
+    /// in the journal are inserted back by `replay_journal`.
+    pub fn enable_journal(&self, path: PathBuf, max_size: usize) {
+        *self.journal.lock() = Some(TransactionJournal::new(path, max_size));
+    }
+
+    /// Insert the transactions in journal back into pool. It should be called
+    /// once the best state is recovered, so that transactions with stale
+    /// nonces are discarded.
+    pub fn replay_journal(&self) {
+        let txs = match self.journal.lock().as_mut() {
+            Some(journal) => journal.load(),
+            None => return,
+        };
+
+        let total = txs.len();
+        let mut state_nonces = HashMap::new();
+        let txs: Vec<TransactionWithSignature> = txs
+            .into_iter()
+            .filter(|tx| match tx.recover_public() {
+                Ok(public) => {
+                    let sender = public_to_address(&public);
+                    let state_nonce =
+                        state_nonces.entry(sender).or_insert_with(|| {
+                            self.get_state_account_info(&sender).0
+                        });
+                    tx.nonce >= *state_nonce
+                }
+                Err(_) => false,
+            })
+            .collect();
+
+        let (passed, _) = self.insert_new_transactions(txs);
+        info!(
+            "Replayed {} of {} transactions in tx pool journal",
+            passed.len(),
+            total
+        );
+
+        // remove the stale transactions and the duplicates appended during
+        // replay.
+        self.rotate_journal();
+    }
+
+    /// Rewrite the journal with the pending transactions followed by the
+    /// queued ones. Both are ordered by nonce for each sender, so that the
+    /// journal keeps the lowest nonces if it can't hold all of them.
+    fn rotate_journal(&self) {
+        if let Some(journal) = self.journal.lock().as_mut() {
+            let (mut txs, queued) = self.pending_and_queued();
+            txs.extend(queued);
+            journal.rotate(txs);
+        }
+    }
+
+    pub fn admission_policy(&self) -> AdmissionPolicy {
+        self.admission.read().policy().clone()
+    }
+
+    /// Replace the built-in admission rules, which are persisted and
+    /// restored after a restart. The transactions already in pool are not
+    /// affected.
+    pub fn set_admission_policy(&self, policy: AdmissionPolicy) {
+        info!("Set tx pool admission policy {:?}", policy);
+        let mut admission = self.admission.write();
+        self.data_man.insert_txpool_admission_policy_to_db(&policy);
+        let mut chain = (**admission).clone();
+        chain.set_policy(policy);
+        *admission = Arc::new(chain);
+    }
+
+    /// Append a custom rule to admit the transactions into pool, which is
+    /// evaluated after the built-in rules.
+    pub fn add_admission_filter(&self, filter: Arc<dyn AdmissionFilter>) {
+        let mut admission = self.admission.write();
+        let mut chain = (**admission).clone();
+        chain.add_filter(filter);
+        *admission = Arc::new(chain);
+    }
+
     pub fn get_transaction(
         &self, tx_hash: &H256,
     ) -> Option<Arc<SignedTransaction>> {
@@ -126,9 +267,10 @@ impl TransactionPool {
         let mut failure = HashMap::new();
 
         // filter out invalid transactions.
+        let next_height = self.next_block_height();
         let mut index = 0;
         while let Some(tx) = transactions.get(index) {
-            match self.verify_transaction(tx) {
+            match self.verify_transaction(tx, next_height) {
                 Ok(_) => index += 1,
                 Err(e) => {
                     let removed = transactions.swap_remove(index);
@@ -138,6 +280,26 @@ impl TransactionPool {
             }
         }
 
+        // filter out the transactions rejected by the admission rules which
+        // don't need the senders, before recovering their public keys.
+        // The transactions keep their order, so that the ones of the same
+        // sender are inserted in the order of their nonces.
+        let admission = self.admission.read().clone();
+        let admission_context = AdmissionContext {
+            fullness_percent: self.inner.read().fullness_percent(),
+        };
+        transactions.retain(|tx| {
+            match admission.check_unsigned(tx, &admission_context) {
+                Ok(_) => true,
+                Err(e) => {
+                    debug!("failed to insert tx into pool (not admitted), hash = {:?}, error = {:?}", tx.hash, e);
+                    ADMISSION_REJECTED_COUNTER.inc(1);
+                    failure.insert(tx.hash, e);
+                    false
+                }
+            }
+        });
+
         // ensure the pool has enough quota to insert new transactions.
         let quota = self.inner.write().remaining_quota();
         if quota < transactions.len() {
@@ -164,6 +326,12 @@ impl TransactionPool {
                 let mut to_prop = self.to_propagate_trans.write();
 
                 for tx in signed_trans {
+                    if let Err(e) = admission.check_signed(&tx) {
+                        debug!("failed to insert tx into pool (not admitted), hash = {:?}, error = {:?}", tx.hash, e);
+                        ADMISSION_REJECTED_COUNTER.inc(1);
+                        failure.insert(tx.hash(), e);
+                        continue;
+                    }
                     if let Err(e) = self.add_transaction_with_readiness_check(
                         &mut *inner,
                         &mut account_cache,
@@ -195,16 +363,48 @@ impl TransactionPool {
         TX_POOL_UNPACKED_GAUGE.update(self.total_unpacked());
         TX_POOL_READY_GAUGE.update(self.total_ready_accounts());
 
+        let journal_full = match self.journal.lock().as_mut() {
+            Some(journal) => {
+                journal.append(&passed_transactions);
+                journal.should_rotate()
+            }
+            None => false,
+        };
+        if journal_full {
+            self.rotate_journal();
+        }
+
         INSERT_TXS_SUCCESS_TPS.mark(passed_transactions.len());
         INSERT_TXS_FAILURE_TPS.mark(failure.len());
 
         (passed_transactions, failure)
     }
 
+    /// The height of the blocks to be generated on the current pivot chain.
+    fn next_block_height(&self) -> u64 {
+        self.consensus_best_info.lock().best_epoch_number + 1
+    }
+
+    /// The median time past of the current pivot chain. A block packed now
+    /// must have a timestamp after it, so it is the lower bound of the time
+    /// at which a packed transaction is executed. `None` if the headers in
+    /// the window are not available.
+    pub fn median_time_past(&self) -> Option<u64> {
+        self.consensus_best_info.lock().median_time_past
+    }
+
+    /// Check a transaction against the rules of the transaction pool that have
+    /// nothing to do with the state, without inserting it.
+    pub fn check_transaction(
+        &self, transaction: &TransactionWithSignature,
+    ) -> Result<(), String> {
+        self.verify_transaction(transaction, self.next_block_height())
+    }
+
     /// verify transactions based on the rules that have nothing to do with
     /// readiness
     fn verify_transaction(
-        &self, transaction: &TransactionWithSignature,
+        &self, transaction: &TransactionWithSignature, height: u64,
     ) -> Result<(), String> {
         // check transaction gas limit
         if transaction.gas > DEFAULT_MAX_TRANSACTION_GAS_LIMIT.into() {
@@ -249,6 +449,18 @@ impl TransactionPool {
             return Err(format!("{:?}", e));
         }
 
+        if let Err(e) = self
+            .verification_config
+            .verify_transaction_chain_id(transaction, height)
+        {
+            debug!(
+                "Transaction {:?} discarded due to invalid chain id: {}",
+                transaction.hash(),
+                e
+            );
+            return Err(format!("{}", e));
+        }
+
         Ok(())
     }
 
@@ -275,9 +487,37 @@ impl TransactionPool {
         let mut to_prop = self.to_propagate_trans.write();
         let mut res = HashMap::new();
         mem::swap(&mut *to_prop, &mut res);
+
+        let mut last_local_rebroadcast = self.last_local_rebroadcast.lock();
+        if last_local_rebroadcast.elapsed()
+            >= LOCAL_TRANSACTION_REBROADCAST_INTERVAL
+        {
+            *last_local_rebroadcast = Instant::now();
+            for tx in self.local_transactions() {
+                res.entry(tx.hash()).or_insert(tx);
+            }
+        }
+
         res
     }
 
+    /// Mark a transaction in pool as local, e.g. submitted via RPC on this
+    /// node. Local transactions are not garbage-collected before executed,
+    /// are packed with priority and are propagated periodically. Return
+    /// `false` if the transaction is not in pool.
+    pub fn mark_local(&self, tx_hash: &H256) -> bool {
+        self.inner.write().mark_local(tx_hash)
+    }
+
+    pub fn is_local(&self, tx_hash: &H256) -> bool {
+        self.inner.read().is_local(tx_hash)
+    }
+
+    /// Return the local transactions that are not executed yet.
+    pub fn local_transactions(&self) -> Vec<Arc<SignedTransaction>> {
+        self.inner.read().local_transactions()
+    }
+
     pub fn set_to_be_propagated_transactions(
         &self, transactions: HashMap<H256, Arc<SignedTransaction>>,
     ) {
@@ -306,6 +546,19 @@ impl TransactionPool {
         }
     }
 
+    /// Recycle the transactions in the epochs retracted from the pivot chain.
+    /// They are re-added as unpacked on the next best info update unless
+    /// their nonces are already stale in the latest state.
+    pub fn recycle_retracted_transactions(
+        &self, transactions: Vec<Arc<SignedTransaction>>,
+    ) {
+        if transactions.is_empty() {
+            return;
+        }
+
+        self.reorg_tx_requests.lock().extend(transactions);
+    }
+
     pub fn set_tx_packed(&self, transactions: &Vec<Arc<SignedTransaction>>) {
         if transactions.is_empty() {
             // Fast return. Also used to for bench mode.
@@ -319,9 +572,16 @@ impl TransactionPool {
 
     pub fn pack_transactions<'a>(
         &self, num_txs: usize, block_gas_limit: U256, block_size_limit: usize,
-    ) -> Vec<Arc<SignedTransaction>> {
+        strategy: &dyn PackingStrategy,
+    ) -> Vec<Arc<SignedTransaction>>
+    {
         let mut inner = self.inner.write();
-        inner.pack_transactions(num_txs, block_gas_limit, block_size_limit)
+        inner.pack_transactions(
+            num_txs,
+            block_gas_limit,
+            block_size_limit,
+            strategy,
+        )
     }
 
     pub fn notify_modified_accounts(
@@ -331,6 +591,21 @@ impl TransactionPool {
         inner.notify_modified_accounts(accounts_from_execution)
     }
 
+    /// Records the accounts touched by the execution of the pivot epoch
+    /// `epoch`, which are invalidated in the state cache when the best
+    /// executed epoch reaches it.
+    pub fn notify_touched_accounts(
+        &self, epoch: &EpochId, touched: Vec<Address>,
+    ) {
+        let parent = match self.data_man.block_header_by_hash(epoch) {
+            Some(header) => *header.parent_hash(),
+            None => return,
+        };
+        self.state_cache
+            .lock()
+            .record_touched(*epoch, parent, touched);
+    }
+
     pub fn clear_tx_pool(&self) {
         let mut inner = self.inner.write();
         inner.clear()
@@ -367,6 +642,23 @@ impl TransactionPool {
         )
     }
 
+    /// Return the memory used by transactions in bytes, the memory budget and
+    /// the number of transactions removed from the pool by reason.
+    pub fn memory_stats(&self) -> (usize, usize, EvictionStats) {
+        let inner = self.inner.read();
+        (
+            inner.memory_used(),
+            inner.memory_budget(),
+            inner.eviction_stats(),
+        )
+    }
+
+    /// Change the maximum number of transactions and the memory budget in
+    /// bytes of the pool at runtime.
+    pub fn set_limits(&self, capacity: usize, memory_budget: usize) {
+        self.inner.write().set_limits(capacity, memory_budget);
+    }
+
     /// content retrieves the ready and deferred transactions.
     pub fn content(
         &self,
@@ -375,12 +667,64 @@ impl TransactionPool {
         inner.content()
     }
 
+    /// Return the pending transactions, which are executable by their nonces,
+    /// and the queued transactions, which are blocked by nonce gaps.
+    pub fn pending_and_queued(
+        &self,
+    ) -> (Vec<Arc<SignedTransaction>>, Vec<Arc<SignedTransaction>>) {
+        self.inner.read().pending_and_queued()
+    }
+
+    /// Return the number of pending and queued transactions.
+    pub fn status(&self) -> (usize, usize) {
+        self.inner.read().total_pending_and_queued()
+    }
+
+    /// Return the `ready` and `future` queues of `address`. The `ready` queue
+    /// holds the transactions with consecutive nonces starting from the
+    /// current nonce of the account, while the `future` queue holds the
+    /// transactions that are blocked by a nonce gap.
+    pub fn account_pending_queues(
+        &self, address: &Address,
+    ) -> (Vec<Arc<SignedTransaction>>, Vec<Arc<SignedTransaction>>) {
+        let local_nonce = self
+            .inner
+            .read()
+            .get_local_nonce_and_balance(address)
+            .map(|(nonce, _)| nonce);
+        let nonce = match local_nonce {
+            Some(nonce) => nonce,
+            None => self.get_state_account_info(address).0,
+        };
+        self.inner.read().account_pending_queues(address, &nonce)
+    }
+
+    /// Return the nonce following the `ready` queue of `address`, which is the
+    /// nonce of the next transaction sent from the account.
+    pub fn get_next_nonce(&self, address: &Address) -> U256 {
+        let local_nonce = self
+            .inner
+            .read()
+            .get_local_nonce_and_balance(address)
+            .map(|(nonce, _)| nonce);
+        let nonce = match local_nonce {
+            Some(nonce) => nonce,
+            None => self.get_state_account_info(address).0,
+        };
+        let (ready, _) =
+            self.inner.read().account_pending_queues(address, &nonce);
+        nonce + U256::from(ready.len())
+    }
+
     pub fn notify_new_best_info(&self, best_info: Arc<BestInformation>) {
         let mut set_tx_buffer = self.set_tx_requests.lock();
         let mut recycle_tx_buffer = self.recycle_tx_requests.lock();
+        let mut reorg_tx_buffer = self.reorg_tx_requests.lock();
         let mut consensus_best_info = self.consensus_best_info.lock();
         *consensus_best_info = best_info;
 
+        let best_executed_epoch = *self.best_executed_epoch.lock();
+        self.state_cache.lock().advance(best_executed_epoch);
         let mut account_cache = self.get_best_state_account_cache();
         let mut inner = self.inner.write();
         let inner = inner.deref_mut();
@@ -411,23 +755,60 @@ impl TransactionPool {
             )
             .ok();
         }
+
+        while let Some(tx) = reorg_tx_buffer.pop() {
+            let state_nonce = account_cache
+                .get_account_mut(&tx.sender)
+                .map_or(0.into(), |x| x.nonce);
+            if tx.nonce < state_nonce {
+                REORG_DROPPED_COUNTER.inc(1);
+                continue;
+            }
+            // Do not replace the transactions with the same nonces sent
+            // after the retracted ones are packed.
+            match self.add_transaction_with_readiness_check(
+                inner,
+                &mut account_cache,
+                tx,
+                false, /* packed */
+                false, /* force */
+            ) {
+                Ok(_) => REORG_RECYCLED_COUNTER.inc(1),
+                Err(_) => REORG_DROPPED_COUNTER.inc(1),
+            }
+        }
     }
 
     pub fn get_best_info_with_packed_transactions(
         &self, num_txs: usize, block_size_limit: usize, block_gas_limit: U256,
         additional_transactions: Vec<Arc<SignedTransaction>>,
+        strategy: &dyn PackingStrategy,
     ) -> (Arc<BestInformation>, Vec<Arc<SignedTransaction>>)
     {
         let consensus_best_info = self.consensus_best_info.lock();
 
-        let transactions_from_pool =
-            self.pack_transactions(num_txs, block_gas_limit, block_size_limit);
+        let transactions_from_pool = self.pack_transactions(
+            num_txs,
+            block_gas_limit,
+            block_size_limit,
+            strategy,
+        );
 
+        // The transactions signed without a chain id may be in pool before the
+        // chain id is enforced, and they can't be packed afterwards.
+        let height = consensus_best_info.best_epoch_number + 1;
         let transactions = [
             additional_transactions.as_slice(),
             transactions_from_pool.as_slice(),
         ]
-        .concat();
+        .concat()
+        .into_iter()
+        .filter(|tx| {
+            self.verification_config
+                .verify_transaction_chain_id(tx, height)
+                .is_ok()
+        })
+        .collect();
 
         (consensus_best_info.clone(), transactions)
     }
@@ -437,13 +818,16 @@ impl TransactionPool {
     }
 
     fn get_best_state_account_cache(&self) -> AccountCache {
-        AccountCache::new(unsafe {
-            self.data_man
-                .storage_manager
-                .get_state_readonly_assumed_existence(
-                    *self.best_executed_epoch.lock(),
-                )
-                .unwrap()
-        })
+        let best_executed_epoch = *self.best_executed_epoch.lock();
+        AccountCache::with_state_cache(
+            unsafe {
+                self.data_man
+                    .storage_manager
+                    .get_state_readonly_assumed_existence(best_executed_epoch)
+                    .unwrap()
+            },
+            best_executed_epoch,
+            &self.state_cache,
+        )
     }
 }
@@ -1,4 +1,4 @@
-use cfx_types::U256;
+use cfx_types::{U256, U512};
 use primitives::SignedTransaction;
 use rand::{prng::XorShiftRng, FromEntropy, RngCore};
 use std::{cmp::Ordering, mem, ops::Deref, sync::Arc};
@@ -14,7 +14,11 @@ impl TxWithReadyInfo {
 
     pub fn get_arc_tx(&self) -> &Arc<SignedTransaction> { &self.transaction }
 
-    pub fn should_replace(&self, x: &Self, force: bool) -> bool {
+    /// A transaction replaces another one with the same sender and nonce only
+    /// if its gas price is higher by at least `min_bump_percent` percent.
+    pub fn should_replace(
+        &self, x: &Self, force: bool, min_bump_percent: u64,
+    ) -> bool {
         if force {
             return true;
         }
@@ -25,6 +29,8 @@ impl TxWithReadyInfo {
             return true;
         }
         self.gas_price > x.gas_price
+            && U512::from(self.gas_price) * U512::from(100)
+                >= U512::from(x.gas_price) * U512::from(100 + min_bump_percent)
     }
 }
 
@@ -95,7 +101,7 @@ impl NoncePoolNode {
     /// will replace with higher gas price transaction
     pub fn insert(
         node: &mut Option<Box<NoncePoolNode>>, tx: &TxWithReadyInfo,
-        priority: u64, force: bool,
+        priority: u64, force: bool, min_bump_percent: u64,
     ) -> InsertResult
     {
         if node.is_none() {
@@ -108,7 +114,11 @@ impl NoncePoolNode {
         let cmp = tx.nonce().cmp(&node.as_ref().unwrap().tx.nonce);
         if cmp == Ordering::Equal {
             let result = {
-                if tx.should_replace(&node.as_ref().unwrap().tx, force) {
+                if tx.should_replace(
+                    &node.as_ref().unwrap().tx,
+                    force,
+                    min_bump_percent,
+                ) {
                     InsertResult::Updated(mem::replace(
                         &mut node.as_mut().unwrap().tx,
                         tx.clone(),
@@ -126,6 +136,7 @@ impl NoncePoolNode {
                 tx,
                 priority,
                 force,
+                min_bump_percent,
             );
             if node.as_ref().unwrap().priority
                 < node.as_ref().unwrap().child[d].as_ref().unwrap().priority
@@ -279,6 +290,17 @@ impl NoncePoolNode {
         }
     }
 
+    /// collect the transactions in current subtree in ascending nonce order
+    fn collect(
+        node: &Option<Box<NoncePoolNode>>, txs: &mut Vec<TxWithReadyInfo>,
+    ) {
+        if let Some(node) = node.as_ref() {
+            NoncePoolNode::collect(&node.child[0], txs);
+            txs.push(node.tx.clone());
+            NoncePoolNode::collect(&node.child[1], txs);
+        }
+    }
+
     /// return the size and the sum of balance of current subtree
     fn size(node: &Option<Box<NoncePoolNode>>) -> (u32, U256) {
         if node.is_none() {
@@ -295,13 +317,21 @@ impl NoncePoolNode {
 pub struct NoncePool {
     root: Option<Box<NoncePoolNode>>,
     rng: XorShiftRng,
+    /// minimum gas price bump in percent for replace-by-fee
+    min_replace_bump_percent: u64,
 }
 
 impl NoncePool {
-    pub fn new() -> Self {
+    #[allow(dead_code)]
+    pub fn new() -> Self { NoncePool::with_min_replace_bump_percent(0) }
+
+    pub fn with_min_replace_bump_percent(
+        min_replace_bump_percent: u64,
+    ) -> Self {
         NoncePool {
             root: None,
             rng: XorShiftRng::from_entropy(),
+            min_replace_bump_percent,
         }
     }
 
@@ -310,7 +340,13 @@ impl NoncePool {
     pub fn insert(
         &mut self, tx: &TxWithReadyInfo, force: bool,
     ) -> InsertResult {
-        NoncePoolNode::insert(&mut self.root, tx, self.rng.next_u64(), force)
+        NoncePoolNode::insert(
+            &mut self.root,
+            tx,
+            self.rng.next_u64(),
+            force,
+            self.min_replace_bump_percent,
+        )
     }
 
     pub fn get_tx_by_nonce(&self, nonce: U256) -> Option<TxWithReadyInfo> {
@@ -359,7 +395,6 @@ impl NoncePool {
     pub fn is_empty(&self) -> bool { self.root.is_none() }
 
     /// return the number of transactions whose nonce >= `nonce`
-    #[allow(dead_code)]
     pub fn count_from(&self, nonce: &U256) -> usize {
         if *nonce == U256::from(0) {
             NoncePoolNode::size(&self.root).0 as usize
@@ -370,6 +405,56 @@ impl NoncePool {
         }
     }
 
+    /// split the transactions into the `ready` queue, i.e. the transactions
+    /// with consecutive nonces starting from `nonce`, and the `future` queue,
+    /// i.e. the transactions after the first nonce gap. Transactions with
+    /// nonce lower than `nonce` are stale and are not returned.
+    pub fn split_by_nonce_gap(
+        &self, nonce: &U256,
+    ) -> (Vec<TxWithReadyInfo>, Vec<TxWithReadyInfo>) {
+        let mut txs = Vec::new();
+        NoncePoolNode::collect(&self.root, &mut txs);
+
+        let mut ready = Vec::new();
+        let mut future = Vec::new();
+        let mut expected_nonce = *nonce;
+        for tx in txs.into_iter().filter(|tx| tx.nonce >= *nonce) {
+            if future.is_empty() && tx.nonce == expected_nonce {
+                expected_nonce += 1.into();
+                ready.push(tx);
+            } else {
+                future.push(tx);
+            }
+        }
+        (ready, future)
+    }
+
+    /// return the sizes of the `ready` and the `future` queues split by
+    /// `split_by_nonce_gap`, without collecting the transactions
+    pub fn count_by_nonce_gap(&self, nonce: &U256) -> (usize, usize) {
+        let total = self.count_from(nonce);
+        let stale = if *nonce == U256::from(0) {
+            0
+        } else {
+            NoncePoolNode::rank(&self.root, &(nonce - 1)).0 as usize
+        };
+        // binary search the length of the consecutive nonces from `nonce`,
+        // which are all present iff as many transactions are in the range
+        let (mut low, mut high) = (0, total);
+        while low < high {
+            let mid = (low + high + 1) / 2;
+            let last = *nonce + U256::from(mid - 1);
+            let count =
+                NoncePoolNode::rank(&self.root, &last).0 as usize - stale;
+            if count == mid {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        (low, total - low)
+    }
+
     pub fn check_nonce_exists(&self, nonce: &U256) -> bool {
         self.root
             .as_ref()
@@ -462,6 +547,32 @@ mod nonce_pool_test {
         assert_eq!(nonce_pool.is_empty(), true);
     }
 
+    #[test]
+    fn test_count_by_nonce_gap() {
+        let me = Random.generate().unwrap();
+        let mut nonce_pool = NoncePool::new();
+        assert_eq!(nonce_pool.count_by_nonce_gap(&U256::from(0)), (0, 0));
+
+        for i in &[1, 2, 3, 5, 6, 9] {
+            nonce_pool.insert(
+                &new_test_tx_with_ready_info(&me, *i, 10, 10000, false),
+                false, /* force */
+            );
+        }
+        for nonce in 0..11 {
+            let nonce = U256::from(nonce);
+            let (ready, future) = nonce_pool.split_by_nonce_gap(&nonce);
+            assert_eq!(
+                nonce_pool.count_by_nonce_gap(&nonce),
+                (ready.len(), future.len())
+            );
+        }
+        assert_eq!(nonce_pool.count_by_nonce_gap(&U256::from(0)), (0, 6));
+        assert_eq!(nonce_pool.count_by_nonce_gap(&U256::from(1)), (3, 3));
+        assert_eq!(nonce_pool.count_by_nonce_gap(&U256::from(5)), (2, 1));
+        assert_eq!(nonce_pool.count_by_nonce_gap(&U256::from(10)), (0, 0));
+    }
+
     #[test]
     fn test_readiness() {
         let me = Random.generate().unwrap();
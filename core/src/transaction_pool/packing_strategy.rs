@@ -0,0 +1,348 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use cfx_types::{Address, H256, U256};
+use primitives::SignedTransaction;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    sync::Arc,
+};
+
+/// The ready transactions in the transaction pool, i.e. the transaction with
+/// the next nonce of each sender.
+pub trait ReadyTransactions {
+    /// Sample a ready transaction randomly, weighted by gas price.
+    fn sample(&self) -> Option<Arc<SignedTransaction>>;
+
+    /// Iterate over all ready transactions in no particular order.
+    fn iter<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = &'a Arc<SignedTransaction>> + 'a>;
+
+    /// The ready transaction of `sender`.
+    fn get(&self, sender: &Address) -> Option<Arc<SignedTransaction>>;
+}
+
+/// The order of the ready transactions to be packed into a block, which is
+/// created for each block.
+pub trait PackingQueue {
+    /// Return the sender of the ready transaction to be packed next, or
+    /// `None` to stop packing.
+    fn next(&mut self, ready: &dyn ReadyTransactions) -> Option<Address>;
+
+    /// Called after `tx` is packed, with the transaction of its sender which
+    /// becomes ready.
+    fn packed(
+        &mut self, _tx: &SignedTransaction,
+        _next_ready: Option<Arc<SignedTransaction>>,
+    )
+    {
+    }
+}
+
+/// A strategy to choose which transactions are packed into a new block.
+pub trait PackingStrategy: Send + Sync {
+    /// Create the queue to pack a block from the ready transactions.
+    fn queue(&self, ready: &dyn ReadyTransactions) -> Box<dyn PackingQueue>;
+
+    /// The total gas limit of the packed transactions in a block with the
+    /// given gas limit.
+    fn target_gas_limit(&self, block_gas_limit: U256) -> U256 {
+        block_gas_limit
+    }
+}
+
+fn target_gas_limit(block_gas_limit: U256, target_gas_percent: u64) -> U256 {
+    block_gas_limit * target_gas_percent / 100
+}
+
+/// Pick transactions randomly with probability proportional to gas price.
+pub struct WeightedRandomStrategy {
+    pub target_gas_percent: u64,
+}
+
+struct WeightedRandomQueue;
+
+impl PackingQueue for WeightedRandomQueue {
+    fn next(&mut self, ready: &dyn ReadyTransactions) -> Option<Address> {
+        ready.sample().map(|tx| tx.sender)
+    }
+}
+
+impl PackingStrategy for WeightedRandomStrategy {
+    fn queue(&self, _ready: &dyn ReadyTransactions) -> Box<dyn PackingQueue> {
+        Box::new(WeightedRandomQueue)
+    }
+
+    fn target_gas_limit(&self, block_gas_limit: U256) -> U256 {
+        target_gas_limit(block_gas_limit, self.target_gas_percent)
+    }
+}
+
+/// Pick transactions in strictly descending order of gas price.
+pub struct GasPriceStrategy {
+    pub target_gas_percent: u64,
+}
+
+/// A heap with an entry of the ready transaction of each sender, ordered by
+/// `P`. The entries are not removed when the ready transaction of a sender
+/// changes, but skipped when they are popped.
+struct SenderHeap<P: Ord> {
+    heap: BinaryHeap<(P, Address, H256)>,
+}
+
+impl<P: Ord> SenderHeap<P> {
+    fn new() -> Self {
+        SenderHeap {
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    fn push(&mut self, priority: P, tx: &SignedTransaction) {
+        self.heap.push((priority, tx.sender, tx.hash()));
+    }
+
+    fn pop(&mut self, ready: &dyn ReadyTransactions) -> Option<Address> {
+        while let Some((_, sender, hash)) = self.heap.pop() {
+            if ready.get(&sender).map(|tx| tx.hash()) == Some(hash) {
+                return Some(sender);
+            }
+        }
+        None
+    }
+}
+
+struct GasPriceQueue {
+    heap: SenderHeap<U256>,
+}
+
+impl PackingQueue for GasPriceQueue {
+    fn next(&mut self, ready: &dyn ReadyTransactions) -> Option<Address> {
+        self.heap.pop(ready)
+    }
+
+    fn packed(
+        &mut self, _tx: &SignedTransaction,
+        next_ready: Option<Arc<SignedTransaction>>,
+    )
+    {
+        if let Some(next_ready) = next_ready {
+            self.heap.push(next_ready.gas_price, &next_ready);
+        }
+    }
+}
+
+impl PackingStrategy for GasPriceStrategy {
+    fn queue(&self, ready: &dyn ReadyTransactions) -> Box<dyn PackingQueue> {
+        let mut heap = SenderHeap::new();
+        for tx in ready.iter() {
+            heap.push(tx.gas_price, tx);
+        }
+        Box::new(GasPriceQueue { heap })
+    }
+
+    fn target_gas_limit(&self, block_gas_limit: U256) -> U256 {
+        target_gas_limit(block_gas_limit, self.target_gas_percent)
+    }
+}
+
+/// Pack one transaction of each sender in turn, so that a long nonce chain
+/// of a single sender can not monopolize the block. Among the senders with
+/// the fewest packed transactions, the one with the highest gas price goes
+/// first.
+pub struct SenderRoundRobinStrategy {
+    pub target_gas_percent: u64,
+}
+
+struct SenderRoundRobinQueue {
+    heap: SenderHeap<(Reverse<usize>, U256)>,
+    /// The number of transactions of each sender packed so far.
+    packed: HashMap<Address, usize>,
+}
+
+impl PackingQueue for SenderRoundRobinQueue {
+    fn next(&mut self, ready: &dyn ReadyTransactions) -> Option<Address> {
+        self.heap.pop(ready)
+    }
+
+    fn packed(
+        &mut self, tx: &SignedTransaction,
+        next_ready: Option<Arc<SignedTransaction>>,
+    )
+    {
+        let packed = self.packed.entry(tx.sender).or_insert(0);
+        *packed += 1;
+        if let Some(next_ready) = next_ready {
+            self.heap
+                .push((Reverse(*packed), next_ready.gas_price), &next_ready);
+        }
+    }
+}
+
+impl PackingStrategy for SenderRoundRobinStrategy {
+    fn queue(&self, ready: &dyn ReadyTransactions) -> Box<dyn PackingQueue> {
+        let mut heap = SenderHeap::new();
+        for tx in ready.iter() {
+            heap.push((Reverse(0), tx.gas_price), tx);
+        }
+        Box::new(SenderRoundRobinQueue {
+            heap,
+            packed: HashMap::new(),
+        })
+    }
+
+    fn target_gas_limit(&self, block_gas_limit: U256) -> U256 {
+        target_gas_limit(block_gas_limit, self.target_gas_percent)
+    }
+}
+
+/// Create the packing strategy with the given name, which fills at most
+/// `target_gas_percent` percent of the block gas limit.
+pub fn new_packing_strategy(
+    name: &str, target_gas_percent: u64,
+) -> Result<Arc<dyn PackingStrategy>, String> {
+    if target_gas_percent == 0 || target_gas_percent > 100 {
+        return Err(format!(
+            "Invalid target gas percent {}, expected 1 to 100",
+            target_gas_percent
+        ));
+    }
+
+    match name {
+        "random" => Ok(Arc::new(WeightedRandomStrategy { target_gas_percent })),
+        "gas_price" => Ok(Arc::new(GasPriceStrategy { target_gas_percent })),
+        "round_robin" => {
+            Ok(Arc::new(SenderRoundRobinStrategy { target_gas_percent }))
+        }
+        _ => Err(format!("Unknown packing strategy {}", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        GasPriceStrategy, PackingStrategy, ReadyTransactions,
+        SenderRoundRobinStrategy,
+    };
+    use cfx_types::{Address, U256};
+    use primitives::{
+        Action, SignedTransaction, Transaction, TransactionWithSignature,
+    };
+    use std::sync::Arc;
+
+    struct ReadyVec(Vec<Arc<SignedTransaction>>);
+
+    impl ReadyVec {
+        /// Remove the ready transaction of `sender` as if it is packed, and
+        /// make `next` ready.
+        fn pack(
+            &mut self, sender: Address, next: Option<Arc<SignedTransaction>>,
+        ) -> Arc<SignedTransaction> {
+            let index = self.0.iter().position(|tx| tx.sender == sender);
+            let packed = self.0.remove(index.unwrap());
+            self.0.extend(next);
+            packed
+        }
+    }
+
+    impl ReadyTransactions for ReadyVec {
+        fn sample(&self) -> Option<Arc<SignedTransaction>> {
+            self.0.first().cloned()
+        }
+
+        fn iter<'a>(
+            &'a self,
+        ) -> Box<dyn Iterator<Item = &'a Arc<SignedTransaction>> + 'a>
+        {
+            Box::new(self.0.iter())
+        }
+
+        fn get(&self, sender: &Address) -> Option<Arc<SignedTransaction>> {
+            self.0.iter().find(|tx| tx.sender == *sender).cloned()
+        }
+    }
+
+    fn new_tx(
+        sender: Address, nonce: u64, gas_price: u64,
+    ) -> Arc<SignedTransaction> {
+        let mut tx = SignedTransaction::new_unsigned(
+            TransactionWithSignature::new_unsigned(Transaction {
+                nonce: nonce.into(),
+                gas_price: gas_price.into(),
+                gas: 21000.into(),
+                action: Action::Call(Address::zero()),
+                value: U256::zero(),
+                data: vec![],
+            }),
+        );
+        tx.sender = sender;
+        Arc::new(tx)
+    }
+
+    /// Pack all the ready transactions with `strategy`, where `next_txs`
+    /// become ready in turn after the transactions of their senders are
+    /// packed, and return the gas prices of the packed transactions.
+    fn pack(
+        strategy: &dyn PackingStrategy, ready: Vec<Arc<SignedTransaction>>,
+        mut next_txs: Vec<Arc<SignedTransaction>>,
+    ) -> Vec<u64>
+    {
+        let mut ready = ReadyVec(ready);
+        let mut queue = strategy.queue(&ready);
+        let mut packed = Vec::new();
+        while let Some(sender) = queue.next(&ready) {
+            let next = next_txs
+                .iter()
+                .position(|tx| tx.sender == sender)
+                .map(|index| next_txs.remove(index));
+            let tx = ready.pack(sender, next.clone());
+            packed.push(tx.gas_price.low_u64());
+            queue.packed(&tx, next);
+        }
+        packed
+    }
+
+    #[test]
+    fn test_packing_strategies() {
+        let (alice, bob) =
+            (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        let ready = vec![new_tx(alice, 0, 10), new_tx(bob, 0, 5)];
+        let next_txs =
+            vec![new_tx(alice, 1, 20), new_tx(alice, 2, 1), new_tx(bob, 1, 3)];
+
+        let gas_price = GasPriceStrategy {
+            target_gas_percent: 100,
+        };
+        let round_robin = SenderRoundRobinStrategy {
+            target_gas_percent: 50,
+        };
+        assert_eq!(
+            pack(&gas_price, ready.clone(), next_txs.clone()),
+            vec![10, 20, 5, 3, 1]
+        );
+        // Each sender has a turn before the others have the next one.
+        assert_eq!(pack(&round_robin, ready, next_txs), vec![10, 5, 20, 3, 1]);
+
+        assert_eq!(round_robin.target_gas_limit(1000.into()), 500.into());
+    }
+
+    #[test]
+    fn test_packing_queue_skips_changed_ready_transactions() {
+        let (alice, bob) =
+            (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        let mut ready = ReadyVec(vec![new_tx(alice, 0, 10), new_tx(bob, 0, 5)]);
+        let strategy = GasPriceStrategy {
+            target_gas_percent: 100,
+        };
+        let mut queue = strategy.queue(&ready);
+
+        // The transaction of alice is packed without the queue, e.g. as a
+        // local transaction.
+        ready.pack(alice, None);
+        assert_eq!(queue.next(&ready), Some(bob));
+        ready.pack(bob, None);
+        assert_eq!(queue.next(&ready), None);
+    }
+}
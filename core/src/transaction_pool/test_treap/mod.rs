@@ -106,7 +106,7 @@ fn next_signed_transaction(rng: &mut ChaChaRng) -> SignedTransaction {
             action: Action::Call(Address::from_low_u64_be(0)),
             data: vec![],
         }
-        .with_signature(Signature::default()),
+        .with_signature(Signature::default(), None),
     )
 }
 
@@ -2,15 +2,18 @@ use super::{
     account_cache::AccountCache,
     impls::TreapMap,
     nonce_pool::{InsertResult, NoncePool, TxWithReadyInfo},
+    packing_strategy::{PackingStrategy, ReadyTransactions},
 };
 use cfx_types::{Address, BigEndianHash, H256, H512, U256, U512};
 use metrics::{
-    register_meter_with_group, Counter, CounterUsize, Meter, MeterTimer,
+    register_meter_with_group, Counter, CounterUsize, Gauge, GaugeUsize, Meter,
+    MeterTimer,
 };
 use primitives::{Account, SignedTransaction, TransactionWithSignature};
 use rlp::*;
 use std::{
-    collections::{hash_map::HashMap, VecDeque},
+    collections::{hash_map::HashMap, BTreeSet, HashSet, VecDeque},
+    mem,
     sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
 };
@@ -33,16 +36,49 @@ lazy_static! {
         CounterUsize::register_with_group("txpool", "gc_ready");
     static ref GC_METER: Arc<dyn Meter> =
         register_meter_with_group("txpool", "gc_txs_tps");
+    static ref GC_LOCAL_EXEMPTED_COUNTER: Arc<dyn Counter<usize>> =
+        CounterUsize::register_with_group("txpool", "gc_local_exempted");
+    static ref MEM_EVICTED_COUNTER: Arc<dyn Counter<usize>> =
+        CounterUsize::register_with_group("txpool", "mem_evicted");
+    static ref REPLACED_COUNTER: Arc<dyn Counter<usize>> =
+        CounterUsize::register_with_group("txpool", "replaced");
+    static ref MEM_USED_GAUGE: Arc<dyn Gauge<usize>> =
+        GaugeUsize::register_with_group("txpool", "mem_used");
+}
+
+/// The estimated memory footprint of a transaction in the pool, i.e. its RLP
+/// size plus the in-memory structure holding the cached hash, sender and
+/// public key recovered from the signature.
+fn tx_mem_size(tx: &SignedTransaction) -> usize {
+    tx.rlp_size() + mem::size_of::<SignedTransaction>()
+}
+
+/// The fee paid by a transaction per byte of its memory footprint.
+fn fee_per_byte(tx: &SignedTransaction) -> U512 {
+    U512::from(tx.gas_price) * U512::from(tx.gas) / U512::from(tx_mem_size(tx))
+}
+
+/// The number of transactions removed from the pool, grouped by reason.
+#[derive(Clone, Debug, Default)]
+pub struct EvictionStats {
+    /// Removed by garbage collection when the pool is full.
+    pub garbage_collected: usize,
+    /// Evicted with the lowest fee per byte to stay within the memory budget.
+    pub over_memory_budget: usize,
+    /// Replaced by a transaction with the same sender and nonce.
+    pub replaced: usize,
 }
 
 struct DeferredPool {
     buckets: HashMap<Address, NoncePool>,
+    min_replace_bump_percent: u64,
 }
 
 impl DeferredPool {
-    fn new() -> Self {
+    fn new(min_replace_bump_percent: u64) -> Self {
         DeferredPool {
             buckets: Default::default(),
+            min_replace_bump_percent,
         }
     }
 
@@ -51,7 +87,10 @@ impl DeferredPool {
     fn insert(&mut self, tx: TxWithReadyInfo, force: bool) -> InsertResult {
         // It's safe to create a new bucket, cause inserting to a empty bucket
         // will always be success
-        let bucket = self.buckets.entry(tx.sender).or_insert(NoncePool::new());
+        let min_replace_bump_percent = self.min_replace_bump_percent;
+        let bucket = self.buckets.entry(tx.sender).or_insert_with(|| {
+            NoncePool::with_min_replace_bump_percent(min_replace_bump_percent)
+        });
         bucket.insert(&tx, force)
     }
 
@@ -84,6 +123,21 @@ impl DeferredPool {
         }
     }
 
+    fn remove(
+        &mut self, addr: &Address, nonce: &U256,
+    ) -> Option<TxWithReadyInfo> {
+        match self.buckets.get_mut(addr) {
+            None => None,
+            Some(bucket) => {
+                let ret = bucket.remove(nonce);
+                if bucket.is_empty() {
+                    self.buckets.remove(addr);
+                }
+                ret
+            }
+        }
+    }
+
     fn get_lowest_nonce(&self, addr: &Address) -> Option<&U256> {
         self.buckets
             .get(addr)
@@ -100,6 +154,36 @@ impl DeferredPool {
         }
     }
 
+    fn count_by_nonce_gap(
+        &self, addr: &Address, nonce: &U256,
+    ) -> (usize, usize) {
+        match self.buckets.get(addr) {
+            None => (0, 0),
+            Some(bucket) => bucket.count_by_nonce_gap(nonce),
+        }
+    }
+
+    fn split_by_nonce_gap(
+        &self, addr: &Address, nonce: &U256,
+    ) -> (Vec<Arc<SignedTransaction>>, Vec<Arc<SignedTransaction>>) {
+        match self.buckets.get(addr) {
+            None => (Vec::new(), Vec::new()),
+            Some(bucket) => {
+                let (ready, future) = bucket.split_by_nonce_gap(nonce);
+                (
+                    ready.into_iter().map(|x| x.transaction).collect(),
+                    future.into_iter().map(|x| x.transaction).collect(),
+                )
+            }
+        }
+    }
+
+    fn get_tx(&self, addr: &Address, nonce: U256) -> Option<TxWithReadyInfo> {
+        self.buckets
+            .get(addr)
+            .and_then(|bucket| bucket.get_tx_by_nonce(nonce))
+    }
+
     fn check_tx_packed(&self, addr: Address, nonce: U256) -> bool {
         if let Some(bucket) = self.buckets.get(&addr) {
             if let Some(tx_with_ready_info) = bucket.get_tx_by_nonce(nonce) {
@@ -162,6 +246,15 @@ impl ReadyAccountPool {
     }
 
     fn pop(&mut self) -> Option<Arc<SignedTransaction>> {
+        let tx = self.sample()?;
+        trace!("Get transaction from ready pool. tx: {:?}", tx.clone());
+
+        self.remove(&tx.sender())
+    }
+}
+
+impl ReadyTransactions for ReadyAccountPool {
+    fn sample(&self) -> Option<Arc<SignedTransaction>> {
         if self.treap.len() == 0 {
             return None;
         }
@@ -175,9 +268,17 @@ impl ReadyAccountPool {
             .get_by_weight(rand_value)
             .expect("Failed to pick transaction by weight")
             .clone();
-        trace!("Get transaction from ready pool. tx: {:?}", tx.clone());
+        Some(tx)
+    }
 
-        self.remove(&tx.sender())
+    fn iter<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = &'a Arc<SignedTransaction>> + 'a> {
+        Box::new(self.treap.iter().map(|(_, tx)| tx))
+    }
+
+    fn get(&self, sender: &Address) -> Option<Arc<SignedTransaction>> {
+        ReadyAccountPool::get(self, sender)
     }
 }
 
@@ -190,19 +291,44 @@ pub struct TransactionPoolInner {
     ready_nonces_and_balances: HashMap<Address, (U256, U256)>,
     garbage_collection_queue: VecDeque<(Address, u64)>,
     txs: HashMap<H256, Arc<SignedTransaction>>,
+    /// Transactions submitted via RPC on this node. They are exempt from
+    /// garbage collection before executed, and packed with priority.
+    local_transactions: HashSet<H256>,
+    /// The memory budget in bytes of all transactions in `txs`.
+    memory_budget: usize,
+    memory_used: usize,
+    /// All transactions in `txs` ordered by fee per byte, used to evict
+    /// the cheapest ones when the memory budget is exceeded.
+    fee_per_byte_index: BTreeSet<(U512, H256)>,
+    eviction_stats: EvictionStats,
+    /// The numbers of pending and queued transactions of each sender, as
+    /// split by `pending_and_queued`, and their totals.
+    pending_and_queued_counts: HashMap<Address, (usize, usize)>,
+    total_pending: usize,
+    total_queued: usize,
 }
 
 impl TransactionPoolInner {
-    pub fn with_capacity(capacity: usize) -> Self {
+    pub fn with_capacity(
+        capacity: usize, min_replace_bump_percent: u64, memory_budget: usize,
+    ) -> Self {
         TransactionPoolInner {
             capacity,
             total_received_count: 0,
             unpacked_transaction_count: 0,
-            deferred_pool: DeferredPool::new(),
+            deferred_pool: DeferredPool::new(min_replace_bump_percent),
             ready_account_pool: ReadyAccountPool::new(),
             ready_nonces_and_balances: HashMap::new(),
             garbage_collection_queue: VecDeque::new(),
             txs: HashMap::new(),
+            local_transactions: HashSet::new(),
+            memory_budget,
+            memory_used: 0,
+            fee_per_byte_index: BTreeSet::new(),
+            eviction_stats: Default::default(),
+            pending_and_queued_counts: HashMap::new(),
+            total_pending: 0,
+            total_queued: 0,
         }
     }
 
@@ -212,8 +338,15 @@ impl TransactionPoolInner {
         self.ready_nonces_and_balances.clear();
         self.garbage_collection_queue.clear();
         self.txs.clear();
+        self.local_transactions.clear();
+        self.fee_per_byte_index.clear();
+        self.memory_used = 0;
+        MEM_USED_GAUGE.update(0);
         self.total_received_count = 0;
         self.unpacked_transaction_count = 0;
+        self.pending_and_queued_counts.clear();
+        self.total_pending = 0;
+        self.total_queued = 0;
     }
 
     pub fn total_deferred(&self) -> usize { self.txs.len() }
@@ -226,10 +359,72 @@ impl TransactionPoolInner {
 
     pub fn total_unpacked(&self) -> usize { self.unpacked_transaction_count }
 
+    pub fn memory_used(&self) -> usize { self.memory_used }
+
+    /// The numbers of pending and queued transactions, which are maintained
+    /// whenever the transactions or the local nonce of a sender change.
+    pub fn total_pending_and_queued(&self) -> (usize, usize) {
+        (self.total_pending, self.total_queued)
+    }
+
+    fn update_pending_and_queued_count(&mut self, addr: &Address) {
+        let (nonce, _) = self
+            .get_local_nonce_and_balance(addr)
+            .unwrap_or((0.into(), 0.into()));
+        let counts = self.deferred_pool.count_by_nonce_gap(addr, &nonce);
+        let (pending, queued) = if counts == (0, 0) {
+            self.pending_and_queued_counts.remove(addr)
+        } else {
+            self.pending_and_queued_counts.insert(*addr, counts)
+        }
+        .unwrap_or((0, 0));
+        self.total_pending = self.total_pending + counts.0 - pending;
+        self.total_queued = self.total_queued + counts.1 - queued;
+    }
+
+    pub fn memory_budget(&self) -> usize { self.memory_budget }
+
+    /// Change the capacity and the memory budget. Transactions already in the
+    /// pool are only evicted when new transactions are inserted.
+    pub fn set_limits(&mut self, capacity: usize, memory_budget: usize) {
+        self.capacity = capacity;
+        self.memory_budget = memory_budget;
+    }
+
+    pub fn eviction_stats(&self) -> EvictionStats {
+        self.eviction_stats.clone()
+    }
+
+    fn add_to_txs(&mut self, tx: Arc<SignedTransaction>) {
+        self.memory_used += tx_mem_size(&tx);
+        MEM_USED_GAUGE.update(self.memory_used);
+        self.fee_per_byte_index
+            .insert((fee_per_byte(&tx), tx.hash()));
+        self.txs.insert(tx.hash(), tx);
+    }
+
+    fn remove_from_txs(&mut self, tx_hash: &H256) {
+        if let Some(tx) = self.txs.remove(tx_hash) {
+            self.memory_used -= tx_mem_size(&tx);
+            MEM_USED_GAUGE.update(self.memory_used);
+            self.fee_per_byte_index
+                .remove(&(fee_per_byte(&tx), *tx_hash));
+        }
+    }
+
     pub fn get(&self, tx_hash: &H256) -> Option<Arc<SignedTransaction>> {
         self.txs.get(tx_hash).map(|x| x.clone())
     }
 
+    /// The number of transactions in the pool in percent of its capacity.
+    pub fn fullness_percent(&self) -> usize {
+        if self.capacity == 0 {
+            100
+        } else {
+            self.garbage_collection_queue.len() * 100 / self.capacity
+        }
+    }
+
     pub fn is_full(&self) -> bool {
         return self.garbage_collection_queue.len() >= self.capacity;
     }
@@ -242,16 +437,16 @@ impl TransactionPoolInner {
 
     fn collect_garbage(&mut self) {
         let count_before_gc = self.garbage_collection_queue.len();
-        while self.is_full() {
-            let (addr, timestamp) =
-                self.garbage_collection_queue.front().unwrap().clone();
+        // Exempted entries are skipped in place, so that the queue stays
+        // ordered by timestamps.
+        let mut pos = 0;
+        while self.is_full() && pos < self.garbage_collection_queue.len() {
+            let (addr, timestamp) = self.garbage_collection_queue[pos];
 
             if timestamp + TIME_WINDOW >= self.get_current_timestamp() {
                 break;
             }
 
-            self.garbage_collection_queue.pop_front();
-
             // abort if a tx'nonce >= ready nonce
             let (ready_nonce, _) = self
                 .get_local_nonce_and_balance(&addr)
@@ -260,6 +455,20 @@ impl TransactionPoolInner {
             let lowest_nonce =
                 *self.deferred_pool.get_lowest_nonce(&addr).unwrap();
 
+            // unexecuted local transactions are never garbage-collected
+            if lowest_nonce >= ready_nonce {
+                let lowest_tx = self.deferred_pool.get_tx(&addr, lowest_nonce);
+                if let Some(tx) = lowest_tx {
+                    if self.local_transactions.contains(&tx.hash()) {
+                        GC_LOCAL_EXEMPTED_COUNTER.inc(1);
+                        pos += 1;
+                        continue;
+                    }
+                }
+            }
+
+            self.garbage_collection_queue.remove(pos);
+
             if lowest_nonce >= ready_nonce {
                 GC_UNEXECUTED_COUNTER.inc(1);
                 warn!("an unexecuted tx is garbage-collected.");
@@ -292,14 +501,86 @@ impl TransactionPoolInner {
             if !self.deferred_pool.contain_address(&addr) {
                 self.ready_nonces_and_balances.remove(&addr);
             }
+            self.update_pending_and_queued_count(&addr);
 
             // maintain txs
-            self.txs.remove(&removed_tx.hash());
+            self.remove_from_txs(&removed_tx.hash());
+            self.local_transactions.remove(&removed_tx.hash());
+            self.eviction_stats.garbage_collected += 1;
         }
 
         GC_METER.mark(count_before_gc - self.garbage_collection_queue.len());
     }
 
+    /// Evict non-local transactions with the lowest fee per byte until a new
+    /// transaction of `size` bytes fits in the memory budget. Return false
+    /// without evicting anything if the budget cannot be met by evicting
+    /// transactions cheaper than `min_fee_per_byte`.
+    fn make_room_for(&mut self, size: usize, min_fee_per_byte: &U512) -> bool {
+        if self.memory_used + size <= self.memory_budget {
+            return true;
+        }
+
+        let mut to_evict = Vec::new();
+        let mut freed = 0;
+        for (fee, hash) in &self.fee_per_byte_index {
+            if self.memory_used + size <= self.memory_budget + freed {
+                break;
+            }
+            if fee >= min_fee_per_byte {
+                return false;
+            }
+            if self.local_transactions.contains(hash) {
+                continue;
+            }
+            let tx = self.txs.get(hash).expect("indexed tx exists").clone();
+            freed += tx_mem_size(&tx);
+            to_evict.push(tx);
+        }
+        if self.memory_used + size > self.memory_budget + freed {
+            return false;
+        }
+
+        for tx in to_evict {
+            self.evict(&tx);
+        }
+        true
+    }
+
+    fn evict(&mut self, tx: &Arc<SignedTransaction>) {
+        let addr = tx.sender();
+        let removed = match self.deferred_pool.remove(&addr, &tx.nonce()) {
+            Some(removed) => removed,
+            None => return,
+        };
+        debug!("Evict tx {:?} over memory budget", tx.hash());
+        MEM_EVICTED_COUNTER.inc(1);
+        self.eviction_stats.over_memory_budget += 1;
+
+        if !removed.is_already_packed() {
+            self.unpacked_transaction_count -= 1;
+        }
+
+        // Every queued entry of an address stands for one of its transactions.
+        if let Some(pos) = self
+            .garbage_collection_queue
+            .iter()
+            .rposition(|(queued_addr, _)| *queued_addr == addr)
+        {
+            self.garbage_collection_queue.remove(pos);
+        }
+
+        self.remove_from_txs(&tx.hash());
+
+        if self.deferred_pool.contain_address(&addr) {
+            self.recalculate_readiness_with_local_info(&addr);
+        } else {
+            self.ready_nonces_and_balances.remove(&addr);
+            self.ready_account_pool.remove(&addr);
+            self.update_pending_and_queued_count(&addr);
+        }
+    }
+
     /// Collect garbage and return the remaining quota of the pool to insert new
     /// transactions.
     pub fn remaining_quota(&mut self) -> usize {
@@ -324,7 +605,12 @@ impl TransactionPoolInner {
             &transaction.nonce(),
         ) {
             self.collect_garbage();
-            if self.is_full() {
+            if self.is_full()
+                || !self.make_room_for(
+                    tx_mem_size(&transaction),
+                    &fee_per_byte(&transaction),
+                )
+            {
                 return InsertResult::Failed("Transaction Pool is full".into());
             }
         }
@@ -346,18 +632,24 @@ impl TransactionPoolInner {
                     transaction.sender(),
                     self.get_current_timestamp(),
                 ));
-                self.txs.insert(transaction.hash(), transaction.clone());
+                self.add_to_txs(transaction.clone());
                 if !packed {
                     self.unpacked_transaction_count += 1;
                 }
+                self.update_pending_and_queued_count(&transaction.sender());
             }
             InsertResult::Failed(_) => {}
             InsertResult::Updated(replaced_tx) => {
                 if !replaced_tx.is_already_packed() {
                     self.unpacked_transaction_count -= 1;
                 }
-                self.txs.remove(&replaced_tx.hash());
-                self.txs.insert(transaction.hash(), transaction.clone());
+                self.remove_from_txs(&replaced_tx.hash());
+                self.add_to_txs(transaction.clone());
+                if replaced_tx.hash() != transaction.hash() {
+                    self.local_transactions.remove(&replaced_tx.hash());
+                    REPLACED_COUNTER.inc(1);
+                    self.eviction_stats.replaced += 1;
+                }
                 if !packed {
                     self.unpacked_transaction_count += 1;
                 }
@@ -427,6 +719,7 @@ impl TransactionPoolInner {
             .deferred_pool
             .recalculate_readiness_with_local_info(addr, nonce, balance);
         self.ready_account_pool.update(addr, ret);
+        self.update_pending_and_queued_count(addr);
     }
 
     fn recalculate_readiness_with_fixed_info(
@@ -437,6 +730,7 @@ impl TransactionPoolInner {
             .deferred_pool
             .recalculate_readiness_with_local_info(addr, nonce, balance);
         self.ready_account_pool.update(addr, ret);
+        self.update_pending_and_queued_count(addr);
     }
 
     fn recalculate_readiness_with_state(
@@ -449,6 +743,7 @@ impl TransactionPoolInner {
             .deferred_pool
             .recalculate_readiness_with_local_info(addr, nonce, balance);
         self.ready_account_pool.update(addr, ret);
+        self.update_pending_and_queued_count(addr);
     }
 
     pub fn check_tx_packed_in_deferred_pool(&self, tx_hash: &H256) -> bool {
@@ -463,7 +758,7 @@ impl TransactionPoolInner {
     /// pack at most num_txs transactions randomly
     pub fn pack_transactions<'a>(
         &mut self, num_txs: usize, block_gas_limit: U256,
-        block_size_limit: usize,
+        block_size_limit: usize, strategy: &dyn PackingStrategy,
     ) -> Vec<Arc<SignedTransaction>>
     {
         let mut packed_transactions: Vec<Arc<SignedTransaction>> = Vec::new();
@@ -471,13 +766,34 @@ impl TransactionPoolInner {
             return packed_transactions;
         }
 
+        let block_gas_limit = strategy.target_gas_limit(block_gas_limit);
         let mut total_tx_gas_limit: U256 = 0.into();
         let mut total_tx_size: usize = 0;
+        let mut queue = strategy.queue(&self.ready_account_pool);
 
         let mut big_tx_resample_times_limit = 10;
         let mut too_big_txs = Vec::new();
 
-        'out: while let Some(tx) = self.ready_account_pool.pop() {
+        // ready local transactions are packed before the sampled ones
+        let mut local_senders: Vec<Address> = self
+            .local_transactions
+            .iter()
+            .filter_map(|hash| self.txs.get(hash))
+            .filter(|tx| match self.ready_account_pool.get(&tx.sender) {
+                Some(ready_tx) => ready_tx.hash() == tx.hash(),
+                None => false,
+            })
+            .map(|tx| tx.sender)
+            .collect();
+
+        'out: while let Some(tx) = local_senders
+            .pop()
+            .and_then(|sender| self.ready_account_pool.remove(&sender))
+            .or_else(|| {
+                let sender = queue.next(&self.ready_account_pool)?;
+                self.ready_account_pool.remove(&sender)
+            })
+        {
             let tx_size = tx.rlp_size();
             if block_gas_limit - total_tx_gas_limit < *tx.gas_limit()
                 || block_size_limit - total_tx_size < tx_size
@@ -501,6 +817,7 @@ impl TransactionPoolInner {
                 true,
             );
             self.recalculate_readiness_with_local_info(&tx.sender());
+            queue.packed(&tx, self.ready_account_pool.get(&tx.sender));
 
             if packed_transactions.len() >= num_txs {
                 break 'out;
@@ -549,6 +866,34 @@ impl TransactionPoolInner {
         }
     }
 
+    /// Mark the transaction as local. Return `false` if it is not in pool.
+    pub fn mark_local(&mut self, tx_hash: &H256) -> bool {
+        if !self.txs.contains_key(tx_hash) {
+            return false;
+        }
+        self.local_transactions.insert(*tx_hash);
+        true
+    }
+
+    pub fn is_local(&self, tx_hash: &H256) -> bool {
+        self.local_transactions.contains(tx_hash)
+    }
+
+    /// Return the local transactions that are not executed yet.
+    pub fn local_transactions(&self) -> Vec<Arc<SignedTransaction>> {
+        self.local_transactions
+            .iter()
+            .filter_map(|hash| self.txs.get(hash))
+            .filter(|tx| {
+                let (nonce, _) = self
+                    .get_local_nonce_and_balance(&tx.sender)
+                    .unwrap_or((0.into(), 0.into()));
+                tx.nonce >= nonce
+            })
+            .cloned()
+            .collect()
+    }
+
     /// content retrieves the ready and deferred transactions.
     pub fn content(
         &self,
@@ -565,6 +910,36 @@ impl TransactionPoolInner {
         (ready_txs, deferred_txs)
     }
 
+    /// Split all transactions into the pending ones, whose nonces are
+    /// consecutive from the local nonce of their senders, and the queued ones,
+    /// which are blocked by nonce gaps. Transactions with nonces lower than
+    /// the local nonce are already executed and not included.
+    pub fn pending_and_queued(
+        &self,
+    ) -> (Vec<Arc<SignedTransaction>>, Vec<Arc<SignedTransaction>>) {
+        let mut pending = Vec::new();
+        let mut queued = Vec::new();
+        for addr in self.deferred_pool.buckets.keys() {
+            let (nonce, _) = self
+                .get_local_nonce_and_balance(addr)
+                .unwrap_or((0.into(), 0.into()));
+            let (ready, future) =
+                self.deferred_pool.split_by_nonce_gap(addr, &nonce);
+            pending.extend(ready);
+            queued.extend(future);
+        }
+        (pending, queued)
+    }
+
+    /// Split the pending transactions of `address` into the `ready` queue,
+    /// whose nonces are consecutive from `nonce`, and the `future` queue,
+    /// which is blocked by a nonce gap.
+    pub fn account_pending_queues(
+        &self, address: &Address, nonce: &U256,
+    ) -> (Vec<Arc<SignedTransaction>>, Vec<Arc<SignedTransaction>>) {
+        self.deferred_pool.split_by_nonce_gap(address, nonce)
+    }
+
     // Add transaction into deferred pool and maintain its readiness
     // the packed tag provided
     // if force tag is true, the replacement in nonce pool must be happened
@@ -635,7 +1010,10 @@ impl TransactionPoolInner {
 
 #[cfg(test)]
 mod test_transaction_pool_inner {
-    use super::{DeferredPool, InsertResult, TxWithReadyInfo};
+    use super::{
+        tx_mem_size, DeferredPool, InsertResult, TransactionPoolInner,
+        TxWithReadyInfo,
+    };
     use cfx_types::{Address, U256};
     use keylib::{Generator, KeyPair, Random};
     use primitives::{Action, SignedTransaction, Transaction};
@@ -671,7 +1049,7 @@ mod test_transaction_pool_inner {
 
     #[test]
     fn test_deferred_pool_insert_and_remove() {
-        let mut deferred_pool = DeferredPool::new();
+        let mut deferred_pool = DeferredPool::new(0);
 
         // insert txs of same sender
         let alice = Random.generate().unwrap();
@@ -765,7 +1143,7 @@ mod test_transaction_pool_inner {
 
     #[test]
     fn test_deferred_pool_recalculate_readiness() {
-        let mut deferred_pool = super::DeferredPool::new();
+        let mut deferred_pool = super::DeferredPool::new(0);
 
         let alice = Random.generate().unwrap();
 
@@ -883,4 +1261,266 @@ mod test_transaction_pool_inner {
             None
         );
     }
+
+    #[test]
+    fn test_deferred_pool_replace_by_fee_and_nonce_gap() {
+        let mut deferred_pool = DeferredPool::new(10);
+
+        let alice = Random.generate().unwrap();
+
+        let tx1 = new_test_tx_with_read_info(
+            &alice, 1, 100, 100, false, /* packed */
+        );
+        let tx1_low = new_test_tx_with_read_info(
+            &alice, 1, 109, 100, false, /* packed */
+        );
+        let tx1_high = new_test_tx_with_read_info(
+            &alice, 1, 110, 100, false, /* packed */
+        );
+        let tx2 = new_test_tx_with_read_info(
+            &alice, 2, 100, 100, false, /* packed */
+        );
+        let tx4 = new_test_tx_with_read_info(
+            &alice, 4, 100, 100, false, /* packed */
+        );
+
+        assert_eq!(
+            deferred_pool.insert(tx1.clone(), false /* force */),
+            InsertResult::NewAdded
+        );
+        assert_eq!(
+            deferred_pool.insert(tx1_low.clone(), false /* force */),
+            InsertResult::Failed(format!("Tx with same nonce already inserted, try to replace it with a higher gas price"))
+        );
+        assert_eq!(
+            deferred_pool.insert(tx1_high.clone(), false /* force */),
+            InsertResult::Updated(tx1.clone())
+        );
+
+        deferred_pool.insert(tx2.clone(), false /* force */);
+        deferred_pool.insert(tx4.clone(), false /* force */);
+
+        assert_eq!(
+            deferred_pool.split_by_nonce_gap(&alice.address(), &1.into()),
+            (
+                vec![tx1_high.transaction.clone(), tx2.transaction.clone()],
+                vec![tx4.transaction.clone()]
+            )
+        );
+        assert_eq!(
+            deferred_pool.split_by_nonce_gap(&alice.address(), &2.into()),
+            (vec![tx2.transaction.clone()], vec![tx4.transaction.clone()])
+        );
+        assert_eq!(
+            deferred_pool.split_by_nonce_gap(&alice.address(), &0.into()),
+            (
+                vec![],
+                vec![
+                    tx1_high.transaction.clone(),
+                    tx2.transaction.clone(),
+                    tx4.transaction.clone()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_evict_over_memory_budget() {
+        let alice = Random.generate().unwrap();
+        let bob = Random.generate().unwrap();
+        let carol = Random.generate().unwrap();
+        let dave = Random.generate().unwrap();
+
+        let alice_tx = new_test_tx(&alice, 0, 100, 100);
+        let bob_tx = new_test_tx(&bob, 0, 300, 100);
+        let carol_tx = new_test_tx(&carol, 0, 200, 100);
+        let dave_tx = new_test_tx(&dave, 0, 50, 100);
+
+        // room for two transactions only
+        let budget = tx_mem_size(&alice_tx) + tx_mem_size(&bob_tx) + 10;
+        let mut inner = TransactionPoolInner::with_capacity(100, 10, budget);
+
+        for tx in &[&alice_tx, &bob_tx, &carol_tx] {
+            assert_eq!(
+                inner.insert_transaction_without_readiness_check(
+                    (*tx).clone(),
+                    false, /* packed */
+                    false, /* force */
+                ),
+                InsertResult::NewAdded
+            );
+        }
+        // the cheapest one is evicted
+        assert!(inner.get(&alice_tx.hash()).is_none());
+        assert!(inner.get(&bob_tx.hash()).is_some());
+        assert!(inner.get(&carol_tx.hash()).is_some());
+        assert_eq!(inner.total_deferred(), 2);
+        assert_eq!(inner.total_unpacked(), 2);
+        assert_eq!(
+            inner.memory_used(),
+            tx_mem_size(&bob_tx) + tx_mem_size(&carol_tx)
+        );
+
+        // a transaction cheaper than all existing ones is rejected
+        assert_eq!(
+            inner.insert_transaction_without_readiness_check(
+                dave_tx.clone(),
+                false, /* packed */
+                false, /* force */
+            ),
+            InsertResult::Failed("Transaction Pool is full".into())
+        );
+        assert_eq!(inner.total_deferred(), 2);
+        assert_eq!(inner.eviction_stats().over_memory_budget, 1);
+    }
+
+    fn insert_expired(
+        inner: &mut TransactionPoolInner, txs: &[&Arc<SignedTransaction>],
+    ) {
+        for tx in txs {
+            assert_eq!(
+                inner.insert_transaction_without_readiness_check(
+                    (*tx).clone(),
+                    false, /* packed */
+                    false, /* force */
+                ),
+                InsertResult::NewAdded
+            );
+        }
+        // make the inserted transactions old enough to be garbage-collected
+        for (_, timestamp) in inner.garbage_collection_queue.iter_mut() {
+            *timestamp = 0;
+        }
+    }
+
+    fn gc_queue_senders(inner: &TransactionPoolInner) -> Vec<Address> {
+        inner
+            .garbage_collection_queue
+            .iter()
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    #[test]
+    fn test_gc_skips_local_transactions_in_place() {
+        let alice = Random.generate().unwrap();
+        let bob = Random.generate().unwrap();
+        let carol = Random.generate().unwrap();
+        let dave = Random.generate().unwrap();
+
+        let alice_tx = new_test_tx(&alice, 0, 100, 100);
+        let bob_tx = new_test_tx(&bob, 0, 100, 100);
+        let carol_tx = new_test_tx(&carol, 0, 100, 100);
+        let dave_tx = new_test_tx(&dave, 0, 100, 100);
+
+        let mut inner = TransactionPoolInner::with_capacity(3, 10, 1 << 30);
+        insert_expired(&mut inner, &[&alice_tx, &bob_tx, &dave_tx]);
+        assert!(inner.mark_local(&alice_tx.hash()));
+
+        assert_eq!(
+            inner.insert_transaction_without_readiness_check(
+                carol_tx.clone(),
+                false, /* packed */
+                false, /* force */
+            ),
+            InsertResult::NewAdded
+        );
+        // the oldest non-local transaction is collected instead
+        assert!(inner.get(&alice_tx.hash()).is_some());
+        assert!(inner.get(&bob_tx.hash()).is_none());
+        assert!(inner.is_local(&alice_tx.hash()));
+        assert_eq!(inner.eviction_stats().garbage_collected, 1);
+        // the exempted transaction keeps its place in the queue
+        assert_eq!(
+            gc_queue_senders(&inner),
+            vec![alice.address(), dave.address(), carol.address()]
+        );
+    }
+
+    #[test]
+    fn test_gc_with_only_local_transactions() {
+        let alice = Random.generate().unwrap();
+        let bob = Random.generate().unwrap();
+        let carol = Random.generate().unwrap();
+
+        let alice_tx = new_test_tx(&alice, 0, 100, 100);
+        let bob_tx = new_test_tx(&bob, 0, 100, 100);
+        let carol_tx = new_test_tx(&carol, 0, 100, 100);
+
+        let mut inner = TransactionPoolInner::with_capacity(2, 10, 1 << 30);
+        insert_expired(&mut inner, &[&alice_tx, &bob_tx]);
+        assert!(inner.mark_local(&alice_tx.hash()));
+        assert!(inner.mark_local(&bob_tx.hash()));
+
+        assert_eq!(
+            inner.insert_transaction_without_readiness_check(
+                carol_tx.clone(),
+                false, /* packed */
+                false, /* force */
+            ),
+            InsertResult::Failed("Transaction Pool is full".into())
+        );
+        assert_eq!(inner.total_deferred(), 2);
+        assert_eq!(inner.local_transactions().len(), 2);
+        assert_eq!(
+            gc_queue_senders(&inner),
+            vec![alice.address(), bob.address()]
+        );
+    }
+
+    #[test]
+    fn test_pending_and_queued_count() {
+        let alice = Random.generate().unwrap();
+        let bob = Random.generate().unwrap();
+        let mut inner = TransactionPoolInner::with_capacity(100, 10, 1 << 30);
+        let check = |inner: &TransactionPoolInner, expected: (usize, usize)| {
+            let (pending, queued) = inner.pending_and_queued();
+            assert_eq!((pending.len(), queued.len()), expected);
+            assert_eq!(inner.total_pending_and_queued(), expected);
+        };
+        check(&inner, (0, 0));
+
+        for tx in &[
+            new_test_tx(&alice, 0, 100, 100),
+            new_test_tx(&alice, 1, 100, 100),
+            new_test_tx(&alice, 3, 100, 100),
+            new_test_tx(&bob, 5, 100, 100),
+        ] {
+            assert_eq!(
+                inner.insert_transaction_without_readiness_check(
+                    tx.clone(),
+                    false, /* packed */
+                    false, /* force */
+                ),
+                InsertResult::NewAdded
+            );
+        }
+        check(&inner, (2, 2));
+
+        // the executed transaction is no longer counted
+        inner.recalculate_readiness_with_fixed_info(
+            &alice.address(),
+            1.into(),
+            1_000_000.into(),
+        );
+        check(&inner, (1, 2));
+
+        // the nonce gap of alice is filled
+        inner.insert_transaction_without_readiness_check(
+            new_test_tx(&alice, 2, 100, 100),
+            false, /* packed */
+            false, /* force */
+        );
+        check(&inner, (3, 1));
+
+        inner.recalculate_readiness_with_fixed_info(
+            &bob.address(),
+            5.into(),
+            1_000_000.into(),
+        );
+        check(&inner, (4, 0));
+
+        inner.clear();
+        check(&inner, (0, 0));
+    }
 }
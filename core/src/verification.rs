@@ -4,30 +4,105 @@
 
 use crate::{
     error::{BlockError, Error},
-    parameters::block::*,
+    parameters::{block::*, consensus_internal::MEDIAN_TIME_PAST_WINDOW},
     pow,
     sync::{Error as SyncError, ErrorKind as SyncErrorKind},
 };
 use cfx_types::{H256, U256};
-use primitives::{Block, BlockHeader};
-use std::collections::HashSet;
+use primitives::{
+    transaction::TransactionError, Block, BlockHeader, TransactionWithSignature,
+};
+use std::{collections::HashSet, sync::Arc};
 use unexpected::{Mismatch, OutOfBounds};
 
+/// The median of `timestamps`, or 0 if there is none.
+pub fn median_time_past(mut timestamps: Vec<u64>) -> u64 {
+    timestamps.sort();
+    timestamps.get(timestamps.len() / 2).cloned().unwrap_or(0)
+}
+
+/// The median time past of the chain ending at `hash`, i.e. the median of
+/// the timestamps of its latest `MEDIAN_TIME_PAST_WINDOW` blocks, or of all
+/// of them if the chain is shorter. The headers are read with
+/// `header_by_hash`, and `None` is returned if one of them is missing.
+pub fn median_time_past_of<F>(
+    hash: &H256, mut header_by_hash: F,
+) -> Option<u64>
+where F: FnMut(&H256) -> Option<Arc<BlockHeader>> {
+    let mut timestamps = Vec::with_capacity(MEDIAN_TIME_PAST_WINDOW as usize);
+    let mut hash = *hash;
+    while timestamps.len() < MEDIAN_TIME_PAST_WINDOW as usize {
+        let header = header_by_hash(&hash)?;
+        timestamps.push(header.timestamp());
+        if header.height() == 0 {
+            break;
+        }
+        hash = *header.parent_hash();
+    }
+    Some(median_time_past(timestamps))
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct VerificationConfig {
     pub verify_timestamp: bool,
+    /// A block whose timestamp is ahead of the local clock by more than this
+    /// number of seconds is discarded, and its sender is disconnected.
+    pub valid_time_drift: u64,
+    /// A block whose timestamp is ahead of the local clock by more than this
+    /// number of seconds is queued until the clock catches up, instead of
+    /// being inserted into the graphs.
+    pub acceptable_time_drift: u64,
+    /// The chain id that transaction signatures are made for.
+    pub chain_id: u64,
+    /// Transactions signed without a chain id are rejected in the blocks at
+    /// or above this height, and are always accepted if it's `None`.
+    pub chain_id_enforcement_height: Option<u64>,
+    /// The timestamp of a block at or above this height must be after the
+    /// median time past of its parent. The rule is not enforced if it's
+    /// `None`.
+    pub median_time_past_activation_height: Option<u64>,
 }
 
 impl VerificationConfig {
-    pub fn new(test_mode: bool) -> Self {
-        if test_mode {
-            VerificationConfig {
-                verify_timestamp: false,
-            }
-        } else {
-            VerificationConfig {
-                verify_timestamp: true,
-            }
+    pub fn new(
+        test_mode: bool, chain_id: u64,
+        chain_id_enforcement_height: Option<u64>,
+        median_time_past_activation_height: Option<u64>, valid_time_drift: u64,
+        acceptable_time_drift: u64,
+    ) -> Self
+    {
+        VerificationConfig {
+            verify_timestamp: !test_mode,
+            valid_time_drift,
+            acceptable_time_drift,
+            chain_id,
+            chain_id_enforcement_height,
+            median_time_past_activation_height,
+        }
+    }
+
+    /// Whether the median time past rule is enforced for a block at
+    /// `height`.
+    pub fn median_time_past_active(&self, height: u64) -> bool {
+        self.median_time_past_activation_height
+            .map_or(false, |activation_height| height >= activation_height)
+    }
+
+    /// Check the chain id of a transaction to be included in a block at
+    /// `height`.
+    pub fn verify_transaction_chain_id(
+        &self, transaction: &TransactionWithSignature, height: u64,
+    ) -> Result<(), TransactionError> {
+        let accept_legacy = self
+            .chain_id_enforcement_height
+            .map_or(true, |enforcement_height| height < enforcement_height);
+        match transaction.chain_id() {
+            Some(chain_id) if chain_id == self.chain_id => Ok(()),
+            None if accept_legacy => Ok(()),
+            got => Err(TransactionError::InvalidChainId {
+                expected: self.chain_id,
+                got,
+            }),
         }
     }
 
@@ -70,7 +145,7 @@ impl VerificationConfig {
     pub fn validate_header_timestamp(
         &self, header: &BlockHeader, now: u64,
     ) -> Result<(), SyncError> {
-        let invalid_threshold = now + VALID_TIME_DRIFT;
+        let invalid_threshold = now + self.valid_time_drift;
         if header.timestamp() > invalid_threshold {
             warn!("block {} has incorrect timestamp", header.hash());
             return Err(SyncErrorKind::InvalidTimestamp.into());
@@ -148,8 +223,10 @@ impl VerificationConfig {
 
         let mut block_size = 0;
         let mut block_gas_limit = U256::zero();
+        let height = block.block_header.height();
         for t in &block.transactions {
             t.transaction.verify_basic()?;
+            self.verify_transaction_chain_id(&t.transaction, height)?;
             block_size += t.rlp_size();
             block_gas_limit += *t.gas_limit();
         }
@@ -177,3 +254,100 @@ impl VerificationConfig {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{median_time_past, median_time_past_of, VerificationConfig};
+    use crate::parameters::{
+        block::{ACCEPTABLE_TIME_DRIFT, VALID_TIME_DRIFT},
+        consensus_internal::MEDIAN_TIME_PAST_WINDOW,
+    };
+    use cfx_types::H256;
+    use primitives::{BlockHeader, BlockHeaderBuilder};
+    use std::{collections::HashMap, sync::Arc};
+
+    /// Build a chain with the given timestamps, and return the headers by
+    /// hash and the hashes from the genesis.
+    fn build_chain(
+        timestamps: &[u64],
+    ) -> (HashMap<H256, Arc<BlockHeader>>, Vec<H256>) {
+        let mut headers = HashMap::new();
+        let mut hashes = Vec::new();
+        let mut parent_hash = H256::zero();
+        for (height, timestamp) in timestamps.iter().enumerate() {
+            let header = BlockHeaderBuilder::new()
+                .with_parent_hash(parent_hash)
+                .with_height(height as u64)
+                .with_timestamp(*timestamp)
+                .build();
+            parent_hash = header.hash();
+            hashes.push(parent_hash);
+            headers.insert(parent_hash, Arc::new(header));
+        }
+        (headers, hashes)
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        assert_eq!(median_time_past(vec![]), 0);
+        assert_eq!(median_time_past(vec![7]), 7);
+        assert_eq!(median_time_past(vec![5, 1, 9]), 5);
+        assert_eq!(median_time_past(vec![4, 1, 3, 2]), 3);
+    }
+
+    #[test]
+    fn test_median_time_past_of_short_chain() {
+        let (headers, hashes) = build_chain(&[0, 30, 10, 20]);
+        let median =
+            median_time_past_of(&hashes[3], |hash| headers.get(hash).cloned());
+        assert_eq!(median, Some(20));
+        let median =
+            median_time_past_of(&hashes[0], |hash| headers.get(hash).cloned());
+        assert_eq!(median, Some(0));
+    }
+
+    #[test]
+    fn test_median_time_past_of_window() {
+        let window = MEDIAN_TIME_PAST_WINDOW as usize;
+        // A block far in the future does not move the median, and only the
+        // latest `window` blocks count.
+        let mut timestamps: Vec<u64> =
+            (0..2 * window as u64).map(|i| 100 + i).collect();
+        timestamps[2 * window - 1] = 1_000_000;
+        let (headers, hashes) = build_chain(&timestamps);
+        let mut read = 0;
+        let median = median_time_past_of(&hashes[2 * window - 1], |hash| {
+            read += 1;
+            headers.get(hash).cloned()
+        });
+        assert_eq!(median, Some(timestamps[2 * window - 1 - window / 2]));
+        assert_eq!(read, window);
+    }
+
+    #[test]
+    fn test_median_time_past_of_missing_header() {
+        let (mut headers, hashes) = build_chain(&[0, 1, 2, 3]);
+        headers.remove(&hashes[1]);
+        let median =
+            median_time_past_of(&hashes[3], |hash| headers.get(hash).cloned());
+        assert_eq!(median, None);
+    }
+
+    #[test]
+    fn test_median_time_past_activation() {
+        let config = |activation_height| {
+            VerificationConfig::new(
+                false,
+                1,
+                None,
+                activation_height,
+                VALID_TIME_DRIFT,
+                ACCEPTABLE_TIME_DRIFT,
+            )
+        };
+        assert!(!config(None).median_time_past_active(1_000_000));
+        assert!(!config(Some(100)).median_time_past_active(99));
+        assert!(config(Some(100)).median_time_past_active(100));
+        assert!(config(Some(100)).median_time_past_active(101));
+    }
+}
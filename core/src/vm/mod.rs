@@ -21,7 +21,7 @@ pub use self::{
     env::Env,
     error::{Error, ExecTrapResult, Result, TrapError, TrapKind, TrapResult},
     return_data::{GasLeft, ReturnData},
-    spec::{CleanDustMode, Spec, WasmCosts},
+    spec::{CleanDustMode, InvalidTransactionPolicy, Spec, WasmCosts},
 };
 
 /// Virtual Machine interface
@@ -133,6 +133,9 @@ pub struct Spec {
     pub keep_unsigned_nonce: bool,
     /// Wasm extra specs, if wasm activated
     pub wasm: Option<WasmCosts>,
+    /// How to execute the remaining transactions of a block after an invalid
+    /// one.
+    pub invalid_tx_policy: InvalidTransactionPolicy,
 }
 
 /// Wasm cost table
@@ -202,6 +205,20 @@ pub enum CleanDustMode {
     WithCodeAndStorage,
 }
 
+/// The handling of individually invalid transactions, e.g. with a future nonce
+/// or an insufficient gas limit, inside a valid block. Such transactions never
+/// bump the sender nonce. Transactions with stale nonces are not considered
+/// invalid here, because the same transaction is commonly packed by
+/// concurrent blocks in an epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidTransactionPolicy {
+    /// Skip the invalid transaction and continue with the next one.
+    Skip,
+    /// Skip the invalid transaction and all remaining transactions in the
+    /// block.
+    Reject,
+}
+
 impl Spec {
     pub fn new(
         max_code_size: usize, fix_exp: bool, no_empty: bool, kill_empty: bool,
@@ -258,6 +275,7 @@ impl Spec {
             kill_dust: CleanDustMode::Off,
             keep_unsigned_nonce: false,
             wasm: None,
+            invalid_tx_policy: InvalidTransactionPolicy::Skip,
         }
     }
 
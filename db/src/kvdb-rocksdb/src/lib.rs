@@ -169,6 +169,9 @@ pub struct DatabaseConfig {
 	pub columns: Option<u32>,
     /// Disable write-ahead-log
     pub disable_wal: bool,
+	/// Open an existing database without modifying it. Writes are discarded,
+	/// and a missing or corrupted database is not created or repaired.
+	pub read_only: bool,
 }
 
 impl DatabaseConfig {
@@ -197,6 +200,7 @@ impl Default for DatabaseConfig {
 			compaction: CompactionProfile::default(),
 			columns: None,
 			disable_wal: false,
+			read_only: false,
 		}
 	}
 }
@@ -205,6 +209,7 @@ pub struct DBAndColumns {
 	db: DB,
 	cf_names: Vec<String>,
 	path: String,
+	read_only: bool,
 	write_opts: WriteOptions,
 	read_opts: ReadOptions,
 	block_opts: BlockBasedOptions,
@@ -250,7 +255,7 @@ impl DBAndColumns {
 			// opts.set_parsed_options(&format!("rate_limiter_bytes_per_sec={}", rate_limit)).map_err(other_io_err)?;
 		}
 		opts.set_use_fsync(false);
-		opts.create_if_missing(true);
+		opts.create_if_missing(!config.read_only);
 		opts.set_max_open_files(config.max_open_files);
 		opts.set_keep_log_file_num(1);
 		opts.set_bytes_per_sync(1048576);
@@ -277,6 +282,9 @@ impl OpenHandler<DBAndColumns> for DBAndColumns {
 
 		// attempt database repair if it has been previously marked as corrupted
 		let db_corrupted = Path::new(path).join(CORRUPTION_FILE_NAME);
+		if db_corrupted.exists() && config.read_only {
+			return Err(other_io_err("DB has been marked as corrupted and cannot be repaired in read-only mode"));
+		}
 		if db_corrupted.exists() {
 			warn!("DB has been previously marked as corrupted, attempting repair");
 			let opts2 = Self::options(config);
@@ -303,7 +311,11 @@ impl OpenHandler<DBAndColumns> for DBAndColumns {
 		// TODO: add to upstream
 		// read_opts.set_verify_checksums(false);
 
+		// a read-only database neither takes the lock of the directory nor
+		// writes to it, so it can be opened while another process is writing
 		let db = match config.columns {
+			Some(_) if config.read_only => DB::open_cf_for_read_only(&opts, path, &cfnames, false),
+			None if config.read_only => DB::open_for_read_only(&opts, path, false),
 			Some(_) => {
 				match DB::open_cf_descriptors(&opts, path, cf_descriptors) {
 					db @ Ok(_) => db,
@@ -327,7 +339,7 @@ impl OpenHandler<DBAndColumns> for DBAndColumns {
 
 		let db = match db {
 			Ok(db) => db,
-			Err(ref s) if is_corrupted(&s.clone().into_string()) => {
+			Err(ref s) if is_corrupted(&s.clone().into_string()) && !config.read_only => {
 				warn!("DB corrupted: {}, attempting repair", s);
 				let opts2 = Self::options(config);
 				DB::repair(opts2, path).map_err(other_io_err)?;
@@ -355,6 +367,7 @@ impl OpenHandler<DBAndColumns> for DBAndColumns {
 			// TODO: avoid clone
 			cf_names: cf_names.clone(),
 			path: path.to_owned(),
+			read_only: config.read_only,
 			write_opts,
 			read_opts,
 			block_opts,
@@ -371,6 +384,7 @@ impl NumColumns for DatabaseConfig {
 struct RocksDBWriteTransaction<'a> {
 	batch: WriteBatch,
 	path: &'a str,
+	read_only: bool,
 	db: &'a DB,
 	write_opts: &'a WriteOptions,
 	cfs: Vec<ColumnFamily<'a>>,
@@ -394,7 +408,10 @@ impl<'a> WriteTransaction for RocksDBWriteTransaction<'a> {
 	}
 
 	fn commit(self: Box<Self>) -> io::Result<()> {
-		let Self { db, path, write_opts, batch, ..} = *self;
+		let Self { db, path, read_only, write_opts, batch, ..} = *self;
+		if read_only {
+			return Ok(());
+		}
 		check_for_corruption(
 			path,
 			db.write_opt(batch, write_opts),
@@ -432,6 +449,7 @@ impl TransactionHandler for DBAndColumns {
 			batch: WriteBatch::default(),
 			db: &self.db,
 			path: &self.path,
+			read_only: self.read_only,
 			write_opts: &self.write_opts,
 			cfs: self.column_families(),
 		})
@@ -650,6 +668,31 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn open_read_only_while_writing() {
+		let tempdir = TempDir::new("").unwrap();
+		let path = tempdir.path().to_str().unwrap();
+		let config = DatabaseConfig::with_columns(Some(1));
+		let db = Database::open(&config, path).unwrap();
+		let mut batch = db.transaction();
+		batch.put(Some(0), b"foo", b"bar");
+		db.write(batch).unwrap();
+		db.flush().unwrap();
+
+		// the lock of the directory is held by `db`
+		let mut read_only_config = DatabaseConfig::with_columns(Some(1));
+		read_only_config.read_only = true;
+		let read_only = Database::open(&read_only_config, path).unwrap();
+		assert_eq!(read_only.get(Some(0), b"foo").unwrap().unwrap().as_ref(), b"bar");
+
+		// the writes are discarded
+		let mut batch = read_only.transaction();
+		batch.put(Some(0), b"foo", b"baz");
+		read_only.write(batch).unwrap();
+		drop(read_only);
+		assert_eq!(db.get(Some(0), b"foo").unwrap().unwrap().as_ref(), b"bar");
+	}
+
 	#[test]
 	fn write_clears_buffered_ops() {
 		let tempdir = TempDir::new("").unwrap();
@@ -0,0 +1,274 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::node_table::NodeId;
+use serde_json;
+use std::{
+    collections::HashMap,
+    fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The node or IP address to refuse connections with.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BanTarget {
+    NodeId(NodeId),
+    Ip(IpAddr),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ban {
+    pub target: BanTarget,
+    pub reason: String,
+    /// The time of the ban in seconds since the unix epoch.
+    pub banned_at: u64,
+    /// The time when the ban expires in seconds since the unix epoch, or
+    /// `None` if it never expires.
+    pub expire_at: Option<u64>,
+}
+
+impl Ban {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expire_at.map_or(false, |expire_at| expire_at <= now)
+    }
+}
+
+/// The bans of nodes and IP addresses, which are saved whenever they are
+/// changed so that they survive restarts. The expired bans are ignored, and
+/// removed when the list is saved.
+pub struct BanList {
+    bans: HashMap<BanTarget, Ban>,
+    path: Option<PathBuf>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl BanList {
+    pub fn new(dir: Option<String>, filename: &str) -> Self {
+        let path = dir.map(|dir| {
+            let mut buf = PathBuf::from(dir);
+            buf.push(filename);
+            buf
+        });
+
+        let mut ban_list = BanList {
+            bans: HashMap::new(),
+            path,
+        };
+        ban_list.load_from_file();
+        ban_list
+    }
+
+    fn load_from_file(&mut self) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                debug!("ban list file not found: {:?}", e);
+                return;
+            }
+        };
+        let res: Result<Vec<Ban>, _> = serde_json::from_reader(file);
+        match res {
+            Ok(bans) => {
+                let now = now_secs();
+                for ban in bans {
+                    if !ban.is_expired(now) {
+                        self.bans.insert(ban.target.clone(), ban);
+                    }
+                }
+            }
+            Err(e) => warn!("Error reading ban list file: {:?}", e),
+        }
+    }
+
+    /// Ban `target` for `duration`, or permanently if `duration` is `None`.
+    /// The existing ban of `target` is replaced, and the list is saved.
+    pub fn ban(
+        &mut self, target: BanTarget, reason: String,
+        duration: Option<Duration>,
+    )
+    {
+        let now = now_secs();
+        let ban = Ban {
+            target: target.clone(),
+            reason,
+            banned_at: now,
+            expire_at: duration.map(|d| now.saturating_add(d.as_secs())),
+        };
+        self.bans.insert(target, ban);
+        self.save();
+    }
+
+    /// Lift the ban of `target`, save the list, and return the ban if it is
+    /// banned.
+    pub fn unban(&mut self, target: &BanTarget) -> Option<Ban> {
+        let ban = self
+            .bans
+            .remove(target)
+            .filter(|ban| !ban.is_expired(now_secs()));
+        self.save();
+        ban
+    }
+
+    pub fn is_banned(&self, target: &BanTarget) -> bool {
+        self.bans
+            .get(target)
+            .map_or(false, |ban| !ban.is_expired(now_secs()))
+    }
+
+    /// All the bans not expired yet.
+    pub fn all(&self) -> Vec<Ban> {
+        let now = now_secs();
+        self.bans
+            .values()
+            .filter(|ban| !ban.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    /// Remove the expired bans and save the others to the file.
+    pub fn save(&mut self) {
+        let now = now_secs();
+        self.bans.retain(|_, ban| !ban.is_expired(now));
+
+        let path = match self.path {
+            Some(ref path) => Path::new(path),
+            None => return,
+        };
+
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                warn!("Error creating ban list directory: {:?}", e);
+                return;
+            }
+        }
+
+        let bans: Vec<&Ban> = self.bans.values().collect();
+        match fs::File::create(&path) {
+            Ok(file) => {
+                if let Err(e) = serde_json::to_writer_pretty(file, &bans) {
+                    warn!("Error writing ban list file: {:?}", e);
+                }
+            }
+            Err(e) => {
+                warn!("Error creating ban list file: {:?}", e);
+            }
+        }
+    }
+}
+
+impl Drop for BanList {
+    fn drop(&mut self) { self.save(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{now_secs, Ban, BanList, BanTarget};
+    use crate::node_table::NodeId;
+    use std::{env, fs, net::IpAddr, path::PathBuf, time::Duration};
+
+    const BANNED_FILE: &str = "banned.json";
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn open(dir: &PathBuf) -> BanList {
+        BanList::new(Some(dir.to_str().unwrap().into()), BANNED_FILE)
+    }
+
+    #[test]
+    fn test_save_load() {
+        let dir = test_dir("ban_list_save_load_test");
+        let node = BanTarget::NodeId(NodeId::random());
+        let ip = BanTarget::Ip("10.0.0.1".parse::<IpAddr>().unwrap());
+        let unbanned = BanTarget::NodeId(NodeId::random());
+        {
+            let mut ban_list = open(&dir);
+            ban_list.ban(node.clone(), "spam".into(), None);
+            ban_list.ban(
+                ip.clone(),
+                "flood".into(),
+                Some(Duration::from_secs(3600)),
+            );
+            ban_list.ban(unbanned.clone(), "mistake".into(), None);
+            assert!(ban_list.unban(&unbanned).is_some());
+
+            // The changes are saved without waiting for the list to be
+            // dropped.
+            let loaded = open(&dir);
+            let mut bans = loaded.all();
+            bans.sort_by_key(|ban| ban.reason.clone());
+            let mut expected = ban_list.all();
+            expected.sort_by_key(|ban| ban.reason.clone());
+            assert_eq!(bans, expected);
+            assert_eq!(bans.len(), 2);
+        }
+
+        let ban_list = open(&dir);
+        assert!(ban_list.is_banned(&node));
+        assert!(ban_list.is_banned(&ip));
+        assert!(!ban_list.is_banned(&unbanned));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expired_bans_dropped_on_load() {
+        let dir = test_dir("ban_list_expired_test");
+        fs::create_dir_all(&dir).unwrap();
+        let now = now_secs();
+        let expired = BanTarget::NodeId(NodeId::random());
+        let active = BanTarget::NodeId(NodeId::random());
+        let bans = vec![
+            Ban {
+                target: expired.clone(),
+                reason: "expired".into(),
+                banned_at: now - 20,
+                expire_at: Some(now - 10),
+            },
+            Ban {
+                target: active.clone(),
+                reason: "active".into(),
+                banned_at: now - 20,
+                expire_at: Some(now + 3600),
+            },
+        ];
+        fs::write(
+            dir.join(BANNED_FILE),
+            serde_json::to_string(&bans).unwrap(),
+        )
+        .unwrap();
+
+        let ban_list = open(&dir);
+        assert!(!ban_list.is_banned(&expired));
+        assert!(ban_list.is_banned(&active));
+        assert_eq!(ban_list.all(), vec![bans[1].clone()]);
+        assert!(!ban_list.bans.contains_key(&expired));
+        drop(ban_list);
+
+        // The expired ban is not written back.
+        let saved: Vec<Ban> = serde_json::from_str(
+            &fs::read_to_string(dir.join(BANNED_FILE)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(saved, vec![bans[1].clone()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
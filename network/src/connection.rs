@@ -22,7 +22,7 @@ use std::{
         atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering},
         Arc,
     },
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 lazy_static! {
@@ -70,6 +70,13 @@ lazy_static! {
             "network_system_data",
             "send_queue_size"
         );
+    static ref NETWORK_SEND_QUEUE_BYTES: Arc<dyn Gauge<usize>> =
+        GaugeUsize::register_with_group(
+            "network_system_data",
+            "send_queue_bytes"
+        );
+    static ref SEND_DROPPED_METER: Arc<dyn Meter> =
+        register_meter_with_group("network_system_data", "send_dropped");
 }
 
 /// Connection write status.
@@ -85,6 +92,27 @@ pub enum WriteStatus {
 
 const MAX_PAYLOAD_SIZE: usize = (1 << 24) - 1;
 
+/// The limits of the send queue of a connection.
+#[derive(Debug, Clone, Copy)]
+pub struct SendQueueConfig {
+    /// Maximum bytes of the queued packets. When reached, the oldest packets
+    /// of `Low` priority are dropped first, then the ones of `Normal`
+    /// priority. The packets of `High` priority are never dropped.
+    pub max_bytes: usize,
+    /// The connection is congested if the smoothed latency from enqueuing a
+    /// packet to sending it out exceeds this.
+    pub congestion_latency: Duration,
+}
+
+impl Default for SendQueueConfig {
+    fn default() -> Self {
+        SendQueueConfig {
+            max_bytes: 64 * 1024 * 1024,
+            congestion_latency: Duration::from_secs(5),
+        }
+    }
+}
+
 /// Global counter for high priority packets.
 /// It is used among connections to send high priority packets.
 static HIGH_PRIORITY_PACKETS: AtomicUsize = AtomicUsize::new(0);
@@ -220,6 +248,13 @@ pub struct GenericConnection<Socket: GenericSocket> {
     recv_buf: BytesMut,
     /// Packets that waiting for sending out.
     send_queue: PrioritySendQueue<Packet>,
+    /// Total bytes of the packets in `send_queue`.
+    send_queue_bytes: usize,
+    send_queue_config: SendQueueConfig,
+    /// Number of packets dropped since the send queue is full.
+    dropped_packets: usize,
+    /// Smoothed latency from enqueuing a packet to sending it out.
+    send_latency: Option<Duration>,
     /// Sending packet.
     sending_packet: Option<Packet>,
     /// Event flags this connection interested
@@ -228,6 +263,12 @@ pub struct GenericConnection<Socket: GenericSocket> {
     registered: AtomicBool,
     /// Assemble packet with extra information before sending out.
     assembler: Box<dyn PacketAssembler>,
+    /// Total bytes read from the socket.
+    bytes_read: usize,
+    /// Total bytes written to the socket.
+    bytes_written: usize,
+    /// The time when the connection is created.
+    creation_time: Instant,
 }
 
 impl<Socket: GenericSocket> GenericConnection<Socket> {
@@ -251,6 +292,7 @@ impl<Socket: GenericSocket> GenericConnection<Socket> {
                         size
                     );
                     READ_METER.mark(size);
+                    self.bytes_read += size;
                     if size == 0 {
                         break;
                     }
@@ -300,6 +342,7 @@ impl<Socket: GenericSocket> GenericConnection<Socket> {
         );
 
         WRITE_METER.mark(size);
+        self.bytes_written += size;
         Ok(size)
     }
 
@@ -323,6 +366,9 @@ impl<Socket: GenericSocket> GenericConnection<Socket> {
                 None => return Ok(WriteStatus::Complete),
             };
 
+            self.send_queue_bytes =
+                self.send_queue_bytes.saturating_sub(packet.throttling_size);
+
             if priority != SendQueuePriority::High {
                 trace!(
                     "Low priority packet promoted to high priority, token = {}",
@@ -361,8 +407,14 @@ impl<Socket: GenericSocket> GenericConnection<Socket> {
 
         WRITE_METER.mark(size);
         WRITABLE_COUNTER.mark(1);
+        self.bytes_written += size;
         if packet.is_send_completed() {
             trace!("Packet sent, token = {}", self.token);
+            let latency = packet.creation_time.elapsed();
+            self.send_latency = Some(match self.send_latency {
+                Some(smoothed) => (smoothed * 7 + latency) / 8,
+                None => latency,
+            });
             self.sending_packet = None;
 
             WRITABLE_PACKET_COUNTER.mark(1);
@@ -383,10 +435,60 @@ impl<Socket: GenericSocket> GenericConnection<Socket> {
             self.interest.remove(Ready::writable());
         }
         NETWORK_SEND_QUEUE_SIZE.update(self.send_queue.len());
+        NETWORK_SEND_QUEUE_BYTES.update(self.send_queue_bytes);
         io.update_registration(self.token)?;
         Ok(status)
     }
 
+    /// Drop the oldest packets of the priorities strictly lower than
+    /// `priority`, from the lowest one, until `size` more bytes fit in the
+    /// send queue. Returns false if they do not fit even after dropping all
+    /// such packets, in which case nothing is dropped unless `priority` is
+    /// `High`, since a packet of `High` priority is queued anyway.
+    fn make_room(&mut self, size: usize, priority: SendQueuePriority) -> bool {
+        let max_bytes = self.send_queue_config.max_bytes;
+        if self.send_queue_bytes + size <= max_bytes {
+            return true;
+        }
+        let lower_priorities: Vec<SendQueuePriority> =
+            [SendQueuePriority::Low, SendQueuePriority::Normal]
+                .iter()
+                .cloned()
+                .filter(|lower| *lower > priority)
+                .collect();
+        if priority != SendQueuePriority::High {
+            let droppable_bytes: usize = lower_priorities
+                .iter()
+                .map(|lower| {
+                    self.send_queue
+                        .iter_by_priority(*lower)
+                        .map(|packet| packet.throttling_size)
+                        .sum::<usize>()
+                })
+                .sum();
+            if self.send_queue_bytes.saturating_sub(droppable_bytes) + size
+                > max_bytes
+            {
+                return false;
+            }
+        }
+        for lower in lower_priorities {
+            while self.send_queue_bytes + size > max_bytes {
+                match self.send_queue.pop_front_by_priority(lower) {
+                    Some(packet) => {
+                        self.send_queue_bytes = self
+                            .send_queue_bytes
+                            .saturating_sub(packet.throttling_size);
+                        self.dropped_packets += 1;
+                        SEND_DROPPED_METER.mark(1);
+                    }
+                    None => break,
+                }
+            }
+        }
+        self.send_queue_bytes + size <= max_bytes
+    }
+
     /// Add a packet to send queue.
     pub fn send<Message: Sync + Send + Clone + 'static>(
         &mut self, io: &IoContext<Message>, data: Vec<u8>,
@@ -401,15 +503,30 @@ impl<Socket: GenericSocket> GenericConnection<Socket> {
 
             trace!("Sending packet, token = {}, size = {}", self.token, size);
 
+            if !self.make_room(size, priority)
+                && priority != SendQueuePriority::High
+            {
+                debug!(
+                    "Send queue full, drop packet, token = {}, size = {}",
+                    self.token, size
+                );
+                self.dropped_packets += 1;
+                SEND_DROPPED_METER.mark(1);
+                return Ok(SendQueueStatus {
+                    queue_length: self.send_queue.len(),
+                });
+            }
+
             let packet = Packet::new(data, priority)?;
             self.send_queue.push_back(packet, priority);
+            self.send_queue_bytes += size;
 
             SEND_METER.mark(size);
             match priority {
                 SendQueuePriority::High => {
                     SEND_HIGH_PRIORITY_METER.mark(size);
                 }
-                SendQueuePriority::Normal => {
+                SendQueuePriority::Normal | SendQueuePriority::Low => {
                     SEND_LOW_PRIORITY_METER.mark(size);
                 }
             }
@@ -426,6 +543,18 @@ impl<Socket: GenericSocket> GenericConnection<Socket> {
     }
 
     pub fn is_sending(&self) -> bool { self.interest.is_writable() }
+
+    pub fn set_send_queue_config(&mut self, config: SendQueueConfig) {
+        self.send_queue_config = config;
+    }
+
+    /// Returns true if the packets are sent out slowly, or the send queue is
+    /// more than half full.
+    pub fn is_congested(&self) -> bool {
+        self.send_latency.map_or(false, |latency| {
+            latency > self.send_queue_config.congestion_latency
+        }) || self.send_queue_bytes > self.send_queue_config.max_bytes / 2
+    }
 }
 
 pub type Connection = GenericConnection<TcpStream>;
@@ -437,10 +566,17 @@ impl Connection {
             socket,
             recv_buf: BytesMut::new(),
             send_queue: PrioritySendQueue::new(),
+            send_queue_bytes: 0,
+            send_queue_config: SendQueueConfig::default(),
+            dropped_packets: 0,
+            send_latency: None,
             sending_packet: None,
             interest: Ready::hup() | Ready::readable(),
             registered: AtomicBool::new(false),
             assembler: Box::new(PacketWithLenAssembler::default()),
+            bytes_read: 0,
+            bytes_written: 0,
+            creation_time: Instant::now(),
         }
     }
 
@@ -520,6 +656,14 @@ impl Connection {
     }
 
     pub fn details(&self) -> ConnectionDetails {
+        let elapsed_ms = self.creation_time.elapsed().as_millis();
+        let throughput = |bytes: usize| {
+            if elapsed_ms > 0 {
+                bytes as f64 * 1000.0 / elapsed_ms as f64
+            } else {
+                0.0
+            }
+        };
         ConnectionDetails {
             token: self.token,
             recv_buf: self.recv_buf.len(),
@@ -533,8 +677,21 @@ impl Connection {
             priority_queue_high: self
                 .send_queue
                 .len_by_priority(SendQueuePriority::High),
+            priority_queue_low: self
+                .send_queue
+                .len_by_priority(SendQueuePriority::Low),
+            send_queue_bytes: self.send_queue_bytes,
+            dropped_packets: self.dropped_packets,
+            send_latency: self
+                .send_latency
+                .map(|latency| format!("{:?}", latency)),
+            congested: self.is_congested(),
             interest: format!("{:?}", self.interest),
             registered: self.registered.load(AtomicOrdering::SeqCst),
+            bytes_read: self.bytes_read,
+            bytes_written: self.bytes_written,
+            read_throughput: throughput(self.bytes_read),
+            write_throughput: throughput(self.bytes_written),
         }
     }
 }
@@ -548,8 +705,19 @@ pub struct ConnectionDetails {
     pub sending_buf: usize,
     pub priority_queue_normal: usize,
     pub priority_queue_high: usize,
+    pub priority_queue_low: usize,
+    pub send_queue_bytes: usize,
+    pub dropped_packets: usize,
+    pub send_latency: Option<String>,
+    pub congested: bool,
     pub interest: String,
     pub registered: bool,
+    pub bytes_read: usize,
+    pub bytes_written: usize,
+    /// Average bytes read per second since the connection is created.
+    pub read_throughput: f64,
+    /// Average bytes written per second since the connection is created.
+    pub write_throughput: f64,
 }
 
 /// Assembler that prefix packet with length information.
@@ -726,11 +894,18 @@ mod tests {
                 token: 1234567890usize,
                 socket: TestSocket::new(),
                 send_queue: PrioritySendQueue::new(),
+                send_queue_bytes: 0,
+                send_queue_config: SendQueueConfig::default(),
+                dropped_packets: 0,
+                send_latency: None,
                 sending_packet: None,
                 recv_buf: BytesMut::new(),
                 interest: Ready::hup() | Ready::readable(),
                 registered: AtomicBool::new(false),
                 assembler: Box::new(PacketWithLenAssembler::new(1, None)),
+                bytes_read: 0,
+                bytes_written: 0,
+                creation_time: Instant::now(),
             }
         }
     }
@@ -771,6 +946,64 @@ mod tests {
         assert_eq!(sending_packet.sending_pos, 10);
     }
 
+    #[test]
+    fn connection_send_queue_drop() {
+        let mut connection = TestConnection::new();
+        connection.send_queue_config.max_bytes = 100;
+        let io = test_io();
+        let len_by_priority =
+            |connection: &TestConnection, priority: SendQueuePriority| {
+                connection.send_queue.len_by_priority(priority)
+            };
+
+        connection
+            .send(&io, vec![0; 40], SendQueuePriority::Low)
+            .unwrap();
+        connection
+            .send(&io, vec![0; 40], SendQueuePriority::Normal)
+            .unwrap();
+        // the packet of low priority is dropped for a packet of normal
+        // priority
+        connection
+            .send(&io, vec![0; 40], SendQueuePriority::Normal)
+            .unwrap();
+        assert_eq!(len_by_priority(&connection, SendQueuePriority::Low), 0);
+        assert_eq!(len_by_priority(&connection, SendQueuePriority::Normal), 2);
+        assert_eq!(connection.dropped_packets, 1);
+
+        // a packet never evicts the packets of the same or higher priority,
+        // and is dropped itself instead
+        connection
+            .send(&io, vec![0; 40], SendQueuePriority::Low)
+            .unwrap();
+        assert_eq!(len_by_priority(&connection, SendQueuePriority::Low), 0);
+        assert_eq!(len_by_priority(&connection, SendQueuePriority::Normal), 2);
+        assert_eq!(connection.dropped_packets, 2);
+
+        connection
+            .send(&io, vec![0; 40], SendQueuePriority::High)
+            .unwrap();
+        assert_eq!(len_by_priority(&connection, SendQueuePriority::Normal), 1);
+        assert_eq!(len_by_priority(&connection, SendQueuePriority::High), 1);
+        assert_eq!(connection.dropped_packets, 3);
+
+        connection
+            .send(&io, vec![0; 40], SendQueuePriority::Normal)
+            .unwrap();
+        assert_eq!(len_by_priority(&connection, SendQueuePriority::Normal), 1);
+        assert_eq!(connection.send_queue_bytes, 80);
+        assert_eq!(connection.dropped_packets, 4);
+
+        // the packets of high priority are never dropped
+        connection
+            .send(&io, vec![0; 80], SendQueuePriority::High)
+            .unwrap();
+        assert_eq!(len_by_priority(&connection, SendQueuePriority::High), 2);
+        assert_eq!(connection.send_queue.len(), 2);
+        assert_eq!(connection.send_queue_bytes, 120);
+        assert_eq!(connection.dropped_packets, 5);
+    }
+
     #[test]
     fn connection_read() {
         let mut connection = TestConnection::new();
@@ -80,6 +80,10 @@ impl NodeIpLimit {
         Some(self.subnet_type.subnet(ip))
     }
 
+    /// Get the subnet of specified `ip`, which is the same as the subnet of
+    /// the `NodeBucket` that a node with this `ip` belongs to.
+    pub fn subnet_of(&self, ip: &IpAddr) -> u32 { self.subnet_type.subnet(ip) }
+
     /// Remove the specified node `id` and return `true` if removed
     /// successfully. If not found, return `false`.
     pub fn remove(&mut self, id: &NodeId) -> bool {
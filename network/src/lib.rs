@@ -37,6 +37,7 @@ pub type ProtocolId = [u8; PROTOCOL_ID_SIZE];
 pub type HandlerWorkType = u8;
 pub type PeerId = usize;
 
+mod ban_list;
 mod connection;
 mod discovery;
 mod error;
@@ -51,7 +52,8 @@ mod session_manager;
 pub mod throttling;
 
 pub use crate::{
-    connection::get_high_priority_packets,
+    ban_list::{Ban, BanTarget},
+    connection::{get_high_priority_packets, SendQueueConfig},
     error::{DisconnectReason, Error, ErrorKind, ThrottlingReason},
     ip::SessionIpLimitConfig,
     node_table::Node,
@@ -66,7 +68,8 @@ use crate::{
         DEFAULT_CONNECTION_LIFETIME_FOR_PROMOTION,
         DEFAULT_DISCOVERY_REFRESH_TIMEOUT, DEFAULT_DISCOVERY_ROUND_TIMEOUT,
         DEFAULT_FAST_DISCOVERY_REFRESH_TIMEOUT, DEFAULT_HOUSEKEEPING_TIMEOUT,
-        DEFAULT_NODE_TABLE_TIMEOUT,
+        DEFAULT_NODE_TABLE_TIMEOUT, DEFAULT_SESSION_PING_INTERVAL,
+        DEFAULT_SESSION_PING_TIMEOUT,
     },
 };
 use ipnetwork::{IpNetwork, IpNetworkError};
@@ -109,6 +112,10 @@ pub struct NetworkConfiguration {
     /// not required to connect to archive nodes. E.g. light node or full node
     /// need not to connect to archive nodes.
     pub max_outgoing_peers_archive: usize,
+    /// Maximum number of outgoing peers in the same subnet, so that sampled
+    /// peers are not concentrated in a few subnets. 0 represents unlimited.
+    /// Reserved nodes are not restricted.
+    pub max_outgoing_peers_per_subnet: usize,
     /// Maximum number of incoming peers
     pub max_incoming_peers: usize,
     /// Maximum number of ongoing handshakes
@@ -131,10 +138,20 @@ pub struct NetworkConfiguration {
     pub node_table_timeout: Duration,
     /// Connection lifetime threshold for promotion
     pub connection_lifetime_for_promotion: Duration,
+    /// Interval of the Ping packets sent to measure the round trip time
+    pub session_ping_interval: Duration,
+    /// Timeout of the Ping packets, after which the session is regarded as
+    /// half-open and disconnected
+    pub session_ping_timeout: Duration,
+    /// Idle duration after which the session without protocol packets is
+    /// disconnected, or never if `None`
+    pub session_idle_timeout: Option<Duration>,
     pub test_mode: bool,
     /// Maximum number of P2P nodes for subnet B (ip/16).
     pub subnet_quota: usize,
     pub session_ip_limit_config: SessionIpLimitConfig,
+    /// Limits of the send queue of each session
+    pub send_queue_config: SendQueueConfig,
 }
 
 impl Default for NetworkConfiguration {
@@ -155,6 +172,7 @@ impl NetworkConfiguration {
             use_secret: None,
             max_outgoing_peers: 16,
             max_outgoing_peers_archive: 0,
+            max_outgoing_peers_per_subnet: 0,
             max_incoming_peers: 32,
             max_handshakes: 64,
             reserved_nodes: Vec::new(),
@@ -167,9 +185,13 @@ impl NetworkConfiguration {
             node_table_timeout: DEFAULT_NODE_TABLE_TIMEOUT,
             connection_lifetime_for_promotion:
                 DEFAULT_CONNECTION_LIFETIME_FOR_PROMOTION,
+            session_ping_interval: DEFAULT_SESSION_PING_INTERVAL,
+            session_ping_timeout: DEFAULT_SESSION_PING_TIMEOUT,
+            session_idle_timeout: None,
             test_mode: false,
             subnet_quota: 32,
             session_ip_limit_config: SessionIpLimitConfig::default(),
+            send_queue_config: SendQueueConfig::default(),
         }
     }
 
@@ -246,6 +268,14 @@ pub enum UpdateNodeOperation {
 pub trait NetworkContext {
     fn get_peer_node_id(&self, peer: PeerId) -> NodeId;
 
+    /// The smoothed round trip time of the session with the peer, measured
+    /// by the Ping packets.
+    fn get_peer_rtt(&self, peer: PeerId) -> Option<Duration>;
+
+    /// Returns true if the messages to the peer are queued for too long, so
+    /// that the requests should be sent to the other peers if possible.
+    fn is_peer_congested(&self, peer: PeerId) -> bool;
+
     fn send(
         &self, peer: PeerId, msg: Vec<u8>, priority: SendQueuePriority,
     ) -> Result<(), Error>;
@@ -264,6 +294,10 @@ pub trait NetworkContext {
     fn dispatch_work(&self, work_type: HandlerWorkType);
 
     fn insert_peer_node_tag(&self, peer: PeerId, key: &str, value: &str);
+
+    /// The version of this protocol negotiated with the peer, which is the
+    /// highest version supported by both sides.
+    fn get_protocol_version(&self, peer: PeerId) -> Option<u8>;
 }
 
 #[derive(Debug, Clone)]
@@ -3,6 +3,7 @@
 // See http://www.gnu.org/licenses/
 
 use crate::{
+    ban_list::{Ban, BanList, BanTarget},
     ip::{NodeIpLimit, NodeTagIndex, ValidateInsertResult},
     node_table::{Node, NodeContact, NodeEntry, NodeId, NodeTable},
     IpFilter,
@@ -13,6 +14,7 @@ use std::{collections::HashSet, net::IpAddr, time::Duration};
 const TRUSTED_NODES_FILE: &str = "trusted_nodes.json";
 const UNTRUSTED_NODES_FILE: &str = "untrusted_nodes.json";
 const BLACKLISTED_NODES_FILE: &str = "blacklisted_nodes.json";
+const BANNED_FILE: &str = "banned.json";
 
 /// Node database maintains all P2P nodes in trusted and untrusted node tables,
 /// and supports to limit the number of nodes for the same IP address.
@@ -52,6 +54,13 @@ const BLACKLISTED_NODES_FILE: &str = "blacklisted_nodes.json";
 /// ## Demote
 /// Demote a node to untrusted when failed to handle protocol messages.
 ///
+/// # Ban
+///
+/// The node operator could ban a node id or an IP address, permanently or
+/// until the ban expires. Unlike the blacklisted nodes, the banned nodes are
+/// kept in the node tables, but no connection is created with them in either
+/// direction.
+///
 /// # Subnet limitation
 ///
 /// Attacker could easily simulate a large amount of malicious nodes of
@@ -92,6 +101,9 @@ pub struct NodeDatabase {
     // Maximum duration to blacklist a node since last contact.
     blacklisted_lifetime: Duration,
 
+    // Node ids and IP addresses banned by the node operator.
+    ban_list: BanList,
+
     // IP address/subnet index for trusted and untrusted nodes.
     ip_limit: NodeIpLimit,
 
@@ -116,8 +128,12 @@ impl NodeDatabase {
         let mut db = NodeDatabase {
             trusted_nodes,
             untrusted_nodes,
-            blacklisted_nodes: NodeTable::new(path, BLACKLISTED_NODES_FILE),
+            blacklisted_nodes: NodeTable::new(
+                path.clone(),
+                BLACKLISTED_NODES_FILE,
+            ),
             blacklisted_lifetime: Duration::from_secs(7 * 24 * 3600),
+            ban_list: BanList::new(path, BANNED_FILE),
             ip_limit,
             trusted_node_tag_index,
         };
@@ -345,6 +361,10 @@ impl NodeDatabase {
         }
     }
 
+    /// Get the subnet of the specified `ip`, grouped in the same way as the
+    /// node buckets in database.
+    pub fn subnet_of(&self, ip: &IpAddr) -> u32 { self.ip_limit.subnet_of(ip) }
+
     pub fn sample_trusted_node_ids_with_tag(
         &self, count: u32, key: &String, value: &String,
     ) -> HashSet<NodeId> {
@@ -376,6 +396,8 @@ impl NodeDatabase {
         self.untrusted_nodes.clear_useless();
 
         self.blacklisted_nodes.save();
+
+        self.ban_list.save();
     }
 
     /// Promote untrusted nodes to trusted with the given duration.
@@ -415,6 +437,11 @@ impl NodeDatabase {
                 removed_trusted_node,
                 false, /* preserve_last_contact */
             );
+            // Save the trusted nodes right away, so that the demoted node is
+            // not trusted again after a restart. The untrusted nodes are
+            // saved periodically, and the demoted node is only forgotten if
+            // they are not saved in time.
+            self.trusted_nodes.save();
         }
     }
 
@@ -548,6 +575,35 @@ impl NodeDatabase {
         }
     }
 
+    /// Ban the specified node id or IP address for `duration`, or
+    /// permanently if `duration` is `None`.
+    pub fn ban(
+        &mut self, target: BanTarget, reason: String,
+        duration: Option<Duration>,
+    )
+    {
+        self.ban_list.ban(target, reason, duration);
+    }
+
+    /// Lift the ban of the specified node id or IP address, return `None` if
+    /// it is not banned.
+    pub fn unban(&mut self, target: &BanTarget) -> Option<Ban> {
+        self.ban_list.unban(target)
+    }
+
+    pub fn bans(&self) -> Vec<Ban> { self.ban_list.all() }
+
+    pub fn is_ip_banned(&self, ip: &IpAddr) -> bool {
+        self.ban_list.is_banned(&BanTarget::Ip(*ip))
+    }
+
+    /// Check if the specified node, or its IP address if `ip` is given, is
+    /// banned.
+    pub fn is_banned(&self, id: &NodeId, ip: Option<&IpAddr>) -> bool {
+        self.ban_list.is_banned(&BanTarget::NodeId(*id))
+            || ip.map_or(false, |ip| self.is_ip_banned(ip))
+    }
+
     /// Check if the specified node is blacklisted.
     /// If blacklisted for a long time, it will be removed from blacklisted node
     /// table.
@@ -582,8 +638,11 @@ impl NodeDatabase {
 #[cfg(test)]
 mod tests {
     use super::NodeDatabase;
-    use crate::node_table::{NodeEndpoint, NodeEntry, NodeId};
-    use std::{str::FromStr, time::Duration};
+    use crate::{
+        ban_list::BanTarget,
+        node_table::{NodeEndpoint, NodeEntry, NodeId},
+    };
+    use std::{env, fs, str::FromStr, time::Duration};
 
     fn new_entry(addr: &str) -> NodeEntry {
         NodeEntry {
@@ -709,6 +768,25 @@ mod tests {
         assert!(db.get(&entry.id, false /* trusted_only */).is_some());
     }
 
+    #[test]
+    fn test_demote_saved() {
+        let dir = env::temp_dir().join("node_database_demote_test");
+        let _ = fs::remove_dir_all(&dir);
+        let path = Some(dir.to_str().unwrap().to_string());
+        let mut db = NodeDatabase::new(path.clone(), 2);
+        let entry = new_entry("127.0.0.1:999");
+        db.insert_trusted(entry.clone());
+        db.save();
+
+        // The demotion is saved before the database is saved or dropped.
+        db.demote(&entry.id);
+        let reopened = NodeDatabase::new(path, 2);
+        assert_eq!(reopened.get(&entry.id, true /* trusted_only */), None);
+        drop(reopened);
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_remove() {
         let mut db = NodeDatabase::new(None, 2);
@@ -764,4 +842,34 @@ mod tests {
         assert_eq!(db.evaluate_blacklisted(&n.id), false);
         assert_eq!(db.get(&n.id, false), None);
     }
+
+    #[test]
+    fn test_ban() {
+        let mut db = NodeDatabase::new(None, 2);
+
+        let n = new_entry("127.0.0.1:999");
+        let ip = n.endpoint.address.ip();
+        db.insert_trusted(n.clone());
+        assert!(!db.is_banned(&n.id, Some(&ip)));
+
+        // banned by IP address, but still in the node table
+        db.ban(BanTarget::Ip(ip), "test".into(), None);
+        assert!(db.is_banned(&n.id, Some(&ip)));
+        assert!(!db.is_banned(&n.id, None /* ip */));
+        assert_eq!(db.get(&n.id, true /* trusted_only */).unwrap().id, n.id);
+        assert_eq!(db.bans().len(), 1);
+
+        assert!(db.unban(&BanTarget::Ip(ip)).is_some());
+        assert!(db.unban(&BanTarget::Ip(ip)).is_none());
+        assert!(!db.is_banned(&n.id, Some(&ip)));
+
+        // expired ban
+        db.ban(
+            BanTarget::NodeId(n.id),
+            "test".into(),
+            Some(Duration::new(0, 0)),
+        );
+        assert!(!db.is_banned(&n.id, None /* ip */));
+        assert!(db.bans().is_empty());
+    }
 }
@@ -4,6 +4,7 @@
 
 use super::DisconnectReason;
 use crate::{
+    ban_list::{Ban, BanTarget},
     discovery::{Discovery, DISCOVER_NODES_COUNT},
     handshake::BYPASS_CRYPTOGRAPHY,
     io::*,
@@ -75,6 +76,12 @@ pub const DEFAULT_NODE_TABLE_TIMEOUT: Duration = Duration::from_secs(300);
 pub const DEFAULT_CONNECTION_LIFETIME_FOR_PROMOTION: Duration =
     Duration::from_secs(3 * 24 * 3600);
 const DEFAULT_CHECK_SESSIONS_TIMEOUT: Duration = Duration::from_secs(10);
+// The interval of sending Ping packets to measure the round trip time of a
+// session.
+pub const DEFAULT_SESSION_PING_INTERVAL: Duration = Duration::from_secs(30);
+// The session is regarded as half-open and disconnected if the Ping packet is
+// not answered within this duration.
+pub const DEFAULT_SESSION_PING_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub const MAX_DATAGRAM_SIZE: usize = 1280;
 
@@ -182,8 +189,13 @@ impl NetworkService {
 
     /// Drop a P2P peer from the client
     pub fn drop_peer(&self, node: NodeEntry) -> Result<(), Error> {
+        self.drop_node(node.id)
+    }
+
+    /// Drop a P2P peer from the client by its node id
+    pub fn drop_node(&self, id: NodeId) -> Result<(), Error> {
         if let Some(ref x) = self.inner {
-            x.drop_node(node.id)
+            x.drop_node(id)
         } else {
             Err("Network service not started yet!".into())
         }
@@ -305,6 +317,74 @@ impl NetworkService {
         );
         Some(peer)
     }
+
+    /// Blacklist the specified node and disconnect it if connected. Return
+    /// `false` if the node is neither connected nor known in the node
+    /// database.
+    pub fn ban_node(&self, id: &NodeId) -> Result<bool, Error> {
+        if self
+            .disconnect_node(id, Some(UpdateNodeOperation::Remove))
+            .is_some()
+        {
+            return Ok(true);
+        }
+
+        if let Some(ref inner) = self.inner {
+            let mut node_db = inner.node_db.write();
+            let known = node_db.get_with_trusty(id).is_some();
+            node_db.set_blacklisted(id);
+            Ok(known)
+        } else {
+            Err("Network service not started yet!".into())
+        }
+    }
+
+    /// Refuse new sessions with the specified node or IP address for
+    /// `duration`, or permanently if `duration` is `None`, and disconnect the
+    /// existing ones. The ban is persisted in the node database. Return the
+    /// number of disconnected sessions.
+    pub fn ban(
+        &self, target: BanTarget, reason: String, duration: Option<Duration>,
+    ) -> Result<usize, Error> {
+        if let Some(ref inner) = self.inner {
+            let io =
+                IoContext::new(self.io_service.as_ref().unwrap().channel(), 0);
+            let tokens = match target {
+                BanTarget::NodeId(ref id) => {
+                    inner.sessions.get_index_by_id(id).into_iter().collect()
+                }
+                BanTarget::Ip(ref ip) => inner.sessions.get_indices_by_ip(ip),
+            };
+            inner.node_db.write().ban(target, reason, duration);
+            for token in &tokens {
+                inner.kill_connection(
+                    *token,
+                    &io,
+                    true,
+                    None,
+                    Some(DisconnectReason::DisconnectRequested),
+                );
+            }
+            Ok(tokens.len())
+        } else {
+            Err("Network service not started yet!".into())
+        }
+    }
+
+    /// Lift the ban of the specified node or IP address. Return `false` if it
+    /// is not banned.
+    pub fn unban(&self, target: &BanTarget) -> Result<bool, Error> {
+        if let Some(ref inner) = self.inner {
+            Ok(inner.node_db.write().unban(target).is_some())
+        } else {
+            Err("Network service not started yet!".into())
+        }
+    }
+
+    /// Return the bans not expired yet.
+    pub fn bans(&self) -> Option<Vec<Ban>> {
+        self.inner.as_ref().map(|inner| inner.node_db.read().bans())
+    }
 }
 
 type SharedSession = Arc<RwLock<Session>>;
@@ -712,10 +792,7 @@ impl NetworkServiceInner {
             } else {
                 0
             };
-            samples = self.node_db.read().sample_trusted_node_ids(
-                egress_attempt_count as u32,
-                &self.config.ip_filter,
-            );
+            samples = self.sample_diverse_node_ids(egress_attempt_count);
         }
 
         let reserved_nodes = self.reserved_nodes.read();
@@ -746,6 +823,54 @@ impl NetworkServiceInner {
         );
     }
 
+    /// Sample trusted nodes for outgoing connections. If
+    /// `max_outgoing_peers_per_subnet` is configured, nodes in subnets that
+    /// already reach the quota with existing egress sessions are skipped, so
+    /// that outgoing peers are diverse and harder to eclipse.
+    fn sample_diverse_node_ids(&self, count: usize) -> Vec<NodeId> {
+        let node_db = self.node_db.read();
+        let quota = self.config.max_outgoing_peers_per_subnet;
+        if quota == 0 {
+            return node_db
+                .sample_trusted_node_ids(count as u32, &self.config.ip_filter)
+                .into_iter()
+                .collect();
+        }
+
+        let mut subnet_counts = HashMap::new();
+        for address in self.sessions.egress_addresses() {
+            *subnet_counts
+                .entry(node_db.subnet_of(&address.ip()))
+                .or_insert(0) += 1;
+        }
+
+        // sample more nodes than required, since some of them may be skipped
+        let mut samples = Vec::new();
+        for id in node_db
+            .sample_trusted_node_ids(2 * count as u32, &self.config.ip_filter)
+        {
+            if samples.len() >= count {
+                break;
+            }
+
+            let subnet = match node_db.get(&id, true /* trusted_only */) {
+                Some(node) => node_db.subnet_of(&node.endpoint.address.ip()),
+                None => continue,
+            };
+
+            let subnet_count = subnet_counts.entry(subnet).or_insert(0);
+            if *subnet_count >= quota {
+                trace!("Skip node {:?} since its subnet is full", id);
+                continue;
+            }
+
+            *subnet_count += 1;
+            samples.push(id);
+        }
+
+        samples
+    }
+
     /// Sample archive nodes for outgoing connections if not enough.
     fn sample_archive_nodes(&self) -> HashSet<NodeId> {
         if self.config.max_outgoing_peers_archive == 0 {
@@ -804,6 +929,14 @@ impl NetworkServiceInner {
                 }
             };
 
+            if self.node_db.read().is_banned(id, Some(&address.ip())) {
+                debug!(
+                    "Abort connect. Node banned, id = {:?}, address = {:?}",
+                    id, address
+                );
+                return;
+            }
+
             if !self.sessions.is_ip_allowed(&address.ip()) {
                 debug!("cannot create outgoing connection to node, id = {:?}, address = {:?}", id, address);
                 return;
@@ -1226,9 +1359,20 @@ impl NetworkServiceInner {
         let mut disconnect_peers = Vec::new();
 
         for session in self.sessions.all() {
-            if let Some(sess) = session.try_read() {
-                if let (true, op) = sess.check_timeout() {
+            if let Some(mut sess) = session.try_write() {
+                if let (true, op) = sess.check_timeout(
+                    self.config.session_ping_timeout,
+                    self.config.session_idle_timeout,
+                ) {
                     disconnect_peers.push((sess.token(), op));
+                } else if let Err(e) =
+                    sess.send_ping_if_due(io, self.config.session_ping_interval)
+                {
+                    debug!(
+                        "Error sending Ping, session = {:?}, error = {:?}",
+                        *sess, e
+                    );
+                    disconnect_peers.push((sess.token(), None));
                 }
             }
         }
@@ -1622,6 +1766,17 @@ impl<'a> NetworkContextTrait for NetworkContext<'a> {
         self.network_service.get_peer_node_id(peer)
     }
 
+    fn get_peer_rtt(&self, peer: PeerId) -> Option<Duration> {
+        self.network_service.sessions.get(peer)?.read().rtt()
+    }
+
+    fn is_peer_congested(&self, peer: PeerId) -> bool {
+        self.network_service
+            .sessions
+            .get(peer)
+            .map_or(false, |session| session.read().is_congested())
+    }
+
     fn send(
         &self, peer: PeerId, msg: Vec<u8>, priority: SendQueuePriority,
     ) -> Result<(), Error> {
@@ -1730,6 +1885,17 @@ impl<'a> NetworkContextTrait for NetworkContext<'a> {
             .sessions
             .add_tag(peer, key.into(), value.into());
     }
+
+    fn get_protocol_version(&self, peer: PeerId) -> Option<u8> {
+        let session = self.network_service.sessions.get(peer)?;
+        let session = session.read();
+        session
+            .metadata
+            .capabilities
+            .iter()
+            .find(|cap| cap.protocol == self.protocol)
+            .map(|cap| cap.version)
+    }
 }
 
 fn save_key(path: &Path, key: &Secret) {
@@ -1781,3 +1947,110 @@ fn load_key(path: &Path) -> Option<Secret> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::NetworkService;
+    use crate::{
+        ban_list::BanTarget,
+        node_table::{NodeEndpoint, NodeEntry, NodeId},
+        NetworkConfiguration,
+    };
+    use std::net::IpAddr;
+
+    fn start_network_service() -> NetworkService {
+        let mut config = NetworkConfiguration::new_local();
+        config.config_path = None;
+        config.nat_enabled = false;
+        let mut service = NetworkService::new(config);
+        service.start().unwrap();
+        service
+    }
+
+    fn node_entry(id: NodeId) -> NodeEntry {
+        NodeEntry {
+            id,
+            endpoint: NodeEndpoint {
+                address: "127.0.0.1:1".parse().unwrap(),
+                udp_port: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn test_not_started() {
+        let service = NetworkService::new(NetworkConfiguration::new_local());
+        let id = NodeId::from_low_u64_be(1);
+        assert!(service.add_peer(node_entry(id)).is_err());
+        assert!(service.ban_node(&id).is_err());
+        assert!(service.unban(&BanTarget::NodeId(id)).is_err());
+        assert!(service.bans().is_none());
+    }
+
+    #[test]
+    fn test_trusted_peers() {
+        let service = start_network_service();
+        let id = NodeId::from_low_u64_be(1);
+        assert!(service.get_node(&id).is_none());
+
+        service.add_peer(node_entry(id)).unwrap();
+        assert_eq!(
+            service.get_node(&id).map(|(trusted, _)| trusted),
+            Some(true)
+        );
+
+        service.drop_node(id).unwrap();
+        assert!(service.get_node(&id).is_none());
+    }
+
+    #[test]
+    fn test_ban_node() {
+        let service = start_network_service();
+        let known = NodeId::from_low_u64_be(1);
+        let unknown = NodeId::from_low_u64_be(2);
+        service.add_peer(node_entry(known)).unwrap();
+
+        assert_eq!(service.ban_node(&known).unwrap(), true);
+        assert!(service.get_node(&known).is_none());
+        assert_eq!(service.ban_node(&unknown).unwrap(), false);
+    }
+
+    #[test]
+    fn test_bans() {
+        let service = start_network_service();
+        let id = NodeId::from_low_u64_be(1);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        // No session is disconnected because none is connected.
+        assert_eq!(
+            service
+                .ban(BanTarget::NodeId(id), "test".into(), None)
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            service.ban(BanTarget::Ip(ip), "test".into(), None).unwrap(),
+            0
+        );
+        let mut targets: Vec<BanTarget> = service
+            .bans()
+            .unwrap()
+            .into_iter()
+            .map(|b| b.target)
+            .collect();
+        targets.sort_by_key(|target| format!("{:?}", target));
+        assert_eq!(targets, vec![BanTarget::Ip(ip), BanTarget::NodeId(id)]);
+
+        assert_eq!(service.unban(&BanTarget::Ip(ip)).unwrap(), true);
+        assert_eq!(service.unban(&BanTarget::Ip(ip)).unwrap(), false);
+        assert_eq!(
+            service
+                .bans()
+                .unwrap()
+                .into_iter()
+                .map(|b| b.target)
+                .collect::<Vec<_>>(),
+            vec![BanTarget::NodeId(id)]
+        );
+    }
+}
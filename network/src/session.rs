@@ -50,6 +50,47 @@ pub struct Session {
     // statistics for read/write
     last_read: Instant,
     last_write: (Instant, WriteStatus),
+
+    /// Whether the remote peer answers the Ping packets, which is announced
+    /// in its Hello packet.
+    peer_supports_ping: bool,
+    keepalive: Keepalive,
+}
+
+/// Application level keepalive of a session. The Ping packets are sent
+/// periodically to measure the round trip time, and to detect the half-open
+/// TCP connections, on which the packets are sent successfully but no Pong
+/// is received.
+struct Keepalive {
+    next_nonce: u64,
+    last_ping: Option<Instant>,
+    /// The nonce and the send time of the Ping not answered yet.
+    pending_ping: Option<(u64, Instant)>,
+    /// The smoothed round trip time.
+    rtt: Option<Duration>,
+    /// The last time a protocol packet is sent or received.
+    last_active: Instant,
+}
+
+impl Keepalive {
+    fn new() -> Self {
+        Keepalive {
+            next_nonce: 0,
+            last_ping: None,
+            pending_ping: None,
+            rtt: None,
+            last_active: Instant::now(),
+        }
+    }
+
+    /// Update the smoothed round trip time with a new sample in the same way
+    /// as TCP, i.e. `rtt = 7/8 * rtt + 1/8 * sample`.
+    fn on_rtt_sample(&mut self, sample: Duration) {
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => rtt * 7 / 8 + sample / 8,
+            None => sample,
+        });
+    }
 }
 
 /// Session state.
@@ -79,6 +120,10 @@ pub enum SessionData {
 const PACKET_HELLO: u8 = 0x80;
 // id for Disconnect packet
 const PACKET_DISCONNECT: u8 = 0x01;
+// id for Ping packet
+const PACKET_PING: u8 = 0x02;
+// id for Pong packet
+const PACKET_PONG: u8 = 0x03;
 // id for protocol packet
 pub const PACKET_USER: u8 = 0x10;
 
@@ -93,6 +138,9 @@ impl Session {
         let originated = id.is_some();
 
         let mut handshake = Handshake::new(token, id, socket);
+        handshake
+            .connection
+            .set_send_queue_config(host.config.send_queue_config);
         handshake.start(io, &host.metadata)?;
 
         Ok(Session {
@@ -109,6 +157,8 @@ impl Session {
             expired: None,
             last_read: Instant::now(),
             last_write: (Instant::now(), WriteStatus::Complete),
+            peer_supports_ping: false,
+            keepalive: Keepalive::new(),
         })
     }
 
@@ -179,7 +229,8 @@ impl Session {
     }
 
     /// Complete the handshake process:
-    /// 1. For incoming session, check if the remote peer is blacklisted.
+    /// 1. For incoming session, check if the remote peer is blacklisted or
+    /// banned.
     /// 2. Change the session state to `State::Session`.
     /// 3. Send Hello packet to remote peer.
     fn complete_handshake<Message>(
@@ -195,8 +246,13 @@ impl Session {
         if self.metadata.id.is_none() {
             let id = wrapper.get().id.clone();
 
-            // refuse incoming session if the node is blacklisted
-            if host.node_db.write().evaluate_blacklisted(&id) {
+            // refuse incoming session if the node is blacklisted or banned
+            let refused = {
+                let mut node_db = host.node_db.write();
+                node_db.evaluate_blacklisted(&id)
+                    || node_db.is_banned(&id, None /* ip */)
+            };
+            if refused {
                 return Err(self.send_disconnect(DisconnectReason::Blacklisted));
             }
 
@@ -240,16 +296,18 @@ impl Session {
                 Ok(SessionData::Continue)
             }
             State::Session(ref mut c) => match c.readable()? {
-                Some(data) => Ok(self.read_packet(data, host)?),
+                Some(data) => Ok(self.read_packet(io, data, host)?),
                 None => Ok(SessionData::None),
             },
         }
     }
 
     /// Handle the packet from underlying connection.
-    fn read_packet(
-        &mut self, data: Bytes, host: &NetworkServiceInner,
-    ) -> Result<SessionData, Error> {
+    fn read_packet<Message: Send + Sync + Clone>(
+        &mut self, io: &IoContext<Message>, data: Bytes,
+        host: &NetworkServiceInner,
+    ) -> Result<SessionData, Error>
+    {
         let packet = SessionPacket::parse(data)?;
 
         // For protocol packet, the Hello packet should already been received.
@@ -280,12 +338,39 @@ impl Session {
                 );
                 Err(ErrorKind::Disconnect(reason).into())
             }
-            PACKET_USER => Ok(SessionData::Message {
-                data: packet.data.to_vec(),
-                protocol: packet
-                    .protocol
-                    .expect("protocol should available for USER packet"),
-            }),
+            PACKET_PING => {
+                self.send_packet(
+                    io,
+                    None,
+                    PACKET_PONG,
+                    packet.data.to_vec(),
+                    SendQueuePriority::High,
+                )?;
+                Ok(SessionData::Continue)
+            }
+            PACKET_PONG => {
+                let nonce: u64 = Rlp::new(&packet.data).as_val()?;
+                match self.keepalive.pending_ping {
+                    Some((pending, sent)) if pending == nonce => {
+                        self.keepalive.pending_ping = None;
+                        self.keepalive.on_rtt_sample(sent.elapsed());
+                    }
+                    _ => debug!(
+                        "read packet PONG, unexpected nonce = {}, session = {:?}",
+                        nonce, self
+                    ),
+                }
+                Ok(SessionData::Continue)
+            }
+            PACKET_USER => {
+                self.keepalive.last_active = Instant::now();
+                Ok(SessionData::Message {
+                    data: packet.data.to_vec(),
+                    protocol: packet
+                        .protocol
+                        .expect("protocol should available for USER packet"),
+                })
+            }
             _ => {
                 debug!(
                     "read packet UNKNOWN, packet_id = {:?}, session = {:?}",
@@ -377,6 +462,8 @@ impl Session {
             return Err(self.send_disconnect(DisconnectReason::UselessPeer));
         }
 
+        self.peer_supports_ping = rlp.item_count()? > 3 && rlp.val_at(3)?;
+
         let mut hello_from = NodeEndpoint::from_rlp(&rlp.at(2)?)?;
         // Use the ip of the socket as endpoint ip directly.
         // We do not allow peers to specify the ip to avoid being used to DDoS
@@ -453,9 +540,49 @@ impl Session {
     ) -> Result<SendQueueStatus, Error>
     {
         let packet = self.prepare_packet(protocol, packet_id, data)?;
+        if packet_id == PACKET_USER {
+            self.keepalive.last_active = Instant::now();
+        }
         self.connection_mut().send(io, packet, priority)
     }
 
+    /// The smoothed round trip time measured by the Ping packets.
+    pub fn rtt(&self) -> Option<Duration> { self.keepalive.rtt }
+
+    pub fn is_congested(&self) -> bool { self.connection().is_congested() }
+
+    /// Send a Ping packet if the last one is sent `ping_interval` ago and has
+    /// been answered.
+    pub fn send_ping_if_due<Message: Send + Sync + Clone>(
+        &mut self, io: &IoContext<Message>, ping_interval: Duration,
+    ) -> Result<(), Error> {
+        if !self.peer_supports_ping
+            || !self.is_ready()
+            || self.expired()
+            || self.keepalive.pending_ping.is_some()
+            || self
+                .keepalive
+                .last_ping
+                .map_or(false, |time| time.elapsed() < ping_interval)
+        {
+            return Ok(());
+        }
+
+        let nonce = self.keepalive.next_nonce;
+        self.keepalive.next_nonce += 1;
+        self.send_packet(
+            io,
+            None,
+            PACKET_PING,
+            rlp::encode(&nonce),
+            SendQueuePriority::High,
+        )?;
+        let now = Instant::now();
+        self.keepalive.last_ping = Some(now);
+        self.keepalive.pending_ping = Some((nonce, now));
+        Ok(())
+    }
+
     /// Send a packet to remote peer immediately.
     pub fn send_packet_immediately(
         &mut self, protocol: Option<ProtocolId>, packet_id: u8, data: Vec<u8>,
@@ -476,10 +603,13 @@ impl Session {
         &mut self, io: &IoContext<Message>, host: &NetworkServiceInner,
     ) -> Result<(), Error> {
         debug!("Sending Hello, session = {:?}", self);
-        let mut rlp = RlpStream::new_list(3);
+        let mut rlp = RlpStream::new_list(4);
         rlp.append(&host.metadata.network_id);
         rlp.append_list(&*host.metadata.capabilities.read());
         host.metadata.public_endpoint.to_rlp_list(&mut rlp);
+        // Announce that the Ping packets are answered. The peers of old
+        // versions ignore it, and are never sent Ping packets.
+        rlp.append(&true);
         self.send_packet(
             io,
             None,
@@ -506,6 +636,7 @@ impl Session {
             originated: self.metadata.originated,
             node_id: self.metadata.id,
             address: self.address,
+            capabilities: self.metadata.peer_capabilities.clone(),
             connection: self.connection().details(),
             status: if let Some(time) = self.expired {
                 format!("expired ({:?})", time.elapsed())
@@ -517,6 +648,7 @@ impl Session {
             last_read: format!("{:?}", self.last_read.elapsed()),
             last_write: format!("{:?}", self.last_write.0.elapsed()),
             last_write_status: format!("{:?}", self.last_write.1),
+            rtt: self.rtt().map(|rtt| format!("{:?}", rtt)),
         }
     }
 
@@ -524,11 +656,12 @@ impl Session {
     /// Once a session is timeout during handshake or exchanging Hello packet,
     /// the TCP connection should be disconnected timely.
     ///
-    /// Note, there is no periodical Ping/Pong mechanism to check if the session
-    /// is inactive for a long time. The synchronization protocol handler has
-    /// heartbeat mechanism to exchange peer status. As a result, Inactive
-    /// sessions (e.g. network issue) will be disconnected timely.
-    pub fn check_timeout(&self) -> (bool, Option<UpdateNodeOperation>) {
+    /// After Hello, the session is timeout if the Ping is not answered in
+    /// `ping_timeout`, i.e. the connection is half-open, or if no protocol
+    /// packet is sent or received in `idle_timeout`.
+    pub fn check_timeout(
+        &self, ping_timeout: Duration, idle_timeout: Option<Duration>,
+    ) -> (bool, Option<UpdateNodeOperation>) {
         if let Some(time) = self.expired {
             // should disconnected timely once expired
             if time.elapsed() > Duration::from_secs(5) {
@@ -539,6 +672,19 @@ impl Session {
             if self.sent_hello.elapsed() > Duration::from_secs(300) {
                 return (true, Some(UpdateNodeOperation::Demotion));
             }
+        } else {
+            if let Some((_, sent)) = self.keepalive.pending_ping {
+                if sent.elapsed() > ping_timeout {
+                    debug!("Ping timeout, session = {:?}", self);
+                    return (true, Some(UpdateNodeOperation::Failure));
+                }
+            }
+            if let Some(idle_timeout) = idle_timeout {
+                if self.keepalive.last_active.elapsed() > idle_timeout {
+                    debug!("Session idle timeout, session = {:?}", self);
+                    return (true, None);
+                }
+            }
         }
 
         (false, None)
@@ -559,11 +705,13 @@ pub struct SessionDetails {
     pub originated: bool,
     pub node_id: Option<NodeId>,
     pub address: SocketAddr,
+    pub capabilities: Vec<Capability>,
     pub connection: ConnectionDetails,
     pub status: String,
     pub last_read: String,
     pub last_write: String,
     pub last_write_status: String,
+    pub rtt: Option<String>,
 }
 
 /// MovableWrapper is a util to move a value out of a struct.
@@ -702,6 +850,16 @@ impl fmt::Debug for SessionPacket {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_keepalive_rtt() {
+        let mut keepalive = Keepalive::new();
+        assert_eq!(keepalive.rtt, None);
+        keepalive.on_rtt_sample(Duration::from_millis(800));
+        assert_eq!(keepalive.rtt, Some(Duration::from_millis(800)));
+        keepalive.on_rtt_sample(Duration::from_millis(0));
+        assert_eq!(keepalive.rtt, Some(Duration::from_millis(700)));
+    }
+
     #[test]
     fn test_packet_assemble() {
         let packet = SessionPacket::assemble(5, None, vec![1, 3]);
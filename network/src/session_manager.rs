@@ -42,6 +42,9 @@ pub struct SessionManager {
     node_id_index: RwLock<HashMap<NodeId, usize>>,
     ip_limit: RwLock<Box<dyn SessionIpLimit>>,
     tag_index: RwLock<SessionTagIndex>,
+    // remote addresses of egress sessions, which are tracked here so that
+    // they are available without locking the sessions.
+    egress_addresses: RwLock<HashMap<usize, SocketAddr>>,
 }
 
 impl SessionManager {
@@ -58,6 +61,7 @@ impl SessionManager {
             node_id_index: RwLock::new(HashMap::new()),
             ip_limit: RwLock::new(new_session_ip_limit(ip_limit_config)),
             tag_index: Default::default(),
+            egress_addresses: Default::default(),
         }
     }
 
@@ -114,6 +118,12 @@ impl SessionManager {
         (handshakes, egress, ingress)
     }
 
+    /// Retrieves the remote addresses of all egress sessions, including the
+    /// ones that are still in handshake.
+    pub fn egress_addresses(&self) -> Vec<SocketAddr> {
+        self.egress_addresses.read().values().cloned().collect()
+    }
+
     /// Check the session existence for the specified node id.
     pub fn contains_node(&self, id: &NodeId) -> bool {
         self.node_id_index.read().contains_key(id)
@@ -129,6 +139,16 @@ impl SessionManager {
         self.ip_limit.read().is_allowed(ip)
     }
 
+    /// Get the indices of the sessions with the specified IP address.
+    pub fn get_indices_by_ip(&self, ip: &IpAddr) -> Vec<usize> {
+        self.all()
+            .iter()
+            .map(|s| s.read())
+            .filter(|s| s.address().ip() == *ip)
+            .map(|s| s.token())
+            .collect()
+    }
+
     /// Creates a new session with specified TCP socket. It is egress connection
     /// if the `id` is not `None`, otherwise it is ingress connection.
     pub fn create(
@@ -141,6 +161,16 @@ impl SessionManager {
             address, id
         );
 
+        // validate against banned IP addresses.
+        let ip = address.ip();
+        if host.node_db.read().is_ip_banned(&ip) {
+            debug!("SessionManager.create: leave on IP banned");
+            return Err(format!(
+                "IP banned, nodeId = {:?}, addr = {:?}",
+                id, address
+            ));
+        }
+
         let mut sessions = self.sessions.write();
         let mut node_id_index = self.node_id_index.write();
         let mut ip_limit = self.ip_limit.write();
@@ -169,7 +199,6 @@ impl SessionManager {
         }
 
         // validate against node IP policy.
-        let ip = address.ip();
         if !ip_limit.is_allowed(&ip) {
             debug!("SessionManager.create: leave on IP policy limited");
             return Err(format!(
@@ -200,6 +229,7 @@ impl SessionManager {
         // update on creation succeeded
         if let Some(node_id) = id {
             node_id_index.insert(node_id.clone(), index);
+            self.add_egress_address(index, address);
         }
 
         assert!(ip_limit.add(ip));
@@ -231,6 +261,7 @@ impl SessionManager {
             }
 
             self.tag_index.write().remove(session.token());
+            self.remove_egress_address(session.token());
 
             debug!("SessionManager.remove: session removed");
         }
@@ -238,6 +269,14 @@ impl SessionManager {
         debug!("SessionManager.remove: leave");
     }
 
+    fn add_egress_address(&self, idx: usize, address: SocketAddr) {
+        self.egress_addresses.write().insert(idx, address);
+    }
+
+    fn remove_egress_address(&self, idx: usize) {
+        self.egress_addresses.write().remove(&idx);
+    }
+
     /// Update the node id index for ingress session.
     /// Return error if the session index does not exist, or the node id already
     /// in use by other session.
@@ -351,7 +390,32 @@ impl SessionTagIndex {
 
 #[cfg(test)]
 mod tests {
-    use crate::session_manager::SessionTagIndex;
+    use crate::{
+        ip::SessionIpLimitConfig,
+        session_manager::{SessionManager, SessionTagIndex},
+    };
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_egress_addresses() {
+        let manager =
+            SessionManager::new(0, 10, 5, &SessionIpLimitConfig::default());
+        let a1: SocketAddr = "10.0.0.1:32323".parse().unwrap();
+        let a2: SocketAddr = "10.0.0.2:32323".parse().unwrap();
+        assert!(manager.egress_addresses().is_empty());
+
+        manager.add_egress_address(1, a1);
+        manager.add_egress_address(3, a2);
+        let mut addresses = manager.egress_addresses();
+        addresses.sort();
+        assert_eq!(addresses, vec![a1, a2]);
+
+        // The address of a removed session is no longer counted, and
+        // removing it again takes no effect.
+        manager.remove_egress_address(1);
+        manager.remove_egress_address(1);
+        assert_eq!(manager.egress_addresses(), vec![a2]);
+    }
 
     #[test]
     fn test_tag_index() {
@@ -108,6 +108,38 @@ impl Service {
         );
     }
 
+    /// Change the queue limits at runtime. Unlike `initialize`, invalid limits
+    /// are rejected with an error rather than a panic.
+    pub fn reconfigure(
+        &mut self, cap_mb: usize, min_throttle_mb: usize,
+        max_throttle_mb: usize,
+    ) -> Result<(), String>
+    {
+        if min_throttle_mb == 0
+            || min_throttle_mb >= max_throttle_mb
+            || max_throttle_mb >= cap_mb
+        {
+            return Err(format!(
+                "Expected 0 < min_throttle ({}) < max_throttle ({}) < capacity ({})",
+                min_throttle_mb, max_throttle_mb, cap_mb
+            ));
+        }
+
+        let mb = n_mb_bytes!(1) as usize;
+        if std::usize::MAX / mb < cap_mb {
+            return Err(format!("Capacity {}M is too large", cap_mb));
+        }
+        if self.cur_queue_size > cap_mb * mb {
+            return Err(format!(
+                "Capacity {}M is less than the current queue size {}",
+                cap_mb, self.cur_queue_size
+            ));
+        }
+
+        self.initialize(cap_mb, min_throttle_mb, max_throttle_mb);
+        Ok(())
+    }
+
     /// Mark data enqueued with specified `data_size`, and return the new queue
     /// size. If exceeds the queue capacity, return error with concrete reason.
     pub(crate) fn on_enqueue(
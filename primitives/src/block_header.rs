@@ -537,6 +537,7 @@ mod tests {
             logs: vec![],
             outcome_status: 0.into(),
             log_bloom: Bloom::zero(),
+            ..Receipt::new(0, 0.into(), vec![])
         };
 
         // 10 blocks with 10 empty receipts each
@@ -573,6 +574,7 @@ mod tests {
                      00000000000000000000000000000000",
                 )
                 .unwrap(),
+                ..Receipt::new(0, 0.into(), vec![])
             },
             Receipt {
                 gas_used: 0.into(),
@@ -597,6 +599,7 @@ mod tests {
                      00000000000000000000000000000000",
                 )
                 .unwrap(),
+                ..Receipt::new(0, 0.into(), vec![])
             },
         ];
 
@@ -623,6 +626,7 @@ mod tests {
                  00000000000000000000000000000000",
             )
             .unwrap(),
+            ..Receipt::new(0, 0.into(), vec![])
         }];
 
         let expected = keccak(
@@ -36,6 +36,9 @@ pub enum FilterError {
     /// The block requested does not exist
     UnknownBlock { hash: H256 },
 
+    /// The bodies and receipts of the blocks requested are pruned.
+    BlockBodiesPruned { earliest_block_body_epoch: u64 },
+
     /// Filter error with custom error message (e.g. timeout)
     Custom(String),
 }
@@ -61,6 +64,12 @@ impl fmt::Display for FilterError {
             UnknownBlock { hash } => format! {
                 "Unable to identify block {}", hash
             },
+            BlockBodiesPruned {
+                earliest_block_body_epoch,
+            } => format! {
+                "Block bodies before epoch {} are pruned",
+                earliest_block_body_epoch
+            },
             Custom(ref s) => s.clone(),
         };
 
@@ -18,6 +18,7 @@ pub mod epoch;
 pub mod filter;
 pub mod log_entry;
 pub mod receipt;
+pub mod receipt_proof;
 pub mod state_root;
 pub mod transaction;
 pub mod transaction_address;
@@ -29,6 +30,7 @@ pub use crate::{
     epoch::{EpochId, EpochNumber},
     log_entry::LogEntry,
     receipt::Receipt,
+    receipt_proof::ReceiptProof,
     state_root::*,
     transaction::{
         Action, SignedTransaction, Transaction, TransactionWithSignature,
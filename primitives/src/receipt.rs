@@ -2,8 +2,8 @@
 // Conflux is free software and distributed under GNU General Public License.
 // See http://www.gnu.org/licenses/
 
-use crate::log_entry::LogEntry;
-use cfx_types::{Bloom, U256};
+use crate::{bytes::Bytes, log_entry::LogEntry};
+use cfx_types::{Address, Bloom, U256};
 use malloc_size_of::{MallocSizeOf, MallocSizeOfOps};
 use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
 
@@ -22,6 +22,56 @@ pub struct Receipt {
     pub logs: Vec<LogEntry>,
     /// Transaction outcome.
     pub outcome_status: u8,
+    /// The execution details, which are `None` for the receipts executed
+    /// before the details are stored.
+    pub details: Option<ReceiptDetails>,
+}
+
+/// The details of the execution of a transaction, which are stored locally
+/// but not part of the receipts root.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReceiptDetails {
+    /// The gas of this transaction charged from the sender.
+    pub gas_charged: U256,
+    /// The gas refunded after the execution of this transaction.
+    pub gas_refunded: U256,
+    /// The contract created by this transaction if it succeeds.
+    pub contract_created: Option<Address>,
+    /// The output of the transaction if it is reverted, which is usually
+    /// the ABI-encoded revert reason.
+    pub revert_reason: Bytes,
+}
+
+impl Encodable for ReceiptDetails {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(4);
+        s.append(&self.gas_charged);
+        s.append(&self.gas_refunded);
+        match self.contract_created {
+            Some(ref address) => s.append(address),
+            None => s.append_empty_data(),
+        };
+        s.append(&self.revert_reason);
+    }
+}
+
+impl Decodable for ReceiptDetails {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 4 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+
+        Ok(ReceiptDetails {
+            gas_charged: rlp.val_at(0)?,
+            gas_refunded: rlp.val_at(1)?,
+            contract_created: if rlp.at(2)?.is_empty() {
+                None
+            } else {
+                Some(rlp.val_at(2)?)
+            },
+            revert_reason: rlp.val_at(3)?,
+        })
+    }
 }
 
 impl Receipt {
@@ -34,6 +84,42 @@ impl Receipt {
             }),
             logs,
             outcome_status: outcome,
+            details: None,
+        }
+    }
+
+    /// Appends the receipt with the execution details, which are stored
+    /// locally. The details are not part of the receipts root, so they are
+    /// omitted by the consensus encoding of `Encodable`.
+    pub fn rlp_append_with_details(&self, s: &mut RlpStream) {
+        s.begin_list(5);
+        s.append(&self.gas_used);
+        s.append(&self.outcome_status);
+        s.append(&self.log_bloom);
+        s.append_list(&self.logs);
+        match self.details {
+            Some(ref details) => s.append(details),
+            None => s.append_empty_data(),
+        };
+    }
+
+    /// Decodes the receipt encoded by `rlp_append_with_details`, or by
+    /// `Encodable` in which case the details are `None`.
+    pub fn decode_with_details(rlp: &Rlp) -> Result<Self, DecoderError> {
+        match rlp.item_count()? {
+            4 => Self::decode(rlp),
+            5 => Ok(Receipt {
+                gas_used: rlp.val_at(0)?,
+                outcome_status: rlp.val_at(1)?,
+                log_bloom: rlp.val_at(2)?,
+                logs: rlp.list_at(3)?,
+                details: if rlp.at(4)?.is_empty() {
+                    None
+                } else {
+                    Some(rlp.val_at(4)?)
+                },
+            }),
+            _ => Err(DecoderError::RlpIncorrectListLen),
         }
     }
 }
@@ -59,6 +145,7 @@ impl Decodable for Receipt {
             outcome_status: rlp.val_at(1)?,
             log_bloom: rlp.val_at(2)?,
             logs: rlp.list_at(3)?,
+            details: None,
         })
     }
 }
@@ -66,5 +153,47 @@ impl Decodable for Receipt {
 impl MallocSizeOf for Receipt {
     fn size_of(&self, ops: &mut MallocSizeOfOps) -> usize {
         self.logs.size_of(ops)
+            + self
+                .details
+                .as_ref()
+                .map_or(0, |details| details.revert_reason.size_of(ops))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receipt_with_details_rlp() {
+        let mut receipt = Receipt::new(1, 21000.into(), vec![]);
+        receipt.details = Some(ReceiptDetails {
+            gas_charged: 30000.into(),
+            gas_refunded: 100.into(),
+            contract_created: Some(Address::from_low_u64_be(1)),
+            revert_reason: vec![1, 2, 3],
+        });
+
+        let mut s = RlpStream::new();
+        receipt.rlp_append_with_details(&mut s);
+        let decoded =
+            Receipt::decode_with_details(&Rlp::new(&s.out())).unwrap();
+        assert_eq!(decoded, receipt);
+
+        // The details of zeros are distinguished from the missing details.
+        let mut zero_details = Receipt::new(1, 21000.into(), vec![]);
+        zero_details.details = Some(ReceiptDetails::default());
+        for receipt in &[zero_details, Receipt::new(1, 21000.into(), vec![])] {
+            let mut s = RlpStream::new();
+            receipt.rlp_append_with_details(&mut s);
+            let decoded =
+                Receipt::decode_with_details(&Rlp::new(&s.out())).unwrap();
+            assert_eq!(&decoded, receipt);
+        }
+
+        // The details are not in the consensus encoding.
+        let encoded = rlp::encode(&receipt);
+        let decoded = Receipt::decode_with_details(&Rlp::new(&encoded));
+        assert_eq!(decoded.unwrap(), Receipt::new(1, 21000.into(), vec![]));
     }
 }
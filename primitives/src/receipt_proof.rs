@@ -0,0 +1,215 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::{
+    block_header::{BlockHeader, BlockHeaderBuilder},
+    bytes::Bytes,
+    hash::keccak,
+    receipt::Receipt,
+};
+use cfx_types::H256;
+use rlp::{Rlp, RlpStream};
+use std::sync::Arc;
+
+/// The proof that a receipt is in the receipts of an epoch, which can be
+/// verified against the `deferred_receipts_root` in the header of a trusted
+/// pivot block at least `deferred_state_epoch_count` epochs later, i.e. the
+/// witness.
+///
+/// The receipts root of an epoch is the hash of the receipt lists of all the
+/// blocks in the epoch as a whole, so the proof carries the RLP of each
+/// receipt list of the epoch. The other epochs committed by a witness which
+/// blames its ancestors are only represented by their receipts roots.
+#[derive(Debug, Clone, PartialEq, RlpEncodable, RlpDecodable)]
+pub struct ReceiptProof {
+    /// The RLP-encoded receipt list of each block in the epoch, in the
+    /// execution order.
+    pub block_receipts: Vec<Bytes>,
+    /// The index of the block containing the receipt in the epoch.
+    pub block_index: usize,
+    /// The index of the receipt in the receipts of its block.
+    pub receipt_index: usize,
+    /// The receipts roots committed by the witness, starting from the one of
+    /// the witness itself, followed by the ones of the pivot blocks it
+    /// blames, as in `BlockHeaderBuilder::compute_blame_state_root_vec_root`.
+    pub witness_receipts_roots: Vec<H256>,
+    /// The index of the receipts root of the epoch in
+    /// `witness_receipts_roots`.
+    pub epoch_index: usize,
+}
+
+impl ReceiptProof {
+    /// Returns `None` if there is no receipt at the given position in
+    /// `epoch_receipts`.
+    pub fn new(
+        epoch_receipts: &Vec<Arc<Vec<Receipt>>>, block_index: usize,
+        receipt_index: usize, witness_receipts_roots: Vec<H256>,
+        epoch_index: usize,
+    ) -> Option<Self> {
+        if receipt_index >= epoch_receipts.get(block_index)?.len()
+            || epoch_index >= witness_receipts_roots.len()
+        {
+            return None;
+        }
+        Some(ReceiptProof {
+            block_receipts: epoch_receipts
+                .iter()
+                .map(|receipts| rlp::encode_list(receipts.as_ref()))
+                .collect(),
+            block_index,
+            receipt_index,
+            witness_receipts_roots,
+            epoch_index,
+        })
+    }
+
+    /// The receipts root of the epoch committed by the proof, which is
+    /// computed in the same way as
+    /// `BlockHeaderBuilder::compute_block_receipts_root`.
+    pub fn receipts_root(&self) -> H256 {
+        let mut rlp_stream = RlpStream::new_list(self.block_receipts.len());
+        for receipts in &self.block_receipts {
+            rlp_stream.append_raw(receipts, 1);
+        }
+        keccak(rlp_stream.out())
+    }
+
+    /// Returns true if `receipt` is at the proven position of the epoch
+    /// whose receipts root is committed by `witness`, which must be trusted
+    /// by the caller. The execution details of `receipt` are not verified
+    /// since they are not in the receipts root.
+    pub fn verify(&self, receipt: &Receipt, witness: &BlockHeader) -> bool {
+        let roots = &self.witness_receipts_roots;
+        if roots.len() != witness.blame() as usize + 1 {
+            return false;
+        }
+        let witness_root = match witness.blame() {
+            0 => roots[0],
+            _ => BlockHeaderBuilder::compute_blame_state_root_vec_root(
+                roots.clone(),
+            ),
+        };
+        if witness_root != *witness.deferred_receipts_root() {
+            return false;
+        }
+        let receipts_root = match roots.get(self.epoch_index) {
+            Some(root) => root,
+            None => return false,
+        };
+
+        let receipts = match self.block_receipts.get(self.block_index) {
+            Some(receipts) => Rlp::new(receipts),
+            None => return false,
+        };
+        match receipts.at(self.receipt_index) {
+            Ok(ref proven) if proven.as_raw() == &rlp::encode(receipt)[..] => {}
+            _ => return false,
+        }
+        self.receipts_root() == *receipts_root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReceiptProof;
+    use crate::{
+        block_header::BlockHeaderBuilder,
+        receipt::{Receipt, ReceiptDetails, TRANSACTION_OUTCOME_SUCCESS},
+    };
+    use cfx_types::{H256, U256};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_receipt_proof() {
+        let receipt = |gas_used: u64| {
+            Receipt::new(
+                TRANSACTION_OUTCOME_SUCCESS,
+                U256::from(gas_used),
+                Vec::new(),
+            )
+        };
+        let epoch_receipts = vec![
+            Arc::new(vec![receipt(1), receipt(2)]),
+            Arc::new(vec![]),
+            Arc::new(vec![receipt(3)]),
+        ];
+        let root =
+            BlockHeaderBuilder::compute_block_receipts_root(&epoch_receipts);
+        let witness = BlockHeaderBuilder::new()
+            .with_deferred_receipts_root(root)
+            .build();
+
+        let proof =
+            ReceiptProof::new(&epoch_receipts, 0, 1, vec![root], 0).unwrap();
+        assert_eq!(proof.receipts_root(), root);
+        assert!(proof.verify(&receipt(2), &witness));
+        assert!(!proof.verify(&receipt(1), &witness));
+        let other_witness = BlockHeaderBuilder::new().build();
+        assert!(!proof.verify(&receipt(2), &other_witness));
+        let mut with_details = receipt(2);
+        with_details.details = Some(ReceiptDetails {
+            gas_charged: U256::from(2),
+            ..Default::default()
+        });
+        assert!(proof.verify(&with_details, &witness));
+
+        let proof =
+            ReceiptProof::new(&epoch_receipts, 2, 0, vec![root], 0).unwrap();
+        assert!(proof.verify(&receipt(3), &witness));
+
+        assert!(
+            ReceiptProof::new(&epoch_receipts, 1, 0, vec![root], 0).is_none()
+        );
+        assert!(
+            ReceiptProof::new(&epoch_receipts, 3, 0, vec![root], 0).is_none()
+        );
+        assert!(ReceiptProof::new(&epoch_receipts, 0, 0, vec![], 0).is_none());
+    }
+
+    #[test]
+    fn test_receipt_proof_with_blaming_witness() {
+        let epoch_receipts = vec![Arc::new(vec![Receipt::new(
+            TRANSACTION_OUTCOME_SUCCESS,
+            U256::from(1),
+            Vec::new(),
+        )])];
+        let root =
+            BlockHeaderBuilder::compute_block_receipts_root(&epoch_receipts);
+        // The witness blames two pivot blocks, and the epoch is committed by
+        // the second one.
+        let roots =
+            vec![H256::from_low_u64_be(1), root, H256::from_low_u64_be(2)];
+        let witness = BlockHeaderBuilder::new()
+            .with_blame(2)
+            .with_deferred_receipts_root(
+                BlockHeaderBuilder::compute_blame_state_root_vec_root(
+                    roots.clone(),
+                ),
+            )
+            .build();
+        let receipt = epoch_receipts[0][0].clone();
+
+        let proof =
+            ReceiptProof::new(&epoch_receipts, 0, 0, roots.clone(), 1).unwrap();
+        assert!(proof.verify(&receipt, &witness));
+
+        // The epoch is at another position of the roots.
+        let proof =
+            ReceiptProof::new(&epoch_receipts, 0, 0, roots.clone(), 0).unwrap();
+        assert!(!proof.verify(&receipt, &witness));
+
+        // The roots are not the ones committed by the witness.
+        let mut wrong_roots = roots.clone();
+        wrong_roots[2] = H256::from_low_u64_be(3);
+        let proof =
+            ReceiptProof::new(&epoch_receipts, 0, 0, wrong_roots, 1).unwrap();
+        assert!(!proof.verify(&receipt, &witness));
+
+        // The number of roots does not match the blame of the witness.
+        let proof =
+            ReceiptProof::new(&epoch_receipts, 0, 0, roots[..2].to_vec(), 1)
+                .unwrap();
+        assert!(!proof.verify(&receipt, &witness));
+    }
+}
@@ -15,6 +15,9 @@ use unexpected::OutOfBounds;
 /// Fake address for unsigned transactions.
 pub const UNSIGNED_SENDER: Address = H160([0xff; 20]);
 
+/// The largest chain id whose signature `v` fits in u64.
+pub const MAX_CHAIN_ID: u64 = (::std::u64::MAX - 36) / 2;
+
 /// Shorter id for transactions in compact blocks
 // TODO should be u48
 pub type TxShortId = u64;
@@ -71,6 +74,14 @@ pub enum TransactionError {
     TooBig,
     /// Invalid RLP encoding
     InvalidRlp(String),
+    /// Transaction's chain id doesn't match the chain id of the network, or
+    /// is missing where it's required.
+    InvalidChainId {
+        /// Chain id of the network
+        expected: u64,
+        /// Transaction chain id
+        got: Option<u64>,
+    },
 }
 
 impl From<keylib::Error> for TransactionError {
@@ -115,6 +126,10 @@ impl fmt::Display for TransactionError {
             InvalidRlp(ref err) => {
                 format!("Transaction has invalid RLP structure: {}.", err)
             }
+            InvalidChainId { expected, got } => format!(
+                "Invalid chain id. Expected={}, Given={:?}",
+                expected, got
+            ),
         };
 
         f.write_fmt(format_args!("Transaction error ({})", msg))
@@ -180,23 +195,57 @@ impl Transaction {
         keccak(s.as_raw())
     }
 
+    /// The hash signed by the sender. If `chain_id` is given, the hash commits
+    /// to it as in EIP-155, so that the signature can't be replayed on other
+    /// chains.
+    pub fn signature_hash(&self, chain_id: Option<u64>) -> H256 {
+        let chain_id = match chain_id {
+            Some(chain_id) => chain_id,
+            None => return self.hash(),
+        };
+        let mut s = RlpStream::new();
+        s.begin_list(9);
+        s.append(&self.nonce);
+        s.append(&self.gas_price);
+        s.append(&self.gas);
+        s.append(&self.action);
+        s.append(&self.value);
+        s.append(&self.data);
+        s.append(&chain_id);
+        s.append(&0u8);
+        s.append(&0u8);
+        keccak(s.as_raw())
+    }
+
     pub fn sign(self, secret: &Secret) -> SignedTransaction {
-        let sig = ::keylib::sign(secret, &self.hash())
+        self.sign_with_chain_id(secret, None)
+    }
+
+    /// Signs the transaction, committing to `chain_id` if it's given.
+    pub fn sign_with_chain_id(
+        self, secret: &Secret, chain_id: Option<u64>,
+    ) -> SignedTransaction {
+        let sig = ::keylib::sign(secret, &self.signature_hash(chain_id))
             .expect("data is valid and context has signing capabilities; qed");
-        let tx_with_sig = self.with_signature(sig);
+        let tx_with_sig = self.with_signature(sig, chain_id);
         let public = tx_with_sig
             .recover_public()
             .expect("secret is valid so it's recoverable");
         SignedTransaction::new(public, tx_with_sig)
     }
 
-    /// Signs the transaction with signature.
-    pub fn with_signature(self, sig: Signature) -> TransactionWithSignature {
+    /// Signs the transaction with signature, which is made for `chain_id`.
+    pub fn with_signature(
+        self, sig: Signature, chain_id: Option<u64>,
+    ) -> TransactionWithSignature {
         TransactionWithSignature {
             unsigned: self,
             r: sig.r().into(),
             s: sig.s().into(),
-            v: sig.v(),
+            v: chain_id
+                .map_or(Some(0), |id| id.checked_mul(2)?.checked_add(35))
+                .and_then(|offset| offset.checked_add(sig.v() as u64))
+                .expect("chain id is at most MAX_CHAIN_ID; qed"),
             hash: H256::zero(),
             rlp_size: None,
         }
@@ -241,8 +290,9 @@ pub struct TransactionWithSignature {
     /// Plain Transaction.
     pub unsigned: Transaction,
     /// The V field of the signature; helps describe which half of the curve
-    /// our point falls in.
-    pub v: u8,
+    /// our point falls in. It's `chain_id * 2 + 35` plus the recovery id if
+    /// the signature is made for a chain id, or the recovery id otherwise.
+    pub v: u64,
     /// The R field of the signature; helps describe the point on the curve.
     pub r: U256,
     /// The S field of the signature; helps describe the point on the curve.
@@ -313,6 +363,25 @@ impl TransactionWithSignature {
     /// Checks whether signature is empty.
     pub fn is_unsigned(&self) -> bool { self.r.is_zero() && self.s.is_zero() }
 
+    /// The chain id the signature is made for, or `None` if the transaction
+    /// is signed without a chain id.
+    pub fn chain_id(&self) -> Option<u64> {
+        if self.v >= 35 {
+            Some((self.v - 35) / 2)
+        } else {
+            None
+        }
+    }
+
+    /// The recovery id of the signature.
+    pub fn standard_v(&self) -> u8 {
+        if self.v >= 35 {
+            ((self.v - 35) % 2) as u8
+        } else {
+            self.v as u8
+        }
+    }
+
     /// Append object with a signature into RLP stream
     fn rlp_append_sealed_transaction(&self, s: &mut RlpStream) {
         s.begin_list(9);
@@ -331,7 +400,7 @@ impl TransactionWithSignature {
     pub fn signature(&self) -> Signature {
         let r: H256 = BigEndianHash::from_uint(&self.r);
         let s: H256 = BigEndianHash::from_uint(&self.s);
-        Signature::from_rsv(&r, &s, self.v)
+        Signature::from_rsv(&r, &s, self.standard_v())
     }
 
     /// Checks whether the signature has a low 's' value.
@@ -347,7 +416,10 @@ impl TransactionWithSignature {
 
     /// Recovers the public key of the sender.
     pub fn recover_public(&self) -> Result<Public, keylib::Error> {
-        Ok(recover(&self.signature(), &self.unsigned.hash())?)
+        Ok(recover(
+            &self.signature(),
+            &self.unsigned.signature_hash(self.chain_id()),
+        )?)
     }
 
     /// Verify basic signature params. Does not attempt sender recovery.
@@ -359,12 +431,29 @@ impl TransactionWithSignature {
             return Err(keylib::Error::InvalidSignature.into());
         }
 
+        // `v` is either the recovery id or `chain_id * 2 + 35` plus it.
+        if self.v > 1 && self.v < 35 {
+            return Err(TransactionError::InvalidSignature(format!(
+                "invalid v {}",
+                self.v
+            )));
+        }
+
         Ok(())
     }
 
     pub fn rlp_size(&self) -> usize {
         self.rlp_size.unwrap_or_else(|| self.rlp_bytes().len())
     }
+
+    /// Decode a raw transaction, which is the RLP encoding accepted by
+    /// `cfx_sendRawTransaction`. The signature isn't checked.
+    pub fn from_raw(raw: &[u8]) -> Result<Self, TransactionError> {
+        Ok(rlp::decode(raw)?)
+    }
+
+    /// Encode the transaction as a raw transaction.
+    pub fn to_raw(&self) -> Vec<u8> { self.rlp_bytes() }
 }
 
 impl MallocSizeOf for TransactionWithSignature {
@@ -429,6 +518,14 @@ impl SignedTransaction {
         }
     }
 
+    /// Decode a raw transaction, check its signature and recover the sender.
+    pub fn from_raw(raw: &[u8]) -> Result<Self, TransactionError> {
+        let transaction = TransactionWithSignature::from_raw(raw)?;
+        transaction.verify_basic()?;
+        let public = transaction.recover_public()?;
+        Ok(SignedTransaction::new(public, transaction))
+    }
+
     pub fn new_unsigned(transaction: TransactionWithSignature) -> Self {
         SignedTransaction {
             transaction,
@@ -478,7 +575,7 @@ impl SignedTransaction {
             Ok(verify_public(
                 &public,
                 &self.signature(),
-                &self.unsigned.hash(),
+                &self.unsigned.signature_hash(self.chain_id()),
             )?)
         } else {
             Ok(true)
@@ -491,3 +588,107 @@ impl MallocSizeOf for SignedTransaction {
         self.transaction.size_of(ops)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Action, SignedTransaction, Transaction, TransactionError,
+        TransactionWithSignature, MAX_CHAIN_ID,
+    };
+    use cfx_types::Address;
+    use keylib::{public_to_address, Generator, Random};
+
+    fn transaction() -> Transaction {
+        Transaction {
+            nonce: 1.into(),
+            gas_price: 1.into(),
+            gas: 21000.into(),
+            action: Action::Call(Address::from_low_u64_be(1)),
+            value: 1.into(),
+            data: vec![],
+        }
+    }
+
+    #[test]
+    fn test_sign_with_chain_id() {
+        let key_pair = Random.generate().unwrap();
+        let sender = public_to_address(key_pair.public());
+
+        let legacy = transaction().sign(key_pair.secret());
+        assert_eq!(legacy.chain_id(), None);
+        assert_eq!(legacy.recover_public().unwrap(), *key_pair.public());
+
+        let tx = transaction().sign_with_chain_id(key_pair.secret(), Some(7));
+        assert_eq!(tx.chain_id(), Some(7));
+        assert_eq!(tx.sender(), sender);
+        assert_eq!(tx.recover_public().unwrap(), *key_pair.public());
+        assert!(tx.verify_public(false).unwrap());
+
+        // The signature doesn't recover the sender on other chains.
+        let mut replayed = tx.transaction.clone();
+        replayed.v += 2;
+        assert_eq!(replayed.chain_id(), Some(8));
+        assert_ne!(replayed.recover_public().ok(), Some(*key_pair.public()));
+    }
+
+    #[test]
+    fn test_verify_basic_v() {
+        let key_pair = Random.generate().unwrap();
+        let legacy = transaction().sign(key_pair.secret()).transaction;
+        assert!(legacy.verify_basic().is_ok());
+        let tx = transaction()
+            .sign_with_chain_id(key_pair.secret(), Some(0))
+            .transaction;
+        assert!(tx.verify_basic().is_ok());
+
+        for v in 2..35 {
+            let mut invalid = tx.clone();
+            invalid.v = v;
+            match invalid.verify_basic() {
+                Err(TransactionError::InvalidSignature(_)) => {}
+                other => panic!("unexpected result {:?} for v {}", other, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_with_signature_max_chain_id() {
+        let key_pair = Random.generate().unwrap();
+        let tx = transaction()
+            .sign_with_chain_id(key_pair.secret(), Some(MAX_CHAIN_ID));
+        assert_eq!(tx.chain_id(), Some(MAX_CHAIN_ID));
+        assert_eq!(tx.recover_public().unwrap(), *key_pair.public());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_with_signature_chain_id_overflow() {
+        let key_pair = Random.generate().unwrap();
+        // `chain_id * 2 + 35` overflows whatever the recovery id is.
+        transaction()
+            .sign_with_chain_id(key_pair.secret(), Some(::std::u64::MAX / 2));
+    }
+
+    #[test]
+    fn test_raw_transaction() {
+        let key_pair = Random.generate().unwrap();
+        let tx = transaction().sign_with_chain_id(key_pair.secret(), Some(7));
+
+        let raw = tx.transaction.to_raw();
+        let decoded = SignedTransaction::from_raw(&raw).unwrap();
+        assert_eq!(decoded.unsigned, tx.unsigned);
+        assert_eq!(decoded.sender(), tx.sender());
+        assert_eq!(decoded.hash(), tx.hash());
+        assert_eq!(decoded.rlp_size(), raw.len());
+
+        let unsigned =
+            TransactionWithSignature::new_unsigned(transaction()).to_raw();
+        assert!(TransactionWithSignature::from_raw(&unsigned).is_ok());
+        assert!(SignedTransaction::from_raw(&unsigned).is_err());
+
+        match SignedTransaction::from_raw(&raw[1..]) {
+            Err(TransactionError::InvalidRlp(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
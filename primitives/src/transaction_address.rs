@@ -4,19 +4,70 @@
 
 use cfx_types::H256;
 use malloc_size_of::{MallocSizeOf, MallocSizeOfOps};
-use rlp_derive::{RlpDecodable, RlpEncodable};
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
 
 /// Represents address of certain transaction within block
-#[derive(
-    Debug, PartialEq, Eq, Hash, Clone, RlpEncodable, RlpDecodable, Default,
-)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
 pub struct TransactionAddress {
     /// Block hash
     pub block_hash: H256,
     /// Transaction index within the block
     pub index: usize,
+    /// Pivot block hash of the epoch in which the transaction is executed.
+    /// It is zero for the addresses persisted without the epoch.
+    pub epoch_hash: H256,
+}
+
+impl Encodable for TransactionAddress {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(3);
+        s.append(&self.block_hash);
+        s.append(&self.index);
+        s.append(&self.epoch_hash);
+    }
+}
+
+impl Decodable for TransactionAddress {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        let epoch_hash = match rlp.item_count()? {
+            2 => H256::zero(),
+            3 => rlp.val_at(2)?,
+            _ => return Err(DecoderError::RlpIncorrectListLen),
+        };
+
+        Ok(TransactionAddress {
+            block_hash: rlp.val_at(0)?,
+            index: rlp.val_at(1)?,
+            epoch_hash,
+        })
+    }
 }
 
 impl MallocSizeOf for TransactionAddress {
     fn size_of(&self, _ops: &mut MallocSizeOfOps) -> usize { 0 }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::TransactionAddress;
+    use cfx_types::H256;
+    use rlp::RlpStream;
+
+    #[test]
+    fn test_decode_address_without_epoch() {
+        let block_hash = H256::from_low_u64_be(1);
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&block_hash).append(&3usize);
+
+        let address: TransactionAddress = rlp::decode(&stream.out()).unwrap();
+        assert_eq!(address.block_hash, block_hash);
+        assert_eq!(address.index, 3);
+        assert_eq!(address.epoch_hash, H256::zero());
+
+        let address = TransactionAddress {
+            epoch_hash: H256::from_low_u64_be(2),
+            ..address
+        };
+        assert_eq!(rlp::decode(&rlp::encode(&address)), Ok(address));
+    }
+}
@@ -5,13 +5,9 @@
 use clap::{load_yaml, App};
 use client::{
     archive::ArchiveClient, configuration::Configuration, light::LightClient,
+    query::QueryClient,
 };
-use log::{info, LevelFilter};
-use log4rs::{
-    append::{console::ConsoleAppender, file::FileAppender},
-    config::{Appender, Config as LogConfig, Logger, Root},
-    encode::pattern::PatternEncoder,
-};
+use log::info;
 use network::throttling::THROTTLING_SERVICE;
 use parking_lot::{Condvar, Mutex};
 use std::sync::Arc;
@@ -47,60 +43,7 @@ fn main() -> Result<(), String> {
 
     let conf = Configuration::parse(&matches)?;
 
-    // If log_conf is provided, use it for log configuration and ignore
-    // log_file and log_level. Otherwise, set stdout to INFO and set
-    // all our crate log to log_level.
-    let log_config = match conf.raw_conf.log_conf {
-        Some(ref log_conf) => {
-            log4rs::load_config_file(log_conf, Default::default()).map_err(
-                |e| format!("failed to load log configuration file: {:?}", e),
-            )?
-        }
-        None => {
-            let mut conf_builder =
-                LogConfig::builder().appender(Appender::builder().build(
-                    "stdout",
-                    Box::new(ConsoleAppender::builder().build()),
-                ));
-            let mut root_builder = Root::builder().appender("stdout");
-            if let Some(ref log_file) = conf.raw_conf.log_file {
-                conf_builder =
-                    conf_builder.appender(Appender::builder().build(
-                        "logfile",
-                        Box::new(
-                            FileAppender::builder().encoder(Box::new(PatternEncoder::new("{d} {h({l}):5.5} {T:<20.20} {t:12.12} - {m}{n}"))).build(log_file).map_err(|e|format!("failed to build log pattern: {:?}", e))?,
-                        ),
-                    ));
-                root_builder = root_builder.appender("logfile");
-            };
-            // Should add new crate names here
-            for crate_name in [
-                "blockgen",
-                "cfxcore",
-                "conflux",
-                "db",
-                "keymgr",
-                "network",
-                "txgen",
-                "client",
-                "primitives",
-            ]
-            .iter()
-            {
-                conf_builder = conf_builder.logger(
-                    Logger::builder()
-                        .build(*crate_name, conf.raw_conf.log_level),
-                );
-            }
-            conf_builder
-                .build(root_builder.build(LevelFilter::Info))
-                .map_err(|e| format!("failed to build log config: {:?}", e))?
-        }
-    };
-
-    log4rs::init_config(log_config).map_err(|e| {
-        format!("failed to initialize log with config: {:?}", e)
-    })?;
+    client::log_control::init(&conf)?;
 
     match matches.subcommand() {
         ("account", Some(account_matches)) => {
@@ -114,6 +57,73 @@ fn main() -> Result<(), String> {
                 _ => {}
             }
         }
+        ("chain", Some(chain_matches)) => match chain_matches.subcommand() {
+            ("export", Some(export_matches)) => {
+                client::chain::export(
+                    conf,
+                    export_matches.value_of("file").unwrap(),
+                )?;
+            }
+            ("import", Some(import_matches)) => {
+                client::chain::import(
+                    conf,
+                    import_matches.value_of("file").unwrap(),
+                )?;
+            }
+            _ => {}
+        },
+        ("replay", Some(replay_matches)) => {
+            let parse_epoch = |name: &str| {
+                replay_matches
+                    .value_of(name)
+                    .unwrap()
+                    .parse::<u64>()
+                    .map_err(|e| format!("invalid {}: {:?}", name, e))
+            };
+            client::replay::run(
+                conf,
+                parse_epoch("from")?,
+                parse_epoch("to")?,
+            )?;
+        }
+        ("audit-blame", Some(audit_matches)) => {
+            let parse_epoch = |name: &str| {
+                audit_matches
+                    .value_of(name)
+                    .unwrap()
+                    .parse::<u64>()
+                    .map_err(|e| format!("invalid {}: {:?}", name, e))
+            };
+            client::blame_audit::run(
+                conf,
+                parse_epoch("from")?,
+                parse_epoch("to")?,
+                audit_matches.value_of("output"),
+            )?;
+        }
+        ("db", Some(db_matches)) => match db_matches.subcommand() {
+            ("reindex", Some(reindex_matches)) => {
+                let from_epoch = match reindex_matches.value_of("from-epoch") {
+                    Some(epoch) => {
+                        Some(epoch.parse::<u64>().map_err(|e| {
+                            format!("invalid from-epoch: {:?}", e)
+                        })?)
+                    }
+                    None => None,
+                };
+                client::reindex::run(conf, from_epoch)?;
+            }
+            _ => {}
+        },
+        ("query", Some(_)) => {
+            let exit = Arc::new((Mutex::new(false), Condvar::new()));
+
+            info!("Starting query client...");
+            let client_handle = QueryClient::start(conf).map_err(|e| {
+                format!("failed to start query client: {:?}", e)
+            })?;
+            QueryClient::run_until_closed(exit, client_handle);
+        }
         _ => {
             THROTTLING_SERVICE.write().initialize(
                 conf.raw_conf.egress_queue_capacity,
@@ -124,6 +124,7 @@ class Status(rlp.Serializable):
     fields = [
         ("protocol_version", big_endian_int),
         ("genesis_hash", hash32),
+        ("chain_id", big_endian_int),
         ("best_epoch", big_endian_int),
         ("terminal_block_hashes", CountableList(hash32)),
     ]
@@ -253,6 +253,9 @@ class RpcClient:
         status = self.node.txpool_status()
         return (status["deferred"], status["ready"])
 
+    def state_snapshot(self) -> dict:
+        return self.node.debug_stateSnapshot()
+
     def new_tx_for_call(self, contract_addr:str, data_hex:str, nonce:int=None):
         if nonce is None:
             nonce = self.get_nonce(self.GENESIS_ADDR)
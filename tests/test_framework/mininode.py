@@ -300,7 +300,8 @@ class P2PInterface(P2PConnection):
 
         # Default protocol version
         self.protocol = b'cfx'
-        self.protocol_version = 1
+        self.protocol_version = 2
+        self.chain_id = 1
         self.genesis = make_genesis()
         self.best_block_hash = self.genesis.block_header.hash
         self.blocks = {self.genesis.block_header.hash: self.genesis}
@@ -327,7 +328,7 @@ class P2PInterface(P2PConnection):
     # Message receiving methods
 
     def send_status(self):
-        status = Status(self.protocol_version, self.genesis.block_header.hash, 0, [self.best_block_hash])
+        status = Status(self.protocol_version, self.genesis.block_header.hash, self.chain_id, 0, [self.best_block_hash])
         self.send_protocol_msg(status)
 
     def on_protocol_packet(self, protocol, payload):
@@ -471,7 +472,7 @@ class DefaultNode(P2PInterface):
     def __init__(self, remote = False):
         super().__init__(remote)
         self.protocol = b'cfx'
-        self.protocol_version = 1
+        self.protocol_version = 2
 
 class NetworkThread(threading.Thread):
 
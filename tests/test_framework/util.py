@@ -272,6 +272,7 @@ def initialize_datadir(dirname, n, conf_parameters):
                         "storage_node_map_size": "200000",
                         "start_mining":"false",
                         "subnet_quota": "0",
+                        "max_outgoing_peers_per_subnet": "0",
                         "session_ip_limits": "\"0,0,0,0\"",
                         "enable_discovery": "false",
                         "metrics_output_file": "\'{}\'".format(os.path.join(datadir, "metrics.log")),
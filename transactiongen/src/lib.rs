@@ -43,6 +43,9 @@ use std::{
 use time::Duration;
 
 pub mod propagate;
+mod workload;
+
+pub use workload::{ArrivalPattern, WorkloadConfig};
 
 lazy_static! {
     static ref TX_GEN_METER: Arc<dyn Meter> =
@@ -58,16 +61,21 @@ pub struct TransactionGeneratorConfig {
     pub generate_tx: bool,
     pub period: time::Duration,
     pub account_count: usize,
+    /// Send the transactions of the workload instead of random transfers.
+    pub workload: Option<WorkloadConfig>,
 }
 
 impl TransactionGeneratorConfig {
     pub fn new(
         generate_tx: bool, period_ms: u64, account_count: usize,
-    ) -> Self {
+        workload: Option<WorkloadConfig>,
+    ) -> Self
+    {
         TransactionGeneratorConfig {
             generate_tx,
             period: time::Duration::from_micros(period_ms),
             account_count,
+            workload,
         }
     }
 }
@@ -0,0 +1,429 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use super::{TransGenState, TransactionGenerator, TX_GEN_METER};
+use crate::bytes::Bytes;
+use cfx_types::{Address, BigEndianHash, H256, U256};
+use cfxcore::{executive::contract_address, vm::CreateContractAddress};
+use hex::FromHex;
+use keccak_hash::keccak;
+use keylib::{Generator, KeyPair, Random, Secret};
+use network::Error;
+use primitives::{transaction::Action, Transaction};
+use rand::prelude::*;
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The FixedSupplyToken contract in `tests/contracts/erc20.sol`, whose total
+/// supply belongs to the creator.
+const ERC20_BYTECODE: &str =
+    include_str!("../../tests/contracts/erc20_bytecode.dat");
+/// A contract writing the block number into `count` storage slots starting
+/// from `start`, where `start` and `count` are the two words of the call data.
+const STORAGE_WRITER_BYTECODE: &str = "601d80600b6000396000f3\
+                                       60003560203581015b80821015601b57\
+                                       4382559060010190600856\
+                                       5b00";
+/// The selector of `transfer(address,uint256)`.
+const ERC20_TRANSFER_SELECTOR: &str = "a9059cbb";
+
+const GAS_PRICE: u64 = 1;
+const TRANSFER_GAS: u64 = 21_000;
+const CONTRACT_CREATE_GAS: u64 = 3_000_000;
+const ERC20_TRANSFER_GAS: u64 = 100_000;
+const STORAGE_WRITE_BASE_GAS: u64 = 50_000;
+const STORAGE_WRITE_GAS_PER_SLOT: u64 = 25_000;
+
+/// The kinds of transactions sent by a workload.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxKind {
+    /// A transfer of 0 to another sender.
+    Transfer,
+    /// A token transfer of the ERC20 contract to another sender.
+    Erc20Transfer,
+    /// A call of the storage writer contract.
+    StorageWrite,
+}
+
+/// How the transactions of a workload arrive at the transaction pool.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArrivalPattern {
+    /// A transaction every period.
+    Constant,
+    /// `burst_size` transactions at once every `burst_size` periods.
+    Bursty { burst_size: u32 },
+}
+
+#[derive(Clone, Debug)]
+pub struct WorkloadConfig {
+    /// The relative weights of the kinds of transactions sent.
+    pub transfer_weight: u32,
+    pub erc20_weight: u32,
+    pub storage_weight: u32,
+    /// The number of storage slots written by a storage write.
+    pub storage_slots: usize,
+    /// The number of accounts sending the transactions, which are funded by
+    /// the node key account.
+    pub sender_count: usize,
+    /// Derive the keys of the senders from the seed so that different runs
+    /// use the same accounts. Random keys are used if not given.
+    pub key_seed: Option<u64>,
+    pub arrival: ArrivalPattern,
+}
+
+impl WorkloadConfig {
+    /// The keys of the senders, which are derived from `key_seed` if given.
+    fn sender_keys(&self) -> Vec<KeyPair> {
+        (0..self.sender_count as u64)
+            .map(|index| match self.key_seed {
+                Some(seed) => seeded_key_pair(seed, index),
+                None => Random.generate().expect("Fail to generate KeyPair."),
+            })
+            .collect()
+    }
+
+    fn choose_kind<R: Rng>(&self, rng: &mut R) -> TxKind {
+        let total =
+            self.transfer_weight + self.erc20_weight + self.storage_weight;
+        if total == 0 {
+            return TxKind::Transfer;
+        }
+        let r = rng.gen_range(0, total);
+        if r < self.transfer_weight {
+            TxKind::Transfer
+        } else if r < self.transfer_weight + self.erc20_weight {
+            TxKind::Erc20Transfer
+        } else {
+            TxKind::StorageWrite
+        }
+    }
+}
+
+/// Derive the key of the `index`-th account from `seed`.
+fn seeded_key_pair(seed: u64, index: u64) -> KeyPair {
+    let mut material =
+        [&seed.to_be_bytes()[..], &index.to_be_bytes()[..]].concat();
+    loop {
+        let hash = keccak(&material);
+        // A hash out of the range of valid secrets is hashed again.
+        if let Ok(key_pair) = KeyPair::from_secret(Secret::from(hash)) {
+            return key_pair;
+        }
+        material = hash.as_ref().to_vec();
+    }
+}
+
+/// Paces the transactions of a workload at the configured period and
+/// arrival pattern, catching up if sending falls behind.
+pub struct RateController {
+    period: Duration,
+    arrival: ArrivalPattern,
+    start: Instant,
+    sent: u32,
+}
+
+impl RateController {
+    pub fn new(period: Duration, arrival: ArrivalPattern) -> Self {
+        RateController {
+            period,
+            arrival,
+            start: Instant::now(),
+            sent: 0,
+        }
+    }
+
+    /// The time since start at which the `n`-th transaction is due.
+    fn due(&self, n: u32) -> Duration {
+        match self.arrival {
+            ArrivalPattern::Constant => self.period * n,
+            ArrivalPattern::Bursty { burst_size } => {
+                let burst_size = std::cmp::max(burst_size, 1);
+                self.period * (n / burst_size * burst_size)
+            }
+        }
+    }
+
+    /// Count a sent transaction and wait until the next one is due.
+    pub fn sent_and_wait(&mut self) {
+        self.sent += 1;
+        let due = self.due(self.sent);
+        if let Some(time_left) = due.checked_sub(self.start.elapsed()) {
+            thread::sleep(time_left);
+        }
+    }
+}
+
+/// The local view of an account sending transactions.
+struct Sender {
+    key_pair: KeyPair,
+    nonce: U256,
+}
+
+impl Sender {
+    fn address(&self) -> Address { self.key_pair.address() }
+}
+
+fn erc20_transfer_data(receiver: &Address, amount: U256) -> Bytes {
+    let amount: H256 = BigEndianHash::from_uint(&amount);
+    let mut data = Vec::from_hex(ERC20_TRANSFER_SELECTOR).unwrap();
+    data.extend_from_slice(&[0u8; 12]);
+    data.extend_from_slice(receiver.as_ref());
+    data.extend_from_slice(amount.as_ref());
+    data
+}
+
+fn storage_write_data(start: U256, count: usize) -> Bytes {
+    let start: H256 = BigEndianHash::from_uint(&start);
+    let count: H256 = BigEndianHash::from_uint(&U256::from(count));
+    [start.as_ref(), count.as_ref()].concat()
+}
+
+impl TransactionGenerator {
+    /// Sign the transaction with the sender's key and insert it into the
+    /// transaction pool. The sender's nonce is increased if it's accepted.
+    fn send_workload_transaction(
+        &self, sender: &mut Sender, gas: u64, action: Action, value: U256,
+        data: Bytes,
+    ) -> bool
+    {
+        let tx = Transaction {
+            nonce: sender.nonce,
+            gas_price: GAS_PRICE.into(),
+            gas: gas.into(),
+            value,
+            action,
+            data,
+        };
+        let signed_tx = tx.sign(sender.key_pair.secret());
+        let (txs, fail) = self
+            .txpool
+            .insert_new_transactions(vec![signed_tx.transaction]);
+        if !fail.is_empty() {
+            trace!("workload transaction rejected: {:?}", fail);
+            return false;
+        }
+        self.sync.append_received_transactions(txs);
+        sender.nonce += U256::one();
+        TX_GEN_METER.mark(1);
+        true
+    }
+
+    /// Send the transactions of a workload described by `workload`: the
+    /// senders are funded and the contracts are deployed by the node key
+    /// account, and then the transactions are sent at the configured rate.
+    pub fn generate_workload(
+        txgen: Arc<TransactionGenerator>, period: Duration,
+        workload: WorkloadConfig,
+    ) -> Result<(), Error>
+    {
+        let initial_key_pair = txgen.key_pair.clone().expect("should exist");
+        // Wait until the node key account is funded
+        let initial_balance = loop {
+            if let TransGenState::Stop = *txgen.state.read() {
+                return Ok(());
+            }
+            if !txgen.sync.catch_up_mode() {
+                let balance = txgen
+                    .consensus
+                    .get_best_state()
+                    .balance(&initial_key_pair.address())
+                    .unwrap_or_default();
+                if !balance.is_zero() {
+                    break balance;
+                }
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+        let mut funder = Sender {
+            nonce: txgen.txpool.get_next_nonce(&initial_key_pair.address()),
+            key_pair: initial_key_pair,
+        };
+        let mut senders: Vec<Sender> = workload
+            .sender_keys()
+            .into_iter()
+            .map(|key_pair| Sender {
+                nonce: txgen.txpool.get_next_nonce(&key_pair.address()),
+                key_pair,
+            })
+            .collect();
+        if senders.is_empty() {
+            return Ok(());
+        }
+
+        info!("Setup {} workload senders", senders.len());
+        let funding = initial_balance / (senders.len() + 1);
+        for sender in &senders {
+            while !txgen.send_workload_transaction(
+                &mut funder,
+                TRANSFER_GAS,
+                Action::Call(sender.address()),
+                funding,
+                Bytes::new(),
+            ) {
+                thread::sleep(period);
+            }
+        }
+
+        let erc20_code = Vec::from_hex(ERC20_BYTECODE).unwrap();
+        let erc20_address = contract_address(
+            CreateContractAddress::FromSenderAndNonce,
+            &funder.address(),
+            &funder.nonce,
+            &erc20_code,
+        )
+        .0;
+        let storage_code = Vec::from_hex(STORAGE_WRITER_BYTECODE).unwrap();
+        let storage_address = contract_address(
+            CreateContractAddress::FromSenderAndNonce,
+            &funder.address(),
+            &(funder.nonce + 1),
+            &storage_code,
+        )
+        .0;
+        for code in vec![erc20_code, storage_code] {
+            while !txgen.send_workload_transaction(
+                &mut funder,
+                CONTRACT_CREATE_GAS,
+                Action::Create,
+                0.into(),
+                code.clone(),
+            ) {
+                thread::sleep(period);
+            }
+        }
+        // The token supply of the creator is shared by the senders.
+        let token_amount = U256::from(1_000_000_000_000_000_000u64);
+        for sender in &senders {
+            while !txgen.send_workload_transaction(
+                &mut funder,
+                ERC20_TRANSFER_GAS,
+                Action::Call(erc20_address),
+                0.into(),
+                erc20_transfer_data(&sender.address(), token_amount),
+            ) {
+                thread::sleep(period);
+            }
+        }
+        debug!(
+            "Workload contracts: erc20={:?} storage={:?}",
+            erc20_address, storage_address
+        );
+
+        // Wait until the setup transactions are executed
+        let last_sender = senders.last().unwrap().address();
+        loop {
+            if let TransGenState::Stop = *txgen.state.read() {
+                return Ok(());
+            }
+            let state = txgen.consensus.get_best_state();
+            let funded =
+                !state.balance(&last_sender).unwrap_or_default().is_zero();
+            let deployed = state
+                .code(&storage_address)
+                .ok()
+                .and_then(|code| code)
+                .is_some();
+            if funded && deployed {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        info!("Start Generating Workload {:?}", workload);
+        let mut rng = thread_rng();
+        let mut rate = RateController::new(period, workload.arrival);
+        let mut storage_start = U256::zero();
+        loop {
+            if let TransGenState::Stop = *txgen.state.read() {
+                return Ok(());
+            }
+
+            let receiver = senders[rng.gen_range(0, senders.len())].address();
+            let sender_index = rng.gen_range(0, senders.len());
+            let sender = &mut senders[sender_index];
+            let kind = workload.choose_kind(&mut rng);
+            let sent = match kind {
+                TxKind::Transfer => txgen.send_workload_transaction(
+                    sender,
+                    TRANSFER_GAS,
+                    Action::Call(receiver),
+                    0.into(),
+                    Bytes::new(),
+                ),
+                TxKind::Erc20Transfer => txgen.send_workload_transaction(
+                    sender,
+                    ERC20_TRANSFER_GAS,
+                    Action::Call(erc20_address),
+                    0.into(),
+                    erc20_transfer_data(&receiver, 1.into()),
+                ),
+                TxKind::StorageWrite => {
+                    let sent = txgen.send_workload_transaction(
+                        sender,
+                        STORAGE_WRITE_BASE_GAS
+                            + STORAGE_WRITE_GAS_PER_SLOT
+                                * workload.storage_slots as u64,
+                        Action::Call(storage_address),
+                        0.into(),
+                        storage_write_data(
+                            storage_start,
+                            workload.storage_slots,
+                        ),
+                    );
+                    // Write new slots each time, so that the storage grows.
+                    storage_start += U256::from(workload.storage_slots);
+                    sent
+                }
+            };
+            if sent {
+                rate.sent_and_wait();
+            } else {
+                // The transaction pool is full, so we back off to avoid busy
+                // spin.
+                thread::sleep(period);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{seeded_key_pair, ArrivalPattern, RateController};
+    use std::time::Duration;
+
+    #[test]
+    fn test_arrival_pattern() {
+        let period = Duration::from_millis(10);
+        let constant = RateController::new(period, ArrivalPattern::Constant);
+        assert_eq!(constant.due(3), Duration::from_millis(30));
+
+        let bursty = RateController::new(
+            period,
+            ArrivalPattern::Bursty { burst_size: 4 },
+        );
+        assert_eq!(bursty.due(3), Duration::from_millis(0));
+        assert_eq!(bursty.due(4), Duration::from_millis(40));
+        assert_eq!(bursty.due(7), Duration::from_millis(40));
+        assert_eq!(bursty.due(8), Duration::from_millis(80));
+    }
+
+    #[test]
+    fn test_seeded_keys() {
+        assert_eq!(
+            seeded_key_pair(7, 0).address(),
+            seeded_key_pair(7, 0).address()
+        );
+        assert_ne!(
+            seeded_key_pair(7, 0).address(),
+            seeded_key_pair(7, 1).address()
+        );
+        assert_ne!(
+            seeded_key_pair(7, 0).address(),
+            seeded_key_pair(8, 0).address()
+        );
+    }
+}
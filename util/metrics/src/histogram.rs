@@ -98,9 +98,9 @@ struct NoopHistogram;
 impl Histogram for NoopHistogram {}
 
 #[derive(Default, Clone)]
-struct Snapshot {
-    count: usize,
-    values: Vec<u64>,
+pub(crate) struct Snapshot {
+    pub(crate) count: usize,
+    pub(crate) values: Vec<u64>,
 }
 
 impl Histogram for Snapshot {
@@ -14,6 +14,6 @@ pub use self::{
     histogram::{Histogram, Sample},
     meter::{register_meter, register_meter_with_group, Meter, MeterTimer},
     metrics::enable,
-    report::{report_async, FileReporter},
+    report::{prometheus_report, report_async, FileReporter},
     timer::{register_timer, register_timer_with_group, Timer},
 };
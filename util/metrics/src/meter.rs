@@ -95,7 +95,7 @@ pub struct StandardMeter {
 }
 
 impl StandardMeter {
-    fn new(name: String) -> Self {
+    pub(crate) fn new(name: String) -> Self {
         StandardMeter {
             name,
             snapshot: RwLock::new(MeterSnapshot::default()),
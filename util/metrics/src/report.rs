@@ -3,12 +3,14 @@ use crate::{
     gauge::{Gauge, GaugeUsize},
     histogram::Histogram,
     meter::{Meter, StandardMeter},
-    metrics::is_enabled,
+    metrics::{is_enabled, Metric},
     registry::{DEFAULT_GROUPING_REGISTRY, DEFAULT_REGISTRY},
 };
 use std::{
+    collections::{btree_map::Entry, BTreeMap, HashMap},
     fs::OpenOptions,
     io::Write,
+    sync::Arc,
     thread,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
@@ -88,9 +90,88 @@ impl Reporter for FileReporter {
     }
 }
 
+/// Render all registered metrics in the Prometheus text exposition format.
+/// A metric in a group is named `<group>_<name>`, and all names are prefixed
+/// with `conflux_`.
+pub fn prometheus_report() -> String {
+    render_prometheus(
+        DEFAULT_REGISTRY.read().get_all(),
+        DEFAULT_GROUPING_REGISTRY.read().get_all(),
+    )
+}
+
+/// Render the ungrouped metrics and the grouped ones in the Prometheus text
+/// exposition format. Prometheus rejects duplicate metric names, so a metric
+/// whose name is taken by another one after sanitizing, e.g. the ungrouped
+/// `a_b` and `b` in group `a`, is skipped. The ungrouped metrics take
+/// precedence, and otherwise the first one in the name order.
+fn render_prometheus(
+    metrics: &HashMap<String, Arc<dyn Metric>>,
+    groups: &HashMap<String, HashMap<String, Arc<dyn Metric>>>,
+) -> String
+{
+    let mut ungrouped: Vec<_> = metrics.iter().collect();
+    ungrouped.sort_by(|a, b| a.0.cmp(b.0));
+    let mut grouped: Vec<_> = groups
+        .iter()
+        .flat_map(|(group_name, group)| {
+            group.iter().map(move |(name, metric)| (group_name, name, metric))
+        })
+        .collect();
+    grouped.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+    let mut rendered: BTreeMap<String, (String, &Arc<dyn Metric>)> =
+        BTreeMap::new();
+    let sources = ungrouped
+        .into_iter()
+        .map(|(name, metric)| (prometheus_name(&[name]), name.clone(), metric))
+        .chain(grouped.into_iter().map(|(group_name, name, metric)| {
+            (
+                prometheus_name(&[group_name, name]),
+                format!("{}.{}", group_name, name),
+                metric,
+            )
+        }));
+    for (name, source, metric) in sources {
+        match rendered.entry(name) {
+            Entry::Vacant(entry) => {
+                entry.insert((source, metric));
+            }
+            Entry::Occupied(entry) => eprintln!(
+                "Skip metric {} in the Prometheus report, whose name {} is taken by {}",
+                source,
+                entry.key(),
+                entry.get().0
+            ),
+        }
+    }
+
+    rendered
+        .iter()
+        .map(|(name, (_, metric))| metric.get_prometheus(name))
+        .collect()
+}
+
+/// Join the parts with `_` and replace the characters that are not allowed
+/// in Prometheus metric names.
+fn prometheus_name(parts: &[&String]) -> String {
+    let name: String = parts
+        .iter()
+        .map(|part| part.as_str())
+        .collect::<Vec<&str>>()
+        .join("_")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("conflux_{}", name)
+}
+
 pub trait Reportable {
     fn get_value(&self) -> String;
     fn get_value_with_group(&self, name: &String) -> String;
+    /// Render the metric with the given name in the Prometheus text
+    /// exposition format.
+    fn get_prometheus(&self, name: &String) -> String;
 }
 
 impl Reportable for CounterUsize {
@@ -99,6 +180,10 @@ impl Reportable for CounterUsize {
     fn get_value_with_group(&self, name: &String) -> String {
         format!("{}: {}", name, self.count())
     }
+
+    fn get_prometheus(&self, name: &String) -> String {
+        format!("# TYPE {0} counter\n{0} {1}\n", name, self.count())
+    }
 }
 
 impl Reportable for GaugeUsize {
@@ -107,6 +192,10 @@ impl Reportable for GaugeUsize {
     fn get_value_with_group(&self, name: &String) -> String {
         format!("{}: {}", name, self.value())
     }
+
+    fn get_prometheus(&self, name: &String) -> String {
+        format!("# TYPE {0} gauge\n{0} {1}\n", name, self.value())
+    }
 }
 
 impl Reportable for StandardMeter {
@@ -134,6 +223,19 @@ impl Reportable for StandardMeter {
             snapshot.rate_mean()
         )
     }
+
+    fn get_prometheus(&self, name: &String) -> String {
+        let snapshot = self.snapshot();
+        format!(
+            "# TYPE {0}_total counter\n{0}_total {1}\n# TYPE {0}_rate gauge\n{0}_rate{{window=\"1m\"}} {2}\n{0}_rate{{window=\"5m\"}} {3}\n{0}_rate{{window=\"15m\"}} {4}\n{0}_rate{{window=\"mean\"}} {5}\n",
+            name,
+            snapshot.count(),
+            snapshot.rate1(),
+            snapshot.rate5(),
+            snapshot.rate15(),
+            snapshot.rate_mean()
+        )
+    }
 }
 
 impl<T: Histogram> Reportable for T {
@@ -175,4 +277,137 @@ impl<T: Histogram> Reportable for T {
             snapshot.percentile(0.999),
         )
     }
+
+    fn get_prometheus(&self, name: &String) -> String {
+        let snapshot = self.snapshot();
+        let mut text = format!("# TYPE {} summary\n", name);
+        for quantile in &[0.5, 0.75, 0.9, 0.95, 0.99, 0.999] {
+            text.push_str(&format!(
+                "{}{{quantile=\"{}\"}} {}\n",
+                name,
+                quantile,
+                snapshot.percentile(*quantile)
+            ));
+        }
+        text.push_str(&format!(
+            "{0}_sum {1}\n{0}_count {2}\n",
+            name,
+            snapshot.sum(),
+            snapshot.count()
+        ));
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prometheus_name, render_prometheus, Reportable};
+    use crate::{
+        counter::{Counter, CounterUsize},
+        gauge::{Gauge, GaugeUsize},
+        histogram::Snapshot,
+        meter::StandardMeter,
+        metrics::Metric,
+    };
+    use std::{collections::HashMap, sync::Arc};
+
+    fn counter(count: usize) -> Arc<dyn Metric> {
+        let counter = CounterUsize::default();
+        counter.inc(count);
+        Arc::new(counter)
+    }
+
+    #[test]
+    fn test_prometheus_name() {
+        let (group, name) = ("sync".to_string(), "block.count-1".to_string());
+        assert_eq!(prometheus_name(&[&name]), "conflux_block_count_1");
+        assert_eq!(
+            prometheus_name(&[&group, &name]),
+            "conflux_sync_block_count_1"
+        );
+        assert_eq!(
+            prometheus_name(&[&"tx pool::size".to_string()]),
+            "conflux_tx_pool__size"
+        );
+    }
+
+    #[test]
+    fn test_prometheus_counter_and_gauge() {
+        let counter = CounterUsize::default();
+        counter.inc(3);
+        assert_eq!(
+            counter.get_prometheus(&"conflux_c".to_string()),
+            "# TYPE conflux_c counter\nconflux_c 3\n"
+        );
+
+        let gauge = GaugeUsize::default();
+        gauge.update(7);
+        assert_eq!(
+            gauge.get_prometheus(&"conflux_g".to_string()),
+            "# TYPE conflux_g gauge\nconflux_g 7\n"
+        );
+    }
+
+    #[test]
+    fn test_prometheus_meter() {
+        let meter = StandardMeter::new("m".into());
+        assert_eq!(
+            meter.get_prometheus(&"conflux_m".to_string()),
+            "# TYPE conflux_m_total counter\n\
+             conflux_m_total 0\n\
+             # TYPE conflux_m_rate gauge\n\
+             conflux_m_rate{window=\"1m\"} 0\n\
+             conflux_m_rate{window=\"5m\"} 0\n\
+             conflux_m_rate{window=\"15m\"} 0\n\
+             conflux_m_rate{window=\"mean\"} 0\n"
+        );
+    }
+
+    #[test]
+    fn test_prometheus_histogram() {
+        let snapshot = Snapshot {
+            count: 4,
+            values: vec![1, 2, 3, 4],
+        };
+        assert_eq!(
+            snapshot.get_prometheus(&"conflux_h".to_string()),
+            "# TYPE conflux_h summary\n\
+             conflux_h{quantile=\"0.5\"} 2\n\
+             conflux_h{quantile=\"0.75\"} 3\n\
+             conflux_h{quantile=\"0.9\"} 3\n\
+             conflux_h{quantile=\"0.95\"} 3\n\
+             conflux_h{quantile=\"0.99\"} 3\n\
+             conflux_h{quantile=\"0.999\"} 3\n\
+             conflux_h_sum 10\n\
+             conflux_h_count 4\n"
+        );
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let mut metrics = HashMap::new();
+        metrics.insert("b".to_string(), counter(1));
+        metrics.insert("a_b".to_string(), counter(2));
+        let mut groups = HashMap::new();
+        let mut group = HashMap::new();
+        // Collides with the ungrouped `a_b`.
+        group.insert("b".to_string(), counter(3));
+        group.insert("c".to_string(), counter(4));
+        groups.insert("a".to_string(), group);
+        // Collides with `b.c` in group `a.b`, which comes first.
+        let mut group = HashMap::new();
+        group.insert("b.c".to_string(), counter(5));
+        groups.insert("a_b".to_string(), group);
+        let mut group = HashMap::new();
+        group.insert("b.c".to_string(), counter(6));
+        groups.insert("a.b".to_string(), group);
+
+        assert_eq!(
+            render_prometheus(&metrics, &groups),
+            "# TYPE conflux_a_b counter\nconflux_a_b 2\n\
+             # TYPE conflux_a_b_b_c counter\nconflux_a_b_b_c 6\n\
+             # TYPE conflux_a_c counter\nconflux_a_c 4\n\
+             # TYPE conflux_b counter\nconflux_b 1\n"
+        );
+    }
 }
@@ -6,10 +6,21 @@ use std::collections::VecDeque;
 
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
 pub enum SendQueuePriority {
+    /// Never dropped when the send queue is full, e.g. the consensus
+    /// critical messages.
     High = 0,
     Normal = 1,
+    /// Dropped first when the send queue is full, e.g. the transaction
+    /// announcements.
+    Low = 2,
 }
 
+const PRIORITIES: [SendQueuePriority; 3] = [
+    SendQueuePriority::High,
+    SendQueuePriority::Normal,
+    SendQueuePriority::Low,
+];
+
 pub struct PrioritySendQueue<T> {
     queues: Vec<VecDeque<T>>,
 }
@@ -17,8 +28,9 @@ pub struct PrioritySendQueue<T> {
 impl<T> PrioritySendQueue<T> {
     pub fn new() -> PrioritySendQueue<T> {
         let mut queues = Vec::new();
-        queues.push(VecDeque::new());
-        queues.push(VecDeque::new());
+        for _ in PRIORITIES.iter() {
+            queues.push(VecDeque::new());
+        }
         PrioritySendQueue { queues }
     }
 
@@ -31,25 +43,35 @@ impl<T> PrioritySendQueue<T> {
     }
 
     pub fn pop_front(&mut self) -> Option<(T, SendQueuePriority)> {
-        if let Some(data) = self.queue_mut(SendQueuePriority::High).pop_front()
-        {
-            return Some((data, SendQueuePriority::High));
-        }
-
-        if let Some(data) =
-            self.queue_mut(SendQueuePriority::Normal).pop_front()
-        {
-            return Some((data, SendQueuePriority::Normal));
+        for priority in PRIORITIES.iter() {
+            if let Some(data) = self.queue_mut(*priority).pop_front() {
+                return Some((data, *priority));
+            }
         }
 
         None
     }
 
+    /// Pop the oldest item of the specified priority, e.g. to drop it when
+    /// the queue is full.
+    pub fn pop_front_by_priority(
+        &mut self, priority: SendQueuePriority,
+    ) -> Option<T> {
+        self.queue_mut(priority).pop_front()
+    }
+
+    /// Iterate the items of the specified priority from the oldest.
+    pub fn iter_by_priority(
+        &self, priority: SendQueuePriority,
+    ) -> impl Iterator<Item = &T> {
+        self.queue(priority).iter()
+    }
+
     pub fn front_mut(&mut self) -> Option<(&mut T, bool)> {
         let mut promoted = false;
 
         if self.queue(SendQueuePriority::High).is_empty() {
-            let res = self.queue_mut(SendQueuePriority::Normal).pop_front()?;
+            let (res, _) = self.pop_front()?;
             self.queue_mut(SendQueuePriority::High).push_back(res);
             promoted = true;
         }
@@ -60,8 +82,7 @@ impl<T> PrioritySendQueue<T> {
     }
 
     pub fn is_empty(&self) -> bool {
-        self.queue(SendQueuePriority::High).is_empty()
-            && self.queue(SendQueuePriority::Normal).is_empty()
+        self.queues.iter().all(|queue| queue.is_empty())
     }
 
     pub fn is_send_queue_empty(&self, priority: SendQueuePriority) -> bool {
@@ -73,8 +94,7 @@ impl<T> PrioritySendQueue<T> {
     }
 
     pub fn len(&self) -> usize {
-        self.queue(SendQueuePriority::High).len()
-            + self.queue(SendQueuePriority::Normal).len()
+        self.queues.iter().map(|queue| queue.len()).sum()
     }
 
     pub fn len_by_priority(&self, priority: SendQueuePriority) -> usize {